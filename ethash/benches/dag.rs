@@ -0,0 +1,74 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+#[macro_use]
+extern crate criterion;
+extern crate ethash;
+
+use criterion::Criterion;
+use ethash::{NodeCacheBuilder, OptimizeFor};
+
+const HASH: [u8; 32] = [0xf5, 0x7e, 0x6f, 0x3a, 0xcf, 0xc0, 0xdd, 0x4b, 0x5b, 0xf2, 0xbe,
+	                    0xe4, 0x0a, 0xb3, 0x35, 0x8a, 0xa6, 0x87, 0x73, 0xa8, 0xd0, 0x9f,
+	                    0x5e, 0x59, 0x5e, 0xab, 0x55, 0x94, 0x05, 0x52, 0x7d, 0x72];
+const NONCE: u64 = 0xd7b3ac70a301a249;
+// Generating a full DAG takes a while, so these only use the first epoch.
+const BLOCK_NUMBER: u64 = 0;
+const THREADS: usize = 4;
+
+criterion_group!{
+	name = dag;
+	config = Criterion::default().sample_size(10);
+	targets = bench_full_dag_generation, bench_full_compute, bench_light_compute
+}
+criterion_main!(dag);
+
+
+fn bench_full_dag_generation(b: &mut Criterion) {
+	use std::env;
+
+	let dir = env::temp_dir();
+	let builder = NodeCacheBuilder::new(OptimizeFor::Cpu, u64::max_value());
+	let light = builder.light(&dir, BLOCK_NUMBER);
+
+	b.bench_function("bench_full_dag_generation", move |b| b.iter(|| {
+		builder.full(&dir, &light, BLOCK_NUMBER, THREADS).unwrap();
+	}));
+}
+
+
+fn bench_full_compute(b: &mut Criterion) {
+	use std::env;
+
+	let dir = env::temp_dir();
+	let builder = NodeCacheBuilder::new(OptimizeFor::Cpu, u64::max_value());
+	let full = builder.full_from_file(&dir, BLOCK_NUMBER).unwrap_or_else(|_| {
+		let light = builder.light(&dir, BLOCK_NUMBER);
+		builder.full(&dir, &light, BLOCK_NUMBER, THREADS).unwrap()
+	});
+
+	b.bench_function("bench_full_compute", move |b| b.iter(|| full.compute(&HASH, NONCE)));
+}
+
+
+fn bench_light_compute(b: &mut Criterion) {
+	use std::env;
+
+	let builder = NodeCacheBuilder::new(OptimizeFor::Cpu, u64::max_value());
+	let light = builder.light(&env::temp_dir(), BLOCK_NUMBER);
+
+	b.bench_function("bench_light_compute", move |b| b.iter(|| light.compute(&HASH, NONCE, BLOCK_NUMBER)));
+}
@@ -16,6 +16,7 @@
 
 use compute::Light;
 use either::Either;
+use full::{self, Full};
 use keccak::{H256, keccak_512};
 use memmap::MmapMut;
 use parking_lot::Mutex;
@@ -69,6 +70,7 @@ pub struct NodeCacheBuilder {
 	// TODO: Remove this locking and just use an `Rc`?
 	seedhash: Arc<Mutex<SeedHashCompute>>,
 	optimize_for: OptimizeFor,
+	progpow_transition: u64,
 }
 
 // TODO: Abstract the "optimize for" logic
@@ -82,17 +84,36 @@ pub struct NodeCache {
 
 impl NodeCacheBuilder {
 	pub fn light(&self, cache_dir: &Path, block_number: u64) -> Light {
-		Light::new_with_builder(self, cache_dir, block_number)
+		Light::new_with_builder(self, cache_dir, block_number, self.progpow_transition)
 	}
 
 	pub fn light_from_file(&self, cache_dir: &Path, block_number: u64) -> io::Result<Light> {
-		Light::from_file_with_builder(self, cache_dir, block_number)
+		Light::from_file_with_builder(self, cache_dir, block_number, self.progpow_transition)
 	}
 
-	pub fn new<T: Into<Option<OptimizeFor>>>(optimize_for: T) -> Self {
+	/// Generate the full DAG for the epoch of `block_number` from its light cache.
+	pub fn full(&self, cache_dir: &Path, light: &Light, block_number: u64, threads: usize) -> io::Result<Full> {
+		let ident = self.block_number_to_ident(block_number);
+		Full::generate(light.cache(), cache_dir, &ident, block_number, threads)
+	}
+
+	/// Load a previously generated full DAG for the epoch of `block_number`.
+	pub fn full_from_file(&self, cache_dir: &Path, block_number: u64) -> io::Result<Full> {
+		let ident = self.block_number_to_ident(block_number);
+		Full::from_file(cache_dir, &ident, block_number)
+	}
+
+	/// Remove full DAGs of all epochs except `keep` from `cache_dir`.
+	pub fn remove_stale_full(&self, cache_dir: &Path, keep: &[u64]) {
+		let keep: Vec<H256> = keep.iter().map(|epoch| self.epoch_to_ident(*epoch)).collect();
+		full::remove_stale_dags(cache_dir, &keep);
+	}
+
+	pub fn new<T: Into<Option<OptimizeFor>>>(optimize_for: T, progpow_transition: u64) -> Self {
 		NodeCacheBuilder {
 			seedhash: Arc::new(Mutex::new(SeedHashCompute::default())),
 			optimize_for: optimize_for.into().unwrap_or_default(),
+			progpow_transition: progpow_transition,
 		}
 	}
 
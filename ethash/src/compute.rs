@@ -21,6 +21,7 @@
 
 use keccak::{keccak_512, keccak_256, H256};
 use cache::{NodeCache, NodeCacheBuilder};
+use progpow::{CDag, generate_cdag, progpow, keccak_f800_short, keccak_f800_long, bytes_to_words};
 use seed_compute::SeedHashCompute;
 use shared::*;
 use std::io;
@@ -30,7 +31,7 @@ use std::path::Path;
 
 const MIX_WORDS: usize = ETHASH_MIX_BYTES / 4;
 const MIX_NODES: usize = MIX_WORDS / NODE_WORDS;
-const FNV_PRIME: u32 = 0x01000193;
+pub const FNV_PRIME: u32 = 0x01000193;
 
 /// Computation result
 pub struct ProofOfWork {
@@ -43,6 +44,9 @@ pub struct ProofOfWork {
 pub struct Light {
 	block_number: u64,
 	cache: NodeCache,
+	progpow_transition: u64,
+	// Only generated for epochs containing ProgPoW blocks.
+	c_dag: Option<Box<CDag>>,
 }
 
 /// Light cache structure
@@ -51,38 +55,69 @@ impl Light {
 		builder: &NodeCacheBuilder,
 		cache_dir: &Path,
 		block_number: u64,
+		progpow_transition: u64,
 	) -> Self {
 		let cache = builder.new_cache(cache_dir.to_path_buf(), block_number);
-
-		Light {
-			block_number: block_number,
-			cache: cache,
-		}
+		Light::with_cache(cache, block_number, progpow_transition)
 	}
 
 	/// Calculate the light boundary data
 	/// `header_hash` - The header hash to pack into the mix
 	/// `nonce` - The nonce to pack into the mix
-	pub fn compute(&self, header_hash: &H256, nonce: u64) -> ProofOfWork {
-		light_compute(self, header_hash, nonce)
+	/// `block_number` - The block number, selects between Ethash and ProgPoW
+	pub fn compute(&self, header_hash: &H256, nonce: u64, block_number: u64) -> ProofOfWork {
+		match self.c_dag {
+			Some(ref c_dag) if block_number >= self.progpow_transition => {
+				let (value, mix_hash) = progpow(
+					*header_hash,
+					nonce,
+					block_number,
+					self.cache.as_ref(),
+					c_dag,
+				);
+				ProofOfWork { value, mix_hash }
+			},
+			_ => light_compute(self, header_hash, nonce),
+		}
 	}
 
 	pub fn from_file_with_builder(
 		builder: &NodeCacheBuilder,
 		cache_dir: &Path,
 		block_number: u64,
+		progpow_transition: u64,
 	) -> io::Result<Self> {
 		let cache = builder.from_file(cache_dir.to_path_buf(), block_number)?;
-		Ok(Light {
+		Ok(Light::with_cache(cache, block_number, progpow_transition))
+	}
+
+	fn with_cache(cache: NodeCache, block_number: u64, progpow_transition: u64) -> Self {
+		// The cache is shared by the whole epoch, so the cached DAG portion is needed
+		// as soon as any block of the epoch is past the transition.
+		let epoch_end = (block_number / ETHASH_EPOCH_LENGTH + 1).saturating_mul(ETHASH_EPOCH_LENGTH);
+		let c_dag = if epoch_end > progpow_transition {
+			Some(Box::new(generate_cdag(cache.as_ref())))
+		} else {
+			None
+		};
+
+		Light {
 			block_number: block_number,
 			cache: cache,
-		})
+			progpow_transition: progpow_transition,
+			c_dag: c_dag,
+		}
 	}
 
 	pub fn to_file(&mut self) -> io::Result<&Path> {
 		self.cache.flush()?;
 		Ok(self.cache.cache_path())
 	}
+
+	/// The light cache nodes, from which the full DAG is generated.
+	pub fn cache(&self) -> &[Node] {
+		self.cache.as_ref()
+	}
 }
 
 pub fn slow_hash_block_number(block_number: u64) -> H256 {
@@ -98,8 +133,14 @@ fn fnv_hash(x: u32, y: u32) -> u32 {
 /// `header_hash`      The hash of the header
 /// `nonce`            The block's nonce
 /// `mix_hash`         The mix digest hash
+/// `progpow`          Whether the seal is a ProgPoW rather than an Ethash seal
 /// Boundary recovered from mix hash
-pub fn quick_get_difficulty(header_hash: &H256, nonce: u64, mix_hash: &H256) -> H256 {
+pub fn quick_get_difficulty(header_hash: &H256, nonce: u64, mix_hash: &H256, progpow: bool) -> H256 {
+	if progpow {
+		let seed = keccak_f800_short(*header_hash, nonce, [0u32; 8]);
+		return keccak_f800_long(*header_hash, seed, bytes_to_words(mix_hash));
+	}
+
 	unsafe {
 		// This is safe - the `keccak_512` call below reads the first 40 bytes (which we explicitly set
 		// with two `copy_nonoverlapping` calls) but writes the first 64, and then we explicitly write
@@ -129,10 +170,16 @@ pub fn quick_get_difficulty(header_hash: &H256, nonce: u64, mix_hash: &H256) ->
 /// `nonce` - The nonce to pack into the mix
 pub fn light_compute(light: &Light, header_hash: &H256, nonce: u64) -> ProofOfWork {
 	let full_size = get_data_size(light.block_number);
-	hash_compute(light, full_size, header_hash, nonce)
+	// deref once for better performance
+	let cache: &[Node] = light.cache.as_ref();
+	hashimoto(full_size, header_hash, nonce, |index| calculate_dag_item(index, cache))
 }
 
-fn hash_compute(light: &Light, full_size: usize, header_hash: &H256, nonce: u64) -> ProofOfWork {
+/// Ethash hashimoto loop, independent of where dataset items come from.
+/// `full_size` - The size of the full dataset in bytes
+/// `lookup` - Returns the dataset item at the given index, either computed from
+/// the light cache or read from the full DAG
+pub fn hashimoto<F: Fn(u32) -> Node>(full_size: usize, header_hash: &H256, nonce: u64, lookup: F) -> ProofOfWork {
 	macro_rules! make_const_array {
 		($n:expr, $value:expr) => {{
 			// We use explicit lifetimes to ensure that val's borrow is invalidated until the
@@ -200,8 +247,6 @@ fn hash_compute(light: &Light, full_size: usize, header_hash: &H256, nonce: u64)
 
 	let page_size = 4 * MIX_WORDS;
 	let num_full_pages = (full_size / page_size) as u32;
-	// deref once for better performance
-	let cache: &[Node] = light.cache.as_ref();
 	let first_val = buf.half_mix.as_words()[0];
 
 	debug_assert_eq!(MIX_NODES, 2);
@@ -220,10 +265,7 @@ fn hash_compute(light: &Light, full_size: usize, header_hash: &H256, nonce: u64)
 		unroll! {
 			// MIX_NODES
 			for n in 0..2 {
-				let tmp_node = calculate_dag_item(
-					index * MIX_NODES as u32 + n as u32,
-					cache,
-				);
+				let tmp_node = lookup(index * MIX_NODES as u32 + n as u32);
 
 				unroll! {
 					// NODE_WORDS
@@ -287,7 +329,7 @@ fn hash_compute(light: &Light, full_size: usize, header_hash: &H256, nonce: u64)
 }
 
 // TODO: Use the `simd` crate
-fn calculate_dag_item(node_index: u32, cache: &[Node]) -> Node {
+pub fn calculate_dag_item(node_index: u32, cache: &[Node]) -> Node {
 	let num_parent_nodes = cache.len();
 	let mut ret = cache[node_index as usize % num_parent_nodes].clone();
 	ret.as_words_mut()[0] ^= node_index;
@@ -361,13 +403,13 @@ mod test {
 			0x4a, 0x8e, 0x95, 0x69, 0xef, 0xc7, 0xd7, 0x1b, 0x33, 0x35, 0xdf, 0x36, 0x8c, 0x9a,
 			0xe9, 0x7e, 0x53, 0x84,
 		];
-		assert_eq!(quick_get_difficulty(&hash, nonce, &mix_hash)[..], boundary_good[..]);
+		assert_eq!(quick_get_difficulty(&hash, nonce, &mix_hash, false)[..], boundary_good[..]);
 		let boundary_bad = [
 			0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x3a, 0x9b, 0x6c, 0x69, 0xbc, 0x2c, 0xe2, 0xa2,
 			0x4a, 0x8e, 0x95, 0x69, 0xef, 0xc7, 0xd7, 0x1b, 0x33, 0x35, 0xdf, 0x36, 0x8c, 0x9a,
 			0xe9, 0x7e, 0x53, 0x84,
 		];
-		assert!(quick_get_difficulty(&hash, nonce, &mix_hash)[..] != boundary_bad[..]);
+		assert!(quick_get_difficulty(&hash, nonce, &mix_hash, false)[..] != boundary_bad[..]);
 	}
 
 	#[test]
@@ -391,7 +433,7 @@ mod test {
 
 		let tempdir = TempDir::new("").unwrap();
 		// difficulty = 0x085657254bd9u64;
-		let light = NodeCacheBuilder::new(None).light(tempdir.path(), 486382);
+		let light = NodeCacheBuilder::new(None, u64::max_value()).light(tempdir.path(), 486382);
 		let result = light_compute(&light, &hash, nonce);
 		assert_eq!(result.mix_hash[..], mix_hash[..]);
 		assert_eq!(result.value[..], boundary[..]);
@@ -400,7 +442,7 @@ mod test {
 	#[test]
 	fn test_drop_old_data() {
 		let tempdir = TempDir::new("").unwrap();
-		let builder = NodeCacheBuilder::new(None);
+		let builder = NodeCacheBuilder::new(None, u64::max_value());
 		let first = builder.light(tempdir.path(), 0).to_file().unwrap().to_owned();
 
 		let second = builder.light(tempdir.path(), ETHASH_EPOCH_LENGTH).to_file().unwrap().to_owned();
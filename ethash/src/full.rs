@@ -0,0 +1,276 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Full DAG generation and hashimoto-full lookups.
+//!
+//! The DAG is generated from the light cache by a pool of threads directly into a
+//! memory-mapped file, so that it survives restarts and doesn't have to fit in RAM.
+
+use compute::{ProofOfWork, calculate_dag_item, hashimoto};
+use keccak::H256;
+use memmap::{Mmap, MmapMut};
+use shared::{NODE_BYTES, Node, epoch, get_data_size, to_hex};
+
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::thread;
+
+/// Prefix of the file names of full DAGs, to tell them apart from light caches.
+const FULL_PREFIX: &'static str = "full-";
+/// Suffix of a DAG that is still being generated.
+const PARTIAL_SUFFIX: &'static str = ".partial";
+
+/// Memory-mapped full dataset for a single epoch.
+pub struct Full {
+	epoch: u64,
+	block_number: u64,
+	path: PathBuf,
+	dag: Mmap,
+}
+
+impl Full {
+	/// Generate the full DAG of the epoch containing `block_number` from its light cache,
+	/// using `threads` worker threads. The DAG is written to `cache_dir` under a temporary
+	/// name and only renamed to its final path once complete.
+	pub fn generate(cache: &[Node], cache_dir: &Path, ident: &H256, block_number: u64, threads: usize) -> io::Result<Self> {
+		let path = full_path(cache_dir, ident);
+		let partial = path.with_extension(&PARTIAL_SUFFIX[1..]);
+		let full_size = get_data_size(block_number);
+
+		debug_assert!(full_size % NODE_BYTES == 0, "Unaligned full size");
+		let num_nodes = full_size / NODE_BYTES;
+
+		{
+			let file = OpenOptions::new()
+				.read(true)
+				.write(true)
+				.create(true)
+				.truncate(true)
+				.open(&partial)?;
+			file.set_len(full_size as _)?;
+
+			let mut memmap = unsafe { MmapMut::map_mut(&file)? };
+			{
+				let nodes: &mut [Node] = unsafe {
+					slice::from_raw_parts_mut(memmap.as_mut_ptr() as *mut Node, num_nodes)
+				};
+				generate_dag(cache, nodes, threads);
+			}
+			memmap.flush()?;
+		}
+
+		fs::rename(&partial, &path)?;
+		Full::from_path(path, block_number)
+	}
+
+	/// Load a previously generated DAG of the epoch containing `block_number`.
+	pub fn from_file(cache_dir: &Path, ident: &H256, block_number: u64) -> io::Result<Self> {
+		Full::from_path(full_path(cache_dir, ident), block_number)
+	}
+
+	fn from_path(path: PathBuf, block_number: u64) -> io::Result<Self> {
+		// The DAG may be shared with other processes, so it's never mapped writable once complete.
+		let file = OpenOptions::new().read(true).open(&path)?;
+		let dag = unsafe { Mmap::map(&file)? };
+
+		if dag.len() != get_data_size(block_number) {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				"Full DAG is of incorrect size",
+			));
+		}
+
+		Ok(Full {
+			epoch: epoch(block_number),
+			block_number: block_number,
+			path: path,
+			dag: dag,
+		})
+	}
+
+	/// Epoch this DAG belongs to.
+	pub fn epoch(&self) -> u64 {
+		self.epoch
+	}
+
+	/// Path of the memory-mapped DAG file.
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	/// Calculate the boundary data by looking up the precomputed dataset.
+	/// `header_hash` - The header hash to pack into the mix
+	/// `nonce` - The nonce to pack into the mix
+	pub fn compute(&self, header_hash: &H256, nonce: u64) -> ProofOfWork {
+		let dag = self.as_ref();
+		hashimoto(get_data_size(self.block_number), header_hash, nonce, |index| dag[index as usize].clone())
+	}
+}
+
+impl AsRef<[Node]> for Full {
+	fn as_ref(&self) -> &[Node] {
+		// The size is checked to be a multiple of `NODE_BYTES` when the DAG is opened.
+		unsafe { slice::from_raw_parts(self.dag.as_ptr() as *const Node, self.dag.len() / NODE_BYTES) }
+	}
+}
+
+/// Remove DAGs (complete or partial) of epochs other than the given ones from `cache_dir`.
+pub fn remove_stale_dags(cache_dir: &Path, keep: &[H256]) {
+	let keep: Vec<String> = keep.iter().map(|ident| format!("{}{}", FULL_PREFIX, to_hex(ident))).collect();
+
+	let entries = match fs::read_dir(cache_dir) {
+		Ok(entries) => entries,
+		Err(_) => return,
+	};
+
+	for entry in entries.filter_map(Result::ok) {
+		let name = entry.file_name().to_string_lossy().into_owned();
+		if !name.starts_with(FULL_PREFIX) {
+			continue;
+		}
+
+		let stem = name.trim_right_matches(PARTIAL_SUFFIX);
+		if !keep.iter().any(|k| k == stem) {
+			fs::remove_file(entry.path()).unwrap_or_else(|error| match error.kind() {
+				io::ErrorKind::NotFound => (),
+				_ => warn!("Error removing stale full DAG: {:?}", error),
+			});
+		}
+	}
+}
+
+fn full_path(cache_dir: &Path, ident: &H256) -> PathBuf {
+	let mut buf = cache_dir.to_path_buf();
+	buf.push(format!("{}{}", FULL_PREFIX, to_hex(ident)));
+	buf
+}
+
+// Raw pointer to a disjoint chunk of the output buffer, handed to exactly one worker.
+struct ChunkPtr(*mut Node, usize);
+
+unsafe impl Send for ChunkPtr {}
+
+// Raw pointer to the light cache, which every worker only reads.
+#[derive(Clone, Copy)]
+struct CachePtr(*const Node, usize);
+
+unsafe impl Send for CachePtr {}
+
+/// Fill `out` with the dataset items `0..out.len()` calculated from `cache`, splitting the
+/// work evenly among `threads` threads.
+pub fn generate_dag(cache: &[Node], out: &mut [Node], threads: usize) {
+	let threads = threads.max(1);
+	let chunk_size = (out.len() + threads - 1) / threads;
+
+	if threads == 1 || chunk_size == 0 {
+		fill_items(cache, out, 0);
+		return;
+	}
+
+	// Worker threads can't borrow the cache, since `thread::spawn` requires `'static`. All
+	// threads are joined before returning, so the cache and output outlive the workers.
+	let cache_ptr = CachePtr(cache.as_ptr(), cache.len());
+
+	let handles: Vec<_> = out.chunks_mut(chunk_size).enumerate().map(|(i, chunk)| {
+		let chunk = ChunkPtr(chunk.as_mut_ptr(), chunk.len());
+		let cache = cache_ptr;
+		let start = (i * chunk_size) as u32;
+
+		thread::Builder::new()
+			.name(format!("ethash-dag-{}", i))
+			.spawn(move || {
+				let cache: &[Node] = unsafe { slice::from_raw_parts(cache.0, cache.1) };
+				let chunk: &mut [Node] = unsafe { slice::from_raw_parts_mut(chunk.0, chunk.1) };
+				fill_items(cache, chunk, start);
+			})
+			.expect("Error spawning DAG generation thread")
+	}).collect();
+
+	for handle in handles {
+		handle.join().expect("DAG generation thread panicked");
+	}
+}
+
+fn fill_items(cache: &[Node], out: &mut [Node], start: u32) {
+	for (i, node) in out.iter_mut().enumerate() {
+		*node = calculate_dag_item(start + i as u32, cache);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use cache::NodeCacheBuilder;
+	use tempdir::TempDir;
+
+	#[test]
+	fn test_generate_dag_parallel_matches_sequential() {
+		let tempdir = TempDir::new("").unwrap();
+		let cache = NodeCacheBuilder::new(None, u64::max_value()).new_cache(tempdir.path().to_owned(), 0);
+
+		let mut sequential = vec![Node { bytes: [0u8; NODE_BYTES] }; 1025];
+		let mut parallel = sequential.clone();
+
+		generate_dag(cache.as_ref(), &mut sequential, 1);
+		generate_dag(cache.as_ref(), &mut parallel, 4);
+
+		for (i, (a, b)) in sequential.iter().zip(parallel.iter()).enumerate() {
+			assert_eq!(a.as_bytes()[..], b.as_bytes()[..]);
+			assert_eq!(a.as_bytes()[..], calculate_dag_item(i as u32, cache.as_ref()).as_bytes()[..]);
+		}
+	}
+
+	#[test]
+	fn test_remove_stale_dags() {
+		let tempdir = TempDir::new("").unwrap();
+		let keep = [1u8; 32];
+		let stale = [2u8; 32];
+
+		let keep_path = full_path(tempdir.path(), &keep);
+		let stale_path = full_path(tempdir.path(), &stale);
+		let partial_path = stale_path.with_extension(&PARTIAL_SUFFIX[1..]);
+
+		for path in &[&keep_path, &stale_path, &partial_path] {
+			fs::File::create(path).unwrap();
+		}
+
+		remove_stale_dags(tempdir.path(), &[keep]);
+
+		assert!(fs::metadata(&keep_path).is_ok());
+		assert!(fs::metadata(&stale_path).is_err());
+		assert!(fs::metadata(&partial_path).is_err());
+	}
+
+	// Generates a full 1GB dataset, run with `--ignored`.
+	#[test]
+	#[ignore]
+	fn test_full_compute_matches_light() {
+		let tempdir = TempDir::new("").unwrap();
+		let builder = NodeCacheBuilder::new(None, u64::max_value());
+		let light = builder.light(tempdir.path(), 0);
+		let full = builder.full(tempdir.path(), &light, 0, 4).unwrap();
+
+		let hash = [0x42u8; 32];
+		for nonce in 0..16 {
+			let expected = light.compute(&hash, nonce, 0);
+			let result = full.compute(&hash, nonce);
+			assert_eq!(result.value, expected.value);
+			assert_eq!(result.mix_hash, expected.mix_hash);
+		}
+	}
+}
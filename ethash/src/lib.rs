@@ -33,9 +33,13 @@ mod seed_compute;
 mod cache;
 mod keccak;
 mod shared;
+mod progpow;
+mod full;
 
 pub use cache::{NodeCacheBuilder, OptimizeFor};
 pub use compute::{ProofOfWork, quick_get_difficulty, slow_hash_block_number};
+pub use full::Full;
+pub use progpow::PROGPOW_PERIOD_LENGTH;
 use compute::Light;
 use ethereum_types::{U256, U512};
 use keccak::H256;
@@ -44,6 +48,7 @@ pub use seed_compute::SeedHashCompute;
 pub use shared::ETHASH_EPOCH_LENGTH;
 use std::mem;
 use std::path::{Path, PathBuf};
+use std::thread;
 
 use std::sync::Arc;
 
@@ -54,19 +59,40 @@ struct LightCache {
 	prev: Option<Arc<Light>>,
 }
 
+/// Number of full DAGs kept mapped (and on disk) at any time.
+const MAX_FULL_DAGS: usize = 2;
+
+#[derive(Default)]
+struct FullCache {
+	// Most recent epochs last.
+	dags: Vec<Arc<Full>>,
+	// Epoch currently being loaded or generated in the background.
+	generating: Option<u64>,
+}
+
+struct FullDag {
+	threads: usize,
+	cache: Arc<Mutex<FullCache>>,
+}
+
 /// Light/Full cache manager.
 pub struct EthashManager {
 	nodecache_builder: NodeCacheBuilder,
 	cache: Mutex<LightCache>,
 	cache_dir: PathBuf,
+	progpow_transition: u64,
+	full: Option<FullDag>,
 }
 
 impl EthashManager {
 	/// Create a new new instance of ethash manager
-	pub fn new<T: Into<Option<OptimizeFor>>>(cache_dir: &Path, optimize_for: T) -> EthashManager {
+	/// `progpow_transition` - First block sealed with ProgPoW instead of Ethash
+	pub fn new<T: Into<Option<OptimizeFor>>>(cache_dir: &Path, optimize_for: T, progpow_transition: u64) -> EthashManager {
 		EthashManager {
 			cache_dir: cache_dir.to_path_buf(),
-			nodecache_builder: NodeCacheBuilder::new(optimize_for.into().unwrap_or_default()),
+			nodecache_builder: NodeCacheBuilder::new(optimize_for.into().unwrap_or_default(), progpow_transition),
+			progpow_transition: progpow_transition,
+			full: None,
 			cache: Mutex::new(LightCache {
 				recent_epoch: None,
 				recent: None,
@@ -76,6 +102,113 @@ impl EthashManager {
 		}
 	}
 
+	/// Enable full DAG generation, using `threads` threads. The DAG of the current epoch
+	/// is generated (or loaded from the cache dir) in the background, and the DAG of the
+	/// next epoch is precomputed once half of the current epoch has passed. Until the DAG
+	/// is available, verification falls back to the light cache.
+	///
+	/// The DAG is built for the epoch of whatever block `compute` is called with, so blocks
+	/// far from the chain head (e.g. during sync) should use `compute_light` instead.
+	pub fn with_full_dag(mut self, threads: usize) -> Self {
+		self.full = Some(FullDag {
+			threads: threads,
+			cache: Arc::new(Mutex::new(FullCache::default())),
+		});
+		self
+	}
+
+	/// Calculate the proof-of-work using the full DAG if it's ready and the light cache
+	/// otherwise. Starts building the DAG of the block's epoch if it's missing, so this
+	/// is meant for blocks near the chain head.
+	/// `block_number` - Block number to check
+	/// `header_hash` - The header hash to pack into the mix
+	/// `nonce` - The nonce to pack into the mix
+	pub fn compute(&self, block_number: u64, header_hash: &H256, nonce: u64) -> ProofOfWork {
+		// The full DAG only speeds up Ethash; ProgPoW uses the cached DAG portion.
+		if !self.is_progpow(block_number) {
+			if let Some(full) = self.full_dag(block_number) {
+				return full.compute(header_hash, nonce);
+			}
+		}
+
+		self.compute_light(block_number, header_hash, nonce)
+	}
+
+	fn full_dag(&self, block_number: u64) -> Option<Arc<Full>> {
+		let full = self.full.as_ref()?;
+		let epoch = block_number / ETHASH_EPOCH_LENGTH;
+
+		let mut cache = full.cache.lock();
+		let dag = cache.dags.iter().find(|dag| dag.epoch() == epoch).cloned();
+
+		let wanted = match dag {
+			Some(_) if block_number % ETHASH_EPOCH_LENGTH >= ETHASH_EPOCH_LENGTH / 2 => Some(epoch + 1),
+			Some(_) => None,
+			None => Some(epoch),
+		};
+
+		if let Some(wanted) = wanted {
+			let have = cache.dags.iter().any(|dag| dag.epoch() == wanted);
+			if !have && cache.generating.is_none() && self.spawn_full_dag(full, wanted) {
+				cache.generating = Some(wanted);
+			}
+		}
+
+		dag
+	}
+
+	// Must be called with `full.cache` locked, so the worker can't finish before
+	// `generating` is set.
+	fn spawn_full_dag(&self, full: &FullDag, epoch: u64) -> bool {
+		let builder = self.nodecache_builder.clone();
+		let cache_dir = self.cache_dir.clone();
+		let cache = full.cache.clone();
+		let threads = full.threads;
+		let block_number = epoch * ETHASH_EPOCH_LENGTH;
+
+		let spawned = thread::Builder::new()
+			.name("ethash-full-dag".into())
+			.spawn(move || {
+				let dag = builder.full_from_file(&cache_dir, block_number).or_else(|e| {
+					debug!("Full DAG file not found for epoch {}: {}", epoch, e);
+					info!("Generating full DAG for epoch {}", epoch);
+					let light = builder.light_from_file(&cache_dir, block_number)
+						.unwrap_or_else(|_| builder.light(&cache_dir, block_number));
+					builder.full(&cache_dir, &light, block_number, threads)
+				});
+
+				let mut cache = cache.lock();
+				cache.generating = None;
+
+				match dag {
+					Ok(dag) => {
+						info!("Full DAG for epoch {} is ready", epoch);
+						cache.dags.push(Arc::new(dag));
+						cache.dags.sort_by_key(|dag| dag.epoch());
+						let excess = cache.dags.len().saturating_sub(MAX_FULL_DAGS);
+						cache.dags.drain(..excess);
+
+						let keep: Vec<u64> = cache.dags.iter().map(|dag| dag.epoch()).collect();
+						builder.remove_stale_full(&cache_dir, &keep);
+					},
+					Err(e) => warn!("Full DAG generation error: {}", e),
+				}
+			});
+
+		match spawned {
+			Ok(_) => true,
+			Err(e) => {
+				warn!("Error spawning full DAG generation thread: {}", e);
+				false
+			},
+		}
+	}
+
+	/// Whether blocks at the given height are sealed with ProgPoW.
+	pub fn is_progpow(&self, block_number: u64) -> bool {
+		block_number >= self.progpow_transition
+	}
+
 	/// Calculate the light client data
 	/// `block_number` - Block number to check
 	/// `light` - The light client handler
@@ -132,7 +265,7 @@ impl EthashManager {
 				Some(light) => light,
 			}
 		};
-		light.compute(header_hash, nonce)
+		light.compute(header_hash, nonce, block_number)
 	}
 }
 
@@ -164,7 +297,7 @@ fn test_lru() {
 	use tempdir::TempDir;
 
 	let tempdir = TempDir::new("").unwrap();
-	let ethash = EthashManager::new(tempdir.path(), None);
+	let ethash = EthashManager::new(tempdir.path(), None, u64::max_value());
 	let hash = [0u8; 32];
 	ethash.compute_light(1, &hash, 1);
 	ethash.compute_light(50000, &hash, 1);
@@ -178,6 +311,27 @@ fn test_lru() {
 	assert_eq!(ethash.cache.lock().prev_epoch.unwrap(), 0);
 }
 
+#[test]
+fn test_progpow_transition() {
+	use tempdir::TempDir;
+
+	let tempdir = TempDir::new("").unwrap();
+	let ethash = EthashManager::new(tempdir.path(), None, 10);
+	let hash = [0u8; 32];
+
+	assert!(!ethash.is_progpow(9));
+	assert!(ethash.is_progpow(10));
+
+	// both algorithms share the light cache of epoch 0.
+	let before = ethash.compute_light(9, &hash, 1);
+	assert_eq!(before.value, quick_get_difficulty(&hash, 1, &before.mix_hash, false));
+
+	let after = ethash.compute_light(10, &hash, 1);
+	assert_eq!(after.value, quick_get_difficulty(&hash, 1, &after.mix_hash, true));
+	assert_eq!(ethash.cache.lock().recent_epoch.unwrap(), 0);
+	assert!(ethash.cache.lock().prev_epoch.is_none());
+}
+
 #[test]
 fn test_difficulty_to_boundary() {
 	use ethereum_types::H256;
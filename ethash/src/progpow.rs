@@ -0,0 +1,542 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! ProgPoW (0.9.2) CPU reference implementation.
+//! See https://github.com/ifdefelse/ProgPOW
+
+use compute::{FNV_PRIME, calculate_dag_item};
+use keccak::H256;
+use shared::{ETHASH_ACCESSES, ETHASH_MIX_BYTES, Node, get_data_size};
+
+const PROGPOW_CACHE_BYTES: usize = 16 * 1024;
+const PROGPOW_CACHE_WORDS: usize = PROGPOW_CACHE_BYTES / 4;
+const PROGPOW_CNT_CACHE: usize = 12;
+const PROGPOW_CNT_MATH: usize = 20;
+const PROGPOW_CNT_DAG: usize = ETHASH_ACCESSES;
+const PROGPOW_DAG_LOADS: usize = 4;
+const PROGPOW_MIX_BYTES: usize = 2 * ETHASH_MIX_BYTES;
+const PROGPOW_LANES: usize = 16;
+const PROGPOW_REGS: usize = 32;
+
+/// Number of blocks for which the same random program is used.
+pub const PROGPOW_PERIOD_LENGTH: u64 = 50;
+
+const FNV_HASH: u32 = 0x811c9dc5;
+
+const KECCAKF_RNDC: [u32; 24] = [
+	0x00000001, 0x00008082, 0x0000808a, 0x80008000, 0x0000808b, 0x80000001,
+	0x80008081, 0x00008009, 0x0000008a, 0x00000088, 0x80008009, 0x8000000a,
+	0x8000808b, 0x0000008b, 0x00008089, 0x00008003, 0x00008002, 0x00000080,
+	0x0000800a, 0x8000000a, 0x80008081, 0x00008080, 0x80000001, 0x80008008
+];
+
+const KECCAKF_ROTC: [u32; 24] = [
+	1,  3,  6,  10, 15, 21, 28, 36, 45, 55, 2,  14,
+	27, 41, 56, 8,  25, 43, 62, 18, 39, 61, 20, 44
+];
+
+const KECCAKF_PILN: [usize; 24] = [
+	10, 7,  11, 17, 18, 3, 5,  16, 8,  21, 24, 4,
+	15, 23, 19, 13, 12, 2, 20, 14, 22, 9,  6,  1
+];
+
+/// The first `PROGPOW_CACHE_BYTES` of the DAG, accessed randomly by every lane.
+pub type CDag = [u32; PROGPOW_CACHE_WORDS];
+
+fn keccak_f800_round(st: &mut [u32; 25], r: usize) {
+	// Theta
+	let mut bc = [0u32; 5];
+	for i in 0..bc.len() {
+		bc[i] = st[i] ^ st[i + 5] ^ st[i + 10] ^ st[i + 15] ^ st[i + 20];
+	}
+
+	for i in 0..bc.len() {
+		let t = bc[(i + 4) % 5] ^ bc[(i + 1) % 5].rotate_left(1);
+		for j in (0..st.len()).step_by(5) {
+			st[j + i] ^= t;
+		}
+	}
+
+	// Rho Pi
+	let mut t = st[1];
+
+	debug_assert_eq!(KECCAKF_ROTC.len(), 24);
+	for i in 0..24 {
+		let j = KECCAKF_PILN[i];
+		bc[0] = st[j];
+		st[j] = t.rotate_left(KECCAKF_ROTC[i]);
+		t = bc[0];
+	}
+
+	// Chi
+	for j in (0..st.len()).step_by(5) {
+		for i in 0..bc.len() {
+			bc[i] = st[j + i];
+		}
+		for i in 0..bc.len() {
+			st[j + i] ^= (!bc[(i + 1) % 5]) & bc[(i + 2) % 5];
+		}
+	}
+
+	// Iota
+	debug_assert!(r < KECCAKF_RNDC.len());
+	st[0] ^= KECCAKF_RNDC[r];
+}
+
+fn keccak_f800(header_hash: H256, nonce: u64, result: [u32; 8], st: &mut [u32; 25]) {
+	for i in 0..8 {
+		st[i] = (header_hash[4 * i] as u32) +
+			((header_hash[4 * i + 1] as u32) << 8) +
+			((header_hash[4 * i + 2] as u32) << 16) +
+			((header_hash[4 * i + 3] as u32) << 24);
+	}
+
+	st[8] = nonce as u32;
+	st[9] = (nonce >> 32) as u32;
+
+	for i in 0..8 {
+		st[10 + i] = result[i];
+	}
+
+	for r in 0..22 {
+		keccak_f800_round(st, r);
+	}
+}
+
+/// Keccak-f[800] of the header, nonce and digest, truncated to the 64-bit seed.
+pub fn keccak_f800_short(header_hash: H256, nonce: u64, result: [u32; 8]) -> u64 {
+	let mut st = [0u32; 25];
+	keccak_f800(header_hash, nonce, result, &mut st);
+	(st[0].swap_bytes() as u64) << 32 | st[1].swap_bytes() as u64
+}
+
+/// Keccak-f[800] of the header, seed and digest, returning the final 256-bit hash.
+pub fn keccak_f800_long(header_hash: H256, nonce: u64, result: [u32; 8]) -> H256 {
+	let mut st = [0u32; 25];
+	keccak_f800(header_hash, nonce, result, &mut st);
+	words_to_bytes(&[st[0], st[1], st[2], st[3], st[4], st[5], st[6], st[7]])
+}
+
+/// Little-endian encoding of a 256-bit digest, as stored in the `mix_hash` seal field.
+pub fn words_to_bytes(words: &[u32; 8]) -> H256 {
+	let mut out = [0u8; 32];
+	for i in 0..8 {
+		out[4 * i] = words[i] as u8;
+		out[4 * i + 1] = (words[i] >> 8) as u8;
+		out[4 * i + 2] = (words[i] >> 16) as u8;
+		out[4 * i + 3] = (words[i] >> 24) as u8;
+	}
+	out
+}
+
+/// Inverse of `words_to_bytes`.
+pub fn bytes_to_words(bytes: &H256) -> [u32; 8] {
+	let mut out = [0u32; 8];
+	for i in 0..8 {
+		out[i] = (bytes[4 * i] as u32) |
+			((bytes[4 * i + 1] as u32) << 8) |
+			((bytes[4 * i + 2] as u32) << 16) |
+			((bytes[4 * i + 3] as u32) << 24);
+	}
+	out
+}
+
+#[inline]
+fn fnv1a_hash(h: u32, d: u32) -> u32 {
+	(h ^ d).wrapping_mul(FNV_PRIME)
+}
+
+#[derive(Clone)]
+struct Kiss99 {
+	z: u32,
+	w: u32,
+	jsr: u32,
+	jcong: u32,
+}
+
+impl Kiss99 {
+	fn new(z: u32, w: u32, jsr: u32, jcong: u32) -> Kiss99 {
+		Kiss99 { z, w, jsr, jcong }
+	}
+
+	#[inline]
+	fn next_u32(&mut self) -> u32 {
+		self.z = 36969u32.wrapping_mul(self.z & 65535).wrapping_add(self.z >> 16);
+		self.w = 18000u32.wrapping_mul(self.w & 65535).wrapping_add(self.w >> 16);
+		let mwc = (self.z << 16).wrapping_add(self.w);
+		self.jsr ^= self.jsr << 17;
+		self.jsr ^= self.jsr >> 13;
+		self.jsr ^= self.jsr << 5;
+		self.jcong = 69069u32.wrapping_mul(self.jcong).wrapping_add(1234567);
+
+		(mwc ^ self.jcong).wrapping_add(self.jsr)
+	}
+}
+
+fn fill_mix(seed: u64, lane_id: u32) -> [u32; PROGPOW_REGS] {
+	// Use FNV to expand the per-warp seed to per-lane
+	// Use KISS to expand the per-lane seed to fill mix
+	let z = fnv1a_hash(FNV_HASH, seed as u32);
+	let w = fnv1a_hash(z, (seed >> 32) as u32);
+	let jsr = fnv1a_hash(w, lane_id);
+	let jcong = fnv1a_hash(jsr, lane_id);
+
+	let mut rnd = Kiss99::new(z, w, jsr, jcong);
+
+	let mut mix = [0; PROGPOW_REGS];
+	for i in 0..mix.len() {
+		mix[i] = rnd.next_u32();
+	}
+
+	mix
+}
+
+// Merge new data from b into the value in a. Assuming A has high entropy only
+// do ops that retain entropy even if B is low entropy (IE don't do A&B)
+fn merge(a: u32, b: u32, r: u32) -> u32 {
+	match r % 4 {
+		0 => a.wrapping_mul(33).wrapping_add(b),
+		1 => (a ^ b).wrapping_mul(33),
+		2 => a.rotate_left(((r >> 16) % 31) + 1) ^ b,
+		_ => a.rotate_right(((r >> 16) % 31) + 1) ^ b,
+	}
+}
+
+// Random math between two input values
+fn math(a: u32, b: u32, r: u32) -> u32 {
+	match r % 11 {
+		0 => a.wrapping_add(b),
+		1 => a.wrapping_mul(b),
+		2 => ((a as u64).wrapping_mul(b as u64) >> 32) as u32,
+		3 => a.min(b),
+		4 => a.rotate_left(b),
+		5 => a.rotate_right(b),
+		6 => a & b,
+		7 => a | b,
+		8 => a ^ b,
+		9 => a.leading_zeros() + b.leading_zeros(),
+		_ => a.count_ones() + b.count_ones(),
+	}
+}
+
+fn progpow_init(seed: u64) -> (Kiss99, [u32; PROGPOW_REGS], [u32; PROGPOW_REGS]) {
+	let z = fnv1a_hash(FNV_HASH, seed as u32);
+	let w = fnv1a_hash(z, (seed >> 32) as u32);
+	let jsr = fnv1a_hash(w, seed as u32);
+	let jcong = fnv1a_hash(jsr, (seed >> 32) as u32);
+
+	let mut rnd = Kiss99::new(z, w, jsr, jcong);
+
+	// Create a random sequence of mix destinations for merge() and mix
+	// sources for cache reads. This guarantees every destination is merged
+	// once and that there are no duplicate cache reads, which could be
+	// optimized away. Uses Fisher-Yates shuffle.
+	let mut mix_seq_dst = [0u32; PROGPOW_REGS];
+	let mut mix_seq_src = [0u32; PROGPOW_REGS];
+	for i in 0..mix_seq_dst.len() {
+		mix_seq_dst[i] = i as u32;
+		mix_seq_src[i] = i as u32;
+	}
+
+	for i in (1..mix_seq_dst.len()).rev() {
+		let j = rnd.next_u32() as usize % (i + 1);
+		mix_seq_dst.swap(i, j);
+
+		let j = rnd.next_u32() as usize % (i + 1);
+		mix_seq_src.swap(i, j);
+	}
+
+	(rnd, mix_seq_dst, mix_seq_src)
+}
+
+fn progpow_loop(
+	seed: u64,
+	loop_: usize,
+	mix: &mut [[u32; PROGPOW_REGS]; PROGPOW_LANES],
+	cache: &[Node],
+	c_dag: &CDag,
+	data_size: usize,
+) {
+	// All lanes share a base address for the global load. Global offset uses
+	// mix[0] to guarantee it depends on the load result.
+	let g_offset = mix[loop_ % PROGPOW_LANES][0] as usize % data_size;
+
+	// 256 bytes of DAG data, calculated from the light cache
+	let mut dag_item = [0u32; 64];
+
+	for l in 0..PROGPOW_DAG_LOADS {
+		let node = calculate_dag_item((g_offset * PROGPOW_DAG_LOADS + l) as u32, cache);
+		dag_item[l * 16..(l + 1) * 16].clone_from_slice(node.as_words());
+	}
+
+	let (rnd, mix_seq_dst, mix_seq_src) = progpow_init(seed);
+
+	// Lanes can execute in parallel and will be convergent
+	for l in 0..mix.len() {
+		let mut rnd = rnd.clone();
+
+		// Initialize the seed and mix destination sequence
+		let mut mix_seq_dst_cnt = 0;
+		let mut mix_seq_src_cnt = 0;
+
+		let mut mix_dst = || {
+			let res = mix_seq_dst[mix_seq_dst_cnt % PROGPOW_REGS] as usize;
+			mix_seq_dst_cnt += 1;
+			res
+		};
+		let mut mix_src = || {
+			let res = mix_seq_src[mix_seq_src_cnt % PROGPOW_REGS] as usize;
+			mix_seq_src_cnt += 1;
+			res
+		};
+
+		for i in 0..PROGPOW_CNT_CACHE.max(PROGPOW_CNT_MATH) {
+			if i < PROGPOW_CNT_CACHE {
+				// Cached memory access, lanes access random 32-bit locations
+				// within the first portion of the DAG
+				let offset = mix[l][mix_src()] as usize % PROGPOW_CACHE_WORDS;
+				let data = c_dag[offset];
+				let dst = mix_dst();
+
+				mix[l][dst] = merge(mix[l][dst], data, rnd.next_u32());
+			}
+
+			if i < PROGPOW_CNT_MATH {
+				// Random math, generate 2 unique sources
+				let src_rnd = rnd.next_u32() % (PROGPOW_REGS * (PROGPOW_REGS - 1)) as u32;
+				let src1 = src_rnd % PROGPOW_REGS as u32;
+				let mut src2 = src_rnd / PROGPOW_REGS as u32;
+				if src2 >= src1 {
+					src2 += 1;
+				}
+
+				let data = math(mix[l][src1 as usize], mix[l][src2 as usize], rnd.next_u32());
+				let dst = mix_dst();
+
+				mix[l][dst] = merge(mix[l][dst], data, rnd.next_u32());
+			}
+		}
+
+		// Global load to sequential locations
+		let mut data_g = [0u32; PROGPOW_DAG_LOADS];
+		let index = ((l ^ loop_) % PROGPOW_LANES) * PROGPOW_DAG_LOADS;
+		for i in 0..PROGPOW_DAG_LOADS {
+			data_g[i] = dag_item[index + i];
+		}
+
+		// Consume the global load data at the very end of the loop to allow
+		// full latency hiding. Always merge into `mix[0]` to feed the offset
+		// calculation.
+		mix[l][0] = merge(mix[l][0], data_g[0], rnd.next_u32());
+		for i in 1..PROGPOW_DAG_LOADS {
+			let dst = mix_dst();
+			mix[l][dst] = merge(mix[l][dst], data_g[i], rnd.next_u32());
+		}
+	}
+}
+
+/// Calculate the ProgPoW hash using only the light cache.
+/// Returns the final hash (compared against the boundary) and the mix digest.
+pub fn progpow(
+	header_hash: H256,
+	nonce: u64,
+	block_number: u64,
+	cache: &[Node],
+	c_dag: &CDag,
+) -> (H256, H256) {
+	let mut mix = [[0u32; PROGPOW_REGS]; PROGPOW_LANES];
+	let mut lane_results = [0u32; PROGPOW_LANES];
+	let mut result = [0u32; 8];
+
+	let data_size = get_data_size(block_number) / PROGPOW_MIX_BYTES;
+
+	// NOTE: This assert is required to aid the optimizer elide the non-zero
+	// remainder check in `progpow_loop`.
+	assert!(data_size > 0);
+
+	// Initialize mix for all lanes
+	let seed = keccak_f800_short(header_hash, nonce, result);
+
+	for l in 0..mix.len() {
+		mix[l] = fill_mix(seed, l as u32);
+	}
+
+	// Execute the randomly generated inner loop
+	let period = block_number / PROGPOW_PERIOD_LENGTH;
+	for i in 0..PROGPOW_CNT_DAG {
+		progpow_loop(period, i, &mut mix, cache, c_dag, data_size);
+	}
+
+	// Reduce mix data to a single per-lane result
+	for l in 0..lane_results.len() {
+		lane_results[l] = FNV_HASH;
+		for i in 0..PROGPOW_REGS {
+			lane_results[l] = fnv1a_hash(lane_results[l], mix[l][i]);
+		}
+	}
+
+	// Reduce all lanes to a single 256-bit result
+	for i in 0..result.len() {
+		result[i] = FNV_HASH;
+	}
+
+	for l in 0..PROGPOW_LANES {
+		result[l % 8] = fnv1a_hash(result[l % 8], lane_results[l]);
+	}
+
+	let digest = keccak_f800_long(header_hash, seed, result);
+
+	(digest, words_to_bytes(&result))
+}
+
+/// Generate the cached portion of the DAG from the light cache.
+pub fn generate_cdag(cache: &[Node]) -> CDag {
+	let mut c_dag = [0u32; PROGPOW_CACHE_WORDS];
+
+	for i in 0..PROGPOW_CACHE_WORDS / 16 {
+		let node = calculate_dag_item(i as u32, cache);
+		for j in 0..16 {
+			c_dag[i * 16 + j] = node.as_words()[j];
+		}
+	}
+
+	c_dag
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use cache::{NodeCacheBuilder, OptimizeFor};
+	use tempdir::TempDir;
+
+	fn from_hex(s: &str) -> Vec<u8> {
+		(0..s.len() / 2).map(|i| u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap()).collect()
+	}
+
+	#[test]
+	fn test_kiss99() {
+		let mut rnd = Kiss99::new(362436069, 521288629, 123456789, 380116160);
+		assert_eq!(rnd.next_u32(), 769445856);
+		assert_eq!(rnd.next_u32(), 742012328);
+		assert_eq!(rnd.next_u32(), 2121196314);
+		assert_eq!(rnd.next_u32(), 2805620942);
+
+		for _ in 0..100000 - 5 {
+			rnd.next_u32();
+		}
+
+		// The 100000th number.
+		assert_eq!(rnd.next_u32(), 941074834);
+	}
+
+	#[test]
+	fn test_math() {
+		let tests = [
+			(0x8626BB1F, 0xBBDFBC4E, 0x883E5B49, 0x4206776D),
+			(0x3F4BDFAC, 0xD79E414F, 0x36B71236, 0x4C5CB214),
+			(0x6D175B7E, 0xC4E89D4C, 0x944ECABB, 0x53E9023F),
+			(0x2EDDD94C, 0x7E70CB54, 0x3F472A85, 0x2EDDD94C),
+			(0x8A81E396, 0x3F4BDFAC, 0xCEC46E67, 0x1E3968A8),
+			(0x8A81E396, 0x7E70CB54, 0xDBE71FF7, 0x1E3968A8),
+			(0xA7352F36, 0xA0EB7045, 0x59E7B9D8, 0xA0212004),
+			(0xC89805AF, 0x64291E2F, 0x1BDC84A9, 0xECB91FAF),
+			(0x760726D3, 0x79FC6A48, 0xC675CAC5, 0x0FFB4C9B),
+			(0x75551D43, 0x3383BA34, 0x2863AD31, 0x00000003),
+			(0xEA260841, 0xE92C44B7, 0xF83FFE7D, 0x0000001B),
+		];
+
+		for &(a, b, r, expected) in tests.iter() {
+			assert_eq!(math(a, b, r), expected);
+		}
+	}
+
+	#[test]
+	fn test_merge() {
+		let tests = [
+			(0x3B0BB37D, 0xA0212004, 0x9BD26AB0, 0x3CA34321),
+			(0x10C02F0D, 0x870FA227, 0xD4F45515, 0x91C1326A),
+			(0x24D2BAE4, 0x0FFB4C9B, 0x7FDBC2F2, 0x2EDDD94C),
+			(0xDA39E821, 0x089C4008, 0x8B6CD8C3, 0x8A81E396),
+		];
+
+		for &(a, b, r, expected) in tests.iter() {
+			assert_eq!(merge(a, b, r), expected);
+		}
+	}
+
+	#[test]
+	fn test_keccak_f800_long() {
+		let expected = from_hex("5dd431e5fbc604f499bfa0232f45f8f142d0ff5178f539e5a7800bf0643697af");
+		assert_eq!(keccak_f800_long([0; 32], 0, [0; 8])[..], expected[..]);
+	}
+
+	#[test]
+	fn test_words_to_bytes_roundtrip() {
+		let words = [0x01020304, 0xdeadbeef, 0, 1, 2, 3, 0xffffffff, 0x80000000];
+		assert_eq!(bytes_to_words(&words_to_bytes(&words)), words);
+	}
+
+	#[test]
+	fn test_progpow_hash() {
+		let tempdir = TempDir::new("").unwrap();
+		let builder = NodeCacheBuilder::new(OptimizeFor::Memory, u64::max_value());
+		let cache = builder.new_cache(tempdir.path().to_owned(), 0);
+		let c_dag = generate_cdag(cache.as_ref());
+
+		let (digest, result) = progpow([0; 32], 0, 0, cache.as_ref(), &c_dag);
+
+		let expected_digest = from_hex("63155f732f2bf556967f906155b510c917e48e99685ead76ea83f4eca03ab12b");
+		let expected_result = from_hex("faeb1be51075b03a4ff44b335067951ead07a3b078539ace76fd56fc410557a3");
+
+		assert_eq!(digest[..], expected_digest[..]);
+		assert_eq!(result[..], expected_result[..]);
+	}
+
+	#[test]
+	fn test_progpow_hash_epoch_one() {
+		let tempdir = TempDir::new("").unwrap();
+		let builder = NodeCacheBuilder::new(OptimizeFor::Memory, u64::max_value());
+		let cache = builder.new_cache(tempdir.path().to_owned(), 30000);
+		let c_dag = generate_cdag(cache.as_ref());
+
+		let mut header_hash = [0; 32];
+		header_hash.copy_from_slice(&from_hex("ffeeddccbbaa9988776655443322110000112233445566778899aabbccddeeff"));
+
+		let (digest, result) = progpow(header_hash, 0x123456789abcdef0, 30000, cache.as_ref(), &c_dag);
+
+		let expected_digest = from_hex("5b7ccd472dbefdd95b895cac8ece67ff0deb5a6bd2ecc6e162383d00c3728ece");
+		let expected_result = from_hex("11f19805c58ab46610ff9c719dcf0a5f18fa2f1605798eef770c47219274767d");
+
+		assert_eq!(digest[..], expected_digest[..]);
+		assert_eq!(result[..], expected_result[..]);
+	}
+
+	#[test]
+	fn test_progpow_quick_difficulty_matches() {
+		let tempdir = TempDir::new("").unwrap();
+		let builder = NodeCacheBuilder::new(OptimizeFor::Memory, u64::max_value());
+		let cache = builder.new_cache(tempdir.path().to_owned(), 0);
+		let c_dag = generate_cdag(cache.as_ref());
+
+		let header_hash = [0xab; 32];
+		let nonce = 0x123456789abcdef0;
+		let (digest, result) = progpow(header_hash, nonce, 0, cache.as_ref(), &c_dag);
+
+		// The final hash can be recomputed from the mix digest alone.
+		let seed = keccak_f800_short(header_hash, nonce, [0; 8]);
+		assert_eq!(keccak_f800_long(header_hash, seed, bytes_to_words(&result)), digest);
+	}
+}
@@ -0,0 +1,102 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.	 See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+#[macro_use]
+extern crate criterion;
+
+extern crate ethcore;
+extern crate ethcore_transaction as transaction;
+extern crate ethereum_types;
+extern crate ethkey;
+extern crate keccak_hash as hash;
+extern crate rlp;
+
+use criterion::Criterion;
+use ethcore::CompressedColumn;
+use ethcore::log_entry::LogEntry;
+use ethcore::receipt::{Receipt, TransactionOutcome};
+use ethereum_types::{Address, H256, U256};
+use ethkey::{Generator, Random};
+use hash::keccak;
+use rlp::RlpStream;
+use transaction::{Action, Transaction};
+
+/// Number of transactions in the benchmarked block.
+const TRANSACTIONS: usize = 200;
+
+/// Body of a block full of token transfers from distinct senders.
+fn token_transfers_body() -> Vec<u8> {
+	let token = Address::from(0x1234);
+	let mut stream = RlpStream::new_list(2);
+	stream.begin_list(TRANSACTIONS);
+	for i in 0..TRANSACTIONS {
+		let keypair = Random.generate().unwrap();
+		let mut data = keccak("transfer(address,uint256)")[..4].to_vec();
+		data.extend_from_slice(&H256::from(Address::random()));
+		data.extend_from_slice(&H256::from(U256::from(i as u64 * 1_000_000)));
+		stream.append(&Transaction {
+			action: Action::Call(token),
+			value: U256::zero(),
+			data,
+			gas: 60_000.into(),
+			gas_price: 20_000_000_000u64.into(),
+			nonce: 0.into(),
+		}.sign(keypair.secret(), Some(1)));
+	}
+	stream.begin_list(0);
+	stream.out()
+}
+
+/// Receipts of a block full of token transfers.
+fn token_transfers_receipts() -> Vec<u8> {
+	let token = Address::from(0x1234);
+	let mut stream = RlpStream::new_list(TRANSACTIONS);
+	for i in 0..TRANSACTIONS {
+		stream.append(&Receipt::new(
+			TransactionOutcome::StatusCode(1),
+			U256::from(52_000 * (i as u64 + 1)),
+			vec![LogEntry {
+				address: token,
+				topics: vec![
+					keccak("Transfer(address,address,uint256)"),
+					H256::from(Address::random()),
+					H256::from(Address::random()),
+				],
+				data: H256::from(U256::from(i as u64 * 1_000_000)).to_vec(),
+			}],
+		));
+	}
+	stream.out()
+}
+
+fn bench_column(c: &mut Criterion, name: &str, column: CompressedColumn, rlp: Vec<u8>) {
+	let encoded = column.encode(&rlp);
+	println!("{}: {} bytes stored as {} bytes", name, rlp.len(), encoded.len());
+
+	c.bench_function(&format!("{}_encode", name), move |b| b.iter(|| column.encode(&rlp)));
+	c.bench_function(&format!("{}_decode", name), move |b| b.iter(|| column.decode(&encoded).unwrap()));
+}
+
+fn bodies(c: &mut Criterion) {
+	bench_column(c, "bodies", CompressedColumn::Bodies, token_transfers_body());
+}
+
+fn receipts(c: &mut Criterion) {
+	bench_column(c, "receipts", CompressedColumn::Receipts, token_transfers_receipts());
+}
+
+criterion_group!(block_compression, bodies, receipts);
+criterion_main!(block_compression);
@@ -237,6 +237,7 @@ impl<T: ChainDataFetcher> Client<T> {
 			ancient_block_number: if first_block.is_some() { Some(0) } else { None },
 			first_block_hash: first_block.as_ref().map(|first| first.hash),
 			first_block_number: first_block.as_ref().map(|first| first.number),
+			first_history_block: None,
 		}
 	}
 
@@ -246,6 +246,14 @@ impl IoHandler<ClientIoMessage> for ClientIoHandler {
 			ClientIoMessage::FeedBlockChunk(ref hash, ref chunk) => {
 				self.snapshot.feed_block_chunk(*hash, chunk)
 			}
+			ClientIoMessage::BeginStateRestoration(ref block, ref receipts, ref total_difficulty) => {
+				if let Err(e) = self.snapshot.init_state_restore(block, receipts, *total_difficulty) {
+					warn!("Failed to initialize state restoration: {}", e);
+				}
+			}
+			ClientIoMessage::FeedStateData(ref data) => {
+				self.snapshot.feed_state_data(data)
+			}
 			ClientIoMessage::TakeSnapshot(num) => {
 				let client = self.client.clone();
 				let snapshot = self.snapshot.clone();
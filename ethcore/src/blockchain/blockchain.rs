@@ -17,20 +17,21 @@
 //! Blockchain database.
 
 use std::collections::{HashMap, HashSet};
-use std::{mem, io};
+use std::{cmp, mem, io};
 use std::path::Path;
 use std::sync::Arc;
 
 use ansi_term::Colour;
 use blockchain::{CacheSize, ImportRoute, Config};
 use blockchain::best_block::{BestBlock, BestAncientBlock};
+use blockchain::compression::CompressedColumn;
 use blockchain::block_info::{BlockInfo, BlockLocation, BranchBecomingCanonChainData};
-use blockchain::extras::{BlockReceipts, BlockDetails, TransactionAddress, EPOCH_KEY_PREFIX, EpochTransitions};
+use blockchain::extras::{BlockReceipts, BlockDetails, TransactionAddress, EPOCH_KEY_PREFIX, FIRST_HISTORY_KEY, EpochTransitions};
 use blockchain::update::{ExtrasUpdate, ExtrasInsert};
 use blooms_db;
 use bytes::Bytes;
 use cache_manager::CacheManager;
-use db::{self, Writable, Readable, CacheUpdatePolicy};
+use db::{self, Writable, Readable, Key, CacheUpdatePolicy};
 use encoded;
 use engines::epoch::{Transition as EpochTransition, PendingTransition as PendingEpochTransition};
 use engines::ForkChoice;
@@ -214,6 +215,9 @@ pub struct BlockChain {
 	// Stores the last block of the last sequence of blocks. `None` if there are no gaps.
 	// This is calculated on start and does not get updated.
 	first_block: Option<H256>,
+	// Stores the number of the first block whose body and receipts are kept. `None` if no history has expired.
+	// Only updated with `expire_history`.
+	first_history_block: RwLock<Option<BlockNumber>>,
 
 	// block cache
 	block_headers: RwLock<HashMap<H256, encoded::Header>>,
@@ -314,7 +318,9 @@ impl BlockProvider for BlockChain {
 		let b = self.db.key_value().get(db::COL_BODIES, hash)
 			.expect("Low level database error when fetching block body data. Some issue with disk?")?;
 
-		let body = encoded::Body::new(decompress(&b, blocks_swapper()).into_vec());
+		let body = CompressedColumn::Bodies.decode(&b)
+			.expect("Corrupted block body data in the database. Some issue with disk?");
+		let body = encoded::Body::new(body);
 		let mut write = self.block_bodies.write();
 		write.insert(*hash, body.clone());
 
@@ -345,7 +351,24 @@ impl BlockProvider for BlockChain {
 
 	/// Get receipts of block with given hash.
 	fn block_receipts(&self, hash: &H256) -> Option<BlockReceipts> {
-		let result = self.db.key_value().read_with_cache(db::COL_EXTRA, &self.block_receipts, hash)?;
+		// Check cache first
+		{
+			let read = self.block_receipts.read();
+			if let Some(v) = read.get(hash) {
+				return Some(v.clone());
+			}
+		}
+
+		// Read from DB and populate cache
+		let key = Key::<BlockReceipts>::key(hash);
+		let b = self.db.key_value().get(db::COL_EXTRA, &key)
+			.expect("Low level database error when fetching block receipts. Some issue with disk?")?;
+
+		let receipts = CompressedColumn::Receipts.decode(&b)
+			.expect("Corrupted block receipts data in the database. Some issue with disk?");
+		let result: BlockReceipts = ::rlp::decode(&receipts).expect("decode db value failed");
+		self.block_receipts.write().insert(*hash, result.clone());
+
 		self.cache_man.lock().note_used(CacheId::BlockReceipts(*hash));
 		Some(result)
 	}
@@ -524,6 +547,7 @@ impl BlockChain {
 
 		let mut bc = BlockChain {
 			first_block: None,
+			first_history_block: RwLock::new(None),
 			best_block: RwLock::new(BestBlock {
 				// BestBlock will be overwritten anyway.
 				header: Default::default(),
@@ -654,6 +678,11 @@ impl BlockChain {
 					number: number,
 				});
 			}
+
+			// load the expired history marker
+			*bc.first_history_block.write() = bc.db.key_value().get(db::COL_EXTRA, FIRST_HISTORY_KEY)
+				.expect("Low level database error when fetching 'first history' block. Some issue with disk?")
+				.map(|v| ::rlp::decode(&v).expect("decode error: the db is corrupted or the data structure has changed"));
 		}
 
 		bc
@@ -781,12 +810,17 @@ impl BlockChain {
 
 		assert!(self.pending_best_block.read().is_none());
 
+		// blocks older than the expired history are stored without their body and receipts.
+		let history_expired = self.first_history_block().map_or(false, |first| block_number < first);
+
 		let compressed_header = compress(block.header_view().rlp().as_raw(), blocks_swapper());
-		let compressed_body = compress(&Self::block_to_body(block.raw()), blocks_swapper());
 
 		// store block in db
 		batch.put(db::COL_HEADERS, &hash, &compressed_header);
-		batch.put(db::COL_BODIES, &hash, &compressed_body);
+		if !history_expired {
+			let compressed_body = CompressedColumn::Bodies.encode(&Self::block_to_body(block.raw()));
+			batch.put(db::COL_BODIES, &hash, &compressed_body);
+		}
 
 		let maybe_parent = self.block_details(&block_parent_hash);
 
@@ -802,9 +836,9 @@ impl BlockChain {
 			self.prepare_update(batch, ExtrasUpdate {
 				block_hashes: self.prepare_block_hashes_update(&info),
 				block_details: self.prepare_block_details_update(block_parent_hash, &info, false),
-				block_receipts: self.prepare_block_receipts_update(receipts, &info),
+				block_receipts: if history_expired { HashMap::new() } else { self.prepare_block_receipts_update(receipts, &info) },
 				blocks_blooms: self.prepare_block_blooms_update(block.header_view().log_bloom(), &info),
-				transactions_addresses: self.prepare_transaction_addresses_update(block.view().transaction_hashes(), &info),
+				transactions_addresses: if history_expired { HashMap::new() } else { self.prepare_transaction_addresses_update(block.view().transaction_hashes(), &info) },
 				info: info,
 				block,
 			}, is_best);
@@ -841,9 +875,9 @@ impl BlockChain {
 			self.prepare_update(batch, ExtrasUpdate {
 				block_hashes: self.prepare_block_hashes_update(&info),
 				block_details: update,
-				block_receipts: self.prepare_block_receipts_update(receipts, &info),
+				block_receipts: if history_expired { HashMap::new() } else { self.prepare_block_receipts_update(receipts, &info) },
 				blocks_blooms: self.prepare_block_blooms_update(block.header_view().log_bloom(), &info),
-				transactions_addresses: self.prepare_transaction_addresses_update(block.view().transaction_hashes(), &info),
+				transactions_addresses: if history_expired { HashMap::new() } else { self.prepare_transaction_addresses_update(block.view().transaction_hashes(), &info) },
 				info: info,
 				block,
 			}, is_best);
@@ -1013,6 +1047,170 @@ impl BlockChain {
 		self.db.key_value().read(db::COL_EXTRA, &hash)
 	}
 
+	/// Rewind the canon chain to the given block, making it the best block. The blocks above it
+	/// become unknown to the canon chain indexes, but stay readable until `remove_blocks`.
+	///
+	/// Returns the hashes of the rewound blocks, or `None` if the block isn't in the canon chain.
+	/// Must not be called while a block is being inserted.
+	pub fn rewind(&self, to: H256) -> Option<Vec<H256>> {
+		if !self.is_canon(&to) {
+			return None;
+		}
+
+		let mut details = self.block_details(&to)?;
+		let block = self.block(&to)?;
+		let best_number = self.best_block_number();
+
+		let mut batch = DBTransaction::new();
+		let mut rewound = Vec::new();
+		let mut rewound_transactions = Vec::new();
+
+		for number in (details.number + 1)..(best_number + 1) {
+			let hash = self.block_hash(number).expect("canon chain contains every block up to the best one; qed");
+			if let Some(body) = self.block_body(&hash) {
+				rewound_transactions.extend(body.transaction_hashes());
+			}
+			Writable::delete::<H256, _>(&mut batch, db::COL_EXTRA, &number);
+			rewound.push(hash);
+		}
+
+		for hash in &rewound_transactions {
+			Writable::delete::<TransactionAddress, _>(&mut batch, db::COL_EXTRA, hash);
+		}
+
+		details.children.retain(|child| !rewound.contains(child));
+		batch.write(db::COL_EXTRA, &to, &details);
+		batch.put(db::COL_EXTRA, b"best", &to);
+		self.db.key_value().write(batch).expect("Low level database error. Some issue with disk?");
+
+		// the blooms of the rewound blocks would match filters beyond the best block.
+		let empty_blooms = vec![Bloom::default(); rewound.len()];
+		self.db.blooms()
+			.insert_blooms(details.number + 1, empty_blooms.iter())
+			.expect("Low level database error when updating blooms. Some issue with disk?");
+
+		// lock order as in `commit` and `collect_garbage`.
+		let mut best_block = self.best_block.write();
+		let mut block_details = self.block_details.write();
+		let mut block_hashes = self.block_hashes.write();
+		let mut transaction_addresses = self.transaction_addresses.write();
+
+		for number in (details.number + 1)..(best_number + 1) {
+			block_hashes.remove(&number);
+		}
+		for hash in &rewound_transactions {
+			transaction_addresses.remove(hash);
+		}
+
+		*best_block = BestBlock {
+			total_difficulty: details.total_difficulty,
+			header: block.decode_header(),
+			block: block,
+		};
+		block_details.insert(to, details);
+
+		Some(rewound)
+	}
+
+	/// Remove blocks which aren't in the canon chain anymore from the database, along with
+	/// their receipts and epoch transitions. Canon blocks are kept.
+	pub fn remove_blocks(&self, hashes: &[H256]) {
+		let removed: Vec<(H256, BlockNumber)> = hashes.iter()
+			.filter(|hash| !self.is_canon(hash))
+			.filter_map(|hash| self.block_number(hash).map(|number| (*hash, number)))
+			.collect();
+
+		let mut batch = DBTransaction::new();
+		for &(ref hash, number) in &removed {
+			batch.delete(db::COL_HEADERS, hash);
+			batch.delete(db::COL_BODIES, hash);
+			Writable::delete::<BlockDetails, _>(&mut batch, db::COL_EXTRA, hash);
+			Writable::delete::<BlockReceipts, _>(&mut batch, db::COL_EXTRA, hash);
+			Writable::delete::<PendingEpochTransition, _>(&mut batch, db::COL_EXTRA, hash);
+
+			let transitions: Option<EpochTransitions> = self.db.key_value().read(db::COL_EXTRA, &number);
+			if let Some(mut transitions) = transitions {
+				transitions.candidates.retain(|candidate| candidate.block_hash != *hash);
+				if transitions.candidates.is_empty() {
+					Writable::delete::<EpochTransitions, _>(&mut batch, db::COL_EXTRA, &number);
+				} else {
+					batch.write(db::COL_EXTRA, &number, &transitions);
+				}
+			}
+		}
+		self.db.key_value().write(batch).expect("Low level database error. Some issue with disk?");
+
+		// lock order as in `commit` and `collect_garbage`.
+		let mut block_headers = self.block_headers.write();
+		let mut block_bodies = self.block_bodies.write();
+		let mut block_details = self.block_details.write();
+		let mut block_receipts = self.block_receipts.write();
+
+		for &(ref hash, _) in &removed {
+			block_headers.remove(hash);
+			block_bodies.remove(hash);
+			block_details.remove(hash);
+			block_receipts.remove(hash);
+		}
+	}
+
+	/// Remove the body, receipts and transaction addresses of the given block, keeping its header and details.
+	///
+	/// The block stays known and canonical, but its body and receipts are no longer available.
+	pub fn prune_block_history(&self, batch: &mut DBTransaction, hash: &H256) {
+		let transaction_hashes = self.block_body(hash)
+			.map(|body| body.transaction_hashes())
+			.unwrap_or_default();
+
+		batch.delete(db::COL_BODIES, hash);
+		Writable::delete::<BlockReceipts, _>(batch, db::COL_EXTRA, hash);
+		for transaction_hash in &transaction_hashes {
+			Writable::delete::<TransactionAddress, _>(batch, db::COL_EXTRA, transaction_hash);
+		}
+
+		// lock order as in `commit` and `collect_garbage`.
+		let mut block_bodies = self.block_bodies.write();
+		let mut transaction_addresses = self.transaction_addresses.write();
+		let mut block_receipts = self.block_receipts.write();
+		block_bodies.remove(hash);
+		for transaction_hash in &transaction_hashes {
+			transaction_addresses.remove(transaction_hash);
+		}
+		block_receipts.remove(hash);
+	}
+
+	/// Number of the first block whose body and receipts are kept, if the history of older blocks has expired.
+	pub fn first_history_block(&self) -> Option<BlockNumber> {
+		*self.first_history_block.read()
+	}
+
+	/// Expire the history of at most `limit` canonical blocks below `before`, continuing
+	/// from where the previous call stopped. The genesis block is always kept.
+	///
+	/// Ancient blocks imported below the first history block afterwards are stored without
+	/// their history. Returns the hashes of the blocks whose history has been removed.
+	pub fn expire_history(&self, batch: &mut DBTransaction, before: BlockNumber, limit: u64) -> Vec<H256> {
+		let from = self.first_history_block().unwrap_or(1);
+		let to = cmp::min(before, from.saturating_add(limit));
+
+		if from >= to {
+			return Vec::new();
+		}
+
+		let expired: Vec<H256> = (from..to)
+			.filter_map(|number| self.block_hash(number))
+			.collect();
+
+		for hash in &expired {
+			self.prune_block_history(batch, hash);
+		}
+
+		batch.put(db::COL_EXTRA, FIRST_HISTORY_KEY, &::rlp::encode(&to));
+		*self.first_history_block.write() = Some(to);
+
+		expired
+	}
+
 	/// Add a child to a given block. Assumes that the block hash is in
 	/// the chain and the child's parent is this block.
 	///
@@ -1058,7 +1256,7 @@ impl BlockChain {
 		assert!(self.pending_best_block.read().is_none());
 
 		let compressed_header = compress(block.header_view().rlp().as_raw(), blocks_swapper());
-		let compressed_body = compress(&Self::block_to_body(block.raw()), blocks_swapper());
+		let compressed_body = CompressedColumn::Bodies.encode(&Self::block_to_body(block.raw()));
 
 		// store block in db
 		batch.put(db::COL_HEADERS, &hash, &compressed_header);
@@ -1147,7 +1345,11 @@ impl BlockChain {
 
 		{
 			let mut write_receipts = self.block_receipts.write();
-			batch.extend_with_cache(db::COL_EXTRA, &mut *write_receipts, update.block_receipts, CacheUpdatePolicy::Remove);
+			for (hash, receipts) in update.block_receipts {
+				let compressed_receipts = CompressedColumn::Receipts.encode(&::rlp::encode(&receipts));
+				batch.put(db::COL_EXTRA, &Key::<BlockReceipts>::key(&hash), &compressed_receipts);
+				write_receipts.remove(&hash);
+			}
 		}
 
 		if let Some((block, blooms)) = update.blocks_blooms {
@@ -1527,6 +1729,7 @@ impl BlockChain {
 		// recursive locking of `best_block`.
 		let first_block_hash = self.first_block();
 		let first_block_number = self.first_block_number().into();
+		let first_history_block = self.first_history_block();
 		let genesis_hash = self.genesis_hash();
 
 		// ensure data consistencly by locking everything first
@@ -1543,6 +1746,7 @@ impl BlockChain {
 			first_block_number,
 			ancient_block_hash: best_ancient_block.as_ref().map(|b| b.hash),
 			ancient_block_number: best_ancient_block.as_ref().map(|b| b.number),
+			first_history_block,
 		}
 	}
 }
@@ -2367,6 +2571,127 @@ mod tests {
 		assert_eq!(bc.best_block_number(), 5);
 	}
 
+	#[test]
+	fn test_rewind() {
+		use ::engines::EpochTransition;
+
+		let genesis = BlockBuilder::genesis();
+		let first = genesis.add_block();
+		let second = first.add_block();
+		let third = second.add_block();
+
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		for block in &[&first, &second, &third] {
+			insert_block(&db, &bc, block.last().encoded(), vec![]);
+		}
+		assert_eq!(bc.best_block_number(), 3);
+
+		let first_hash = first.last().hash();
+		let second_hash = second.last().hash();
+		let third_hash = third.last().hash();
+		let mut batch = DBTransaction::new();
+		bc.insert_epoch_transition(&mut batch, 2, EpochTransition {
+			block_hash: second_hash,
+			block_number: 2,
+			proof: vec![],
+		});
+		db.key_value().write(batch).unwrap();
+
+		assert_eq!(bc.rewind(second_hash), Some(vec![third_hash]));
+		assert_eq!(bc.rewind(first_hash), Some(vec![second_hash]));
+		assert_eq!(bc.best_block_hash(), first_hash);
+		assert_eq!(bc.block_hash(2), None);
+		assert!(bc.block_details(&first_hash).unwrap().children.is_empty());
+		assert!(bc.rewind(second_hash).is_none());
+
+		// rewound blocks stay readable until they are removed.
+		assert!(bc.is_known(&second_hash));
+		bc.remove_blocks(&[first_hash, second_hash, third_hash]);
+		assert!(bc.is_known(&first_hash));
+		assert!(!bc.is_known(&second_hash));
+		assert!(!bc.is_known(&third_hash));
+		assert!(bc.epoch_transition(2, second_hash).is_none());
+
+		// the rewound chain is persisted and can be extended again.
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		assert_eq!(bc.best_block_hash(), first_hash);
+		insert_block(&db, &bc, second.last().encoded(), vec![]);
+		assert_eq!(bc.best_block_hash(), second_hash);
+	}
+
+	#[test]
+	fn test_prune_block_history() {
+		let genesis = BlockBuilder::genesis();
+		let first = genesis.add_block();
+		let second = first.add_block();
+
+		let db = new_db();
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		insert_block(&db, &bc, first.last().encoded(), vec![]);
+		insert_block(&db, &bc, second.last().encoded(), vec![]);
+
+		let first_hash = first.last().hash();
+		assert!(bc.block_body(&first_hash).is_some());
+		assert!(bc.block_receipts(&first_hash).is_some());
+
+		let mut batch = DBTransaction::new();
+		bc.prune_block_history(&mut batch, &first_hash);
+		db.key_value().write(batch).unwrap();
+
+		assert!(bc.block_body(&first_hash).is_none());
+		assert!(bc.block_receipts(&first_hash).is_none());
+		assert!(bc.block_header_data(&first_hash).is_some());
+		assert!(bc.is_canon(&first_hash));
+		assert_eq!(bc.best_block_number(), 2);
+	}
+
+	#[test]
+	fn test_expire_history() {
+		let t1 = Transaction {
+			nonce: 0.into(),
+			gas_price: 0.into(),
+			gas: 100_000.into(),
+			action: Action::Create,
+			value: 100.into(),
+			data: "601080600c6000396000f3006000355415600957005b60203560003555".from_hex().unwrap(),
+		}.sign(&secret(), None);
+		let t1_hash = t1.hash();
+
+		let genesis = BlockBuilder::genesis();
+		let b1 = genesis.add_block_with_transactions(iter::once(t1));
+		let b3 = b1.add_blocks(2);
+
+		let db = new_db();
+		{
+			let bc = new_chain(genesis.last().encoded(), db.clone());
+			for block in BlockGenerator::new(vec![b1.clone(), b3.clone()]) {
+				insert_block(&db, &bc, block.encoded(), vec![]);
+			}
+			assert_eq!(bc.first_history_block(), None);
+			assert!(bc.transaction_address(&t1_hash).is_some());
+
+			let mut batch = DBTransaction::new();
+			assert_eq!(bc.expire_history(&mut batch, 3, 1), vec![b1.last().hash()]);
+			db.key_value().write(batch).unwrap();
+
+			assert_eq!(bc.first_history_block(), Some(2));
+			assert_eq!(bc.chain_info().first_history_block, Some(2));
+			assert!(bc.block_body(&b1.last().hash()).is_none());
+			assert!(bc.transaction_address(&t1_hash).is_none());
+			assert!(bc.block_body(&genesis.last().hash()).is_some());
+
+			let mut batch = DBTransaction::new();
+			assert_eq!(bc.expire_history(&mut batch, 3, 100).len(), 1);
+			assert!(bc.expire_history(&mut batch, 3, 100).is_empty());
+			db.key_value().write(batch).unwrap();
+			assert!(bc.block_body(&b3.last().hash()).is_some());
+		}
+
+		let bc = new_chain(genesis.last().encoded(), db.clone());
+		assert_eq!(bc.first_history_block(), Some(3));
+	}
+
 	#[test]
 	fn epoch_transitions_iter() {
 		use ::engines::EpochTransition;
@@ -0,0 +1,250 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Versioned storage format of block bodies and receipts.
+//!
+//! Values written before this format existed are plain RLP lists (bodies go through the
+//! `rlp-compress` swapper first), so their first byte is always `0xc0` or above. Compressed
+//! values start with a format tag below `0xc0`, followed by the big-endian length of the
+//! uncompressed payload and a zstd frame compressed against the column's shared dictionary.
+//!
+//! Both layouts are readable, so databases written by older versions keep working and the
+//! encoder falls back to the plain layout whenever compression doesn't pay off. A dictionary
+//! must never change once values have been written with its tag; add a new tag instead.
+
+use std::io;
+
+use byteorder::{BigEndian, ByteOrder};
+use hash::{keccak, KECCAK_EMPTY, KECCAK_NULL_RLP};
+use rlp;
+use rlp_compress::{compress, decompress, blocks_swapper};
+use zstd::block::{Compressor, Decompressor};
+
+/// Tag of values compressed with zstd against the first version of the column dictionary.
+const ZSTD_DICT_V1: u8 = 1;
+/// Values starting with a byte at or above this are stored uncompressed.
+const RLP_LIST_OFFSET: u8 = 0xc0;
+/// Length of the tag and payload length prefixed to compressed values.
+const HEADER_LEN: usize = 5;
+/// zstd compression level. Higher levels barely help on values of this size.
+const COMPRESSION_LEVEL: i32 = 3;
+
+lazy_static! {
+	static ref BODIES_DICTIONARY: Vec<u8> = bodies_dictionary();
+	static ref RECEIPTS_DICTIONARY: Vec<u8> = receipts_dictionary();
+}
+
+/// Database column stored in the compressed format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressedColumn {
+	/// Block bodies, keyed by block hash.
+	Bodies,
+	/// Block receipts, stored in the extras column.
+	Receipts,
+}
+
+impl CompressedColumn {
+	/// Encode the RLP of a value for storage.
+	pub fn encode(&self, rlp: &[u8]) -> Vec<u8> {
+		let plain = self.plain(rlp);
+		let compressed = Compressor::with_dict(self.dictionary().clone())
+			.compress(&plain, COMPRESSION_LEVEL);
+
+		match compressed {
+			Ok(ref compressed) if compressed.len() + HEADER_LEN < plain.len() => {
+				let mut value = vec![0u8; HEADER_LEN];
+				value[0] = ZSTD_DICT_V1;
+				BigEndian::write_u32(&mut value[1..HEADER_LEN], plain.len() as u32);
+				value.extend_from_slice(compressed);
+				value
+			},
+			Ok(_) => plain,
+			Err(e) => {
+				warn!(target: "db", "Failed to compress {:?} value, storing it uncompressed: {}", self, e);
+				plain
+			},
+		}
+	}
+
+	/// Decode a stored value, in either layout, back into its RLP.
+	pub fn decode(&self, value: &[u8]) -> io::Result<Vec<u8>> {
+		match value.first() {
+			None => Err(invalid_data("empty value".into())),
+			Some(&tag) if tag >= RLP_LIST_OFFSET => Ok(self.unplain(value)),
+			Some(&ZSTD_DICT_V1) if value.len() >= HEADER_LEN => {
+				let len = BigEndian::read_u32(&value[1..HEADER_LEN]) as usize;
+				let plain = Decompressor::with_dict(self.dictionary().clone())
+					.decompress(&value[HEADER_LEN..], len)?;
+				if plain.len() != len {
+					return Err(invalid_data(format!("expected {} bytes, got {}", len, plain.len())));
+				}
+				Ok(self.unplain(&plain))
+			},
+			Some(&tag) => Err(invalid_data(format!("unknown storage format {}", tag))),
+		}
+	}
+
+	/// Returns true if the stored value uses the compressed layout.
+	pub fn is_compressed(value: &[u8]) -> bool {
+		value.first().map_or(false, |&tag| tag < RLP_LIST_OFFSET)
+	}
+
+	fn plain(&self, rlp: &[u8]) -> Vec<u8> {
+		match *self {
+			CompressedColumn::Bodies => compress(rlp, blocks_swapper()).into_vec(),
+			CompressedColumn::Receipts => rlp.to_vec(),
+		}
+	}
+
+	fn unplain(&self, plain: &[u8]) -> Vec<u8> {
+		match *self {
+			CompressedColumn::Bodies => decompress(plain, blocks_swapper()).into_vec(),
+			CompressedColumn::Receipts => plain.to_vec(),
+		}
+	}
+
+	fn dictionary(&self) -> &'static Vec<u8> {
+		match *self {
+			CompressedColumn::Bodies => &*BODIES_DICTIONARY,
+			CompressedColumn::Receipts => &*RECEIPTS_DICTIONARY,
+		}
+	}
+}
+
+fn invalid_data(msg: String) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Padding of an address to a 32-byte word, as found in call data and log topics.
+const ADDRESS_PADDING: [u8; 12] = [0u8; 12];
+
+/// Raw content dictionary of block bodies: common call data prefixes and uncle header fields.
+/// zstd prefers matches close to the end of the dictionary, so the most common content goes last.
+fn bodies_dictionary() -> Vec<u8> {
+	let mut dictionary = Vec::new();
+	for hash in &[KECCAK_NULL_RLP, KECCAK_EMPTY] {
+		dictionary.extend_from_slice(&rlp::encode(hash));
+	}
+	dictionary.extend_from_slice(&[0u8; 32]);
+	for signature in &["approve(address,uint256)", "transferFrom(address,address,uint256)", "transfer(address,uint256)"] {
+		dictionary.extend_from_slice(&keccak(signature)[..4]);
+		dictionary.extend_from_slice(&ADDRESS_PADDING);
+	}
+	dictionary
+}
+
+/// Raw content dictionary of block receipts: the empty bloom and topics of common events.
+fn receipts_dictionary() -> Vec<u8> {
+	let mut dictionary = Vec::new();
+	// RLP of an empty 256-byte bloom.
+	dictionary.extend_from_slice(&[0xb9, 0x01, 0x00]);
+	dictionary.extend_from_slice(&[0u8; 256]);
+	for event in &["Withdrawal(address,uint256)", "Deposit(address,uint256)", "Approval(address,address,uint256)", "Transfer(address,address,uint256)"] {
+		dictionary.extend_from_slice(&rlp::encode(&keccak(event)));
+		dictionary.push(0xa0);
+		dictionary.extend_from_slice(&ADDRESS_PADDING);
+	}
+	dictionary
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use ethereum_types::{Address, H256, U256};
+	use receipt::{Receipt, TransactionOutcome};
+	use log_entry::LogEntry;
+	use blockchain::BlockReceipts;
+	use blockchain::generator::BlockBuilder;
+	use transaction::{Action, Transaction};
+	use ethkey::{Generator, Random};
+	use rlp::RlpStream;
+
+	fn transfer_receipts(count: usize) -> Vec<u8> {
+		let token = Address::from(0x1234);
+		let receipts = (0..count).map(|i| Receipt::new(
+			TransactionOutcome::StatusCode(1),
+			U256::from(21_000 * (i as u64 + 1)),
+			vec![LogEntry {
+				address: token,
+				topics: vec![keccak("Transfer(address,address,uint256)"), H256::from(i as u64), H256::from(i as u64 + 1)],
+				data: H256::from(1_000u64).to_vec(),
+			}],
+		)).collect();
+		rlp::encode(&BlockReceipts::new(receipts)).into_vec()
+	}
+
+	#[test]
+	fn compresses_receipts() {
+		let receipts = transfer_receipts(50);
+		let encoded = CompressedColumn::Receipts.encode(&receipts);
+		assert!(CompressedColumn::is_compressed(&encoded));
+		assert!(encoded.len() * 4 < receipts.len(), "{} of {} bytes", encoded.len(), receipts.len());
+		assert_eq!(CompressedColumn::Receipts.decode(&encoded).unwrap(), receipts);
+	}
+
+	#[test]
+	fn compresses_bodies() {
+		let keypair = Random.generate().unwrap();
+		let transactions = (0..20).map(|nonce| Transaction {
+			action: Action::Call(Address::from(0x1234)),
+			value: U256::zero(),
+			data: keccak("transfer(address,uint256)")[..4].iter().cloned().chain(vec![0u8; 64]).collect(),
+			gas: 60_000.into(),
+			gas_price: 1.into(),
+			nonce: nonce.into(),
+		}.sign(keypair.secret(), None)).collect();
+		let block = BlockBuilder::genesis().add_block_with_transactions(transactions).last().encoded();
+		let body = ::blockchain::BlockChain::block_to_body(block.raw());
+
+		let encoded = CompressedColumn::Bodies.encode(&body);
+		assert!(CompressedColumn::is_compressed(&encoded));
+		assert!(encoded.len() < body.len());
+		assert_eq!(CompressedColumn::Bodies.decode(&encoded).unwrap(), body);
+	}
+
+	#[test]
+	fn keeps_small_values_uncompressed() {
+		let mut body = RlpStream::new_list(2);
+		body.begin_list(0);
+		body.begin_list(0);
+		let body = body.out();
+		let encoded = CompressedColumn::Bodies.encode(&body);
+		assert!(!CompressedColumn::is_compressed(&encoded));
+		assert_eq!(CompressedColumn::Bodies.decode(&encoded).unwrap(), body);
+	}
+
+	#[test]
+	fn reads_legacy_values() {
+		let mut body = RlpStream::new_list(2);
+		body.append_list(&[KECCAK_NULL_RLP]);
+		body.begin_list(0);
+		let body = body.out();
+		let legacy = compress(&body, blocks_swapper());
+		assert_eq!(CompressedColumn::Bodies.decode(&legacy).unwrap(), body);
+
+		let receipts = transfer_receipts(1);
+		assert_eq!(CompressedColumn::Receipts.decode(&receipts).unwrap(), receipts);
+	}
+
+	#[test]
+	fn rejects_unknown_format() {
+		let mut encoded = CompressedColumn::Receipts.encode(&transfer_receipts(50));
+		assert!(CompressedColumn::Receipts.decode(&encoded[..3]).is_err());
+		encoded[0] = 0x42;
+		assert!(CompressedColumn::Receipts.decode(&encoded).is_err());
+		assert!(CompressedColumn::Receipts.decode(&[]).is_err());
+	}
+}
@@ -108,6 +108,15 @@ impl Key<::engines::epoch::PendingTransition> for H256 {
 	}
 }
 
+/// Returns true if `key` is the extras column key of a block's receipts.
+pub fn is_block_receipts_key(key: &[u8]) -> bool {
+	key.len() == 33 && key[0] == ExtrasIndex::BlockReceipts as u8
+}
+
+/// Key under which the number of the first block with a body and receipts is stored,
+/// once the history of older blocks has expired.
+pub const FIRST_HISTORY_KEY: &'static [u8] = b"first_history";
+
 /// length of epoch keys.
 pub const EPOCH_KEY_LEN: usize = DB_PREFIX_LEN + 16;
 
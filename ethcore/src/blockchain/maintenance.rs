@@ -0,0 +1,287 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Offline maintenance of the blockchain database: consistency checks and history pruning.
+
+use std::sync::Arc;
+
+use ethereum_types::H256;
+use ethtrie::TrieDB;
+use hash::keccak;
+use hashdb::HashDB;
+use header::BlockNumber;
+use journaldb::{self, Algorithm};
+use keccak_hasher::KeccakHasher;
+use kvdb::{DBTransaction, DBValue};
+use rlp::Encodable;
+use trie::Trie;
+use trace::TraceDB;
+use triehash::ordered_trie_root;
+
+use super::{BlockChain, BlockChainDB, BlockProvider};
+
+// how often progress is logged and pruned blocks are written out, in blocks.
+const PROGRESS_INTERVAL: u64 = 10_000;
+
+/// Result of checking the canonical chain.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ChainCheck {
+	/// Number of canonical headers checked.
+	pub headers: u64,
+	/// Number of block bodies checked against their headers.
+	pub bodies: u64,
+	/// Number of block receipts checked against their headers.
+	pub receipts: u64,
+	/// Number of accounts found in the state of the best block.
+	pub accounts: u64,
+	/// Inconsistencies found.
+	pub errors: Vec<String>,
+}
+
+impl ChainCheck {
+	/// Whether no inconsistencies were found.
+	pub fn is_ok(&self) -> bool {
+		self.errors.is_empty()
+	}
+}
+
+/// Check the chain and state stored in the given database, using the given pruning
+/// algorithm to read the state. See `check_chain`.
+pub fn check_database(db: Arc<BlockChainDB>, genesis: &[u8], algorithm: Algorithm) -> ChainCheck {
+	// opening the chain of an empty database would write the genesis block.
+	match db.key_value().get(::db::COL_EXTRA, b"best") {
+		Ok(Some(_)) => {},
+		Ok(None) => return ChainCheck { errors: vec!["The database has no best block".into()], ..Default::default() },
+		Err(e) => return ChainCheck { errors: vec![format!("Failed to read the best block: {}", e)], ..Default::default() },
+	}
+
+	let chain = BlockChain::new(Default::default(), genesis, db.clone());
+	let state = journaldb::new(db.key_value().clone(), algorithm, ::db::COL_STATE);
+	check_chain(&chain, state.as_hashdb())
+}
+
+/// Remove the bodies, receipts and traces of the canonical blocks before `before`, keeping
+/// their headers. The genesis block is always kept.
+///
+/// Returns the number of blocks pruned.
+pub fn prune_history(db: Arc<BlockChainDB>, genesis: &[u8], before: BlockNumber) -> Result<u64, ::error::Error> {
+	let chain = Arc::new(BlockChain::new(Default::default(), genesis, db.clone()));
+	let best = chain.best_block_number();
+	if before > best {
+		return Err(format!("Cannot prune history beyond the best block #{}", best).into());
+	}
+
+	let tracedb = TraceDB::new(Default::default(), db.clone(), chain.clone());
+	let mut pruned = 0;
+	loop {
+		let start = chain.first_history_block();
+		let mut batch = DBTransaction::new();
+		let expired = chain.expire_history(&mut batch, before, PROGRESS_INTERVAL);
+		tracedb.expire_traces(&mut batch, &expired);
+		db.key_value().write(batch)?;
+		pruned += expired.len() as u64;
+
+		let first = chain.first_history_block();
+		if first == start {
+			break;
+		}
+		if let Some(first) = first {
+			info!(target: "db", "Pruned history up to block #{}", first - 1);
+		}
+	}
+
+	db.key_value().flush()?;
+	Ok(pruned)
+}
+
+/// Walk the canonical chain from the best block down to genesis, checking that every
+/// header links to its parent and matches the stored body and receipts, then walk the
+/// account trie of the best block's state root.
+///
+/// Blocks whose body or receipts are missing (not downloaded yet or pruned) are skipped,
+/// as is the gap left by an unfinished ancient block download.
+fn check_chain(chain: &BlockChain, state: &HashDB<KeccakHasher, DBValue>) -> ChainCheck {
+	let mut check = ChainCheck::default();
+	let best = chain.best_block_number();
+
+	// the range of ancient blocks yet to be downloaded, if any.
+	let gap = match (chain.best_ancient_number(), chain.first_block_number()) {
+		(Some(ancient), Some(first)) => Some((ancient + 1, first)),
+		_ => None,
+	};
+
+	// parent hash expected by the block above the current one.
+	let mut expected: Option<H256> = None;
+	for number in (0..best + 1).rev() {
+		if number % PROGRESS_INTERVAL == 0 {
+			info!(target: "db", "Checking block #{}", number);
+		}
+
+		if gap.map_or(false, |(start, end)| number >= start && number < end) {
+			expected = None;
+			continue;
+		}
+
+		let hash = match chain.block_hash(number) {
+			Some(hash) => hash,
+			None => {
+				check.errors.push(format!("#{}: missing from the canonical index", number));
+				expected = None;
+				continue;
+			}
+		};
+
+		if let Some(expected) = expected {
+			if expected != hash {
+				check.errors.push(format!("#{}: canonical hash {:x} is not the parent of #{} ({:x})", number, hash, number + 1, expected));
+			}
+		}
+
+		let header = match chain.block_header_data(&hash) {
+			Some(header) => header,
+			None => {
+				check.errors.push(format!("#{}: missing header {:x}", number, hash));
+				expected = None;
+				continue;
+			}
+		};
+
+		check.headers += 1;
+		expected = Some(header.parent_hash());
+
+		if header.hash() != hash {
+			check.errors.push(format!("#{}: header hashes to {:x} instead of {:x}", number, header.hash(), hash));
+		}
+		if header.number() != number {
+			check.errors.push(format!("#{}: header {:x} has number {}", number, hash, header.number()));
+		}
+
+		if let Some(body) = chain.block_body(&hash) {
+			check.bodies += 1;
+			let transactions_root = ordered_trie_root(body.transactions_rlp().iter().map(|r| r.as_raw()));
+			if transactions_root != header.transactions_root() {
+				check.errors.push(format!("#{}: body doesn't match the transactions root", number));
+			}
+			if keccak(body.uncles_rlp().as_raw()) != header.uncles_hash() {
+				check.errors.push(format!("#{}: body doesn't match the uncles hash", number));
+			}
+		}
+
+		if let Some(receipts) = chain.block_receipts(&hash) {
+			check.receipts += 1;
+			let receipts_root = ordered_trie_root(receipts.receipts.iter().map(|r| r.rlp_bytes()));
+			if receipts_root != header.receipts_root() {
+				check.errors.push(format!("#{}: receipts don't match the receipts root", number));
+			}
+		}
+	}
+
+	let state_root = *chain.best_block_header().state_root();
+	info!(target: "db", "Checking state at {:x}", state_root);
+	let walk = TrieDB::new(state, &state_root).and_then(|trie| {
+		for entry in trie.iter()? {
+			entry?;
+			check.accounts += 1;
+		}
+		Ok(())
+	});
+	if let Err(e) = walk {
+		check.errors.push(format!("state of the best block is incomplete: {}", e));
+	}
+
+	check
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use blockchain::ExtrasInsert;
+	use blockchain::generator::{BlockBuilder, BlockGenerator};
+	use header::Header;
+	use memorydb::MemoryDB;
+	use rlp::RlpStream;
+	use rlp_compress::{compress, blocks_swapper};
+	use test_helpers::new_db;
+
+	fn insert_blocks(db: &Arc<BlockChainDB>, genesis: &[u8], generator: BlockGenerator) -> BlockChain {
+		let bc = BlockChain::new(Default::default(), genesis, db.clone());
+		for block in generator {
+			let mut batch = DBTransaction::new();
+			bc.insert_block(&mut batch, block.encoded(), vec![], ExtrasInsert {
+				fork_choice: ::engines::ForkChoice::New,
+				is_finalized: false,
+			});
+			db.key_value().write(batch).unwrap();
+			bc.commit();
+		}
+		bc
+	}
+
+	#[test]
+	fn detects_body_not_matching_header() {
+		let genesis = BlockBuilder::genesis();
+		let generator = BlockGenerator::new(vec![genesis.add_blocks(3)]);
+		let genesis = genesis.last().encoded();
+
+		let db = new_db();
+		let bc = insert_blocks(&db, genesis.raw(), generator);
+
+		// generated blocks have an empty state.
+		let state = MemoryDB::<KeccakHasher, DBValue>::new();
+		let check = check_chain(&bc, &state);
+		assert!(check.is_ok(), "{:?}", check.errors);
+		assert_eq!(check.headers, 4);
+		assert_eq!(check.bodies, 4);
+
+		// give block #2 an uncle its header doesn't commit to.
+		let mut body = RlpStream::new_list(2);
+		body.begin_list(0);
+		body.begin_list(1).append(&Header::default());
+		let mut batch = DBTransaction::new();
+		batch.put(::db::COL_BODIES, &bc.block_hash(2).unwrap(), &compress(&body.out(), blocks_swapper()));
+		db.key_value().write(batch).unwrap();
+
+		let bc = BlockChain::new(Default::default(), genesis.raw(), db.clone());
+		let check = check_chain(&bc, &state);
+		assert_eq!(check.errors, vec!["#2: body doesn't match the uncles hash".to_owned()]);
+	}
+
+	#[test]
+	fn prunes_history_before_block() {
+		let genesis = BlockBuilder::genesis();
+		let generator = BlockGenerator::new(vec![genesis.add_blocks(3)]);
+		let genesis = genesis.last().encoded();
+
+		let db = new_db();
+		insert_blocks(&db, genesis.raw(), generator);
+
+		assert!(prune_history(db.clone(), genesis.raw(), 4).is_err());
+		assert_eq!(prune_history(db.clone(), genesis.raw(), 3).unwrap(), 2);
+
+		let bc = BlockChain::new(Default::default(), genesis.raw(), db.clone());
+		assert!(bc.block_body(&bc.block_hash(0).unwrap()).is_some());
+		assert!(bc.block_body(&bc.block_hash(1).unwrap()).is_none());
+		assert!(bc.block_body(&bc.block_hash(2).unwrap()).is_none());
+		assert!(bc.block_body(&bc.block_hash(3).unwrap()).is_some());
+		assert_eq!(bc.first_history_block(), Some(3));
+
+		// pruned blocks are skipped by the check.
+		let check = check_chain(&bc, &MemoryDB::<KeccakHasher, DBValue>::new());
+		assert!(check.is_ok(), "{:?}", check.errors);
+		assert_eq!(check.headers, 4);
+		assert_eq!(check.bodies, 2);
+	}
+}
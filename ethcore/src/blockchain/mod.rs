@@ -20,9 +20,12 @@ mod best_block;
 mod block_info;
 mod blockchain;
 mod cache;
+mod compression;
+mod maintenance;
 mod config;
 mod extras;
 mod import_route;
+mod state_pruning;
 mod update;
 
 #[cfg(test)]
@@ -30,8 +33,11 @@ pub mod generator;
 
 pub use self::blockchain::{BlockProvider, BlockChain, BlockChainDB, BlockChainDBHandler};
 pub use self::cache::CacheSize;
+pub use self::compression::CompressedColumn;
+pub use self::maintenance::{check_database, prune_history, ChainCheck};
+pub use self::state_pruning::{prune_state, repair_journal, JournalRepair, StatePruning};
 pub use self::config::Config;
-pub use self::extras::{BlockReceipts, BlockDetails, TransactionAddress};
+pub use self::extras::{BlockReceipts, BlockDetails, TransactionAddress, is_block_receipts_key};
 pub use self::import_route::ImportRoute;
 pub use self::update::ExtrasInsert;
 pub use types::tree_route::TreeRoute;
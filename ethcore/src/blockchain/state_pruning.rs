@@ -0,0 +1,585 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Offline pruning of an archive state database.
+//!
+//! Every state entry reachable from the most recent canonical state roots is marked in a
+//! separate database. A node is only marked once everything below it is, so a marked node
+//! stands for its whole subtree and an interrupted run resumes by skipping marked subtrees.
+//! The recent states are then journaled the way `OverlayRecentDB` expects, and all unmarked
+//! state entries are deleted.
+//!
+//! Repairing the journal of a pruned database works the same way: the state of a trusted
+//! block is marked, the journal is dropped in favour of that state, and everything else is
+//! deleted.
+
+use std::collections::HashSet;
+use std::mem;
+use std::sync::Arc;
+
+use account_db::combine_key;
+use error::Error;
+use ethereum_types::H256;
+use ethtrie::{RlpCodec, TrieError};
+use hash::{KECCAK_EMPTY, KECCAK_NULL_RLP};
+use hashdb::HashDB;
+use header::BlockNumber;
+use journaldb::{self, Algorithm, JournalReset};
+use keccak_hasher::KeccakHasher;
+use kvdb::{DBTransaction, DBValue, KeyValueDB};
+use snapshot::state_delta;
+use trace::TraceDB;
+use trie::NodeCodec;
+use trie::node::Node;
+use types::basic_account::BasicAccount;
+
+use super::{BlockChain, BlockChainDB, BlockProvider};
+
+// number of marks or deletions buffered before they are written out.
+const BATCH_SIZE: usize = 100_000;
+// how often marking and sweeping progress is logged, in state entries.
+const PROGRESS_INTERVAL: u64 = 1_000_000;
+
+/// Result of pruning the state database.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StatePruning {
+	/// Number of state entries newly marked to be kept.
+	pub marked: u64,
+	/// Number of recent states written to the journal.
+	pub journaled: u64,
+	/// Number of state entries deleted.
+	pub deleted: u64,
+}
+
+/// Result of repairing the journal of a pruned state database.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct JournalRepair {
+	/// Number of state entries newly marked to be kept.
+	pub marked: u64,
+	/// Changes made to the journal.
+	pub reset: JournalReset,
+	/// Number of state entries deleted.
+	pub deleted: u64,
+	/// Number of blocks reverted from the canonical chain.
+	pub reverted: u64,
+}
+
+/// Delete all state of an archive database except the state of the `keep` most recent
+/// canonical blocks, journaling those so the database can be opened with
+/// `Algorithm::OverlayRecent` afterwards.
+///
+/// `marks` holds the set of entries to keep; passing the same database again resumes an
+/// interrupted run.
+pub fn prune_state(db: Arc<BlockChainDB>, genesis: &[u8], marks: &KeyValueDB, keep: u64) -> Result<StatePruning, Error> {
+	if keep == 0 {
+		return Err("At least one recent state must be kept".into());
+	}
+
+	let chain = Arc::new(BlockChain::new(Default::default(), genesis, db.clone()));
+	let best = chain.best_block_number();
+	let first = best.saturating_sub(keep - 1);
+	let roots = (first..best + 1)
+		.map(|number| chain.block_hash(number)
+			.and_then(|hash| chain.block_header_data(&hash).map(|header| (number, hash, header.state_root())))
+			.ok_or_else(|| Error::from(format!("Block #{} is missing from the database", number)))
+		)
+		.collect::<Result<Vec<_>, _>>()?;
+
+	let archive = journaldb::new(db.key_value().clone(), Algorithm::Archive, ::db::COL_STATE);
+	let state = archive.as_hashdb();
+	let mut result = StatePruning::default();
+
+	{
+		let mut marker = Marker::new(state, marks);
+
+		for &(number, _, ref root) in &roots {
+			if !state.contains(root) {
+				return Err(format!("State of block #{} is missing. Is this an archive database?", number).into());
+			}
+			marker.mark_node(&::rlp::encode(root), None, &mut Vec::new())?;
+			marker.flush()?;
+			info!(target: "db", "Marked state of block #{}, {} state entries in total", number, marker.marked);
+		}
+		result.marked = marker.marked;
+	}
+
+	// the oldest kept state becomes the canonical one, the newer ones are journaled on top.
+	let mut journal = journaldb::new(db.key_value().clone(), Algorithm::OverlayRecent, ::db::COL_STATE);
+	if journal.earliest_era().is_none() {
+		let mut batch = DBTransaction::new();
+		for pair in roots.windows(2) {
+			let (_, _, ref parent_root) = pair[0];
+			let (number, ref hash, ref root) = pair[1];
+
+			for (key, value) in state_delta(state, parent_root, root)? {
+				journal.emplace(key, value);
+			}
+			for (key, _) in state_delta(state, root, parent_root)? {
+				journal.remove(&key);
+			}
+			journal.journal_under(&mut batch, number, hash)?;
+			result.journaled += 1;
+		}
+		db.key_value().write(batch)?;
+		info!(target: "db", "Journaled the state of {} recent blocks", result.journaled);
+	}
+
+	result.deleted = sweep(&*db, marks)?;
+	db.key_value().flush()?;
+	Ok(result)
+}
+
+/// Rebuild the state journal of a pruned database from the state of a trusted canonical
+/// block, the best block by default.
+///
+/// The journal is dropped, the trusted state becomes the canonical one and all other state
+/// entries are deleted. Blocks after the trusted one are reverted, as their state is gone.
+/// `marks` holds the set of entries to keep; passing the same database and block again
+/// resumes an interrupted run.
+pub fn repair_journal(
+	db: Arc<BlockChainDB>,
+	genesis: &[u8],
+	algorithm: Algorithm,
+	marks: &KeyValueDB,
+	block: Option<BlockNumber>,
+) -> Result<JournalRepair, Error> {
+	if algorithm == Algorithm::Archive {
+		return Err("Archive databases have no journal to repair".into());
+	}
+
+	let chain = BlockChain::new(Default::default(), genesis, db.clone());
+	let best = chain.best_block_number();
+	let number = block.unwrap_or(best);
+	if number > best {
+		return Err(format!("Block #{} is beyond the best block #{}", number, best).into());
+	}
+	let (hash, root) = chain.block_hash(number)
+		.and_then(|hash| chain.block_header_data(&hash).map(|header| (hash, header.state_root())))
+		.ok_or_else(|| Error::from(format!("Block #{} is missing from the database", number)))?;
+
+	let mut result = JournalRepair::default();
+	{
+		let journal = journaldb::new(db.key_value().clone(), algorithm, ::db::COL_STATE);
+		let mut marker = Marker::new(journal.as_hashdb(), marks);
+		marker.mark_node(&::rlp::encode(&root), None, &mut Vec::new())
+			.map_err(|e| format!("State of block #{} can't be used to repair the journal: {}", number, e))?;
+		marker.flush()?;
+		result.marked = marker.marked;
+		info!(target: "db", "Marked state of block #{}", number);
+	}
+
+	let canonical = |era| if era <= number { chain.block_hash(era) } else { None };
+	result.reset = journaldb::reset_journal(&**db.key_value(), ::db::COL_STATE, algorithm, number, &canonical)?;
+	result.deleted = sweep(&*db, marks)?;
+
+	if number < best {
+		// traces are reverted first, so a failed write leaves the chain untouched.
+		let reverted: Vec<H256> = ((number + 1)..(best + 1)).filter_map(|n| chain.block_hash(n)).collect();
+		let mut batch = DBTransaction::new();
+		TraceDB::new(Default::default(), db.clone(), chain.clone()).rewind_traces(&mut batch, number + 1, &reverted);
+		db.key_value().write(batch)?;
+
+		chain.rewind(hash).ok_or_else(|| Error::from(format!("Block #{} is not canonical", number)))?;
+		chain.remove_blocks(&reverted);
+		result.reverted = reverted.len() as u64;
+	}
+
+	db.key_value().flush()?;
+	Ok(result)
+}
+
+// Delete every state entry which isn't marked, returning the number of deleted entries.
+fn sweep(db: &BlockChainDB, marks: &KeyValueDB) -> Result<u64, Error> {
+	let mut batch = DBTransaction::new();
+	let mut pending = 0;
+	let mut swept = 0;
+	let mut deleted = 0;
+	for (key, _) in db.key_value().iter(::db::COL_STATE) {
+		swept += 1;
+		if swept % PROGRESS_INTERVAL == 0 {
+			info!(target: "db", "Swept {} state entries, deleted {}", swept, deleted);
+		}
+
+		// the journal and its metadata aren't keyed by hash.
+		if key.len() != 32 || marks.get(None, &key)?.is_some() {
+			continue;
+		}
+
+		batch.delete(::db::COL_STATE, &key);
+		deleted += 1;
+		pending += 1;
+		if pending == BATCH_SIZE {
+			db.key_value().write(mem::replace(&mut batch, DBTransaction::new()))?;
+			pending = 0;
+		}
+	}
+
+	db.key_value().write(batch)?;
+	Ok(deleted)
+}
+
+// Marks the state entries reachable from a state root, children before their parents.
+struct Marker<'a> {
+	state: &'a HashDB<KeccakHasher, DBValue>,
+	marks: &'a KeyValueDB,
+	pending: HashSet<H256>,
+	marked: u64,
+	// accounts walked so far. Accounts below already marked nodes aren't walked again.
+	accounts: u64,
+}
+
+impl<'a> Marker<'a> {
+	fn new(state: &'a HashDB<KeccakHasher, DBValue>, marks: &'a KeyValueDB) -> Self {
+		Marker {
+			state,
+			marks,
+			pending: HashSet::new(),
+			marked: 0,
+			accounts: 0,
+		}
+	}
+
+	fn is_marked(&self, key: &H256) -> Result<bool, Error> {
+		Ok(self.pending.contains(key) || self.marks.get(None, key)?.is_some())
+	}
+
+	fn mark(&mut self, key: H256) -> Result<(), Error> {
+		self.pending.insert(key);
+		self.marked += 1;
+		if self.marked % PROGRESS_INTERVAL == 0 {
+			info!(target: "db", "Marked {} state entries, walked {} accounts", self.marked, self.accounts);
+		}
+		if self.pending.len() >= BATCH_SIZE {
+			self.flush()?;
+		}
+		Ok(())
+	}
+
+	fn flush(&mut self) -> Result<(), Error> {
+		let mut batch = DBTransaction::new();
+		for key in self.pending.drain() {
+			batch.put(None, &key, &[]);
+		}
+		self.marks.write(batch)?;
+		Ok(())
+	}
+
+	// Mark the node behind a node reference and everything below it. `owner` is the hash of
+	// the account whose storage trie is walked, `key` holds the nibbles leading to the node.
+	fn mark_node(&mut self, node: &[u8], owner: Option<&H256>, key: &mut Vec<u8>) -> Result<(), Error> {
+		if RlpCodec::is_empty_node(node) {
+			return Ok(());
+		}
+
+		let db_key = RlpCodec::try_decode_hash(node).map(|hash| match owner {
+			Some(owner) => combine_key(owner, &hash),
+			None => hash,
+		});
+		let data = match db_key {
+			Some(ref db_key) => {
+				if self.is_marked(db_key)? {
+					return Ok(());
+				}
+				self.state.get(db_key).ok_or_else(|| TrieError::IncompleteDatabase(*db_key))?.to_vec()
+			},
+			None => node.to_vec(),
+		};
+
+		let depth = key.len();
+		match RlpCodec::decode(&data)? {
+			Node::Empty => {},
+			Node::Leaf(partial, value) => if owner.is_none() {
+				for i in 0..partial.len() {
+					key.push(partial.at(i));
+				}
+				let account_hash = H256::from_slice(&nibbles_to_key(key));
+				self.mark_account(&account_hash, ::rlp::decode(value)?)?;
+			},
+			Node::Extension(partial, child) => {
+				for i in 0..partial.len() {
+					key.push(partial.at(i));
+				}
+				self.mark_node(child, owner, key)?;
+			},
+			Node::Branch(children, _) => {
+				for i in 0..16 {
+					key.push(i as u8);
+					self.mark_node(children[i], owner, key)?;
+					key.truncate(depth);
+				}
+			},
+		}
+		key.truncate(depth);
+
+		match db_key {
+			Some(db_key) => self.mark(db_key),
+			None => Ok(()),
+		}
+	}
+
+	fn mark_account(&mut self, account_hash: &H256, account: BasicAccount) -> Result<(), Error> {
+		self.accounts += 1;
+		if account.storage_root != KECCAK_NULL_RLP {
+			self.mark_node(&::rlp::encode(&account.storage_root), Some(account_hash), &mut Vec::new())?;
+		}
+
+		if account.code_hash != KECCAK_EMPTY {
+			let code_key = combine_key(account_hash, &account.code_hash);
+			if !self.is_marked(&code_key)? {
+				if !self.state.contains(&code_key) {
+					return Err(TrieError::IncompleteDatabase(account.code_hash).into());
+				}
+				self.mark(code_key)?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+fn nibbles_to_key(nibbles: &[u8]) -> Vec<u8> {
+	nibbles.chunks(2).map(|pair| (pair[0] << 4) | pair.get(1).cloned().unwrap_or(0)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use account_db::{AccountDB, AccountDBMut};
+	use blockchain::ExtrasInsert;
+	use blockchain::generator::Block;
+	use ethtrie::{TrieDB, TrieDBMut};
+	use hash::keccak;
+	use kvdb_memorydb;
+	use rlp::RlpStream;
+	use test_helpers::new_db;
+	use trie::{Trie, TrieMut};
+
+	fn account(balance: u64, storage_root: H256, code_hash: H256) -> Vec<u8> {
+		::rlp::encode(&BasicAccount {
+			nonce: 0.into(),
+			balance: balance.into(),
+			storage_root,
+			code_hash,
+		}).into_vec()
+	}
+
+	// walk the whole state, checking that every node and code is there.
+	fn check_state(db: &HashDB<KeccakHasher, DBValue>, root: &H256) {
+		let trie = TrieDB::new(db, root).unwrap();
+		for item in trie.iter().unwrap() {
+			let (key, value) = item.unwrap();
+			let account: BasicAccount = ::rlp::decode(&value).unwrap();
+			let account_db = AccountDB::from_hash(db, H256::from_slice(&key));
+			let storage = TrieDB::new(&account_db, &account.storage_root).unwrap();
+			assert!(storage.iter().unwrap().all(|item| item.is_ok()));
+			if account.code_hash != KECCAK_EMPTY {
+				assert!(account_db.contains(&account.code_hash));
+			}
+		}
+	}
+
+	#[test]
+	fn prunes_all_but_recent_states() {
+		let db = new_db();
+		let mut archive = journaldb::new(db.key_value().clone(), Algorithm::Archive, ::db::COL_STATE);
+		let (a1, a2, a3) = (keccak("a1"), keccak("a2"), keccak("a3"));
+
+		// each state changes a single account of the previous one.
+		let mut roots = Vec::new();
+		let mut root = H256::new();
+		let mut storage_root = KECCAK_NULL_RLP;
+		for era in 0..4u64 {
+			{
+				let state = archive.as_hashdb_mut();
+				let mut storage_db = AccountDBMut::from_hash(state, a2);
+				let mut storage = if era == 0 {
+					TrieDBMut::new(&mut storage_db, &mut storage_root)
+				} else {
+					TrieDBMut::from_existing(&mut storage_db, &mut storage_root).unwrap()
+				};
+				let value = if era == 2 { [0x22; 40] } else { [0x11; 40] };
+				for i in 0..64u8 {
+					storage.insert(&keccak([i]), &value).unwrap();
+				}
+			}
+			let code_hash = AccountDBMut::from_hash(archive.as_hashdb_mut(), a3).insert(b"some code");
+			{
+				let state = archive.as_hashdb_mut();
+				let mut trie = if era == 0 {
+					TrieDBMut::new(state, &mut root)
+				} else {
+					TrieDBMut::from_existing(state, &mut root).unwrap()
+				};
+				trie.insert(&a1, &account(era, KECCAK_NULL_RLP, KECCAK_EMPTY)).unwrap();
+				trie.insert(&a2, &account(1, storage_root, KECCAK_EMPTY)).unwrap();
+				if era >= 2 {
+					trie.insert(&a3, &account(1, KECCAK_NULL_RLP, code_hash)).unwrap();
+				}
+			}
+			let mut batch = DBTransaction::new();
+			archive.journal_under(&mut batch, era, &H256::from(era)).unwrap();
+			db.key_value().write(batch).unwrap();
+			roots.push(root);
+		}
+
+		let mut blocks: Vec<Block> = Vec::new();
+		for (number, root) in roots.iter().enumerate() {
+			let mut block = Block::default();
+			block.header.set_number(number as u64);
+			block.header.set_state_root(*root);
+			block.header.set_difficulty(10.into());
+			if let Some(parent) = blocks.last() {
+				block.header.set_parent_hash(parent.hash());
+			}
+			blocks.push(block);
+		}
+
+		let genesis = blocks[0].encoded();
+		{
+			let bc = BlockChain::new(Default::default(), genesis.raw(), db.clone());
+			for block in &blocks[1..] {
+				let mut batch = DBTransaction::new();
+				bc.insert_block(&mut batch, block.encoded(), vec![], ExtrasInsert {
+					fork_choice: ::engines::ForkChoice::New,
+					is_finalized: false,
+				});
+				db.key_value().write(batch).unwrap();
+				bc.commit();
+			}
+		}
+
+		let marks = kvdb_memorydb::create(0);
+		assert!(prune_state(db.clone(), genesis.raw(), &marks, 0).is_err());
+
+		let pruned = prune_state(db.clone(), genesis.raw(), &marks, 2).unwrap();
+		assert!(pruned.marked > 0);
+		assert!(pruned.deleted > 0);
+		assert_eq!(pruned.journaled, 1);
+		assert!(db.key_value().get(::db::COL_STATE, &roots[0]).unwrap().is_none());
+		assert!(db.key_value().get(::db::COL_STATE, &roots[1]).unwrap().is_none());
+
+		// nothing is left to do when resuming.
+		let resumed = prune_state(db.clone(), genesis.raw(), &marks, 2).unwrap();
+		assert_eq!(resumed, StatePruning::default());
+
+		let mut pruned = journaldb::new(db.key_value().clone(), Algorithm::OverlayRecent, ::db::COL_STATE);
+		assert_eq!(pruned.earliest_era(), Some(3));
+		assert_eq!(pruned.latest_era(), Some(3));
+		check_state(pruned.as_hashdb(), &roots[2]);
+		check_state(pruned.as_hashdb(), &roots[3]);
+
+		// once the journaled state becomes canonical only the latest state is on disk.
+		let mut batch = DBTransaction::new();
+		pruned.mark_canonical(&mut batch, 3, &blocks[3].hash()).unwrap();
+		db.key_value().write(batch).unwrap();
+
+		let archive = journaldb::new(db.key_value().clone(), Algorithm::Archive, ::db::COL_STATE);
+		check_state(archive.as_hashdb(), &roots[3]);
+		assert!(!archive.as_hashdb().contains(&roots[2]));
+	}
+
+	#[test]
+	fn repairs_journal_from_trusted_block() {
+		let db = new_db();
+		let mut journal = journaldb::new(db.key_value().clone(), Algorithm::OverlayRecent, ::db::COL_STATE);
+		let (a1, a2) = (keccak("a1"), keccak("a2"));
+
+		let mut roots = Vec::new();
+		let mut root = H256::new();
+		for era in 0..4u64 {
+			{
+				let state = journal.as_hashdb_mut();
+				let mut trie = if era == 0 {
+					TrieDBMut::new(state, &mut root)
+				} else {
+					TrieDBMut::from_existing(state, &mut root).unwrap()
+				};
+				trie.insert(&a1, &account(era, KECCAK_NULL_RLP, KECCAK_EMPTY)).unwrap();
+				trie.insert(&a2, &account(era * 2, KECCAK_NULL_RLP, KECCAK_EMPTY)).unwrap();
+			}
+			let mut batch = DBTransaction::new();
+			journal.journal_under(&mut batch, era, &H256::from(era)).unwrap();
+			if era == 1 {
+				journal.mark_canonical(&mut batch, 0, &H256::from(0)).unwrap();
+			}
+			db.key_value().write(batch).unwrap();
+			journal.flush();
+			roots.push(root);
+		}
+
+		// a record left behind by an interrupted commit.
+		let mut batch = DBTransaction::new();
+		let mut record = RlpStream::new_list(3);
+		record.append(&H256::from(7)).begin_list(0);
+		record.begin_list(0);
+		let mut key = RlpStream::new_list(3);
+		key.append(&7u64).append(&0usize).append(&&[0u8; 10][..]);
+		batch.put(::db::COL_STATE, &key.out(), &record.out());
+		db.key_value().write(batch).unwrap();
+
+		let check = journaldb::check_journal(&**db.key_value(), ::db::COL_STATE, Algorithm::OverlayRecent).unwrap();
+		assert_eq!(check.orphaned, vec![(7, 0)]);
+
+		let mut blocks: Vec<Block> = Vec::new();
+		for (number, root) in roots.iter().enumerate() {
+			let mut block = Block::default();
+			block.header.set_number(number as u64);
+			block.header.set_state_root(*root);
+			block.header.set_difficulty(10.into());
+			if let Some(parent) = blocks.last() {
+				block.header.set_parent_hash(parent.hash());
+			}
+			blocks.push(block);
+		}
+
+		let genesis = blocks[0].encoded();
+		{
+			let bc = BlockChain::new(Default::default(), genesis.raw(), db.clone());
+			for block in &blocks[1..] {
+				let mut batch = DBTransaction::new();
+				bc.insert_block(&mut batch, block.encoded(), vec![], ExtrasInsert {
+					fork_choice: ::engines::ForkChoice::New,
+					is_finalized: false,
+				});
+				db.key_value().write(batch).unwrap();
+				bc.commit();
+			}
+		}
+
+		let marks = kvdb_memorydb::create(0);
+		assert!(repair_journal(db.clone(), genesis.raw(), Algorithm::Archive, &marks, None).is_err());
+		assert!(repair_journal(db.clone(), genesis.raw(), Algorithm::OverlayRecent, &marks, Some(4)).is_err());
+
+		let repair = repair_journal(db.clone(), genesis.raw(), Algorithm::OverlayRecent, &marks, Some(2)).unwrap();
+		assert!(repair.marked > 0);
+		assert!(repair.deleted > 0);
+		assert_eq!(repair.reset.records, 4);
+		assert_eq!(repair.reverted, 1);
+
+		let check = journaldb::check_journal(&**db.key_value(), ::db::COL_STATE, Algorithm::OverlayRecent).unwrap();
+		assert!(check.is_ok(), "{:?}", check);
+		assert_eq!(check.latest_era, Some(2));
+
+		// only the trusted state is left, stored on disk.
+		let archive = journaldb::new(db.key_value().clone(), Algorithm::Archive, ::db::COL_STATE);
+		check_state(archive.as_hashdb(), &roots[2]);
+		assert!(!archive.as_hashdb().contains(&roots[3]));
+		assert!(!archive.as_hashdb().contains(&roots[0]));
+
+		let bc = BlockChain::new(Default::default(), genesis.raw(), db.clone());
+		assert_eq!(bc.best_block_number(), 2);
+	}
+}
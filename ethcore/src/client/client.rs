@@ -44,7 +44,7 @@ use client::{
 use client::{
 	BlockId, TransactionId, UncleId, TraceId, ClientConfig, BlockChainClient,
 	TraceFilter, CallAnalytics, Mode,
-	ChainNotify, NewBlocks, ChainRoute, PruningInfo, ProvingBlockChainClient, EngineInfo, ChainMessageType,
+	ChainNotify, NewBlocks, ChainRoute, ChainRouteType, PruningInfo, ProvingBlockChainClient, EngineInfo, ChainMessageType,
 	IoClient, BadBlocks,
 };
 use client::bad_blocks;
@@ -58,6 +58,7 @@ use vm::{EnvInfo, LastHashes};
 use evm::Schedule;
 use executive::{Executive, Executed, TransactOptions, contract_address};
 use factory::{Factories, VmFactory};
+use flat_state::{FlatState, FLAT_STATE_LAYERS};
 use header::{BlockNumber, Header, ExtendedHeader};
 use io::IoChannel;
 use log_entry::LocalizedLogEntry;
@@ -66,7 +67,7 @@ use ethcore_miner::pool::VerifiedTransaction;
 use parking_lot::{Mutex, RwLock};
 use rand::OsRng;
 use receipt::{Receipt, LocalizedReceipt};
-use snapshot::{self, io as snapshot_io, SnapshotClient};
+use snapshot::{self, io as snapshot_io, SnapshotClient, TrieRange};
 use spec::Spec;
 use state_db::StateDB;
 use state::{self, State};
@@ -93,6 +94,10 @@ const MAX_ANCIENT_BLOCKS_QUEUE_SIZE: usize = 4096;
 const MAX_ANCIENT_BLOCKS_TO_IMPORT: usize = 4;
 const MAX_QUEUE_SIZE_TO_SLEEP_ON: usize = 2;
 const MIN_HISTORY_SIZE: u64 = 8;
+// Min number of recent blocks whose history is kept, so snapshots can still be taken.
+const MIN_HISTORY_EXPIRY: u64 = 30_000;
+// Max number of blocks whose history is expired on each tick.
+const MAX_HISTORY_EXPIRY_PER_TICK: u64 = 1_000;
 
 /// Report on the status of a client.
 #[derive(Default, Clone, Debug, Eq, PartialEq)]
@@ -229,6 +234,9 @@ pub struct Client {
 	/// Number of eras kept in a journal before they are pruned
 	history: u64,
 
+	/// Number of recent blocks whose bodies and receipts are kept, if older history expires
+	history_expiry: Option<u64>,
+
 	/// An action to be done if a mode/spec_name change happens
 	on_user_defaults_change: Mutex<Option<Box<FnMut(Option<Mode>) + 'static + Send>>>,
 
@@ -570,6 +578,23 @@ impl Importer {
 		client.db.read().key_value().write_buffered(batch);
 		chain.commit();
 
+		// merge the flat state of the block which just became deep enough.
+		if let (true, Some(flat_state)) = (is_canon, state.flat_state()) {
+			let flattened = number.checked_sub(FLAT_STATE_LAYERS)
+				.and_then(|n| chain.block_hash(n).and_then(|h| chain.block_header_data(&h)).map(|h| (n, h.state_root())));
+			if let Some((n, root)) = flattened {
+				if let Err(e) = flat_state.flatten(n, &root) {
+					warn!(target: "client", "Failed to flatten the state of block #{}: {}", n, e);
+				}
+			}
+			if flat_state.needs_generation() {
+				info!(target: "client", "Generating the flat state of block #{} in the background.", number);
+				if let Err(e) = FlatState::generate_in_background(flat_state.clone(), state.journal_db().boxed_clone(), number, *header.state_root()) {
+					warn!(target: "client", "Failed to start generating the flat state: {}", e);
+				}
+			}
+		}
+
 		self.check_epoch_end(&header, &finalized, &chain, client);
 
 		client.update_last_hashes(&parent, hash);
@@ -750,10 +775,23 @@ impl Client {
 			config.history
 		};
 
+		let history_expiry = config.history_expiry.map(|expiry| if expiry < MIN_HISTORY_EXPIRY {
+			info!(target: "client", "Ignoring history expiry parameter of {}\
+				, falling back to minimum of {}",
+				expiry, MIN_HISTORY_EXPIRY);
+			MIN_HISTORY_EXPIRY
+		} else {
+			expiry
+		});
+
 		if !chain.block_header_data(&chain.best_block_hash()).map_or(true, |h| state_db.journal_db().contains(&h.state_root())) {
 			warn!("State root not found for block #{} ({:x})", chain.best_block_number(), chain.best_block_hash());
 		}
 
+		if config.flat_state {
+			state_db = state_db.with_flat_state(open_flat_state(db.key_value().clone(), &state_db, &chain)?);
+		}
+
 		let engine = spec.engine.clone();
 
 		let awake = match config.mode { Mode::Dark(..) | Mode::Off => false, _ => true };
@@ -787,6 +825,7 @@ impl Client {
 			last_hashes: RwLock::new(VecDeque::new()),
 			factories: factories,
 			history: history,
+			history_expiry,
 			on_user_defaults_change: Mutex::new(None),
 			registrar_address,
 			exit_handler: Mutex::new(None),
@@ -964,6 +1003,9 @@ impl Client {
 			None => return Ok(()),
 		};
 
+		// the state the flat state is generated from is kept until generation finishes.
+		let pinned = state_db.flat_state().and_then(|flat_state| flat_state.generating());
+
 		// prune all ancient eras until we're below the memory target,
 		// but have at least the minimum number of states.
 		loop {
@@ -972,7 +1014,7 @@ impl Client {
 
 			if !needs_pruning { break }
 			match state_db.journal_db().earliest_era() {
-				Some(era) if era + self.history <= number => {
+				Some(era) if era + self.history <= number && pinned.map_or(true, |pinned| era < pinned) => {
 					trace!(target: "client", "Pruning state for ancient era {}", era);
 					match chain.block_hash(era) {
 						Some(ancient_hash) => {
@@ -1098,6 +1140,7 @@ impl Client {
 	// TODO: manage by real events.
 	pub fn tick(&self, prevent_sleep: bool) {
 		self.check_garbage();
+		self.expire_history();
 		if !prevent_sleep {
 			self.check_snooze();
 		}
@@ -1109,6 +1152,28 @@ impl Client {
 		self.tracedb.read().collect_garbage();
 	}
 
+	/// Removes the bodies, receipts and traces of blocks older than the history expiry,
+	/// a bounded number of blocks at a time.
+	fn expire_history(&self) {
+		let keep = match self.history_expiry {
+			Some(keep) => keep,
+			None => return,
+		};
+
+		let chain = self.chain.read();
+		let before = chain.best_block_number().saturating_sub(keep);
+		if chain.first_history_block().unwrap_or(1) >= before {
+			return;
+		}
+
+		let mut batch = DBTransaction::new();
+		let expired = chain.expire_history(&mut batch, before, MAX_HISTORY_EXPIRY_PER_TICK);
+		self.tracedb.read().expire_traces(&mut batch, &expired);
+		self.db.read().key_value().write(batch).expect("Low level database error when expiring history. Some issue with disk?");
+
+		trace!(target: "client", "Expired history of {} blocks, first history block is now #{:?}", expired.len(), chain.first_history_block());
+	}
+
 	fn check_snooze(&self) {
 		let mode = self.mode.lock().clone();
 		match mode {
@@ -1180,11 +1245,32 @@ impl Client {
 		Ok(())
 	}
 
+	/// Take a delta snapshot at the given block against the given base snapshot.
+	/// The state of the base snapshot must still be available in the database.
+	pub fn take_delta_snapshot(&self, writer: snapshot_io::LooseWriter, at: BlockId, base: &snapshot::ManifestData, p: &snapshot::Progress) -> Result<(), EthcoreError> {
+		let db = self.state_db.read().journal_db().boxed_clone();
+		let block_number = self.block_number(at).ok_or(snapshot::Error::InvalidStartingBlock(at))?;
+
+		if db.is_pruned() && self.pruning_info().earliest_state > ::std::cmp::min(base.block_number, block_number) {
+			return Err(snapshot::Error::OldBlockPrunedDB.into());
+		}
+
+		let start_hash = self.block_hash(at).ok_or(snapshot::Error::InvalidStartingBlock(at))?;
+		snapshot::take_delta_snapshot(&*self.engine, &self.chain.read(), start_hash, base, db.as_hashdb(), writer, p)?;
+
+		Ok(())
+	}
+
 	/// Ask the client what the history parameter is.
 	pub fn pruning_history(&self) -> u64 {
 		self.history
 	}
 
+	/// Get the snapshot configuration the client was started with.
+	pub fn snapshot_config(&self) -> &snapshot::SnapshotConfiguration {
+		&self.config.snapshot
+	}
+
 	fn block_hash(chain: &BlockChain, id: BlockId) -> Option<H256> {
 		match id {
 			BlockId::Hash(hash) => Some(hash),
@@ -1323,8 +1409,12 @@ impl snapshot::DatabaseRestore for Client {
 		db.restore(new_db)?;
 
 		let cache_size = state_db.cache_size();
-		*state_db = StateDB::new(journaldb::new(db.key_value().clone(), self.pruning, ::db::COL_STATE), cache_size);
+		let mut new_state_db = StateDB::new(journaldb::new(db.key_value().clone(), self.pruning, ::db::COL_STATE), cache_size);
 		*chain = Arc::new(BlockChain::new(self.config.blockchain.clone(), &[], db.clone()));
+		if self.config.flat_state {
+			new_state_db = new_state_db.with_flat_state(open_flat_state(db.key_value().clone(), &new_state_db, &chain)?);
+		}
+		*state_db = new_state_db;
 		*tracedb = TraceDB::new(self.config.tracing.clone(), db.clone(), chain.clone());
 		Ok(())
 	}
@@ -1886,6 +1976,29 @@ impl BlockChainClient for Client {
 		self.state_db.read().journal_db().state(hash)
 	}
 
+	fn state_range(&self, root: &H256, origin: &H256, limit: &H256, max_bytes: usize) -> Option<TrieRange> {
+		let state_db = self.state_db.read();
+		match snapshot::prove_range(state_db.journal_db().as_hashdb(), root, origin, limit, max_bytes) {
+			Ok(range) => Some(range),
+			Err(e) => {
+				trace!(target: "client", "state_range: Couldn't prove range of {}: {}", root, e);
+				None
+			}
+		}
+	}
+
+	fn storage_range(&self, account_hash: &H256, storage_root: &H256, origin: &H256, limit: &H256, max_bytes: usize) -> Option<TrieRange> {
+		let state_db = self.state_db.read();
+		let account_db = self.factories.accountdb.readonly(state_db.journal_db().as_hashdb(), *account_hash);
+		match snapshot::prove_range(account_db.as_hashdb(), storage_root, origin, limit, max_bytes) {
+			Ok(range) => Some(range),
+			Err(e) => {
+				trace!(target: "client", "storage_range: Couldn't prove range of {}: {}", storage_root, e);
+				None
+			}
+		}
+	}
+
 	fn block_receipts(&self, hash: &H256) -> Option<BlockReceipts> {
 		self.chain.read().block_receipts(hash)
 	}
@@ -2085,6 +2198,10 @@ impl BlockChainClient for Client {
 		self.importer.miner.ready_transactions(self, max_len, ::miner::PendingOrdering::Priority)
 	}
 
+	fn pooled_transaction(&self, hash: &H256) -> Option<Arc<VerifiedTransaction>> {
+		self.importer.miner.transaction(hash)
+	}
+
 	fn signing_chain_id(&self) -> Option<u64> {
 		self.engine.signing_chain_id(&self.latest_env_info())
 	}
@@ -2104,8 +2221,9 @@ impl BlockChainClient for Client {
 	}
 
 	fn pruning_info(&self) -> PruningInfo {
+		let chain = self.chain.read();
 		PruningInfo {
-			earliest_chain: self.chain.read().first_block_number().unwrap_or(1),
+			earliest_chain: cmp::max(chain.first_block_number().unwrap_or(1), chain.first_history_block().unwrap_or(1)),
 			earliest_state: self.state_db.read().journal_db().earliest_era().unwrap_or(0),
 		}
 	}
@@ -2130,6 +2248,63 @@ impl BlockChainClient for Client {
 	fn registrar_address(&self) -> Option<Address> {
 		self.registrar_address.clone()
 	}
+
+	fn revert_to(&self, hash: H256) -> Result<(), String> {
+		let removed = {
+			let _import_lock = self.importer.import_lock.lock();
+			let chain = self.chain.read();
+			if !chain.is_canon(&hash) {
+				return Err(format!("Block {} is not in the canon chain", hash));
+			}
+
+			let number = chain.block_number(&hash).expect("canon blocks are known; qed");
+			{
+				let state_db = self.state_db.read();
+				if state_db.is_pruned() && state_db.journal_db().earliest_era().unwrap_or(0) > number {
+					return Err(format!("State of block #{} is already pruned", number));
+				}
+			}
+
+			// state journal and traces are reverted first, so a failed write leaves the chain untouched.
+			let best = chain.best_block_number();
+			let removed: Vec<H256> = ((number + 1)..(best + 1)).filter_map(|n| chain.block_hash(n)).collect();
+			let mut batch = DBTransaction::new();
+			self.state_db.write().revert_to_era(&mut batch, number)
+				.map_err(|e| format!("Failed to revert the state journal: {}", e))?;
+			self.tracedb.read().rewind_traces(&mut batch, number + 1, &removed);
+			self.db.read().key_value().write(batch).map_err(|e| format!("Failed to revert the state journal and traces: {}", e))?;
+
+			chain.rewind(hash).expect("block is in the canon chain; qed");
+			info!(target: "client", "Reverted chain to #{} {}, removing {} blocks", number, hash, removed.len());
+
+			// drop everything derived from the removed blocks.
+			self.state_db.read().clear_cache();
+			self.last_hashes.write().clear();
+			self.importer.block_queue.clear();
+			removed
+		};
+
+		// the miner may seal new blocks on top, so the import lock must be released.
+		// the removed blocks are still readable, the miner re-imports their transactions.
+		self.importer.miner.chain_new_blocks(self, &[], &[], &[hash], &removed, false);
+		let route = ChainRoute::new(removed.iter().map(|hash| (*hash, ChainRouteType::Retracted)).collect());
+		self.notify(|notify| {
+			notify.new_blocks(
+				NewBlocks::new(
+					vec![],
+					vec![],
+					route.clone(),
+					vec![],
+					vec![],
+					Duration::from_millis(0),
+					false,
+				)
+			);
+		});
+
+		self.chain.read().remove_blocks(&removed);
+		Ok(())
+	}
 }
 
 impl IoClient for Client {
@@ -2461,6 +2636,18 @@ impl Drop for Client {
 	}
 }
 
+// Load the flat state, generating it in the background from the state of the best block if
+// that isn't available. State is read from the trie until then.
+fn open_flat_state(db: Arc<KeyValueDB>, state_db: &StateDB, chain: &BlockChain) -> Result<Arc<FlatState>, ::error::Error> {
+	let flat_state = Arc::new(FlatState::new(db)?);
+	let best = chain.best_block_header();
+	if !flat_state.is_available(&best.state_root()) {
+		info!(target: "client", "Generating the flat state of block #{} in the background. This may take a while.", best.number());
+		FlatState::generate_in_background(flat_state.clone(), state_db.journal_db().boxed_clone(), best.number(), best.state_root())?;
+	}
+	Ok(flat_state)
+}
+
 /// Returns `LocalizedReceipt` given `LocalizedTransaction`
 /// and a vector of receipts from given block up to transaction index.
 fn transaction_receipt(
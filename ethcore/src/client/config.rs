@@ -117,6 +117,11 @@ pub struct ClientConfig {
 	pub history: u64,
 	/// Ideal memory usage for state pruning history.
 	pub history_mem: usize,
+	/// Number of recent blocks whose bodies and receipts are kept. Older block history expires.
+	/// `None` keeps the history of all blocks.
+	pub history_expiry: Option<u64>,
+	/// Keep a flat copy of recent states for fast account and storage reads.
+	pub flat_state: bool,
 	/// Check seal valididity on block import
 	pub check_seal: bool,
 	/// Maximal number of transactions queued for verification in a separate thread.
@@ -147,6 +152,8 @@ impl Default for ClientConfig {
 			jump_table_size: 1 * mb,
 			history: 64,
 			history_mem: 32 * mb,
+			history_expiry: None,
+			flat_state: false,
 			check_seal: true,
 			transaction_verification_queue_size: 8192,
 			max_round_blocks_to_import: 12,
@@ -17,8 +17,8 @@
 use std::fmt;
 use bytes::Bytes;
 use client::Client;
-use ethereum_types::H256;
-use snapshot::ManifestData;
+use ethereum_types::{H256, U256};
+use snapshot::{ManifestData, StateSyncData};
 
 /// Message type for external and internal events
 #[derive(Debug)]
@@ -33,6 +33,10 @@ pub enum ClientIoMessage {
 	FeedStateChunk(H256, Bytes),
 	/// Feed a block chunk to the snapshot service
 	FeedBlockChunk(H256, Bytes),
+	/// Begin restoring the state of a block, its receipts and total difficulty without a snapshot
+	BeginStateRestoration(Bytes, Bytes, U256),
+	/// Feed state downloaded without a snapshot to the snapshot service
+	FeedStateData(StateSyncData),
 	/// Take a snapshot for the block with given number.
 	TakeSnapshot(u64),
 	/// Execute wrapped closure
@@ -64,6 +64,7 @@ use header::Header;
 use encoded;
 use engines::EthEngine;
 use ethtrie;
+use snapshot::TrieRange;
 use state::StateInfo;
 use views::BlockView;
 
@@ -109,6 +110,8 @@ pub struct TestBlockChainClient {
 	pub ancient_block: RwLock<Option<(H256, u64)>>,
 	/// First block info.
 	pub first_block: RwLock<Option<(H256, u64)>>,
+	/// First block with a body and receipts, if history has expired
+	pub first_history_block: RwLock<Option<BlockNumber>>,
 	/// Traces to return
 	pub traces: RwLock<Option<Vec<LocalizedTrace>>>,
 	/// Pruning history size to report.
@@ -178,6 +181,7 @@ impl TestBlockChainClient {
 			latest_block_timestamp: RwLock::new(10_000_000),
 			ancient_block: RwLock::new(None),
 			first_block: RwLock::new(None),
+			first_history_block: RwLock::new(None),
 			traces: RwLock::new(None),
 			history: RwLock::new(None),
 			disabled: AtomicBool::new(false),
@@ -472,7 +476,8 @@ impl ChainInfo for TestBlockChainClient {
 			first_block_hash: self.first_block.read().as_ref().map(|x| x.0),
 			first_block_number: self.first_block.read().as_ref().map(|x| x.1),
 			ancient_block_hash: self.ancient_block.read().as_ref().map(|x| x.0),
-			ancient_block_number: self.ancient_block.read().as_ref().map(|x| x.1)
+			ancient_block_number: self.ancient_block.read().as_ref().map(|x| x.1),
+			first_history_block: *self.first_history_block.read(),
 		}
 	}
 }
@@ -789,6 +794,14 @@ impl BlockChainClient for TestBlockChainClient {
 		None
 	}
 
+	fn state_range(&self, _root: &H256, _origin: &H256, _limit: &H256, _max_bytes: usize) -> Option<TrieRange> {
+		None
+	}
+
+	fn storage_range(&self, _account_hash: &H256, _storage_root: &H256, _origin: &H256, _limit: &H256, _max_bytes: usize) -> Option<TrieRange> {
+		None
+	}
+
 	fn block_receipts(&self, hash: &H256) -> Option<BlockReceipts> {
 		// starts with 'f' ?
 		if *hash > H256::from("f000000000000000000000000000000000000000000000000000000000000000") {
@@ -839,6 +852,10 @@ impl BlockChainClient for TestBlockChainClient {
 		self.miner.ready_transactions(self, 4096, miner::PendingOrdering::Priority)
 	}
 
+	fn pooled_transaction(&self, hash: &H256) -> Option<Arc<VerifiedTransaction>> {
+		self.miner.transaction(hash)
+	}
+
 	fn signing_chain_id(&self) -> Option<u64> { None }
 
 	fn mode(&self) -> Mode { Mode::Active }
@@ -875,6 +892,10 @@ impl BlockChainClient for TestBlockChainClient {
 	}
 
 	fn registrar_address(&self) -> Option<Address> { None }
+
+	fn revert_to(&self, _hash: H256) -> Result<(), String> {
+		Err("Test client doesn't support reverting the chain.".into())
+	}
 }
 
 impl IoClient for TestBlockChainClient {
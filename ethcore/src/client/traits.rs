@@ -38,6 +38,7 @@ use verification::queue::kind::blocks::Unverified;
 use state::StateInfo;
 use header::Header;
 use engines::EthEngine;
+use snapshot::TrieRange;
 
 use ethereum_types::{H256, U256, Address};
 use ethcore_miner::pool::VerifiedTransaction;
@@ -294,6 +295,14 @@ pub trait BlockChainClient : Sync + Send + AccountData + BlockChain + CallContra
 	/// Get latest state node
 	fn state_data(&self, hash: &H256) -> Option<Bytes>;
 
+	/// Get the entries of the state trie with the given root from `origin` on, along with
+	/// their proof. See `snapshot::prove_range` for the limits.
+	fn state_range(&self, root: &H256, origin: &H256, limit: &H256, max_bytes: usize) -> Option<TrieRange>;
+
+	/// Get the storage entries of an account from `origin` on, along with their proof,
+	/// given the root of its storage trie.
+	fn storage_range(&self, account_hash: &H256, storage_root: &H256, origin: &H256, limit: &H256, max_bytes: usize) -> Option<TrieRange>;
+
 	/// Get block receipts data by block header hash.
 	fn block_receipts(&self, hash: &H256) -> Option<BlockReceipts>;
 
@@ -338,6 +347,9 @@ pub trait BlockChainClient : Sync + Send + AccountData + BlockChain + CallContra
 	/// List all ready transactions that should be propagated to other peers.
 	fn transactions_to_propagate(&self) -> Vec<Arc<VerifiedTransaction>>;
 
+	/// Get a transaction from the pool by its hash.
+	fn pooled_transaction(&self, hash: &H256) -> Option<Arc<VerifiedTransaction>>;
+
 	/// Sorted list of transaction gas prices from at least last sample_size blocks.
 	fn gas_price_corpus(&self, sample_size: usize) -> ::stats::Corpus<U256> {
 		let mut h = self.chain_info().best_block_hash;
@@ -392,6 +404,9 @@ pub trait BlockChainClient : Sync + Send + AccountData + BlockChain + CallContra
 
 	/// Get the address of the registry itself.
 	fn registrar_address(&self) -> Option<Address>;
+
+	/// Revert the chain to the given canon block, discarding all blocks and state above it.
+	fn revert_to(&self, hash: H256) -> Result<(), String>;
 }
 
 /// Provides `reopen_block` method
@@ -73,6 +73,8 @@ pub struct AuthorityRoundParams {
 	pub block_reward_contract_transition: u64,
 	/// Block reward contract.
 	pub block_reward_contract: Option<BlockRewardContract>,
+	/// Block from which block reward contract rewards are traced with their beneficiary kinds.
+	pub block_reward_contract_kinds_transition: u64,
 	/// Number of accepted uncles transition block.
 	pub maximum_uncle_count_transition: u64,
 	/// Number of accepted uncles.
@@ -108,6 +110,7 @@ impl From<ethjson::spec::AuthorityRoundParams> for AuthorityRoundParams {
 				(_, Some(address)) => Some(BlockRewardContract::new_from_address(address.into())),
 				(None, None) => None,
 			},
+			block_reward_contract_kinds_transition: p.block_reward_contract_kinds_transition.map_or(u64::max_value(), Into::into),
 			maximum_uncle_count_transition: p.maximum_uncle_count_transition.map_or(0, Into::into),
 			maximum_uncle_count: p.maximum_uncle_count.map_or(0, Into::into),
 			empty_steps_transition: p.empty_steps_transition.map_or(u64::max_value(), |n| ::std::cmp::max(n.into(), 1)),
@@ -421,6 +424,7 @@ pub struct AuthorityRound {
 	block_reward: U256,
 	block_reward_contract_transition: u64,
 	block_reward_contract: Option<BlockRewardContract>,
+	block_reward_contract_kinds_transition: u64,
 	maximum_uncle_count_transition: u64,
 	maximum_uncle_count: usize,
 	empty_steps_transition: u64,
@@ -674,6 +678,7 @@ impl AuthorityRound {
 				block_reward: our_params.block_reward,
 				block_reward_contract_transition: our_params.block_reward_contract_transition,
 				block_reward_contract: our_params.block_reward_contract,
+				block_reward_contract_kinds_transition: our_params.block_reward_contract_kinds_transition,
 				maximum_uncle_count_transition: our_params.maximum_uncle_count_transition,
 				maximum_uncle_count: our_params.maximum_uncle_count,
 				empty_steps_transition: our_params.empty_steps_transition,
@@ -1195,7 +1200,8 @@ impl Engine<EthereumMachine> for AuthorityRound {
 				let mut call = super::default_system_or_code_call(&self.machine, block);
 
 				let rewards = c.reward(&beneficiaries, &mut call)?;
-				rewards.into_iter().map(|(author, amount)| (author, RewardKind::External, amount)).collect()
+				let keep_kinds = block.header().number() >= self.block_reward_contract_kinds_transition;
+				block_reward::contract_rewards(&beneficiaries, rewards, keep_kinds)
 			},
 			_ => {
 				beneficiaries.into_iter().map(|(author, reward_kind)| (author, reward_kind, self.block_reward)).collect()
@@ -1560,6 +1566,7 @@ mod tests {
 			block_reward: Default::default(),
 			block_reward_contract_transition: 0,
 			block_reward_contract: Default::default(),
+			block_reward_contract_kinds_transition: u64::max_value(),
 			strict_empty_steps_transition: 0,
 		};
 
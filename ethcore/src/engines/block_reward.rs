@@ -150,6 +150,28 @@ impl BlockRewardContract {
 	}
 }
 
+/// Attributes the rewards allocated by a block reward contract to the beneficiaries they were
+/// requested for. The contract is free to reorder, drop or add rewards, so only a reward returned
+/// at the same position and for the same address as a beneficiary keeps its kind, all the other
+/// ones are attributed as `RewardKind::External`.
+///
+/// Chains which traced every contract reward as external keep doing so until their engine's
+/// `blockRewardContractKindsTransition`, so `keep_kinds` is only set from that block on.
+pub fn contract_rewards(
+	beneficiaries: &[(Address, RewardKind)],
+	rewards: Vec<(Address, U256)>,
+	keep_kinds: bool,
+) -> Vec<(Address, RewardKind, U256)> {
+	rewards.into_iter().enumerate().map(|(i, (address, amount))| {
+		let kind = match beneficiaries.get(i) {
+			Some(&(beneficiary, kind)) if keep_kinds && beneficiary == address => kind,
+			_ => RewardKind::External,
+		};
+
+		(address, kind, amount)
+	}).collect()
+}
+
 /// Applies the given block rewards, i.e. adds the given balance to each beneficiary' address.
 /// If tracing is enabled the operations are recorded.
 pub fn apply_block_rewards<M: Machine + WithBalances + WithRewards>(
@@ -168,12 +190,12 @@ pub fn apply_block_rewards<M: Machine + WithBalances + WithRewards>(
 #[cfg(test)]
 mod test {
 	use client::PrepareOpenBlock;
-	use ethereum_types::U256;
+	use ethereum_types::{Address, U256};
 	use spec::Spec;
 	use test_helpers::generate_dummy_client_with_spec_and_accounts;
 
 	use engines::SystemOrCodeCallKind;
-	use super::{BlockRewardContract, RewardKind};
+	use super::{BlockRewardContract, RewardKind, contract_rewards};
 
 	#[test]
 	fn block_reward_contract() {
@@ -230,4 +252,36 @@ mod test {
 
 		assert_eq!(expected, rewards);
 	}
+
+	#[test]
+	fn contract_rewards_keep_beneficiary_kinds() {
+		let author: Address = "0000000000000000000000000000000000000033".into();
+		let uncle: Address = "0000000000000000000000000000000000000034".into();
+		let other: Address = "0000000000000000000000000000000000000035".into();
+
+		let beneficiaries = vec![
+			(author, RewardKind::Author),
+			(uncle, RewardKind::Uncle(1)),
+		];
+
+		// the second reward isn't returned in the position of the uncle
+		let rewards = vec![
+			(author, U256::from(1000)),
+			(other, U256::from(10)),
+			(uncle, U256::from(500)),
+		];
+
+		assert_eq!(contract_rewards(&beneficiaries, rewards.clone(), true), vec![
+			(author, RewardKind::Author, U256::from(1000)),
+			(other, RewardKind::External, U256::from(10)),
+			(uncle, RewardKind::External, U256::from(500)),
+		]);
+
+		// before the kinds transition every reward is external.
+		assert_eq!(contract_rewards(&beneficiaries, rewards, false), vec![
+			(author, RewardKind::External, U256::from(1000)),
+			(other, RewardKind::External, U256::from(10)),
+			(uncle, RewardKind::External, U256::from(500)),
+		]);
+	}
 }
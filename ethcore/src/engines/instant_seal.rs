@@ -14,6 +14,10 @@
 // You should have received a copy of the GNU General Public License
 // along with Parity.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use ethereum_types::H256;
+use parking_lot::Mutex;
 use engines::{Engine, Seal};
 use parity_machine::{Machine, Transactions, TotalScoredHeader};
 
@@ -32,11 +36,119 @@ impl From<::ethjson::spec::InstantSealParams> for InstantSealParams {
 	}
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct DevTime {
+	/// Seconds added to the system time.
+	offset: u64,
+	/// Timestamp of the next sealed block, overriding the clock.
+	next_timestamp: Option<u64>,
+}
+
+/// Manual control over block production and timestamps of a development chain.
+///
+/// Snapshots only record which block to return to; reverting the chain itself
+/// is up to the client.
+#[derive(Debug)]
+pub struct DevControls {
+	automine: AtomicBool,
+	force_seal: AtomicBool,
+	time: Mutex<DevTime>,
+	snapshots: Mutex<Vec<(H256, DevTime)>>,
+}
+
+impl Default for DevControls {
+	fn default() -> Self {
+		DevControls {
+			automine: AtomicBool::new(true),
+			force_seal: AtomicBool::new(false),
+			time: Mutex::new(DevTime::default()),
+			snapshots: Mutex::new(Vec::new()),
+		}
+	}
+}
+
+impl DevControls {
+	/// Whether blocks are sealed as soon as there are transactions.
+	pub fn automine(&self) -> bool {
+		self.automine.load(AtomicOrdering::SeqCst)
+	}
+
+	/// Enable or disable sealing blocks as soon as there are transactions.
+	pub fn set_automine(&self, automine: bool) {
+		self.automine.store(automine, AtomicOrdering::SeqCst);
+	}
+
+	/// Whether the next blocks are sealed regardless of automine and transactions.
+	pub fn is_seal_forced(&self) -> bool {
+		self.force_seal.load(AtomicOrdering::SeqCst)
+	}
+
+	/// Force sealing of blocks, even empty ones, until reset.
+	pub fn set_force_seal(&self, force: bool) {
+		self.force_seal.store(force, AtomicOrdering::SeqCst);
+	}
+
+	/// Move the clock forward by the given number of seconds, returning the total offset.
+	pub fn increase_time(&self, seconds: u64) -> u64 {
+		let mut time = self.time.lock();
+		time.offset = time.offset.saturating_add(seconds);
+		time.offset
+	}
+
+	/// Use the given timestamp for the next sealed block.
+	pub fn set_next_timestamp(&self, timestamp: u64) {
+		self.time.lock().next_timestamp = Some(timestamp);
+	}
+
+	/// Record the state of the clock along with the current best block, returning the snapshot id.
+	pub fn snapshot(&self, best_block: H256) -> usize {
+		let mut snapshots = self.snapshots.lock();
+		snapshots.push((best_block, *self.time.lock()));
+		snapshots.len()
+	}
+
+	/// Restore the clock from the given snapshot, returning the block the chain should be reverted to.
+	/// The snapshot is discarded along with all the later ones.
+	pub fn revert(&self, id: usize) -> Option<H256> {
+		let mut snapshots = self.snapshots.lock();
+		if id == 0 || id > snapshots.len() {
+			return None;
+		}
+
+		let (best_block, time) = snapshots.split_off(id - 1).remove(0);
+		*self.time.lock() = time;
+		Some(best_block)
+	}
+
+	fn should_seal(&self, has_transactions: bool) -> bool {
+		self.is_seal_forced() || (has_transactions && self.automine())
+	}
+
+	fn timestamp(&self, now: u64, millisecond_timestamp: bool) -> (u64, Option<u64>) {
+		let time = self.time.lock();
+		let offset = match millisecond_timestamp {
+			true => time.offset.saturating_mul(1000),
+			false => time.offset,
+		};
+		(now.saturating_add(offset), time.next_timestamp)
+	}
+
+	// the clock continues from the overridden timestamp of a sealed block.
+	fn note_sealed(&self, now: u64, millisecond_timestamp: bool) {
+		let mut time = self.time.lock();
+		if let Some(next) = time.next_timestamp.take() {
+			let scale = if millisecond_timestamp { 1000 } else { 1 };
+			time.offset = (next / scale).saturating_sub(now / scale);
+		}
+	}
+}
+
 /// An engine which does not provide any consensus mechanism, just seals blocks internally.
-/// Only seals blocks which have transactions.
+/// Only seals blocks which have transactions, unless told otherwise through its `DevControls`.
 pub struct InstantSeal<M> {
 	params: InstantSealParams,
 	machine: M,
+	dev: DevControls,
 }
 
 impl<M> InstantSeal<M> {
@@ -44,7 +156,17 @@ impl<M> InstantSeal<M> {
 	pub fn new(params: InstantSealParams, machine: M) -> Self {
 		InstantSeal {
 			params, machine,
+			dev: DevControls::default(),
+		}
+	}
+
+	fn now(&self) -> u64 {
+		let dur = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+		let mut now = dur.as_secs();
+		if self.params.millisecond_timestamp {
+			now = now * 1000 + dur.subsec_millis() as u64;
 		}
+		now
 	}
 }
 
@@ -62,7 +184,16 @@ impl<M: Machine> Engine<M> for InstantSeal<M>
 	fn seals_internally(&self) -> Option<bool> { Some(true) }
 
 	fn generate_seal(&self, block: &M::LiveBlock, _parent: &M::Header) -> Seal {
-		if block.transactions().is_empty() { Seal::None } else { Seal::Regular(Vec::new()) }
+		if !self.dev.should_seal(!block.transactions().is_empty()) {
+			return Seal::None;
+		}
+
+		self.dev.note_sealed(self.now(), self.params.millisecond_timestamp);
+		Seal::Regular(Vec::new())
+	}
+
+	fn dev_controls(&self) -> Option<&DevControls> {
+		Some(&self.dev)
 	}
 
 	fn verify_local_seal(&self, _header: &M::Header) -> Result<(), M::Error> {
@@ -70,14 +201,10 @@ impl<M: Machine> Engine<M> for InstantSeal<M>
 	}
 
 	fn open_block_header_timestamp(&self, parent_timestamp: u64) -> u64 {
-		use std::{time, cmp};
+		use std::cmp;
 
-		let dur = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap_or_default();
-		let mut now = dur.as_secs();
-		if self.params.millisecond_timestamp {
-			now = now * 1000 + dur.subsec_millis() as u64;
-		}
-		cmp::max(now, parent_timestamp)
+		let (now, next_timestamp) = self.dev.timestamp(self.now(), self.params.millisecond_timestamp);
+		cmp::max(next_timestamp.unwrap_or(now), parent_timestamp)
 	}
 
 	fn is_timestamp_valid(&self, header_timestamp: u64, parent_timestamp: u64) -> bool {
@@ -98,6 +225,7 @@ mod tests {
 	use header::Header;
 	use block::*;
 	use engines::Seal;
+	use super::DevControls;
 
 	#[test]
 	fn instant_can_seal() {
@@ -124,4 +252,51 @@ mod tests {
 
 		assert!(engine.verify_block_unordered(&header).is_ok());
 	}
+
+	#[test]
+	fn dev_controls_force_seal_and_time() {
+		let spec = Spec::new_instant();
+		let engine = &*spec.engine;
+		let dev = engine.dev_controls().unwrap();
+		let db = spec.ensure_db_good(get_temp_state_db(), &Default::default()).unwrap();
+		let genesis_header = spec.genesis_header();
+		let last_hashes = Arc::new(vec![genesis_header.hash()]);
+		let b = OpenBlock::new(engine, Default::default(), false, db, &genesis_header, last_hashes, Address::default(), (3141562.into(), 31415620.into()), vec![], false, &mut Vec::new().into_iter()).unwrap();
+		let b = b.close_and_lock().unwrap();
+
+		// empty blocks are only sealed on request, regardless of automine.
+		assert_eq!(engine.generate_seal(b.block(), &genesis_header), Seal::None);
+		dev.set_automine(false);
+		dev.set_force_seal(true);
+		assert_eq!(engine.generate_seal(b.block(), &genesis_header), Seal::Regular(Vec::new()));
+		dev.set_force_seal(false);
+
+		let now = engine.open_block_header_timestamp(0);
+		assert_eq!(dev.increase_time(100), 100);
+		assert!(engine.open_block_header_timestamp(0) >= now + 100);
+
+		// the clock continues from an overridden timestamp once a block is sealed.
+		dev.set_next_timestamp(now + 1000);
+		assert_eq!(engine.open_block_header_timestamp(0), now + 1000);
+		dev.set_force_seal(true);
+		engine.generate_seal(b.block(), &genesis_header);
+		assert!(engine.open_block_header_timestamp(0) >= now + 1000);
+		assert!(engine.open_block_header_timestamp(now + 2000) == now + 2000);
+	}
+
+	#[test]
+	fn dev_controls_snapshots() {
+		let dev = DevControls::default();
+		assert_eq!(dev.snapshot(1.into()), 1);
+		dev.increase_time(10);
+		assert_eq!(dev.snapshot(2.into()), 2);
+		dev.increase_time(10);
+
+		assert_eq!(dev.revert(2), Some(2.into()));
+		assert_eq!(dev.increase_time(0), 10);
+		assert_eq!(dev.revert(2), None);
+		assert_eq!(dev.revert(1), Some(1.into()));
+		assert_eq!(dev.increase_time(0), 0);
+		assert_eq!(dev.revert(0), None);
+	}
 }
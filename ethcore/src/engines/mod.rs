@@ -29,7 +29,7 @@ pub mod epoch;
 pub use self::authority_round::AuthorityRound;
 pub use self::basic_authority::BasicAuthority;
 pub use self::epoch::{EpochVerifier, Transition as EpochTransition};
-pub use self::instant_seal::{InstantSeal, InstantSealParams};
+pub use self::instant_seal::{InstantSeal, InstantSealParams, DevControls};
 pub use self::null_engine::NullEngine;
 
 use std::sync::{Weak, Arc};
@@ -253,6 +253,9 @@ pub trait Engine<M: Machine>: Sync + Send {
 	/// Optional maximum gas limit.
 	fn maximum_gas_limit(&self) -> Option<U256> { None }
 
+	/// Blocks at which the engine's own consensus rules change.
+	fn fork_transitions(&self) -> Vec<BlockNumber> { Vec::new() }
+
 	/// Block transformation functions, before the transactions.
 	/// `epoch_begin` set to true if this block kicks off an epoch.
 	fn on_new_block(
@@ -285,6 +288,13 @@ pub trait Engine<M: Machine>: Sync + Send {
 	/// light clients do not generate seals.
 	fn generate_seal(&self, _block: &M::LiveBlock, _parent: &M::Header) -> Seal { Seal::None }
 
+	/// Whether external miners need the block number alongside the work package
+	/// of a block at the given height (e.g. because the PoW algorithm depends on it).
+	fn work_package_requires_number(&self, _block: BlockNumber) -> bool { false }
+
+	/// Manual control over block production and timestamps, for engines of development chains.
+	fn dev_controls(&self) -> Option<&DevControls> { None }
+
 	/// Verify a locally-generated seal of a header.
 	///
 	/// If this engine seals internally,
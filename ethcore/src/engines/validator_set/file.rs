@@ -0,0 +1,482 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Validator set read from a local file signed by an administrator key.
+//!
+//! The file holds a list of transitions, each naming the first block it
+//! applies to. Every transition is signed on its own, so the signed transition
+//! doubles as the epoch change proof checked by light clients and warp sync.
+//! The file is re-read periodically; changes to transitions which already
+//! took effect on the local chain are rejected.
+//!
+//! There is no file watcher: the file is checked for changes when the engine
+//! next asks for the validators (to verify or seal a block), at most once per
+//! `RELOAD_INTERVAL_SECS`. A change thus takes effect for the first block
+//! handled after that. A file that can't be read, or holds an invalid or empty
+//! list, is logged and ignored, and the last valid list stays in use.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Weak;
+use std::time::{Duration, Instant};
+
+use client::EngineClient;
+use ethereum_types::{H256, H520, Address};
+use ethjson::spec::SignedValidators;
+use ethkey::{self, Signature};
+use hash::keccak;
+use header::{BlockNumber, Header};
+use ids::BlockId;
+use machine::{AuxiliaryData, Call, EthereumMachine};
+use parking_lot::RwLock;
+use rlp::{DecoderError, Rlp, RlpStream};
+use super::ValidatorSet;
+use super::simple_list::SimpleList;
+
+/// How often the file is checked for changes.
+const RELOAD_INTERVAL_SECS: u64 = 5;
+
+/// Validator set activated at a given block, signed by the administrator.
+#[derive(Clone, Debug, PartialEq)]
+struct Transition {
+	block: BlockNumber,
+	validators: Vec<Address>,
+	signature: Signature,
+}
+
+impl Transition {
+	/// Hash signed by the administrator: `keccak(rlp([block, validators]))`.
+	fn message(block: BlockNumber, validators: &[Address]) -> H256 {
+		let mut stream = RlpStream::new_list(2);
+		stream.append(&block);
+		stream.append_list(validators);
+		keccak(stream.out())
+	}
+
+	fn verify(&self, signer: &Address) -> Result<(), String> {
+		let message = Transition::message(self.block, &self.validators);
+		match ethkey::verify_address(signer, &self.signature, &message) {
+			Ok(true) => Ok(()),
+			Ok(false) => Err(format!("Transition at block {} is not signed by {}", self.block, signer)),
+			Err(e) => Err(format!("Invalid signature of transition at block {}: {}", self.block, e)),
+		}
+	}
+
+	/// Encode as an epoch change proof: `rlp([block, validators, signature])`.
+	fn proof(&self) -> Vec<u8> {
+		let mut stream = RlpStream::new_list(3);
+		stream.append(&self.block);
+		stream.append_list(&self.validators);
+		stream.append(&&self.signature[..]);
+		stream.out()
+	}
+
+	fn from_proof(proof: &[u8]) -> Result<Self, DecoderError> {
+		let rlp = Rlp::new(proof);
+		let signature: Vec<u8> = rlp.val_at(2)?;
+		if signature.len() != 65 {
+			return Err(DecoderError::Custom("Invalid signature length"));
+		}
+
+		Ok(Transition {
+			block: rlp.val_at(0)?,
+			validators: rlp.list_at(1)?,
+			signature: H520::from_slice(&signature).into(),
+		})
+	}
+}
+
+struct Loaded {
+	transitions: BTreeMap<BlockNumber, Transition>,
+	// hash of the file contents the transitions were read from.
+	contents: Option<H256>,
+	last_check: Option<Instant>,
+}
+
+/// Validator set backed by a signed local file.
+pub struct ValidatorFile {
+	path: PathBuf,
+	signer: Address,
+	loaded: RwLock<Loaded>,
+	client: RwLock<Option<Weak<EngineClient>>>,
+}
+
+impl ValidatorFile {
+	/// Create a new validator set reading transitions signed by `signer` from `path`.
+	pub fn new(path: PathBuf, signer: Address) -> Self {
+		let set = ValidatorFile {
+			path: path,
+			signer: signer,
+			loaded: RwLock::new(Loaded {
+				transitions: BTreeMap::new(),
+				contents: None,
+				last_check: None,
+			}),
+			client: RwLock::new(None),
+		};
+		set.reload();
+		set
+	}
+
+	/// Read and verify the transitions from the file.
+	fn read(&self) -> Result<(H256, BTreeMap<BlockNumber, Transition>), String> {
+		let mut contents = Vec::new();
+		File::open(&self.path)
+			.and_then(|mut file| file.read_to_end(&mut contents))
+			.map_err(|e| format!("Unable to read {}: {}", self.path.display(), e))?;
+
+		let signed = SignedValidators::load(&contents[..])
+			.map_err(|e| format!("Invalid validator set file {}: {}", self.path.display(), e))?;
+
+		let mut transitions = BTreeMap::new();
+		for transition in signed.transitions {
+			if transition.signature.len() != 65 {
+				return Err(format!("Invalid signature length in validator set file {}", self.path.display()));
+			}
+
+			let transition = Transition {
+				block: transition.block.into(),
+				validators: transition.validators.into_iter().map(Into::into).collect(),
+				signature: H520::from_slice(&transition.signature).into(),
+			};
+			transition.verify(&self.signer)?;
+			if transition.validators.is_empty() {
+				return Err(format!("Transition at block {} has no validators", transition.block));
+			}
+
+			let block = transition.block;
+			if transitions.insert(block, transition).is_some() {
+				return Err(format!("Duplicate transition at block {}", block));
+			}
+		}
+
+		// every block must have validators, starting with the first one after genesis.
+		match transitions.keys().next() {
+			Some(&first) if first <= 1 => {},
+			Some(first) => return Err(format!("First transition in {} is at block {}, not at genesis", self.path.display(), first)),
+			None => return Err(format!("No transitions in validator set file {}", self.path.display())),
+		}
+
+		Ok((keccak(&contents), transitions))
+	}
+
+	/// Blocks up to this number are fixed: their transitions can't be changed anymore.
+	fn frozen_until(&self) -> BlockNumber {
+		let best = self.client.read().as_ref()
+			.and_then(Weak::upgrade)
+			.map_or(0, |client| client.chain_info().best_block_number);
+
+		// the epoch end for the child of the best block has already been announced.
+		best + 1
+	}
+
+	/// Re-read the file, replacing the transitions if it changed consistently
+	/// with the local chain.
+	fn reload(&self) {
+		let mut loaded = self.loaded.write();
+		loaded.last_check = Some(Instant::now());
+
+		let (contents, transitions) = match self.read() {
+			Ok(read) => read,
+			Err(ref e) if loaded.transitions.is_empty() => {
+				error!(target: "engine", "No validator set loaded, blocks can't be verified until it's fixed: {}", e);
+				return;
+			}
+			Err(e) => {
+				warn!(target: "engine", "Keeping current validator set: {}", e);
+				return;
+			}
+		};
+
+		if loaded.contents == Some(contents) {
+			return;
+		}
+		loaded.contents = Some(contents);
+
+		if !loaded.transitions.is_empty() {
+			let frozen_until = self.frozen_until();
+			let old = loaded.transitions.range(..frozen_until + 1);
+			let new = transitions.range(..frozen_until + 1);
+			if !old.eq(new) {
+				warn!(target: "engine", "Ignoring change to validator set file {}: transitions up to block {} can't be changed",
+					self.path.display(), frozen_until);
+				return;
+			}
+		}
+
+		info!(target: "engine", "Loaded {} validator set transitions from {}", transitions.len(), self.path.display());
+		loaded.transitions = transitions;
+	}
+
+	fn reload_if_due(&self) {
+		let due = self.loaded.read().last_check
+			.map_or(true, |last| last.elapsed() >= Duration::from_secs(RELOAD_INTERVAL_SECS));
+
+		if due {
+			self.reload();
+		}
+	}
+
+	/// Transition in effect for the block with the given number.
+	fn transition_for(&self, number: BlockNumber) -> Option<Transition> {
+		self.reload_if_due();
+		self.loaded.read().transitions
+			.range(..number + 1)
+			.next_back()
+			.map(|(_, transition)| transition.clone())
+	}
+
+	/// Validators of the child of the given block, or of the child of the best block if
+	/// the given one is unknown. Only empty if no valid file was ever loaded.
+	fn validators_after(&self, parent: &H256) -> Vec<Address> {
+		let number = self.client.read().as_ref()
+			.and_then(Weak::upgrade)
+			.and_then(|client| client.block_number(BlockId::Hash(*parent)));
+
+		let next = match number {
+			Some(number) => number + 1,
+			None => {
+				debug!(target: "engine", "Unknown block {}, using the validator set after the best block", parent);
+				self.frozen_until()
+			}
+		};
+
+		self.transition_for(next).map_or_else(Vec::new, |t| t.validators)
+	}
+}
+
+impl ValidatorSet for ValidatorFile {
+	fn default_caller(&self, _block_id: BlockId) -> Box<Call> {
+		Box::new(|_, _| Err("Validator set file doesn't require calls.".into()))
+	}
+
+	fn genesis_epoch_data(&self, _header: &Header, _call: &Call) -> Result<Vec<u8>, String> {
+		self.transition_for(1)
+			.map(|transition| transition.proof())
+			.ok_or_else(|| format!("No initial validator set in {}", self.path.display()))
+	}
+
+	fn is_epoch_end(&self, first: bool, chain_head: &Header) -> Option<Vec<u8>> {
+		// transitions take effect at the given block, so the epoch ends at its parent.
+		let next = chain_head.number() + 1;
+		self.transition_for(next)
+			.and_then(|transition| match first || transition.block == next {
+				true => Some(transition.proof()),
+				false => None,
+			})
+	}
+
+	fn signals_epoch_end(&self, _: bool, _: &Header, _: AuxiliaryData)
+		-> ::engines::EpochChange<EthereumMachine>
+	{
+		// transitions are signed and apply immediately, proven by `is_epoch_end`.
+		::engines::EpochChange::No
+	}
+
+	fn epoch_set(&self, first: bool, _: &EthereumMachine, number: BlockNumber, proof: &[u8]) -> Result<(SimpleList, Option<H256>), ::error::Error> {
+		let transition = Transition::from_proof(proof)?;
+		transition.verify(&self.signer).map_err(::engines::EngineError::InsufficientProof)?;
+
+		// like `is_epoch_end`: only the first epoch may be proven by an earlier transition,
+		// otherwise an outdated validator list could be replayed.
+		let valid = match first {
+			true => transition.block <= number + 1,
+			false => transition.block == number + 1,
+		};
+		if !valid {
+			return Err(::engines::EngineError::InsufficientProof(
+				format!("Transition at block {} proven at block {}", transition.block, number)
+			).into());
+		}
+
+		Ok((SimpleList::new(transition.validators), None))
+	}
+
+	fn contains_with_caller(&self, bh: &H256, address: &Address, _: &Call) -> bool {
+		self.validators_after(bh).contains(address)
+	}
+
+	fn get_with_caller(&self, bh: &H256, nonce: usize, _: &Call) -> Address {
+		let validators = self.validators_after(bh);
+
+		// nobody may seal until a valid file is loaded, and blocks by anyone are rejected.
+		if validators.is_empty() {
+			warn!(target: "engine", "No validator set loaded from {}", self.path.display());
+			return Address::default();
+		}
+
+		validators[nonce % validators.len()]
+	}
+
+	fn count_with_caller(&self, bh: &H256, _: &Call) -> usize {
+		self.validators_after(bh).len()
+	}
+
+	fn register_client(&self, client: Weak<EngineClient>) {
+		*self.client.write() = Some(client);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs::{self, File};
+	use std::io::Write;
+	use std::path::Path;
+	use ethereum_types::{H256, H520, Address};
+	use ethkey::{self, KeyPair, Secret};
+	use hash::keccak;
+	use header::Header;
+	use machine::Call;
+	use spec::Spec;
+	use tempdir::TempDir;
+	use super::super::ValidatorSet;
+	use super::{Transition, ValidatorFile};
+
+	fn admin() -> KeyPair {
+		let secret: Secret = keccak("admin").into();
+		KeyPair::from_secret(secret).unwrap()
+	}
+
+	fn write_file(path: &Path, key: &KeyPair, transitions: &[(u64, Vec<Address>)]) {
+		let entries: Vec<_> = transitions.iter().map(|&(block, ref validators)| {
+			let signature = ethkey::sign(key.secret(), &Transition::message(block, validators)).unwrap();
+			let validators: Vec<_> = validators.iter().map(|v| format!("\"0x{:x}\"", v)).collect();
+			format!(
+				r#"{{ "block": {}, "validators": [{}], "signature": "0x{:x}" }}"#,
+				block, validators.join(", "), H520::from(signature),
+			)
+		}).collect();
+
+		let mut file = File::create(path).unwrap();
+		write!(file, r#"{{ "transitions": [{}] }}"#, entries.join(", ")).unwrap();
+	}
+
+	fn header(number: u64) -> Header {
+		let mut header = Header::default();
+		header.set_number(number);
+		header
+	}
+
+	#[test]
+	fn transitions_and_proofs() {
+		let tempdir = TempDir::new("").unwrap();
+		let path = tempdir.path().join("validators.json");
+		let (v1, v2) = (Address::from(1), Address::from(2));
+		write_file(&path, &admin(), &[(0, vec![v1]), (5, vec![v1, v2])]);
+
+		let set = ValidatorFile::new(path, admin().address());
+		let machine = Spec::new_test_machine();
+
+		assert!(set.is_epoch_end(false, &header(1)).is_none());
+		assert!(set.is_epoch_end(true, &header(1)).is_some());
+
+		// the set changes for block 5, so block 4 ends the epoch.
+		let proof = set.is_epoch_end(false, &header(4)).unwrap();
+		let (list, finality) = set.epoch_set(false, &machine, 4, &proof).unwrap();
+		assert_eq!(list.into_inner(), vec![v1, v2]);
+		assert!(finality.is_none());
+
+		// proofs can't be used before the transition.
+		assert!(set.epoch_set(false, &machine, 3, &proof).is_err());
+
+		let genesis = set.genesis_epoch_data(&header(0), &|_, _| Err("no calls".into())).unwrap();
+		assert_eq!(set.epoch_set(true, &machine, 0, &genesis).unwrap().0.into_inner(), vec![v1]);
+
+		// nor replayed for a later epoch.
+		assert!(set.epoch_set(false, &machine, 9, &proof).is_err());
+		assert!(set.epoch_set(false, &machine, 4, &genesis).is_err());
+	}
+
+	#[test]
+	fn rejects_foreign_signatures() {
+		let tempdir = TempDir::new("").unwrap();
+		let path = tempdir.path().join("validators.json");
+		let other = KeyPair::from_secret(keccak("other").into()).unwrap();
+		write_file(&path, &other, &[(0, vec![Address::from(1)])]);
+
+		let set = ValidatorFile::new(path.clone(), admin().address());
+		assert!(set.genesis_epoch_data(&header(0), &|_, _| Err("no calls".into())).is_err());
+
+		// a proof signed by someone else is rejected as well.
+		let forged = ValidatorFile::new(path, other.address());
+		let proof = forged.is_epoch_end(true, &header(0)).unwrap();
+		assert!(set.epoch_set(true, &Spec::new_test_machine(), 0, &proof).is_err());
+	}
+
+	#[test]
+	fn reloads_future_transitions_only() {
+		let tempdir = TempDir::new("").unwrap();
+		let path = tempdir.path().join("validators.json");
+		let (v1, v2, v3) = (Address::from(1), Address::from(2), Address::from(3));
+		write_file(&path, &admin(), &[(0, vec![v1])]);
+
+		let set = ValidatorFile::new(path.clone(), admin().address());
+
+		// adding a future transition is picked up.
+		write_file(&path, &admin(), &[(0, vec![v1]), (10, vec![v2])]);
+		set.reload();
+		assert_eq!(set.transition_for(10).unwrap().validators, vec![v2]);
+
+		// rewriting the set in effect is ignored.
+		write_file(&path, &admin(), &[(0, vec![v3]), (10, vec![v2])]);
+		set.reload();
+		assert_eq!(set.transition_for(0).unwrap().validators, vec![v1]);
+	}
+
+	#[test]
+	fn rejects_empty_validator_lists() {
+		let tempdir = TempDir::new("").unwrap();
+		let path = tempdir.path().join("validators.json");
+		write_file(&path, &admin(), &[(0, vec![])]);
+
+		let set = ValidatorFile::new(path.clone(), admin().address());
+		assert!(set.transition_for(1).is_none());
+
+		write_file(&path, &admin(), &[(0, vec![Address::from(1)]), (10, vec![])]);
+		set.reload();
+		assert!(set.transition_for(1).is_none());
+
+		write_file(&path, &admin(), &[]);
+		set.reload();
+		assert!(set.transition_for(1).is_none());
+	}
+
+	#[test]
+	fn keeps_validators_when_file_breaks() {
+		let tempdir = TempDir::new("").unwrap();
+		let path = tempdir.path().join("validators.json");
+		let (v1, v2) = (Address::from(1), Address::from(2));
+		write_file(&path, &admin(), &[(0, vec![v1, v2])]);
+
+		let set = ValidatorFile::new(path.clone(), admin().address());
+		let no_calls: &Call = &|_, _| Err("no calls".into());
+		assert_eq!(set.get_with_caller(&H256::zero(), 1, no_calls), v2);
+
+		// an unparsable file is ignored.
+		File::create(&path).unwrap().write_all(b"{ \"transitions\": [").unwrap();
+		set.reload();
+		assert_eq!(set.get_with_caller(&H256::zero(), 1, no_calls), v2);
+		assert_eq!(set.count_with_caller(&H256::zero(), no_calls), 2);
+
+		// and so is a deleted one.
+		fs::remove_file(&path).unwrap();
+		set.reload();
+		assert_eq!(set.get_with_caller(&H256::zero(), 1, no_calls), v2);
+		assert!(set.contains_with_caller(&H256::zero(), &v1, no_calls));
+	}
+}
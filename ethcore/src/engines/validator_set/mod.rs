@@ -22,6 +22,7 @@ mod simple_list;
 mod safe_contract;
 mod contract;
 mod multi;
+mod file;
 
 use std::sync::Weak;
 use ids::BlockId;
@@ -38,6 +39,7 @@ pub use self::simple_list::SimpleList;
 use self::contract::ValidatorContract;
 use self::safe_contract::ValidatorSafeContract;
 use self::multi::Multi;
+use self::file::ValidatorFile;
 use super::SystemCall;
 
 /// Creates a validator set from spec.
@@ -49,6 +51,7 @@ pub fn new_validator_set(spec: ValidatorSpec) -> Box<ValidatorSet> {
 		ValidatorSpec::Multi(sequence) => Box::new(
 			Multi::new(sequence.into_iter().map(|(block, set)| (block.into(), new_validator_set(set))).collect())
 		),
+		ValidatorSpec::File(file) => Box::new(ValidatorFile::new(file.path.into(), file.signer.into())),
 	}
 }
 
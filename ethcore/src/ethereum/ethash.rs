@@ -18,6 +18,7 @@ use std::path::Path;
 use std::cmp;
 use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use hash::{KECCAK_EMPTY_LIST_RLP};
 use engines::block_reward::{self, BlockRewardContract, RewardKind};
 use ethash::{self, quick_get_difficulty, slow_hash_block_number, EthashManager, OptimizeFor};
@@ -36,6 +37,9 @@ use machine::EthereumMachine;
 const SNAPSHOT_BLOCKS: u64 = 5000;
 /// Maximum number of blocks allowed in an ethash snapshot.
 const MAX_SNAPSHOT_BLOCKS: u64 = 30000;
+/// Blocks at most this many seconds old are near the chain head and verified with the full DAG,
+/// if enabled. Older blocks, like those of a sync or import, use the light cache.
+const FULL_DAG_MAX_BLOCK_AGE: u64 = 24 * 60 * 60;
 
 /// Ethash specific seal
 #[derive(Debug, PartialEq)]
@@ -108,8 +112,12 @@ pub struct EthashParams {
 	pub block_reward_contract_transition: u64,
 	/// Block reward contract.
 	pub block_reward_contract: Option<BlockRewardContract>,
+	/// Block from which block reward contract rewards are traced with their beneficiary kinds.
+	pub block_reward_contract_kinds_transition: u64,
 	/// Difficulty bomb delays.
 	pub difficulty_bomb_delays: BTreeMap<BlockNumber, BlockNumber>,
+	/// Block to transition to progpow
+	pub progpow_transition: u64,
 }
 
 impl From<ethjson::spec::EthashParams> for EthashParams {
@@ -151,6 +159,7 @@ impl From<ethjson::spec::EthashParams> for EthashParams {
 			expip2_transition: p.expip2_transition.map_or(u64::max_value(), Into::into),
 			expip2_duration_limit: p.expip2_duration_limit.map_or(30, Into::into),
 			block_reward_contract_transition: p.block_reward_contract_transition.map_or(0, Into::into),
+			block_reward_contract_kinds_transition: p.block_reward_contract_kinds_transition.map_or(u64::max_value(), Into::into),
 			block_reward_contract: match (p.block_reward_contract_code, p.block_reward_contract_address) {
 				(Some(code), _) => Some(BlockRewardContract::new_from_code(Arc::new(code.into()))),
 				(_, Some(address)) => Some(BlockRewardContract::new_from_address(address.into())),
@@ -158,7 +167,8 @@ impl From<ethjson::spec::EthashParams> for EthashParams {
 			},
 			difficulty_bomb_delays: p.difficulty_bomb_delays.unwrap_or_default().into_iter()
 				.map(|(block, delay)| (block.into(), delay.into()))
-				.collect()
+				.collect(),
+			progpow_transition: p.progpow_transition.map_or(u64::max_value(), Into::into),
 		}
 	}
 }
@@ -173,16 +183,25 @@ pub struct Ethash {
 
 impl Ethash {
 	/// Create a new instance of Ethash engine
+	/// `full_dag` - Whether to generate full DAGs in the background for faster verification
+	/// of blocks near the chain head
 	pub fn new<T: Into<Option<OptimizeFor>>>(
 		cache_dir: &Path,
 		ethash_params: EthashParams,
 		machine: EthereumMachine,
 		optimize_for: T,
+		full_dag: bool,
 	) -> Arc<Self> {
+		let progpow_transition = ethash_params.progpow_transition;
+		let mut pow = EthashManager::new(cache_dir.as_ref(), optimize_for.into(), progpow_transition);
+		if full_dag {
+			pow = pow.with_full_dag(::num_cpus::get());
+		}
+
 		Arc::new(Ethash {
 			ethash_params,
 			machine,
-			pow: EthashManager::new(cache_dir.as_ref(), optimize_for.into()),
+			pow,
 		})
 	}
 }
@@ -222,8 +241,37 @@ impl Engine<EthereumMachine> for Arc<Ethash> {
 
 	fn maximum_uncle_count(&self, _block: BlockNumber) -> usize { 2 }
 
+	// ProgPoW miners derive the random program from the block number.
+	fn work_package_requires_number(&self, block: BlockNumber) -> bool {
+		self.pow.is_progpow(block)
+	}
+
 	fn maximum_gas_limit(&self) -> Option<U256> { Some(0x7fff_ffff_ffff_ffffu64.into()) }
 
+	fn fork_transitions(&self) -> Vec<BlockNumber> {
+		let params = &self.ethash_params;
+		let mut forks = vec![
+			params.homestead_transition,
+			params.difficulty_hardfork_transition,
+			params.bomb_defuse_transition,
+			params.eip100b_transition,
+			params.ecip1010_pause_transition,
+			params.ecip1010_continue_transition,
+			params.expip2_transition,
+			params.progpow_transition,
+		];
+		if params.block_reward_contract.is_some() {
+			forks.push(params.block_reward_contract_transition);
+		}
+		forks.extend(params.block_reward.keys());
+		forks.extend(params.difficulty_bomb_delays.keys());
+		if let Some(ext) = self.machine.ethash_extensions() {
+			forks.push(ext.homestead_transition);
+			forks.push(ext.dao_hardfork_transition);
+		}
+		forks
+	}
+
 	fn populate_from_parent(&self, header: &mut Header, parent: &Header) {
 		let difficulty = self.calculate_difficulty(header, parent);
 		header.set_difficulty(difficulty);
@@ -251,7 +299,8 @@ impl Engine<EthereumMachine> for Arc<Ethash> {
 				let mut call = engines::default_system_or_code_call(&self.machine, block);
 
 				let rewards = c.reward(&beneficiaries, &mut call)?;
-				rewards.into_iter().map(|(author, amount)| (author, RewardKind::External, amount)).collect()
+				let keep_kinds = number >= self.ethash_params.block_reward_contract_kinds_transition;
+				block_reward::contract_rewards(&beneficiaries, rewards, keep_kinds)
 			},
 			_ => {
 				let mut rewards = Vec::new();
@@ -317,7 +366,8 @@ impl Engine<EthereumMachine> for Arc<Ethash> {
 		let difficulty = ethash::boundary_to_difficulty(&H256(quick_get_difficulty(
 			&header.bare_hash().0,
 			seal.nonce.low_u64(),
-			&seal.mix_hash.0
+			&seal.mix_hash.0,
+			header.number() >= self.ethash_params.progpow_transition
 		)));
 
 		if &difficulty < header.difficulty() {
@@ -330,7 +380,12 @@ impl Engine<EthereumMachine> for Arc<Ethash> {
 	fn verify_block_unordered(&self, header: &Header) -> Result<(), Error> {
 		let seal = Seal::parse_seal(header.seal())?;
 
-		let result = self.pow.compute_light(header.number() as u64, &header.bare_hash().0, seal.nonce.low_u64());
+		let number = header.number() as u64;
+		let result = if near_chain_head(header.timestamp(), SystemTime::now()) {
+			self.pow.compute(number, &header.bare_hash().0, seal.nonce.low_u64())
+		} else {
+			self.pow.compute_light(number, &header.bare_hash().0, seal.nonce.low_u64())
+		};
 		let mix = H256(result.mix_hash);
 		let difficulty = ethash::boundary_to_difficulty(&H256(result.value));
 		trace!(target: "miner", "num: {num}, seed: {seed}, h: {h}, non: {non}, mix: {mix}, res: {res}",
@@ -473,11 +528,18 @@ fn ecip1017_eras_block_reward(era_rounds: u64, mut reward: U256, block_number:u6
 	(eras, reward)
 }
 
+// Whether a block with the given timestamp is recent enough to be verified with the full DAG.
+fn near_chain_head(timestamp: u64, now: SystemTime) -> bool {
+	let now = now.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+	now.saturating_sub(timestamp) <= FULL_DAG_MAX_BLOCK_AGE
+}
+
 #[cfg(test)]
 mod tests {
 	use std::str::FromStr;
 	use std::sync::Arc;
 	use std::collections::BTreeMap;
+	use std::time::{Duration, UNIX_EPOCH};
 	use ethereum_types::{H64, H256, U256, Address};
 	use block::*;
 	use test_helpers::get_temp_state_db;
@@ -485,8 +547,10 @@ mod tests {
 	use header::Header;
 	use spec::Spec;
 	use engines::Engine;
+	use trace::{Tracing, FlatTrace, RewardType};
+	use trace::trace::Action;
 	use super::super::{new_morden, new_mcip3_test, new_homestead_test_machine};
-	use super::{Ethash, EthashParams, ecip1017_eras_block_reward};
+	use super::{Ethash, EthashParams, ecip1017_eras_block_reward, near_chain_head, FULL_DAG_MAX_BLOCK_AGE};
 	use rlp;
 	use tempdir::TempDir;
 
@@ -519,7 +583,9 @@ mod tests {
 			expip2_duration_limit: 30,
 			block_reward_contract: None,
 			block_reward_contract_transition: 0,
+			block_reward_contract_kinds_transition: u64::max_value(),
 			difficulty_bomb_delays: BTreeMap::new(),
+			progpow_transition: u64::max_value(),
 		}
 	}
 
@@ -607,6 +673,55 @@ mod tests {
 		assert_eq!(b.state().balance(&dev_contract).unwrap(), U256::from_str("c249fdd327780000").unwrap());
 	}
 
+	fn block_reward_contract_reward_types(spec: Spec) -> Vec<(Address, RewardType)> {
+		let engine = &*spec.engine;
+		let genesis_header = spec.genesis_header();
+		let db = spec.ensure_db_good(get_temp_state_db(), &Default::default()).unwrap();
+		let last_hashes = Arc::new(vec![genesis_header.hash()]);
+		let b = OpenBlock::new(engine, Default::default(), true, db, &genesis_header, last_hashes, Address::zero(), (3141562.into(), 31415620.into()), vec![], false, &mut Vec::new().into_iter()).unwrap();
+		let b = b.close().unwrap();
+
+		let traces = match b.block().traces {
+			Tracing::Enabled(ref traces) => traces.clone(),
+			Tracing::Disabled => panic!("Tracing is enabled for the block; qed"),
+		};
+
+		traces.into_iter()
+			.flat_map(|traces| { let traces: Vec<FlatTrace> = traces.into(); traces })
+			.map(|trace| match trace.action {
+				Action::Reward(reward) => {
+					assert_eq!(b.state().balance(&reward.author).unwrap(), reward.value);
+					(reward.author, reward.reward_type)
+				},
+				action => panic!("Unexpected trace action: {:?}", action),
+			})
+			.collect()
+	}
+
+	#[test]
+	fn traces_block_reward_contract_rewards_as_external() {
+		// chains already using a reward contract keep their trace kinds.
+		let rewards = block_reward_contract_reward_types(new_mcip3_test());
+
+		assert_eq!(rewards.len(), 3);
+		assert!(rewards.iter().all(|&(_, ref kind)| *kind == RewardType::External));
+	}
+
+	#[test]
+	fn traces_block_reward_contract_rewards_with_kinds() {
+		let json = String::from_utf8(include_bytes!("../../res/ethereum/mcip3_test.json").to_vec()).unwrap()
+			.replacen("\"blockRewardContractCode\"", "\"blockRewardContractKindsTransition\": \"0x0\", \"blockRewardContractCode\"", 1);
+		let tempdir = TempDir::new("").unwrap();
+		let spec = Spec::load(&tempdir.path(), json.as_bytes()).unwrap();
+		let rewards = block_reward_contract_reward_types(spec);
+
+		assert_eq!(rewards.len(), 3);
+		for (author, kind) in rewards {
+			let expected = if author == Address::zero() { RewardType::Block } else { RewardType::External };
+			assert_eq!(kind, expected);
+		}
+	}
+
 	#[test]
 	fn has_valid_metadata() {
 		let engine = test_spec().engine;
@@ -760,7 +875,7 @@ mod tests {
 		let machine = new_homestead_test_machine();
 		let ethparams = get_default_ethash_params();
 		let tempdir = TempDir::new("").unwrap();
-		let ethash = Ethash::new(tempdir.path(), ethparams, machine, None);
+		let ethash = Ethash::new(tempdir.path(), ethparams, machine, None, false);
 
 		let mut parent_header = Header::default();
 		parent_header.set_number(1000000);
@@ -779,7 +894,7 @@ mod tests {
 		let machine = new_homestead_test_machine();
 		let ethparams = get_default_ethash_params();
 		let tempdir = TempDir::new("").unwrap();
-		let ethash = Ethash::new(tempdir.path(), ethparams, machine, None);
+		let ethash = Ethash::new(tempdir.path(), ethparams, machine, None, false);
 
 		let mut parent_header = Header::default();
 		parent_header.set_number(1500000);
@@ -801,7 +916,7 @@ mod tests {
 			..get_default_ethash_params()
 		};
 		let tempdir = TempDir::new("").unwrap();
-		let ethash = Ethash::new(tempdir.path(), ethparams, machine, None);
+		let ethash = Ethash::new(tempdir.path(), ethparams, machine, None, false);
 
 		let mut parent_header = Header::default();
 		parent_header.set_number(3500000);
@@ -836,7 +951,7 @@ mod tests {
 			..get_default_ethash_params()
 		};
 		let tempdir = TempDir::new("").unwrap();
-		let ethash = Ethash::new(tempdir.path(), ethparams, machine, None);
+		let ethash = Ethash::new(tempdir.path(), ethparams, machine, None, false);
 
 		let mut parent_header = Header::default();
 		parent_header.set_number(5000102);
@@ -883,7 +998,7 @@ mod tests {
 		let machine = new_homestead_test_machine();
 		let ethparams = get_default_ethash_params();
 		let tempdir = TempDir::new("").unwrap();
-		let ethash = Ethash::new(tempdir.path(), ethparams, machine, None);
+		let ethash = Ethash::new(tempdir.path(), ethparams, machine, None, false);
 
 		let mut parent_header = Header::default();
 		parent_header.set_number(1000000);
@@ -902,11 +1017,35 @@ mod tests {
 		let machine = new_homestead_test_machine();
 		let ethparams = get_default_ethash_params();
 		let tempdir = TempDir::new("").unwrap();
-		let ethash = Ethash::new(tempdir.path(), ethparams, machine, None);
+		let ethash = Ethash::new(tempdir.path(), ethparams, machine, None, false);
 		let mut header = Header::default();
 		header.set_seal(vec![rlp::encode(&H256::from("b251bd2e0283d0658f2cadfdc8ca619b5de94eca5742725e2e757dd13ed7503d")), rlp::encode(&H64::zero())]);
 		let info = ethash.extra_info(&header);
 		assert_eq!(info["nonce"], "0x0000000000000000");
 		assert_eq!(info["mixHash"], "0xb251bd2e0283d0658f2cadfdc8ca619b5de94eca5742725e2e757dd13ed7503d");
 	}
+
+	#[test]
+	fn progpow_work_package_requires_number() {
+		let machine = new_homestead_test_machine();
+		let mut ethparams = get_default_ethash_params();
+		ethparams.progpow_transition = 10;
+		let tempdir = TempDir::new("").unwrap();
+		let ethash = Ethash::new(tempdir.path(), ethparams, machine, None, false);
+
+		assert!(!ethash.work_package_requires_number(9));
+		assert!(ethash.work_package_requires_number(10));
+	}
+
+	#[test]
+	fn only_recent_blocks_are_near_chain_head() {
+		let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+		assert!(near_chain_head(1_000_000, now));
+		assert!(near_chain_head(1_000_000 - FULL_DAG_MAX_BLOCK_AGE, now));
+		assert!(!near_chain_head(1_000_000 - FULL_DAG_MAX_BLOCK_AGE - 1, now));
+		assert!(!near_chain_head(0, now));
+		// blocks from the future are checked for drift elsewhere.
+		assert!(near_chain_head(1_000_100, now));
+	}
 }
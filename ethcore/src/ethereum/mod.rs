@@ -218,4 +218,20 @@ mod tests {
 
 		let _ = frontier.engine;
 	}
+
+	#[test]
+	fn hard_forks() {
+		let frontier = new_foundation(&::std::env::temp_dir());
+		assert_eq!(frontier.hard_forks(), vec![1_150_000, 1_920_000, 2_463_000, 2_675_000, 4_370_000, 7_080_000]);
+
+		let classic = new_classic(&::std::env::temp_dir());
+		assert_eq!(classic.hard_forks(), vec![1_150_000, 2_500_000, 3_000_000, 5_000_000, 5_900_000]);
+
+		let ropsten = new_ropsten(&::std::env::temp_dir());
+		assert_eq!(ropsten.hard_forks(), vec![10, 1_700_000, 4_230_000]);
+
+		// Parity-specific transitions, like wasm activation at 6_600_000, aren't forks.
+		let kovan = new_kovan(&::std::env::temp_dir());
+		assert_eq!(kovan.hard_forks(), vec![1_000_000, 5_067_000, 9_200_000]);
+	}
 }
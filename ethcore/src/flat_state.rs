@@ -0,0 +1,733 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Flat account and storage state.
+//!
+//! The accounts of one recent canonical state are stored in `COL_FLAT_STATE` keyed by
+//! address hash, and its storage values keyed by address hash followed by the hash of the
+//! storage key, so they can be read with a single lookup instead of a trie walk.
+//!
+//! The states of more recent blocks are kept as diff layers on top of it. Layers are
+//! identified by state root rather than block hash: a state root commits to the whole
+//! state, so layers of competing forks coexist without knowing which one is canonical.
+//! Layers are journaled to the same column so they survive a restart, and are merged into
+//! the flat state on disk once their block is `FLAT_STATE_LAYERS` blocks deep.
+//!
+//! Generating the flat state from a state trie may take a long time, so it is done on a
+//! separate thread. Layers of blocks imported meanwhile are kept on top of the state being
+//! generated, and reads fall back to the trie until generation finishes.
+
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::sync::Arc;
+use std::thread;
+
+use account_db::AccountDB;
+use bytes::Bytes;
+use db::COL_FLAT_STATE;
+use error::Error;
+use ethereum_types::{H256, U256};
+use ethtrie::TrieDB;
+use hash::KECCAK_NULL_RLP;
+use hashdb::HashDB;
+use header::BlockNumber;
+use journaldb::JournalDB;
+use keccak_hasher::KeccakHasher;
+use kvdb::{DBTransaction, DBValue, KeyValueDB};
+use parking_lot::RwLock;
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+use trie::Trie;
+use types::basic_account::BasicAccount;
+
+/// Number of recent blocks whose state is kept in diff layers.
+pub const FLAT_STATE_LAYERS: u64 = 128;
+
+// key of the state root and block number of the flat state on disk.
+const DISK_ROOT_KEY: &'static [u8] = b"flat_state_root";
+// prefix of journaled diff layers, followed by their state root.
+const LAYER_KEY_PREFIX: &'static [u8] = b"flat_state_layer";
+// number of entries written at once while generating the flat state.
+const BATCH_SIZE: usize = 10_000;
+// how often generation progress is logged, in accounts.
+const PROGRESS_INTERVAL: u64 = 100_000;
+
+/// Changes made to the state by a block.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FlatChanges {
+	/// RLP of changed accounts by address hash, `None` for removed accounts.
+	pub accounts: HashMap<H256, Option<Bytes>>,
+	/// Changed storage values by address hash and storage key hash.
+	pub storage: HashMap<H256, HashMap<H256, H256>>,
+	/// Address hashes whose storage was cleared before `storage` was applied.
+	pub cleared: HashSet<H256>,
+}
+
+impl FlatChanges {
+	/// Apply more recent changes on top of these.
+	pub fn extend(&mut self, other: FlatChanges) {
+		for address_hash in other.cleared {
+			self.storage.remove(&address_hash);
+			self.cleared.insert(address_hash);
+		}
+		self.accounts.extend(other.accounts);
+		for (address_hash, storage) in other.storage {
+			self.storage.entry(address_hash).or_insert_with(HashMap::new).extend(storage);
+		}
+	}
+}
+
+// The changes of a block on top of the state with root `parent`.
+#[derive(Debug, Clone, PartialEq)]
+struct Layer {
+	parent: H256,
+	number: BlockNumber,
+	changes: FlatChanges,
+}
+
+impl Encodable for Layer {
+	fn rlp_append(&self, s: &mut RlpStream) {
+		s.begin_list(5);
+		s.append(&self.parent);
+		s.append(&self.number);
+
+		s.begin_list(self.changes.accounts.len());
+		for (address_hash, account) in &self.changes.accounts {
+			// account RLP is never empty, so an empty item stands for a removed account.
+			s.begin_list(2).append(address_hash).append(account.as_ref().unwrap_or(&Vec::new()));
+		}
+
+		s.begin_list(self.changes.storage.len());
+		for (address_hash, storage) in &self.changes.storage {
+			s.begin_list(2).append(address_hash).begin_list(storage.len());
+			for (key, value) in storage {
+				s.begin_list(2).append(key).append(value);
+			}
+		}
+
+		s.append_list(&self.changes.cleared.iter().cloned().collect::<Vec<H256>>());
+	}
+}
+
+impl Decodable for Layer {
+	fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+		if rlp.item_count()? != 5 {
+			return Err(DecoderError::RlpIncorrectListLen);
+		}
+
+		let mut changes = FlatChanges::default();
+		for item in rlp.at(2)?.iter() {
+			let account: Bytes = item.val_at(1)?;
+			changes.accounts.insert(item.val_at(0)?, if account.is_empty() { None } else { Some(account) });
+		}
+		for item in rlp.at(3)?.iter() {
+			let mut storage = HashMap::new();
+			for entry in item.at(1)?.iter() {
+				storage.insert(entry.val_at(0)?, entry.val_at(1)?);
+			}
+			changes.storage.insert(item.val_at(0)?, storage);
+		}
+		changes.cleared = rlp.list_at::<H256>(4)?.into_iter().collect();
+
+		Ok(Layer {
+			parent: rlp.val_at(0)?,
+			number: rlp.val_at(1)?,
+			changes,
+		})
+	}
+}
+
+struct Layers {
+	// state root and block number of the flat state on disk.
+	disk: Option<(H256, BlockNumber)>,
+	// state root and block number of the flat state being generated.
+	generating: Option<(H256, BlockNumber)>,
+	// diff layers by state root.
+	layers: HashMap<H256, Layer>,
+}
+
+impl Layers {
+	// Walk from the layer with the given root towards the disk, returning the first value
+	// `f` finds. `Err(true)` if the disk was reached, `Err(false)` if the root is unknown.
+	fn find<F, T>(&self, root: &H256, f: F) -> Result<T, bool> where F: Fn(&Layer) -> Option<T> {
+		let mut root = *root;
+		// a layer is visited at most once on the way down.
+		for _ in 0..self.layers.len() + 1 {
+			if self.disk.map_or(false, |(disk_root, _)| disk_root == root) {
+				return Err(true);
+			}
+			match self.layers.get(&root) {
+				Some(layer) => match f(layer) {
+					Some(value) => return Ok(value),
+					None => root = layer.parent,
+				},
+				None => return Err(false),
+			}
+		}
+		Err(false)
+	}
+}
+
+/// Flat state on disk along with the diff layers of recent blocks.
+pub struct FlatState {
+	db: Arc<KeyValueDB>,
+	layers: RwLock<Layers>,
+}
+
+impl FlatState {
+	/// Load the flat state and its journaled diff layers from the database.
+	pub fn new(db: Arc<KeyValueDB>) -> Result<Self, Error> {
+		let disk = match db.get(COL_FLAT_STATE, DISK_ROOT_KEY)? {
+			Some(value) => {
+				let rlp = Rlp::new(&value);
+				Some((rlp.val_at(0)?, rlp.val_at(1)?))
+			},
+			None => None,
+		};
+
+		let mut layers = HashMap::new();
+		for (key, value) in db.iter_from_prefix(COL_FLAT_STATE, LAYER_KEY_PREFIX) {
+			if !key.starts_with(LAYER_KEY_PREFIX) {
+				break;
+			}
+			layers.insert(H256::from_slice(&key[LAYER_KEY_PREFIX.len()..]), ::rlp::decode(&value)?);
+		}
+
+		Ok(FlatState {
+			db,
+			layers: RwLock::new(Layers { disk, generating: None, layers }),
+		})
+	}
+
+	/// Whether the accounts and storage of the state with the given root can be read.
+	pub fn is_available(&self, root: &H256) -> bool {
+		self.layers.read().find(root, |_| None::<()>) == Err(true)
+	}
+
+	/// Whether there is neither a flat state on disk nor one being generated.
+	pub fn needs_generation(&self) -> bool {
+		let layers = self.layers.read();
+		layers.disk.is_none() && layers.generating.is_none()
+	}
+
+	/// Number of the block whose flat state is being generated, if any. The state of that
+	/// block must not be pruned until generation finishes.
+	pub fn generating(&self) -> Option<BlockNumber> {
+		self.layers.read().generating.map(|(_, number)| number)
+	}
+
+	/// Number of diff layers kept in memory.
+	pub fn layers(&self) -> usize {
+		self.layers.read().layers.len()
+	}
+
+	/// Get the RLP of an account in the state with the given root.
+	/// Returns `None` if that state isn't available and `Some(None)` if the account doesn't exist.
+	pub fn account(&self, root: &H256, address_hash: &H256) -> Option<Option<Bytes>> {
+		let layers = self.layers.read();
+		match layers.find(root, |layer| layer.changes.accounts.get(address_hash).cloned()) {
+			Ok(account) => Some(account),
+			Err(true) => Some(self.db.get(COL_FLAT_STATE, address_hash)
+				.expect("Low-level database error. Some issue with disk?")
+				.map(|value| value.into_vec())),
+			Err(false) => None,
+		}
+	}
+
+	/// Get a storage value of an account in the state with the given root.
+	/// Returns `None` if that state isn't available.
+	pub fn storage(&self, root: &H256, address_hash: &H256, key_hash: &H256) -> Option<H256> {
+		let layers = self.layers.read();
+		let found = layers.find(root, |layer| match layer.changes.storage.get(address_hash).and_then(|storage| storage.get(key_hash)) {
+			Some(value) => Some(*value),
+			None if layer.changes.cleared.contains(address_hash) => Some(H256::zero()),
+			None => None,
+		});
+
+		match found {
+			Ok(value) => Some(value),
+			Err(true) => Some(self.db.get(COL_FLAT_STATE, &storage_key(address_hash, key_hash))
+				.expect("Low-level database error. Some issue with disk?")
+				.map_or_else(H256::zero, |value| H256::from_slice(&value))),
+			Err(false) => None,
+		}
+	}
+
+	/// Add the layer of a newly committed block with the given state root on top of the layer
+	/// of its parent state, journaling it into `batch`. Nothing is recorded unless there is
+	/// a flat state on disk or one being generated.
+	pub fn insert_layer(&self, batch: &mut DBTransaction, parent: H256, root: H256, number: BlockNumber, changes: FlatChanges) {
+		let mut layers = self.layers.write();
+		let base = layers.disk.or(layers.generating);
+		match base {
+			None => return,
+			Some((base_root, _)) if base_root == root => return,
+			Some(_) if parent == root || layers.layers.contains_key(&root) => return,
+			Some(_) => {},
+		}
+
+		let layer = Layer { parent, number, changes };
+		batch.put(COL_FLAT_STATE, &layer_key(&root), &::rlp::encode(&layer));
+		layers.layers.insert(root, layer);
+	}
+
+	/// Merge the layer of the canonical block with the given number and state root, and all
+	/// layers below it, into the flat state on disk. Layers of forks which can no longer
+	/// become canonical are dropped.
+	///
+	/// If the layer doesn't lead to the flat state on disk, e.g. after a reorganization deeper
+	/// than `FLAT_STATE_LAYERS`, the flat state is discarded and has to be generated again,
+	/// see `needs_generation`.
+	pub fn flatten(&self, number: BlockNumber, root: &H256) -> Result<(), Error> {
+		let mut batch = DBTransaction::new();
+		let merged = {
+			let layers = self.layers.read();
+			let disk_number = match layers.disk {
+				Some((_, disk_number)) => disk_number,
+				None => return Ok(()),
+			};
+			if number <= disk_number {
+				return Ok(());
+			}
+
+			// collect the layers from the root down to the disk.
+			let mut merged = Vec::new();
+			let mut current = *root;
+			while layers.disk.map_or(true, |(disk_root, _)| disk_root != current) && merged.len() <= layers.layers.len() {
+				match layers.layers.get(&current) {
+					Some(layer) => {
+						merged.push(current);
+						current = layer.parent;
+					},
+					None => break,
+				}
+			}
+			if layers.disk.map_or(true, |(disk_root, _)| disk_root != current) {
+				None
+			} else {
+				// merge the changes first, so storage cleared by a later layer is dropped.
+				let mut changes = FlatChanges::default();
+				for root in merged.iter().rev() {
+					changes.extend(layers.layers[root].changes.clone());
+				}
+
+				for address_hash in &changes.cleared {
+					self.clear_storage(&mut batch, address_hash);
+				}
+				for (address_hash, account) in &changes.accounts {
+					match *account {
+						Some(ref account) => batch.put(COL_FLAT_STATE, address_hash, account),
+						None => batch.delete(COL_FLAT_STATE, address_hash),
+					}
+				}
+				for (address_hash, storage) in &changes.storage {
+					for (key_hash, value) in storage {
+						let key = storage_key(address_hash, key_hash);
+						if value.is_zero() {
+							batch.delete(COL_FLAT_STATE, &key);
+						} else {
+							batch.put(COL_FLAT_STATE, &key, value);
+						}
+					}
+				}
+				Some(merged)
+			}
+		};
+
+		let merged = match merged {
+			Some(merged) => merged,
+			None => {
+				warn!(target: "flat_state", "State of block #{} doesn't lead to the flat state. It will be generated again.", number);
+				return self.reset(None);
+			},
+		};
+
+		let mut layers = self.layers.write();
+		let mut dropped: Vec<H256> = merged;
+		dropped.extend(layers.layers.iter().filter(|&(_, layer)| layer.number <= number).map(|(root, _)| *root));
+		for root in &dropped {
+			layers.layers.remove(root);
+		}
+
+		// drop the layers built on top of dropped forks.
+		loop {
+			let orphans: Vec<H256> = layers.layers.iter()
+				.filter(|&(_, layer)| layer.parent != *root && !layers.layers.contains_key(&layer.parent))
+				.map(|(orphan, _)| *orphan)
+				.collect();
+			if orphans.is_empty() {
+				break;
+			}
+			for orphan in orphans {
+				layers.layers.remove(&orphan);
+				dropped.push(orphan);
+			}
+		}
+
+		for root in &dropped {
+			batch.delete(COL_FLAT_STATE, &layer_key(root));
+		}
+		batch.put(COL_FLAT_STATE, DISK_ROOT_KEY, &disk_root_value(root, number));
+
+		// the layers being merged stay visible until the disk is written, so readers never see
+		// a partially updated state.
+		self.db.write(batch)?;
+		layers.disk = Some((*root, number));
+		trace!(target: "flat_state", "Flattened state of block #{}, {} layers left", number, layers.layers.len());
+		Ok(())
+	}
+
+	/// Fill the flat state from the state trie with the given root, which becomes the flat
+	/// state on disk. All existing flat state and layers are discarded.
+	/// Returns the number of accounts written.
+	pub fn generate(&self, state: &HashDB<KeccakHasher, DBValue>, number: BlockNumber, root: &H256) -> Result<u64, Error> {
+		self.reset(Some((*root, number)))?;
+		self.fill(state, number, root)
+	}
+
+	/// Like `generate`, but fills the flat state on a separate thread. The state with the given
+	/// root has to stay in `state` until generation finishes, which fails otherwise, so pruning
+	/// must hold back while `generating` returns the block number.
+	pub fn generate_in_background(flat_state: Arc<FlatState>, state: Box<JournalDB>, number: BlockNumber, root: H256) -> Result<(), Error> {
+		flat_state.reset(Some((root, number)))?;
+		let generator = flat_state.clone();
+		let spawned = thread::Builder::new()
+			.name("Flat state".into())
+			.spawn(move || {
+				if let Err(e) = generator.fill(state.as_hashdb(), number, &root) {
+					warn!(target: "flat_state", "Failed to generate the flat state of block #{}: {}", number, e);
+				}
+			});
+		if let Err(e) = spawned {
+			flat_state.layers.write().generating = None;
+			return Err(e.into());
+		}
+		Ok(())
+	}
+
+	// Fill the flat state being generated from the state trie. Reads of the flat state are
+	// served from the trie until it becomes the flat state on disk.
+	fn fill(&self, state: &HashDB<KeccakHasher, DBValue>, number: BlockNumber, root: &H256) -> Result<u64, Error> {
+		let result = self.clear_entries().and_then(|_| self.fill_entries(state, root));
+		let mut layers = self.layers.write();
+		let accounts = match result {
+			Ok(accounts) => accounts,
+			Err(e) => {
+				if layers.generating == Some((*root, number)) {
+					layers.generating = None;
+				}
+				return Err(e);
+			},
+		};
+
+		// the flat state was discarded meanwhile.
+		if layers.generating != Some((*root, number)) {
+			return Ok(accounts);
+		}
+		let mut batch = DBTransaction::new();
+		batch.put(COL_FLAT_STATE, DISK_ROOT_KEY, &disk_root_value(root, number));
+		self.db.write(batch)?;
+		layers.disk = Some((*root, number));
+		layers.generating = None;
+		info!(target: "flat_state", "Generated flat state of block #{} with {} accounts, {} layers on top", number, accounts, layers.layers.len());
+		Ok(accounts)
+	}
+
+	// Write the accounts and storage values of the state trie with the given root.
+	fn fill_entries(&self, state: &HashDB<KeccakHasher, DBValue>, root: &H256) -> Result<u64, Error> {
+		let mut batch = DBTransaction::new();
+		let mut pending = 0;
+		let mut accounts = 0;
+		// the empty state has no nodes in the database.
+		if *root != KECCAK_NULL_RLP {
+			let trie = TrieDB::new(state, root)?;
+			for item in trie.iter()? {
+				let (key, value) = item?;
+				let address_hash = H256::from_slice(&key);
+				batch.put(COL_FLAT_STATE, &address_hash, &value);
+				pending += 1;
+
+				let account: BasicAccount = ::rlp::decode(&value)?;
+				if account.storage_root != KECCAK_NULL_RLP {
+					let account_db = AccountDB::from_hash(state, address_hash);
+					let storage = TrieDB::new(&account_db, &account.storage_root)?;
+					for item in storage.iter()? {
+						let (key_hash, value) = item?;
+						let value: U256 = ::rlp::decode(&value)?;
+						batch.put(COL_FLAT_STATE, &storage_key(&address_hash, &H256::from_slice(&key_hash)), &H256::from(value));
+						pending += 1;
+						if pending >= BATCH_SIZE {
+							self.db.write(mem::replace(&mut batch, DBTransaction::new()))?;
+							pending = 0;
+						}
+					}
+				}
+				if pending >= BATCH_SIZE {
+					self.db.write(mem::replace(&mut batch, DBTransaction::new()))?;
+					pending = 0;
+				}
+
+				accounts += 1;
+				if accounts % PROGRESS_INTERVAL == 0 {
+					info!(target: "flat_state", "Generating flat state: {} accounts written", accounts);
+				}
+			}
+		}
+		self.db.write(batch)?;
+		Ok(accounts)
+	}
+
+	// Discard the flat state on disk along with all layers, and start recording layers on top
+	// of the given state if it is being generated. Accounts and storage values of the discarded
+	// flat state are left to be deleted before the next one is filled.
+	fn reset(&self, generating: Option<(H256, BlockNumber)>) -> Result<(), Error> {
+		let mut layers = self.layers.write();
+		let mut batch = DBTransaction::new();
+		batch.delete(COL_FLAT_STATE, DISK_ROOT_KEY);
+		for root in layers.layers.keys() {
+			batch.delete(COL_FLAT_STATE, &layer_key(root));
+		}
+		self.db.write(batch)?;
+		layers.disk = None;
+		layers.generating = generating;
+		layers.layers.clear();
+		Ok(())
+	}
+
+	// Delete all accounts and storage values, keeping the journaled layers.
+	fn clear_entries(&self) -> Result<(), Error> {
+		let mut batch = DBTransaction::new();
+		let mut pending = 0;
+		for (key, _) in self.db.iter(COL_FLAT_STATE) {
+			if key.len() != 32 && key.len() != 64 {
+				continue;
+			}
+			batch.delete(COL_FLAT_STATE, &key);
+			pending += 1;
+			if pending >= BATCH_SIZE {
+				self.db.write(mem::replace(&mut batch, DBTransaction::new()))?;
+				pending = 0;
+			}
+		}
+		self.db.write(batch)?;
+		Ok(())
+	}
+
+	// Delete all storage values of an account from the flat state on disk.
+	fn clear_storage(&self, batch: &mut DBTransaction, address_hash: &H256) {
+		for (key, _) in self.db.iter_from_prefix(COL_FLAT_STATE, address_hash) {
+			if !key.starts_with(address_hash) {
+				break;
+			}
+			if key.len() == 64 {
+				batch.delete(COL_FLAT_STATE, &key);
+			}
+		}
+	}
+}
+
+fn storage_key(address_hash: &H256, key_hash: &H256) -> [u8; 64] {
+	let mut key = [0u8; 64];
+	key[..32].copy_from_slice(address_hash);
+	key[32..].copy_from_slice(key_hash);
+	key
+}
+
+fn layer_key(root: &H256) -> Vec<u8> {
+	let mut key = LAYER_KEY_PREFIX.to_vec();
+	key.extend_from_slice(root);
+	key
+}
+
+fn disk_root_value(root: &H256, number: BlockNumber) -> Bytes {
+	let mut stream = RlpStream::new_list(2);
+	stream.append(root).append(&number);
+	stream.out()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use ethereum_types::Address;
+	use hash::keccak;
+	use kvdb_memorydb;
+	use state::State;
+	use test_helpers::get_temp_state_db;
+
+	fn account(nonce: u64) -> Bytes {
+		::rlp::encode(&BasicAccount {
+			nonce: nonce.into(),
+			balance: 0.into(),
+			storage_root: KECCAK_NULL_RLP,
+			code_hash: ::hash::KECCAK_EMPTY,
+		}).into_vec()
+	}
+
+	fn changes(accounts: &[(H256, Option<Bytes>)], storage: &[(H256, H256, H256)], cleared: &[H256]) -> FlatChanges {
+		let mut changes = FlatChanges::default();
+		changes.accounts = accounts.iter().cloned().collect();
+		for &(address_hash, key, value) in storage {
+			changes.storage.entry(address_hash).or_insert_with(HashMap::new).insert(key, value);
+		}
+		changes.cleared = cleared.iter().cloned().collect();
+		changes
+	}
+
+	// flat state with an empty state of block 0 on disk.
+	fn new_flat_state() -> (Arc<KeyValueDB>, FlatState) {
+		let db: Arc<KeyValueDB> = Arc::new(kvdb_memorydb::create(::db::NUM_COLUMNS.unwrap()));
+		let flat = FlatState::new(db.clone()).unwrap();
+		flat.generate(&::memorydb::MemoryDB::<KeccakHasher, DBValue>::new(), 0, &KECCAK_NULL_RLP).unwrap();
+		(db, flat)
+	}
+
+	#[test]
+	fn reads_through_layers() {
+		let (a, b) = (keccak("a"), keccak("b"));
+		let (r1, r2, fork) = (H256::from(1), H256::from(2), H256::from(3));
+		let (_, flat) = new_flat_state();
+
+		let mut batch = DBTransaction::new();
+		flat.insert_layer(&mut batch, KECCAK_NULL_RLP, r1, 1, changes(&[(a, Some(account(1)))], &[(a, 1.into(), 10.into())], &[]));
+		flat.insert_layer(&mut batch, r1, r2, 2, changes(&[(b, Some(account(2)))], &[(a, 2.into(), 20.into())], &[]));
+		flat.insert_layer(&mut batch, r1, fork, 2, changes(&[(a, None)], &[], &[a]));
+
+		assert_eq!(flat.account(&r2, &a), Some(Some(account(1))));
+		assert_eq!(flat.account(&r2, &b), Some(Some(account(2))));
+		assert_eq!(flat.account(&r1, &b), Some(None));
+		assert_eq!(flat.account(&fork, &a), Some(None));
+		assert_eq!(flat.storage(&r2, &a, &1.into()), Some(10.into()));
+		assert_eq!(flat.storage(&r1, &a, &2.into()), Some(H256::zero()));
+		assert_eq!(flat.storage(&fork, &a, &1.into()), Some(H256::zero()));
+		assert_eq!(flat.account(&H256::from(4), &a), None);
+		assert!(!flat.is_available(&H256::from(4)));
+	}
+
+	#[test]
+	fn flattens_canonical_layers() {
+		let a = keccak("a");
+		let (r1, r2, fork, fork_child) = (H256::from(1), H256::from(2), H256::from(3), H256::from(4));
+		let (db, flat) = new_flat_state();
+
+		let mut batch = DBTransaction::new();
+		flat.insert_layer(&mut batch, KECCAK_NULL_RLP, r1, 1, changes(&[(a, Some(account(1)))], &[(a, 1.into(), 10.into()), (a, 2.into(), 20.into())], &[]));
+		flat.insert_layer(&mut batch, KECCAK_NULL_RLP, fork, 1, changes(&[(a, Some(account(5)))], &[], &[]));
+		flat.insert_layer(&mut batch, fork, fork_child, 2, changes(&[], &[], &[]));
+		flat.insert_layer(&mut batch, r1, r2, 2, changes(&[(a, Some(account(2)))], &[(a, 2.into(), H256::zero())], &[]));
+		db.write(batch).unwrap();
+
+		flat.flatten(1, &r1).unwrap();
+		assert_eq!(flat.layers(), 1);
+		assert!(!flat.is_available(&fork_child));
+		assert_eq!(flat.account(&r1, &a), Some(Some(account(1))));
+		assert_eq!(flat.storage(&r2, &a, &1.into()), Some(10.into()));
+		assert_eq!(flat.storage(&r2, &a, &2.into()), Some(H256::zero()));
+
+		flat.flatten(2, &r2).unwrap();
+		assert_eq!(flat.layers(), 0);
+		assert_eq!(flat.account(&r2, &a), Some(Some(account(2))));
+		assert_eq!(flat.storage(&r2, &a, &2.into()), Some(H256::zero()));
+
+		// clearing the storage removes it from the disk.
+		let mut batch = DBTransaction::new();
+		let r3 = H256::from(5);
+		flat.insert_layer(&mut batch, r2, r3, 3, changes(&[(a, None)], &[], &[a]));
+		db.write(batch).unwrap();
+		flat.flatten(3, &r3).unwrap();
+		assert_eq!(flat.account(&r3, &a), Some(None));
+		assert_eq!(flat.storage(&r3, &a, &1.into()), Some(H256::zero()));
+		assert!(db.iter(COL_FLAT_STATE).all(|(key, _)| key.len() != 64));
+
+		// the journal is reloaded.
+		let reloaded = FlatState::new(db.clone()).unwrap();
+		assert!(reloaded.is_available(&r3));
+		assert!(!reloaded.is_available(&r2));
+	}
+
+	#[test]
+	fn discards_flat_state_on_deep_reorg() {
+		let (db, flat) = new_flat_state();
+		let mut batch = DBTransaction::new();
+		flat.insert_layer(&mut batch, KECCAK_NULL_RLP, H256::from(1), 1, FlatChanges::default());
+		db.write(batch).unwrap();
+
+		flat.flatten(1, &H256::from(2)).unwrap();
+		assert!(!flat.is_available(&KECCAK_NULL_RLP));
+		assert!(flat.needs_generation());
+		assert!(!FlatState::new(db.clone()).unwrap().is_available(&KECCAK_NULL_RLP));
+
+		// no layers are recorded without a flat state.
+		let mut batch = DBTransaction::new();
+		flat.insert_layer(&mut batch, KECCAK_NULL_RLP, H256::from(3), 2, FlatChanges::default());
+		db.write(batch).unwrap();
+		assert_eq!(flat.layers(), 0);
+		assert_eq!(FlatState::new(db).unwrap().layers(), 0);
+	}
+
+	#[test]
+	fn keeps_layers_during_generation() {
+		let a = keccak("a");
+		let (db, flat) = new_flat_state();
+		let mut batch = DBTransaction::new();
+		batch.put(COL_FLAT_STATE, &a, &account(1));
+		db.write(batch).unwrap();
+
+		flat.reset(Some((KECCAK_NULL_RLP, 1))).unwrap();
+		assert!(!flat.needs_generation());
+		assert_eq!(flat.generating(), Some(1));
+		let mut batch = DBTransaction::new();
+		flat.insert_layer(&mut batch, KECCAK_NULL_RLP, H256::from(1), 2, changes(&[(keccak("b"), Some(account(2)))], &[], &[]));
+		db.write(batch).unwrap();
+		// reads fall back to the trie until generation finishes.
+		assert_eq!(flat.account(&H256::from(1), &a), None);
+		flat.flatten(2, &H256::from(1)).unwrap();
+		assert_eq!(flat.layers(), 1);
+
+		flat.fill(&::memorydb::MemoryDB::<KeccakHasher, DBValue>::new(), 1, &KECCAK_NULL_RLP).unwrap();
+		assert_eq!(flat.generating(), None);
+		assert!(flat.is_available(&H256::from(1)));
+		assert_eq!(flat.account(&H256::from(1), &a), Some(None));
+		assert_eq!(flat.account(&H256::from(1), &keccak("b")), Some(Some(account(2))));
+		assert!(FlatState::new(db).unwrap().is_available(&H256::from(1)));
+	}
+
+	#[test]
+	fn state_reads_and_tracks_flat_state() {
+		let flat = Arc::new(FlatState::new(Arc::new(kvdb_memorydb::create(::db::NUM_COLUMNS.unwrap()))).unwrap());
+		let mut state_db = get_temp_state_db();
+		flat.generate(state_db.as_hashdb(), 0, &KECCAK_NULL_RLP).unwrap();
+		state_db = state_db.with_flat_state(flat.clone());
+
+		let (a, b) = (Address::from(1), Address::from(2));
+		let mut state = State::new(state_db, 0.into(), Default::default());
+		state.add_balance(&a, &100.into(), ::state::CleanupMode::NoEmpty).unwrap();
+		state.set_storage(&a, 1.into(), 10.into()).unwrap();
+		state.new_contract(&b, 0.into(), 0.into()).unwrap();
+		state.set_storage(&b, 2.into(), 20.into()).unwrap();
+		state.commit().unwrap();
+		let (root, mut state_db) = state.drop();
+
+		let mut batch = DBTransaction::new();
+		state_db.journal_under(&mut batch, 1, &H256::from(1)).unwrap();
+		assert!(flat.is_available(&root));
+		assert_eq!(flat.storage(&root, &keccak(&a), &keccak(&H256::from(1))), Some(10.into()));
+
+		let state = State::from_existing(state_db.boxed_clone(), root, 0.into(), Default::default()).unwrap();
+		assert_eq!(state.balance(&a).unwrap(), 100.into());
+		assert_eq!(state.storage_at(&a, &1.into()).unwrap(), 10.into());
+		assert_eq!(state.storage_at(&b, &2.into()).unwrap(), 20.into());
+		assert_eq!(state.storage_at(&b, &3.into()).unwrap(), H256::zero());
+	}
+}
@@ -112,6 +112,7 @@ extern crate serde;
 #[cfg(any(test, feature = "json-tests", feature = "test-helpers"))]
 extern crate tempdir;
 extern crate len_caching_lock;
+extern crate zstd;
 
 #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows", target_os = "android"))]
 extern crate hardware_wallet;
@@ -160,6 +161,7 @@ pub mod error;
 pub mod ethereum;
 pub mod executed;
 pub mod executive;
+pub mod flat_state;
 pub mod header;
 pub mod machine;
 pub mod miner;
@@ -189,5 +191,5 @@ pub mod test_helpers;
 pub use types::*;
 pub use executive::contract_address;
 pub use evm::CreateContractAddress;
-pub use blockchain::{BlockChainDB, BlockChainDBHandler};
+pub use blockchain::{BlockChainDB, BlockChainDBHandler, ChainCheck, CompressedColumn, check_database, is_block_receipts_key, prune_history, prune_state, repair_journal, JournalRepair, StatePruning};
 pub use trie::TrieSpec;
@@ -616,6 +616,7 @@ impl Miner {
 			let sealing = self.sealing.lock();
 			if block.transactions().is_empty()
 				&& !self.forced_sealing()
+				&& !self.engine.dev_controls().map_or(false, |dev| dev.is_seal_forced())
 				&& Instant::now() <= sealing.next_mandatory_reseal
 			{
 				return false
@@ -142,6 +142,27 @@ pub fn to_fat_rlps(account_hash: &H256, acc: &BasicAccount, acct_db: &AccountDB,
 	}
 }
 
+// encode an account along with a part of its storage as an RLP item of a state chunk,
+// referring to any code by its hash.
+pub fn to_fat_rlp_with_storage(account_hash: &H256, acc: &BasicAccount, storage: &[(H256, Bytes)]) -> Bytes {
+	let mut stream = RlpStream::new_list(2);
+	stream.append(account_hash);
+	stream.begin_list(5);
+	stream.append(&acc.nonce).append(&acc.balance);
+
+	if acc.code_hash == KECCAK_EMPTY {
+		stream.append(&CodeState::Empty.raw()).append_empty_data();
+	} else {
+		stream.append(&CodeState::Hash.raw()).append(&acc.code_hash);
+	}
+
+	stream.begin_list(storage.len());
+	for &(ref key, ref value) in storage {
+		stream.begin_list(2).append(key).append(value);
+	}
+	stream.out()
+}
+
 // decode a fat rlp, and rebuild the storage trie as we go.
 // returns the account structure along with its newly recovered code,
 // if it exists.
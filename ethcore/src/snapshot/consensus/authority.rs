@@ -142,6 +142,7 @@ impl SnapshotComponents for PoaSnapshot {
 		}))
 	}
 
+	fn has_epoch_transitions(&self) -> bool { true }
 	fn min_supported_version(&self) -> u64 { 3 }
 	fn current_version(&self) -> u64 { 3 }
 }
@@ -24,7 +24,8 @@ use blockchain::{BlockChain, BlockChainDB};
 use engines::EthEngine;
 use snapshot::{Error, ManifestData, Progress};
 
-use ethereum_types::H256;
+use bytes::Bytes;
+use ethereum_types::{H256, U256};
 
 mod authority;
 mod work;
@@ -67,6 +68,20 @@ pub trait SnapshotComponents: Send {
 		manifest: &ManifestData,
 	) -> Result<Box<Rebuilder>, ::error::Error>;
 
+	/// Create a block chunk holding just the given block and its receipts, for restoring
+	/// a state downloaded without a snapshot. The parent's total difficulty is trusted.
+	///
+	/// `None` indicates the engine can't be restored from a single block.
+	fn pivot_chunk(&self, _block: &[u8], _receipts: &[u8], _parent_total_difficulty: U256) -> Option<Bytes> {
+		None
+	}
+
+	/// Whether the chunks carry epoch transition proofs, which restoring verifies from
+	/// genesis up to the snapshot block.
+	fn has_epoch_transitions(&self) -> bool {
+		false
+	}
+
 	/// Minimum supported snapshot version number.
 	fn min_supported_version(&self) -> u64;
 
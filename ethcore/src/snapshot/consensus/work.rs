@@ -30,7 +30,7 @@ use blockchain::{BlockChain, BlockChainDB, BlockProvider};
 use engines::EthEngine;
 use snapshot::{Error, ManifestData, Progress};
 use snapshot::block::AbridgedBlock;
-use ethereum_types::H256;
+use ethereum_types::{H256, U256};
 use kvdb::KeyValueDB;
 use bytes::Bytes;
 use rlp::{RlpStream, Rlp};
@@ -87,6 +87,17 @@ impl SnapshotComponents for PowSnapshot {
 		PowRebuilder::new(chain, db.key_value().clone(), manifest, self.max_restore_blocks).map(|r| Box::new(r) as Box<_>)
 	}
 
+	fn pivot_chunk(&self, block: &[u8], receipts: &[u8], parent_total_difficulty: U256) -> Option<Bytes> {
+		let block = encoded::Block::new(block.to_vec());
+		let header = block.header_view();
+		let abridged_rlp = AbridgedBlock::from_block_view(&block.view()).into_inner();
+
+		let mut stream = RlpStream::new_list(4);
+		stream.append(&(header.number() - 1)).append(&header.parent_hash()).append(&parent_total_difficulty);
+		stream.begin_list(2).append_raw(&abridged_rlp, 1).append_raw(receipts, 1);
+		Some(stream.out())
+	}
+
 	fn min_supported_version(&self) -> u64 { ::snapshot::MIN_SUPPORTED_STATE_CHUNK_VERSION }
 	fn current_version(&self) -> u64 { ::snapshot::STATE_CHUNK_VERSION }
 }
@@ -226,7 +237,6 @@ impl Rebuilder for PowRebuilder {
 	/// Returns the number of blocks fed or any errors.
 	fn feed(&mut self, chunk: &[u8], engine: &EthEngine, abort_flag: &AtomicBool) -> Result<(), ::error::Error> {
 		use snapshot::verify_old_block;
-		use ethereum_types::U256;
 		use triehash::ordered_trie_root;
 
 		let rlp = Rlp::new(chunk);
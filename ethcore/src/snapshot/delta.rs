@@ -0,0 +1,458 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Delta snapshots.
+//!
+//! A delta snapshot only holds the state-trie nodes (and code) of its block which aren't
+//! part of the state of its base snapshot. They are found by walking the new state trie
+//! alongside the base one and skipping every subtree whose reference didn't change at the
+//! same position, so the base state must still be in the database when taking a delta.
+//!
+//! Delta state chunks are lists of items of one of the following forms:
+//!   [0, node] - a node of the account trie.
+//!   [1, account_hash, data] - a storage trie node or the code of the given account.
+//!   [2, account_hash] - the given account is non-empty, for the account bloom.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use account_db::{AccountDB, AccountDBMut, combine_key};
+use bloom_journal::Bloom;
+use bytes::Bytes;
+use ethereum_types::{H256, U256};
+use ethtrie::{RlpCodec, TrieDB, TrieError};
+use hash::{keccak, KECCAK_EMPTY, KECCAK_NULL_RLP};
+use hashdb::HashDB;
+use keccak_hasher::KeccakHasher;
+use kvdb::DBValue;
+use parking_lot::Mutex;
+use rlp::{Rlp, RlpStream};
+use trie::{NibbleSlice, NodeCodec, Trie};
+use trie::node::Node;
+
+use super::{DeltaManifest, ManifestData, Error, Progress, StateChunker, PREFERRED_CHUNK_SIZE};
+use super::io::SnapshotWriter;
+use state::Account as StateAccount;
+use types::basic_account::BasicAccount;
+
+const STATE_NODE: u8 = 0;
+const ACCOUNT_DATA: u8 = 1;
+const ACCOUNT_PRESENT: u8 = 2;
+
+/// Check that each of the delta snapshots is based on the snapshot preceding it,
+/// starting with the full snapshot `base`.
+pub fn verify_delta_chain(base: &ManifestData, deltas: &[DeltaManifest]) -> Result<(), Error> {
+	let mut prev = base;
+	for delta in deltas {
+		if delta.base_hash != prev.block_hash || delta.base_state_root != prev.state_root {
+			return Err(Error::UnlinkedDelta(delta.manifest.block_number));
+		}
+		prev = &delta.manifest;
+	}
+
+	Ok(())
+}
+
+/// Walk the state trie at `root` alongside the base state trie at `base_root`, writing
+/// out all nodes which aren't part of the base state as delta chunks.
+///
+/// Returns a list of hashes of chunks created, or any error it may
+/// have encountered.
+pub fn chunk_state_delta<'a>(db: &HashDB<KeccakHasher, DBValue>, base_root: &H256, root: &H256, writer: &Mutex<SnapshotWriter + 'a>, progress: &'a Progress) -> Result<Vec<H256>, Error> {
+	let mut chunker = DeltaChunker(StateChunker {
+		hashes: Vec::new(),
+		rlps: Vec::new(),
+		cur_size: 0,
+		snappy_buffer: vec![0; ::snappy::max_compressed_len(PREFERRED_CHUNK_SIZE)],
+		writer: writer,
+		progress: progress,
+	});
+
+	walk_state_delta(db, base_root, root, &mut chunker)?;
+
+	let mut chunker = chunker.0;
+	if chunker.cur_size != 0 {
+		chunker.write_chunk()?;
+	}
+
+	Ok(chunker.hashes)
+}
+
+/// Collect the state-trie nodes and code of the state at `root` which aren't part of the
+/// state at `base_root`, keyed as they are stored in the state database.
+pub fn state_delta(db: &HashDB<KeccakHasher, DBValue>, base_root: &H256, root: &H256) -> Result<Vec<(H256, DBValue)>, Error> {
+	let mut collector = DeltaCollector(Vec::new());
+	walk_state_delta(db, base_root, root, &mut collector)?;
+	Ok(collector.0)
+}
+
+fn walk_state_delta<S: DeltaSink>(db: &HashDB<KeccakHasher, DBValue>, base_root: &H256, root: &H256, sink: &mut S) -> Result<(), Error> {
+	if !db.contains(base_root) {
+		return Err(TrieError::InvalidStateRoot(*base_root).into());
+	}
+
+	let mut visitor = AccountVisitor {
+		db: db,
+		base: TrieDB::new(db, base_root)?,
+		sink: sink,
+		empty_account: StateAccount::new_basic(U256::zero(), U256::zero()).rlp(),
+	};
+
+	diff_node(db, &::rlp::encode(root), Some(&::rlp::encode(base_root)[..]), &mut Vec::new(), &mut visitor)
+}
+
+/// Write the items of an uncompressed delta state chunk into the given database,
+/// noting non-empty accounts in the bloom.
+pub fn rebuild_delta(
+	db: &mut HashDB<KeccakHasher, DBValue>,
+	chunk: Rlp,
+	bloom: &mut Bloom,
+	abort_flag: &AtomicBool,
+) -> Result<(), ::error::Error> {
+	for item in chunk.iter() {
+		if !abort_flag.load(Ordering::SeqCst) { return Err(Error::RestorationAborted.into()) }
+
+		match item.val_at::<u8>(0)? {
+			STATE_NODE => {
+				let node: Bytes = item.val_at(1)?;
+				db.emplace(keccak(&node), DBValue::from_slice(&node));
+			},
+			ACCOUNT_DATA => {
+				let account_hash: H256 = item.val_at(1)?;
+				let data: Bytes = item.val_at(2)?;
+				AccountDBMut::from_hash(db, account_hash).emplace(keccak(&data), DBValue::from_slice(&data));
+			},
+			ACCOUNT_PRESENT => {
+				let account_hash: H256 = item.val_at(1)?;
+				bloom.set(&*account_hash);
+			},
+			kind => return Err(Error::WrongChunkFormat(format!("Unknown delta item kind {}", kind)).into()),
+		}
+	}
+
+	Ok(())
+}
+
+// State chunker which starts a new chunk whenever the next item wouldn't fit.
+struct DeltaChunker<'a>(StateChunker<'a>);
+
+impl<'a> DeltaChunker<'a> {
+	fn push(&mut self, item: Bytes) -> Result<(), Error> {
+		if self.0.chunk_size() != 0 && self.0.chunk_size() + item.len() > PREFERRED_CHUNK_SIZE {
+			self.0.write_chunk()?;
+		}
+		self.0.push(item)
+	}
+}
+
+// Receives the items of a state delta.
+trait DeltaSink {
+	// A node of the account trie.
+	fn state_node(&mut self, data: &[u8]) -> Result<(), Error>;
+
+	// The given account is non-empty.
+	fn account_present(&mut self, account_hash: &H256) -> Result<(), Error>;
+
+	// A storage trie node or the code of the given account.
+	fn account_data(&mut self, account_hash: &H256, data: &[u8]) -> Result<(), Error>;
+}
+
+impl<'a> DeltaSink for DeltaChunker<'a> {
+	fn state_node(&mut self, data: &[u8]) -> Result<(), Error> {
+		let mut stream = RlpStream::new_list(2);
+		stream.append(&STATE_NODE).append(&data);
+		self.push(stream.out())
+	}
+
+	fn account_present(&mut self, account_hash: &H256) -> Result<(), Error> {
+		let mut stream = RlpStream::new_list(2);
+		stream.append(&ACCOUNT_PRESENT).append(account_hash);
+		self.push(stream.out())
+	}
+
+	fn account_data(&mut self, account_hash: &H256, data: &[u8]) -> Result<(), Error> {
+		let mut stream = RlpStream::new_list(3);
+		stream.append(&ACCOUNT_DATA).append(account_hash).append(&data);
+		self.push(stream.out())
+	}
+}
+
+// Collects the nodes and code of a state delta under their database keys.
+struct DeltaCollector(Vec<(H256, DBValue)>);
+
+impl DeltaSink for DeltaCollector {
+	fn state_node(&mut self, data: &[u8]) -> Result<(), Error> {
+		self.0.push((keccak(data), DBValue::from_slice(data)));
+		Ok(())
+	}
+
+	fn account_present(&mut self, _account_hash: &H256) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn account_data(&mut self, account_hash: &H256, data: &[u8]) -> Result<(), Error> {
+		self.0.push((combine_key(account_hash, &keccak(data)), DBValue::from_slice(data)));
+		Ok(())
+	}
+}
+
+// Receives the nodes and values found by `diff_node`.
+trait Visitor {
+	// A node which isn't shared with the base trie.
+	fn node(&mut self, data: &[u8]) -> Result<(), Error>;
+
+	// A value stored in a node which isn't shared with the base trie.
+	fn value(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error>;
+}
+
+// Visits the account trie, descending into the storage of changed accounts.
+struct AccountVisitor<'a, S: 'a> {
+	db: &'a HashDB<KeccakHasher, DBValue>,
+	base: TrieDB<'a>,
+	sink: &'a mut S,
+	empty_account: Bytes,
+}
+
+impl<'a, S: DeltaSink> Visitor for AccountVisitor<'a, S> {
+	fn node(&mut self, data: &[u8]) -> Result<(), Error> {
+		self.sink.state_node(data)
+	}
+
+	fn value(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+		let account_hash = H256::from_slice(key);
+		let account: BasicAccount = ::rlp::decode(value)?;
+		let base: Option<BasicAccount> = match self.base.get(key)? {
+			Some(base) => Some(::rlp::decode(&base)?),
+			None => None,
+		};
+
+		if value != &self.empty_account[..] {
+			self.sink.account_present(&account_hash)?;
+		}
+
+		let account_db = AccountDB::from_hash(self.db, account_hash);
+
+		if account.storage_root != KECCAK_NULL_RLP {
+			let base_root = match base {
+				Some(ref base) if base.storage_root != KECCAK_NULL_RLP => Some(::rlp::encode(&base.storage_root)),
+				_ => None,
+			};
+
+			let mut visitor = StorageVisitor {
+				account_hash: account_hash,
+				sink: &mut *self.sink,
+			};
+
+			diff_node(&account_db, &::rlp::encode(&account.storage_root), base_root.as_ref().map(|r| &r[..]), &mut Vec::new(), &mut visitor)?;
+		}
+
+		let code_changed = base.map_or(true, |base| base.code_hash != account.code_hash);
+		if account.code_hash != KECCAK_EMPTY && code_changed {
+			let code = account_db.get(&account.code_hash)
+				.ok_or_else(|| Error::MissingCode(vec![account.code_hash]))?;
+			self.sink.account_data(&account_hash, &code)?;
+		}
+
+		Ok(())
+	}
+}
+
+// Visits the storage trie of a single account.
+struct StorageVisitor<'a, S: 'a> {
+	account_hash: H256,
+	sink: &'a mut S,
+}
+
+impl<'a, S: DeltaSink> Visitor for StorageVisitor<'a, S> {
+	fn node(&mut self, data: &[u8]) -> Result<(), Error> {
+		self.sink.account_data(&self.account_hash, data)
+	}
+
+	fn value(&mut self, _key: &[u8], _value: &[u8]) -> Result<(), Error> {
+		Ok(())
+	}
+}
+
+// Get the encoded node behind a node reference, which is either a hash or an inline node.
+fn resolve(db: &HashDB<KeccakHasher, DBValue>, node: &[u8]) -> Result<Bytes, Error> {
+	match RlpCodec::try_decode_hash(node) {
+		Some(hash) => db.get(&hash)
+			.map(|data| data.to_vec())
+			.ok_or_else(|| TrieError::IncompleteDatabase(hash).into()),
+		None => Ok(node.to_vec()),
+	}
+}
+
+pub(super) fn push_nibbles(key: &mut Vec<u8>, partial: &NibbleSlice) {
+	for i in 0..partial.len() {
+		key.push(partial.at(i));
+	}
+}
+
+pub(super) fn nibbles_to_key(nibbles: &[u8]) -> Vec<u8> {
+	nibbles.chunks(2).map(|pair| (pair[0] << 4) | pair.get(1).cloned().unwrap_or(0)).collect()
+}
+
+// Walk the node referenced by `node`, skipping it if the base trie has the same reference
+// at this position and comparing its children with the base node's children otherwise.
+// `key` holds the nibbles leading to the node.
+fn diff_node<V: Visitor>(
+	db: &HashDB<KeccakHasher, DBValue>,
+	node: &[u8],
+	base: Option<&[u8]>,
+	key: &mut Vec<u8>,
+	visitor: &mut V,
+) -> Result<(), Error> {
+	if base == Some(node) || RlpCodec::is_empty_node(node) {
+		return Ok(());
+	}
+
+	let data = resolve(db, node)?;
+	if RlpCodec::try_decode_hash(node).is_some() {
+		visitor.node(&data)?;
+	}
+
+	let base_data = match base {
+		Some(base) if !RlpCodec::is_empty_node(base) => Some(resolve(db, base)?),
+		_ => None,
+	};
+	let base_node = match base_data {
+		Some(ref base_data) => Some(RlpCodec::decode(base_data)?),
+		None => None,
+	};
+
+	let depth = key.len();
+	match RlpCodec::decode(&data)? {
+		Node::Empty => {},
+		Node::Leaf(partial, value) => {
+			push_nibbles(key, &partial);
+			visitor.value(&nibbles_to_key(key), value)?;
+		},
+		Node::Extension(partial, child) => {
+			let base_child = match base_node {
+				Some(Node::Extension(ref base_partial, ref base_child)) if *base_partial == partial => Some(*base_child),
+				_ => None,
+			};
+
+			push_nibbles(key, &partial);
+			diff_node(db, child, base_child, key, visitor)?;
+		},
+		Node::Branch(children, value) => {
+			let base_children = match base_node {
+				Some(Node::Branch(base_children, _)) => Some(base_children),
+				_ => None,
+			};
+
+			if let Some(value) = value {
+				visitor.value(&nibbles_to_key(key), value)?;
+			}
+
+			for i in 0..16 {
+				key.push(i as u8);
+				diff_node(db, children[i], base_children.map(|c| c[i]), key, visitor)?;
+				key.truncate(depth);
+			}
+		},
+	}
+	key.truncate(depth);
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashSet;
+
+	use ethereum_types::H256;
+	use ethtrie::TrieDBMut;
+	use hash::keccak;
+	use hashdb::HashDB;
+	use keccak_hasher::KeccakHasher;
+	use kvdb::DBValue;
+	use memorydb::MemoryDB;
+	use trie::TrieMut;
+
+	use super::{diff_node, Visitor};
+	use snapshot::Error;
+
+	#[derive(Default)]
+	struct Collect {
+		nodes: HashSet<H256>,
+		values: Vec<(Vec<u8>, Vec<u8>)>,
+	}
+
+	impl Visitor for Collect {
+		fn node(&mut self, data: &[u8]) -> Result<(), Error> {
+			self.nodes.insert(keccak(data));
+			Ok(())
+		}
+
+		fn value(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+			self.values.push((key.to_vec(), value.to_vec()));
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn diff_finds_only_new_nodes() {
+		let mut db = MemoryDB::<KeccakHasher, DBValue>::new();
+		let mut base_root = H256::new();
+
+		{
+			let mut trie = TrieDBMut::new(&mut db, &mut base_root);
+			for i in 0..256u64 {
+				let key = keccak(&[i as u8]);
+				trie.insert(&key, &[0x55; 40]).unwrap();
+			}
+		}
+
+		let mut root = base_root;
+		{
+			let mut trie = TrieDBMut::from_existing(&mut db, &mut root).unwrap();
+			trie.insert(&keccak(&[3u8]), &[0x66; 40]).unwrap();
+			trie.insert(&keccak(b"new"), &[0x77; 40]).unwrap();
+		}
+
+		let mut collect = Collect::default();
+		diff_node(&db, &::rlp::encode(&root), Some(&::rlp::encode(&base_root)[..]), &mut Vec::new(), &mut collect).unwrap();
+
+		let mut values = collect.values.clone();
+		values.sort();
+		let mut expected = vec![
+			(keccak(&[3u8]).to_vec(), vec![0x66; 40]),
+			(keccak(b"new").to_vec(), vec![0x77; 40]),
+		];
+		expected.sort();
+		assert_eq!(values, expected);
+
+		// every node of the new trie is either new or part of the base trie.
+		let mut base = Collect::default();
+		diff_node(&db, &::rlp::encode(&base_root), None, &mut Vec::new(), &mut base).unwrap();
+		let mut full = Collect::default();
+		diff_node(&db, &::rlp::encode(&root), None, &mut Vec::new(), &mut full).unwrap();
+
+		assert!(!collect.nodes.is_empty());
+		assert!(collect.nodes.len() < full.nodes.len() / 4);
+		for node in &full.nodes {
+			assert!(collect.nodes.contains(node) || base.nodes.contains(node));
+		}
+		assert!(collect.nodes.iter().all(|node| db.contains(node)));
+
+		// nothing changed since itself.
+		let mut same = Collect::default();
+		diff_node(&db, &::rlp::encode(&root), Some(&::rlp::encode(&root)[..]), &mut Vec::new(), &mut same).unwrap();
+		assert!(same.nodes.is_empty() && same.values.is_empty());
+	}
+}
@@ -67,6 +67,14 @@ pub enum Error {
 	WrongChunkFormat(String),
 	/// Unlinked ancient block chain
 	UnlinkedAncientBlockChain,
+	/// Delta snapshot at the given block isn't based on the preceding snapshot.
+	UnlinkedDelta(u64),
+	/// Delta state chunk fed before the base state was restored.
+	DeltaBeforeBase,
+	/// Chunk contents don't match its hash.
+	ChunkHashMismatch(H256, H256),
+	/// Trie range doesn't match its proof.
+	InvalidRangeProof(&'static str),
 }
 
 impl fmt::Display for Error {
@@ -94,6 +102,10 @@ impl fmt::Display for Error {
 			Error::BadEpochProof(i) => write!(f, "Bad epoch proof for transition to epoch {}", i),
 			Error::WrongChunkFormat(ref msg) => write!(f, "Wrong chunk format: {}", msg),
 			Error::UnlinkedAncientBlockChain => write!(f, "Unlinked ancient blocks chain"),
+			Error::UnlinkedDelta(num) => write!(f, "Delta snapshot at block {} is not based on the preceding snapshot", num),
+			Error::DeltaBeforeBase => write!(f, "Delta chunk fed before the base state was restored"),
+			Error::ChunkHashMismatch(ref expected, ref found) => write!(f, "Chunk has wrong hash. Expected {:?}, got {:?}", expected, found),
+			Error::InvalidRangeProof(reason) => write!(f, "Invalid range proof: {}", reason),
 		}
 	}
 }
@@ -29,7 +29,7 @@ use bytes::Bytes;
 use ethereum_types::H256;
 use rlp::{RlpStream, Rlp};
 
-use super::ManifestData;
+use super::{ManifestData, DeltaManifest};
 
 const SNAPSHOT_VERSION: u64 = 2;
 
@@ -184,6 +184,21 @@ impl SnapshotWriter for LooseWriter {
 	}
 }
 
+impl LooseWriter {
+	/// Complete writing a delta snapshot. The delta manifest is written instead of
+	/// the regular one, so the directory can't be mistaken for a full snapshot.
+	pub fn finish_delta(self, manifest: DeltaManifest) -> io::Result<()> {
+		let rlp = manifest.into_rlp();
+		let mut path = self.dir.clone();
+		path.push("DELTA_MANIFEST");
+
+		let mut file = File::create(path)?;
+		file.write_all(&rlp[..])?;
+
+		Ok(())
+	}
+}
+
 /// Something which can read compressed snapshots.
 pub trait SnapshotReader {
 	/// Get the manifest data for this snapshot.
@@ -294,6 +309,7 @@ impl SnapshotReader for PackedReader {
 pub struct LooseReader {
 	dir: PathBuf,
 	manifest: ManifestData,
+	delta: Option<DeltaManifest>,
 }
 
 impl LooseReader {
@@ -313,8 +329,34 @@ impl LooseReader {
 		Ok(LooseReader {
 			dir: dir,
 			manifest: manifest,
+			delta: None,
+		})
+	}
+
+	/// Create a new `LooseReader` for the delta snapshot in the given directory.
+	/// The reader's manifest is the one of the delta itself.
+	pub fn delta(mut dir: PathBuf) -> Result<Self, ::error::Error> {
+		let mut manifest_buf = Vec::new();
+
+		dir.push("DELTA_MANIFEST");
+		let mut manifest_file = File::open(&dir)?;
+		manifest_file.read_to_end(&mut manifest_buf)?;
+
+		let delta = DeltaManifest::from_rlp(&manifest_buf[..])?;
+
+		dir.pop();
+
+		Ok(LooseReader {
+			dir: dir,
+			manifest: delta.manifest.clone(),
+			delta: Some(delta),
 		})
 	}
+
+	/// Get the delta manifest, if this is a delta snapshot.
+	pub fn delta_manifest(&self) -> Option<&DeltaManifest> {
+		self.delta.as_ref()
+	}
 }
 
 impl SnapshotReader for LooseReader {
@@ -336,7 +378,7 @@ mod tests {
 	use tempdir::TempDir;
 	use hash::keccak;
 
-	use snapshot::ManifestData;
+	use snapshot::{ManifestData, DeltaManifest};
 	use super::{SnapshotWriter, SnapshotReader, PackedWriter, PackedReader, LooseWriter, LooseReader, SNAPSHOT_VERSION};
 
 	const STATE_CHUNKS: &'static [&'static [u8]] = &[b"dog", b"cat", b"hello world", b"hi", b"notarealchunk"];
@@ -420,4 +462,44 @@ mod tests {
 			reader.chunk(hash.clone()).unwrap();
 		}
 	}
+
+	#[test]
+	fn loose_delta_write_and_read() {
+		let tempdir = TempDir::new("").unwrap();
+		let mut writer = LooseWriter::new(tempdir.path().into()).unwrap();
+
+		let mut state_hashes = Vec::new();
+		for chunk in STATE_CHUNKS {
+			let hash = keccak(&chunk);
+			state_hashes.push(hash.clone());
+			writer.write_state_chunk(hash, chunk).unwrap();
+		}
+
+		let delta = DeltaManifest {
+			base_number: 12345678987654000,
+			base_hash: keccak(b"notarealbaseblock"),
+			base_state_root: keccak(b"notarealbaseroot"),
+			manifest: ManifestData {
+				version: SNAPSHOT_VERSION,
+				state_hashes: state_hashes,
+				block_hashes: Vec::new(),
+				state_root: keccak(b"notarealroot"),
+				block_number: 12345678987654321,
+				block_hash: keccak(b"notarealblock"),
+			},
+		};
+
+		writer.finish_delta(delta.clone()).unwrap();
+
+		// a delta isn't a full snapshot.
+		assert!(LooseReader::new(tempdir.path().into()).is_err());
+
+		let reader = LooseReader::delta(tempdir.path().into()).unwrap();
+		assert_eq!(reader.manifest(), &delta.manifest);
+		assert_eq!(reader.delta_manifest(), Some(&delta));
+
+		for hash in &delta.manifest.state_hashes {
+			reader.chunk(hash.clone()).unwrap();
+		}
+	}
 }
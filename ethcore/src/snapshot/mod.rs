@@ -19,7 +19,7 @@
 //! Documentation of the format can be found at
 //! https://wiki.parity.io/Warp-Sync-Snapshot-Format
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::cmp;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
@@ -39,13 +39,14 @@ use bytes::Bytes;
 use parking_lot::Mutex;
 use journaldb::{self, Algorithm, JournalDB};
 use kvdb::{KeyValueDB, DBValue};
-use trie::{Trie, TrieMut};
-use ethtrie::{TrieDB, TrieDBMut};
+use trie::{NodeCodec, Trie, TrieMut};
+use trie::node::Node;
+use ethtrie::{RlpCodec, TrieDB, TrieDBMut};
 use rlp::{RlpStream, Rlp};
 use bloom_journal::Bloom;
 use num_cpus;
 
-use self::io::SnapshotWriter;
+use self::io::{SnapshotWriter, LooseWriter};
 
 use super::state_db::StateDB;
 use super::state::Account as StateAccount;
@@ -57,19 +58,26 @@ pub use self::error::Error;
 
 pub use self::consensus::*;
 pub use self::service::{SnapshotClient, Service, DatabaseRestore};
-pub use self::traits::SnapshotService;
+pub use self::traits::{SnapshotService, StateSyncData};
 pub use self::watcher::Watcher;
-pub use types::snapshot_manifest::ManifestData;
+pub use self::ranges::{TrieRange, prove_range, verify_range};
+pub use self::verify::{ChunkStats, SnapshotInfo, inspect_snapshot, verify_snapshot};
+pub use types::snapshot_manifest::{ManifestData, DeltaManifest};
 pub use types::restoration_status::RestorationStatus;
 pub use types::basic_account::BasicAccount;
 
+pub(crate) use self::delta::state_delta;
+
 pub mod io;
 pub mod service;
 
 mod account;
 mod block;
 mod consensus;
+mod delta;
 mod error;
+mod ranges;
+mod verify;
 mod watcher;
 
 #[cfg(test)]
@@ -101,6 +109,12 @@ pub struct SnapshotConfiguration {
 	pub no_periodic: bool,
 	/// Number of threads for creating snapshots
 	pub processing_threads: usize,
+	/// Number of blocks between periodic snapshots
+	pub period: u64,
+	/// Number of full snapshots to keep on disk, including the current one
+	pub retention: usize,
+	/// Number of delta snapshots to take between two full snapshots
+	pub deltas: usize,
 }
 
 impl Default for SnapshotConfiguration {
@@ -108,6 +122,9 @@ impl Default for SnapshotConfiguration {
 		SnapshotConfiguration {
 			no_periodic: false,
 			processing_threads: ::std::cmp::max(1, num_cpus::get() / 2),
+			period: 5000,
+			retention: 1,
+			deltas: 0,
 		}
 	}
 }
@@ -222,6 +239,65 @@ pub fn take_snapshot<W: SnapshotWriter + Send>(
 	Ok(())
 }
 
+/// Take a delta snapshot at the given block against the snapshot described by `base`,
+/// writing into the given writer. The state of the base snapshot must still be available
+/// in the database.
+pub fn take_delta_snapshot(
+	engine: &EthEngine,
+	chain: &BlockChain,
+	block_at: H256,
+	base: &ManifestData,
+	state_db: &HashDB<KeccakHasher, DBValue>,
+	writer: LooseWriter,
+	p: &Progress,
+) -> Result<(), Error> {
+	let start_header = chain.block_header_data(&block_at)
+		.ok_or(Error::InvalidStartingBlock(BlockId::Hash(block_at)))?;
+	let state_root = start_header.state_root();
+	let number = start_header.number();
+
+	if number <= base.block_number {
+		return Err(Error::InvalidStartingBlock(BlockId::Hash(block_at)));
+	}
+
+	info!("Taking delta snapshot starting at block {} against block {}", number, base.block_number);
+
+	let writer = Mutex::new(writer);
+	let chunker = engine.snapshot_components().ok_or(Error::SnapshotsUnsupported)?;
+	let snapshot_version = chunker.current_version();
+	let (state_hashes, block_hashes) = scope(|scope| -> Result<(Vec<H256>, Vec<H256>), Error> {
+		let writer = &writer;
+		let block_guard = scope.spawn(move || chunk_secondary(chunker, chain, block_at, writer, p));
+
+		let state_hashes = delta::chunk_state_delta(state_db, &base.state_root, &state_root, writer, p)?;
+		let block_hashes = block_guard.join().expect("Sub-thread never panics; qed")?;
+
+		Ok((state_hashes, block_hashes))
+	})?;
+
+	info!(target: "snapshot", "produced {} delta state chunks and {} block chunks.", state_hashes.len(), block_hashes.len());
+
+	let manifest = DeltaManifest {
+		base_number: base.block_number,
+		base_hash: base.block_hash,
+		base_state_root: base.state_root,
+		manifest: ManifestData {
+			version: snapshot_version,
+			state_hashes: state_hashes,
+			block_hashes: block_hashes,
+			state_root: state_root,
+			block_number: number,
+			block_hash: block_at,
+		},
+	};
+
+	writer.into_inner().finish_delta(manifest)?;
+
+	p.done.store(true, Ordering::SeqCst);
+
+	Ok(())
+}
+
 /// Create and write out all secondary chunks to disk, returning a vector of all
 /// the hashes of secondary chunks created.
 ///
@@ -260,6 +336,31 @@ pub fn chunk_secondary<'a>(mut chunker: Box<SnapshotComponents>, chain: &'a Bloc
 	Ok(chunk_hashes)
 }
 
+// Check that every node of the account trie at `root`, of the storage tries and every
+// contract code it refers to is in the database.
+fn check_state_complete(db: &HashDB<KeccakHasher, DBValue>, root: &H256) -> Result<(), Error> {
+	let account_trie = TrieDB::new(db, root)?;
+	for item in account_trie.iter()? {
+		let (account_key, account_data) = item?;
+		let account_hash = H256::from_slice(&account_key);
+		let account: BasicAccount = ::rlp::decode(&*account_data)?;
+		let account_db = AccountDB::from_hash(db, account_hash);
+
+		if account.storage_root != KECCAK_NULL_RLP {
+			let storage_trie = TrieDB::new(&account_db, &account.storage_root)?;
+			for item in storage_trie.iter()? {
+				item?;
+			}
+		}
+
+		if account.code_hash != KECCAK_EMPTY && !account_db.contains(&account.code_hash) {
+			return Err(Error::MissingCode(vec![account.code_hash]));
+		}
+	}
+
+	Ok(())
+}
+
 /// State trie chunker.
 struct StateChunker<'a> {
 	hashes: Vec<H256>,
@@ -389,6 +490,34 @@ pub struct StateRebuilder {
 	missing_code: HashMap<H256, Vec<H256>>, // maps code hashes to lists of accounts missing that code.
 	bloom: Bloom,
 	known_storage_roots: HashMap<H256, H256>, // maps account hashes to last known storage root. Only filled for last account per chunk.
+	healing: Option<Healing>,
+}
+
+// A trie node missing from the state being healed.
+enum HealItem {
+	// Node of the account trie at the given nibble path.
+	State(Vec<u8>),
+	// Node of the storage trie of the given account.
+	Storage(H256),
+}
+
+// Progress of healing the state towards some state root.
+struct Healing {
+	root: H256,
+	// hashes of missing nodes, in the order they were found.
+	queue: VecDeque<H256>,
+	// missing nodes mapped to where they belong. A node may be found in several places.
+	pending: HashMap<H256, Vec<HealItem>>,
+}
+
+impl Healing {
+	fn push(&mut self, hash: H256, item: HealItem) {
+		let items = self.pending.entry(hash).or_insert_with(Vec::new);
+		if items.is_empty() {
+			self.queue.push_back(hash);
+		}
+		items.push(item);
+	}
 }
 
 impl StateRebuilder {
@@ -401,6 +530,7 @@ impl StateRebuilder {
 			missing_code: HashMap::new(),
 			bloom: StateDB::load_bloom(&*db),
 			known_storage_roots: HashMap::new(),
+			healing: None,
 		}
 	}
 
@@ -465,6 +595,243 @@ impl StateRebuilder {
 		Ok(())
 	}
 
+	/// Feed an uncompressed delta state chunk into the rebuilder. Delta chunks may only
+	/// be fed once the state of the base snapshot has been fed completely.
+	pub fn feed_delta(&mut self, chunk: &[u8], flag: &AtomicBool) -> Result<(), ::error::Error> {
+		let backing = self.db.backing().clone();
+
+		delta::rebuild_delta(self.db.as_hashdb_mut(), Rlp::new(chunk), &mut self.bloom, flag)?;
+
+		let bloom_journal = self.bloom.drain_journal();
+		let mut batch = backing.transaction();
+		StateDB::commit_bloom(&mut batch, bloom_journal)?;
+		self.db.inject(&mut batch)?;
+		backing.write_buffered(batch);
+		Ok(())
+	}
+
+	/// Move the rebuilder to the state root of the last delta, once all the delta
+	/// chunks leading to it have been fed. The whole state under `root` is walked, so a
+	/// delta chain missing any node or code is rejected.
+	pub fn finish_deltas(&mut self, root: H256) -> Result<(), ::error::Error> {
+		check_state_complete(self.db.as_hashdb(), &root)?;
+
+		self.state_root = root;
+		Ok(())
+	}
+
+	/// Feed a verified range of accounts into the rebuilder. Accounts with storage are left
+	/// out: they are written along with their storage by `feed_storage_range`.
+	pub fn feed_account_range(&mut self, accounts: &[(H256, BasicAccount)], flag: &AtomicBool) -> Result<(), ::error::Error> {
+		let fat_rlps: Vec<_> = accounts.iter()
+			.filter(|&&(_, ref account)| account.storage_root == KECCAK_NULL_RLP)
+			.map(|&(ref hash, ref account)| account::to_fat_rlp_with_storage(hash, account, &[]))
+			.collect();
+
+		let mut stream = RlpStream::new_list(fat_rlps.len());
+		for fat_rlp in &fat_rlps {
+			stream.append_raw(fat_rlp, 1);
+		}
+		self.feed(&stream.out(), flag)
+	}
+
+	/// Feed a verified range of an account's storage into the rebuilder, along with the account.
+	/// Consecutive ranges of the same account's storage continue its storage trie.
+	pub fn feed_storage_range(&mut self, account_hash: &H256, account: &BasicAccount, storage: &[(H256, Bytes)], flag: &AtomicBool) -> Result<(), ::error::Error> {
+		let mut stream = RlpStream::new_list(1);
+		stream.append_raw(&account::to_fat_rlp_with_storage(account_hash, account, storage), 1);
+		self.feed(&stream.out(), flag)
+	}
+
+	/// Start healing the state towards the trie at `root`. Its nodes which aren't in the
+	/// database yet are listed by `missing_nodes` and have to be fed through `feed_nodes`.
+	pub fn begin_healing(&mut self, root: H256) {
+		let mut healing = Healing {
+			root: root,
+			queue: VecDeque::new(),
+			pending: HashMap::new(),
+		};
+		if !self.db.as_hashdb().contains(&root) {
+			healing.push(root, HealItem::State(Vec::new()));
+		}
+		self.healing = Some(healing);
+	}
+
+	/// Hashes of up to `count` missing contract codes and trie nodes, codes first.
+	pub fn missing_nodes(&self, count: usize) -> Vec<H256> {
+		let nodes = self.healing.iter()
+			.flat_map(|healing| healing.queue.iter().filter(move |hash| healing.pending.contains_key(*hash)));
+		self.missing_code.keys().chain(nodes).take(count).cloned().collect()
+	}
+
+	/// Feed contract codes and trie nodes listed by `missing_nodes`. Anything that wasn't
+	/// missing is ignored.
+	pub fn feed_nodes(&mut self, nodes: &[Bytes], flag: &AtomicBool) -> Result<(), ::error::Error> {
+		for node in nodes {
+			if !flag.load(Ordering::SeqCst) { return Err(Error::RestorationAborted.into()) }
+
+			let hash = keccak(node);
+			if let Some(accounts) = self.missing_code.remove(&hash) {
+				for addr_hash in &accounts {
+					AccountDBMut::from_hash(self.db.as_hashdb_mut(), *addr_hash).emplace(hash, DBValue::from_slice(node));
+				}
+				if let Some(&first_with) = accounts.first() {
+					self.known_code.insert(hash, first_with);
+				}
+			}
+
+			let items = match self.healing.as_mut().and_then(|healing| healing.pending.remove(&hash)) {
+				Some(items) => items,
+				None => continue,
+			};
+			for item in items {
+				match item {
+					HealItem::State(path) => {
+						self.db.as_hashdb_mut().emplace(hash, DBValue::from_slice(node));
+						self.heal_state_node(node, path)?;
+					},
+					HealItem::Storage(addr_hash) => {
+						AccountDBMut::from_hash(self.db.as_hashdb_mut(), addr_hash).emplace(hash, DBValue::from_slice(node));
+						self.heal_storage_node(&addr_hash, node)?;
+					},
+				}
+			}
+		}
+
+		if let Some(ref mut healing) = self.healing {
+			while healing.queue.front().map_or(false, |hash| !healing.pending.contains_key(hash)) {
+				healing.queue.pop_front();
+			}
+		}
+
+		let backing = self.db.backing().clone();
+		let bloom_journal = self.bloom.drain_journal();
+		let mut batch = backing.transaction();
+		StateDB::commit_bloom(&mut batch, bloom_journal)?;
+		self.db.inject(&mut batch)?;
+		backing.write_buffered(batch);
+		Ok(())
+	}
+
+	/// Whether healing has begun and nothing is missing anymore.
+	pub fn is_healed(&self) -> bool {
+		self.missing_code.is_empty() && self.healing.as_ref().map_or(false, |healing| healing.pending.is_empty())
+	}
+
+	/// Move the rebuilder to the state root being healed, once nothing is missing anymore.
+	pub fn finish_healing(&mut self) -> Result<(), ::error::Error> {
+		let root = match self.healing {
+			Some(ref healing) if self.is_healed() => healing.root,
+			_ => return Err(Error::Trie(::ethtrie::TrieError::IncompleteDatabase(self.state_root)).into()),
+		};
+		self.finish_deltas(root)
+	}
+
+	// walk a node of the account trie being healed, found at the given nibble path.
+	fn heal_state_node(&mut self, node: &[u8], mut path: Vec<u8>) -> Result<(), ::error::Error> {
+		match RlpCodec::decode(node)? {
+			Node::Empty => {},
+			Node::Leaf(partial, value) => {
+				delta::push_nibbles(&mut path, &partial);
+				if path.len() != 64 {
+					return Err(Error::WrongChunkFormat(format!("Account trie leaf at depth {}", path.len())).into());
+				}
+				self.heal_account(H256::from_slice(&delta::nibbles_to_key(&path)), value)?;
+			},
+			Node::Extension(partial, child) => {
+				delta::push_nibbles(&mut path, &partial);
+				self.heal_state_child(child, path)?;
+			},
+			Node::Branch(children, _) => {
+				for (i, child) in children.iter().enumerate() {
+					let mut child_path = path.clone();
+					child_path.push(i as u8);
+					self.heal_state_child(child, child_path)?;
+				}
+			},
+		}
+		Ok(())
+	}
+
+	fn heal_state_child(&mut self, reference: &[u8], path: Vec<u8>) -> Result<(), ::error::Error> {
+		match RlpCodec::try_decode_hash(reference) {
+			Some(hash) => if !self.db.as_hashdb().contains(&hash) {
+				self.healing_mut().push(hash, HealItem::State(path));
+			},
+			None => if !RlpCodec::is_empty_node(reference) {
+				self.heal_state_node(reference, path)?;
+			},
+		}
+		Ok(())
+	}
+
+	// note the storage and code of an account found while healing which aren't in the database.
+	fn heal_account(&mut self, addr_hash: H256, value: &[u8]) -> Result<(), ::error::Error> {
+		let account: BasicAccount = ::rlp::decode(value)?;
+		if value != &StateAccount::new_basic(U256::zero(), U256::zero()).rlp()[..] {
+			self.bloom.set(&*addr_hash);
+		}
+
+		let (has_storage, has_code) = {
+			let account_db = AccountDB::from_hash(self.db.as_hashdb(), addr_hash);
+			(
+				account.storage_root == KECCAK_NULL_RLP || account_db.contains(&account.storage_root),
+				account.code_hash == KECCAK_EMPTY || account_db.contains(&account.code_hash),
+			)
+		};
+
+		if !has_storage {
+			self.healing_mut().push(account.storage_root, HealItem::Storage(addr_hash));
+		}
+		if !has_code {
+			match self.known_code.get(&account.code_hash).cloned() {
+				Some(first_with) => {
+					let code = AccountDB::from_hash(self.db.as_hashdb(), first_with)
+						.get(&account.code_hash)
+						.ok_or_else(|| Error::MissingCode(vec![first_with]))?;
+					AccountDBMut::from_hash(self.db.as_hashdb_mut(), addr_hash).emplace(account.code_hash, code);
+				},
+				None => {
+					let accounts = self.missing_code.entry(account.code_hash).or_insert_with(Vec::new);
+					if !accounts.contains(&addr_hash) {
+						accounts.push(addr_hash);
+					}
+				},
+			}
+		}
+		Ok(())
+	}
+
+	// walk a node of an account's storage trie being healed.
+	fn heal_storage_node(&mut self, addr_hash: &H256, node: &[u8]) -> Result<(), ::error::Error> {
+		match RlpCodec::decode(node)? {
+			Node::Empty | Node::Leaf(..) => {},
+			Node::Extension(_, child) => self.heal_storage_child(addr_hash, child)?,
+			Node::Branch(children, _) => {
+				for child in children.iter() {
+					self.heal_storage_child(addr_hash, child)?;
+				}
+			},
+		}
+		Ok(())
+	}
+
+	fn heal_storage_child(&mut self, addr_hash: &H256, reference: &[u8]) -> Result<(), ::error::Error> {
+		match RlpCodec::try_decode_hash(reference) {
+			Some(hash) => if !AccountDB::from_hash(self.db.as_hashdb(), *addr_hash).contains(&hash) {
+				self.healing_mut().push(hash, HealItem::Storage(*addr_hash));
+			},
+			None => if !RlpCodec::is_empty_node(reference) {
+				self.heal_storage_node(addr_hash, reference)?;
+			},
+		}
+		Ok(())
+	}
+
+	fn healing_mut(&mut self) -> &mut Healing {
+		self.healing.as_mut().expect("missing nodes are only found while healing; qed")
+	}
+
 	/// Finalize the restoration. Check for accounts missing code and make a dummy
 	/// journal entry.
 	/// Once all chunks have been fed, there should be nothing missing.
@@ -0,0 +1,446 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Merkle proofs for ranges of trie entries.
+//!
+//! A range proof shows that a list of consecutive entries of a trie, starting at some origin
+//! key, is complete: no key between the origin and the last entry was left out. The proof
+//! consists of the nodes on the paths to the origin and to the last entry. The verifier
+//! rebuilds the part of the trie between those two paths from the entries alone, keeps the
+//! references of the proof nodes outside of the range, and checks the resulting root.
+//!
+//! Only tries with 32-byte keys, like the secure state and storage tries, are supported.
+
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use ethereum_types::H256;
+use ethtrie::{RlpCodec, TrieDB};
+use hash::{keccak, KECCAK_NULL_RLP};
+use hashdb::HashDB;
+use keccak_hasher::KeccakHasher;
+use kvdb::DBValue;
+use rlp::RlpStream;
+use trie::{NibbleSlice, NodeCodec, Recorder, Trie};
+use trie::node::Node;
+
+use super::Error;
+
+/// Number of nibbles in a key.
+const KEY_NIBBLES: usize = 64;
+
+/// Consecutive entries of a trie along with the proof of their completeness.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrieRange {
+	/// Keys and values, in ascending key order.
+	pub entries: Vec<(H256, Bytes)>,
+	/// Trie nodes on the paths to the origin and to the last entry.
+	pub proof: Vec<Bytes>,
+}
+
+/// Collect the entries of the trie at `root` from `origin` on, along with their proof.
+/// Stops after the first key at or past `limit`, or once `max_bytes` were collected, but
+/// returns at least one entry if there is any at or past `origin`.
+pub fn prove_range(db: &HashDB<KeccakHasher, DBValue>, root: &H256, origin: &H256, limit: &H256, max_bytes: usize) -> Result<TrieRange, Error> {
+	let trie = TrieDB::new(db, root)?;
+
+	let mut entries = Vec::new();
+	let mut size = 0;
+	let mut iter = trie.iter()?;
+	iter.seek(origin)?;
+	for item in iter {
+		let (key, value) = item?;
+		if key.len() != 32 {
+			return Err(Error::InvalidRangeProof("key of wrong length"));
+		}
+
+		let key = H256::from_slice(&key);
+		size += key.len() + value.len();
+		entries.push((key, value.to_vec()));
+		if key >= *limit || size >= max_bytes {
+			break;
+		}
+	}
+
+	// An empty range is proven up to the end of the key space.
+	let last = entries.last().map_or_else(|| H256::from([0xff; 32]), |&(ref key, _)| *key);
+	let mut recorder = Recorder::new();
+	for key in &[*origin, last] {
+		trie.get_with(key, (&mut recorder, |_: &[u8]| ()))?;
+	}
+
+	let mut proof: Vec<Bytes> = Vec::new();
+	for record in recorder.drain() {
+		if !proof.contains(&record.data) {
+			proof.push(record.data);
+		}
+	}
+
+	Ok(TrieRange { entries, proof })
+}
+
+/// Verify that `entries` are all entries of the trie at `root` from `origin` up to the last
+/// of them. Returns whether the trie holds any entries past the last one.
+pub fn verify_range(root: &H256, origin: &H256, entries: &[(H256, Bytes)], proof: &[Bytes]) -> Result<bool, Error> {
+	if entries.first().map_or(false, |&(ref key, _)| key < origin) {
+		return Err(Error::InvalidRangeProof("entry before origin"));
+	}
+	if entries.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+		return Err(Error::InvalidRangeProof("entries out of order"));
+	}
+	if *root == KECCAK_NULL_RLP {
+		return match entries.is_empty() {
+			true => Ok(false),
+			false => Err(Error::InvalidRangeProof("entries in an empty trie")),
+		};
+	}
+
+	let last = entries.last().map_or_else(|| H256::from([0xff; 32]), |&(ref key, _)| *key);
+	let mut verifier = RangeVerifier {
+		nodes: proof.iter().map(|node| (keccak(node), &node[..])).collect(),
+		left: nibbles(origin),
+		right: nibbles(&last),
+		more: false,
+	};
+
+	let entries: Vec<_> = entries.iter().map(|&(ref key, ref value)| (nibbles(key), &value[..])).collect();
+	match verifier.verify(&::rlp::encode(root), &mut Vec::new(), &entries)? {
+		Subtrie::Node(ref node) if keccak(node) == *root => Ok(verifier.more),
+		_ => Err(Error::InvalidRangeProof("root mismatch")),
+	}
+}
+
+// Part of the trie as rebuilt by the verifier.
+enum Subtrie {
+	// Subtrie outside of the range, kept as referenced by the proof.
+	Unchanged(Bytes),
+	// Encoded node of a subtrie within or across the range bounds.
+	Node(Bytes),
+}
+
+impl Subtrie {
+	fn into_reference(self) -> Bytes {
+		match self {
+			Subtrie::Unchanged(reference) => reference,
+			Subtrie::Node(node) => node_reference(&node),
+		}
+	}
+}
+
+struct RangeVerifier<'a> {
+	nodes: HashMap<H256, &'a [u8]>,
+	left: Vec<u8>,
+	right: Vec<u8>,
+	more: bool,
+}
+
+impl<'a> RangeVerifier<'a> {
+	// Rebuild the subtrie behind `reference` at the nibble path `path`, given the entries
+	// below that path.
+	fn verify(&mut self, reference: &[u8], path: &mut Vec<u8>, entries: &[(Vec<u8>, &[u8])]) -> Result<Subtrie, Error> {
+		let depth = path.len();
+		if depth > KEY_NIBBLES {
+			return Err(Error::InvalidRangeProof("path too long"));
+		}
+
+		if path[..] < self.left[..depth] {
+			return Ok(Subtrie::Unchanged(reference.to_vec()));
+		}
+		if path[..] > self.right[..depth] {
+			if !RlpCodec::is_empty_node(reference) {
+				self.more = true;
+			}
+			return Ok(Subtrie::Unchanged(reference.to_vec()));
+		}
+
+		let after_left = path[..] != self.left[..depth] || self.left[depth..].iter().all(|&n| n == 0);
+		let before_right = path[..] != self.right[..depth] || self.right[depth..].iter().all(|&n| n == 0xf);
+		if after_left && before_right {
+			return Ok(Subtrie::Node(build(entries, depth)));
+		}
+
+		// The path leads to one of the bounds, so the node must be part of the proof.
+		let data = match RlpCodec::try_decode_hash(reference) {
+			Some(hash) => self.nodes.get(&hash).cloned().ok_or(Error::InvalidRangeProof("missing proof node"))?,
+			None => reference,
+		};
+
+		let node = match RlpCodec::decode(data)? {
+			Node::Empty => {
+				if !entries.is_empty() {
+					return Err(Error::InvalidRangeProof("entries missing from the trie"));
+				}
+				RlpCodec::empty_node()
+			},
+			Node::Leaf(partial, value) => {
+				let mut key = path.clone();
+				key.extend(slice_nibbles(&partial));
+				if key.len() != KEY_NIBBLES {
+					return Err(Error::InvalidRangeProof("key of wrong length"));
+				}
+
+				let in_range = key >= self.left && key <= self.right;
+				if key > self.right {
+					self.more = true;
+				}
+				let matches = match entries.len() {
+					0 => !in_range,
+					1 => in_range && entries[0].0 == key && entries[0].1 == value,
+					_ => false,
+				};
+				if !matches {
+					return Err(Error::InvalidRangeProof("entries don't match the trie"));
+				}
+				data.to_vec()
+			},
+			Node::Extension(partial, child) => {
+				let partial = slice_nibbles(&partial);
+				path.extend_from_slice(&partial);
+				if entries.iter().any(|&(ref key, _)| !key.starts_with(&path[..])) {
+					return Err(Error::InvalidRangeProof("entries missing from the trie"));
+				}
+				let child = self.verify(child, path, entries)?;
+				path.truncate(depth);
+
+				let mut stream = RlpStream::new_list(2);
+				stream.append(&encode_path(&partial, false));
+				stream.append_raw(&child.into_reference(), 1);
+				stream.out()
+			},
+			Node::Branch(children, value) => {
+				if value.is_some() {
+					return Err(Error::InvalidRangeProof("value in a branch"));
+				}
+
+				let mut stream = RlpStream::new_list(17);
+				let mut start = 0;
+				for (i, child) in children.iter().enumerate() {
+					let end = start + entries[start..].iter().take_while(|&&(ref key, _)| key[depth] as usize == i).count();
+					path.push(i as u8);
+					let child = self.verify(child, path, &entries[start..end])?;
+					path.truncate(depth);
+					stream.append_raw(&child.into_reference(), 1);
+					start = end;
+				}
+				stream.append_empty_data();
+				stream.out()
+			},
+		};
+
+		Ok(Subtrie::Node(node))
+	}
+}
+
+// Build the subtrie holding the given sorted entries, which share their first `depth` nibbles.
+fn build(entries: &[(Vec<u8>, &[u8])], depth: usize) -> Bytes {
+	if entries.is_empty() {
+		return RlpCodec::empty_node();
+	}
+
+	if entries.len() == 1 {
+		let (ref key, value) = entries[0];
+		let mut stream = RlpStream::new_list(2);
+		stream.append(&encode_path(&key[depth..], true));
+		stream.append(&value);
+		return stream.out();
+	}
+
+	let first = &entries[0].0;
+	let last = &entries[entries.len() - 1].0;
+	let shared = first[depth..].iter().zip(&last[depth..]).take_while(|&(a, b)| a == b).count();
+	if shared > 0 {
+		let mut stream = RlpStream::new_list(2);
+		stream.append(&encode_path(&first[depth..depth + shared], false));
+		stream.append_raw(&node_reference(&build(entries, depth + shared)), 1);
+		return stream.out();
+	}
+
+	let mut stream = RlpStream::new_list(17);
+	let mut start = 0;
+	for i in 0..16u8 {
+		let end = start + entries[start..].iter().take_while(|&&(ref key, _)| key[depth] == i).count();
+		stream.append_raw(&node_reference(&build(&entries[start..end], depth + 1)), 1);
+		start = end;
+	}
+	stream.append_empty_data();
+	stream.out()
+}
+
+// How a node is referenced by its parent: inline if its encoding is short, by hash otherwise.
+fn node_reference(node: &[u8]) -> Bytes {
+	match node.len() < 32 {
+		true => node.to_vec(),
+		false => ::rlp::encode(&keccak(node)).into_vec(),
+	}
+}
+
+// Hex-prefix encoding of a nibble path.
+fn encode_path(nibbles: &[u8], leaf: bool) -> Bytes {
+	let flag = if leaf { 0x20 } else { 0 };
+	let mut encoded = Vec::with_capacity(nibbles.len() / 2 + 1);
+	let rest = if nibbles.len() % 2 == 1 {
+		encoded.push(flag | 0x10 | nibbles[0]);
+		&nibbles[1..]
+	} else {
+		encoded.push(flag);
+		nibbles
+	};
+	encoded.extend(rest.chunks(2).map(|pair| (pair[0] << 4) | pair[1]));
+	encoded
+}
+
+fn slice_nibbles(slice: &NibbleSlice) -> Vec<u8> {
+	(0..slice.len()).map(|i| slice.at(i)).collect()
+}
+
+fn nibbles(key: &H256) -> Vec<u8> {
+	let mut nibbles = Vec::with_capacity(KEY_NIBBLES);
+	for byte in key.iter() {
+		nibbles.push(byte >> 4);
+		nibbles.push(byte & 0x0f);
+	}
+	nibbles
+}
+
+#[cfg(test)]
+mod tests {
+	use bytes::Bytes;
+	use ethereum_types::{H256, U256};
+	use ethtrie::TrieDBMut;
+	use hash::keccak;
+	use keccak_hasher::KeccakHasher;
+	use kvdb::DBValue;
+	use memorydb::MemoryDB;
+	use trie::TrieMut;
+
+	use super::{prove_range, verify_range};
+
+	fn trie(count: usize) -> (MemoryDB<KeccakHasher, DBValue>, H256, Vec<(H256, Bytes)>) {
+		let mut db = MemoryDB::<KeccakHasher, DBValue>::new();
+		let mut root = H256::new();
+		let mut entries: Vec<(H256, Bytes)> = (0..count)
+			.map(|i| (keccak(&[i as u8, (i >> 8) as u8]), vec![i as u8; 1 + i % 40]))
+			.collect();
+		{
+			let mut trie = TrieDBMut::new(&mut db, &mut root);
+			for &(ref key, ref value) in &entries {
+				trie.insert(key, value).unwrap();
+			}
+		}
+		entries.sort();
+		(db, root, entries)
+	}
+
+	fn next_key(key: &H256) -> H256 {
+		H256::from(U256::from(key) + U256::one())
+	}
+
+	fn max_key() -> H256 {
+		H256::from([0xff; 32])
+	}
+
+	#[test]
+	fn proves_whole_trie() {
+		let (db, root, entries) = trie(300);
+		let range = prove_range(&db, &root, &H256::zero(), &max_key(), usize::max_value()).unwrap();
+		assert_eq!(range.entries, entries);
+		assert_eq!(verify_range(&root, &H256::zero(), &range.entries, &range.proof).unwrap(), false);
+	}
+
+	#[test]
+	fn proves_consecutive_ranges() {
+		let (db, root, entries) = trie(500);
+		let mut origin = H256::zero();
+		let mut collected = Vec::new();
+		loop {
+			let range = prove_range(&db, &root, &origin, &max_key(), 1000).unwrap();
+			assert!(!range.entries.is_empty());
+			let more = verify_range(&root, &origin, &range.entries, &range.proof).unwrap();
+			let last = range.entries.last().unwrap().0;
+			collected.extend(range.entries);
+			if !more {
+				break;
+			}
+			origin = next_key(&last);
+		}
+		assert_eq!(collected, entries);
+	}
+
+	#[test]
+	fn stops_at_limit() {
+		let (db, root, entries) = trie(100);
+		let origin = entries[10].0;
+		let range = prove_range(&db, &root, &origin, &entries[20].0, usize::max_value()).unwrap();
+		assert_eq!(range.entries, &entries[10..21]);
+		assert_eq!(verify_range(&root, &origin, &range.entries, &range.proof).unwrap(), true);
+
+		// Ranges starting between two keys include the next key.
+		let origin = next_key(&entries[50].0);
+		let range = prove_range(&db, &root, &origin, &origin, usize::max_value()).unwrap();
+		assert_eq!(range.entries, &entries[51..52]);
+		assert_eq!(verify_range(&root, &origin, &range.entries, &range.proof).unwrap(), true);
+	}
+
+	#[test]
+	fn proves_empty_ranges() {
+		let (db, root, entries) = trie(100);
+		let origin = next_key(&entries[99].0);
+		let range = prove_range(&db, &root, &origin, &max_key(), usize::max_value()).unwrap();
+		assert!(range.entries.is_empty());
+		assert_eq!(verify_range(&root, &origin, &range.entries, &range.proof).unwrap(), false);
+
+		// Leaving out the remaining entries of a trie is detected.
+		let origin = entries[90].0;
+		let range = prove_range(&db, &root, &origin, &max_key(), usize::max_value()).unwrap();
+		assert!(verify_range(&root, &origin, &[], &range.proof).is_err());
+
+		let (db, root, _) = trie(0);
+		let range = prove_range(&db, &root, &H256::zero(), &max_key(), usize::max_value()).unwrap();
+		assert_eq!(verify_range(&root, &H256::zero(), &range.entries, &range.proof).unwrap(), false);
+	}
+
+	#[test]
+	fn rejects_tampered_ranges() {
+		let (db, root, entries) = trie(200);
+		let origin = entries[20].0;
+		let range = prove_range(&db, &root, &origin, &entries[80].0, usize::max_value()).unwrap();
+		assert!(verify_range(&root, &origin, &range.entries, &range.proof).is_ok());
+
+		let mut missing = range.entries.clone();
+		missing.remove(30);
+		assert!(verify_range(&root, &origin, &missing, &range.proof).is_err());
+
+		let mut changed = range.entries.clone();
+		changed[5].1.push(0);
+		assert!(verify_range(&root, &origin, &changed, &range.proof).is_err());
+
+		let mut extra = range.entries.clone();
+		extra.insert(1, (next_key(&extra[0].0), vec![1]));
+		assert!(verify_range(&root, &origin, &extra, &range.proof).is_err());
+
+		let mut reordered = range.entries.clone();
+		reordered.swap(3, 4);
+		assert!(verify_range(&root, &origin, &reordered, &range.proof).is_err());
+
+		// Skipping the first entry of the range.
+		assert!(verify_range(&root, &origin, &range.entries[1..], &range.proof).is_err());
+
+		let mut proof = range.proof.clone();
+		proof.pop();
+		assert!(verify_range(&root, &origin, &range.entries, &proof).is_err());
+		assert!(verify_range(&keccak(&root), &origin, &range.entries, &range.proof).is_err());
+	}
+}
@@ -24,9 +24,11 @@ use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::cmp;
 
-use super::{ManifestData, StateRebuilder, Rebuilder, RestorationStatus, SnapshotService, MAX_CHUNK_SIZE};
+use super::{ManifestData, DeltaManifest, StateRebuilder, Rebuilder, RestorationStatus, SnapshotService, StateSyncData, MAX_CHUNK_SIZE};
 use super::io::{SnapshotReader, LooseReader, SnapshotWriter, LooseWriter};
 
+use basic_account::BasicAccount;
+use block::Block;
 use blockchain::{BlockChain, BlockChainDB, BlockChainDBHandler};
 use client::{BlockInfo, BlockChainClient, Client, ChainInfo, ClientIoMessage};
 use engines::EthEngine;
@@ -37,11 +39,13 @@ use ids::BlockId;
 
 use io::IoChannel;
 
-use ethereum_types::H256;
+use ethereum_types::{H256, U256};
 use parking_lot::{Mutex, RwLock, RwLockReadGuard};
 use bytes::Bytes;
 use journaldb::Algorithm;
 use kvdb::DBTransaction;
+use receipt::Receipt;
+use rlp::{self, Rlp};
 use snappy;
 
 /// Helper for removing directories in case of error.
@@ -75,29 +79,47 @@ struct Restoration {
 	manifest: ManifestData,
 	state_chunks_left: HashSet<H256>,
 	block_chunks_left: HashSet<H256>,
+	delta_chunks: HashSet<H256>,
+	base_chunks_left: usize,
 	state: StateRebuilder,
 	secondary: Box<Rebuilder>,
 	writer: Option<LooseWriter>,
 	snappy_buffer: Bytes,
+	base_state_root: H256,
 	final_state_root: H256,
 	guard: Guard,
 	db: Arc<BlockChainDB>,
+	pivot: Option<Bytes>,
+	ranges_done: bool,
 }
 
 struct RestorationParams<'a> {
 	manifest: ManifestData, // manifest to base restoration on.
+	deltas: Vec<DeltaManifest>, // delta snapshots to apply on top of the base, oldest first.
 	pruning: Algorithm, // pruning algorithm for the database.
 	db: Arc<BlockChainDB>, // database
 	writer: Option<LooseWriter>, // writer for recovered snapshot.
 	genesis: &'a [u8], // genesis block of the chain.
 	guard: Guard, // guard for the restoration directory.
 	engine: &'a EthEngine,
+	pivot: Option<Bytes>, // block chunk of the block a state downloaded without a snapshot belongs to.
 }
 
 impl Restoration {
 	// make a new restoration using the given parameters.
 	fn new(params: RestorationParams) -> Result<Self, Error> {
-		let manifest = params.manifest;
+		let base = params.manifest;
+		super::delta::verify_delta_chain(&base, &params.deltas)?;
+
+		// restoring deltas yields the block chunks and state of the last one, with the
+		// state chunks of all of them.
+		let mut manifest = params.deltas.last().map_or_else(|| base.clone(), |delta| delta.manifest.clone());
+		let delta_chunks: HashSet<H256> = params.deltas.iter()
+			.flat_map(|delta| delta.manifest.state_hashes.iter().cloned())
+			.collect();
+		manifest.state_hashes = base.state_hashes.iter().cloned()
+			.chain(params.deltas.iter().flat_map(|delta| delta.manifest.state_hashes.iter().cloned()))
+			.collect();
 
 		let state_chunks = manifest.state_hashes.iter().cloned().collect();
 		let block_chunks = manifest.block_hashes.iter().cloned().collect();
@@ -116,19 +138,71 @@ impl Restoration {
 			manifest: manifest,
 			state_chunks_left: state_chunks,
 			block_chunks_left: block_chunks,
+			delta_chunks: delta_chunks,
+			base_chunks_left: base.state_hashes.len(),
 			state: StateRebuilder::new(raw_db.key_value().clone(), params.pruning),
 			secondary: secondary,
 			writer: params.writer,
 			snappy_buffer: Vec::new(),
+			base_state_root: base.state_root,
 			final_state_root: root,
 			guard: params.guard,
 			db: raw_db,
+			pivot: params.pivot,
+			ranges_done: false,
 		})
 	}
 
+	// move a state restoration to a later block, keeping the state fed so far.
+	fn move_pivot(&mut self, manifest: ManifestData, pivot: Bytes, genesis: &[u8], engine: &EthEngine) -> Result<(), Error> {
+		let chain = BlockChain::new(Default::default(), genesis, self.db.clone());
+		let components = engine.snapshot_components()
+			.ok_or_else(|| ::snapshot::Error::SnapshotsUnsupported)?;
+
+		self.secondary = components.rebuilder(chain, self.db.clone(), &manifest)?;
+		self.base_state_root = manifest.state_root;
+		self.final_state_root = manifest.state_root;
+		if self.ranges_done {
+			self.state.begin_healing(manifest.state_root);
+		}
+		self.manifest = manifest;
+		self.pivot = Some(pivot);
+		Ok(())
+	}
+
+	// feeds state downloaded without a snapshot.
+	fn feed_state_data(&mut self, data: &StateSyncData, flag: &AtomicBool) -> Result<(), Error> {
+		if self.pivot.is_none() { return Ok(()) }
+
+		match *data {
+			StateSyncData::Accounts(ref accounts) => {
+				let accounts = accounts.iter()
+					.map(|&(ref hash, ref account)| Ok((*hash, rlp::decode(account)?)))
+					.collect::<Result<Vec<(H256, BasicAccount)>, rlp::DecoderError>>()?;
+				self.state.feed_account_range(&accounts, flag)?;
+			},
+			StateSyncData::Storage(ref hash, ref account, ref storage) => {
+				let account: BasicAccount = rlp::decode(account)?;
+				self.state.feed_storage_range(hash, &account, storage, flag)?;
+			},
+			StateSyncData::Nodes(ref nodes) => self.state.feed_nodes(nodes, flag)?,
+			StateSyncData::RangesDone => if !self.ranges_done {
+				self.ranges_done = true;
+				self.state.begin_healing(self.final_state_root);
+			},
+		}
+
+		Ok(())
+	}
+
 	// feeds a state chunk, aborts early if `flag` becomes false.
 	fn feed_state(&mut self, hash: H256, chunk: &[u8], flag: &AtomicBool) -> Result<(), Error> {
 		if self.state_chunks_left.contains(&hash) {
+			let is_delta = self.delta_chunks.contains(&hash);
+			if is_delta && self.base_chunks_left != 0 {
+				return Err(::snapshot::Error::DeltaBeforeBase.into());
+			}
+
 			let expected_len = snappy::decompressed_len(chunk)?;
 			if expected_len > MAX_CHUNK_SIZE {
 				trace!(target: "snapshot", "Discarding large chunk: {} vs {}", expected_len, MAX_CHUNK_SIZE);
@@ -136,13 +210,19 @@ impl Restoration {
 			}
 			let len = snappy::decompress_into(chunk, &mut self.snappy_buffer)?;
 
-			self.state.feed(&self.snappy_buffer[..len], flag)?;
+			match is_delta {
+				true => self.state.feed_delta(&self.snappy_buffer[..len], flag)?,
+				false => self.state.feed(&self.snappy_buffer[..len], flag)?,
+			}
 
 			if let Some(ref mut writer) = self.writer.as_mut() {
 				writer.write_state_chunk(hash, chunk)?;
 			}
 
 			self.state_chunks_left.remove(&hash);
+			if !is_delta {
+				self.base_chunks_left -= 1;
+			}
 		}
 
 		Ok(())
@@ -175,6 +255,23 @@ impl Restoration {
 
 		if !self.is_done() { return Ok(()) }
 
+		// verify the base state root before moving on to the state of the last delta.
+		if !self.delta_chunks.is_empty() {
+			let root = self.state.state_root();
+			if root != self.base_state_root {
+				warn!("Restored base state has wrong state root: expected {:?}, got {:?}", self.base_state_root, root);
+				return Err(TrieError::InvalidStateRoot(root).into());
+			}
+
+			self.state.finish_deltas(self.final_state_root)?;
+		}
+
+		// move to the healed state and restore the block it belongs to.
+		if let Some(pivot) = self.pivot.take() {
+			self.state.finish_healing()?;
+			self.secondary.feed(&pivot, engine, &AtomicBool::new(true))?;
+		}
+
 		// verify final state root.
 		let root = self.state.state_root();
 		if root != self.final_state_root {
@@ -198,7 +295,8 @@ impl Restoration {
 
 	// is everything done?
 	fn is_done(&self) -> bool {
-		self.block_chunks_left.is_empty() && self.state_chunks_left.is_empty()
+		self.block_chunks_left.is_empty() && self.state_chunks_left.is_empty() &&
+			(self.pivot.is_none() || (self.ranges_done && self.state.is_healed()))
 	}
 }
 
@@ -309,6 +407,13 @@ impl Service {
 		dir
 	}
 
+	// get the dir the replaced snapshot is moved to before it's destroyed.
+	fn replaced_snapshot_dir(&self) -> PathBuf {
+		let mut dir = self.snapshot_root.clone();
+		dir.push("replaced");
+		dir
+	}
+
 	// get the restoration directory.
 	fn restoration_dir(&self) -> PathBuf {
 		let mut dir = self.snapshot_root.clone();
@@ -330,6 +435,20 @@ impl Service {
 		dir
 	}
 
+	// get the archived snapshots dir.
+	fn archive_dir(&self) -> PathBuf {
+		let mut dir = self.snapshot_root.clone();
+		dir.push("archive");
+		dir
+	}
+
+	// get the dir of the archived snapshot at the given block.
+	fn archived_snapshot_dir(&self, num: u64) -> PathBuf {
+		let mut dir = self.archive_dir();
+		dir.push(num.to_string());
+		dir
+	}
+
 	// previous snapshot chunks path.
 	fn prev_chunks_dir(&self) -> PathBuf {
 		let mut dir = self.snapshot_root.clone();
@@ -461,12 +580,39 @@ impl Service {
 	/// calling this while a restoration is in progress or vice versa
 	/// will lead to a race condition where the first one to finish will
 	/// have their produced snapshot overwritten.
+	///
+	/// Depending on the client's snapshot configuration this is either a full
+	/// snapshot, which replaces the current one, or a delta snapshot against the
+	/// last snapshot taken, which is archived next to it.
 	pub fn take_snapshot(&self, client: &Client, num: u64) -> Result<(), Error> {
 		if self.taking_snapshot.compare_and_swap(false, true, Ordering::SeqCst) {
 			info!("Skipping snapshot at #{} as another one is currently in-progress.", num);
 			return Ok(());
 		}
 
+		let config = client.snapshot_config();
+		let res = match self.delta_base(config.deltas) {
+			Some(base) => match self.take_delta(client, num, &base) {
+				Ok(()) => Ok(()),
+				Err(Error(SnapshotErrorKind::Snapshot(SnapshotError::OldBlockPrunedDB), _)) => {
+					warn!("Delta snapshot at #{} needs the state of #{}, which is already pruned. Taking a full snapshot instead; \
+						run with --pruning archive or a --pruning-history covering --snapshot-period to take delta snapshots.",
+						num, base.block_number);
+					self.take_full(client, num, config.retention)
+				},
+				Err(e) => {
+					info!("Failed to take delta snapshot at #{}: {}. Taking a full snapshot instead.", num, e);
+					self.take_full(client, num, config.retention)
+				}
+			},
+			None => self.take_full(client, num, config.retention),
+		};
+
+		self.taking_snapshot.store(false, Ordering::SeqCst);
+		res.and_then(|_| self.prune_archive(config.retention))
+	}
+
+	fn take_full(&self, client: &Client, num: u64, retention: usize) -> Result<(), Error> {
 		info!("Taking snapshot at #{}", num);
 		self.progress.reset();
 
@@ -480,7 +626,6 @@ impl Service {
 		let guard = Guard::new(temp_dir.clone());
 		let res = client.take_snapshot(writer, BlockId::Number(num), &self.progress);
 
-		self.taking_snapshot.store(false, Ordering::SeqCst);
 		if let Err(e) = res {
 			if client.chain_info().best_block_number >= num + client.pruning_history() {
 				// "Cancelled" is mincing words a bit -- what really happened
@@ -498,24 +643,193 @@ impl Service {
 
 		let mut reader = self.reader.write();
 
-		// destroy the old snapshot reader.
-		*reader = None;
+		// move the old snapshot aside to be archived or destroyed. The old reader keeps
+		// serving it until the new snapshot has taken its place.
+		let (old_dir, archive) = match reader.as_ref().map(|old| old.manifest().block_number) {
+			Some(old_number) if retention > 1 && old_number != num => {
+				fs::create_dir_all(self.archive_dir())?;
+				(self.archived_snapshot_dir(old_number), true)
+			}
+			_ => (self.replaced_snapshot_dir(), false),
+		};
 
-		if snapshot_dir.exists() {
-			fs::remove_dir_all(&snapshot_dir)?;
+		let moved = snapshot_dir.exists();
+		if moved {
+			let _ = fs::remove_dir_all(&old_dir);
+			fs::rename(&snapshot_dir, &old_dir)?;
 		}
 
-		fs::rename(temp_dir, &snapshot_dir)?;
+		let new_reader = fs::rename(&temp_dir, &snapshot_dir)
+			.map_err(Into::into)
+			.and_then(|_| LooseReader::new(snapshot_dir.clone()));
+
+		match new_reader {
+			Ok(new_reader) => *reader = Some(new_reader),
+			Err(e) => {
+				// put the old snapshot back so it can still be served.
+				if moved {
+					let _ = fs::remove_dir_all(&snapshot_dir);
+					fs::rename(&old_dir, &snapshot_dir)?;
+				}
+				return Err(e);
+			}
+		}
 
-		*reader = Some(LooseReader::new(snapshot_dir)?);
+		if moved && !archive {
+			fs::remove_dir_all(&old_dir)?;
+		}
 
 		guard.disarm();
 		Ok(())
 	}
 
+	fn take_delta(&self, client: &Client, num: u64, base: &ManifestData) -> Result<(), Error> {
+		info!("Taking delta snapshot at #{} against #{}", num, base.block_number);
+		self.progress.reset();
+
+		let temp_dir = self.temp_snapshot_dir();
+		let _ = fs::remove_dir_all(&temp_dir);
+
+		let writer = LooseWriter::new(temp_dir.clone())?;
+
+		let guard = Guard::new(temp_dir.clone());
+		client.take_delta_snapshot(writer, BlockId::Number(num), base, &self.progress)?;
+
+		info!("Finished taking delta snapshot at #{}", num);
+
+		fs::create_dir_all(self.archive_dir())?;
+		let delta_dir = self.archived_snapshot_dir(num);
+		if delta_dir.exists() {
+			fs::remove_dir_all(&delta_dir)?;
+		}
+		fs::rename(temp_dir, delta_dir)?;
+
+		guard.disarm();
+		Ok(())
+	}
+
+	// get the manifest a delta snapshot should be based on, if the next snapshot
+	// should be a delta at all.
+	fn delta_base(&self, deltas: usize) -> Option<ManifestData> {
+		if deltas == 0 { return None }
+
+		let current = self.reader.read().as_ref().map(|r| r.manifest().clone())?;
+		let taken: Vec<ManifestData> = self.archived_snapshots().into_iter()
+			.filter_map(|(_, reader)| reader.delta_manifest().cloned())
+			.filter(|delta| delta.manifest.block_number > current.block_number)
+			.map(|delta| delta.manifest)
+			.collect();
+
+		match taken.len() < deltas {
+			true => Some(taken.into_iter().last().unwrap_or(current)),
+			false => None,
+		}
+	}
+
+	// remove archived snapshots beyond the retention limit, along with any
+	// delta snapshots which no longer have a full snapshot to be applied to.
+	fn prune_archive(&self, retention: usize) -> Result<(), Error> {
+		let archived = self.archived_snapshots();
+		let full: Vec<u64> = archived.iter()
+			.filter(|&&(_, ref reader)| reader.delta_manifest().is_none())
+			.map(|&(_, ref reader)| reader.manifest().block_number)
+			.collect();
+
+		// the current snapshot counts towards the retention limit.
+		let oldest_kept = full.iter().rev().take(retention.saturating_sub(1)).last().cloned()
+			.or_else(|| self.reader.read().as_ref().map(|r| r.manifest().block_number))
+			.unwrap_or(0);
+
+		for (path, reader) in archived {
+			if reader.manifest().block_number < oldest_kept {
+				trace!(target: "snapshot", "Removing archived snapshot at {}", path.display());
+				fs::remove_dir_all(&path)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Get the archived full and delta snapshots, ordered by block number.
+	pub fn archived_snapshots(&self) -> Vec<(PathBuf, LooseReader)> {
+		let entries = match fs::read_dir(self.archive_dir()) {
+			Ok(entries) => entries,
+			Err(_) => return Vec::new(),
+		};
+
+		let mut snapshots: Vec<_> = entries
+			.filter_map(|entry| entry.ok().map(|e| e.path()))
+			.filter_map(|path| {
+				LooseReader::new(path.clone())
+					.or_else(|_| LooseReader::delta(path.clone()))
+					.ok()
+					.map(|reader| (path, reader))
+			})
+			.collect();
+
+		snapshots.sort_by_key(|&(_, ref reader)| reader.manifest().block_number);
+		snapshots
+	}
+
 	/// Initialize the restoration synchronously.
 	/// The recover flag indicates whether to recover the restored snapshot.
 	pub fn init_restore(&self, manifest: ManifestData, recover: bool) -> Result<(), Error> {
+		self.init_restoration(manifest, Vec::new(), None, recover)
+	}
+
+	/// Initialize the restoration of a full snapshot followed by a chain of delta
+	/// snapshots, oldest first, synchronously. All the state chunks of the full snapshot
+	/// must be fed before those of the deltas, and only the block chunks of the last delta
+	/// are restored. Restored delta chains are not recovered into the local snapshot.
+	pub fn init_restore_with_deltas(&self, manifest: ManifestData, deltas: Vec<DeltaManifest>) -> Result<(), Error> {
+		self.init_restoration(manifest, deltas, None, false)
+	}
+
+	/// Initialize the restoration of the state of the given block without a snapshot,
+	/// synchronously. The state is then fed with `feed_state_data`. If a state restoration
+	/// is ongoing already, it is moved to the given block, keeping the state fed so far.
+	pub fn init_state_restore(&self, block: &[u8], receipts: &[u8], total_difficulty: U256) -> Result<(), Error> {
+		let header = rlp::decode::<Block>(block)?.header;
+		Rlp::new(receipts).as_list::<Receipt>()?;
+		if header.number() == 0 {
+			return Err(::snapshot::Error::InvalidStartingBlock(BlockId::Number(0)).into());
+		}
+
+		self.engine.verify_block_basic(&header)?;
+		self.engine.verify_block_unordered(&header)?;
+
+		let components = self.engine.snapshot_components()
+			.ok_or_else(|| ::snapshot::Error::SnapshotsUnsupported)?;
+		let (parent_total_difficulty, overflow) = total_difficulty.overflowing_sub(*header.difficulty());
+		if overflow {
+			return Err(::snapshot::Error::InvalidStartingBlock(BlockId::Hash(header.hash())).into());
+		}
+		let pivot = components.pivot_chunk(block, receipts, parent_total_difficulty)
+			.ok_or_else(|| ::snapshot::Error::SnapshotsUnsupported)?;
+
+		let manifest = ManifestData {
+			version: components.current_version(),
+			state_hashes: Vec::new(),
+			block_hashes: Vec::new(),
+			state_root: *header.state_root(),
+			block_number: header.number(),
+			block_hash: header.hash(),
+		};
+
+		{
+			let mut res = self.restoration.lock();
+			if let Some(ref mut rest) = *res {
+				if rest.pivot.is_some() {
+					trace!(target: "snapshot", "Moving state restoration to #{}", manifest.block_number);
+					return rest.move_pivot(manifest, pivot, &self.genesis_block, &*self.engine);
+				}
+			}
+		}
+
+		self.init_restoration(manifest, Vec::new(), Some(pivot), false)
+	}
+
+	fn init_restoration(&self, manifest: ManifestData, deltas: Vec<DeltaManifest>, pivot: Option<Bytes>, recover: bool) -> Result<(), Error> {
 		let mut res = self.restoration.lock();
 
 		let rest_dir = self.restoration_dir();
@@ -564,19 +878,23 @@ impl Service {
 		};
 
 		let params = RestorationParams {
-			manifest: manifest.clone(),
+			manifest: manifest,
+			deltas: deltas,
 			pruning: self.pruning,
 			db: self.restoration_db_handler.open(&rest_db)?,
 			writer: writer,
 			genesis: &self.genesis_block,
 			guard: Guard::new(rest_db),
 			engine: &*self.engine,
+			pivot: pivot,
 		};
 
+		let restoration = Restoration::new(params)?;
+		let manifest = restoration.manifest.clone();
 		let state_chunks = manifest.state_hashes.len();
 		let block_chunks = manifest.block_hashes.len();
 
-		*res = Some(Restoration::new(params)?);
+		*res = Some(restoration);
 
 		self.restoring_snapshot.store(true, Ordering::SeqCst);
 
@@ -756,6 +1074,46 @@ impl Service {
 		Ok(())
 	}
 
+	/// Feed state downloaded without a snapshot to be processed synchronously.
+	pub fn feed_state_data(&self, data: &StateSyncData) {
+		let mut restoration = self.restoration.lock();
+		match self.feed_state_data_with_restoration(&mut restoration, data) {
+			Ok(()) |
+			Err(Error(SnapshotErrorKind::Snapshot(SnapshotError::RestorationAborted), _)) => (),
+			Err(e) => {
+				warn!("Encountered error during state restoration: {}", e);
+				*restoration = None;
+				*self.status.lock() = RestorationStatus::Failed;
+				let _ = fs::remove_dir_all(self.restoration_dir());
+			}
+		}
+	}
+
+	fn feed_state_data_with_restoration(&self, restoration: &mut Option<Restoration>, data: &StateSyncData) -> Result<(), Error> {
+		match self.status() {
+			RestorationStatus::Ongoing { .. } => {},
+			_ => {
+				trace!(target: "snapshot", "Tried to restore state data while not restoring");
+				return Ok(());
+			},
+		}
+
+		let (is_done, db) = match *restoration {
+			Some(ref mut rest) => {
+				rest.feed_state_data(data, &self.restoring_snapshot)?;
+				(rest.is_done(), rest.db.clone())
+			},
+			None => return Ok(()),
+		};
+
+		if is_done {
+			db.key_value().flush()?;
+			drop(db);
+			return self.finalize_restoration(restoration);
+		}
+		Ok(())
+	}
+
 	/// Feed a state chunk to be processed synchronously.
 	pub fn feed_state_chunk(&self, hash: H256, chunk: &[u8]) {
 		self.feed_chunk(hash, chunk, true);
@@ -846,6 +1204,23 @@ impl SnapshotService for Service {
 		}
 	}
 
+	fn begin_state_restore(&self, block: Bytes, receipts: Bytes, total_difficulty: U256) {
+		if let Err(e) = self.io_channel.lock().send(ClientIoMessage::BeginStateRestoration(block, receipts, total_difficulty)) {
+			trace!("Error sending snapshot service message: {:?}", e);
+		}
+	}
+
+	fn restore_state_data(&self, data: StateSyncData) {
+		if let Err(e) = self.io_channel.lock().send(ClientIoMessage::FeedStateData(data)) {
+			trace!("Error sending snapshot service message: {:?}", e);
+		}
+	}
+
+	fn missing_state_data(&self, count: usize) -> Vec<H256> {
+		self.restoration.lock().as_ref()
+			.map_or_else(Vec::new, |rest| rest.state.missing_nodes(count))
+	}
+
 	fn shutdown(&self) {
 		self.abort_restore();
 	}
@@ -863,7 +1238,7 @@ mod tests {
 	use io::{IoService};
 	use spec::Spec;
 	use journaldb::Algorithm;
-	use snapshot::{ManifestData, RestorationStatus, SnapshotService};
+	use snapshot::{ManifestData, RestorationStatus, SnapshotService, StateSyncData};
 	use super::*;
 	use tempdir::TempDir;
 	use test_helpers::{generate_dummy_client_with_spec_and_data, restoration_db_handler};
@@ -907,6 +1282,9 @@ mod tests {
 		service.abort_restore();
 		service.restore_state_chunk(Default::default(), vec![]);
 		service.restore_block_chunk(Default::default(), vec![]);
+		service.begin_state_restore(vec![], vec![], Default::default());
+		service.restore_state_data(StateSyncData::RangesDone);
+		assert!(service.missing_state_data(16).is_empty());
 	}
 
 	#[test]
@@ -932,12 +1310,14 @@ mod tests {
 				block_number: 100000,
 				block_hash: H256::default(),
 			},
+			deltas: Vec::new(),
 			pruning: Algorithm::Archive,
 			db: restoration_db_handler(db_config).open(&tempdir.path().to_owned()).unwrap(),
 			writer: None,
 			genesis: &gb,
 			guard: Guard::benign(),
 			engine: &*spec.engine.clone(),
+			pivot: None,
 		};
 
 		let mut restoration = Restoration::new(params).unwrap();
@@ -95,6 +95,95 @@ fn snap_and_restore() {
 	compare_dbs(&old_db, new_db.as_hashdb());
 }
 
+#[test]
+fn snap_delta_and_restore() {
+	use snapshot::delta::chunk_state_delta;
+	use snapshot::io::{LooseReader, LooseWriter};
+
+	let mut producer = StateProducer::new();
+	let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+	let mut old_db = MemoryDB::new();
+	let db_cfg = DatabaseConfig::with_columns(::db::NUM_COLUMNS);
+
+	for _ in 0..100 {
+		producer.tick(&mut rng, &mut old_db);
+	}
+
+	let base_root = producer.state_root();
+	let tempdir = TempDir::new("").unwrap();
+	let base_writer = Mutex::new(PackedWriter::new(&tempdir.path().join("SNAP")).unwrap());
+	let base_hashes = chunk_state(&old_db, &base_root, &base_writer, &Progress::default(), None).unwrap();
+
+	// keep the base state around, as an archive node would.
+	let base_db = old_db.clone();
+	for _ in 0..50 {
+		producer.tick(&mut rng, &mut old_db);
+	}
+	old_db.consolidate(base_db);
+
+	let state_root = producer.state_root();
+	let delta_writer = Mutex::new(LooseWriter::new(tempdir.path().join("delta")).unwrap());
+	let delta_hashes = chunk_state_delta(&old_db, &base_root, &state_root, &delta_writer, &Progress::default()).unwrap();
+	base_writer.into_inner().finish(::snapshot::ManifestData {
+		version: 2,
+		state_hashes: base_hashes.clone(),
+		block_hashes: Vec::new(),
+		state_root: base_root,
+		block_number: 1000,
+		block_hash: H256::default(),
+	}).unwrap();
+	delta_writer.into_inner().finish_delta(::snapshot::DeltaManifest {
+		base_number: 1000,
+		base_hash: H256::default(),
+		base_state_root: base_root,
+		manifest: ::snapshot::ManifestData {
+			version: 2,
+			state_hashes: delta_hashes,
+			block_hashes: Vec::new(),
+			state_root: state_root,
+			block_number: 1500,
+			block_hash: H256::default(),
+		},
+	}).unwrap();
+
+	let db_path = tempdir.path().join("db");
+	let db = {
+		let new_db = Arc::new(Database::open(&db_cfg, &db_path.to_string_lossy()).unwrap());
+		let mut rebuilder = StateRebuilder::new(new_db.clone(), Algorithm::OverlayRecent);
+		let base = PackedReader::new(&tempdir.path().join("SNAP")).unwrap().unwrap();
+		let delta = LooseReader::delta(tempdir.path().join("delta")).unwrap();
+
+		let flag = AtomicBool::new(true);
+
+		for chunk_hash in &base.manifest().state_hashes {
+			let chunk = ::snappy::decompress(&base.chunk(*chunk_hash).unwrap()).unwrap();
+			rebuilder.feed(&chunk, &flag).unwrap();
+		}
+		assert_eq!(rebuilder.state_root(), base_root);
+
+		// the new root alone doesn't make a complete state.
+		let root_node = ::hashdb::HashDB::get(&old_db, &state_root).unwrap();
+		let mut stream = ::rlp::RlpStream::new_list(1);
+		stream.begin_list(2).append(&0u8).append(&&root_node[..]);
+		rebuilder.feed_delta(&stream.out(), &flag).unwrap();
+		assert!(rebuilder.finish_deltas(state_root).is_err());
+
+		for chunk_hash in &delta.manifest().state_hashes {
+			let chunk = ::snappy::decompress(&delta.chunk(*chunk_hash).unwrap()).unwrap();
+			rebuilder.feed_delta(&chunk, &flag).unwrap();
+		}
+
+		rebuilder.finish_deltas(state_root).unwrap();
+		assert_eq!(rebuilder.state_root(), state_root);
+		rebuilder.finalize(1500, H256::default()).unwrap();
+
+		new_db
+	};
+
+	let new_db = journaldb::new(db, Algorithm::OverlayRecent, ::db::COL_STATE);
+	compare_dbs(&old_db, new_db.as_hashdb());
+}
+
 #[test]
 fn get_code_from_prev_chunk() {
 	use std::collections::HashSet;
@@ -199,3 +288,81 @@ fn checks_flag() {
 		}
 	}
 }
+
+#[test]
+fn sync_ranges_and_heal() {
+	use std::collections::HashMap;
+	use account_db::AccountDB;
+	use ethereum_types::U256;
+	use hashdb::HashDB;
+	use snapshot::{prove_range, verify_range};
+
+	let mut producer = StateProducer::new();
+	let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+	let mut old_db = MemoryDB::new();
+	let db_cfg = DatabaseConfig::with_columns(::db::NUM_COLUMNS);
+	let max_key = H256::from([0xff; 32]);
+
+	for _ in 0..100 {
+		producer.tick(&mut rng, &mut old_db);
+	}
+
+	let tempdir = TempDir::new("").unwrap();
+	let db_path = tempdir.path().join("db");
+	let new_db = Arc::new(Database::open(&db_cfg, &db_path.to_string_lossy()).unwrap());
+	let mut rebuilder = StateRebuilder::new(new_db.clone(), Algorithm::OverlayRecent);
+	let flag = AtomicBool::new(true);
+
+	// download the accounts and storage of the current state in ranges.
+	let pivot_root = producer.state_root();
+	let mut origin = H256::zero();
+	loop {
+		let range = prove_range(&old_db, &pivot_root, &origin, &max_key, 4096).unwrap();
+		let more = verify_range(&pivot_root, &origin, &range.entries, &range.proof).unwrap();
+		let accounts: Vec<(H256, BasicAccount)> = range.entries.iter()
+			.map(|&(ref hash, ref rlp)| (*hash, ::rlp::decode(rlp).unwrap()))
+			.collect();
+
+		for &(ref hash, ref account) in &accounts {
+			if account.storage_root != KECCAK_NULL_RLP {
+				let storage = prove_range(&AccountDB::from_hash(&old_db, *hash), &account.storage_root, &H256::zero(), &max_key, usize::max_value()).unwrap();
+				assert!(!verify_range(&account.storage_root, &H256::zero(), &storage.entries, &storage.proof).unwrap());
+				rebuilder.feed_storage_range(hash, account, &storage.entries, &flag).unwrap();
+			}
+		}
+		rebuilder.feed_account_range(&accounts, &flag).unwrap();
+
+		if !more {
+			break;
+		}
+		origin = H256::from(U256::from(accounts.last().unwrap().0) + U256::one());
+	}
+	assert_eq!(rebuilder.state_root(), pivot_root);
+
+	// the chain moves on and the state is healed towards the new root.
+	for _ in 0..20 {
+		producer.tick(&mut rng, &mut old_db);
+	}
+	let state_root = producer.state_root();
+	let nodes: HashMap<H256, Vec<u8>> = old_db.keys().keys()
+		.filter_map(|key| old_db.get(key))
+		.map(|value| (keccak(&value), value.to_vec()))
+		.collect();
+
+	rebuilder.begin_healing(state_root);
+	let mut healed = 0;
+	while !rebuilder.is_healed() {
+		let missing: Vec<_> = rebuilder.missing_nodes(64).iter().map(|hash| nodes[hash].clone()).collect();
+		assert!(!missing.is_empty());
+		healed += missing.len();
+		rebuilder.feed_nodes(&missing, &flag).unwrap();
+	}
+	assert!(healed > 0);
+
+	rebuilder.finish_healing().unwrap();
+	assert_eq!(rebuilder.state_root(), state_root);
+	rebuilder.finalize(1000, H256::default()).unwrap();
+
+	let new_db = journaldb::new(new_db, Algorithm::OverlayRecent, ::db::COL_STATE);
+	compare_dbs(&old_db, new_db.as_hashdb());
+}
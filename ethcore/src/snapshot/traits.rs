@@ -15,9 +15,22 @@
 // along with Parity.  If not, see <http://www.gnu.org/licenses/>.
 
 use super::{ManifestData, RestorationStatus};
-use ethereum_types::H256;
+use ethereum_types::{H256, U256};
 use bytes::Bytes;
 
+/// State downloaded without a snapshot, to be fed into a state restoration.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateSyncData {
+	/// Verified range of the account trie: account hashes and RLP-encoded accounts.
+	Accounts(Vec<(H256, Bytes)>),
+	/// Verified range of an account's storage, along with the account hash and RLP-encoded account.
+	Storage(H256, Bytes, Vec<(H256, Bytes)>),
+	/// Contract codes and trie nodes listed by `SnapshotService::missing_state_data`.
+	Nodes(Vec<Bytes>),
+	/// All account and storage ranges were fed. The state is healed from now on.
+	RangesDone,
+}
+
 /// The interface for a snapshot network service.
 /// This handles:
 ///    - restoration of snapshots to temporary databases.
@@ -55,6 +68,19 @@ pub trait SnapshotService : Sync + Send {
 	/// no-op if currently restoring.
 	fn restore_block_chunk(&self, hash: H256, chunk: Bytes);
 
+	/// Begin restoring the state of the given block without a snapshot, asynchronously.
+	/// `total_difficulty` is the block's total difficulty and is trusted.
+	/// If a state restoration is in progress already, it is moved to the given block,
+	/// keeping the state fed so far.
+	fn begin_state_restore(&self, block: Bytes, receipts: Bytes, total_difficulty: U256);
+
+	/// Feed downloaded state to the service to be processed asynchronously.
+	/// no-op if not currently restoring a state begun by `begin_state_restore`.
+	fn restore_state_data(&self, data: StateSyncData);
+
+	/// Hashes of up to `count` contract codes and trie nodes the state restoration is missing.
+	fn missing_state_data(&self, count: usize) -> Vec<H256>;
+
 	/// Shutdown the Snapshot Service by aborting any ongoing restore
 	fn shutdown(&self);
 }
@@ -0,0 +1,275 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Offline verification and inspection of snapshots.
+//!
+//! Verification rebuilds the whole snapshot into a scratch database, the same way
+//! a restoration would, without touching the client database.
+
+use std::cmp;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use blockchain::{BlockChain, BlockChainDB};
+use engines::EthEngine;
+use ethereum_types::H256;
+use hash::keccak;
+use journaldb::Algorithm;
+use rlp::Rlp;
+use snappy;
+
+use super::io::SnapshotReader;
+use super::{Error, ManifestData, StateRebuilder, MAX_CHUNK_SIZE};
+
+/// Size statistics for a set of chunks.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ChunkStats {
+	/// Number of chunks.
+	pub count: usize,
+	/// Total size of the compressed chunks.
+	pub compressed_size: usize,
+	/// Total size of the chunks once decompressed.
+	pub size: usize,
+	/// Size of the largest decompressed chunk.
+	pub largest: usize,
+}
+
+impl ChunkStats {
+	fn add(&mut self, compressed: usize, decompressed: usize) {
+		self.count += 1;
+		self.compressed_size += compressed;
+		self.size += decompressed;
+		self.largest = cmp::max(self.largest, decompressed);
+	}
+}
+
+/// Summary of a snapshot's contents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotInfo {
+	/// The snapshot's manifest.
+	pub manifest: ManifestData,
+	/// State chunk statistics.
+	pub state_chunks: ChunkStats,
+	/// Block chunk statistics.
+	pub block_chunks: ChunkStats,
+	/// Number of accounts in the state chunks.
+	pub accounts: u64,
+	/// First block contained in the block chunks, if the chunk format carries
+	/// a contiguous range of blocks.
+	pub first_block: Option<u64>,
+}
+
+// read a chunk, check it against its hash and decompress it.
+fn read_chunk<R: SnapshotReader + ?Sized>(reader: &R, hash: H256) -> Result<(usize, Vec<u8>), ::error::Error> {
+	let raw = reader.chunk(hash)?;
+
+	let actual = keccak(&raw);
+	if actual != hash {
+		return Err(Error::ChunkHashMismatch(hash, actual).into());
+	}
+
+	if snappy::decompressed_len(&raw)? > MAX_CHUNK_SIZE {
+		return Err(Error::ChunkTooLarge.into());
+	}
+
+	Ok((raw.len(), snappy::decompress(&raw)?))
+}
+
+/// Gather the manifest, chunk sizes, account count and block range of a snapshot.
+/// Every chunk is checked against its hash along the way.
+pub fn inspect_snapshot<R: SnapshotReader + ?Sized>(reader: &R) -> Result<SnapshotInfo, ::error::Error> {
+	let manifest = reader.manifest().clone();
+	let mut state_chunks = ChunkStats::default();
+	let mut block_chunks = ChunkStats::default();
+	let mut accounts = 0;
+	let mut first_block = None;
+
+	// accounts with large storage are split over consecutive chunks.
+	let mut last_account = None;
+	for hash in &manifest.state_hashes {
+		let (compressed, chunk) = read_chunk(reader, *hash)?;
+		state_chunks.add(compressed, chunk.len());
+
+		for entry in Rlp::new(&chunk).iter() {
+			let account: H256 = entry.val_at(0)?;
+			if last_account != Some(account) {
+				accounts += 1;
+			}
+			last_account = Some(account);
+		}
+	}
+
+	for hash in &manifest.block_hashes {
+		let (compressed, chunk) = read_chunk(reader, *hash)?;
+		block_chunks.add(compressed, chunk.len());
+
+		// proof-of-work chunks start with the number, hash and total difficulty of
+		// the parent of their first block.
+		let rlp = Rlp::new(&chunk);
+		if rlp.item_count()? >= 3 && rlp.at(2)?.is_data() {
+			let parent_number: u64 = rlp.val_at(0)?;
+			first_block = Some(first_block.map_or(parent_number + 1, |first: u64| cmp::min(first, parent_number + 1)));
+		}
+	}
+
+	Ok(SnapshotInfo {
+		manifest: manifest,
+		state_chunks: state_chunks,
+		block_chunks: block_chunks,
+		accounts: accounts,
+		first_block: first_block,
+	})
+}
+
+/// Verify a snapshot by rebuilding it into the given scratch database.
+///
+/// Every chunk is checked against its hash, the state is rebuilt and checked against
+/// the manifest's state root, and the block chunks are fed to the engine's
+/// `SnapshotComponents`, which check them against the restored state.
+pub fn verify_snapshot<R: SnapshotReader + ?Sized>(
+	reader: &R,
+	engine: &EthEngine,
+	genesis: &[u8],
+	db: Arc<BlockChainDB>,
+) -> Result<(), ::error::Error> {
+	use trie::TrieError;
+
+	let manifest = reader.manifest();
+	let components = engine.snapshot_components().ok_or(Error::SnapshotsUnsupported)?;
+	if manifest.version < components.min_supported_version() || manifest.version > components.current_version() {
+		return Err(Error::VersionNotSupported(manifest.version).into());
+	}
+
+	let flag = AtomicBool::new(true);
+
+	info!(target: "snapshot", "Verifying {} state chunks", manifest.state_hashes.len());
+	let mut state = StateRebuilder::new(db.key_value().clone(), Algorithm::Archive);
+	for (i, hash) in manifest.state_hashes.iter().enumerate() {
+		let (_, chunk) = read_chunk(reader, *hash)?;
+		state.feed(&chunk, &flag)?;
+		trace!(target: "snapshot", "Verified state chunk {}/{}", i + 1, manifest.state_hashes.len());
+	}
+
+	let root = state.state_root();
+	if root != manifest.state_root {
+		return Err(TrieError::InvalidStateRoot(root).into());
+	}
+	state.finalize(manifest.block_number, manifest.block_hash)?;
+
+	info!(target: "snapshot", "Verifying {} block chunks", manifest.block_hashes.len());
+	let chain = BlockChain::new(Default::default(), genesis, db.clone());
+	let mut blocks = components.rebuilder(chain, db, manifest)?;
+	for (i, hash) in manifest.block_hashes.iter().enumerate() {
+		let (_, chunk) = read_chunk(reader, *hash)?;
+		blocks.feed(&chunk, engine, &flag)?;
+		trace!(target: "snapshot", "Verified block chunk {}/{}", i + 1, manifest.block_hashes.len());
+	}
+
+	blocks.finalize(engine)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use snapshot::io::{PackedReader, PackedWriter, SnapshotWriter};
+	use tempdir::TempDir;
+
+	#[test]
+	fn inspect_detects_corrupted_chunk() {
+		let tempdir = TempDir::new("").unwrap();
+		let path = tempdir.path().join("SNAP");
+
+		let chunk = snappy::compress(&::rlp::EMPTY_LIST_RLP);
+		let hash = keccak(&chunk);
+		let mut writer = PackedWriter::new(&path).unwrap();
+		writer.write_state_chunk(hash, &chunk).unwrap();
+		writer.finish(ManifestData {
+			version: 2,
+			state_hashes: vec![hash],
+			block_hashes: Vec::new(),
+			state_root: H256::default(),
+			block_number: 1000,
+			block_hash: H256::default(),
+		}).unwrap();
+
+		let reader = PackedReader::new(&path).unwrap().unwrap();
+		let info = inspect_snapshot(&reader).unwrap();
+		assert_eq!(info.state_chunks.count, 1);
+		assert_eq!(info.state_chunks.compressed_size, chunk.len());
+		assert_eq!(info.accounts, 0);
+		assert_eq!(info.first_block, None);
+
+		let path = tempdir.path().join("BAD");
+		let mut writer = PackedWriter::new(&path).unwrap();
+		writer.write_state_chunk(H256::from(1), &chunk).unwrap();
+		writer.finish(ManifestData {
+			version: 2,
+			state_hashes: vec![H256::from(1)],
+			block_hashes: Vec::new(),
+			state_root: H256::default(),
+			block_number: 1000,
+			block_hash: H256::default(),
+		}).unwrap();
+
+		let reader = PackedReader::new(&path).unwrap().unwrap();
+		assert!(inspect_snapshot(&reader).is_err());
+	}
+
+	// copy a snapshot, corrupting its manifest and chunks on the way.
+	fn rewrite<F, G>(reader: &SnapshotReader, path: &::std::path::Path, manifest: F, chunk: G) -> PackedReader where
+		F: Fn(&mut ManifestData),
+		G: Fn(&mut Vec<u8>),
+	{
+		let mut writer = PackedWriter::new(path).unwrap();
+		for hash in &reader.manifest().state_hashes {
+			let mut data = reader.chunk(*hash).unwrap();
+			chunk(&mut data);
+			writer.write_state_chunk(*hash, &data).unwrap();
+		}
+		for hash in &reader.manifest().block_hashes {
+			writer.write_block_chunk(*hash, &reader.chunk(*hash).unwrap()).unwrap();
+		}
+
+		let mut data = reader.manifest().clone();
+		manifest(&mut data);
+		writer.finish(data).unwrap();
+		PackedReader::new(path).unwrap().unwrap()
+	}
+
+	#[test]
+	fn verify_snapshot_checks_state() {
+		use snapshot::tests::helpers::snap;
+		use spec::Spec;
+		use test_helpers::{generate_dummy_client_with_spec_and_data, new_db};
+
+		let gas_prices = vec![1.into(), 2.into(), 3.into(), 999.into()];
+		let client = generate_dummy_client_with_spec_and_data(Spec::new_null, 20, 5, &gas_prices);
+		let (reader, tempdir) = snap(&client);
+		let spec = Spec::new_null();
+		let genesis = spec.genesis_block();
+
+		verify_snapshot(&*reader, &*spec.engine, &genesis, new_db()).unwrap();
+
+		let bad_root = rewrite(&*reader, &tempdir.path().join("ROOT"), |manifest| manifest.state_root = H256::from(1), |_| ());
+		assert!(verify_snapshot(&bad_root, &*spec.engine, &genesis, new_db()).is_err());
+
+		let bad_chunk = rewrite(&*reader, &tempdir.path().join("CHUNK"), |_| (), |chunk| {
+			let last = chunk.len() - 1;
+			chunk[last] ^= 1;
+		});
+		assert!(verify_snapshot(&bad_chunk, &*spec.engine, &genesis, new_db()).is_err());
+	}
+}
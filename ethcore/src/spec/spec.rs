@@ -210,6 +210,29 @@ impl CommonParams {
 		}
 	}
 
+	/// Blocks at which the hard-fork EIPs among the common parameters begin.
+	///
+	/// These are the forks other clients count in EIP-2124 fork identifiers, so Parity-specific
+	/// transitions (receipt and chain id validation, dust protection, wasm, code size limits,
+	/// transaction permissions) and EIPs no public chain activated (98, 210) are left out.
+	pub fn transitions(&self) -> Vec<BlockNumber> {
+		vec![
+			self.eip150_transition,
+			self.eip160_transition,
+			self.eip161abc_transition,
+			self.eip161d_transition,
+			self.eip658_transition,
+			self.eip155_transition,
+			self.eip140_transition,
+			self.eip211_transition,
+			self.eip214_transition,
+			self.eip145_transition,
+			self.eip1052_transition,
+			self.eip1283_transition,
+			self.eip1014_transition,
+		]
+	}
+
 	/// Return Some if the current parameters contain a bugfix hard fork not on block 0.
 	pub fn nonzero_bugfix_hard_fork(&self) -> Option<&str> {
 		if self.eip155_transition != 0 {
@@ -345,6 +368,9 @@ pub struct SpecParams<'a> {
 	/// memory. This may get more fine-grained in the future but for now is simply a binary
 	/// option.
 	pub optimization_setting: Option<OptimizeFor>,
+	/// Whether to generate full Ethash DAGs in the background, trading disk space
+	/// for faster block verification.
+	pub full_dag: bool,
 }
 
 impl<'a> SpecParams<'a> {
@@ -353,6 +379,7 @@ impl<'a> SpecParams<'a> {
 		SpecParams {
 			cache_dir: path,
 			optimization_setting: None,
+			full_dag: false,
 		}
 	}
 
@@ -361,8 +388,15 @@ impl<'a> SpecParams<'a> {
 		SpecParams {
 			cache_dir: path,
 			optimization_setting: Some(optimization),
+			full_dag: false,
 		}
 	}
+
+	/// Enable or disable full DAG generation.
+	pub fn with_full_dag(mut self, full_dag: bool) -> Self {
+		self.full_dag = full_dag;
+		self
+	}
 }
 
 impl<'a, T: AsRef<Path>> From<&'a T> for SpecParams<'a> {
@@ -601,7 +635,7 @@ impl Spec {
 
 		match engine_spec {
 			ethjson::spec::Engine::Null(null) => Arc::new(NullEngine::new(null.params.into(), machine)),
-			ethjson::spec::Engine::Ethash(ethash) => Arc::new(::ethereum::Ethash::new(spec_params.cache_dir, ethash.params.into(), machine, spec_params.optimization_setting)),
+			ethjson::spec::Engine::Ethash(ethash) => Arc::new(::ethereum::Ethash::new(spec_params.cache_dir, ethash.params.into(), machine, spec_params.optimization_setting, spec_params.full_dag)),
 			ethjson::spec::Engine::InstantSeal(Some(instant_seal)) => Arc::new(InstantSeal::new(instant_seal.params.into(), machine)),
 			ethjson::spec::Engine::InstantSeal(None) => Arc::new(InstantSeal::new(InstantSealParams::default(), machine)),
 			ethjson::spec::Engine::BasicAuthority(basic_authority) => Arc::new(BasicAuthority::new(basic_authority.params.into(), machine)),
@@ -730,6 +764,19 @@ impl Spec {
 		self.params().fork_block
 	}
 
+	/// Get the blocks at which the chain's rules change, in ascending order.
+	///
+	/// Rules active from genesis aren't forks, and transitions at or past `i64::max_value()`
+	/// are how specs disable a rule, so neither is included.
+	pub fn hard_forks(&self) -> Vec<BlockNumber> {
+		let mut forks = self.params().transitions();
+		forks.extend(self.engine.fork_transitions());
+		forks.retain(|&block| block != 0 && block < i64::max_value() as BlockNumber);
+		forks.sort();
+		forks.dedup();
+		forks
+	}
+
 	/// Get the header of the genesis block.
 	pub fn genesis_header(&self) -> Header {
 		let mut header: Header = Default::default();
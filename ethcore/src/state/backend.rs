@@ -24,6 +24,8 @@
 use std::collections::{HashSet, HashMap};
 use std::sync::Arc;
 
+use bytes::Bytes;
+use flat_state::FlatChanges;
 use state::Account;
 use parking_lot::Mutex;
 use ethereum_types::{Address, H256};
@@ -68,6 +70,20 @@ pub trait Backend: Send {
 	/// Check whether an account is known to be empty. Returns true if known to be
 	/// empty, false otherwise.
 	fn is_known_null(&self, address: &Address) -> bool;
+
+	/// Whether the backend keeps a flat state which should be queried and kept up to date.
+	fn has_flat_state(&self) -> bool { false }
+
+	/// Get the RLP of an account from the flat state of the given state root.
+	/// Returns `None` if the flat state of that root is not available.
+	fn flat_account(&self, _root: &H256, _address_hash: &H256) -> Option<Option<Bytes>> { None }
+
+	/// Get a storage value from the flat state of the given state root.
+	/// Returns `None` if the flat state of that root is not available.
+	fn flat_storage(&self, _root: &H256, _address_hash: &H256, _key_hash: &H256) -> Option<H256> { None }
+
+	/// Note the changes committed on top of the state with root `parent_root`, resulting in `root`.
+	fn note_flat_changes(&mut self, _parent_root: &H256, _root: &H256, _changes: FlatChanges) {}
 }
 
 /// A raw backend used to check proofs of execution.
@@ -24,7 +24,7 @@ use std::collections::hash_map::Entry;
 use std::collections::{HashMap, BTreeMap, BTreeSet, HashSet};
 use std::fmt;
 use std::sync::Arc;
-use hash::{KECCAK_NULL_RLP, KECCAK_EMPTY};
+use hash::{keccak, KECCAK_NULL_RLP, KECCAK_EMPTY};
 
 use receipt::{Receipt, TransactionOutcome};
 use machine::EthereumMachine as Machine;
@@ -32,6 +32,7 @@ use vm::EnvInfo;
 use error::Error;
 use executive::{Executive, TransactOptions};
 use factory::Factories;
+use flat_state::FlatChanges;
 use trace::{self, FlatTrace, VMTrace};
 use pod_account::*;
 use pod_state::{self, PodState};
@@ -356,9 +357,6 @@ impl<B: Backend> StateInfo for State<B> {
 	fn code(&self, address: &Address) -> TrieResult<Option<Arc<Bytes>>> { State::code(self, address) }
 }
 
-const SEC_TRIE_DB_UNWRAP_STR: &'static str = "A state can only be created with valid root. Creating a SecTrieDB with a valid root will not fail. \
-			 Therefore creating a SecTrieDB with this state's root will not fail.";
-
 impl<B: Backend> State<B> {
 	/// Creates new state with empty state root
 	/// Used for tests.
@@ -653,6 +651,9 @@ impl<B: Backend> State<B> {
 			let trie_res = self.db.get_cached(address, |acc| match acc {
 				None => Ok(H256::new()),
 				Some(a) => {
+					if let Some(value) = self.flat_storage_at(address, a, key, &f_cached_at) {
+						return Ok(value);
+					}
 					let account_db = self.factories.accountdb.readonly(self.db.as_hashdb(), a.address_hash(address));
 					f_at(a, account_db.as_hashdb(), key)
 				}
@@ -665,6 +666,9 @@ impl<B: Backend> State<B> {
 			// otherwise cache the account localy and cache storage key there.
 			if let Some(ref mut acc) = local_account {
 				if let Some(ref account) = acc.account {
+					if let Some(value) = self.flat_storage_at(address, account, key, &f_cached_at) {
+						return Ok(value);
+					}
 					let account_db = self.factories.accountdb.readonly(self.db.as_hashdb(), account.address_hash(address));
 					return f_at(account, account_db.as_hashdb(), key)
 				} else {
@@ -677,10 +681,11 @@ impl<B: Backend> State<B> {
 		if self.db.is_known_null(address) { return Ok(H256::zero()) }
 
 		// account is not found in the global cache, get from the DB and insert into local
-		let db = self.factories.trie.readonly(self.db.as_hashdb(), &self.root).expect(SEC_TRIE_DB_UNWRAP_STR);
-		let from_rlp = |b: &[u8]| Account::from_rlp(b).expect("decoding db value failed");
-		let maybe_acc = db.get_with(address, from_rlp)?;
+		let maybe_acc = self.account_from_db(address)?;
 		let r = maybe_acc.as_ref().map_or(Ok(H256::new()), |a| {
+			if let Some(value) = self.flat_storage_at(address, a, key, &f_cached_at) {
+				return Ok(value);
+			}
 			let account_db = self.factories.accountdb.readonly(self.db.as_hashdb(), a.address_hash(address));
 			f_at(a, account_db.as_hashdb(), key)
 		});
@@ -688,6 +693,30 @@ impl<B: Backend> State<B> {
 		r
 	}
 
+	// Look up a storage value which isn't cached in the flat state of the current root.
+	// Every account storage root matches the state at `self.root` until the next commit,
+	// after which the root changes as well.
+	fn flat_storage_at<FCachedStorageAt>(&self, address: &Address, account: &Account, key: &H256, f_cached_at: &FCachedStorageAt) -> Option<H256> where
+		FCachedStorageAt: Fn(&Account, &H256) -> Option<H256>
+	{
+		if !self.db.has_flat_state() {
+			return None;
+		}
+		f_cached_at(account, key).or_else(|| self.db.flat_storage(&self.root, &account.address_hash(address), &keccak(key)))
+	}
+
+	// Load an account from the flat state if available, or else from the trie.
+	fn account_from_db(&self, address: &Address) -> TrieResult<Option<Account>> {
+		let from_rlp = |b: &[u8]| Account::from_rlp(b).expect("decoding db value failed");
+		if self.db.has_flat_state() {
+			if let Some(maybe_rlp) = self.db.flat_account(&self.root, &keccak(address)) {
+				return Ok(maybe_rlp.map(|rlp| from_rlp(&rlp)));
+			}
+		}
+		let db = self.factories.trie.readonly(self.db.as_hashdb(), &self.root)?;
+		db.get_with(address, from_rlp)
+	}
+
 	/// Mutate storage of account `address` so that it is `value` for `key`.
 	pub fn storage_at(&self, address: &Address, key: &H256) -> TrieResult<H256> {
 		self.storage_at_inner(
@@ -869,11 +898,26 @@ impl<B: Backend> State<B> {
 	/// Commits our cached account changes into the trie.
 	pub fn commit(&mut self) -> Result<(), Error> {
 		assert!(self.checkpoints.borrow().is_empty());
+		let parent_root = self.root;
+		let track_flat = self.db.has_flat_state();
+		let mut flat_changes = FlatChanges::default();
+
 		// first, commit the sub trees.
 		let mut accounts = self.cache.borrow_mut();
 		for (address, ref mut a) in accounts.iter_mut().filter(|&(_, ref a)| a.is_dirty()) {
 			if let Some(ref mut account) = a.account {
 				let addr_hash = account.address_hash(address);
+				if track_flat {
+					// the storage was reset if it no longer starts from the original one.
+					if account.original_storage_root() != account.base_storage_root() {
+						flat_changes.cleared.insert(addr_hash);
+					}
+					if !account.storage_is_clean() {
+						flat_changes.storage.insert(addr_hash, account.storage_changes().iter()
+							.map(|(key, value)| (keccak(key), *value))
+							.collect());
+					}
+				}
 				{
 					let mut account_db = self.factories.accountdb.create(self.db.as_hashdb_mut(), addr_hash);
 					account.commit_storage(&self.factories.trie, account_db.as_hashdb_mut())?;
@@ -891,15 +935,27 @@ impl<B: Backend> State<B> {
 				a.state = AccountState::Committed;
 				match a.account {
 					Some(ref mut account) => {
-						trie.insert(address, &account.rlp())?;
+						let rlp = account.rlp();
+						trie.insert(address, &rlp)?;
+						if track_flat {
+							flat_changes.accounts.insert(account.address_hash(address), Some(rlp));
+						}
 					},
 					None => {
 						trie.remove(address)?;
+						if track_flat {
+							let addr_hash = keccak(address);
+							flat_changes.accounts.insert(addr_hash, None);
+							flat_changes.cleared.insert(addr_hash);
+						}
 					},
 				};
 			}
 		}
 
+		if track_flat {
+			self.db.note_flat_changes(&parent_root, &self.root, flat_changes);
+		}
 		Ok(())
 	}
 
@@ -1149,9 +1205,7 @@ impl<B: Backend> State<B> {
 				if check_null && self.db.is_known_null(a) { return Ok(f(None)); }
 
 				// not found in the global cache, get from the DB and insert into local
-				let db = self.factories.trie.readonly(self.db.as_hashdb(), &self.root)?;
-				let from_rlp = |b: &[u8]| Account::from_rlp(b).expect("decoding db value failed");
-				let mut maybe_acc = db.get_with(a, from_rlp)?;
+				let mut maybe_acc = self.account_from_db(a)?;
 				if let Some(ref mut account) = maybe_acc.as_mut() {
 					let accountdb = self.factories.accountdb.readonly(self.db.as_hashdb(), account.address_hash(a));
 					if !Self::update_account_cache(require, account, &self.db, accountdb.as_hashdb()) {
@@ -1181,9 +1235,7 @@ impl<B: Backend> State<B> {
 				Some(acc) => self.insert_cache(a, AccountEntry::new_clean_cached(acc)),
 				None => {
 					let maybe_acc = if !self.db.is_known_null(a) {
-						let db = self.factories.trie.readonly(self.db.as_hashdb(), &self.root)?;
-						let from_rlp = |b:&[u8]| { Account::from_rlp(b).expect("decoding db value failed") };
-						AccountEntry::new_clean(db.get_with(a, from_rlp)?)
+						AccountEntry::new_clean(self.account_from_db(a)?)
 					} else {
 						AccountEntry::new_clean(None)
 					};
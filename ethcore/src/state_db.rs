@@ -23,7 +23,9 @@ use std::sync::Arc;
 use bloom_journal::{Bloom, BloomJournal};
 use byteorder::{LittleEndian, ByteOrder};
 use db::COL_ACCOUNT_BLOOM;
+use bytes::Bytes;
 use ethereum_types::{H256, Address};
+use flat_state::{FlatChanges, FlatState};
 use hash::keccak;
 use hashdb::HashDB;
 use keccak_hasher::KeccakHasher;
@@ -123,6 +125,11 @@ pub struct StateDB {
 	commit_hash: Option<H256>,
 	/// Number of the committing block or `None` if not committed yet.
 	commit_number: Option<BlockNumber>,
+	/// Flat state, if enabled.
+	flat_state: Option<Arc<FlatState>>,
+	/// Parent state root, resulting state root and changes committed since this instance
+	/// was created.
+	flat_changes: Option<(H256, H256, FlatChanges)>,
 }
 
 impl StateDB {
@@ -150,9 +157,22 @@ impl StateDB {
 			parent_hash: None,
 			commit_hash: None,
 			commit_number: None,
+			flat_state: None,
+			flat_changes: None,
 		}
 	}
 
+	/// Keep the given flat state up to date and read from it.
+	pub fn with_flat_state(mut self, flat_state: Arc<FlatState>) -> StateDB {
+		self.flat_state = Some(flat_state);
+		self
+	}
+
+	/// Get the flat state, if enabled.
+	pub fn flat_state(&self) -> Option<&Arc<FlatState>> {
+		self.flat_state.as_ref()
+	}
+
 	/// Loads accounts bloom from the database
 	/// This bloom is used to handle request for the non-existant account fast
 	pub fn load_bloom(db: &KeyValueDB) -> Bloom {
@@ -203,6 +223,9 @@ impl StateDB {
  			Self::commit_bloom(batch, bloom_lock.drain_journal())?;
  		}
 		let records = self.db.journal_under(batch, now, id)?;
+		if let (Some(flat_state), Some((parent, root, changes))) = (self.flat_state.as_ref(), self.flat_changes.take()) {
+			flat_state.insert_layer(batch, parent, root, now, changes);
+		}
 		self.commit_hash = Some(id.clone());
 		self.commit_number = Some(now);
 		Ok(records)
@@ -214,6 +237,11 @@ impl StateDB {
 		self.db.mark_canonical(batch, end_era, canon_id)
 	}
 
+	/// Drop the journal of the eras after the given one, whose blocks are no longer in the chain.
+	pub fn revert_to_era(&mut self, batch: &mut DBTransaction, era: u64) -> io::Result<u32> {
+		self.db.revert_to_era(batch, era)
+	}
+
 	/// Propagate local cache into the global cache and synchonize
 	/// the global cache with the best block state.
 	/// This function updates the global cache by removing entries
@@ -333,6 +361,8 @@ impl StateDB {
 			parent_hash: None,
 			commit_hash: None,
 			commit_number: None,
+			flat_state: self.flat_state.clone(),
+			flat_changes: None,
 		}
 	}
 
@@ -348,6 +378,8 @@ impl StateDB {
 			parent_hash: Some(parent.clone()),
 			commit_hash: None,
 			commit_number: None,
+			flat_state: self.flat_state.clone(),
+			flat_changes: None,
 		}
 	}
 
@@ -366,6 +398,13 @@ impl StateDB {
 		}
 	}
 
+	/// Drop all cached accounts, e.g. after the canon chain was rewound.
+	pub fn clear_cache(&self) {
+		let mut cache = self.account_cache.lock();
+		cache.accounts.clear();
+		cache.modifications.clear();
+	}
+
 	/// Returns underlying `JournalDB`.
 	pub fn journal_db(&self) -> &JournalDB {
 		&*self.db
@@ -467,6 +506,28 @@ impl state::Backend for StateDB {
 		let is_null = !bloom.check(&*keccak(address));
 		is_null
 	}
+
+	fn has_flat_state(&self) -> bool {
+		self.flat_state.is_some()
+	}
+
+	fn flat_account(&self, root: &H256, address_hash: &H256) -> Option<Option<Bytes>> {
+		self.flat_state.as_ref().and_then(|flat_state| flat_state.account(root, address_hash))
+	}
+
+	fn flat_storage(&self, root: &H256, address_hash: &H256, key_hash: &H256) -> Option<H256> {
+		self.flat_state.as_ref().and_then(|flat_state| flat_state.storage(root, address_hash, key_hash))
+	}
+
+	fn note_flat_changes(&mut self, parent_root: &H256, root: &H256, changes: FlatChanges) {
+		match self.flat_changes {
+			Some((_, ref mut current_root, ref mut current)) => {
+				*current_root = *root;
+				current.extend(changes);
+			},
+			None => self.flat_changes = Some((*parent_root, *root, changes)),
+		}
+	}
 }
 
 /// Sync wrapper for the account.
@@ -19,7 +19,7 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use blockchain::{BlockChainDB};
 use heapsize::HeapSizeOf;
-use ethereum_types::{H256, H264};
+use ethereum_types::{H256, H264, Bloom};
 use kvdb::{DBTransaction};
 use parking_lot::RwLock;
 use header::BlockNumber;
@@ -112,6 +112,30 @@ impl<T> TraceDB<T> where T: DatabaseExtras {
 		});
 	}
 
+	/// Removes the traces of blocks whose history has expired.
+	pub fn expire_traces(&self, batch: &mut DBTransaction, block_hashes: &[H256]) {
+		let mut traces = self.traces.write();
+		for hash in block_hashes {
+			Writable::delete::<FlatBlockTraces, _>(batch, db::COL_TRACE, hash);
+			traces.remove(hash);
+		}
+	}
+
+	/// Remove the traces of blocks rewound from the canon chain and clear their trace blooms.
+	/// `block_hashes` are the rewound blocks, starting at number `from`.
+	pub fn rewind_traces(&self, batch: &mut DBTransaction, from: BlockNumber, block_hashes: &[H256]) {
+		// the database may hold traces even if tracing is disabled now, so always clear them.
+		self.expire_traces(batch, block_hashes);
+		if block_hashes.is_empty() {
+			return;
+		}
+
+		let empty = vec![Bloom::default(); block_hashes.len()];
+		self.db.trace_blooms()
+			.insert_blooms(from, empty.iter())
+			.expect("Low level database error. Some issue with disk?");
+	}
+
 	/// Returns traces for block with hash.
 	fn traces(&self, block_hash: &H256) -> Option<FlatBlockTraces> {
 		let result = self.db.key_value().read_with_cache(db::COL_TRACE, &self.traces, block_hash);
@@ -315,14 +339,14 @@ impl<T> TraceDatabase for TraceDB<T> where T: DatabaseExtras {
 			.expect("Low level database error. Some issue with disk?");
 
 		numbers.into_iter()
-			.flat_map(|n| {
+			.filter_map(|n| {
 				let number = n as BlockNumber;
 				let hash = self.extras.block_hash(number)
 					.expect("Expected to find block hash. Extras db is probably corrupted");
-				let traces = self.traces(&hash)
-					.expect("Expected to find a trace. Db is probably corrupted.");
-				self.matching_block_traces(filter, traces, hash, number)
+				// traces of blocks with expired history are gone.
+				self.traces(&hash).map(|traces| (traces, hash, number))
 			})
+			.flat_map(|(traces, hash, number)| self.matching_block_traces(filter, traces, hash, number))
 			.collect()
 	}
 }
@@ -23,7 +23,7 @@ use bytes::Bytes;
 use devp2p::NetworkService;
 use network::{NetworkProtocolHandler, NetworkContext, PeerId, ProtocolId,
 	NetworkConfiguration as BasicNetworkConfiguration, NonReservedPeerMode, Error, ErrorKind,
-	ConnectionFilter};
+	ConnectionFilter, TrafficStats, PacketUploadRate};
 
 use types::pruning_info::PruningInfo;
 use ethereum_types::{H256, H512, U256};
@@ -34,12 +34,15 @@ use ethcore::snapshot::SnapshotService;
 use ethcore::header::BlockNumber;
 use sync_io::NetSyncIo;
 use chain::{ChainSyncApi, SyncStatus as EthSyncStatus};
+use fork_id::{ForkFilter, ForkIdEnrFilter};
+use reputation::Reputation;
+use checkpoint::Checkpoint;
 use std::net::{SocketAddr, AddrParseError};
 use std::str::FromStr;
 use parking_lot::{RwLock, Mutex};
-use chain::{ETH_PROTOCOL_VERSION_63, ETH_PROTOCOL_VERSION_62,
-	PAR_PROTOCOL_VERSION_1, PAR_PROTOCOL_VERSION_2, PAR_PROTOCOL_VERSION_3,
-	PRIVATE_TRANSACTION_PACKET, SIGNED_PRIVATE_TRANSACTION_PACKET};
+use chain::{ETH_PROTOCOL_VERSION_66, ETH_PROTOCOL_VERSION_65, ETH_PROTOCOL_VERSION_64, ETH_PROTOCOL_VERSION_63, ETH_PROTOCOL_VERSION_62,
+	PAR_PROTOCOL_VERSION_1, PAR_PROTOCOL_VERSION_2, PAR_PROTOCOL_VERSION_3, PAR_PROTOCOL_VERSION_4,
+	PRIVATE_TRANSACTION_PACKET, SIGNED_PRIVATE_TRANSACTION_PACKET, SNAPSHOT_MANIFEST_PACKET, SNAPSHOT_DATA_PACKET};
 use light::client::AsLightClient;
 use light::Provider;
 use light::net::{
@@ -92,8 +95,18 @@ impl WarpSync {
 	}
 }
 
+/// Determine how the initial state is retrieved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMode {
+	/// Import every block from the last known one.
+	Full,
+	/// Download the state of a recent block from proven account and storage ranges,
+	/// then import the blocks after it.
+	State,
+}
+
 /// Sync configuration
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct SyncConfig {
 	/// Max blocks to download ahead
 	pub max_download_ahead_blocks: usize,
@@ -107,8 +120,14 @@ pub struct SyncConfig {
 	pub light_subprotocol_name: [u8; 3],
 	/// Fork block to check
 	pub fork_block: Option<(BlockNumber, H256)>,
+	/// Blocks at which the chain rules change, announced in the EIP-2124 fork identifier.
+	pub hard_forks: Vec<BlockNumber>,
 	/// Enable snapshot sync
 	pub warp_sync: WarpSync,
+	/// Initial sync mode.
+	pub sync_mode: SyncMode,
+	/// Trusted block to restore the state of instead of syncing from genesis.
+	pub checkpoint: Option<Checkpoint>,
 	/// Enable light client server.
 	pub serve_light: bool,
 }
@@ -122,7 +141,10 @@ impl Default for SyncConfig {
 			subprotocol_name: ETH_PROTOCOL,
 			light_subprotocol_name: LIGHT_PROTOCOL,
 			fork_block: None,
+			hard_forks: Vec::new(),
 			warp_sync: WarpSync::Disabled,
+			sync_mode: SyncMode::Full,
+			checkpoint: None,
 			serve_light: false,
 		}
 	}
@@ -150,6 +172,8 @@ pub struct TransactionStats {
 	pub first_seen: u64,
 	/// Peers it was propagated to.
 	pub propagated_to: BTreeMap<H512, usize>,
+	/// Peers its hash was announced to.
+	pub announced_to: BTreeMap<H512, usize>,
 }
 
 /// Peer connection information
@@ -169,6 +193,28 @@ pub struct PeerInfo {
 	pub eth_info: Option<EthProtocolInfo>,
 	/// Light protocol info.
 	pub pip_info: Option<PipProtocolInfo>,
+	/// Bytes exchanged with the peer.
+	pub traffic: NetworkTraffic,
+}
+
+/// Bytes transferred in total and by protocol.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NetworkTraffic {
+	/// All packets, including control packets.
+	pub total: TrafficStats,
+	/// Packets of each protocol, by protocol name.
+	pub protocols: BTreeMap<String, TrafficStats>,
+}
+
+impl NetworkTraffic {
+	fn new(total: TrafficStats, protocols: HashMap<ProtocolId, TrafficStats>) -> Self {
+		NetworkTraffic {
+			total,
+			protocols: protocols.into_iter()
+				.map(|(protocol, traffic)| (String::from_utf8_lossy(&protocol).into_owned(), traffic))
+				.collect(),
+		}
+	}
 }
 
 /// Ethereum protocol info.
@@ -180,6 +226,8 @@ pub struct EthProtocolInfo {
 	pub head: H256,
 	/// Peer total difficulty if known
 	pub difficulty: Option<U256>,
+	/// Peer reputation rating
+	pub reputation: i32,
 }
 
 /// PIP protocol info.
@@ -346,13 +394,20 @@ impl EthSync {
 		};
 
 		let (priority_tasks_tx, priority_tasks_rx) = mpsc::channel();
+		let bans_path = params.network_config.net_config_path
+			.clone()
+			.map(::std::path::PathBuf::from)
+			.map(|mut p| { p.push("peer_bans"); p });
 		let sync = ChainSyncApi::new(
-			params.config,
+			params.config.clone(),
 			&*params.chain,
 			params.private_tx_handler.clone(),
 			priority_tasks_rx,
+			Reputation::new(bans_path),
 		);
 		let service = NetworkService::new(params.network_config.clone().into_basic()?, connection_filter)?;
+		let fork_filter = ForkFilter::new(params.chain.chain_info().genesis_hash, params.config.hard_forks.clone());
+		service.set_enr_filter(Arc::new(ForkIdEnrFilter::new(fork_filter, params.chain.clone())));
 
 		let sync = Arc::new(EthSync {
 			network: service,
@@ -405,6 +460,7 @@ impl SyncProvider for EthSync {
 					local_address: session_info.local_address,
 					eth_info: peer_info,
 					pip_info: light_proto.as_ref().and_then(|lp| lp.peer_status(peer_id)).map(Into::into),
+					traffic: NetworkTraffic::new(session_info.traffic, session_info.protocol_traffic),
 				})
 			}).collect()
 		}).unwrap_or_else(Vec::new)
@@ -549,10 +605,10 @@ impl ChainNotify for EthSync {
 			_ => {},
 		}
 
-		self.network.register_protocol(self.eth_handler.clone(), self.subprotocol_name, &[ETH_PROTOCOL_VERSION_62, ETH_PROTOCOL_VERSION_63])
+		self.network.register_protocol(self.eth_handler.clone(), self.subprotocol_name, &[ETH_PROTOCOL_VERSION_62, ETH_PROTOCOL_VERSION_63, ETH_PROTOCOL_VERSION_64, ETH_PROTOCOL_VERSION_65, ETH_PROTOCOL_VERSION_66])
 			.unwrap_or_else(|e| warn!("Error registering ethereum protocol: {:?}", e));
 		// register the warp sync subprotocol
-		self.network.register_protocol(self.eth_handler.clone(), WARP_SYNC_PROTOCOL_ID, &[PAR_PROTOCOL_VERSION_1, PAR_PROTOCOL_VERSION_2, PAR_PROTOCOL_VERSION_3])
+		self.network.register_protocol(self.eth_handler.clone(), WARP_SYNC_PROTOCOL_ID, &[PAR_PROTOCOL_VERSION_1, PAR_PROTOCOL_VERSION_2, PAR_PROTOCOL_VERSION_3, PAR_PROTOCOL_VERSION_4])
 			.unwrap_or_else(|e| warn!("Error registering snapshot sync protocol: {:?}", e));
 
 		// register the light protocol.
@@ -620,6 +676,8 @@ pub trait ManageNetwork : Send + Sync {
 	fn num_peers_range(&self) -> Range<u32>;
 	/// Get network context for protocol.
 	fn with_proto_context(&self, proto: ProtocolId, f: &mut FnMut(&NetworkContext));
+	/// Returns bytes transferred with all peers since start.
+	fn traffic(&self) -> NetworkTraffic;
 }
 
 impl ManageNetwork for EthSync {
@@ -663,6 +721,11 @@ impl ManageNetwork for EthSync {
 	fn with_proto_context(&self, proto: ProtocolId, f: &mut FnMut(&NetworkContext)) {
 		self.network.with_context_eval(proto, f);
 	}
+
+	fn traffic(&self) -> NetworkTraffic {
+		let (total, protocols) = self.network.traffic();
+		NetworkTraffic::new(total, protocols)
+	}
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -684,6 +747,10 @@ pub struct NetworkConfiguration {
 	pub discovery_enabled: bool,
 	/// List of initial node addresses
 	pub boot_nodes: Vec<String>,
+	/// List of EIP-1459 DNS node list URLs
+	pub dns_node_lists: Vec<String>,
+	/// Name servers to resolve DNS node lists with, the system's by default
+	pub dns_servers: Vec<String>,
 	/// Use provided node key instead of default
 	pub use_secret: Option<Secret>,
 	/// Max number of connected peers to maintain
@@ -702,6 +769,14 @@ pub struct NetworkConfiguration {
 	pub ip_filter: IpFilter,
 	/// Client version string
 	pub client_version: String,
+	/// Max upload rate to all peers, in bytes per second
+	pub max_upload_rate: Option<u64>,
+	/// Max upload rate to a single peer, in bytes per second
+	pub max_peer_upload_rate: Option<u64>,
+	/// Max upload rate of snapshot manifests and chunks, in bytes per second
+	pub warp_upload_rate: Option<u64>,
+	/// Max upload rate for serving light clients, in bytes per second
+	pub light_upload_rate: Option<u64>,
 }
 
 impl NetworkConfiguration {
@@ -717,6 +792,15 @@ impl NetworkConfiguration {
 
 	/// Attempt to convert this config into a BasicNetworkConfiguration.
 	pub fn into_basic(self) -> Result<BasicNetworkConfiguration, AddrParseError> {
+		let packet_upload_rates = self.warp_upload_rate.into_iter().map(|rate| PacketUploadRate {
+			protocol: WARP_SYNC_PROTOCOL_ID,
+			packets: vec![SNAPSHOT_MANIFEST_PACKET, SNAPSHOT_DATA_PACKET],
+			rate,
+		}).collect();
+		let mut protocol_upload_rates = HashMap::new();
+		if let Some(rate) = self.light_upload_rate {
+			protocol_upload_rates.insert(LIGHT_PROTOCOL, rate);
+		}
 		Ok(BasicNetworkConfiguration {
 			config_path: self.config_path,
 			net_config_path: self.net_config_path,
@@ -726,6 +810,8 @@ impl NetworkConfiguration {
 			nat_enabled: self.nat_enabled,
 			discovery_enabled: self.discovery_enabled,
 			boot_nodes: self.boot_nodes,
+			dns_node_lists: self.dns_node_lists,
+			dns_servers: self.dns_servers,
 			use_secret: self.use_secret,
 			max_peers: self.max_peers,
 			min_peers: self.min_peers,
@@ -735,6 +821,10 @@ impl NetworkConfiguration {
 			ip_filter: self.ip_filter,
 			non_reserved_mode: if self.allow_non_reserved { NonReservedPeerMode::Accept } else { NonReservedPeerMode::Deny },
 			client_version: self.client_version,
+			max_upload_rate: self.max_upload_rate,
+			max_peer_upload_rate: self.max_peer_upload_rate,
+			protocol_upload_rates,
+			packet_upload_rates,
 		})
 	}
 }
@@ -750,6 +840,8 @@ impl From<BasicNetworkConfiguration> for NetworkConfiguration {
 			nat_enabled: other.nat_enabled,
 			discovery_enabled: other.discovery_enabled,
 			boot_nodes: other.boot_nodes,
+			dns_node_lists: other.dns_node_lists,
+			dns_servers: other.dns_servers,
 			use_secret: other.use_secret,
 			max_peers: other.max_peers,
 			min_peers: other.min_peers,
@@ -759,6 +851,10 @@ impl From<BasicNetworkConfiguration> for NetworkConfiguration {
 			ip_filter: other.ip_filter,
 			allow_non_reserved: match other.non_reserved_mode { NonReservedPeerMode::Accept => true, _ => false } ,
 			client_version: other.client_version,
+			max_upload_rate: other.max_upload_rate,
+			max_peer_upload_rate: other.max_peer_upload_rate,
+			warp_upload_rate: other.packet_upload_rates.iter().find(|limit| limit.protocol == WARP_SYNC_PROTOCOL_ID).map(|limit| limit.rate),
+			light_upload_rate: other.protocol_upload_rates.get(&LIGHT_PROTOCOL).cloned(),
 		}
 	}
 }
@@ -942,6 +1038,11 @@ impl ManageNetwork for LightSync {
 	fn with_proto_context(&self, proto: ProtocolId, f: &mut FnMut(&NetworkContext)) {
 		self.network.with_context_eval(proto, f);
 	}
+
+	fn traffic(&self) -> NetworkTraffic {
+		let (total, protocols) = self.network.traffic();
+		NetworkTraffic::new(total, protocols)
+	}
 }
 
 impl LightSyncProvider for LightSync {
@@ -975,6 +1076,7 @@ impl LightSyncProvider for LightSync {
 					local_address: session_info.local_address,
 					eth_info: None,
 					pip_info: self.proto.peer_status(peer_id).map(Into::into),
+					traffic: NetworkTraffic::new(session_info.traffic, session_info.protocol_traffic),
 				})
 			}).collect()
 		}).unwrap_or_else(Vec::new)
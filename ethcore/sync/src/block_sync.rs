@@ -29,10 +29,9 @@ use ethcore::error::{ImportErrorKind, QueueErrorKind, BlockError, Error as Ethco
 use sync_io::SyncIo;
 use blocks::{BlockCollection, SyncBody, SyncHeader};
 use chain::BlockSet;
+use checkpoint::Checkpoint;
+use request_size::MAX_HEADERS_TO_REQUEST;
 
-const MAX_HEADERS_TO_REQUEST: usize = 128;
-const MAX_BODIES_TO_REQUEST: usize = 32;
-const MAX_RECEPITS_TO_REQUEST: usize = 128;
 const SUBCHAIN_SIZE: u64 = 256;
 const MAX_ROUND_PARENTS: usize = 16;
 const MAX_PARALLEL_SUBCHAIN_DOWNLOAD: usize = 5;
@@ -134,6 +133,8 @@ pub struct BlockDownloader {
 	download_receipts: bool,
 	/// Sync up to the block with this hash.
 	target_hash: Option<H256>,
+	/// Trusted block the chain must include.
+	checkpoint: Option<Checkpoint>,
 	/// Probing range for seeking common best block.
 	retract_step: u64,
 	/// consecutive useless headers this round
@@ -161,6 +162,7 @@ impl BlockDownloader {
 			round_parents: VecDeque::new(),
 			download_receipts: sync_receipts,
 			target_hash: None,
+			checkpoint: None,
 			retract_step: 1,
 			useless_headers_count: 0,
 		}
@@ -189,6 +191,11 @@ impl BlockDownloader {
 		self.state == State::Complete
 	}
 
+	/// Check if blocks of a sync round are being downloaded
+	pub fn is_downloading_blocks(&self) -> bool {
+		self.state == State::Blocks
+	}
+
 	/// Check if particular block hash is being downloaded
 	pub fn is_downloading(&self, hash: &H256) -> bool {
 		self.blocks.is_downloading(hash)
@@ -199,6 +206,11 @@ impl BlockDownloader {
 		self.target_hash = Some(hash.clone());
 	}
 
+	/// Set a block the downloaded chain must include.
+	pub fn set_checkpoint(&mut self, checkpoint: Checkpoint) {
+		self.checkpoint = Some(checkpoint);
+	}
+
 	/// Unmark header as being downloaded.
 	pub fn clear_header_download(&mut self, hash: &H256) {
 		self.blocks.clear_header_download(hash)
@@ -278,6 +290,10 @@ impl BlockDownloader {
 				debug!(target: "sync", "Invalid headers response");
 				return Err(BlockDownloaderImportError::Invalid);
 			}
+			if self.checkpoint.map_or(false, |checkpoint| checkpoint.number == number && checkpoint.hash != hash) {
+				debug!(target: "sync", "Header #{} doesn't match the checkpoint", number);
+				return Err(BlockDownloaderImportError::Invalid);
+			}
 
 			last_header = Some((number, hash));
 			if self.blocks.contains(&hash) {
@@ -463,17 +479,19 @@ impl BlockDownloader {
 		self.imported_this_round = None;
 	}
 
-	/// Find some headers or blocks to download for a peer.
-	pub fn request_blocks(&mut self, io: &mut SyncIo, num_active_peers: usize) -> Option<BlockRequest> {
+	/// Find some headers or blocks to download for a peer. Untrusted peers are only asked for
+	/// bodies and receipts, which are checked against the headers we already have. At most
+	/// `headers` headers of a subchain, `bodies` bodies or `receipts` receipts are asked for at once.
+	pub fn request_blocks(&mut self, io: &mut SyncIo, num_active_peers: usize, trusted: bool, headers: usize, bodies: usize, receipts: usize) -> Option<BlockRequest> {
 		match self.state {
 			State::Idle => {
 				self.start_sync_round(io);
 				if self.state == State::ChainHead {
-					return self.request_blocks(io, num_active_peers);
+					return self.request_blocks(io, num_active_peers, trusted, headers, bodies, receipts);
 				}
 			},
 			State::ChainHead => {
-				if num_active_peers < MAX_PARALLEL_SUBCHAIN_DOWNLOAD {
+				if trusted && num_active_peers < MAX_PARALLEL_SUBCHAIN_DOWNLOAD {
 					// Request subchain headers
 					trace_sync!(self, "Starting sync with better chain");
 					// Request MAX_HEADERS_TO_REQUEST - 2 headers apart so that
@@ -487,7 +505,7 @@ impl BlockDownloader {
 			},
 			State::Blocks => {
 				// check to see if we need to download any block bodies first
-				let needed_bodies = self.blocks.needed_bodies(MAX_BODIES_TO_REQUEST, false);
+				let needed_bodies = self.blocks.needed_bodies(bodies, false);
 				if !needed_bodies.is_empty() {
 					return Some(BlockRequest::Bodies {
 						hashes: needed_bodies,
@@ -495,7 +513,7 @@ impl BlockDownloader {
 				}
 
 				if self.download_receipts {
-					let needed_receipts = self.blocks.needed_receipts(MAX_RECEPITS_TO_REQUEST, false);
+					let needed_receipts = self.blocks.needed_receipts(receipts, false);
 					if !needed_receipts.is_empty() {
 						return Some(BlockRequest::Receipts {
 							hashes: needed_receipts,
@@ -504,7 +522,10 @@ impl BlockDownloader {
 				}
 
 				// find subchain to download
-				if let Some((h, count)) = self.blocks.needed_headers(MAX_HEADERS_TO_REQUEST, false) {
+				if !trusted {
+					return None;
+				}
+				if let Some((h, count)) = self.blocks.needed_headers(cmp::min(headers, MAX_HEADERS_TO_REQUEST), false) {
 					return Some(BlockRequest::Headers {
 						start: h,
 						count: count as u64,
@@ -784,6 +805,34 @@ mod tests {
 		};
 	}
 
+	#[test]
+	fn import_headers_rejects_chain_without_checkpoint() {
+		::env_logger::try_init().ok();
+
+		let mut chain = TestBlockChainClient::new();
+		let snapshot_service = TestSnapshotService::new();
+		let queue = RwLock::new(VecDeque::new());
+		let mut io = TestIo::new(&mut chain, &snapshot_service, &queue, None);
+
+		let mut headers = Vec::with_capacity(2);
+		headers.push(dummy_header(127, H256::random()));
+		let parent_hash = headers[0].hash();
+		headers.push(dummy_header(128, parent_hash));
+
+		let mut downloader = BlockDownloader::new(BlockSet::NewBlocks, &H256::random(), 0);
+		downloader.state = State::Blocks;
+		downloader.set_checkpoint(Checkpoint { hash: H256::random(), number: 128 });
+		downloader.blocks.reset_to(vec![headers[0].hash()]);
+
+		match import_headers(&headers, &mut downloader, &mut io) {
+			Err(BlockDownloaderImportError::Invalid) => (),
+			_ => panic!("expected BlockDownloaderImportError"),
+		};
+
+		downloader.set_checkpoint(Checkpoint { hash: headers[1].hash(), number: 128 });
+		import_headers_ok(&headers, &mut downloader, &mut io);
+	}
+
 	#[test]
 	fn import_bodies() {
 		::env_logger::try_init().ok();
@@ -14,23 +14,28 @@
 // You should have received a copy of the GNU General Public License
 // along with Parity.  If not, see <http://www.gnu.org/licenses/>.
 
-use api::WARP_SYNC_PROTOCOL_ID;
+use api::{SyncMode, WARP_SYNC_PROTOCOL_ID};
 use block_sync::{BlockDownloaderImportError as DownloaderImportError, DownloadAction};
 use bytes::Bytes;
 use ethcore::client::{BlockId, BlockStatus};
 use ethcore::error::{Error as EthcoreError, ErrorKind as EthcoreErrorKind, ImportErrorKind, BlockError};
-use ethcore::header::BlockNumber;
-use ethcore::snapshot::{ManifestData, RestorationStatus};
+use ethcore::header::{BlockNumber, Header};
+use ethcore::snapshot::{ManifestData, RestorationStatus, StateSyncData, verify_range};
 use ethcore::verification::queue::kind::blocks::Unverified;
 use ethereum_types::{H256, U256};
-use hash::keccak;
+use hash::{keccak, KECCAK_NULL_RLP};
 use network::PeerId;
-use rlp::Rlp;
+use rlp::{Rlp, DecoderError};
 use snapshot::ChunkType;
+use state_sync::StateRange;
+use fork_id::ForkId;
+use reputation::Conduct;
+use request_size::RequestSize;
 use std::cmp;
-use std::mem;
+use std::collections::BTreeMap;
 use std::time::Instant;
 use sync_io::SyncIo;
+use types::basic_account::BasicAccount;
 
 use super::{
 	BlockSet,
@@ -43,20 +48,30 @@ use super::{
 	SyncState,
 	ETH_PROTOCOL_VERSION_62,
 	ETH_PROTOCOL_VERSION_63,
+	ETH_PROTOCOL_VERSION_64,
+	ETH_PROTOCOL_VERSION_65,
+	ETH_PROTOCOL_VERSION_66,
 	MAX_NEW_BLOCK_AGE,
 	MAX_NEW_HASHES,
 	PAR_PROTOCOL_VERSION_1,
-	PAR_PROTOCOL_VERSION_3,
+	PAR_PROTOCOL_VERSION_4,
+	SNAPSHOT_RESTORE_THRESHOLD,
+	ACCOUNT_RANGE_PACKET,
 	BLOCK_BODIES_PACKET,
 	BLOCK_HEADERS_PACKET,
 	NEW_BLOCK_HASHES_PACKET,
 	NEW_BLOCK_PACKET,
+	NEW_POOLED_TRANSACTION_HASHES_PACKET,
+	NODE_DATA_PACKET,
+	POOLED_TRANSACTIONS_PACKET,
 	PRIVATE_TRANSACTION_PACKET,
 	RECEIPTS_PACKET,
 	SIGNED_PRIVATE_TRANSACTION_PACKET,
 	SNAPSHOT_DATA_PACKET,
 	SNAPSHOT_MANIFEST_PACKET,
 	STATUS_PACKET,
+	STORAGE_RANGE_PACKET,
+	unwrap_request_id,
 };
 
 /// The Chain Sync Handler: handles responses from peers
@@ -68,13 +83,20 @@ impl SyncHandler {
 		let rlp = Rlp::new(data);
 		let result = match packet_id {
 			STATUS_PACKET => SyncHandler::on_peer_status(sync, io, peer, &rlp),
-			BLOCK_HEADERS_PACKET => SyncHandler::on_peer_block_headers(sync, io, peer, &rlp),
-			BLOCK_BODIES_PACKET => SyncHandler::on_peer_block_bodies(sync, io, peer, &rlp),
-			RECEIPTS_PACKET => SyncHandler::on_peer_block_receipts(sync, io, peer, &rlp),
+			BLOCK_HEADERS_PACKET => SyncHandler::on_response(sync, io, peer, &rlp, SyncHandler::on_peer_block_headers),
+			BLOCK_BODIES_PACKET => SyncHandler::on_response(sync, io, peer, &rlp, SyncHandler::on_peer_block_bodies),
+			RECEIPTS_PACKET => SyncHandler::on_response(sync, io, peer, &rlp, SyncHandler::on_peer_block_receipts),
+			NODE_DATA_PACKET => SyncHandler::on_response(sync, io, peer, &rlp, SyncHandler::on_peer_node_data),
 			NEW_BLOCK_PACKET => SyncHandler::on_peer_new_block(sync, io, peer, &rlp),
 			NEW_BLOCK_HASHES_PACKET => SyncHandler::on_peer_new_hashes(sync, io, peer, &rlp),
+			NEW_POOLED_TRANSACTION_HASHES_PACKET => SyncHandler::on_peer_new_pooled_transaction_hashes(sync, io, peer, &rlp),
+			POOLED_TRANSACTIONS_PACKET => SyncHandler::on_response(sync, io, peer, &rlp, |sync, io, peer, _, r| {
+				SyncHandler::on_peer_pooled_transactions(sync, io, peer, r)
+			}),
 			SNAPSHOT_MANIFEST_PACKET => SyncHandler::on_snapshot_manifest(sync, io, peer, &rlp),
 			SNAPSHOT_DATA_PACKET => SyncHandler::on_snapshot_data(sync, io, peer, &rlp),
+			ACCOUNT_RANGE_PACKET => SyncHandler::on_state_range(sync, io, peer, PeerAsking::AccountRange, &rlp),
+			STORAGE_RANGE_PACKET => SyncHandler::on_state_range(sync, io, peer, PeerAsking::StorageRange, &rlp),
 			PRIVATE_TRANSACTION_PACKET => SyncHandler::on_private_transaction(sync, io, peer, &rlp),
 			SIGNED_PRIVATE_TRANSACTION_PACKET => SyncHandler::on_signed_private_transaction(sync, io, peer, &rlp),
 			_ => {
@@ -86,10 +108,16 @@ impl SyncHandler {
 		match result {
 			Err(DownloaderImportError::Invalid) => {
 				debug!(target:"sync", "{} -> Invalid packet {}", peer, packet_id);
+				let conduct = match packet_id {
+					BLOCK_HEADERS_PACKET | BLOCK_BODIES_PACKET | RECEIPTS_PACKET | NEW_BLOCK_PACKET => Conduct::InvalidBlock,
+					_ => Conduct::InvalidPacket,
+				};
+				sync.note_conduct(io, peer, conduct);
 				io.disable_peer(peer);
 				sync.deactivate_peer(io, peer);
 			},
 			Err(DownloaderImportError::Useless) => {
+				sync.note_conduct(io, peer, Conduct::Useless);
 				sync.deactivate_peer(io, peer);
 			},
 			Ok(()) => {
@@ -99,6 +127,47 @@ impl SyncHandler {
 		}
 	}
 
+	/// Passes a response on to its handler, along with the request id of eth/66 peers.
+	/// Useful responses improve the peer's reputation according to the throughput.
+	fn on_response<F>(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, r: &Rlp, handler: F) -> Result<(), DownloaderImportError>
+		where F: FnOnce(&mut ChainSync, &mut SyncIo, PeerId, Option<u64>, &Rlp) -> Result<(), DownloaderImportError>
+	{
+		let (result, sent) = if sync.peers.get(&peer_id).map_or(false, |p| p.request_ids) {
+			let (request_id, response) = unwrap_request_id(r)?;
+			let sent = SyncHandler::request_time(sync, peer_id, Some(request_id));
+			(handler(sync, io, peer_id, Some(request_id), &response), sent)
+		} else {
+			let sent = SyncHandler::request_time(sync, peer_id, None);
+			(handler(sync, io, peer_id, None, r), sent)
+		};
+		if let (&Ok(()), Some(sent)) = (&result, sent) {
+			if let Some(node_id) = sync.peers.get(&peer_id).and_then(|p| p.node_id) {
+				sync.reputation.note_response(&node_id, r.as_raw().len(), sent.elapsed());
+			}
+		}
+		result
+	}
+
+	/// Time a pending request answered by a response was sent at.
+	fn request_time(sync: &ChainSync, peer_id: PeerId, request_id: Option<u64>) -> Option<Instant> {
+		let peer = sync.peers.get(&peer_id)?;
+		let request = match request_id {
+			Some(id) => peer.requests.get(&id),
+			None => peer.requests.values().next(),
+		};
+		request.map(|request| request.time)
+	}
+
+	/// Whether a response answers a pending request of the given kind.
+	fn is_answering(sync: &ChainSync, peer_id: PeerId, request_id: Option<u64>, asking: PeerAsking) -> bool {
+		match sync.peers.get(&peer_id) {
+			Some(peer) => peer.answered_request(request_id, &asking)
+				.and_then(|id| peer.requests.get(&id))
+				.map_or(false, |request| request.asking == asking),
+			None => false,
+		}
+	}
+
 	/// Called when peer sends us new consensus packet
 	pub fn on_consensus_packet(io: &mut SyncIo, peer_id: PeerId, r: &Rlp) {
 		trace!(target: "sync", "Received consensus packet from {:?}", peer_id);
@@ -112,19 +181,33 @@ impl SyncHandler {
 		if sync.peers.contains_key(&peer_id) {
 			debug!(target: "sync", "Disconnected {}", peer_id);
 			sync.clear_peer_download(peer_id);
+			sync.transactions_fetcher.on_peer_disconnected(peer_id);
 			sync.peers.remove(&peer_id);
 			sync.active_peers.remove(&peer_id);
+			if let Some(ref mut state_sync) = sync.state_sync {
+				state_sync.on_peer_disconnected(peer_id);
+			}
 
 			if sync.state == SyncState::SnapshotManifest {
 				// Check if we are asking other peers for
 				// the snapshot manifest as well.
 				// If not, return to initial state
 				let still_asking_manifest = sync.peers.iter()
-					.filter(|&(id, p)| sync.active_peers.contains(id) && p.asking == PeerAsking::SnapshotManifest)
+					.filter(|&(id, p)| sync.active_peers.contains(id) && p.requests.values().any(|r| r.asking == PeerAsking::SnapshotManifest))
 					.next().is_none();
 
 				if still_asking_manifest {
-					sync.state = ChainSync::get_init_state(sync.warp_sync, io.chain());
+					sync.state = ChainSync::get_init_state(sync.warp_sync, sync.sync_mode, io.chain());
+				}
+			}
+			if sync.state == SyncState::StatePivot {
+				// Pick the pivot from another peer unless it's being retrieved already
+				let still_asking_pivot = sync.peers.values().any(|p| p.requests.values().any(|r| match r.asking {
+					PeerAsking::PivotHeaders | PeerAsking::CheckpointHeaders | PeerAsking::PivotBody | PeerAsking::PivotReceipts => true,
+					_ => false,
+				}));
+				if !still_asking_pivot {
+					sync.state = SyncState::WaitingPeers;
 				}
 			}
 			sync.continue_sync(io);
@@ -278,31 +361,32 @@ impl SyncHandler {
 	}
 
 	/// Called by peer once it has new block bodies
-	fn on_peer_block_bodies(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, r: &Rlp) -> Result<(), DownloaderImportError> {
-		sync.clear_peer_download(peer_id);
-		let block_set = sync.peers.get(&peer_id)
-			.and_then(|p| p.block_set)
-			.unwrap_or(BlockSet::NewBlocks);
-		let allowed = sync.peers.get(&peer_id).map(|p| p.is_allowed()).unwrap_or(false);
-
-		if !sync.reset_peer_asking(peer_id, PeerAsking::BlockBodies) || !allowed {
-			trace!(target: "sync", "{}: Ignored unexpected bodies", peer_id);
-			return Ok(());
+	fn on_peer_block_bodies(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, request_id: Option<u64>, r: &Rlp) -> Result<(), DownloaderImportError> {
+		if SyncHandler::is_answering(sync, peer_id, request_id, PeerAsking::PivotBody) {
+			return SyncHandler::on_pivot_body(sync, io, peer_id, request_id, r);
 		}
-		let expected_blocks = match sync.peers.get_mut(&peer_id) {
-			Some(peer) => mem::replace(&mut peer.asking_blocks, Vec::new()),
+
+		let allowed = sync.peers.get(&peer_id).map(|p| p.is_allowed()).unwrap_or(false);
+		let request = match sync.take_peer_request(peer_id, request_id, PeerAsking::BlockBodies) {
+			Some(request) => request,
 			None => {
-				trace!(target: "sync", "{}: Ignored unexpected bodies (peer not found)", peer_id);
+				trace!(target: "sync", "{}: Ignored unexpected bodies", peer_id);
 				return Ok(());
 			}
 		};
+		if !allowed {
+			trace!(target: "sync", "{}: Ignored unexpected bodies (peer not allowed)", peer_id);
+			return Ok(());
+		}
+		let block_set = request.block_set.unwrap_or(BlockSet::NewBlocks);
 		let item_count = r.item_count()?;
+		if let Some(peer) = sync.peers.get_mut(&peer_id) {
+			peer.bodies_request_size.on_response(request.blocks.len(), item_count, request.time, Instant::now());
+		}
+		let expected_blocks = request.blocks;
 		trace!(target: "sync", "{} -> BlockBodies ({} entries), set = {:?}", peer_id, item_count, block_set);
 		if item_count == 0 {
 			Err(DownloaderImportError::Useless)
-		} else if sync.state == SyncState::Waiting {
-			trace!(target: "sync", "Ignored block bodies while waiting");
-			Ok(())
 		} else {
 			{
 				let downloader = match block_set {
@@ -317,7 +401,11 @@ impl SyncHandler {
 				};
 				downloader.import_bodies(r, expected_blocks.as_slice())?;
 			}
-			sync.collect_blocks(io, block_set);
+			if sync.state == SyncState::Waiting {
+				trace!(target: "sync", "Keeping block bodies until the block queue has room");
+			} else {
+				sync.collect_blocks(io, block_set);
+			}
 			Ok(())
 		}
 	}
@@ -325,7 +413,6 @@ impl SyncHandler {
 	fn on_peer_fork_header(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, r: &Rlp) -> Result<(), DownloaderImportError> {
 		{
 			let peer = sync.peers.get_mut(&peer_id).expect("Is only called when peer is present in peers");
-			peer.asking = PeerAsking::Nothing;
 			let item_count = r.item_count()?;
 			let (fork_number, fork_hash) = sync.fork_block.expect("ForkHeader request is sent only fork block is Some; qed").clone();
 
@@ -354,26 +441,28 @@ impl SyncHandler {
 	}
 
 	/// Called by peer once it has new block headers during sync
-	fn on_peer_block_headers(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, r: &Rlp) -> Result<(), DownloaderImportError> {
-		let is_fork_header_request = match sync.peers.get(&peer_id) {
-			Some(peer) if peer.asking == PeerAsking::ForkHeader => true,
-			_ => false,
-		};
-
-		if is_fork_header_request {
+	fn on_peer_block_headers(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, request_id: Option<u64>, r: &Rlp) -> Result<(), DownloaderImportError> {
+		if SyncHandler::is_answering(sync, peer_id, request_id, PeerAsking::ForkHeader) {
+			sync.take_peer_request(peer_id, request_id, PeerAsking::ForkHeader);
 			return SyncHandler::on_peer_fork_header(sync, io, peer_id, r);
 		}
+		if SyncHandler::is_answering(sync, peer_id, request_id, PeerAsking::PivotHeaders) {
+			return SyncHandler::on_pivot_headers(sync, io, peer_id, request_id, r);
+		}
+		if SyncHandler::is_answering(sync, peer_id, request_id, PeerAsking::CheckpointHeaders) {
+			return SyncHandler::on_checkpoint_headers(sync, io, peer_id, request_id, r);
+		}
 
-		sync.clear_peer_download(peer_id);
-		let expected_hash = sync.peers.get(&peer_id).and_then(|p| p.asking_hash);
 		let allowed = sync.peers.get(&peer_id).map(|p| p.is_allowed()).unwrap_or(false);
-		let block_set = sync.peers.get(&peer_id).and_then(|p| p.block_set).unwrap_or(BlockSet::NewBlocks);
-
-		if !sync.reset_peer_asking(peer_id, PeerAsking::BlockHeaders) {
-			debug!(target: "sync", "{}: Ignored unexpected headers", peer_id);
-			return Ok(());
-		}
-		let expected_hash = match expected_hash {
+		let request = match sync.take_peer_request(peer_id, request_id, PeerAsking::BlockHeaders) {
+			Some(request) => request,
+			None => {
+				debug!(target: "sync", "{}: Ignored unexpected headers", peer_id);
+				return Ok(());
+			}
+		};
+		let block_set = request.block_set.unwrap_or(BlockSet::NewBlocks);
+		let expected_hash = match request.hash {
 			Some(hash) => hash,
 			None => {
 				debug!(target: "sync", "{}: Ignored unexpected headers (expected_hash is None)", peer_id);
@@ -386,6 +475,9 @@ impl SyncHandler {
 		}
 
 		let item_count = r.item_count()?;
+		if let (Some(asked), Some(peer)) = (request.header_count, sync.peers.get_mut(&peer_id)) {
+			peer.headers_request_size.on_response(asked, item_count, request.time, Instant::now());
+		}
 		trace!(target: "sync", "{} -> BlockHeaders ({} entries), state = {:?}, set = {:?}", peer_id, item_count, sync.state, block_set);
 		if (sync.state == SyncState::Idle || sync.state == SyncState::WaitingPeers) && sync.old_blocks.is_none() {
 			trace!(target: "sync", "Ignored unexpected block headers");
@@ -421,28 +513,32 @@ impl SyncHandler {
 	}
 
 	/// Called by peer once it has new block receipts
-	fn on_peer_block_receipts(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, r: &Rlp) -> Result<(), DownloaderImportError> {
-		sync.clear_peer_download(peer_id);
-		let block_set = sync.peers.get(&peer_id).and_then(|p| p.block_set).unwrap_or(BlockSet::NewBlocks);
-		let allowed = sync.peers.get(&peer_id).map(|p| p.is_allowed()).unwrap_or(false);
-		if !sync.reset_peer_asking(peer_id, PeerAsking::BlockReceipts) || !allowed {
-			trace!(target: "sync", "{}: Ignored unexpected receipts", peer_id);
-			return Ok(());
+	fn on_peer_block_receipts(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, request_id: Option<u64>, r: &Rlp) -> Result<(), DownloaderImportError> {
+		if SyncHandler::is_answering(sync, peer_id, request_id, PeerAsking::PivotReceipts) {
+			return SyncHandler::on_pivot_receipts(sync, io, peer_id, request_id, r);
 		}
-		let expected_blocks = match sync.peers.get_mut(&peer_id) {
-			Some(peer) => mem::replace(&mut peer.asking_blocks, Vec::new()),
+
+		let allowed = sync.peers.get(&peer_id).map(|p| p.is_allowed()).unwrap_or(false);
+		let request = match sync.take_peer_request(peer_id, request_id, PeerAsking::BlockReceipts) {
+			Some(request) => request,
 			None => {
-				trace!(target: "sync", "{}: Ignored unexpected bodies (peer not found)", peer_id);
+				trace!(target: "sync", "{}: Ignored unexpected receipts", peer_id);
 				return Ok(());
 			}
 		};
+		if !allowed {
+			trace!(target: "sync", "{}: Ignored unexpected receipts (peer not allowed)", peer_id);
+			return Ok(());
+		}
+		let block_set = request.block_set.unwrap_or(BlockSet::NewBlocks);
 		let item_count = r.item_count()?;
+		if let Some(peer) = sync.peers.get_mut(&peer_id) {
+			peer.receipts_request_size.on_response(request.blocks.len(), item_count, request.time, Instant::now());
+		}
+		let expected_blocks = request.blocks;
 		trace!(target: "sync", "{} -> BlockReceipts ({} entries)", peer_id, item_count);
 		if item_count == 0 {
 			Err(DownloaderImportError::Useless)
-		} else if sync.state == SyncState::Waiting {
-			trace!(target: "sync", "Ignored block receipts while waiting");
-			Ok(())
 		} else {
 			{
 				let downloader = match block_set {
@@ -457,7 +553,11 @@ impl SyncHandler {
 				};
 				downloader.import_receipts(r, expected_blocks.as_slice())?;
 			}
-			sync.collect_blocks(io, block_set);
+			if sync.state == SyncState::Waiting {
+				trace!(target: "sync", "Keeping block receipts until the block queue has room");
+			} else {
+				sync.collect_blocks(io, block_set);
+			}
 			Ok(())
 		}
 	}
@@ -468,8 +568,7 @@ impl SyncHandler {
 			trace!(target: "sync", "Ignoring snapshot manifest from unconfirmed peer {}", peer_id);
 			return Ok(());
 		}
-		sync.clear_peer_download(peer_id);
-		if !sync.reset_peer_asking(peer_id, PeerAsking::SnapshotManifest) || sync.state != SyncState::SnapshotManifest {
+		if sync.take_peer_request(peer_id, None, PeerAsking::SnapshotManifest).is_none() || sync.state != SyncState::SnapshotManifest {
 			trace!(target: "sync", "{}: Ignored unexpected/expired manifest", peer_id);
 			return Ok(());
 		}
@@ -484,6 +583,15 @@ impl SyncHandler {
 			trace!(target: "sync", "{}: Snapshot manifest version not supported: {}", peer_id, manifest.version);
 			return Err(DownloaderImportError::Invalid);
 		}
+		if let Some(checkpoint) = sync.pending_checkpoint(io.chain()) {
+			// Later snapshots are checked against the checkpoint as the ancient blocks come in
+			let before_checkpoint = manifest.block_number < checkpoint.number;
+			if before_checkpoint || (manifest.block_number == checkpoint.number && manifest.block_hash != checkpoint.hash) {
+				trace!(target: "sync", "{}: Snapshot of #{} doesn't match the checkpoint {}", peer_id, manifest.block_number, checkpoint);
+				sync.snapshot.note_bad(keccak(manifest_rlp.as_raw()));
+				return Err(DownloaderImportError::Invalid);
+			}
+		}
 		sync.snapshot.reset_to(&manifest, &keccak(manifest_rlp.as_raw()));
 		io.snapshot_service().begin_restore(manifest);
 		sync.state = SyncState::SnapshotData;
@@ -497,8 +605,7 @@ impl SyncHandler {
 			trace!(target: "sync", "Ignoring snapshot data from unconfirmed peer {}", peer_id);
 			return Ok(());
 		}
-		sync.clear_peer_download(peer_id);
-		if !sync.reset_peer_asking(peer_id, PeerAsking::SnapshotData) || (sync.state != SyncState::SnapshotData && sync.state != SyncState::SnapshotWaiting) {
+		if sync.take_peer_request(peer_id, None, PeerAsking::SnapshotData).is_none() || (sync.state != SyncState::SnapshotData && sync.state != SyncState::SnapshotWaiting) {
 			trace!(target: "sync", "{}: Ignored unexpected snapshot data", peer_id);
 			return Ok(());
 		}
@@ -540,6 +647,7 @@ impl SyncHandler {
 			}
 			Err(()) => {
 				trace!(target: "sync", "{}: Got bad snapshot chunk", peer_id);
+				sync.note_conduct(io, peer_id, Conduct::Useless);
 				io.disconnect_peer(peer_id);
 				return Ok(());
 			}
@@ -553,6 +661,250 @@ impl SyncHandler {
 		Ok(())
 	}
 
+	/// Called by peer once it has the headers the state sync pivot is picked from.
+	fn on_pivot_headers(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, request_id: Option<u64>, r: &Rlp) -> Result<(), DownloaderImportError> {
+		if sync.take_peer_request(peer_id, request_id, PeerAsking::PivotHeaders).is_none() || sync.state != SyncState::StatePivot {
+			trace!(target: "sync", "{}: Ignored unexpected pivot headers", peer_id);
+			return Ok(());
+		}
+		// Pick the pivot again unless these headers lead to it
+		sync.state = SyncState::WaitingPeers;
+
+		let item_count = r.item_count()?;
+		trace!(target: "sync", "{} -> PivotHeaders ({} entries)", peer_id, item_count);
+		if item_count == 0 {
+			return Err(DownloaderImportError::Useless);
+		}
+		let headers: Vec<Header> = r.as_list()?;
+		let (number, hash) = match sync.state_sync.as_mut().and_then(|s| s.on_pivot_headers(headers)) {
+			Some(pivot) => (pivot.number(), pivot.hash()),
+			None => {
+				trace!(target: "sync", "{}: Invalid pivot headers", peer_id);
+				return Err(DownloaderImportError::Invalid);
+			},
+		};
+
+		let best_block = io.chain().chain_info().best_block_number;
+		match sync.state_sync.as_ref().and_then(|s| s.pivot()).map(|pivot| pivot.number()) {
+			None if number <= best_block + SNAPSHOT_RESTORE_THRESHOLD => {
+				trace!(target: "sync", "Pivot #{} is close to our best block, starting full sync", number);
+				sync.state_sync = None;
+				sync.sync_mode = SyncMode::Full;
+				sync.state = SyncState::Idle;
+				sync.continue_sync(io);
+			},
+			Some(current) if number <= current => {
+				trace!(target: "sync", "Pivot #{} is not ahead of #{}, resuming state sync", number, current);
+				if let Some(ref mut state_sync) = sync.state_sync {
+					state_sync.cancel_pivot();
+				}
+				sync.state = SyncState::StateData;
+			},
+			_ => {
+				SyncRequester::request_pivot_body(sync, io, peer_id, &hash);
+				sync.state = SyncState::StatePivot;
+			},
+		}
+		Ok(())
+	}
+
+	/// Called by peer once it has headers back from the checkpoint.
+	fn on_checkpoint_headers(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, request_id: Option<u64>, r: &Rlp) -> Result<(), DownloaderImportError> {
+		if sync.take_peer_request(peer_id, request_id, PeerAsking::CheckpointHeaders).is_none() || sync.state != SyncState::StatePivot {
+			trace!(target: "sync", "{}: Ignored unexpected checkpoint headers", peer_id);
+			return Ok(());
+		}
+		sync.state = SyncState::WaitingPeers;
+
+		let item_count = r.item_count()?;
+		trace!(target: "sync", "{} -> CheckpointHeaders ({} entries)", peer_id, item_count);
+		if item_count == 0 {
+			return Err(DownloaderImportError::Useless);
+		}
+		let headers: Vec<Header> = r.as_list()?;
+		if !sync.checkpoint_headers.as_mut().map_or(false, |download| download.on_headers(headers)) {
+			trace!(target: "sync", "{}: Invalid checkpoint headers", peer_id);
+			return Err(DownloaderImportError::Invalid);
+		}
+		trace!(target: "sync", "{} checkpoint headers left", sync.checkpoint_headers.as_ref().map_or(0, |d| d.remaining()));
+		sync.maybe_start_state_sync(io);
+		Ok(())
+	}
+
+	/// Called by peer once it has the body of the state sync pivot.
+	fn on_pivot_body(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, request_id: Option<u64>, r: &Rlp) -> Result<(), DownloaderImportError> {
+		if sync.take_peer_request(peer_id, request_id, PeerAsking::PivotBody).is_none() || sync.state != SyncState::StatePivot {
+			trace!(target: "sync", "{}: Ignored unexpected pivot body", peer_id);
+			return Ok(());
+		}
+		sync.state = SyncState::WaitingPeers;
+
+		let item_count = r.item_count()?;
+		trace!(target: "sync", "{} -> PivotBody ({} entries)", peer_id, item_count);
+		if item_count == 0 {
+			return Err(DownloaderImportError::Useless);
+		}
+		let body = r.at(0)?;
+		let hash = sync.state_sync.as_mut().and_then(|s| if s.on_pivot_body(body.as_raw()) {
+			s.next_pivot().map(|pivot| pivot.hash())
+		} else {
+			None
+		});
+		match hash {
+			Some(hash) => {
+				SyncRequester::request_pivot_receipts(sync, io, peer_id, &hash);
+				sync.state = SyncState::StatePivot;
+				Ok(())
+			},
+			None => {
+				trace!(target: "sync", "{}: Invalid pivot body", peer_id);
+				Err(DownloaderImportError::Invalid)
+			},
+		}
+	}
+
+	/// Called by peer once it has the receipts of the state sync pivot.
+	fn on_pivot_receipts(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, request_id: Option<u64>, r: &Rlp) -> Result<(), DownloaderImportError> {
+		if sync.take_peer_request(peer_id, request_id, PeerAsking::PivotReceipts).is_none() || sync.state != SyncState::StatePivot {
+			trace!(target: "sync", "{}: Ignored unexpected pivot receipts", peer_id);
+			return Ok(());
+		}
+		sync.state = SyncState::WaitingPeers;
+
+		let item_count = r.item_count()?;
+		trace!(target: "sync", "{} -> PivotReceipts ({} entries)", peer_id, item_count);
+		if item_count == 0 {
+			return Err(DownloaderImportError::Useless);
+		}
+		let receipts = r.at(0)?;
+		match sync.state_sync.as_mut().and_then(|s| s.on_pivot_receipts(receipts.as_raw(), Instant::now())) {
+			Some((block, total_difficulty)) => {
+				trace!(target: "sync", "{}: Downloading state of the new pivot", peer_id);
+				io.snapshot_service().begin_state_restore(block, receipts.as_raw().to_vec(), total_difficulty);
+				sync.state = SyncState::StateData;
+				Ok(())
+			},
+			None => {
+				trace!(target: "sync", "{}: Invalid pivot receipts", peer_id);
+				Err(DownloaderImportError::Invalid)
+			},
+		}
+	}
+
+	/// Called when a peer delivers a proven range of accounts or of an account's storage.
+	fn on_state_range(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, asking: PeerAsking, r: &Rlp) -> Result<(), DownloaderImportError> {
+		if !sync.peers.get(&peer_id).map_or(false, |p| p.can_sync()) {
+			trace!(target: "sync", "Ignoring state range from unconfirmed peer {}", peer_id);
+			return Ok(());
+		}
+		let range = match sync.take_peer_request(peer_id, None, asking.clone()).and_then(|request| request.state_range) {
+			Some(ref range) if sync.state == SyncState::StateData => range.clone(),
+			_ => {
+				trace!(target: "sync", "{}: Ignored unexpected state range", peer_id);
+				return Ok(());
+			},
+		};
+
+		let entries = r.at(0)?.iter()
+			.map(|entry| Ok((entry.val_at(0)?, entry.val_at(1)?)))
+			.collect::<Result<Vec<(H256, Bytes)>, DecoderError>>()?;
+		let proof: Vec<Bytes> = r.at(1)?.as_list()?;
+		trace!(target: "sync", "{} -> {:?} ({} entries)", peer_id, asking, entries.len());
+		if entries.is_empty() && proof.is_empty() {
+			trace!(target: "sync", "{}: Pivot state is not available", peer_id);
+			SyncHandler::note_stale_peer(sync, peer_id);
+			return Ok(());
+		}
+
+		let more = match verify_range(&range.root(), &range.origin(), &entries, &proof) {
+			Ok(more) => more,
+			Err(e) => {
+				debug!(target: "sync", "{}: Invalid state range: {}", peer_id, e);
+				return Err(DownloaderImportError::Invalid);
+			},
+		};
+		let limit = range.limit();
+		let entries: Vec<_> = entries.into_iter().take_while(|&(ref key, _)| *key <= limit).collect();
+		let last = entries.last().map(|&(key, _)| key);
+
+		let state_sync = match sync.state_sync {
+			Some(ref mut state_sync) => state_sync,
+			None => return Ok(()),
+		};
+		if !state_sync.on_range_delivered(&range, last, more) {
+			trace!(target: "sync", "{}: State range is not needed anymore", peer_id);
+			return Ok(());
+		}
+		let data = match range {
+			StateRange::Accounts { .. } => {
+				for &(ref hash, ref account) in &entries {
+					let storage_root = Rlp::new(account).as_val::<BasicAccount>()?.storage_root;
+					if storage_root != KECCAK_NULL_RLP {
+						state_sync.add_storage_task(*hash, account.clone(), storage_root);
+					}
+				}
+				StateSyncData::Accounts(entries)
+			},
+			StateRange::Storage { account_hash, account, .. } => StateSyncData::Storage(account_hash, account, entries),
+		};
+		io.snapshot_service().restore_state_data(data);
+		if state_sync.take_ranges_done() {
+			trace!(target: "sync", "All state ranges are downloaded, healing the state");
+			io.snapshot_service().restore_state_data(StateSyncData::RangesDone);
+		}
+		Ok(())
+	}
+
+	/// Notes a peer doesn't have the pivot state anymore. Once no peer serving state ranges has
+	/// it, a newer pivot is picked.
+	fn note_stale_peer(sync: &mut ChainSync, peer_id: PeerId) {
+		let all_stale = match sync.state_sync {
+			Some(ref mut state_sync) => {
+				state_sync.note_stale(peer_id);
+				!state_sync.ranges_done() && sync.peers.iter()
+					.filter(|&(_, p)| p.serves_state_ranges())
+					.all(|(id, _)| state_sync.is_stale(*id))
+			},
+			None => false,
+		};
+		if all_stale {
+			trace!(target: "sync", "Pivot state is stale, picking a new pivot");
+			sync.state = SyncState::WaitingPeers;
+		}
+	}
+
+	/// Called by peer once it has trie nodes and codes missing from the state restoration.
+	fn on_peer_node_data(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, request_id: Option<u64>, r: &Rlp) -> Result<(), DownloaderImportError> {
+		let request = match sync.take_peer_request(peer_id, request_id, PeerAsking::NodeData) {
+			Some(ref request) if sync.state == SyncState::StateData => request.clone(),
+			_ => {
+				trace!(target: "sync", "{}: Ignored unexpected node data", peer_id);
+				return Ok(());
+			},
+		};
+		let item_count = r.item_count()?;
+		trace!(target: "sync", "{} -> NodeData ({} entries)", peer_id, item_count);
+		if item_count == 0 {
+			return Err(DownloaderImportError::Useless);
+		}
+
+		let mut hashes = Vec::with_capacity(item_count);
+		let mut nodes = Vec::with_capacity(item_count);
+		for item in r.iter() {
+			let node: Bytes = item.as_val()?;
+			let hash = keccak(&node);
+			if request.blocks.contains(&hash) {
+				hashes.push(hash);
+				nodes.push(node);
+			}
+		}
+		if let Some(ref mut state_sync) = sync.state_sync {
+			state_sync.on_nodes_delivered(&hashes, Instant::now());
+		}
+		io.snapshot_service().restore_state_data(StateSyncData::Nodes(nodes));
+		Ok(())
+	}
+
 	/// Called by peer to report status
 	fn on_peer_status(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, r: &Rlp) -> Result<(), DownloaderImportError> {
 		sync.handshaking_peers.remove(&peer_id);
@@ -564,22 +916,27 @@ impl SyncHandler {
 			difficulty: Some(r.val_at(2)?),
 			latest_hash: r.val_at(3)?,
 			genesis: r.val_at(4)?,
-			asking: PeerAsking::Nothing,
-			asking_blocks: Vec::new(),
-			asking_hash: None,
-			ask_time: Instant::now(),
+			request_ids: io.eth_protocol_version(peer_id) >= ETH_PROTOCOL_VERSION_66.0,
+			requests: BTreeMap::new(),
+			next_request_id: 0,
 			last_sent_transactions: Default::default(),
 			last_sent_private_transactions: Default::default(),
-			expired: false,
 			confirmation: if sync.fork_block.is_none() { ForkConfirmation::Confirmed } else { ForkConfirmation::Unconfirmed },
-			asking_snapshot_data: None,
 			snapshot_hash: if warp_protocol { Some(r.val_at(5)?) } else { None },
 			snapshot_number: if warp_protocol { Some(r.val_at(6)?) } else { None },
-			block_set: None,
+			node_id: io.peer_session_info(peer_id).and_then(|info| info.id),
+			headers_request_size: RequestSize::headers(),
+			bodies_request_size: RequestSize::bodies(),
+			receipts_request_size: RequestSize::receipts(),
+		};
+		let fork_id: Option<ForkId> = if io.eth_protocol_version(peer_id) >= ETH_PROTOCOL_VERSION_64.0 {
+			Some(r.val_at(if warp_protocol { 7 } else { 5 })?)
+		} else {
+			None
 		};
 
-		trace!(target: "sync", "New peer {} (protocol: {}, network: {:?}, difficulty: {:?}, latest:{}, genesis:{}, snapshot:{:?})",
-			peer_id, peer.protocol_version, peer.network_id, peer.difficulty, peer.latest_hash, peer.genesis, peer.snapshot_number);
+		trace!(target: "sync", "New peer {} (protocol: {}, network: {:?}, difficulty: {:?}, latest:{}, genesis:{}, snapshot:{:?}, fork id:{:?})",
+			peer_id, peer.protocol_version, peer.network_id, peer.difficulty, peer.latest_hash, peer.genesis, peer.snapshot_number, fork_id);
 		if io.is_expired() {
 			trace!(target: "sync", "Status packet from expired session {}:{}", peer_id, io.peer_info(peer_id));
 			return Ok(());
@@ -589,6 +946,11 @@ impl SyncHandler {
 			debug!(target: "sync", "Unexpected status packet from {}:{}", peer_id, io.peer_info(peer_id));
 			return Ok(());
 		}
+		if peer.node_id.map_or(false, |id| sync.reputation.is_banned(&id)) {
+			trace!(target: "sync", "Peer {} is banned", peer_id);
+			io.disable_peer(peer_id);
+			return Ok(());
+		}
 		let chain_info = io.chain().chain_info();
 		if peer.genesis != chain_info.genesis_hash {
 			trace!(target: "sync", "Peer {} genesis hash mismatch (ours: {}, theirs: {})", peer_id, chain_info.genesis_hash, peer.genesis);
@@ -598,10 +960,17 @@ impl SyncHandler {
 			trace!(target: "sync", "Peer {} network id mismatch (ours: {}, theirs: {})", peer_id, sync.network_id, peer.network_id);
 			return Err(DownloaderImportError::Invalid);
 		}
+		if let Some(fork_id) = fork_id {
+			if let Err(e) = sync.fork_filter.validate(chain_info.best_block_number, &fork_id) {
+				trace!(target: "sync", "Peer {} fork id mismatch (ours: {}, theirs: {}): {}",
+					peer_id, sync.fork_filter.current(chain_info.best_block_number), fork_id, e);
+				return Err(DownloaderImportError::Invalid);
+			}
+		}
 
 		if false
-			|| (warp_protocol && (peer.protocol_version < PAR_PROTOCOL_VERSION_1.0 || peer.protocol_version > PAR_PROTOCOL_VERSION_3.0))
-			|| (!warp_protocol && (peer.protocol_version < ETH_PROTOCOL_VERSION_62.0 || peer.protocol_version > ETH_PROTOCOL_VERSION_63.0))
+			|| (warp_protocol && (peer.protocol_version < PAR_PROTOCOL_VERSION_1.0 || peer.protocol_version > PAR_PROTOCOL_VERSION_4.0))
+			|| (!warp_protocol && (peer.protocol_version < ETH_PROTOCOL_VERSION_62.0 || peer.protocol_version > ETH_PROTOCOL_VERSION_66.0))
 		{
 			trace!(target: "sync", "Peer {} unsupported eth protocol ({})", peer_id, peer.protocol_version);
 			return Err(DownloaderImportError::Invalid);
@@ -648,6 +1017,75 @@ impl SyncHandler {
 		Ok(())
 	}
 
+	/// Called when peer announces transactions from its pool
+	fn on_peer_new_pooled_transaction_hashes(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, r: &Rlp) -> Result<(), DownloaderImportError> {
+		if io.eth_protocol_version(peer_id) < ETH_PROTOCOL_VERSION_65.0 {
+			trace!(target: "sync", "{} Announced transactions without eth/65", peer_id);
+			return Err(DownloaderImportError::Invalid);
+		}
+		// Accept transactions only when fully synced
+		if !io.is_chain_queue_empty() || (sync.state != SyncState::Idle && sync.state != SyncState::NewBlocks) {
+			trace!(target: "sync", "{} Ignoring announced transactions while syncing", peer_id);
+			return Ok(());
+		}
+		if !sync.peers.get(&peer_id).map_or(false, |p| p.can_sync()) {
+			trace!(target: "sync", "{} Ignoring announced transactions from unconfirmed/unknown peer", peer_id);
+			return Ok(());
+		}
+
+		let hashes: Vec<H256> = r.as_list()?;
+		trace!(target: "sync", "{:02} -> NewPooledTransactionHashes ({} entries)", peer_id, hashes.len());
+		if let Some(peer) = sync.peers.get_mut(&peer_id) {
+			peer.last_sent_transactions.extend(hashes.iter().cloned());
+		}
+		let queued = {
+			let chain = io.chain();
+			sync.transactions_fetcher.on_announced(peer_id, &hashes, |hash| chain.pooled_transaction(hash).is_some())
+		};
+		if queued > 0 {
+			SyncRequester::request_pooled_transactions(sync, io, peer_id);
+		}
+		Ok(())
+	}
+
+	/// Called when peer delivers requested transactions
+	fn on_peer_pooled_transactions(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, r: &Rlp) -> Result<(), DownloaderImportError> {
+		if io.eth_protocol_version(peer_id) < ETH_PROTOCOL_VERSION_65.0 {
+			trace!(target: "sync", "{} Delivered pooled transactions without eth/65", peer_id);
+			return Err(DownloaderImportError::Invalid);
+		}
+		let item_count = r.item_count()?;
+		trace!(target: "sync", "{:02} -> PooledTransactions ({} entries)", peer_id, item_count);
+		let mut transactions = Vec::with_capacity(item_count);
+		let mut hashes = Vec::with_capacity(item_count);
+		for i in 0 .. item_count {
+			let tx = r.at(i)?.as_raw().to_vec();
+			hashes.push(keccak(&tx));
+			transactions.push(tx);
+		}
+
+		let requested = match sync.transactions_fetcher.on_delivered(peer_id, &hashes) {
+			Some(requested) => requested,
+			None => {
+				trace!(target: "sync", "{}: Ignoring unexpected pooled transactions", peer_id);
+				return Ok(());
+			},
+		};
+		if hashes.iter().any(|hash| !requested.contains(hash)) {
+			trace!(target: "sync", "{}: Delivered transactions that weren't requested", peer_id);
+			return Err(DownloaderImportError::Invalid);
+		}
+
+		if let Some(peer) = sync.peers.get_mut(&peer_id) {
+			peer.last_sent_transactions.extend(hashes.iter().cloned());
+		}
+		if !transactions.is_empty() {
+			io.chain().queue_transactions(transactions, peer_id);
+		}
+		SyncRequester::request_pooled_transactions(sync, io, peer_id);
+		Ok(())
+	}
+
 	/// Called when peer sends us signed private transaction packet
 	fn on_signed_private_transaction(sync: &mut ChainSync, _io: &mut SyncIo, peer_id: PeerId, r: &Rlp) -> Result<(), DownloaderImportError> {
 		if !sync.peers.get(&peer_id).map_or(false, |p| p.can_sync()) {
@@ -703,7 +1141,12 @@ mod tests {
 	use tests::helpers::{TestIo};
 	use tests::snapshot::TestSnapshotService;
 
+	use ethkey::{Generator, Random};
+	use rlp::RlpStream;
+	use transaction::Transaction;
+
 	use super::*;
+	use super::super::GET_POOLED_TRANSACTIONS_PACKET;
 	use super::super::tests::{
 		dummy_sync_with_peer,
 		get_dummy_block,
@@ -728,6 +1171,40 @@ mod tests {
 		assert!(result.is_ok());
 	}
 
+	#[test]
+	fn validates_fork_id_of_warp_peers() {
+		let mut client = TestBlockChainClient::new();
+		client.add_blocks(10, EachBlockWith::Nothing);
+		let chain = client.chain_info();
+		let queue = RwLock::new(VecDeque::new());
+		let mut sync = dummy_sync_with_peer(client.block_hash_delta_minus(5), &client);
+		let ss = TestSnapshotService::new();
+		let mut io = TestIo::new(&mut client, &ss, &queue, None);
+		io.eth_protocol_version = ETH_PROTOCOL_VERSION_64.0;
+
+		let ours = sync.fork_filter.current(chain.best_block_number);
+		let theirs = ForkId { hash: ours.hash ^ 1, next: 0 };
+		let network_id = sync.network_id;
+		let status = |fork_id: &ForkId| {
+			let mut packet = RlpStream::new_list(8);
+			packet.append(&(PAR_PROTOCOL_VERSION_4.0 as u32));
+			packet.append(&network_id);
+			packet.append(&chain.total_difficulty);
+			packet.append(&chain.best_block_hash);
+			packet.append(&chain.genesis_hash);
+			packet.append(&H256::new());
+			packet.append(&0u64);
+			packet.append(fork_id);
+			packet.out()
+		};
+		let (matching, mismatching) = (status(&ours), status(&theirs));
+
+		assert!(SyncHandler::on_peer_status(&mut sync, &mut io, 1, &Rlp::new(&matching)).is_ok());
+		assert!(sync.peers.contains_key(&1));
+		assert!(SyncHandler::on_peer_status(&mut sync, &mut io, 2, &Rlp::new(&mismatching)).is_err());
+		assert!(!sync.peers.contains_key(&2));
+	}
+
 	#[test]
 	fn handles_peer_new_block_malformed() {
 		let mut client = TestBlockChainClient::new();
@@ -798,4 +1275,53 @@ mod tests {
 
 		assert!(result.is_ok());
 	}
+
+	#[test]
+	fn requests_announced_transactions() {
+		let mut client = TestBlockChainClient::new();
+		client.add_blocks(10, EachBlockWith::Uncle);
+		let queue = RwLock::new(VecDeque::new());
+		let mut sync = dummy_sync_with_peer(client.block_hash_delta_minus(5), &client);
+		sync.state = SyncState::Idle;
+		let ss = TestSnapshotService::new();
+		let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+		let keypair = Random.generate().unwrap();
+		let tx = Transaction::default().sign(keypair.secret(), None);
+		let other = Transaction { nonce: 1.into(), ..Default::default() }.sign(keypair.secret(), None);
+		let announce = |hashes: &[H256]| {
+			let mut rlp = RlpStream::new_list(hashes.len());
+			for hash in hashes {
+				rlp.append(hash);
+			}
+			rlp.out()
+		};
+		let deliver = |tx: &::transaction::SignedTransaction| {
+			let mut rlp = RlpStream::new_list(1);
+			rlp.append(tx);
+			rlp.out()
+		};
+
+		// Peers which didn't negotiate eth/65 can't announce transactions.
+		assert!(SyncHandler::on_peer_new_pooled_transaction_hashes(&mut sync, &mut io, 0, &Rlp::new(&announce(&[tx.hash()]))).is_err());
+		assert!(io.packets.is_empty());
+
+		io.eth_protocol_version = ETH_PROTOCOL_VERSION_65.0;
+		SyncHandler::on_peer_new_pooled_transaction_hashes(&mut sync, &mut io, 0, &Rlp::new(&announce(&[tx.hash(), 1.into()]))).unwrap();
+		assert_eq!(io.packets.len(), 1);
+		assert_eq!(io.packets[0].packet_id, GET_POOLED_TRANSACTIONS_PACKET);
+		assert_eq!(Rlp::new(&io.packets[0].data).as_list::<H256>().unwrap(), vec![tx.hash(), 1.into()]);
+
+		// Only one request is pending at a time.
+		SyncHandler::on_peer_new_pooled_transaction_hashes(&mut sync, &mut io, 0, &Rlp::new(&announce(&[2.into()]))).unwrap();
+		assert_eq!(io.packets.len(), 1);
+
+		// A delivery completes the request and the next one follows.
+		SyncHandler::on_peer_pooled_transactions(&mut sync, &mut io, 0, &Rlp::new(&deliver(&tx))).unwrap();
+		assert_eq!(io.packets.len(), 2);
+		assert_eq!(Rlp::new(&io.packets[1].data).as_list::<H256>().unwrap(), vec![H256::from(2)]);
+
+		// Transactions that weren't requested are rejected.
+		assert!(SyncHandler::on_peer_pooled_transactions(&mut sync, &mut io, 0, &Rlp::new(&deliver(&other))).is_err());
+	}
 }
@@ -16,7 +16,7 @@
 
 //! `BlockChain` synchronization strategy.
 //! Syncs to peers and keeps up to date.
-//! This implementation uses ethereum protocol v63
+//! This implementation uses ethereum protocol v65
 //!
 //! Syncing strategy summary.
 //! Split the chain into ranges of N blocks each. Download ranges sequentially. Split each range into subchains of M blocks. Download subchains in parallel.
@@ -102,19 +102,25 @@ use ethereum_types::{H256, U256};
 use fastmap::{H256FastMap, H256FastSet};
 use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
 use bytes::Bytes;
-use rlp::{RlpStream, DecoderError};
-use network::{self, PeerId, PacketId};
+use rlp::{Rlp, RlpStream, DecoderError};
+use network::{self, PeerId, PacketId, NodeId};
 use ethcore::header::{BlockNumber};
 use ethcore::client::{BlockChainClient, BlockStatus, BlockId, BlockChainInfo, BlockQueueInfo};
 use ethcore::snapshot::{RestorationStatus};
 use sync_io::SyncIo;
-use super::{WarpSync, SyncConfig};
+use super::{WarpSync, SyncConfig, SyncMode};
 use block_sync::{BlockDownloader, DownloadAction};
 use rand::Rng;
 use snapshot::{Snapshot};
+use fork_id::ForkFilter;
 use api::{EthProtocolInfo as PeerInfoDigest, WARP_SYNC_PROTOCOL_ID, PriorityTask};
 use private_tx::PrivateTxHandler;
 use transactions_stats::{TransactionsStats, Stats as TransactionStats};
+use transactions_fetcher::TransactionsFetcher;
+use state_sync::{StateSync, StateRange};
+use checkpoint::{Checkpoint, CheckpointHeaders};
+use reputation::{Reputation, Conduct};
+use request_size::RequestSize;
 use transaction::UnverifiedTransaction;
 
 use self::handler::SyncHandler;
@@ -126,6 +132,12 @@ known_heap_size!(0, PeerInfo);
 
 pub type PacketDecodeError = DecoderError;
 
+/// 66 version of Ethereum protocol (request ids added to requests and responses).
+pub const ETH_PROTOCOL_VERSION_66: (u8, u8) = (66, 0x11);
+/// 65 version of Ethereum protocol (transaction announcement and retrieval added).
+pub const ETH_PROTOCOL_VERSION_65: (u8, u8) = (65, 0x11);
+/// 64 version of Ethereum protocol (EIP-2124 fork identifier added to status).
+pub const ETH_PROTOCOL_VERSION_64: (u8, u8) = (64, 0x11);
 /// 63 version of Ethereum protocol.
 pub const ETH_PROTOCOL_VERSION_63: (u8, u8) = (63, 0x11);
 /// 62 version of Ethereum protocol.
@@ -136,12 +148,17 @@ pub const PAR_PROTOCOL_VERSION_1: (u8, u8) = (1, 0x15);
 pub const PAR_PROTOCOL_VERSION_2: (u8, u8) = (2, 0x16);
 /// 3 version of Parity protocol (private transactions messages added).
 pub const PAR_PROTOCOL_VERSION_3: (u8, u8) = (3, 0x18);
+/// 4 version of Parity protocol (state range proofs added).
+pub const PAR_PROTOCOL_VERSION_4: (u8, u8) = (4, 0x1c);
 
 pub const MAX_BODIES_TO_SEND: usize = 256;
 pub const MAX_HEADERS_TO_SEND: usize = 512;
 pub const MAX_NODE_DATA_TO_SEND: usize = 1024;
 pub const MAX_RECEIPTS_TO_SEND: usize = 1024;
 pub const MAX_RECEIPTS_HEADERS_TO_SEND: usize = 256;
+pub const MAX_POOLED_TRANSACTIONS_TO_SEND: usize = 256;
+pub const MAX_RANGE_BYTES_TO_SEND: usize = 512 * 1024;
+const MAX_TRANSACTION_HASHES_TO_ANNOUNCE: usize = 4096;
 const MIN_PEERS_PROPAGATION: usize = 4;
 const MAX_PEERS_PROPAGATION: usize = 128;
 const MAX_PEER_LAG_PROPAGATION: BlockNumber = 20;
@@ -162,6 +179,9 @@ pub const BLOCK_HEADERS_PACKET: u8 = 0x04;
 pub const GET_BLOCK_BODIES_PACKET: u8 = 0x05;
 const BLOCK_BODIES_PACKET: u8 = 0x06;
 const NEW_BLOCK_PACKET: u8 = 0x07;
+const NEW_POOLED_TRANSACTION_HASHES_PACKET: u8 = 0x08;
+const GET_POOLED_TRANSACTIONS_PACKET: u8 = 0x09;
+const POOLED_TRANSACTIONS_PACKET: u8 = 0x0a;
 
 pub const GET_NODE_DATA_PACKET: u8 = 0x0d;
 pub const NODE_DATA_PACKET: u8 = 0x0e;
@@ -175,8 +195,14 @@ pub const SNAPSHOT_DATA_PACKET: u8 = 0x14;
 pub const CONSENSUS_DATA_PACKET: u8 = 0x15;
 pub const PRIVATE_TRANSACTION_PACKET: u8 = 0x16;
 pub const SIGNED_PRIVATE_TRANSACTION_PACKET: u8 = 0x17;
+pub const GET_ACCOUNT_RANGE_PACKET: u8 = 0x18;
+pub const ACCOUNT_RANGE_PACKET: u8 = 0x19;
+pub const GET_STORAGE_RANGE_PACKET: u8 = 0x1a;
+pub const STORAGE_RANGE_PACKET: u8 = 0x1b;
 
 const MAX_SNAPSHOT_CHUNKS_DOWNLOAD_AHEAD: usize = 3;
+/// Maximum number of requests in flight to a peer that supports request ids.
+const MAX_PEER_REQUESTS: usize = 4;
 
 const WAIT_PEERS_TIMEOUT: Duration = Duration::from_secs(5);
 const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
@@ -186,6 +212,8 @@ const RECEIPTS_TIMEOUT: Duration = Duration::from_secs(10);
 const FORK_HEADER_TIMEOUT: Duration = Duration::from_secs(3);
 const SNAPSHOT_MANIFEST_TIMEOUT: Duration = Duration::from_secs(5);
 const SNAPSHOT_DATA_TIMEOUT: Duration = Duration::from_secs(120);
+const STATE_RANGE_TIMEOUT: Duration = Duration::from_secs(20);
+const NODE_DATA_TIMEOUT: Duration = Duration::from_secs(15);
 
 /// Defines how much time we have to complete priority transaction or block propagation.
 /// after the deadline is reached the task is considered finished
@@ -203,6 +231,10 @@ pub enum SyncState {
 	SnapshotData,
 	/// Waiting for snapshot restoration progress.
 	SnapshotWaiting,
+	/// Retrieving the pivot block for state sync
+	StatePivot,
+	/// Downloading the state of the pivot block
+	StateData,
 	/// Downloading new blocks
 	Blocks,
 	/// Initial chain sync complete. Waiting for new packets
@@ -252,7 +284,9 @@ impl SyncStatus {
 		match self.state {
 			SyncState::SnapshotManifest |
 				SyncState::SnapshotData |
-				SyncState::SnapshotWaiting => true,
+				SyncState::SnapshotWaiting |
+				SyncState::StatePivot |
+				SyncState::StateData => true,
 			_ => false,
 		}
 	}
@@ -277,13 +311,19 @@ impl SyncStatus {
 #[derive(PartialEq, Eq, Debug, Clone)]
 /// Peer data type requested
 pub enum PeerAsking {
-	Nothing,
 	ForkHeader,
 	BlockHeaders,
 	BlockBodies,
 	BlockReceipts,
 	SnapshotManifest,
 	SnapshotData,
+	PivotHeaders,
+	CheckpointHeaders,
+	PivotBody,
+	PivotReceipts,
+	AccountRange,
+	StorageRange,
+	NodeData,
 }
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
@@ -304,6 +344,45 @@ pub enum ForkConfirmation {
 	Confirmed,
 }
 
+#[derive(Clone)]
+/// Request sent to a peer and not answered yet.
+pub struct PeerRequest {
+	/// Type of data requested.
+	asking: PeerAsking,
+	/// Hashes of the blocks whose bodies or receipts are requested.
+	blocks: Vec<H256>,
+	/// Hash of the first requested header if requesting block headers by hash.
+	hash: Option<H256>,
+	/// Number of headers requested, if consecutive headers of a subchain are requested.
+	header_count: Option<usize>,
+	/// Hash of the requested snapshot chunk.
+	snapshot_data: Option<H256>,
+	/// Requested account or storage range.
+	state_range: Option<StateRange>,
+	/// Block set the request is made for.
+	block_set: Option<BlockSet>,
+	/// Request timestamp
+	time: Instant,
+	/// Request is expired and the response should be ignored.
+	expired: bool,
+}
+
+impl PeerRequest {
+	fn new(asking: PeerAsking) -> Self {
+		PeerRequest {
+			asking,
+			blocks: Vec::new(),
+			hash: None,
+			header_count: None,
+			snapshot_data: None,
+			state_range: None,
+			block_set: None,
+			time: Instant::now(),
+			expired: false,
+		}
+	}
+}
+
 #[derive(Clone)]
 /// Syncing peer information
 pub struct PeerInfo {
@@ -317,47 +396,87 @@ pub struct PeerInfo {
 	latest_hash: H256,
 	/// Peer total difficulty if known
 	difficulty: Option<U256>,
-	/// Type of data currenty being requested from peer.
-	asking: PeerAsking,
-	/// A set of block numbers being requested
-	asking_blocks: Vec<H256>,
-	/// Holds requested header hash if currently requesting block header by hash
-	asking_hash: Option<H256>,
-	/// Holds requested snapshot chunk hash if any.
-	asking_snapshot_data: Option<H256>,
-	/// Request timestamp
-	ask_time: Instant,
+	/// Peer tags requests and responses with request ids (eth/66).
+	request_ids: bool,
+	/// Requests currently sent to the peer, by request id.
+	requests: BTreeMap<u64, PeerRequest>,
+	/// Id of the next request sent to the peer.
+	next_request_id: u64,
 	/// Holds a set of transactions recently sent to this peer to avoid spamming.
 	last_sent_transactions: H256FastSet,
 	/// Holds a set of private transactions and their signatures recently sent to this peer to avoid spamming.
 	last_sent_private_transactions: H256FastSet,
-	/// Pending request is expired and result should be ignored
-	expired: bool,
 	/// Peer fork confirmation status
 	confirmation: ForkConfirmation,
 	/// Best snapshot hash
 	snapshot_hash: Option<H256>,
 	/// Best snapshot block number
 	snapshot_number: Option<BlockNumber>,
-	/// Block set requested
-	block_set: Option<BlockSet>,
+	/// Peer node id, if known
+	node_id: Option<NodeId>,
+	/// Number of block headers to ask the peer for at once
+	headers_request_size: RequestSize,
+	/// Number of block bodies to ask the peer for at once
+	bodies_request_size: RequestSize,
+	/// Number of block receipts to ask the peer for at once
+	receipts_request_size: RequestSize,
 }
 
 impl PeerInfo {
 	fn can_sync(&self) -> bool {
-		self.confirmation == ForkConfirmation::Confirmed && !self.expired
+		self.confirmation == ForkConfirmation::Confirmed && !self.is_expired()
 	}
 
 	fn is_allowed(&self) -> bool {
-		self.confirmation != ForkConfirmation::Unconfirmed && !self.expired
+		self.confirmation != ForkConfirmation::Unconfirmed && !self.is_expired()
+	}
+
+	/// Peer has to answer an expired request before it is asked for anything else.
+	/// Responses of peers supporting request ids can't be mistaken for answers to newer requests.
+	fn is_expired(&self) -> bool {
+		!self.request_ids && self.requests.values().any(|request| request.expired)
+	}
+
+	/// Peer can be sent another request. Only peers supporting request ids take several
+	/// requests at once, as long as the ones in flight aren't repeated until answered.
+	fn can_request(&self) -> bool {
+		match self.requests.len() {
+			0 => true,
+			n if self.request_ids => n < MAX_PEER_REQUESTS && self.requests.values().all(|request| match request.asking {
+				PeerAsking::BlockBodies | PeerAsking::BlockReceipts | PeerAsking::SnapshotData |
+					PeerAsking::AccountRange | PeerAsking::StorageRange | PeerAsking::NodeData => true,
+				_ => false,
+			}),
+			_ => false,
+		}
+	}
+
+	/// Peer serves proofs of account and storage ranges.
+	fn serves_state_ranges(&self) -> bool {
+		self.snapshot_number.is_some() && self.protocol_version >= PAR_PROTOCOL_VERSION_4.0
+	}
+
+	/// Id of the pending request answered by a response. Responses without a request id come in
+	/// order: the oldest request is answered first, or the oldest of its kind for peers
+	/// supporting request ids, which only use them on the eth protocol.
+	fn answered_request(&self, request_id: Option<u64>, asking: &PeerAsking) -> Option<u64> {
+		match request_id {
+			Some(id) => Some(id),
+			None => self.requests.iter()
+				.find(|&(_, request)| !self.request_ids || request.asking == *asking)
+				.map(|(id, _)| *id),
+		}
 	}
 
-	fn reset_asking(&mut self) {
-		self.asking_blocks.clear();
-		self.asking_hash = None;
-		// mark any pending requests as expired
-		if self.asking != PeerAsking::Nothing && self.is_allowed() {
-			self.expired = true;
+	/// Mark pending requests matching the filter as expired
+	fn reset_asking<F>(&mut self, filter: F) where F: Fn(&PeerRequest) -> bool {
+		let allowed = self.is_allowed();
+		for request in self.requests.values_mut().filter(|request| filter(request)) {
+			request.blocks.clear();
+			request.hash = None;
+			if allowed {
+				request.expired = true;
+			}
 		}
 	}
 
@@ -380,6 +499,19 @@ pub mod random {
 pub type RlpResponseResult = Result<Option<(PacketId, RlpStream)>, PacketDecodeError>;
 pub type Peers = HashMap<PeerId, PeerInfo>;
 
+/// Split an eth/66 packet into its request id and the packet it wraps.
+fn unwrap_request_id<'a>(rlp: &Rlp<'a>) -> Result<(u64, Rlp<'a>), PacketDecodeError> {
+	Ok((rlp.val_at(0)?, rlp.at(1)?))
+}
+
+/// Wrap a packet into an eth/66 packet with the given request id.
+fn wrap_request_id(request_id: u64, packet: &[u8]) -> RlpStream {
+	let mut rlp = RlpStream::new_list(2);
+	rlp.append(&request_id);
+	rlp.append_raw(packet, 1);
+	rlp
+}
+
 /// Thread-safe wrapper for `ChainSync`.
 ///
 /// NOTE always lock in order of fields declaration
@@ -397,9 +529,12 @@ impl ChainSyncApi {
 		chain: &BlockChainClient,
 		private_tx_handler: Arc<PrivateTxHandler>,
 		priority_tasks: mpsc::Receiver<PriorityTask>,
+		reputation: Reputation,
 	) -> Self {
+		let mut sync = ChainSync::new(config, chain, private_tx_handler);
+		sync.reputation = reputation;
 		ChainSyncApi {
-			sync: RwLock::new(ChainSync::new(config, chain, private_tx_handler)),
+			sync: RwLock::new(sync),
 			priority_tasks: Mutex::new(priority_tasks),
 		}
 	}
@@ -573,7 +708,10 @@ impl ChainSync {
 		peers
 	}
 
-	fn get_init_state(warp_sync: WarpSync, chain: &BlockChainClient) -> SyncState {
+	fn get_init_state(warp_sync: WarpSync, sync_mode: SyncMode, chain: &BlockChainClient) -> SyncState {
+		if sync_mode == SyncMode::State {
+			return SyncState::WaitingPeers;
+		}
 		let best_block = chain.chain_info().best_block_number;
 		match warp_sync {
 			WarpSync::Enabled => SyncState::WaitingPeers,
@@ -614,6 +752,8 @@ pub struct ChainSync {
 	network_id: u64,
 	/// Optional fork block to check
 	fork_block: Option<(BlockNumber, H256)>,
+	/// EIP-2124 fork identifiers of our chain.
+	fork_filter: ForkFilter,
 	/// Snapshot downloader.
 	snapshot: Snapshot,
 	/// Connected peers pending Status message.
@@ -623,12 +763,24 @@ pub struct ChainSync {
 	sync_start_time: Option<Instant>,
 	/// Transactions propagation statistics
 	transactions_stats: TransactionsStats,
+	/// Announced transactions being retrieved
+	transactions_fetcher: TransactionsFetcher,
 	/// Enable ancient block downloading
 	download_old_blocks: bool,
 	/// Shared private tx service.
 	private_tx_handler: Arc<PrivateTxHandler>,
 	/// Enable warp sync.
 	warp_sync: WarpSync,
+	/// Initial sync mode.
+	sync_mode: SyncMode,
+	/// State download, while state syncing.
+	state_sync: Option<StateSync>,
+	/// Trusted block to sync from.
+	checkpoint: Option<Checkpoint>,
+	/// Download of the headers back from the checkpoint, while state syncing from it.
+	checkpoint_headers: Option<CheckpointHeaders>,
+	/// Scores and bans of peers.
+	reputation: Reputation,
 }
 
 impl ChainSync {
@@ -640,7 +792,7 @@ impl ChainSync {
 	) -> Self {
 		let chain_info = chain.chain_info();
 		let best_block = chain.chain_info().best_block_number;
-		let state = Self::get_init_state(config.warp_sync, chain);
+		let state = Self::get_init_state(config.warp_sync, config.sync_mode, chain);
 
 		let mut sync = ChainSync {
 			state,
@@ -654,12 +806,19 @@ impl ChainSync {
 			last_sent_block_number: 0,
 			network_id: config.network_id,
 			fork_block: config.fork_block,
+			fork_filter: ForkFilter::new(chain_info.genesis_hash, config.hard_forks),
 			download_old_blocks: config.download_old_blocks,
 			snapshot: Snapshot::new(),
 			sync_start_time: None,
 			transactions_stats: TransactionsStats::default(),
+			transactions_fetcher: TransactionsFetcher::default(),
 			private_tx_handler,
 			warp_sync: config.warp_sync,
+			sync_mode: config.sync_mode,
+			state_sync: None,
+			checkpoint: config.checkpoint,
+			checkpoint_headers: None,
+			reputation: Reputation::new(None),
 		};
 		sync.update_targets(chain);
 		sync
@@ -670,7 +829,7 @@ impl ChainSync {
 		let last_imported_number = self.new_blocks.last_imported_block_number();
 		SyncStatus {
 			state: self.state.clone(),
-			protocol_version: ETH_PROTOCOL_VERSION_63.0,
+			protocol_version: ETH_PROTOCOL_VERSION_66.0,
 			network_id: self.network_id,
 			start_block_number: self.starting_block,
 			last_imported_block_number: Some(last_imported_number),
@@ -679,7 +838,7 @@ impl ChainSync {
 			blocks_received: if last_imported_number > self.starting_block { last_imported_number - self.starting_block } else { 0 },
 			blocks_total: match self.highest_block { Some(x) if x > self.starting_block => x - self.starting_block, _ => 0 },
 			num_peers: self.peers.values().filter(|p| p.is_allowed()).count(),
-			num_active_peers: self.peers.values().filter(|p| p.is_allowed() && p.asking != PeerAsking::Nothing).count(),
+			num_active_peers: self.peers.values().filter(|p| p.is_allowed() && !p.requests.is_empty()).count(),
 			num_snapshot_chunks: self.snapshot.total_chunks(),
 			snapshot_chunks_done: self.snapshot.done_chunks(),
 			mem_used:
@@ -696,6 +855,7 @@ impl ChainSync {
 				version: peer_data.protocol_version as u32,
 				difficulty: peer_data.difficulty,
 				head: peer_data.latest_hash,
+				reputation: peer_data.node_id.map_or(0, |id| self.reputation.rating(&id)),
 			}
 		})
 	}
@@ -724,15 +884,14 @@ impl ChainSync {
 		self.new_blocks.reset();
 		let chain_info = io.chain().chain_info();
 		for (_, ref mut p) in &mut self.peers {
-			if p.block_set != Some(BlockSet::OldBlocks) {
-				p.reset_asking();
-				if p.difficulty.is_none() {
-					// assume peer has up to date difficulty
-					p.difficulty = Some(chain_info.pending_total_difficulty);
-				}
+			p.reset_asking(|request| request.block_set != Some(BlockSet::OldBlocks));
+			let asking_old_blocks = p.requests.values().any(|request| request.block_set == Some(BlockSet::OldBlocks));
+			if !asking_old_blocks && p.difficulty.is_none() {
+				// assume peer has up to date difficulty
+				p.difficulty = Some(chain_info.pending_total_difficulty);
 			}
 		}
-		self.state = state.unwrap_or_else(|| Self::get_init_state(self.warp_sync, io.chain()));
+		self.state = state.unwrap_or_else(|| Self::get_init_state(self.warp_sync, self.sync_mode, io.chain()));
 		// Reactivate peers only if some progress has been made
 		// since the last sync round of if starting fresh.
 		self.active_peers = self.peers.keys().cloned().collect();
@@ -745,6 +904,10 @@ impl ChainSync {
 			debug!(target:"sync", "Aborting snapshot restore");
 			io.snapshot_service().abort_restore();
 		}
+		if self.state_sync.take().is_some() {
+			debug!(target:"sync", "Aborting state restore");
+			io.snapshot_service().abort_restore();
+		}
 		self.snapshot.clear();
 		self.reset(io, None);
 		self.continue_sync(io);
@@ -757,6 +920,37 @@ impl ChainSync {
 		self.active_peers.remove(&peer_id);
 	}
 
+	/// Update the reputation of a peer, disabling the peer if it gets banned.
+	fn note_conduct(&mut self, io: &mut SyncIo, peer_id: PeerId, conduct: Conduct) {
+		let node_id = match self.peers.get(&peer_id).and_then(|p| p.node_id) {
+			Some(id) => id,
+			None => return,
+		};
+		if self.reputation.note(&node_id, conduct) {
+			debug!(target: "sync", "{}: Banned for {:?}", peer_id, conduct);
+			io.disable_peer(peer_id);
+			self.deactivate_peer(io, peer_id);
+		}
+	}
+
+	/// Peer rating to prefer peers by.
+	fn peer_rating(&self, peer_id: PeerId) -> i32 {
+		self.peers.get(&peer_id).and_then(|p| p.node_id).map_or(0, |id| self.reputation.rating(&id))
+	}
+
+	/// Whether a peer can be trusted with headers and snapshot selection.
+	fn is_trusted_peer(&self, peer_id: PeerId) -> bool {
+		self.peers.get(&peer_id).and_then(|p| p.node_id).map_or(true, |id| self.reputation.is_trusted(&id))
+	}
+
+	/// Checkpoint to sync from, unless the chain is past it already.
+	fn pending_checkpoint(&self, chain: &BlockChainClient) -> Option<Checkpoint> {
+		match self.checkpoint {
+			Some(checkpoint) if chain.chain_info().best_block_number < checkpoint.number => Some(checkpoint),
+			_ => None,
+		}
+	}
+
 	fn maybe_start_snapshot_sync(&mut self, io: &mut SyncIo) {
 		if !self.warp_sync.is_enabled() || io.snapshot_service().supported_versions().is_none() {
 			trace!(target: "sync", "Skipping warp sync. Disabled or not supported.");
@@ -770,6 +964,7 @@ impl ChainSync {
 		// that it is higher than fork detection block
 		let our_best_block = io.chain().chain_info().best_block_number;
 		let fork_block = self.fork_block.map_or(0, |(n, _)| n);
+		let checkpoint = self.pending_checkpoint(io.chain());
 
 		let (best_hash, max_peers, snapshot_peers) = {
 			let expected_warp_block = match self.warp_sync {
@@ -777,19 +972,24 @@ impl ChainSync {
 				_ => 0,
 			};
 			//collect snapshot infos from peers
+			// only trusted peers vote for the snapshot to restore
 			let snapshots = self.peers.iter()
-				.filter(|&(_, p)| p.is_allowed() && p.snapshot_number.map_or(false, |sn|
-					// Snapshot must be old enough that it's usefull to sync with it
-					our_best_block < sn && (sn - our_best_block) > SNAPSHOT_RESTORE_THRESHOLD &&
-					// Snapshot must have been taken after the Fork
-					sn > fork_block &&
-					// Snapshot must be greater than the warp barrier if any
-					sn > expected_warp_block &&
-					// If we know a highest block, snapshot must be recent enough
-					self.highest_block.map_or(true, |highest| {
-						highest < sn || (highest - sn) <= SNAPSHOT_RESTORE_THRESHOLD
-					})
-				))
+				.filter(|&(id, _)| self.is_trusted_peer(*id))
+				.filter(|&(_, p)| p.is_allowed() && p.snapshot_number.map_or(false, |sn| match checkpoint {
+					// Snapshots before the checkpoint can't be checked against it
+					Some(ref checkpoint) => sn >= checkpoint.number,
+					None =>
+						// Snapshot must be old enough that it's usefull to sync with it
+						our_best_block < sn && (sn - our_best_block) > SNAPSHOT_RESTORE_THRESHOLD &&
+						// Snapshot must have been taken after the Fork
+						sn > fork_block &&
+						// Snapshot must be greater than the warp barrier if any
+						sn > expected_warp_block &&
+						// If we know a highest block, snapshot must be recent enough
+						self.highest_block.map_or(true, |highest| {
+							highest < sn || (highest - sn) <= SNAPSHOT_RESTORE_THRESHOLD
+						}),
+				}))
 				.filter_map(|(p, peer)| peer.snapshot_hash.map(|hash| (p, hash.clone())))
 				.filter(|&(_, ref hash)| !self.snapshot.is_known_bad(hash));
 
@@ -824,10 +1024,94 @@ impl ChainSync {
 		}
 	}
 
+	fn maybe_start_state_sync(&mut self, io: &mut SyncIo) {
+		if self.sync_mode != SyncMode::State || self.state != SyncState::WaitingPeers {
+			return;
+		}
+		if io.snapshot_service().supported_versions().is_none() {
+			trace!(target: "sync", "Skipping state sync. Not supported.");
+			self.state = SyncState::Idle;
+			self.continue_sync(io);
+			return;
+		}
+		if let Some(checkpoint) = self.pending_checkpoint(io.chain()) {
+			self.start_checkpoint_state_sync(io, checkpoint);
+			return;
+		}
+
+		// Pick the pivot from the best of the peers serving state ranges
+		let our_difficulty = io.chain().chain_info().pending_total_difficulty;
+		let (candidates, best) = {
+			let peers: Vec<_> = self.peers.iter()
+				.filter(|&(id, p)| p.can_sync() && p.can_request() && p.serves_state_ranges() && self.active_peers.contains(id))
+				.filter_map(|(id, p)| p.difficulty.and_then(|td| if td > our_difficulty { Some((*id, p.latest_hash, td)) } else { None }))
+				.collect();
+			(peers.len(), peers.into_iter().max_by_key(|&(_, _, td)| td))
+		};
+
+		let timeout = self.sync_start_time.map_or(false, |t| t.elapsed() > WAIT_PEERS_TIMEOUT);
+		match best {
+			Some((peer_id, latest, difficulty)) if candidates >= SNAPSHOT_MIN_PEERS || timeout => {
+				trace!(target: "sync", "Starting state sync pivot with {} at {}", peer_id, latest);
+				self.state_sync.get_or_insert_with(StateSync::new).request_pivot(latest, difficulty);
+				SyncRequester::request_pivot_headers(self, io, peer_id, &latest);
+				self.state = SyncState::StatePivot;
+			},
+			None if timeout && self.state_sync.as_ref().map_or(true, |s| s.pivot().is_none()) => {
+				trace!(target: "sync", "No peers serving state ranges, starting full sync");
+				self.state = SyncState::Idle;
+				self.continue_sync(io);
+			},
+			_ => (),
+		}
+	}
+
+	/// Downloads the headers back from the checkpoint to our best block, then makes the checkpoint
+	/// block the state sync pivot with the total difficulty they add up to.
+	fn start_checkpoint_state_sync(&mut self, io: &mut SyncIo, checkpoint: Checkpoint) {
+		if self.state_sync.as_ref().map_or(false, |s| s.pivot().is_some()) {
+			// Keep downloading the checkpoint state as peers that have it come along
+			self.state = SyncState::StateData;
+			return;
+		}
+		let pivot = match self.checkpoint_headers {
+			Some(ref headers) => headers.pivot(),
+			None => {
+				let chain = io.chain().chain_info();
+				trace!(target: "sync", "Downloading headers from checkpoint {} back to #{}", checkpoint, chain.best_block_number);
+				self.checkpoint_headers = Some(CheckpointHeaders::new(checkpoint, chain.best_block_hash, chain.best_block_number, chain.total_difficulty));
+				None
+			},
+		};
+		let peer = self.peers.iter()
+			.filter(|&(id, p)| p.can_sync() && p.can_request() && self.active_peers.contains(id) && self.is_trusted_peer(*id))
+			.filter(|&(_, p)| pivot.is_none() || p.serves_state_ranges())
+			.map(|(id, _)| *id)
+			.next();
+		let peer_id = match peer {
+			Some(peer_id) => peer_id,
+			None => return,
+		};
+		match pivot {
+			Some((header, total_difficulty)) => {
+				trace!(target: "sync", "Starting state sync of checkpoint {} with {}", checkpoint, peer_id);
+				self.state_sync.get_or_insert_with(StateSync::new).request_checkpoint(header, total_difficulty);
+				SyncRequester::request_pivot_body(self, io, peer_id, &checkpoint.hash);
+			},
+			None => {
+				let request = self.checkpoint_headers.as_ref().and_then(|headers| headers.next_request());
+				if let Some((hash, count)) = request {
+					SyncRequester::request_checkpoint_headers(self, io, peer_id, &hash, count);
+				}
+			},
+		}
+		self.state = SyncState::StatePivot;
+	}
+
 	fn start_snapshot_sync(&mut self, io: &mut SyncIo, peers: &[PeerId]) {
 		if !self.snapshot.have_manifest() {
 			for p in peers {
-				if self.peers.get(p).map_or(false, |p| p.asking == PeerAsking::Nothing) {
+				if self.peers.get(p).map_or(false, |p| p.requests.is_empty()) {
 					SyncRequester::request_snapshot_manifest(self, io, *p);
 				}
 			}
@@ -850,12 +1134,19 @@ impl ChainSync {
 		// Do not assume that the block queue/chain still has our last_imported_block
 		let chain = chain.chain_info();
 		self.new_blocks = BlockDownloader::new(BlockSet::NewBlocks, &chain.best_block_hash, chain.best_block_number);
+		if let Some(checkpoint) = self.checkpoint {
+			self.new_blocks.set_checkpoint(checkpoint);
+		}
 		self.old_blocks = None;
 		if self.download_old_blocks {
 			if let (Some(ancient_block_hash), Some(ancient_block_number)) = (chain.ancient_block_hash, chain.ancient_block_number) {
 
 				trace!(target: "sync", "Downloading old blocks from {:?} (#{}) till {:?} (#{:?})", ancient_block_hash, ancient_block_number, chain.first_block_hash, chain.first_block_number);
 				let mut downloader = BlockDownloader::new(BlockSet::OldBlocks, &ancient_block_hash, ancient_block_number);
+				// Blocks restored from a snapshot past the checkpoint must link back through it
+				if let Some(checkpoint) = self.checkpoint {
+					downloader.set_checkpoint(checkpoint);
+				}
 				if let Some(hash) = chain.first_block_hash {
 					trace!(target: "sync", "Downloader target set to {:?}", hash);
 					downloader.set_target(&hash);
@@ -873,9 +1164,9 @@ impl ChainSync {
 			trace!(target: "sync", "Waiting for the snapshot restoration");
 		} else {
 			// Collect active peers that can sync
-			let mut peers: Vec<(PeerId, u8)> = self.peers.iter().filter_map(|(peer_id, peer)|
-				if peer.can_sync() && peer.asking == PeerAsking::Nothing && self.active_peers.contains(&peer_id) {
-					Some((*peer_id, peer.protocol_version))
+			let mut peers: Vec<(PeerId, u8, i32)> = self.peers.iter().filter_map(|(peer_id, peer)|
+				if peer.can_sync() && peer.can_request() && self.active_peers.contains(&peer_id) {
+					Some((*peer_id, peer.protocol_version, self.peer_rating(*peer_id)))
 				} else {
 					None
 				}
@@ -888,11 +1179,11 @@ impl ChainSync {
 					self.active_peers.len(), peers.len(), self.peers.len()
 				);
 
-				random::new().shuffle(&mut peers); // TODO (#646): sort by rating
-				// prefer peers with higher protocol version
-				peers.sort_by(|&(_, ref v1), &(_, ref v2)| v1.cmp(v2));
+				random::new().shuffle(&mut peers);
+				// prefer peers with higher protocol version, then with better reputation
+				peers.sort_by(|&(_, ref v1, r1), &(_, ref v2, r2)| v1.cmp(v2).then(r2.cmp(&r1)));
 
-				for (peer_id, _) in peers {
+				for (peer_id, _, _) in peers {
 					self.sync_peer(io, peer_id, false);
 				}
 			}
@@ -900,7 +1191,7 @@ impl ChainSync {
 
 		if
 			(self.state == SyncState::Blocks || self.state == SyncState::NewBlocks) &&
-			!self.peers.values().any(|p| p.asking != PeerAsking::Nothing && p.block_set != Some(BlockSet::OldBlocks) && p.can_sync())
+			!self.peers.values().any(|p| p.can_sync() && p.requests.values().any(|request| request.block_set != Some(BlockSet::OldBlocks)))
 		{
 			self.complete_sync(io);
 		}
@@ -919,25 +1210,47 @@ impl ChainSync {
 	}
 
 	/// Find something to do for a peer. Called for a new peer or when a peer is done with its task.
+	/// Peers supporting request ids are kept busy with as many requests as they can take.
 	fn sync_peer(&mut self, io: &mut SyncIo, peer_id: PeerId, force: bool) {
+		loop {
+			let pending = self.peers.get(&peer_id).map_or(0, |p| p.requests.len());
+			self.request_from_peer(io, peer_id, force);
+			let keep_asking = self.peers.get(&peer_id).map_or(false, |p| p.requests.len() > pending && p.can_request());
+			if !keep_asking {
+				break;
+			}
+		}
+	}
+
+	/// Send a request to a peer if there is anything to ask for.
+	fn request_from_peer(&mut self, io: &mut SyncIo, peer_id: PeerId, force: bool) {
 		if !self.active_peers.contains(&peer_id) {
 			trace!(target: "sync", "Skipping deactivated peer {}", peer_id);
 			return;
 		}
-		let (peer_latest, peer_difficulty, peer_snapshot_number, peer_snapshot_hash) = {
+		let (peer_latest, peer_difficulty, peer_snapshot_number, peer_snapshot_hash, headers, bodies, receipts) = {
 			if let Some(peer) = self.peers.get_mut(&peer_id) {
-				if peer.asking != PeerAsking::Nothing || !peer.can_sync() {
+				if !peer.can_request() || !peer.can_sync() {
 					trace!(target: "sync", "Skipping busy peer {}", peer_id);
 					return;
 				}
-				(peer.latest_hash.clone(), peer.difficulty.clone(), peer.snapshot_number.as_ref().cloned().unwrap_or(0), peer.snapshot_hash.as_ref().cloned())
+				(
+					peer.latest_hash.clone(),
+					peer.difficulty.clone(),
+					peer.snapshot_number.as_ref().cloned().unwrap_or(0),
+					peer.snapshot_hash.as_ref().cloned(),
+					peer.headers_request_size.get(),
+					peer.bodies_request_size.get(),
+					peer.receipts_request_size.get(),
+				)
 			} else {
 				return;
 			}
 		};
 		let chain_info = io.chain().chain_info();
 		let syncing_difficulty = chain_info.pending_total_difficulty;
-		let num_active_peers = self.peers.values().filter(|p| p.asking != PeerAsking::Nothing).count();
+		let num_active_peers = self.peers.values().filter(|p| !p.requests.is_empty()).count();
+		let trusted = self.is_trusted_peer(peer_id);
 
 		let higher_difficulty = peer_difficulty.map_or(true, |pd| pd > syncing_difficulty);
 		if force || higher_difficulty || self.old_blocks.is_some() {
@@ -951,6 +1264,7 @@ impl ChainSync {
 						peer_id
 					);
 					self.maybe_start_snapshot_sync(io);
+					self.maybe_start_state_sync(io);
 				},
 				SyncState::Idle | SyncState::Blocks | SyncState::NewBlocks => {
 					if io.chain().queue_info().is_full() {
@@ -963,7 +1277,7 @@ impl ChainSync {
 					if !have_latest && (higher_difficulty || force || self.state == SyncState::NewBlocks) {
 						// check if got new blocks to download
 						trace!(target: "sync", "Syncing with peer {}, force={}, td={:?}, our td={}, state={:?}", peer_id, force, peer_difficulty, syncing_difficulty, self.state);
-						if let Some(request) = self.new_blocks.request_blocks(io, num_active_peers) {
+						if let Some(request) = self.new_blocks.request_blocks(io, num_active_peers, trusted, headers, bodies, receipts) {
 							SyncRequester::request_blocks(self, io, peer_id, request, BlockSet::NewBlocks);
 							if self.state == SyncState::Idle {
 								self.state = SyncState::Blocks;
@@ -976,7 +1290,7 @@ impl ChainSync {
 					let equal_or_higher_difficulty = peer_difficulty.map_or(false, |pd| pd >= syncing_difficulty);
 
 					if force || equal_or_higher_difficulty {
-						if let Some(request) = self.old_blocks.as_mut().and_then(|d| d.request_blocks(io, num_active_peers)) {
+						if let Some(request) = self.old_blocks.as_mut().and_then(|d| d.request_blocks(io, num_active_peers, trusted, headers, bodies, receipts)) {
 							SyncRequester::request_blocks(self, io, peer_id, request, BlockSet::OldBlocks);
 							return;
 						}
@@ -1012,11 +1326,17 @@ impl ChainSync {
 					}
 
 					if peer_snapshot_hash.is_some() && peer_snapshot_hash == self.snapshot.snapshot_hash() {
-						self.clear_peer_download(peer_id);
 						SyncRequester::request_snapshot_data(self, io, peer_id);
 					}
 				},
+				SyncState::StateData => {
+					match io.snapshot_service().status() {
+						RestorationStatus::Ongoing { .. } => SyncRequester::request_state_data(self, io, peer_id),
+						_ => trace!(target: "sync", "State restoration is not ongoing"),
+					}
+				},
 				SyncState::SnapshotManifest | //already downloading from other peer
+					SyncState::StatePivot |
 					SyncState::Waiting |
 					SyncState::SnapshotWaiting => ()
 			}
@@ -1027,35 +1347,51 @@ impl ChainSync {
 
 	/// Clear all blocks/headers marked as being downloaded by a peer.
 	fn clear_peer_download(&mut self, peer_id: PeerId) {
-		if let Some(ref peer) = self.peers.get(&peer_id) {
-			match peer.asking {
-				PeerAsking::BlockHeaders => {
-					if let Some(ref hash) = peer.asking_hash {
-						self.new_blocks.clear_header_download(hash);
-						if let Some(ref mut old) = self.old_blocks {
-							old.clear_header_download(hash);
-						}
-					}
-				},
-				PeerAsking::BlockBodies => {
-					self.new_blocks.clear_body_download(&peer.asking_blocks);
-					if let Some(ref mut old) = self.old_blocks {
-						old.clear_body_download(&peer.asking_blocks);
-					}
-				},
-				PeerAsking::BlockReceipts => {
-					self.new_blocks.clear_receipt_download(&peer.asking_blocks);
+		let requests: Vec<_> = self.peers.get(&peer_id).map_or_else(Vec::new, |peer| peer.requests.values().cloned().collect());
+		for request in &requests {
+			self.clear_request_download(request);
+		}
+	}
+
+	/// Clear all blocks/headers marked as being downloaded by a request.
+	fn clear_request_download(&mut self, request: &PeerRequest) {
+		match request.asking {
+			PeerAsking::BlockHeaders => {
+				if let Some(ref hash) = request.hash {
+					self.new_blocks.clear_header_download(hash);
 					if let Some(ref mut old) = self.old_blocks {
-						old.clear_receipt_download(&peer.asking_blocks);
+						old.clear_header_download(hash);
 					}
-				},
-				PeerAsking::SnapshotData => {
-					if let Some(hash) = peer.asking_snapshot_data {
-						self.snapshot.clear_chunk_download(&hash);
-					}
-				},
-				_ => (),
-			}
+				}
+			},
+			PeerAsking::BlockBodies => {
+				self.new_blocks.clear_body_download(&request.blocks);
+				if let Some(ref mut old) = self.old_blocks {
+					old.clear_body_download(&request.blocks);
+				}
+			},
+			PeerAsking::BlockReceipts => {
+				self.new_blocks.clear_receipt_download(&request.blocks);
+				if let Some(ref mut old) = self.old_blocks {
+					old.clear_receipt_download(&request.blocks);
+				}
+			},
+			PeerAsking::SnapshotData => {
+				if let Some(hash) = request.snapshot_data {
+					self.snapshot.clear_chunk_download(&hash);
+				}
+			},
+			PeerAsking::AccountRange | PeerAsking::StorageRange => {
+				if let (Some(state_sync), Some(range)) = (self.state_sync.as_mut(), request.state_range.as_ref()) {
+					state_sync.clear_range(range);
+				}
+			},
+			PeerAsking::NodeData => {
+				if let Some(ref mut state_sync) = self.state_sync {
+					state_sync.clear_nodes(&request.blocks);
+				}
+			},
+			_ => (),
 		}
 	}
 
@@ -1094,35 +1430,79 @@ impl ChainSync {
 	/// Mark all outstanding requests as expired
 	fn reset_downloads(&mut self, block_set: BlockSet) {
 		trace!(target: "sync", "Resetting downloads for {:?}", block_set);
-		for (_, ref mut p) in self.peers.iter_mut().filter(|&(_, ref p)| p.block_set == Some(block_set)) {
-			p.reset_asking();
+		for (_, ref mut p) in &mut self.peers {
+			p.reset_asking(|request| request.block_set == Some(block_set));
 		}
 	}
 
-	/// Reset peer status after request is complete.
-	fn reset_peer_asking(&mut self, peer_id: PeerId, asking: PeerAsking) -> bool {
-		if let Some(ref mut peer) = self.peers.get_mut(&peer_id) {
-			peer.expired = false;
-			peer.block_set = None;
-			if peer.asking != asking {
-				trace!(target:"sync", "Asking {:?} while expected {:?}", peer.asking, asking);
-				peer.asking = PeerAsking::Nothing;
-				return false;
-			} else {
-				peer.asking = PeerAsking::Nothing;
-				return true;
+	/// Complete the request answered by a response and clear what it was downloading.
+	/// Returns the request unless it's unknown, expired or of another kind than expected.
+	fn take_peer_request(&mut self, peer_id: PeerId, request_id: Option<u64>, asking: PeerAsking) -> Option<PeerRequest> {
+		let request = {
+			let peer = self.peers.get_mut(&peer_id)?;
+			let id = match peer.answered_request(request_id, &asking) {
+				Some(id) => id,
+				None => {
+					trace!(target:"sync", "{}: Nothing asked while expected {:?}", peer_id, asking);
+					return None;
+				}
+			};
+			match peer.requests.remove(&id) {
+				Some(request) => request,
+				None => {
+					trace!(target:"sync", "{}: Unknown request {} while expected {:?}", peer_id, id, asking);
+					return None;
+				}
+			}
+		};
+		self.clear_request_download(&request);
+		if request.asking != asking {
+			trace!(target:"sync", "Asking {:?} while expected {:?}", request.asking, asking);
+			return None;
+		}
+		if request.expired {
+			trace!(target:"sync", "{}: Response to expired request {:?}", peer_id, asking);
+			return None;
+		}
+		Some(request)
+	}
+
+	/// Expire block header, body and receipt requests peers are slow to answer, so that the blocks
+	/// are asked from other peers instead of holding up the import. The slow peers are asked for less.
+	fn expire_stalled_requests(&mut self, now: Instant) {
+		let mut stalled = Vec::new();
+		for (peer_id, peer) in &mut self.peers {
+			for request in peer.requests.values_mut().filter(|request| !request.expired) {
+				let size = match request.asking {
+					PeerAsking::BlockHeaders if request.header_count.is_some() => &mut peer.headers_request_size,
+					PeerAsking::BlockBodies => &mut peer.bodies_request_size,
+					PeerAsking::BlockReceipts => &mut peer.receipts_request_size,
+					_ => continue,
+				};
+				if size.is_stalled(request.time, now) {
+					trace!(target: "sync", "{}: Stalled {:?} request", peer_id, request.asking);
+					size.on_stall(now);
+					stalled.push(request.clone());
+					request.blocks.clear();
+					request.expired = true;
+				}
 			}
 		}
-		false
+		for request in &stalled {
+			self.clear_request_download(request);
+		}
 	}
 
 	/// Send Status message
 	fn send_status(&mut self, io: &mut SyncIo, peer: PeerId) -> Result<(), network::Error> {
 		let warp_protocol_version = io.protocol_version(&WARP_SYNC_PROTOCOL_ID, peer);
 		let warp_protocol = warp_protocol_version != 0;
-		let protocol = if warp_protocol { warp_protocol_version } else { ETH_PROTOCOL_VERSION_63.0 };
+		let eth_protocol_version = io.eth_protocol_version(peer);
+		// the fork id is sent whenever eth/64 is negotiated, after the snapshot fields of the warp status.
+		let fork_id = eth_protocol_version >= ETH_PROTOCOL_VERSION_64.0;
+		let protocol = if warp_protocol { warp_protocol_version } else { cmp::max(eth_protocol_version, ETH_PROTOCOL_VERSION_63.0) };
 		trace!(target: "sync", "Sending status to {}, protocol version {}", peer, protocol);
-		let mut packet = RlpStream::new_list(if warp_protocol { 7 } else { 5 });
+		let mut packet = RlpStream::new_list(5 + if warp_protocol { 2 } else { 0 } + if fork_id { 1 } else { 0 });
 		let chain = io.chain().chain_info();
 		packet.append(&(protocol as u32));
 		packet.append(&self.network_id);
@@ -1136,6 +1516,9 @@ impl ChainSync {
 			packet.append(&manifest_hash);
 			packet.append(&block_number);
 		}
+		if fork_id {
+			packet.append(&self.fork_filter.current(chain.best_block_number));
+		}
 		io.respond(STATUS_PACKET, packet.out())
 	}
 
@@ -1143,16 +1526,23 @@ impl ChainSync {
 		let tick = Instant::now();
 		let mut aborting = Vec::new();
 		for (peer_id, peer) in &self.peers {
-			let elapsed = tick - peer.ask_time;
-			let timeout = match peer.asking {
-				PeerAsking::BlockHeaders => elapsed > HEADERS_TIMEOUT,
-				PeerAsking::BlockBodies => elapsed > BODIES_TIMEOUT,
-				PeerAsking::BlockReceipts => elapsed > RECEIPTS_TIMEOUT,
-				PeerAsking::Nothing => false,
-				PeerAsking::ForkHeader => elapsed > FORK_HEADER_TIMEOUT,
-				PeerAsking::SnapshotManifest => elapsed > SNAPSHOT_MANIFEST_TIMEOUT,
-				PeerAsking::SnapshotData => elapsed > SNAPSHOT_DATA_TIMEOUT,
-			};
+			let timeout = peer.requests.values().any(|request| {
+				let elapsed = tick - request.time;
+				match request.asking {
+					PeerAsking::BlockHeaders => elapsed > HEADERS_TIMEOUT,
+					PeerAsking::BlockBodies => elapsed > BODIES_TIMEOUT,
+					PeerAsking::BlockReceipts => elapsed > RECEIPTS_TIMEOUT,
+					PeerAsking::ForkHeader => elapsed > FORK_HEADER_TIMEOUT,
+					PeerAsking::SnapshotManifest => elapsed > SNAPSHOT_MANIFEST_TIMEOUT,
+					PeerAsking::SnapshotData => elapsed > SNAPSHOT_DATA_TIMEOUT,
+					PeerAsking::PivotHeaders => elapsed > HEADERS_TIMEOUT,
+					PeerAsking::CheckpointHeaders => elapsed > HEADERS_TIMEOUT,
+					PeerAsking::PivotBody => elapsed > BODIES_TIMEOUT,
+					PeerAsking::PivotReceipts => elapsed > RECEIPTS_TIMEOUT,
+					PeerAsking::AccountRange | PeerAsking::StorageRange => elapsed > STATE_RANGE_TIMEOUT,
+					PeerAsking::NodeData => elapsed > NODE_DATA_TIMEOUT,
+				}
+			});
 			if timeout {
 				debug!(target:"sync", "Timeout {}", peer_id);
 				io.disconnect_peer(*peer_id);
@@ -1160,8 +1550,11 @@ impl ChainSync {
 			}
 		}
 		for p in aborting {
+			self.note_conduct(io, p, Conduct::Timeout);
 			SyncHandler::on_peer_aborting(self, io, p);
 		}
+		self.expire_stalled_requests(tick);
+		self.reputation.maintain();
 
 		// Check for handshake timeouts
 		for (peer, &ask_time) in &self.handshaking_peers {
@@ -1171,12 +1564,27 @@ impl ChainSync {
 				io.disconnect_peer(*peer);
 			}
 		}
+
+		// Retry announced transactions that weren't delivered with other peers
+		for peer_id in self.transactions_fetcher.expire(tick) {
+			trace!(target: "sync", "{}: Pooled transactions timeout", peer_id);
+		}
+		for peer_id in self.transactions_fetcher.idle_peers() {
+			SyncRequester::request_pooled_transactions(self, io, peer_id);
+		}
 	}
 
 	fn check_resume(&mut self, io: &mut SyncIo) {
 		match self.state {
 			SyncState::Waiting if !io.chain().queue_info().is_full() => {
 				self.state = SyncState::Blocks;
+				// Import what was downloaded while waiting
+				if self.new_blocks.is_downloading_blocks() {
+					self.collect_blocks(io, BlockSet::NewBlocks);
+				}
+				if self.old_blocks.as_ref().map_or(false, |d| d.is_downloading_blocks()) {
+					self.collect_blocks(io, BlockSet::OldBlocks);
+				}
 				self.continue_sync(io);
 			},
 			SyncState::SnapshotData => match io.snapshot_service().status() {
@@ -1185,6 +1593,28 @@ impl ChainSync {
 				},
 				RestorationStatus::Initializing { .. } | RestorationStatus::Ongoing { .. } => (),
 			},
+			SyncState::StateData => {
+				let (ranges_done, started) = self.state_sync.as_ref().map_or((false, None), |s| (s.ranges_done(), s.started()));
+				match io.snapshot_service().status() {
+					RestorationStatus::Inactive if ranges_done => {
+						trace!(target:"sync", "State restoration is complete");
+						self.state_sync = None;
+						self.sync_mode = SyncMode::Full;
+						self.restart(io);
+					},
+					RestorationStatus::Inactive if started.map_or(false, |t| t.elapsed() <= WAIT_PEERS_TIMEOUT) => {
+						trace!(target:"sync", "State restoration is starting");
+					},
+					RestorationStatus::Inactive | RestorationStatus::Failed => {
+						warn!(target: "sync", "State restoration failed, falling back to full sync");
+						self.state_sync = None;
+						self.sync_mode = SyncMode::Full;
+						self.restart(io);
+					},
+					RestorationStatus::Initializing { .. } => (),
+					RestorationStatus::Ongoing { .. } => self.continue_sync(io),
+				}
+			},
 			SyncState::SnapshotWaiting => {
 				match io.snapshot_service().status() {
 					RestorationStatus::Inactive => {
@@ -1257,6 +1687,7 @@ impl ChainSync {
 	/// Maintain other peers. Send out any new blocks and transactions
 	pub fn maintain_sync(&mut self, io: &mut SyncIo) {
 		self.maybe_start_snapshot_sync(io);
+		self.maybe_start_state_sync(io);
 		self.check_resume(io);
 	}
 
@@ -1439,18 +1870,18 @@ pub mod tests {
 				network_id: 0,
 				latest_hash: peer_latest_hash,
 				difficulty: None,
-				asking: PeerAsking::Nothing,
-				asking_blocks: Vec::new(),
-				asking_hash: None,
-				ask_time: Instant::now(),
+				request_ids: false,
+				requests: BTreeMap::new(),
+				next_request_id: 0,
 				last_sent_transactions: Default::default(),
 				last_sent_private_transactions: Default::default(),
-				expired: false,
 				confirmation: super::ForkConfirmation::Confirmed,
 				snapshot_number: None,
 				snapshot_hash: None,
-				asking_snapshot_data: None,
-				block_set: None,
+				node_id: None,
+				headers_request_size: RequestSize::headers(),
+				bodies_request_size: RequestSize::bodies(),
+				receipts_request_size: RequestSize::receipts(),
 			});
 
 	}
@@ -1598,5 +2029,117 @@ pub mod tests {
 		let status = io.chain.miner.queue_status();
 		assert_eq!(status.status.transaction_count, 0);
 	}
-}
 
+	#[test]
+	fn pipelines_requests_to_peers_with_request_ids() {
+		let client = TestBlockChainClient::new();
+		let mut sync = dummy_sync_with_peer(H256::new(), &client);
+		let peer = sync.peers.get_mut(&0).unwrap();
+		peer.requests.insert(0, PeerRequest::new(PeerAsking::BlockBodies));
+		assert!(!peer.can_request());
+
+		peer.request_ids = true;
+		for id in 1..MAX_PEER_REQUESTS as u64 {
+			assert!(peer.can_request());
+			peer.requests.insert(id, PeerRequest::new(PeerAsking::BlockReceipts));
+		}
+		assert!(!peer.can_request());
+
+		// Header requests aren't pipelined.
+		peer.requests.clear();
+		peer.requests.insert(0, PeerRequest::new(PeerAsking::BlockHeaders));
+		assert!(!peer.can_request());
+	}
+
+	#[test]
+	fn matches_responses_to_requests() {
+		let client = TestBlockChainClient::new();
+		let mut sync = dummy_sync_with_peer(H256::new(), &client);
+		{
+			let peer = sync.peers.get_mut(&0).unwrap();
+			peer.request_ids = true;
+			peer.requests.insert(0, PeerRequest::new(PeerAsking::SnapshotData));
+			peer.requests.insert(1, PeerRequest { blocks: vec![H256::from(1)], ..PeerRequest::new(PeerAsking::BlockBodies) });
+			peer.requests.insert(2, PeerRequest { blocks: vec![H256::from(2)], ..PeerRequest::new(PeerAsking::BlockBodies) });
+		}
+
+		// Answered out of order.
+		assert_eq!(sync.take_peer_request(0, Some(2), PeerAsking::BlockBodies).unwrap().blocks, vec![H256::from(2)]);
+		assert!(sync.take_peer_request(0, Some(2), PeerAsking::BlockBodies).is_none());
+		// A response of another kind completes the request without being accepted.
+		assert!(sync.take_peer_request(0, Some(1), PeerAsking::BlockReceipts).is_none());
+		assert!(!sync.peers[&0].requests.contains_key(&1));
+		// Warp responses carry no request id.
+		assert!(sync.take_peer_request(0, None, PeerAsking::SnapshotData).is_some());
+		assert!(sync.peers[&0].requests.is_empty());
+
+		// Responses to expired requests are ignored, without holding up the peer meanwhile.
+		sync.peers.get_mut(&0).unwrap().requests.insert(3, PeerRequest { block_set: Some(BlockSet::NewBlocks), ..PeerRequest::new(PeerAsking::BlockHeaders) });
+		sync.reset_downloads(BlockSet::NewBlocks);
+		assert!(sync.peers[&0].can_sync());
+		assert!(sync.take_peer_request(0, Some(3), PeerAsking::BlockHeaders).is_none());
+	}
+
+	#[test]
+	fn expired_request_holds_up_peer_without_request_ids() {
+		let client = TestBlockChainClient::new();
+		let mut sync = dummy_sync_with_peer(H256::new(), &client);
+		sync.peers.get_mut(&0).unwrap().requests.insert(0, PeerRequest { block_set: Some(BlockSet::NewBlocks), ..PeerRequest::new(PeerAsking::BlockHeaders) });
+		sync.reset_downloads(BlockSet::NewBlocks);
+		assert!(!sync.peers[&0].can_sync());
+
+		assert!(sync.take_peer_request(0, None, PeerAsking::BlockHeaders).is_none());
+		assert!(sync.peers[&0].can_sync());
+	}
+
+	#[test]
+	fn expires_stalled_block_requests() {
+		let client = TestBlockChainClient::new();
+		let mut sync = dummy_sync_with_peer(H256::new(), &client);
+		let sent = Instant::now();
+		{
+			let peer = sync.peers.get_mut(&0).unwrap();
+			peer.request_ids = true;
+			peer.requests.insert(0, PeerRequest { blocks: vec![H256::from(1)], block_set: Some(BlockSet::NewBlocks), ..PeerRequest::new(PeerAsking::BlockBodies) });
+			peer.requests.insert(1, PeerRequest { block_set: Some(BlockSet::NewBlocks), ..PeerRequest::new(PeerAsking::BlockHeaders) });
+			peer.requests.insert(2, PeerRequest { hash: Some(H256::from(2)), header_count: Some(128), block_set: Some(BlockSet::NewBlocks), ..PeerRequest::new(PeerAsking::BlockHeaders) });
+		}
+		let size = sync.peers[&0].bodies_request_size.get();
+		let header_size = sync.peers[&0].headers_request_size.get();
+
+		sync.expire_stalled_requests(sent + Duration::from_secs(1));
+		assert!(!sync.peers[&0].requests[&0].expired);
+
+		sync.expire_stalled_requests(sent + Duration::from_secs(10));
+		let peer = &sync.peers[&0];
+		assert!(peer.requests[&0].expired);
+		assert!(peer.requests[&0].blocks.is_empty());
+		assert!(!peer.requests[&1].expired);
+		assert!(peer.requests[&2].expired);
+		assert_eq!(peer.bodies_request_size.get(), size / 2);
+		assert_eq!(peer.headers_request_size.get(), header_size / 2);
+	}
+
+	#[test]
+	fn bans_and_distrusts_misbehaving_peers() {
+		let mut client = TestBlockChainClient::new();
+		let queue = RwLock::new(VecDeque::new());
+		let ss = TestSnapshotService::new();
+		let mut sync = dummy_sync_with_peer(H256::new(), &client);
+		insert_dummy_peer(&mut sync, 1, H256::new());
+		sync.peers.get_mut(&0).unwrap().node_id = Some(1.into());
+		sync.peers.get_mut(&1).unwrap().node_id = Some(2.into());
+		let mut io = TestIo::new(&mut client, &ss, &queue, None);
+
+		sync.note_conduct(&mut io, 0, Conduct::InvalidBlock);
+		assert!(!sync.is_trusted_peer(0));
+		assert!(sync.peer_rating(0) < sync.peer_rating(1));
+		assert!(io.to_disconnect.is_empty());
+
+		sync.note_conduct(&mut io, 0, Conduct::InvalidBlock);
+		assert!(io.to_disconnect.contains(&0));
+		assert!(!sync.active_peers.contains(&0));
+		assert!(sync.reputation.is_banned(&1.into()));
+		assert!(sync.is_trusted_peer(1));
+	}
+}
@@ -30,6 +30,8 @@ use transaction::SignedTransaction;
 use super::{
 	random,
 	ChainSync,
+	ETH_PROTOCOL_VERSION_65,
+	MAX_TRANSACTION_HASHES_TO_ANNOUNCE,
 	MAX_TRANSACTION_PACKET_SIZE,
 	MAX_PEER_LAG_PROPAGATION,
 	MAX_PEERS_PROPAGATION,
@@ -37,6 +39,7 @@ use super::{
 	CONSENSUS_DATA_PACKET,
 	NEW_BLOCK_HASHES_PACKET,
 	NEW_BLOCK_PACKET,
+	NEW_POOLED_TRANSACTION_HASHES_PACKET,
 	TRANSACTIONS_PACKET,
 };
 
@@ -137,9 +140,18 @@ impl SyncPropagator {
 		let mut affected_peers = HashSet::new();
 		if !transactions.is_empty() {
 			let peers = SyncPropagator::select_peers_for_transactions(sync, |_| true);
-			affected_peers = SyncPropagator::propagate_transactions_to_peers(
+			// eth/65 peers not receiving the transactions get their hashes announced instead
+			let announce_peers = sync.peers.keys()
+				.filter(|peer_id| !peers.contains(peer_id) && io.eth_protocol_version(**peer_id) >= ETH_PROTOCOL_VERSION_65.0)
+				.cloned()
+				.collect();
+			affected_peers = SyncPropagator::announce_transactions_to_peers(
+				sync, io, announce_peers, &transactions, &mut should_continue,
+			);
+			let propagated_peers = SyncPropagator::propagate_transactions_to_peers(
 				sync, io, peers, transactions, &mut should_continue,
 			);
+			affected_peers.extend(&propagated_peers);
 		}
 
 		// most of times service_transactions will be empty
@@ -155,6 +167,58 @@ impl SyncPropagator {
 		affected_peers.len()
 	}
 
+	fn announce_transactions_to_peers<F: FnMut() -> bool>(
+		sync: &mut ChainSync,
+		io: &mut SyncIo,
+		peers: Vec<PeerId>,
+		transactions: &[&SignedTransaction],
+		mut should_continue: F,
+	) -> HashSet<PeerId> {
+		let all_transactions_hashes = transactions.iter()
+			.map(|tx| tx.hash())
+			.collect::<H256FastSet>();
+		let block_number = io.chain().chain_info().best_block_number;
+		let mut announced_to_peers = HashSet::new();
+
+		for peer_id in peers {
+			if !should_continue() {
+				break;
+			}
+
+			let stats = &mut sync.transactions_stats;
+			let peer_info = sync.peers.get_mut(&peer_id)
+				.expect("peer_id is from peers; peers are selected from self.peers; qed");
+
+			let to_announce = transactions.iter()
+				.map(|tx| tx.hash())
+				.filter(|hash| !peer_info.last_sent_transactions.contains(hash))
+				.take(MAX_TRANSACTION_HASHES_TO_ANNOUNCE)
+				.collect::<Vec<_>>();
+			if to_announce.is_empty() {
+				continue;
+			}
+
+			let id = io.peer_session_info(peer_id).and_then(|info| info.id);
+			let mut packet = RlpStream::new_list(to_announce.len());
+			for hash in &to_announce {
+				stats.announced(hash, id, block_number);
+				packet.append(hash);
+			}
+			peer_info.last_sent_transactions = all_transactions_hashes
+				.intersection(&peer_info.last_sent_transactions)
+				.chain(&to_announce)
+				.cloned()
+				.collect();
+
+			trace!(target: "sync", "{:02} <- NewPooledTransactionHashes ({} entries)", peer_id, to_announce.len());
+			SyncPropagator::send_packet(io, peer_id, NEW_POOLED_TRANSACTION_HASHES_PACKET, packet.out());
+			announced_to_peers.insert(peer_id);
+		}
+
+		debug!(target: "sync", "Announced transactions to {} peers.", announced_to_peers.len());
+		announced_to_peers
+	}
+
 	fn propagate_transactions_to_peers<F: FnMut() -> bool>(
 		sync: &mut ChainSync,
 		io: &mut SyncIo,
@@ -434,18 +498,18 @@ mod tests {
 				network_id: 0,
 				latest_hash: client.block_hash_delta_minus(1),
 				difficulty: None,
-				asking: PeerAsking::Nothing,
-				asking_blocks: Vec::new(),
-				asking_hash: None,
-				ask_time: Instant::now(),
+				request_ids: false,
+				requests: BTreeMap::new(),
+				next_request_id: 0,
 				last_sent_transactions: Default::default(),
 				last_sent_private_transactions: Default::default(),
-				expired: false,
 				confirmation: ForkConfirmation::Confirmed,
 				snapshot_number: None,
 				snapshot_hash: None,
-				asking_snapshot_data: None,
-				block_set: None,
+				node_id: None,
+				headers_request_size: RequestSize::headers(),
+				bodies_request_size: RequestSize::bodies(),
+				receipts_request_size: RequestSize::receipts(),
 			});
 		let ss = TestSnapshotService::new();
 		let mut io = TestIo::new(&mut client, &ss, &queue, None);
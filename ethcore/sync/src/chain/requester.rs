@@ -21,6 +21,7 @@ use ethcore::header::BlockNumber;
 use ethereum_types::H256;
 use network::{PeerId, PacketId};
 use rlp::RlpStream;
+use state_sync::{StateRange, PIVOT_DISTANCE, MAX_NODE_DATA_TO_REQUEST};
 use std::time::Instant;
 use sync_io::SyncIo;
 
@@ -28,12 +29,19 @@ use super::{
 	BlockSet,
 	ChainSync,
 	PeerAsking,
+	PeerRequest,
+	wrap_request_id,
 	ETH_PROTOCOL_VERSION_63,
+	MAX_RANGE_BYTES_TO_SEND,
+	GET_ACCOUNT_RANGE_PACKET,
 	GET_BLOCK_BODIES_PACKET,
 	GET_BLOCK_HEADERS_PACKET,
+	GET_NODE_DATA_PACKET,
+	GET_POOLED_TRANSACTIONS_PACKET,
 	GET_RECEIPTS_PACKET,
 	GET_SNAPSHOT_DATA_PACKET,
 	GET_SNAPSHOT_MANIFEST_PACKET,
+	GET_STORAGE_RANGE_PACKET,
 };
 
 /// The Chain Sync Requester: requesting data to other peers
@@ -62,10 +70,8 @@ impl SyncRequester {
 		for h in &hashes {
 			rlp.append(&h.clone());
 		}
-		SyncRequester::send_request(sync, io, peer_id, PeerAsking::BlockBodies, GET_BLOCK_BODIES_PACKET, rlp.out());
-		let peer = sync.peers.get_mut(&peer_id).expect("peer_id may originate either from on_packet, where it is already validated or from enumerating self.peers. qed");
-		peer.asking_blocks = hashes;
-		peer.block_set = Some(set);
+		let request = PeerRequest { blocks: hashes, block_set: Some(set), ..PeerRequest::new(PeerAsking::BlockBodies) };
+		SyncRequester::send_request(sync, io, peer_id, request, GET_BLOCK_BODIES_PACKET, rlp.out());
 	}
 
 	/// Request headers from a peer by block number
@@ -76,16 +82,13 @@ impl SyncRequester {
 		rlp.append(&1u32);
 		rlp.append(&0u32);
 		rlp.append(&0u32);
-		SyncRequester::send_request(sync, io, peer_id, PeerAsking::ForkHeader, GET_BLOCK_HEADERS_PACKET, rlp.out());
+		SyncRequester::send_request(sync, io, peer_id, PeerRequest::new(PeerAsking::ForkHeader), GET_BLOCK_HEADERS_PACKET, rlp.out());
 	}
 
 	/// Find some headers or blocks to download for a peer.
 	pub fn request_snapshot_data(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId) {
 		// find chunk data to download
 		if let Some(hash) = sync.snapshot.needed_chunk() {
-			if let Some(ref mut peer) = sync.peers.get_mut(&peer_id) {
-				peer.asking_snapshot_data = Some(hash.clone());
-			}
 			SyncRequester::request_snapshot_chunk(sync, io, peer_id, &hash);
 		}
 	}
@@ -94,7 +97,113 @@ impl SyncRequester {
 	pub fn request_snapshot_manifest(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId) {
 		trace!(target: "sync", "{} <- GetSnapshotManifest", peer_id);
 		let rlp = RlpStream::new_list(0);
-		SyncRequester::send_request(sync, io, peer_id, PeerAsking::SnapshotManifest, GET_SNAPSHOT_MANIFEST_PACKET, rlp.out());
+		SyncRequester::send_request(sync, io, peer_id, PeerRequest::new(PeerAsking::SnapshotManifest), GET_SNAPSHOT_MANIFEST_PACKET, rlp.out());
+	}
+
+	/// Request the headers a state sync pivot is picked from: the peer's best block and the
+	/// ones before it, in reverse.
+	pub fn request_pivot_headers(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, latest: &H256) {
+		trace!(target: "sync", "{} <- GetPivotHeaders: {} entries back from {}", peer_id, PIVOT_DISTANCE + 1, latest);
+		let mut rlp = RlpStream::new_list(4);
+		rlp.append(latest);
+		rlp.append(&(PIVOT_DISTANCE + 1));
+		rlp.append(&0u32);
+		rlp.append(&1u32);
+		let request = PeerRequest { hash: Some(*latest), ..PeerRequest::new(PeerAsking::PivotHeaders) };
+		SyncRequester::send_request(sync, io, peer_id, request, GET_BLOCK_HEADERS_PACKET, rlp.out());
+	}
+
+	/// Request headers in reverse from a block on the way from the checkpoint back to our best block.
+	pub fn request_checkpoint_headers(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, hash: &H256, count: u64) {
+		trace!(target: "sync", "{} <- GetCheckpointHeaders: {} entries back from {}", peer_id, count, hash);
+		let mut rlp = RlpStream::new_list(4);
+		rlp.append(hash);
+		rlp.append(&count);
+		rlp.append(&0u32);
+		rlp.append(&1u32);
+		let request = PeerRequest { hash: Some(*hash), ..PeerRequest::new(PeerAsking::CheckpointHeaders) };
+		SyncRequester::send_request(sync, io, peer_id, request, GET_BLOCK_HEADERS_PACKET, rlp.out());
+	}
+
+	/// Request the body of the state sync pivot.
+	pub fn request_pivot_body(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, hash: &H256) {
+		trace!(target: "sync", "{} <- GetPivotBody: {}", peer_id, hash);
+		let mut rlp = RlpStream::new_list(1);
+		rlp.append(hash);
+		let request = PeerRequest { blocks: vec![*hash], ..PeerRequest::new(PeerAsking::PivotBody) };
+		SyncRequester::send_request(sync, io, peer_id, request, GET_BLOCK_BODIES_PACKET, rlp.out());
+	}
+
+	/// Request the receipts of the state sync pivot.
+	pub fn request_pivot_receipts(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, hash: &H256) {
+		trace!(target: "sync", "{} <- GetPivotReceipts: {}", peer_id, hash);
+		let mut rlp = RlpStream::new_list(1);
+		rlp.append(hash);
+		let request = PeerRequest { blocks: vec![*hash], ..PeerRequest::new(PeerAsking::PivotReceipts) };
+		SyncRequester::send_request(sync, io, peer_id, request, GET_RECEIPTS_PACKET, rlp.out());
+	}
+
+	/// Find some state to download for a peer: a range of the state if the peer serves them
+	/// and still has the pivot state, or missing trie nodes otherwise.
+	pub fn request_state_data(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId) {
+		let serves_ranges = sync.peers.get(&peer_id).map_or(false, |p| p.serves_state_ranges());
+		let range = match sync.state_sync {
+			Some(ref mut state_sync) => if serves_ranges && !state_sync.is_stale(peer_id) {
+				state_sync.next_range()
+			} else {
+				None
+			},
+			None => return,
+		};
+		if let Some(range) = range {
+			SyncRequester::request_state_range(sync, io, peer_id, range);
+			return;
+		}
+
+		let hashes = match sync.state_sync {
+			Some(ref mut state_sync) => {
+				let missing = io.snapshot_service().missing_state_data(state_sync.requested_nodes() + MAX_NODE_DATA_TO_REQUEST);
+				state_sync.nodes_to_request(missing, Instant::now())
+			},
+			None => return,
+		};
+		if hashes.is_empty() {
+			return;
+		}
+		trace!(target: "sync", "{} <- GetNodeData: {} entries", peer_id, hashes.len());
+		let mut rlp = RlpStream::new_list(hashes.len());
+		for h in &hashes {
+			rlp.append(h);
+		}
+		let request = PeerRequest { blocks: hashes, ..PeerRequest::new(PeerAsking::NodeData) };
+		SyncRequester::send_request(sync, io, peer_id, request, GET_NODE_DATA_PACKET, rlp.out());
+	}
+
+	/// Request a proven range of accounts or of an account's storage.
+	fn request_state_range(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, range: StateRange) {
+		let (packet_id, packet, asking) = match range {
+			StateRange::Accounts { ref root, ref origin, ref limit } => {
+				trace!(target: "sync", "{} <- GetAccountRange: {} to {}", peer_id, origin, limit);
+				let mut rlp = RlpStream::new_list(4);
+				rlp.append(root);
+				rlp.append(origin);
+				rlp.append(limit);
+				rlp.append(&MAX_RANGE_BYTES_TO_SEND);
+				(GET_ACCOUNT_RANGE_PACKET, rlp.out(), PeerAsking::AccountRange)
+			},
+			StateRange::Storage { ref account_hash, ref storage_root, ref origin, ref limit, .. } => {
+				trace!(target: "sync", "{} <- GetStorageRange: {} from {}", peer_id, account_hash, origin);
+				let mut rlp = RlpStream::new_list(5);
+				rlp.append(account_hash);
+				rlp.append(storage_root);
+				rlp.append(origin);
+				rlp.append(limit);
+				rlp.append(&MAX_RANGE_BYTES_TO_SEND);
+				(GET_STORAGE_RANGE_PACKET, rlp.out(), PeerAsking::StorageRange)
+			},
+		};
+		let request = PeerRequest { state_range: Some(range), ..PeerRequest::new(asking) };
+		SyncRequester::send_request(sync, io, peer_id, request, packet_id, packet);
 	}
 
 	/// Request headers from a peer by block hash
@@ -105,10 +214,13 @@ impl SyncRequester {
 		rlp.append(&count);
 		rlp.append(&skip);
 		rlp.append(&if reverse {1u32} else {0u32});
-		SyncRequester::send_request(sync, io, peer_id, PeerAsking::BlockHeaders, GET_BLOCK_HEADERS_PACKET, rlp.out());
-		let peer = sync.peers.get_mut(&peer_id).expect("peer_id may originate either from on_packet, where it is already validated or from enumerating self.peers. qed");
-		peer.asking_hash = Some(h.clone());
-		peer.block_set = Some(set);
+		let request = PeerRequest {
+			hash: Some(h.clone()),
+			header_count: if skip == 0 && !reverse { Some(count as usize) } else { None },
+			block_set: Some(set),
+			..PeerRequest::new(PeerAsking::BlockHeaders)
+		};
+		SyncRequester::send_request(sync, io, peer_id, request, GET_BLOCK_HEADERS_PACKET, rlp.out());
 	}
 
 	/// Request block receipts from a peer
@@ -118,10 +230,38 @@ impl SyncRequester {
 		for h in &hashes {
 			rlp.append(&h.clone());
 		}
-		SyncRequester::send_request(sync, io, peer_id, PeerAsking::BlockReceipts, GET_RECEIPTS_PACKET, rlp.out());
-		let peer = sync.peers.get_mut(&peer_id).expect("peer_id may originate either from on_packet, where it is already validated or from enumerating self.peers. qed");
-		peer.asking_blocks = hashes;
-		peer.block_set = Some(set);
+		let request = PeerRequest { blocks: hashes, block_set: Some(set), ..PeerRequest::new(PeerAsking::BlockReceipts) };
+		SyncRequester::send_request(sync, io, peer_id, request, GET_RECEIPTS_PACKET, rlp.out());
+	}
+
+	/// Request announced transactions from a peer, unless a request to it is already pending.
+	pub fn request_pooled_transactions(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId) {
+		let hashes = {
+			let chain = io.chain();
+			match sync.transactions_fetcher.request(peer_id, Instant::now(), |hash| chain.pooled_transaction(hash).is_some()) {
+				Some(hashes) => hashes,
+				None => return,
+			}
+		};
+		trace!(target: "sync", "{} <- GetPooledTransactions: {} entries", peer_id, hashes.len());
+		let mut rlp = RlpStream::new_list(hashes.len());
+		for h in &hashes {
+			rlp.append(h);
+		}
+		// Not tracked with the peer requests, so that it doesn't hold up block downloads.
+		// The response is matched against the pending request in the fetcher instead.
+		let packet = match sync.peers.get_mut(&peer_id) {
+			Some(peer) if peer.request_ids => {
+				let request_id = peer.next_request_id;
+				peer.next_request_id += 1;
+				wrap_request_id(request_id, &rlp.out()).out()
+			},
+			_ => rlp.out(),
+		};
+		if let Err(e) = io.send(peer_id, GET_POOLED_TRANSACTIONS_PACKET, packet) {
+			debug!(target:"sync", "Error sending request: {:?}", e);
+			io.disconnect_peer(peer_id);
+		}
 	}
 
 	/// Request snapshot chunk from a peer.
@@ -129,23 +269,27 @@ impl SyncRequester {
 		trace!(target: "sync", "{} <- GetSnapshotData {:?}", peer_id, chunk);
 		let mut rlp = RlpStream::new_list(1);
 		rlp.append(chunk);
-		SyncRequester::send_request(sync, io, peer_id, PeerAsking::SnapshotData, GET_SNAPSHOT_DATA_PACKET, rlp.out());
+		let request = PeerRequest { snapshot_data: Some(chunk.clone()), ..PeerRequest::new(PeerAsking::SnapshotData) };
+		SyncRequester::send_request(sync, io, peer_id, request, GET_SNAPSHOT_DATA_PACKET, rlp.out());
 	}
 
 	/// Generic request sender
-	fn send_request(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, asking: PeerAsking,  packet_id: PacketId, packet: Bytes) {
+	fn send_request(sync: &mut ChainSync, io: &mut SyncIo, peer_id: PeerId, request: PeerRequest, packet_id: PacketId, packet: Bytes) {
 		if let Some(ref mut peer) = sync.peers.get_mut(&peer_id) {
-			if peer.asking != PeerAsking::Nothing {
-				warn!(target:"sync", "Asking {:?} while requesting {:?}", peer.asking, asking);
+			if !peer.can_request() {
+				warn!(target:"sync", "Asking {:?} while {} requests are pending", request.asking, peer.requests.len());
 			}
-			peer.asking = asking;
-			peer.ask_time = Instant::now();
+			let request_id = peer.next_request_id;
+			peer.next_request_id += 1;
 			// TODO [ToDr] This seems quite fragile. Be careful when protocol is updated.
 			let result = if packet_id >= ETH_PROTOCOL_VERSION_63.1 {
 				io.send_protocol(WARP_SYNC_PROTOCOL_ID, peer_id, packet_id, packet)
+			} else if peer.request_ids {
+				io.send(peer_id, packet_id, wrap_request_id(request_id, &packet).out())
 			} else {
 				io.send(peer_id, packet_id, packet)
 			};
+			peer.requests.insert(request_id, request);
 			if let Err(e) = result {
 				debug!(target:"sync", "Error sending request: {:?}", e);
 				io.disconnect_peer(peer_id);
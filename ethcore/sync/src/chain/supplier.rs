@@ -17,6 +17,7 @@
 use bytes::Bytes;
 use ethcore::client::BlockId;
 use ethcore::header::BlockNumber;
+use ethcore::snapshot::TrieRange;
 use ethereum_types::H256;
 use network::{self, PeerId};
 use parking_lot::RwLock;
@@ -30,26 +31,38 @@ use super::{
 	SyncHandler,
 	RlpResponseResult,
 	PacketDecodeError,
+	ACCOUNT_RANGE_PACKET,
 	BLOCK_BODIES_PACKET,
 	BLOCK_HEADERS_PACKET,
 	CONSENSUS_DATA_PACKET,
+	ETH_PROTOCOL_VERSION_66,
+	GET_ACCOUNT_RANGE_PACKET,
 	GET_BLOCK_BODIES_PACKET,
 	GET_BLOCK_HEADERS_PACKET,
 	GET_NODE_DATA_PACKET,
+	GET_POOLED_TRANSACTIONS_PACKET,
 	GET_RECEIPTS_PACKET,
 	GET_SNAPSHOT_DATA_PACKET,
 	GET_SNAPSHOT_MANIFEST_PACKET,
+	GET_STORAGE_RANGE_PACKET,
 	MAX_BODIES_TO_SEND,
 	MAX_HEADERS_TO_SEND,
 	MAX_NODE_DATA_TO_SEND,
+	MAX_POOLED_TRANSACTIONS_TO_SEND,
+	MAX_RANGE_BYTES_TO_SEND,
 	MAX_RECEIPTS_HEADERS_TO_SEND,
 	MAX_RECEIPTS_TO_SEND,
+	MAX_TRANSACTION_PACKET_SIZE,
 	NODE_DATA_PACKET,
+	POOLED_TRANSACTIONS_PACKET,
 	RECEIPTS_PACKET,
 	SNAPSHOT_DATA_PACKET,
 	SNAPSHOT_MANIFEST_PACKET,
 	STATUS_PACKET,
+	STORAGE_RANGE_PACKET,
 	TRANSACTIONS_PACKET,
+	unwrap_request_id,
+	wrap_request_id,
 };
 
 /// The Chain Sync Supplier: answers requests from peers with available data
@@ -61,22 +74,26 @@ impl SyncSupplier {
 		let rlp = Rlp::new(data);
 
 		let result = match packet_id {
-			GET_BLOCK_BODIES_PACKET => SyncSupplier::return_rlp(io, &rlp, peer,
+			GET_BLOCK_BODIES_PACKET => SyncSupplier::return_eth_rlp(io, &rlp, peer,
 				SyncSupplier::return_block_bodies,
 				|e| format!("Error sending block bodies: {:?}", e)),
 
-			GET_BLOCK_HEADERS_PACKET => SyncSupplier::return_rlp(io, &rlp, peer,
+			GET_BLOCK_HEADERS_PACKET => SyncSupplier::return_eth_rlp(io, &rlp, peer,
 				SyncSupplier::return_block_headers,
 				|e| format!("Error sending block headers: {:?}", e)),
 
-			GET_RECEIPTS_PACKET => SyncSupplier::return_rlp(io, &rlp, peer,
+			GET_RECEIPTS_PACKET => SyncSupplier::return_eth_rlp(io, &rlp, peer,
 				SyncSupplier::return_receipts,
 				|e| format!("Error sending receipts: {:?}", e)),
 
-			GET_NODE_DATA_PACKET => SyncSupplier::return_rlp(io, &rlp, peer,
+			GET_NODE_DATA_PACKET => SyncSupplier::return_eth_rlp(io, &rlp, peer,
 				SyncSupplier::return_node_data,
 				|e| format!("Error sending nodes: {:?}", e)),
 
+			GET_POOLED_TRANSACTIONS_PACKET => SyncSupplier::return_eth_rlp(io, &rlp, peer,
+				SyncSupplier::return_pooled_transactions,
+				|e| format!("Error sending pooled transactions: {:?}", e)),
+
 			GET_SNAPSHOT_MANIFEST_PACKET => SyncSupplier::return_rlp(io, &rlp, peer,
 				SyncSupplier::return_snapshot_manifest,
 				|e| format!("Error sending snapshot manifest: {:?}", e)),
@@ -85,6 +102,14 @@ impl SyncSupplier {
 				SyncSupplier::return_snapshot_data,
 				|e| format!("Error sending snapshot data: {:?}", e)),
 
+			GET_ACCOUNT_RANGE_PACKET => SyncSupplier::return_rlp(io, &rlp, peer,
+				SyncSupplier::return_account_range,
+				|e| format!("Error sending account range: {:?}", e)),
+
+			GET_STORAGE_RANGE_PACKET => SyncSupplier::return_rlp(io, &rlp, peer,
+				SyncSupplier::return_storage_range,
+				|e| format!("Error sending storage range: {:?}", e)),
+
 			STATUS_PACKET => {
 				sync.write().on_packet(io, peer, packet_id, data);
 				Ok(())
@@ -273,6 +298,31 @@ impl SyncSupplier {
 		Ok(Some((RECEIPTS_PACKET, rlp_result)))
 	}
 
+	/// Respond to GetPooledTransactions request
+	fn return_pooled_transactions(io: &SyncIo, r: &Rlp, peer_id: PeerId) -> RlpResponseResult {
+		let mut count = r.item_count().unwrap_or(0);
+		trace!(target: "sync", "{} -> GetPooledTransactions: {} entries", peer_id, count);
+		if count == 0 {
+			debug!(target: "sync", "Empty GetPooledTransactions request, ignoring.");
+			return Ok(None);
+		}
+		count = cmp::min(count, MAX_POOLED_TRANSACTIONS_TO_SEND);
+		let mut added = 0usize;
+		let mut rlp = RlpStream::new();
+		rlp.begin_unbounded_list();
+		for i in 0..count {
+			if let Some(tx) = io.chain().pooled_transaction(&r.val_at::<H256>(i)?) {
+				if !rlp.append_raw_checked(&::rlp::encode(tx.signed()), 1, MAX_TRANSACTION_PACKET_SIZE) {
+					break;
+				}
+				added += 1;
+			}
+		}
+		rlp.complete_unbounded_list();
+		trace!(target: "sync", "{} -> GetPooledTransactions: returned {} entries", peer_id, added);
+		Ok(Some((POOLED_TRANSACTIONS_PACKET, rlp)))
+	}
+
 	/// Respond to GetSnapshotManifest request
 	fn return_snapshot_manifest(io: &SyncIo, r: &Rlp, peer_id: PeerId) -> RlpResponseResult {
 		let count = r.item_count().unwrap_or(0);
@@ -315,6 +365,47 @@ impl SyncSupplier {
 		Ok(Some((SNAPSHOT_DATA_PACKET, rlp)))
 	}
 
+	/// Respond to GetAccountRange request
+	fn return_account_range(io: &SyncIo, r: &Rlp, peer_id: PeerId) -> RlpResponseResult {
+		let root: H256 = r.val_at(0)?;
+		let origin: H256 = r.val_at(1)?;
+		let limit: H256 = r.val_at(2)?;
+		let max_bytes = cmp::min(r.val_at(3)?, MAX_RANGE_BYTES_TO_SEND);
+		trace!(target: "warp", "{} -> GetAccountRange: {} to {} in {}", peer_id, origin, limit, root);
+		let range = io.chain().state_range(&root, &origin, &limit, max_bytes);
+		Ok(Some((ACCOUNT_RANGE_PACKET, SyncSupplier::state_range_rlp(peer_id, range))))
+	}
+
+	/// Respond to GetStorageRange request
+	fn return_storage_range(io: &SyncIo, r: &Rlp, peer_id: PeerId) -> RlpResponseResult {
+		let account_hash: H256 = r.val_at(0)?;
+		let storage_root: H256 = r.val_at(1)?;
+		let origin: H256 = r.val_at(2)?;
+		let limit: H256 = r.val_at(3)?;
+		let max_bytes = cmp::min(r.val_at(4)?, MAX_RANGE_BYTES_TO_SEND);
+		trace!(target: "warp", "{} -> GetStorageRange: {} from {}", peer_id, account_hash, origin);
+		let range = io.chain().storage_range(&account_hash, &storage_root, &origin, &limit, max_bytes);
+		Ok(Some((STORAGE_RANGE_PACKET, SyncSupplier::state_range_rlp(peer_id, range))))
+	}
+
+	/// Entries and proof of a state range, or two empty lists if the trie isn't available.
+	fn state_range_rlp(peer_id: PeerId, range: Option<TrieRange>) -> RlpStream {
+		let range = range.unwrap_or_else(|| {
+			trace!(target: "warp", "{}: No state range to return", peer_id);
+			TrieRange { entries: Vec::new(), proof: Vec::new() }
+		});
+		trace!(target: "warp", "{} <- StateRange: {} entries", peer_id, range.entries.len());
+		let mut rlp = RlpStream::new_list(2);
+		rlp.begin_list(range.entries.len());
+		for &(ref key, ref value) in &range.entries {
+			rlp.begin_list(2);
+			rlp.append(key);
+			rlp.append(value);
+		}
+		rlp.append_list::<Bytes, Bytes>(&range.proof);
+		rlp
+	}
+
 	fn return_rlp<FRlp, FError>(io: &mut SyncIo, rlp: &Rlp, peer: PeerId, rlp_func: FRlp, error_func: FError) -> Result<(), PacketDecodeError>
 		where FRlp : Fn(&SyncIo, &Rlp, PeerId) -> RlpResponseResult,
 			FError : FnOnce(network::Error) -> String
@@ -330,6 +421,21 @@ impl SyncSupplier {
 			_ => Ok(())
 		}
 	}
+
+	/// Respond to an eth request. Requests of eth/66 peers carry a request id, which is echoed
+	/// in the response.
+	fn return_eth_rlp<FRlp, FError>(io: &mut SyncIo, rlp: &Rlp, peer: PeerId, rlp_func: FRlp, error_func: FError) -> Result<(), PacketDecodeError>
+		where FRlp : Fn(&SyncIo, &Rlp, PeerId) -> RlpResponseResult,
+			FError : FnOnce(network::Error) -> String
+	{
+		if io.eth_protocol_version(peer) < ETH_PROTOCOL_VERSION_66.0 {
+			return SyncSupplier::return_rlp(io, rlp, peer, rlp_func, error_func);
+		}
+		let (request_id, request) = unwrap_request_id(rlp)?;
+		SyncSupplier::return_rlp(io, &request, peer, |io, r, peer| {
+			Ok(rlp_func(io, r, peer)?.map(|(packet_id, response)| (packet_id, wrap_request_id(request_id, &response.out()))))
+		}, error_func)
+	}
 }
 
 #[cfg(test)]
@@ -483,4 +589,54 @@ mod test {
 		SyncSupplier::dispatch_packet(&RwLock::new(sync), &mut io, 0usize, GET_RECEIPTS_PACKET, &receipts_request);
 		assert_eq!(1, io.packets.len());
 	}
+	#[test]
+	fn return_unavailable_state_ranges_empty() {
+		let mut client = TestBlockChainClient::new();
+		let queue = RwLock::new(VecDeque::new());
+		let sync = dummy_sync_with_peer(H256::new(), &client);
+		let ss = TestSnapshotService::new();
+		let mut io = TestIo::new(&mut client, &ss, &queue, Some(2usize));
+
+		let mut request = RlpStream::new_list(4);
+		request.append(&H256::from(1));
+		request.append(&H256::zero());
+		request.append(&H256::from(2));
+		request.append(&MAX_RANGE_BYTES_TO_SEND);
+		let result = SyncSupplier::return_account_range(&io, &Rlp::new(&request.out()), 0).unwrap().unwrap();
+		assert_eq!(ACCOUNT_RANGE_PACKET, result.0);
+		assert_eq!(vec![0xc2, 0xc0, 0xc0], result.1.out());
+
+		let mut request = RlpStream::new_list(5);
+		request.append(&H256::from(1));
+		request.append(&H256::from(2));
+		request.append(&H256::zero());
+		request.append(&H256::from(3));
+		request.append(&MAX_RANGE_BYTES_TO_SEND);
+		SyncSupplier::dispatch_packet(&RwLock::new(sync), &mut io, 0usize, GET_STORAGE_RANGE_PACKET, &request.out());
+		assert_eq!(1, io.packets.len());
+		assert_eq!(STORAGE_RANGE_PACKET, io.packets[0].packet_id);
+		assert_eq!(vec![0xc2, 0xc0, 0xc0], io.packets[0].data);
+	}
+
+	#[test]
+	fn echoes_request_ids() {
+		let mut client = TestBlockChainClient::new();
+		let queue = RwLock::new(VecDeque::new());
+		let sync = dummy_sync_with_peer(H256::new(), &client);
+		let ss = TestSnapshotService::new();
+		let mut io = TestIo::new(&mut client, &ss, &queue, Some(2usize));
+		io.eth_protocol_version = ETH_PROTOCOL_VERSION_66.0;
+
+		let mut node_list = RlpStream::new_list(1);
+		node_list.append(&H256::from("ffffffffffffffffffffffffffffffffffffffffffffaaaaaaaaaaaaaaaaaaaa"));
+		let request = wrap_request_id(42, &node_list.out()).out();
+
+		SyncSupplier::dispatch_packet(&RwLock::new(sync), &mut io, 0usize, GET_NODE_DATA_PACKET, &request);
+		assert_eq!(1, io.packets.len());
+		assert_eq!(NODE_DATA_PACKET, io.packets[0].packet_id);
+		let response = Rlp::new(&io.packets[0].data);
+		let (request_id, nodes) = unwrap_request_id(&response).unwrap();
+		assert_eq!(42, request_id);
+		assert_eq!(Ok(1), nodes.item_count());
+	}
 }
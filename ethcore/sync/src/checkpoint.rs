@@ -0,0 +1,245 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Sync from a trusted checkpoint.
+//!
+//! A checkpoint is a block the user trusts to be on the canonical chain. Instead of executing
+//! the chain from genesis, a new node restores the state at or after the checkpoint and
+//! downloads the blocks before it later as ancient blocks, which must include the checkpoint.
+//!
+//! With warp sync the state comes from a snapshot of the checkpoint block or a later one. With
+//! state sync the headers are downloaded from the checkpoint back to the last block we know,
+//! which only need to link up by hash, and give the total difficulty of the checkpoint block,
+//! whose state is then downloaded.
+
+use std::cmp;
+use std::fmt;
+use std::str::FromStr;
+
+use ethcore::header::{BlockNumber, Header};
+use ethereum_types::{H256, U256};
+
+/// Max number of headers to request back from the checkpoint at once.
+pub const MAX_CHECKPOINT_HEADERS: u64 = 512;
+
+/// Trusted block to sync from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+	/// Block hash.
+	pub hash: H256,
+	/// Block number.
+	pub number: BlockNumber,
+}
+
+impl FromStr for Checkpoint {
+	type Err = String;
+
+	/// Parses `<block-hash>@<block-number>`.
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let invalid = || format!("Invalid checkpoint: {}. Expected <block-hash>@<block-number>.", s);
+		let mut parts = s.splitn(2, '@');
+		let hash = parts.next().unwrap_or_default();
+		let hash = if hash.starts_with("0x") { &hash[2..] } else { hash };
+		let hash: H256 = hash.parse().map_err(|_| invalid())?;
+		let number: BlockNumber = parts.next().and_then(|n| n.parse().ok()).ok_or_else(invalid)?;
+		if number == 0 {
+			return Err(invalid());
+		}
+		Ok(Checkpoint { hash, number })
+	}
+}
+
+impl fmt::Display for Checkpoint {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "0x{:x}@{}", self.hash, self.number)
+	}
+}
+
+/// Download of the headers from a checkpoint back to the last block we know. Only the checkpoint
+/// header is kept, the difficulty of the others is added up for the checkpoint's total difficulty.
+pub struct CheckpointHeaders {
+	checkpoint: Checkpoint,
+	/// Hash and number of the last block we know, which the headers must link up to.
+	base: (H256, BlockNumber),
+	/// Checkpoint header, once received.
+	header: Option<Header>,
+	/// Hash and number of the next header expected, `None` once the base is reached.
+	next: Option<(H256, BlockNumber)>,
+	/// Total difficulty of the base and the headers received.
+	total_difficulty: U256,
+}
+
+impl CheckpointHeaders {
+	/// Creates the download of the headers from the checkpoint back to the known block with the
+	/// given hash, number and total difficulty, which must come before the checkpoint.
+	pub fn new(checkpoint: Checkpoint, base_hash: H256, base_number: BlockNumber, base_difficulty: U256) -> Self {
+		debug_assert!(base_number < checkpoint.number, "Checkpoint must come after the base block");
+		CheckpointHeaders {
+			checkpoint,
+			base: (base_hash, base_number),
+			header: None,
+			next: Some((checkpoint.hash, checkpoint.number)),
+			total_difficulty: base_difficulty,
+		}
+	}
+
+	/// Block to request headers in reverse from and how many, unless all were received.
+	pub fn next_request(&self) -> Option<(H256, u64)> {
+		self.next.map(|(hash, number)| (hash, cmp::min(number - self.base.1, MAX_CHECKPOINT_HEADERS)))
+	}
+
+	/// Number of headers still to be received.
+	pub fn remaining(&self) -> BlockNumber {
+		self.next.map_or(0, |(_, number)| number - self.base.1)
+	}
+
+	/// Takes headers in reverse order, starting at the next header expected. Returns false
+	/// and drops them if they don't link up, or don't end at the block we know.
+	pub fn on_headers(&mut self, headers: Vec<Header>) -> bool {
+		let mut next = match self.next {
+			Some(next) if !headers.is_empty() => next,
+			_ => return false,
+		};
+		let (base_hash, base_number) = self.base;
+		let mut total_difficulty = self.total_difficulty;
+		let mut checkpoint_header = None;
+		let mut reached_base = false;
+		for header in headers {
+			if reached_base {
+				// the block we know and those before it aren't needed
+				break;
+			}
+			let (hash, number) = next;
+			if header.number() != number || header.hash() != hash {
+				return false;
+			}
+			let (difficulty, overflow) = total_difficulty.overflowing_add(*header.difficulty());
+			if overflow {
+				return false;
+			}
+			total_difficulty = difficulty;
+			if number - 1 == base_number {
+				if *header.parent_hash() != base_hash {
+					return false;
+				}
+				reached_base = true;
+			}
+			next = (*header.parent_hash(), number - 1);
+			if number == self.checkpoint.number {
+				checkpoint_header = Some(header);
+			}
+		}
+
+		self.next = if reached_base { None } else { Some(next) };
+		self.total_difficulty = total_difficulty;
+		if checkpoint_header.is_some() {
+			self.header = checkpoint_header;
+		}
+		true
+	}
+
+	/// Checkpoint header and its total difficulty, once all headers back to the base are in.
+	pub fn pivot(&self) -> Option<(Header, U256)> {
+		match (self.next, self.header.as_ref()) {
+			(None, Some(header)) => Some((header.clone(), self.total_difficulty)),
+			_ => None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// headers from `length - 1` down to 0, each with a difficulty of 10.
+	fn chain(length: u64) -> Vec<Header> {
+		let mut headers: Vec<Header> = Vec::new();
+		for number in 0..length {
+			let mut header = Header::new();
+			header.set_number(number);
+			header.set_difficulty(U256::from(10));
+			if let Some(parent) = headers.last() {
+				header.set_parent_hash(parent.hash());
+			}
+			headers.push(header);
+		}
+		headers.reverse();
+		headers
+	}
+
+	#[test]
+	fn parses_checkpoint() {
+		let hash = "0xd4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3";
+		let checkpoint: Checkpoint = format!("{}@1000", hash).parse().unwrap();
+		assert_eq!(checkpoint.number, 1000);
+		assert_eq!(checkpoint.to_string(), format!("{}@1000", hash));
+		assert_eq!(format!("{}@1000", &hash[2..]).parse(), Ok(checkpoint));
+
+		assert!(hash.parse::<Checkpoint>().is_err());
+		assert!(format!("{}@0", hash).parse::<Checkpoint>().is_err());
+		assert!(format!("{}@1000@4096", hash).parse::<Checkpoint>().is_err());
+		assert!("0xd4e5@1000".parse::<Checkpoint>().is_err());
+	}
+
+	#[test]
+	fn links_headers_back_to_genesis() {
+		let headers = chain(MAX_CHECKPOINT_HEADERS + 10);
+		let genesis = headers.last().unwrap().clone();
+		let checkpoint = Checkpoint { hash: headers[0].hash(), number: headers[0].number() };
+		let mut download = CheckpointHeaders::new(checkpoint, genesis.hash(), 0, *genesis.difficulty());
+
+		assert_eq!(download.next_request(), Some((checkpoint.hash, MAX_CHECKPOINT_HEADERS)));
+		assert!(!download.on_headers(headers[1..11].to_vec()));
+		assert!(download.on_headers(headers[..MAX_CHECKPOINT_HEADERS as usize].to_vec()));
+		assert!(download.pivot().is_none());
+
+		let next = &headers[MAX_CHECKPOINT_HEADERS as usize];
+		assert_eq!(download.next_request(), Some((next.hash(), 9)));
+		assert_eq!(download.remaining(), 9);
+		// the genesis header we know may come along.
+		assert!(download.on_headers(headers[MAX_CHECKPOINT_HEADERS as usize..].to_vec()));
+		assert_eq!(download.next_request(), None);
+
+		let (header, total_difficulty) = download.pivot().unwrap();
+		assert_eq!(header.hash(), checkpoint.hash);
+		assert_eq!(total_difficulty, U256::from(10 * (MAX_CHECKPOINT_HEADERS + 10)));
+	}
+
+	#[test]
+	fn links_headers_back_to_last_known_block() {
+		let headers = chain(100);
+		let checkpoint = Checkpoint { hash: headers[0].hash(), number: 99 };
+		// we already have the blocks up to #89, with a total difficulty of 900.
+		let known = &headers[10];
+		let mut download = CheckpointHeaders::new(checkpoint, known.hash(), known.number(), U256::from(900));
+
+		assert_eq!(download.next_request(), Some((checkpoint.hash, 10)));
+		assert!(download.on_headers(headers[..10].to_vec()));
+		assert_eq!(download.next_request(), None);
+		assert_eq!(download.pivot().unwrap().1, U256::from(1000));
+	}
+
+	#[test]
+	fn rejects_headers_not_linking_to_known_block() {
+		let headers = chain(10);
+		let checkpoint = Checkpoint { hash: headers[0].hash(), number: headers[0].number() };
+		let mut download = CheckpointHeaders::new(checkpoint, H256::from(1), 0, U256::from(10));
+
+		assert!(!download.on_headers(headers));
+		assert_eq!(download.remaining(), 9);
+		assert!(download.pivot().is_none());
+	}
+}
@@ -0,0 +1,372 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! EIP-2124 fork identifiers.
+//!
+//! A fork identifier summarizes the rules a node runs: a CRC32 checksum of the genesis hash and
+//! every fork block already passed, plus the number of the next known fork. Comparing it against
+//! our own fork history tells whether a peer is on the same chain, merely behind or ahead of us,
+//! or on a chain that has diverged.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crc::crc32;
+use ethcore::client::BlockChainClient;
+use ethcore::header::BlockNumber;
+use ethereum_types::H256;
+use network::EnrFilter;
+use rlp::{Encodable, Decodable, DecoderError, RlpStream, Rlp};
+
+/// Identifier of the rules a node follows at its current head.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForkId {
+	/// CRC32 checksum of the genesis hash and the forks passed so far.
+	pub hash: u32,
+	/// Next fork block, or 0 if no fork is known.
+	pub next: BlockNumber,
+}
+
+impl Encodable for ForkId {
+	fn rlp_append(&self, s: &mut RlpStream) {
+		let hash = vec![(self.hash >> 24) as u8, (self.hash >> 16) as u8, (self.hash >> 8) as u8, self.hash as u8];
+		s.begin_list(2);
+		s.append(&hash);
+		s.append(&self.next);
+	}
+}
+
+impl Decodable for ForkId {
+	fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+		if rlp.item_count()? != 2 {
+			return Err(DecoderError::RlpIncorrectListLen);
+		}
+		let hash: Vec<u8> = rlp.val_at(0)?;
+		if hash.len() != 4 {
+			return Err(DecoderError::RlpInvalidLength);
+		}
+		Ok(ForkId {
+			hash: hash.iter().fold(0, |acc, &byte| (acc << 8) | byte as u32),
+			next: rlp.val_at(1)?,
+		})
+	}
+}
+
+impl fmt::Display for ForkId {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:08x}/{}", self.hash, self.next)
+	}
+}
+
+/// Reason a remote fork identifier is rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForkIdError {
+	/// We are past a fork the remote announces but we don't know of.
+	LocalIncompatibleOrStale,
+	/// The remote is on a chain we don't know of, or it hasn't upgraded for a fork we passed.
+	RemoteStale,
+}
+
+impl fmt::Display for ForkIdError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ForkIdError::LocalIncompatibleOrStale => write!(f, "local node is on an incompatible chain or needs an update"),
+			ForkIdError::RemoteStale => write!(f, "remote node is on an incompatible chain or needs an update"),
+		}
+	}
+}
+
+/// Computes our own fork identifiers and validates the ones of remote peers.
+#[derive(Debug, Clone)]
+pub struct ForkFilter {
+	/// Fork blocks in ascending order.
+	forks: Vec<BlockNumber>,
+	/// Checksums before each fork, and one more after the last.
+	hashes: Vec<u32>,
+}
+
+impl ForkFilter {
+	/// Creates a filter for the chain with the given genesis hash and fork blocks.
+	/// Fork blocks are sorted and duplicates or forks at genesis are ignored.
+	pub fn new(genesis: H256, forks: Vec<BlockNumber>) -> Self {
+		let mut forks = forks;
+		forks.retain(|&block| block != 0);
+		forks.sort();
+		forks.dedup();
+
+		let mut hashes = Vec::with_capacity(forks.len() + 1);
+		hashes.push(crc32::checksum_ieee(&genesis));
+		for fork in &forks {
+			let mut block = [0u8; 8];
+			for (i, byte) in block.iter_mut().enumerate() {
+				*byte = (fork >> (56 - 8 * i)) as u8;
+			}
+			let hash = crc32::update(*hashes.last().expect("starts with the genesis checksum; qed"), &crc32::IEEE_TABLE, &block);
+			hashes.push(hash);
+		}
+
+		ForkFilter { forks, hashes }
+	}
+
+	/// Number of forks passed at the given head.
+	fn passed(&self, head: BlockNumber) -> usize {
+		self.forks.iter().take_while(|&&fork| fork <= head).count()
+	}
+
+	/// Fork identifier to announce with the given head.
+	pub fn current(&self, head: BlockNumber) -> ForkId {
+		let passed = self.passed(head);
+		ForkId {
+			hash: self.hashes[passed],
+			next: self.forks.get(passed).cloned().unwrap_or(0),
+		}
+	}
+
+	/// Validates a remote fork identifier against our chain at the given head.
+	pub fn validate(&self, head: BlockNumber, remote: &ForkId) -> Result<(), ForkIdError> {
+		let passed = self.passed(head);
+
+		// Same rules: compatible unless the remote announces a fork we have already passed.
+		if remote.hash == self.hashes[passed] {
+			return match remote.next {
+				next if next != 0 && head >= next => Err(ForkIdError::LocalIncompatibleOrStale),
+				_ => Ok(()),
+			};
+		}
+
+		// The remote is behind us: it must know the fork that comes next for it.
+		if let Some(i) = self.hashes[..passed].iter().position(|&hash| hash == remote.hash) {
+			return match remote.next == self.forks[i] {
+				true => Ok(()),
+				false => Err(ForkIdError::RemoteStale),
+			};
+		}
+
+		// The remote is ahead of us: it must have passed forks we already know.
+		if self.hashes[passed + 1..].contains(&remote.hash) {
+			return Ok(());
+		}
+
+		Err(ForkIdError::RemoteStale)
+	}
+}
+
+/// Advertises our fork identifier in the `eth` entry of the node record, and skips discovered
+/// nodes announcing an incompatible one.
+pub struct ForkIdEnrFilter {
+	filter: ForkFilter,
+	chain: Arc<BlockChainClient>,
+}
+
+impl ForkIdEnrFilter {
+	/// Creates a record filter following the head of the given chain.
+	pub fn new(filter: ForkFilter, chain: Arc<BlockChainClient>) -> Self {
+		ForkIdEnrFilter { filter, chain }
+	}
+
+	fn head(&self) -> BlockNumber {
+		self.chain.chain_info().best_block_number
+	}
+}
+
+impl EnrFilter for ForkIdEnrFilter {
+	fn key(&self) -> &[u8] {
+		b"eth"
+	}
+
+	fn local_value(&self) -> Vec<u8> {
+		// the entry is a list, with the fork identifier as its first item
+		let mut entry = RlpStream::new_list(1);
+		entry.append(&self.filter.current(self.head()));
+		entry.out()
+	}
+
+	fn value_allowed(&self, value: &[u8]) -> bool {
+		match Rlp::new(value).val_at::<ForkId>(0) {
+			Ok(fork_id) => self.filter.validate(self.head(), &fork_id).is_ok(),
+			Err(_) => false,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rlp;
+	use ethcore::client::{TestBlockChainClient, EachBlockWith};
+	use ethcore::ethereum;
+	use tempdir::TempDir;
+
+	fn mainnet() -> ForkFilter {
+		ForkFilter::new(
+			"d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3".into(),
+			vec![1_150_000, 1_920_000, 2_463_000, 2_675_000, 4_370_000, 7_280_000],
+		)
+	}
+
+	fn id(hash: u32, next: BlockNumber) -> ForkId {
+		ForkId { hash, next }
+	}
+
+	#[test]
+	fn computes_mainnet_fork_ids() {
+		let filter = mainnet();
+		let cases = [
+			(0, id(0xfc64ec04, 1_150_000)),
+			(1_149_999, id(0xfc64ec04, 1_150_000)),
+			(1_150_000, id(0x97c2c34c, 1_920_000)),
+			(1_919_999, id(0x97c2c34c, 1_920_000)),
+			(1_920_000, id(0x91d1f948, 2_463_000)),
+			(2_463_000, id(0x7a64da13, 2_675_000)),
+			(2_675_000, id(0x3edd5b10, 4_370_000)),
+			(4_370_000, id(0xa00bc324, 7_280_000)),
+			(7_279_999, id(0xa00bc324, 7_280_000)),
+			(7_280_000, id(0x668db0af, 0)),
+			(10_000_000, id(0x668db0af, 0)),
+		];
+		for &(head, expected) in &cases {
+			assert_eq!(filter.current(head), expected, "head {}", head);
+		}
+	}
+
+	// Test vectors published in EIP-2124, with every fork up to Muir Glacier.
+	const MAINNET_VECTORS: &[(BlockNumber, u32, BlockNumber)] = &[
+		(0, 0xfc64ec04, 1_150_000),
+		(1_149_999, 0xfc64ec04, 1_150_000),
+		(1_150_000, 0x97c2c34c, 1_920_000),
+		(1_919_999, 0x97c2c34c, 1_920_000),
+		(1_920_000, 0x91d1f948, 2_463_000),
+		(2_462_999, 0x91d1f948, 2_463_000),
+		(2_463_000, 0x7a64da13, 2_675_000),
+		(2_674_999, 0x7a64da13, 2_675_000),
+		(2_675_000, 0x3edd5b10, 4_370_000),
+		(4_369_999, 0x3edd5b10, 4_370_000),
+		(4_370_000, 0xa00bc324, 7_280_000),
+		(7_279_999, 0xa00bc324, 7_280_000),
+		(7_280_000, 0x668db0af, 9_069_000),
+		(9_068_999, 0x668db0af, 9_069_000),
+		(9_069_000, 0x879d6e30, 9_200_000),
+		(9_199_999, 0x879d6e30, 9_200_000),
+		(9_200_000, 0xe029e991, 0),
+	];
+
+	const ROPSTEN_VECTORS: &[(BlockNumber, u32, BlockNumber)] = &[
+		(0, 0x30c7ddbc, 10),
+		(9, 0x30c7ddbc, 10),
+		(10, 0x63760190, 1_700_000),
+		(1_699_999, 0x63760190, 1_700_000),
+		(1_700_000, 0x3ea159c7, 4_230_000),
+		(4_229_999, 0x3ea159c7, 4_230_000),
+		(4_230_000, 0x97b544f3, 4_939_394),
+		(4_939_393, 0x97b544f3, 4_939_394),
+		(4_939_394, 0xd6e2149b, 6_485_846),
+		(6_485_845, 0xd6e2149b, 6_485_846),
+		(6_485_846, 0x4bc66396, 7_117_117),
+		(7_117_116, 0x4bc66396, 7_117_117),
+		(7_117_117, 0x6727ef90, 0),
+	];
+
+	const MAINNET_GENESIS: &str = "d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3";
+	const ROPSTEN_GENESIS: &str = "41941023680923e0fe4d74a34bdac8141f2540e3ae90623718e47d66d1ca4a2d";
+
+	fn assert_vectors(filter: &ForkFilter, vectors: &[(BlockNumber, u32, BlockNumber)]) {
+		for &(head, hash, next) in vectors {
+			assert_eq!(filter.current(head), id(hash, next), "head {}", head);
+		}
+	}
+
+	#[test]
+	fn matches_published_fork_ids() {
+		let mainnet = ForkFilter::new(MAINNET_GENESIS.into(), vec![
+			1_150_000, 1_920_000, 2_463_000, 2_675_000, 4_370_000, 7_280_000, 9_069_000, 9_200_000,
+		]);
+		assert_vectors(&mainnet, MAINNET_VECTORS);
+
+		// Tangerine Whistle is active from genesis, Spurious Dragon forks at block 10.
+		let ropsten = ForkFilter::new(ROPSTEN_GENESIS.into(), vec![
+			0, 10, 1_700_000, 4_230_000, 4_939_394, 6_485_846, 7_117_117,
+		]);
+		assert_vectors(&ropsten, ROPSTEN_VECTORS);
+	}
+
+	#[test]
+	fn bundled_specs_match_published_fork_ids() {
+		let tempdir = TempDir::new("").unwrap();
+
+		// The bundled specs predate Petersburg, so they match up to the last fork they know.
+		let mainnet = ethereum::new_foundation(&tempdir.path());
+		let filter = ForkFilter::new(mainnet.genesis_header().hash(), mainnet.hard_forks());
+		assert_vectors(&filter, &MAINNET_VECTORS[..10]);
+
+		let ropsten = ethereum::new_ropsten(&tempdir.path());
+		let filter = ForkFilter::new(ropsten.genesis_header().hash(), ropsten.hard_forks());
+		assert_vectors(&filter, &ROPSTEN_VECTORS[..6]);
+	}
+
+	#[test]
+	fn validates_remote_fork_ids() {
+		let filter = mainnet();
+		let cases = [
+			// Same rules, no next fork known by either side.
+			(7_987_396, id(0x668db0af, 0), Ok(())),
+			// Same rules, the remote announces a fork we don't know of yet.
+			(7_987_396, id(0x668db0af, BlockNumber::max_value()), Ok(())),
+			// The remote is still syncing and knows the fork it's about to pass.
+			(7_987_396, id(0xa00bc324, 7_280_000), Ok(())),
+			(7_987_396, id(0x3edd5b10, 4_370_000), Ok(())),
+			// We are still syncing and the remote has passed forks we know.
+			(7_279_999, id(0x668db0af, 0), Ok(())),
+			(1_150_000, id(0xa00bc324, 7_280_000), Ok(())),
+			// The remote never upgraded for a fork we passed.
+			(7_987_396, id(0xa00bc324, 0), Err(ForkIdError::RemoteStale)),
+			// The remote announces a fork we have passed without knowing it.
+			(88_888_888, id(0x668db0af, 88_888_888), Err(ForkIdError::LocalIncompatibleOrStale)),
+			// The remote is on another chain.
+			(7_987_396, id(0xafec6b27, 0), Err(ForkIdError::RemoteStale)),
+		];
+		for &(head, remote, expected) in &cases {
+			assert_eq!(filter.validate(head, &remote), expected, "head {} remote {}", head, remote);
+		}
+	}
+
+	#[test]
+	fn encodes_fork_ids() {
+		let fork_id = id(0xfc64ec04, 1_150_000);
+		let encoded = rlp::encode(&fork_id).into_vec();
+		assert_eq!(encoded, vec![0xc9, 0x84, 0xfc, 0x64, 0xec, 0x04, 0x83, 0x11, 0x8c, 0x30]);
+		assert_eq!(rlp::decode::<ForkId>(&encoded).unwrap(), fork_id);
+
+		assert!(rlp::decode::<ForkId>(&[0xc5, 0x83, 0xfc, 0x64, 0xec, 0x80]).is_err());
+	}
+
+	#[test]
+	fn filters_node_record_entries() {
+		let chain = TestBlockChainClient::new();
+		chain.add_blocks(10, EachBlockWith::Nothing);
+		let genesis = chain.chain_info().genesis_hash;
+		let filter = ForkIdEnrFilter::new(ForkFilter::new(genesis, vec![5, 20]), Arc::new(chain));
+
+		let local = filter.local_value();
+		assert_eq!(Rlp::new(&local).val_at::<ForkId>(0).unwrap(), ForkFilter::new(genesis, vec![5, 20]).current(10));
+		assert!(filter.value_allowed(&local));
+
+		let other_chain = ForkFilter::new(H256::zero(), vec![5, 20]).current(10);
+		let mut entry = RlpStream::new_list(1);
+		entry.append(&other_chain);
+		assert!(!filter.value_allowed(&entry.out()));
+		assert!(!filter.value_allowed(&[0xc0]));
+	}
+}
@@ -22,6 +22,7 @@
 //!
 
 extern crate common_types as types;
+extern crate crc;
 extern crate ethcore_network as network;
 extern crate ethcore_network_devp2p as devp2p;
 extern crate parity_bytes as bytes;
@@ -43,6 +44,7 @@ extern crate ethcore_light as light;
 #[cfg(test)] extern crate kvdb_memorydb;
 #[cfg(test)] extern crate rustc_hex;
 #[cfg(test)] extern crate ethcore_private_tx;
+#[cfg(test)] extern crate tempdir;
 
 #[macro_use]
 extern crate macros;
@@ -60,6 +62,12 @@ mod sync_io;
 mod private_tx;
 mod snapshot;
 mod transactions_stats;
+mod transactions_fetcher;
+mod state_sync;
+mod checkpoint;
+mod fork_id;
+mod reputation;
+mod request_size;
 
 pub mod light_sync;
 
@@ -70,6 +78,8 @@ mod api;
 
 pub use api::*;
 pub use chain::{SyncStatus, SyncState};
-pub use devp2p::validate_node_url;
-pub use network::{NonReservedPeerMode, Error, ErrorKind, ConnectionFilter, ConnectionDirection};
+pub use checkpoint::Checkpoint;
+pub use fork_id::{ForkId, ForkFilter, ForkIdError, ForkIdEnrFilter};
+pub use devp2p::{validate_node_url, validate_enrtree_url, validate_dns_server};
+pub use network::{NonReservedPeerMode, Error, ErrorKind, ConnectionFilter, ConnectionDirection, TrafficStats};
 pub use private_tx::{PrivateTxHandler, NoopPrivateTxHandler, SimplePrivateTxHandler};
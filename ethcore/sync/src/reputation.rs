@@ -0,0 +1,293 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Peer reputation.
+//!
+//! Every peer conduct we notice adjusts a score kept by node id, so it survives reconnects.
+//! Scores decay towards neutral over time, so old offenses are eventually forgiven. The rating
+//! used to prefer peers adds a bonus for the throughput of their responses, which decays the
+//! same way. Peers whose score falls below the ban threshold are banned for a day, and bans
+//! are kept across restarts.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use ethereum_types::H512;
+use rlp::{Rlp, RlpStream};
+
+type NodeId = H512;
+
+/// Time for a score to decay to half its value.
+const SCORE_HALF_LIFE: Duration = Duration::from_secs(15 * 60);
+const MAX_SCORE: f64 = 100.0;
+/// Peers scoring less are not trusted with headers or snapshot selection.
+const TRUST_THRESHOLD: f64 = -30.0;
+/// Peers scoring less are banned.
+const BAN_THRESHOLD: f64 = -100.0;
+const BAN_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+/// Response throughput worth a rating point, in bytes per second.
+const BANDWIDTH_UNIT: f64 = 64.0 * 1024.0;
+const MAX_BANDWIDTH_RATING: f64 = 10.0;
+/// Weight of the latest response in the throughput moving average.
+const THROUGHPUT_WEIGHT: f64 = 0.2;
+
+/// Peer conduct affecting its reputation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Conduct {
+	/// Response with the data asked for.
+	Useful,
+	/// Response with nothing we could use.
+	Useless,
+	/// Request not answered in time.
+	Timeout,
+	/// Malformed or unexpected packet.
+	InvalidPacket,
+	/// Block data failing verification.
+	InvalidBlock,
+}
+
+impl Conduct {
+	fn score(&self) -> f64 {
+		match *self {
+			Conduct::Useful => 1.0,
+			Conduct::Useless => -5.0,
+			Conduct::Timeout => -10.0,
+			Conduct::InvalidPacket => -25.0,
+			Conduct::InvalidBlock => -50.0,
+		}
+	}
+}
+
+struct PeerReputation {
+	score: f64,
+	updated: Instant,
+	/// Moving average of response throughput in bytes per second.
+	throughput: f64,
+}
+
+impl PeerReputation {
+	fn new() -> Self {
+		PeerReputation {
+			score: 0.0,
+			updated: Instant::now(),
+			throughput: 0.0,
+		}
+	}
+
+	fn decay(&self, now: Instant) -> f64 {
+		let elapsed = now.duration_since(self.updated);
+		let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+		0.5f64.powf(elapsed / SCORE_HALF_LIFE.as_secs() as f64)
+	}
+
+	fn score(&self, now: Instant) -> f64 {
+		self.score * self.decay(now)
+	}
+
+	fn throughput(&self, now: Instant) -> f64 {
+		self.throughput * self.decay(now)
+	}
+
+	fn add(&mut self, score: f64) {
+		let now = Instant::now();
+		self.score = (self.score(now) + score).min(MAX_SCORE);
+		self.throughput = self.throughput(now);
+		self.updated = now;
+	}
+
+	fn rating(&self, now: Instant) -> i32 {
+		let bandwidth = (self.throughput(now) / BANDWIDTH_UNIT).min(MAX_BANDWIDTH_RATING);
+		(self.score(now) + bandwidth).round() as i32
+	}
+
+	// Whether neither the score nor the throughput affect the rating any more.
+	fn is_neutral(&self, now: Instant) -> bool {
+		self.score(now).abs() < 0.5 && self.throughput(now) < BANDWIDTH_UNIT / 2.0
+	}
+}
+
+/// Scores and bans of peers.
+pub struct Reputation {
+	peers: HashMap<NodeId, PeerReputation>,
+	/// Banned peers and the unix time their ban ends.
+	bans: HashMap<NodeId, u64>,
+	/// File the bans are kept in.
+	path: Option<PathBuf>,
+}
+
+impl Reputation {
+	/// Create a new instance, loading bans from the given file.
+	pub fn new(path: Option<PathBuf>) -> Self {
+		let mut bans = path.as_ref().map_or_else(HashMap::new, |path| load_bans(path));
+		let now = unix_now();
+		bans.retain(|_, until| *until > now);
+		Reputation {
+			peers: HashMap::new(),
+			bans,
+			path,
+		}
+	}
+
+	/// Note a peer conduct. Returns `true` if the peer got banned for it.
+	pub fn note(&mut self, node: &NodeId, conduct: Conduct) -> bool {
+		let score = {
+			let peer = self.peers.entry(*node).or_insert_with(PeerReputation::new);
+			peer.add(conduct.score());
+			peer.score
+		};
+		trace!(target: "sync", "Peer {} reputation {:.1} after {:?}", node, score, conduct);
+		if score > BAN_THRESHOLD {
+			return false;
+		}
+		debug!(target: "sync", "Banning peer {}", node);
+		self.peers.remove(node);
+		self.bans.insert(*node, unix_now() + BAN_DURATION.as_secs());
+		self.save();
+		true
+	}
+
+	/// Note a useful response and the time it took to arrive.
+	pub fn note_response(&mut self, node: &NodeId, bytes: usize, elapsed: Duration) {
+		self.note(node, Conduct::Useful);
+		if let Some(peer) = self.peers.get_mut(node) {
+			let secs = (elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9).max(0.001);
+			peer.throughput += (bytes as f64 / secs - peer.throughput) * THROUGHPUT_WEIGHT;
+		}
+	}
+
+	/// Rating to order peers by.
+	pub fn rating(&self, node: &NodeId) -> i32 {
+		self.peers.get(node).map_or(0, |peer| peer.rating(Instant::now()))
+	}
+
+	/// Whether the peer's reputation allows trusting it with headers and snapshot selection.
+	pub fn is_trusted(&self, node: &NodeId) -> bool {
+		self.peers.get(node).map_or(true, |peer| peer.score(Instant::now()) >= TRUST_THRESHOLD)
+	}
+
+	/// Whether the peer is currently banned.
+	pub fn is_banned(&self, node: &NodeId) -> bool {
+		self.bans.get(node).map_or(false, |until| *until > unix_now())
+	}
+
+	/// Forget scores that decayed to neutral and bans that ended.
+	pub fn maintain(&mut self) {
+		let now = Instant::now();
+		self.peers.retain(|_, peer| !peer.is_neutral(now));
+		let unix_now = unix_now();
+		let before = self.bans.len();
+		self.bans.retain(|_, until| *until > unix_now);
+		if self.bans.len() != before {
+			self.save();
+		}
+	}
+
+	fn save(&self) {
+		let path = match self.path {
+			Some(ref path) => path,
+			None => return,
+		};
+		let mut s = RlpStream::new_list(self.bans.len());
+		for (node, until) in &self.bans {
+			s.begin_list(2).append(node).append(until);
+		}
+		if let Err(e) = fs::write(path, s.out()) {
+			warn!(target: "sync", "Error saving peer bans to {}: {:?}", path.display(), e);
+		}
+	}
+}
+
+fn load_bans(path: &Path) -> HashMap<NodeId, u64> {
+	let data = match fs::read(path) {
+		Ok(data) => data,
+		Err(_) => return HashMap::new(),
+	};
+	let rlp = Rlp::new(&data);
+	rlp.iter()
+		.map(|ban| -> Result<(NodeId, u64), ::rlp::DecoderError> { Ok((ban.val_at(0)?, ban.val_at(1)?)) })
+		.collect::<Result<HashMap<_, _>, _>>()
+		.unwrap_or_else(|e| {
+			warn!(target: "sync", "Error loading peer bans from {}: {:?}", path.display(), e);
+			HashMap::new()
+		})
+}
+
+fn unix_now() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tempdir::TempDir;
+
+	#[test]
+	fn scores_decay() {
+		let mut peer = PeerReputation::new();
+		peer.add(Conduct::InvalidBlock.score());
+		let now = peer.updated;
+		assert_eq!(peer.score(now).round() as i32, -50);
+		assert_eq!(peer.score(now + SCORE_HALF_LIFE).round() as i32, -25);
+		assert_eq!(peer.score(now + SCORE_HALF_LIFE * 4).round() as i32, -3);
+	}
+
+	#[test]
+	fn throughput_decays_to_neutral() {
+		let mut peer = PeerReputation::new();
+		peer.add(Conduct::Useful.score());
+		peer.throughput = BANDWIDTH_UNIT * 4.0;
+		let now = peer.updated;
+		assert_eq!(peer.rating(now), 5);
+		assert_eq!(peer.rating(now + SCORE_HALF_LIFE), 3);
+		assert!(!peer.is_neutral(now + SCORE_HALF_LIFE * 3));
+		assert!(peer.is_neutral(now + SCORE_HALF_LIFE * 4));
+	}
+
+	#[test]
+	fn rates_by_conduct_and_bandwidth() {
+		let mut reputation = Reputation::new(None);
+		let (fast, slow, bad) = (NodeId::from(1), NodeId::from(2), NodeId::from(3));
+		reputation.note_response(&fast, 1024 * 1024, Duration::from_millis(500));
+		reputation.note_response(&slow, 1024, Duration::from_millis(500));
+		reputation.note(&bad, Conduct::InvalidPacket);
+		reputation.note(&bad, Conduct::Timeout);
+
+		assert!(reputation.rating(&fast) > reputation.rating(&slow));
+		assert!(reputation.rating(&slow) > reputation.rating(&NodeId::from(4)));
+		assert!(reputation.rating(&NodeId::from(4)) > reputation.rating(&bad));
+		assert!(reputation.is_trusted(&slow));
+		assert!(!reputation.is_trusted(&bad));
+	}
+
+	#[test]
+	fn bans_persist() {
+		let tempdir = TempDir::new("").unwrap();
+		let path = tempdir.path().join("peer_bans");
+		let node = NodeId::from(1);
+		{
+			let mut reputation = Reputation::new(Some(path.clone()));
+			assert!(!reputation.note(&node, Conduct::InvalidBlock));
+			assert!(!reputation.is_banned(&node));
+			assert!(reputation.note(&node, Conduct::InvalidBlock));
+			assert!(reputation.is_banned(&node));
+		}
+
+		let reputation = Reputation::new(Some(path));
+		assert!(reputation.is_banned(&node));
+		assert!(!reputation.is_banned(&NodeId::from(2)));
+	}
+}
@@ -0,0 +1,189 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Adaptive sizing of block header, body and receipt requests.
+//!
+//! Each peer is asked for as many items as it delivered in a target response time, going by a
+//! moving average of its throughput. Pipelined requests are answered one after the other, so
+//! a response is only timed from the later of its request and the previous response.
+//!
+//! Responses of different peers complete blocks out of order. `BlockCollection` is the reorder
+//! buffer for them: it keeps the blocks until all blocks before them are complete, and only
+//! hands the consecutive complete blocks after the last imported one to the import.
+
+use std::cmp;
+use std::time::{Duration, Instant};
+
+/// Block headers asked for at most, which fill the gap between two subchain heads. Peers are
+/// asked for that many until they turn out to be slow.
+pub const MAX_HEADERS_TO_REQUEST: usize = 128;
+/// Block bodies asked for until the peer throughput is known.
+pub const INITIAL_BODIES_TO_REQUEST: usize = 32;
+pub const MAX_BODIES_TO_REQUEST: usize = 128;
+/// Block receipts asked for until the peer throughput is known.
+pub const INITIAL_RECEIPTS_TO_REQUEST: usize = 128;
+pub const MAX_RECEIPTS_TO_REQUEST: usize = 256;
+const MIN_REQUEST_SIZE: usize = 4;
+/// Time a response should take to arrive.
+const TARGET_RESPONSE_TIME: Duration = Duration::from_millis(1500);
+/// A request is stalled when nothing arrived for it for that long.
+const STALL_TIMEOUT: Duration = Duration::from_secs(5);
+/// Weight of the latest response in the throughput moving average.
+const RATE_WEIGHT: f64 = 0.3;
+
+/// Number of items to ask a peer for at once.
+#[derive(Debug, Clone)]
+pub struct RequestSize {
+	size: usize,
+	max: usize,
+	/// Moving average of items delivered per second.
+	rate: Option<f64>,
+	/// Time the last response arrived.
+	last_response: Option<Instant>,
+}
+
+impl RequestSize {
+	/// Request size for consecutive block headers.
+	pub fn headers() -> Self {
+		RequestSize::new(MAX_HEADERS_TO_REQUEST, MAX_HEADERS_TO_REQUEST)
+	}
+
+	/// Request size for block bodies.
+	pub fn bodies() -> Self {
+		RequestSize::new(INITIAL_BODIES_TO_REQUEST, MAX_BODIES_TO_REQUEST)
+	}
+
+	/// Request size for block receipts.
+	pub fn receipts() -> Self {
+		RequestSize::new(INITIAL_RECEIPTS_TO_REQUEST, MAX_RECEIPTS_TO_REQUEST)
+	}
+
+	fn new(initial: usize, max: usize) -> Self {
+		RequestSize {
+			size: initial,
+			max,
+			rate: None,
+			last_response: None,
+		}
+	}
+
+	/// Number of items to ask for.
+	pub fn get(&self) -> usize {
+		self.size
+	}
+
+	/// Note a response delivering `received` of the `asked` items of a request sent at `sent`.
+	pub fn on_response(&mut self, asked: usize, received: usize, sent: Instant, now: Instant) {
+		let elapsed = self.elapsed(sent, now);
+		self.last_response = Some(now);
+		if received == 0 {
+			return;
+		}
+		let rate = received as f64 / secs(elapsed).max(0.001);
+		let rate = self.rate.map_or(rate, |average| average + (rate - average) * RATE_WEIGHT);
+		self.rate = Some(rate);
+		let mut size = (rate * secs(TARGET_RESPONSE_TIME)) as usize;
+		if received < asked {
+			// The peer limits the size of its responses
+			size = cmp::min(size, received);
+		}
+		self.size = cmp::max(MIN_REQUEST_SIZE, cmp::min(size, self.max));
+	}
+
+	/// Whether a request sent at `sent` is taking too long, so its items should be asked from
+	/// another peer.
+	pub fn is_stalled(&self, sent: Instant, now: Instant) -> bool {
+		self.elapsed(sent, now) > STALL_TIMEOUT
+	}
+
+	/// Note a stalled request. The next requests are smaller.
+	pub fn on_stall(&mut self, now: Instant) {
+		self.last_response = Some(now);
+		self.rate = self.rate.map(|rate| rate / 2.0);
+		self.size = cmp::max(MIN_REQUEST_SIZE, self.size / 2);
+	}
+
+	/// Time spent on a request sent at `sent`, since the later of the request and the previous response.
+	fn elapsed(&self, sent: Instant, now: Instant) -> Duration {
+		let start = self.last_response.map_or(sent, |last| cmp::max(last, sent));
+		if now > start { now - start } else { Duration::from_secs(0) }
+	}
+}
+
+fn secs(duration: Duration) -> f64 {
+	duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sizes_requests_by_throughput() {
+		let now = Instant::now();
+		let mut fast = RequestSize::bodies();
+		let mut slow = RequestSize::bodies();
+		for i in 1..20 {
+			let sent = now + Duration::from_secs(30 * i);
+			let asked = fast.get();
+			fast.on_response(asked, asked, sent, sent + Duration::from_millis(100));
+			// 10 bodies per second
+			let asked = slow.get();
+			slow.on_response(asked, asked, sent, sent + Duration::from_millis(100 * asked as u64));
+		}
+		assert_eq!(fast.get(), MAX_BODIES_TO_REQUEST);
+		assert!(slow.get() < INITIAL_BODIES_TO_REQUEST);
+		assert!(slow.get() >= MIN_REQUEST_SIZE);
+	}
+
+	#[test]
+	fn shrinks_header_requests_for_slow_peers() {
+		let now = Instant::now();
+		let mut size = RequestSize::headers();
+		assert_eq!(size.get(), MAX_HEADERS_TO_REQUEST);
+		for i in 1..10 {
+			// 20 headers per second
+			let sent = now + Duration::from_secs(30 * i);
+			let asked = size.get();
+			size.on_response(asked, asked, sent, sent + Duration::from_millis(50 * asked as u64));
+		}
+		assert!(size.get() < MAX_HEADERS_TO_REQUEST / 2);
+		assert!(size.get() >= MIN_REQUEST_SIZE);
+	}
+
+	#[test]
+	fn times_pipelined_responses_from_previous_response() {
+		let now = Instant::now();
+		let mut size = RequestSize::receipts();
+		// Four requests sent at once, answered every 500ms
+		for i in 1..5 {
+			size.on_response(128, 128, now, now + Duration::from_millis(500 * i));
+		}
+		assert_eq!(size.get(), MAX_RECEIPTS_TO_REQUEST);
+		assert!(!size.is_stalled(now, now + Duration::from_secs(6)));
+		assert!(size.is_stalled(now, now + Duration::from_secs(8)));
+	}
+
+	#[test]
+	fn shrinks_for_partial_responses_and_stalls() {
+		let now = Instant::now();
+		let mut size = RequestSize::bodies();
+		size.on_response(32, 20, now, now + Duration::from_millis(10));
+		assert_eq!(size.get(), 20);
+		size.on_stall(now + Duration::from_secs(6));
+		assert_eq!(size.get(), 10);
+	}
+}
@@ -0,0 +1,561 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Download of the state of a recent block without a snapshot.
+//!
+//! A pivot block is picked a few blocks behind the best block of a peer. Its state is
+//! downloaded as ranges of the account trie, split in intervals fetched in parallel, and
+//! ranges of the storage of every account that has some, all proven against the state root.
+//! When peers prune the pivot state, a later pivot is picked and the download carries on
+//! where it was: once all ranges are in, the state restoration heals whatever changed in
+//! the meantime by fetching the missing trie nodes by hash.
+
+use std::cmp;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use ethcore::header::{BlockNumber, Header};
+use ethereum_types::{H256, U256};
+use fastmap::H256FastMap;
+use hash::keccak;
+use network::PeerId;
+use rlp::{Rlp, RlpStream};
+use triehash_ethereum::ordered_trie_root;
+
+/// Distance of the pivot from the best block of the peer it's picked from.
+pub const PIVOT_DISTANCE: BlockNumber = 32;
+/// Maximum number of trie nodes and codes asked for in a single request.
+pub const MAX_NODE_DATA_TO_REQUEST: usize = 384;
+/// Number of intervals of the account trie downloaded in parallel.
+const ACCOUNT_TASKS: usize = 16;
+/// Time after which a requested node is asked for again if it's still missing.
+const NODE_DATA_RETRY: Duration = Duration::from_secs(20);
+
+/// Range of the account trie or of an account's storage to download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateRange {
+	/// Accounts of the state trie with the given root.
+	Accounts {
+		/// Root of the state trie.
+		root: H256,
+		/// First account hash of the range.
+		origin: H256,
+		/// Last account hash of the range.
+		limit: H256,
+	},
+	/// Storage of an account.
+	Storage {
+		/// Hash of the account address.
+		account_hash: H256,
+		/// RLP-encoded account.
+		account: Bytes,
+		/// Root of the account's storage trie.
+		storage_root: H256,
+		/// First storage key hash of the range.
+		origin: H256,
+		/// Last storage key hash of the range.
+		limit: H256,
+	},
+}
+
+impl StateRange {
+	/// Root of the trie the range belongs to.
+	pub fn root(&self) -> H256 {
+		match *self {
+			StateRange::Accounts { root, .. } => root,
+			StateRange::Storage { storage_root, .. } => storage_root,
+		}
+	}
+
+	/// First key of the range.
+	pub fn origin(&self) -> H256 {
+		match *self {
+			StateRange::Accounts { origin, .. } | StateRange::Storage { origin, .. } => origin,
+		}
+	}
+
+	/// Last key of the range.
+	pub fn limit(&self) -> H256 {
+		match *self {
+			StateRange::Accounts { limit, .. } | StateRange::Storage { limit, .. } => limit,
+		}
+	}
+}
+
+struct AccountTask {
+	next: H256,
+	limit: H256,
+	fetching: bool,
+}
+
+struct StorageTask {
+	account_hash: H256,
+	account: Bytes,
+	storage_root: H256,
+	next: H256,
+	fetching: bool,
+}
+
+/// Block whose state is about to be downloaded, while its body and receipts are retrieved.
+struct Pivot {
+	header: Header,
+	total_difficulty: U256,
+	body: Option<(Bytes, Bytes)>,
+}
+
+/// Tracks the pivot block and the parts of its state still to be downloaded.
+pub struct StateSync {
+	/// Block whose state is downloaded.
+	pivot: Option<Header>,
+	/// Best block and total difficulty of the peer the next pivot is picked from.
+	pivot_request: Option<(H256, U256)>,
+	/// Next pivot being retrieved.
+	next_pivot: Option<Pivot>,
+	/// Account trie intervals not downloaded yet.
+	account_tasks: Vec<AccountTask>,
+	/// Accounts whose storage is not downloaded yet.
+	storage_tasks: Vec<StorageTask>,
+	/// Whether the state restoration was told that all ranges were fed.
+	ranges_done: bool,
+	/// Trie nodes and codes requested lately.
+	requested_nodes: H256FastMap<Instant>,
+	/// Peers that don't have the pivot state anymore.
+	stale_peers: HashSet<PeerId>,
+	/// Time the state restoration was begun.
+	started: Option<Instant>,
+}
+
+impl StateSync {
+	/// Creates the download of the whole account trie. Nothing is downloaded until a pivot is set.
+	pub fn new() -> Self {
+		let step = U256::max_value() / U256::from(ACCOUNT_TASKS) + U256::one();
+		let account_tasks = (0..ACCOUNT_TASKS)
+			.map(|i| {
+				let origin = step * U256::from(i);
+				AccountTask {
+					next: H256::from(origin),
+					limit: H256::from(origin + (step - U256::one())),
+					fetching: false,
+				}
+			})
+			.collect();
+
+		StateSync {
+			pivot: None,
+			pivot_request: None,
+			next_pivot: None,
+			account_tasks,
+			storage_tasks: Vec::new(),
+			ranges_done: false,
+			requested_nodes: H256FastMap::default(),
+			stale_peers: HashSet::new(),
+			started: None,
+		}
+	}
+
+	/// Block whose state is downloaded.
+	pub fn pivot(&self) -> Option<&Header> {
+		self.pivot.as_ref()
+	}
+
+	/// Time the state restoration was begun.
+	pub fn started(&self) -> Option<Instant> {
+		self.started
+	}
+
+	/// Notes that headers were requested in reverse from a peer's best block, with the
+	/// peer's total difficulty, to pick the next pivot from.
+	pub fn request_pivot(&mut self, latest: H256, total_difficulty: U256) {
+		self.pivot_request = Some((latest, total_difficulty));
+		self.next_pivot = None;
+	}
+
+	/// Makes a checkpoint block with a known total difficulty the next pivot.
+	pub fn request_checkpoint(&mut self, header: Header, total_difficulty: U256) {
+		self.pivot_request = None;
+		self.next_pivot = Some(Pivot { header, total_difficulty, body: None });
+	}
+
+	/// Picks the next pivot from the requested headers. Returns it unless the headers don't
+	/// link up to the requested block, or there are too few of them.
+	pub fn on_pivot_headers(&mut self, mut headers: Vec<Header>) -> Option<&Header> {
+		let (latest, mut total_difficulty) = self.pivot_request.take()?;
+		let expected = match headers.first() {
+			Some(header) if header.hash() == latest => cmp::min(PIVOT_DISTANCE, header.number()) as usize + 1,
+			_ => return None,
+		};
+		if headers.len() != expected || headers.windows(2).any(|pair| *pair[0].parent_hash() != pair[1].hash()) {
+			return None;
+		}
+		for header in &headers[..expected - 1] {
+			let (difficulty, overflow) = total_difficulty.overflowing_sub(*header.difficulty());
+			if overflow {
+				return None;
+			}
+			total_difficulty = difficulty;
+		}
+
+		let header = headers.pop().expect("headers are not empty; qed");
+		self.next_pivot = Some(Pivot { header, total_difficulty, body: None });
+		self.next_pivot.as_ref().map(|pivot| &pivot.header)
+	}
+
+	/// Header of the next pivot being retrieved.
+	pub fn next_pivot(&self) -> Option<&Header> {
+		self.next_pivot.as_ref().map(|pivot| &pivot.header)
+	}
+
+	/// Drops the next pivot.
+	pub fn cancel_pivot(&mut self) {
+		self.pivot_request = None;
+		self.next_pivot = None;
+	}
+
+	/// Takes the RLP-encoded body of the next pivot. Returns false and drops the pivot if
+	/// the body doesn't match its header.
+	pub fn on_pivot_body(&mut self, body: &[u8]) -> bool {
+		let body = Rlp::new(body);
+		let matches = match (self.next_pivot.as_mut(), body.at(0), body.at(1)) {
+			(Some(pivot), Ok(transactions), Ok(uncles)) => {
+				let transactions_root = ordered_trie_root(transactions.iter().map(|r| r.as_raw()));
+				if transactions_root == *pivot.header.transactions_root() && keccak(uncles.as_raw()) == *pivot.header.uncles_hash() {
+					pivot.body = Some((transactions.as_raw().to_vec(), uncles.as_raw().to_vec()));
+					true
+				} else {
+					false
+				}
+			},
+			_ => false,
+		};
+		if !matches {
+			self.cancel_pivot();
+		}
+		matches
+	}
+
+	/// Takes the RLP-encoded receipts of the next pivot and moves the download to it.
+	/// Returns the RLP-encoded pivot block and its total difficulty, or `None` if the
+	/// receipts don't match its header, which drops the pivot.
+	pub fn on_pivot_receipts(&mut self, receipts: &[u8], now: Instant) -> Option<(Bytes, U256)> {
+		let pivot = self.next_pivot.take()?;
+		let (transactions, uncles) = pivot.body?;
+		let receipts = Rlp::new(receipts);
+		if !receipts.is_list() || ordered_trie_root(receipts.iter().map(|r| r.as_raw())) != *pivot.header.receipts_root() {
+			return None;
+		}
+
+		let mut block = RlpStream::new_list(3);
+		block.append(&pivot.header);
+		block.append_raw(&transactions, 1);
+		block.append_raw(&uncles, 1);
+
+		self.set_pivot(pivot.header, now);
+		Some((block.out(), pivot.total_difficulty))
+	}
+
+	/// Moves the download to a new pivot. Account ranges carry on from where they are, the
+	/// storage not downloaded yet is left for healing as its root may have changed.
+	fn set_pivot(&mut self, header: Header, now: Instant) {
+		if self.pivot.is_some() {
+			self.storage_tasks.clear();
+		}
+		self.pivot = Some(header);
+		self.stale_peers.clear();
+		self.started.get_or_insert(now);
+	}
+
+	/// Picks the next range to download: storage first, so that accounts are written soon.
+	pub fn next_range(&mut self) -> Option<StateRange> {
+		let root = *self.pivot.as_ref()?.state_root();
+		if let Some(task) = self.storage_tasks.iter_mut().find(|task| !task.fetching) {
+			task.fetching = true;
+			return Some(StateRange::Storage {
+				account_hash: task.account_hash,
+				account: task.account.clone(),
+				storage_root: task.storage_root,
+				origin: task.next,
+				limit: H256::from(U256::max_value()),
+			});
+		}
+		self.account_tasks.iter_mut().find(|task| !task.fetching).map(|task| {
+			task.fetching = true;
+			StateRange::Accounts { root, origin: task.next, limit: task.limit }
+		})
+	}
+
+	/// Makes a range that was requested available to be requested again.
+	pub fn clear_range(&mut self, range: &StateRange) {
+		if let Some(fetching) = self.task_fetching(range) {
+			*fetching = false;
+		}
+	}
+
+	/// Completes a range with the last key delivered, and whether the trie holds keys past it.
+	/// Returns false if the range isn't needed anymore.
+	pub fn on_range_delivered(&mut self, range: &StateRange, last: Option<H256>, more: bool) -> bool {
+		let done = !more || last.map_or(true, |last| last >= range.limit());
+		let next = last.map(|last| H256::from(U256::from(last) + U256::one()));
+		match *range {
+			StateRange::Accounts { origin, limit, .. } => {
+				let index = match self.account_tasks.iter().position(|task| task.next == origin && task.limit == limit) {
+					Some(index) => index,
+					None => return false,
+				};
+				if done {
+					self.account_tasks.remove(index);
+				} else {
+					let task = &mut self.account_tasks[index];
+					task.next = next.expect("there is a last key unless done; qed");
+					task.fetching = false;
+				}
+			},
+			StateRange::Storage { account_hash, storage_root, origin, .. } => {
+				let index = match self.storage_tasks.iter().position(|task| task.account_hash == account_hash && task.storage_root == storage_root && task.next == origin) {
+					Some(index) => index,
+					None => return false,
+				};
+				if done {
+					self.storage_tasks.remove(index);
+				} else {
+					let task = &mut self.storage_tasks[index];
+					task.next = next.expect("there is a last key unless done; qed");
+					task.fetching = false;
+				}
+			},
+		}
+		true
+	}
+
+	/// Queues the download of an account's storage.
+	pub fn add_storage_task(&mut self, account_hash: H256, account: Bytes, storage_root: H256) {
+		self.storage_tasks.push(StorageTask { account_hash, account, storage_root, next: H256::zero(), fetching: false });
+	}
+
+	/// Returns true once, when all ranges have been downloaded.
+	pub fn take_ranges_done(&mut self) -> bool {
+		if self.ranges_done || !self.account_tasks.is_empty() || !self.storage_tasks.is_empty() {
+			return false;
+		}
+		self.ranges_done = true;
+		true
+	}
+
+	/// Whether all ranges have been downloaded.
+	pub fn ranges_done(&self) -> bool {
+		self.ranges_done
+	}
+
+	/// Number of trie nodes and codes requested lately.
+	pub fn requested_nodes(&self) -> usize {
+		self.requested_nodes.len()
+	}
+
+	/// Picks missing trie nodes and codes to request which weren't requested lately.
+	pub fn nodes_to_request(&mut self, missing: Vec<H256>, now: Instant) -> Vec<H256> {
+		self.requested_nodes.retain(|_, time| now.duration_since(*time) <= NODE_DATA_RETRY);
+		let hashes: Vec<_> = missing.into_iter()
+			.filter(|hash| !self.requested_nodes.contains_key(hash))
+			.take(MAX_NODE_DATA_TO_REQUEST)
+			.collect();
+		for hash in &hashes {
+			self.requested_nodes.insert(*hash, now);
+		}
+		hashes
+	}
+
+	/// Makes requested nodes available to be requested again.
+	pub fn clear_nodes(&mut self, hashes: &[H256]) {
+		for hash in hashes {
+			self.requested_nodes.remove(hash);
+		}
+	}
+
+	/// Notes delivered nodes, which aren't requested again while the restoration processes them.
+	pub fn on_nodes_delivered(&mut self, hashes: &[H256], now: Instant) {
+		for hash in hashes {
+			self.requested_nodes.insert(*hash, now);
+		}
+	}
+
+	/// Notes a peer doesn't have the pivot state anymore.
+	pub fn note_stale(&mut self, peer: PeerId) {
+		self.stale_peers.insert(peer);
+	}
+
+	/// Whether a peer doesn't have the pivot state anymore.
+	pub fn is_stale(&self, peer: PeerId) -> bool {
+		self.stale_peers.contains(&peer)
+	}
+
+	/// Forgets about a disconnected peer.
+	pub fn on_peer_disconnected(&mut self, peer: PeerId) {
+		self.stale_peers.remove(&peer);
+	}
+
+	fn task_fetching(&mut self, range: &StateRange) -> Option<&mut bool> {
+		match *range {
+			StateRange::Accounts { origin, limit, .. } => self.account_tasks.iter_mut()
+				.find(|task| task.next == origin && task.limit == limit)
+				.map(|task| &mut task.fetching),
+			StateRange::Storage { account_hash, storage_root, origin, .. } => self.storage_tasks.iter_mut()
+				.find(|task| task.account_hash == account_hash && task.storage_root == storage_root && task.next == origin)
+				.map(|task| &mut task.fetching),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rlp;
+
+	fn chain(length: u64) -> Vec<Header> {
+		let mut headers: Vec<Header> = Vec::new();
+		for number in 0..length {
+			let mut header = Header::new();
+			header.set_number(number);
+			header.set_difficulty(U256::from(10));
+			if let Some(parent) = headers.last() {
+				header.set_parent_hash(parent.hash());
+			}
+			headers.push(header);
+		}
+		headers
+	}
+
+	fn pivoted(now: Instant) -> StateSync {
+		let mut headers = chain(PIVOT_DISTANCE + 11);
+		let latest = headers.last().unwrap().hash();
+		let mut sync = StateSync::new();
+		headers.reverse();
+		sync.request_pivot(latest, U256::from(10 * (PIVOT_DISTANCE + 11)));
+		sync.on_pivot_headers(headers).unwrap();
+
+		let mut body = RlpStream::new_list(2);
+		body.begin_list(0);
+		body.begin_list(0);
+		assert!(sync.on_pivot_body(&body.out()));
+		sync.on_pivot_receipts(&rlp::EMPTY_LIST_RLP, now).unwrap();
+		sync
+	}
+
+	#[test]
+	fn picks_pivot_behind_latest() {
+		let mut headers = chain(PIVOT_DISTANCE + 11);
+		let latest = headers.last().unwrap().hash();
+		headers.reverse();
+
+		let mut sync = StateSync::new();
+		sync.request_pivot(latest, U256::from(10 * (PIVOT_DISTANCE + 11)));
+		assert!(sync.on_pivot_headers(headers[1..].to_vec()).is_none());
+
+		sync.request_pivot(latest, U256::from(10 * (PIVOT_DISTANCE + 11)));
+		assert!(sync.on_pivot_headers(headers[..PIVOT_DISTANCE as usize].to_vec()).is_none());
+
+		sync.request_pivot(latest, U256::from(10 * (PIVOT_DISTANCE + 11)));
+		let pivot = sync.on_pivot_headers(headers[..PIVOT_DISTANCE as usize + 1].to_vec()).unwrap().clone();
+		assert_eq!(pivot.number(), 10);
+
+		let mut body = RlpStream::new_list(2);
+		body.begin_list(0);
+		body.begin_list(0);
+		assert!(sync.on_pivot_body(&body.out()));
+		assert!(sync.on_pivot_receipts(&rlp::NULL_RLP, Instant::now()).is_none());
+		assert!(sync.pivot().is_none());
+	}
+
+	#[test]
+	fn moves_to_pivot_with_its_total_difficulty() {
+		let now = Instant::now();
+		let mut headers = chain(PIVOT_DISTANCE + 11);
+		let latest = headers.last().unwrap().hash();
+		headers.reverse();
+
+		let mut sync = StateSync::new();
+		sync.request_pivot(latest, U256::from(10 * (PIVOT_DISTANCE + 11)));
+		sync.on_pivot_headers(headers).unwrap();
+
+		let mut bad_body = RlpStream::new_list(2);
+		bad_body.begin_list(1).append(&1u8);
+		bad_body.begin_list(0);
+		assert!(!sync.on_pivot_body(&bad_body.out()));
+		assert!(sync.next_pivot().is_none());
+
+		let sync = pivoted(now);
+		assert_eq!(sync.pivot().unwrap().number(), 10);
+		assert_eq!(sync.started(), Some(now));
+	}
+
+	#[test]
+	fn downloads_storage_before_accounts() {
+		let mut sync = StateSync::new();
+		assert!(sync.next_range().is_none());
+
+		let mut sync = pivoted(Instant::now());
+		let first = sync.next_range().unwrap();
+		assert_eq!(first.origin(), H256::zero());
+		assert_eq!(first.limit(), H256::from((U256::one() << 252) - U256::one()));
+
+		sync.add_storage_task(1.into(), vec![0xc0], 2.into());
+		let storage = sync.next_range().unwrap();
+		assert_eq!(storage.root(), 2.into());
+
+		// Ranges being fetched are handed out again once cleared.
+		sync.clear_range(&storage);
+		assert_eq!(sync.next_range(), Some(storage.clone()));
+		assert!(sync.on_range_delivered(&storage, Some(5.into()), true));
+		assert_eq!(sync.next_range().unwrap().origin(), 6.into());
+	}
+
+	#[test]
+	fn completes_ranges() {
+		let mut sync = pivoted(Instant::now());
+		let mut ranges = Vec::new();
+		while let Some(range) = sync.next_range() {
+			ranges.push(range);
+		}
+		assert_eq!(ranges.len(), ACCOUNT_TASKS);
+		assert_eq!(ranges.last().unwrap().limit(), H256::from(U256::max_value()));
+
+		for range in &ranges[1..] {
+			assert!(sync.on_range_delivered(range, None, false));
+		}
+		assert!(!sync.take_ranges_done());
+
+		// Keys past the limit of the range complete it even if the trie has more.
+		assert!(sync.on_range_delivered(&ranges[0], Some(ranges[1].origin()), true));
+		assert!(!sync.on_range_delivered(&ranges[0], None, false));
+		assert!(sync.take_ranges_done());
+		assert!(!sync.take_ranges_done());
+		assert!(sync.ranges_done());
+	}
+
+	#[test]
+	fn requests_missing_nodes_once() {
+		let now = Instant::now();
+		let mut sync = StateSync::new();
+		let missing: Vec<H256> = (0..(MAX_NODE_DATA_TO_REQUEST as u64 + 10)).map(H256::from).collect();
+
+		assert_eq!(sync.nodes_to_request(missing.clone(), now).len(), MAX_NODE_DATA_TO_REQUEST);
+		assert_eq!(sync.nodes_to_request(missing.clone(), now).len(), 10);
+		assert_eq!(sync.requested_nodes(), missing.len());
+
+		sync.clear_nodes(&missing[..5]);
+		assert_eq!(sync.nodes_to_request(missing.clone(), now), missing[..5].to_vec());
+		assert_eq!(sync.nodes_to_request(missing.clone(), now + NODE_DATA_RETRY + Duration::from_secs(1)).len(), MAX_NODE_DATA_TO_REQUEST);
+	}
+}
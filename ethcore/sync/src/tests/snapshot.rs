@@ -17,10 +17,10 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 use hash::keccak;
-use ethereum_types::H256;
+use ethereum_types::{H256, U256};
 use parking_lot::Mutex;
 use bytes::Bytes;
-use ethcore::snapshot::{SnapshotService, ManifestData, RestorationStatus};
+use ethcore::snapshot::{SnapshotService, ManifestData, RestorationStatus, StateSyncData};
 use ethcore::header::BlockNumber;
 use ethcore::client::EachBlockWith;
 use super::helpers::*;
@@ -33,6 +33,8 @@ pub struct TestSnapshotService {
 	restoration_manifest: Mutex<Option<ManifestData>>,
 	state_restoration_chunks: Mutex<HashMap<H256, Bytes>>,
 	block_restoration_chunks: Mutex<HashMap<H256, Bytes>>,
+	state_restoration_pivot: Mutex<Option<(Bytes, Bytes, U256)>>,
+	state_restoration_data: Mutex<Vec<StateSyncData>>,
 }
 
 impl TestSnapshotService {
@@ -43,6 +45,8 @@ impl TestSnapshotService {
 			restoration_manifest: Mutex::new(None),
 			state_restoration_chunks: Mutex::new(HashMap::new()),
 			block_restoration_chunks: Mutex::new(HashMap::new()),
+			state_restoration_pivot: Mutex::new(None),
+			state_restoration_data: Mutex::new(Vec::new()),
 		}
 	}
 
@@ -67,6 +71,8 @@ impl TestSnapshotService {
 			restoration_manifest: Mutex::new(None),
 			state_restoration_chunks: Mutex::new(HashMap::new()),
 			block_restoration_chunks: Mutex::new(HashMap::new()),
+			state_restoration_pivot: Mutex::new(None),
+			state_restoration_data: Mutex::new(Vec::new()),
 		}
 	}
 }
@@ -89,6 +95,9 @@ impl SnapshotService for TestSnapshotService {
 	}
 
 	fn status(&self) -> RestorationStatus {
+		if self.state_restoration_pivot.lock().is_some() {
+			return RestorationStatus::Ongoing { state_chunks: 0, block_chunks: 0, state_chunks_done: 0, block_chunks_done: 0 };
+		}
 		match *self.restoration_manifest.lock() {
 			Some(ref manifest) if self.state_restoration_chunks.lock().len() == manifest.state_hashes.len() &&
 				self.block_restoration_chunks.lock().len() == manifest.block_hashes.len() => RestorationStatus::Inactive,
@@ -116,7 +125,23 @@ impl SnapshotService for TestSnapshotService {
 		self.block_restoration_chunks.lock().clear();
 	}
 
+	fn begin_state_restore(&self, block: Bytes, receipts: Bytes, total_difficulty: U256) {
+		*self.state_restoration_pivot.lock() = Some((block, receipts, total_difficulty));
+	}
+
+	fn restore_state_data(&self, data: StateSyncData) {
+		if self.state_restoration_pivot.lock().is_some() {
+			self.state_restoration_data.lock().push(data);
+		}
+	}
+
+	fn missing_state_data(&self, _count: usize) -> Vec<H256> {
+		Vec::new()
+	}
+
 	fn abort_restore(&self) {
+		*self.state_restoration_pivot.lock() = None;
+		self.state_restoration_data.lock().clear();
 		*self.restoration_manifest.lock() = None;
 		self.state_restoration_chunks.lock().clear();
 		self.block_restoration_chunks.lock().clear();
@@ -0,0 +1,235 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Retrieval of transactions announced by eth/65 peers.
+//!
+//! Every peer's announcements are queued until they can be requested from it. A hash is only
+//! requested from one peer at a time; if that peer doesn't deliver, the hash stays queued for
+//! the other peers that announced it.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use ethereum_types::H256;
+use fastmap::H256FastSet;
+use network::PeerId;
+
+/// Maximum number of announced hashes queued for a single peer.
+const MAX_ANNOUNCED_PER_PEER: usize = 4096;
+/// Maximum number of transactions asked for in a single request.
+const MAX_TRANSACTIONS_TO_REQUEST: usize = 256;
+/// Time a peer has to deliver requested transactions.
+const POOLED_TRANSACTIONS_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Request {
+	hashes: Vec<H256>,
+	time: Instant,
+}
+
+// Hashes announced by a peer, in announcement order, along with a set for lookups.
+#[derive(Default)]
+struct Announced {
+	queue: VecDeque<H256>,
+	hashes: H256FastSet,
+}
+
+impl Announced {
+	fn retain<F>(&mut self, keep: F) where F: Fn(&H256) -> bool {
+		let hashes = &mut self.hashes;
+		self.queue.retain(|hash| {
+			let kept = keep(hash);
+			if !kept {
+				hashes.remove(hash);
+			}
+			kept
+		});
+	}
+}
+
+/// Tracks announced transactions and the requests retrieving them.
+#[derive(Default)]
+pub struct TransactionsFetcher {
+	/// Hashes announced by each peer, in announcement order, that haven't been retrieved yet.
+	announced: HashMap<PeerId, Announced>,
+	/// Hashes being requested from any peer.
+	fetching: H256FastSet,
+	/// Pending request of each peer.
+	requests: HashMap<PeerId, Request>,
+}
+
+impl TransactionsFetcher {
+	/// Queues hashes announced by a peer. Hashes for which `is_known` returns true are skipped.
+	/// Returns the number of hashes queued.
+	pub fn on_announced<F>(&mut self, peer: PeerId, hashes: &[H256], is_known: F) -> usize where F: Fn(&H256) -> bool {
+		let announced = self.announced.entry(peer).or_insert_with(Announced::default);
+		let mut queued = 0;
+		for hash in hashes {
+			if announced.queue.len() >= MAX_ANNOUNCED_PER_PEER {
+				trace!(target: "sync", "{}: Too many announced transactions, ignoring {}", peer, hashes.len() - queued);
+				break;
+			}
+			if announced.hashes.contains(hash) || is_known(hash) {
+				continue;
+			}
+			announced.queue.push_back(*hash);
+			announced.hashes.insert(*hash);
+			queued += 1;
+		}
+		queued
+	}
+
+	/// Picks the next hashes to request from a peer, unless a request to it is already pending.
+	/// Hashes for which `is_known` returns true by now are dropped from the peer's queue.
+	pub fn request<F>(&mut self, peer: PeerId, now: Instant, is_known: F) -> Option<Vec<H256>> where F: Fn(&H256) -> bool {
+		if self.requests.contains_key(&peer) {
+			return None;
+		}
+
+		let fetching = &mut self.fetching;
+		let hashes: Vec<_> = match self.announced.get_mut(&peer) {
+			Some(announced) => {
+				announced.retain(|hash| !is_known(hash));
+				announced.queue.iter()
+					.filter(|hash| !fetching.contains(hash))
+					.take(MAX_TRANSACTIONS_TO_REQUEST)
+					.cloned()
+					.collect()
+			},
+			None => return None,
+		};
+		if hashes.is_empty() {
+			return None;
+		}
+
+		for hash in &hashes {
+			fetching.insert(*hash);
+		}
+		self.requests.insert(peer, Request { hashes: hashes.clone(), time: now });
+		Some(hashes)
+	}
+
+	/// Completes the pending request to a peer with the hashes of the delivered transactions.
+	/// Returns the requested hashes, or `None` if nothing was requested from the peer.
+	pub fn on_delivered(&mut self, peer: PeerId, delivered: &[H256]) -> Option<Vec<H256>> {
+		let request = self.requests.remove(&peer)?;
+		for hash in &request.hashes {
+			self.fetching.remove(hash);
+		}
+
+		// Delivered transactions are not needed from anyone anymore, missing ones are left
+		// queued for the other peers that announced them.
+		let delivered: H256FastSet = delivered.iter().filter(|hash| request.hashes.contains(hash)).cloned().collect();
+		for announced in self.announced.values_mut() {
+			if delivered.iter().any(|hash| announced.hashes.contains(hash)) {
+				announced.retain(|hash| !delivered.contains(hash));
+			}
+		}
+		if let Some(announced) = self.announced.get_mut(&peer) {
+			announced.retain(|hash| !request.hashes.contains(hash));
+		}
+		Some(request.hashes)
+	}
+
+	/// Drops requests that weren't answered in time and returns the peers they were sent to.
+	pub fn expire(&mut self, now: Instant) -> Vec<PeerId> {
+		let expired: Vec<_> = self.requests.iter()
+			.filter(|&(_, request)| now.duration_since(request.time) > POOLED_TRANSACTIONS_TIMEOUT)
+			.map(|(peer, _)| *peer)
+			.collect();
+		for peer in &expired {
+			self.on_delivered(*peer, &[]);
+		}
+		expired
+	}
+
+	/// Forgets everything about a disconnected peer.
+	pub fn on_peer_disconnected(&mut self, peer: PeerId) {
+		self.on_delivered(peer, &[]);
+		self.announced.remove(&peer);
+	}
+
+	/// Peers with announcements that could be requested now.
+	pub fn idle_peers(&self) -> Vec<PeerId> {
+		self.announced.iter()
+			.filter(|&(peer, announced)| !self.requests.contains_key(peer) && announced.queue.iter().any(|hash| !self.fetching.contains(hash)))
+			.map(|(peer, _)| *peer)
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn hashes(range: ::std::ops::Range<u64>) -> Vec<H256> {
+		range.map(H256::from).collect()
+	}
+
+	#[test]
+	fn skips_known_and_duplicate_hashes() {
+		let mut fetcher = TransactionsFetcher::default();
+		let known = H256::from(1);
+
+		assert_eq!(fetcher.on_announced(0, &hashes(0..4), |hash| *hash == known), 3);
+		assert_eq!(fetcher.on_announced(0, &hashes(2..6), |_| false), 2);
+		assert_eq!(fetcher.request(0, Instant::now(), |_| false), Some(vec![0.into(), 2.into(), 3.into(), 4.into(), 5.into()]));
+	}
+
+	#[test]
+	fn requests_each_hash_from_one_peer() {
+		let mut fetcher = TransactionsFetcher::default();
+		let now = Instant::now();
+		fetcher.on_announced(0, &hashes(0..3), |_| false);
+		fetcher.on_announced(1, &hashes(1..5), |_| false);
+
+		assert_eq!(fetcher.request(0, now, |_| false), Some(hashes(0..3)));
+		assert_eq!(fetcher.request(0, now, |_| false), None);
+		assert_eq!(fetcher.request(1, now, |_| false), Some(hashes(3..5)));
+		assert!(fetcher.idle_peers().is_empty());
+
+		// Peer 0 delivers only the first two, so the third one is left for peer 1.
+		assert_eq!(fetcher.on_delivered(0, &hashes(0..2)), Some(hashes(0..3)));
+		assert_eq!(fetcher.on_delivered(0, &[]), None);
+		assert_eq!(fetcher.on_delivered(1, &hashes(3..5)), Some(hashes(3..5)));
+		assert_eq!(fetcher.idle_peers(), vec![1]);
+		assert_eq!(fetcher.request(1, now, |_| false), Some(vec![2.into()]));
+	}
+
+	#[test]
+	fn limits_requests_and_announcements() {
+		let mut fetcher = TransactionsFetcher::default();
+		let announced = hashes(0..MAX_ANNOUNCED_PER_PEER as u64 + 10);
+		assert_eq!(fetcher.on_announced(0, &announced, |_| false), MAX_ANNOUNCED_PER_PEER);
+		assert_eq!(fetcher.request(0, Instant::now(), |_| false).unwrap().len(), MAX_TRANSACTIONS_TO_REQUEST);
+	}
+
+	#[test]
+	fn expires_requests() {
+		let mut fetcher = TransactionsFetcher::default();
+		let now = Instant::now();
+		fetcher.on_announced(0, &hashes(0..2), |_| false);
+		fetcher.on_announced(1, &hashes(0..2), |_| false);
+		fetcher.request(0, now, |_| false).unwrap();
+
+		assert!(fetcher.expire(now).is_empty());
+		assert_eq!(fetcher.expire(now + POOLED_TRANSACTIONS_TIMEOUT + Duration::from_secs(1)), vec![0]);
+		assert_eq!(fetcher.request(1, now, |_| false), Some(hashes(0..2)));
+
+		fetcher.on_peer_disconnected(1);
+		assert_eq!(fetcher.on_delivered(1, &[]), None);
+		assert!(fetcher.idle_peers().is_empty());
+	}
+}
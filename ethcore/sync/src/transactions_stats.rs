@@ -27,6 +27,7 @@ type BlockNumber = u64;
 pub struct Stats {
 	first_seen: BlockNumber,
 	propagated_to: HashMap<NodeId, usize>,
+	announced_to: HashMap<NodeId, usize>,
 }
 
 impl Stats {
@@ -34,6 +35,7 @@ impl Stats {
 		Stats {
 			first_seen: number,
 			propagated_to: Default::default(),
+			announced_to: Default::default(),
 		}
 	}
 }
@@ -46,6 +48,10 @@ impl<'a> From<&'a Stats> for TransactionStats {
 				.iter()
 				.map(|(hash, size)| (*hash, *size))
 				.collect(),
+			announced_to: other.announced_to
+				.iter()
+				.map(|(hash, size)| (*hash, *size))
+				.collect(),
 		}
 	}
 }
@@ -64,6 +70,14 @@ impl TransactionsStats {
 		*count = count.saturating_add(1);
 	}
 
+	/// Increases number of hash announcements to given `enodeid`.
+	pub fn announced(&mut self, hash: &H256, enode_id: Option<NodeId>, current_block_num: BlockNumber) {
+		let enode_id = enode_id.unwrap_or_default();
+		let stats = self.pending_transactions.entry(*hash).or_insert_with(|| Stats::new(current_block_num));
+		let count = stats.announced_to.entry(enode_id).or_insert(0);
+		*count = count.saturating_add(1);
+	}
+
 	/// Returns propagation stats for given hash or `None` if hash is not known.
 	#[cfg(test)]
 	pub fn get(&self, hash: &H256) -> Option<&Stats> {
@@ -114,6 +128,34 @@ mod tests {
 				enodeid1 => 2,
 				enodeid2 => 1
 			],
+			announced_to: HashMap::new(),
+		}));
+	}
+
+	#[test]
+	fn should_keep_track_of_announcements() {
+		// given
+		let mut stats = TransactionsStats::default();
+		let hash = 5.into();
+		let enodeid1 = 2.into();
+		let enodeid2 = 5.into();
+
+		// when
+		stats.announced(&hash, Some(enodeid1), 5);
+		stats.announced(&hash, Some(enodeid2), 10);
+		stats.propagated(&hash, Some(enodeid1), 15);
+
+		// then
+		let stats = stats.get(&hash);
+		assert_eq!(stats, Some(&Stats {
+			first_seen: 5,
+			propagated_to: hash_map![
+				enodeid1 => 1
+			],
+			announced_to: hash_map![
+				enodeid1 => 1,
+				enodeid2 => 1
+			],
 		}));
 	}
 
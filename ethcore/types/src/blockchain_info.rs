@@ -45,6 +45,8 @@ pub struct BlockChainInfo {
 	pub first_block_hash: Option<H256>,
 	/// Number of the first block on the best sequence.
 	pub first_block_number: Option<BlockNumber>,
+	/// Number of the first block whose body and receipts are kept, if older history has expired.
+	pub first_history_block: Option<BlockNumber>,
 }
 
 impl BlockChainInfo {
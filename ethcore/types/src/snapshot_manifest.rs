@@ -76,3 +76,43 @@ impl ManifestData {
 		})
 	}
 }
+
+/// Manifest of a delta snapshot: a snapshot which only holds the state-trie nodes
+/// that changed since its base snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaManifest {
+	/// Block number of the base snapshot.
+	pub base_number: u64,
+	/// Block hash of the base snapshot.
+	pub base_hash: H256,
+	/// State root of the base snapshot.
+	pub base_state_root: H256,
+	/// Manifest of the delta itself. Its state chunks hold trie nodes rather than
+	/// accounts, its block chunks are complete.
+	pub manifest: ManifestData,
+}
+
+impl DeltaManifest {
+	/// Encode the delta manifest to rlp.
+	pub fn into_rlp(self) -> Bytes {
+		let mut stream = RlpStream::new_list(4);
+		stream.append(&self.base_number);
+		stream.append(&self.base_hash);
+		stream.append(&self.base_state_root);
+		stream.append_raw(&self.manifest.into_rlp(), 1);
+
+		stream.out()
+	}
+
+	/// Try to restore delta manifest data from raw bytes, interpreted as RLP.
+	pub fn from_rlp(raw: &[u8]) -> Result<Self, DecoderError> {
+		let decoder = Rlp::new(raw);
+
+		Ok(DeltaManifest {
+			base_number: decoder.val_at(0)?,
+			base_hash: decoder.val_at(1)?,
+			base_state_root: decoder.val_at(2)?,
+			manifest: ManifestData::from_rlp(decoder.at(3)?.as_raw())?,
+		})
+	}
+}
@@ -48,6 +48,9 @@ pub struct AuthorityRoundParams {
 	pub block_reward_contract_address: Option<Address>,
 	/// Block reward code. This overrides the block reward contract address.
 	pub block_reward_contract_code: Option<Bytes>,
+	/// Block from which rewards returned by the block reward contract for a beneficiary are traced
+	/// with that beneficiary's kind (author, uncle, empty step) instead of as external rewards.
+	pub block_reward_contract_kinds_transition: Option<Uint>,
 	/// Block at which maximum uncle count should be considered.
 	pub maximum_uncle_count_transition: Option<Uint>,
 	/// Maximum number of accepted uncles.
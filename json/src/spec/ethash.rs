@@ -61,6 +61,9 @@ pub struct EthashParams {
 	pub block_reward_contract_address: Option<Address>,
 	/// Block reward code. This overrides the block reward contract address.
 	pub block_reward_contract_code: Option<Bytes>,
+	/// Block from which rewards returned by the block reward contract for a beneficiary are traced
+	/// with that beneficiary's kind (author, uncle, empty step) instead of as external rewards.
+	pub block_reward_contract_kinds_transition: Option<Uint>,
 
 	/// See main EthashParams docs.
 	pub dao_hardfork_transition: Option<Uint>,
@@ -95,6 +98,8 @@ pub struct EthashParams {
 	pub expip2_transition: Option<Uint>,
 	/// EXPIP-2 duration limit
 	pub expip2_duration_limit: Option<Uint>,
+	/// Block to transition to progpow
+	pub progpow_transition: Option<Uint>,
 }
 
 /// Ethash engine deserialization.
@@ -122,6 +127,8 @@ mod tests {
 				"durationLimit": "0x0d",
 				"homesteadTransition": "0x42",
 				"blockReward": "0x100",
+				"blockRewardContractTransition": "0x20",
+				"blockRewardContractAddress": "0x0000000000000000000000000000000000000042",
 				"daoHardforkTransition": "0x08",
 				"daoHardforkBeneficiary": "0xabcabcabcabcabcabcabcabcabcabcabcabcabca",
 				"daoHardforkAccounts": [
@@ -149,7 +156,8 @@ mod tests {
 				"difficultyHardforkTransition": "0x59d9",
 				"difficultyHardforkBoundDivisor": "0x0200",
 				"bombDefuseTransition": "0x41",
-				"eip100bTransition": "0x42"
+				"eip100bTransition": "0x42",
+				"progpowTransition": "0x100"
 			}
 		}"#;
 
@@ -164,9 +172,10 @@ mod tests {
 				duration_limit: Some(Uint(U256::from(0x0d))),
 				homestead_transition: Some(Uint(U256::from(0x42))),
 				block_reward: Some(BlockReward::Single(Uint(U256::from(0x100)))),
-				block_reward_contract_address: None,
+				block_reward_contract_address: Some(Address(H160::from("0x0000000000000000000000000000000000000042"))),
 				block_reward_contract_code: None,
-				block_reward_contract_transition: None,
+				block_reward_contract_transition: Some(Uint(U256::from(0x20))),
+				block_reward_contract_kinds_transition: None,
 				dao_hardfork_transition: Some(Uint(U256::from(0x08))),
 				dao_hardfork_beneficiary: Some(Address(H160::from("0xabcabcabcabcabcabcabcabcabcabcabcabcabca"))),
 				dao_hardfork_accounts: Some(vec![
@@ -201,6 +210,7 @@ mod tests {
 				expip2_transition: None,
 				expip2_duration_limit: None,
 				difficulty_bomb_delays: None,
+				progpow_transition: Some(Uint(U256::from(0x100))),
 			}
 		});
 	}
@@ -227,6 +237,7 @@ mod tests {
 				block_reward_contract_address: None,
 				block_reward_contract_code: None,
 				block_reward_contract_transition: None,
+				block_reward_contract_kinds_transition: None,
 				dao_hardfork_transition: None,
 				dao_hardfork_beneficiary: None,
 				dao_hardfork_accounts: None,
@@ -240,6 +251,7 @@ mod tests {
 				expip2_transition: None,
 				expip2_duration_limit: None,
 				difficulty_bomb_delays: None,
+				progpow_transition: None,
 			}
 		});
 	}
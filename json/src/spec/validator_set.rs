@@ -17,8 +17,11 @@
 //! Validator set deserialization.
 
 use std::collections::BTreeMap;
+use std::io::Read;
+use serde_json::{self, Error};
 use uint::Uint;
 use hash::Address;
+use bytes::Bytes;
 
 /// Different ways of specifying validators.
 #[derive(Debug, PartialEq, Deserialize)]
@@ -33,6 +36,45 @@ pub enum ValidatorSet {
 	Contract(Address),
 	/// A map of starting blocks for each validator set.
 	Multi(BTreeMap<Uint, ValidatorSet>),
+	/// A local file with validator set transitions signed by an administrator key.
+	File(ValidatorFile),
+}
+
+/// Location of a signed validator set file.
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ValidatorFile {
+	/// Path to the file.
+	pub path: String,
+	/// Address whose key signs every transition in the file.
+	pub signer: Address,
+}
+
+/// Contents of a signed validator set file.
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SignedValidators {
+	/// Validator set transitions.
+	pub transitions: Vec<SignedTransition>,
+}
+
+impl SignedValidators {
+	/// Loads the validator set file from json.
+	pub fn load<R>(reader: R) -> Result<Self, Error> where R: Read {
+		serde_json::from_reader(reader)
+	}
+}
+
+/// Validator set activated at the given block.
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SignedTransition {
+	/// First block validated by this set.
+	pub block: Uint,
+	/// The validators.
+	pub validators: Vec<Address>,
+	/// Signature of `keccak(rlp([block, validators]))` by the signer.
+	pub signature: Bytes,
 }
 
 #[cfg(test)]
@@ -41,7 +83,7 @@ mod tests {
 	use uint::Uint;
 	use ethereum_types::{H160, U256};
 	use hash::Address;
-	use spec::validator_set::ValidatorSet;
+	use spec::validator_set::{ValidatorSet, ValidatorFile, SignedValidators};
 
 	#[test]
 	fn validator_set_deserialization() {
@@ -57,10 +99,15 @@ mod tests {
 				"10": { "list": ["0xd6d9d2cd449a754c494264e1809c50e34d64562b"] },
 				"20": { "contract": "0xc6d9d2cd449a754c494264e1809c50e34d64562b" }
 			}
+		}, {
+			"file": {
+				"path": "validators.json",
+				"signer": "0xc6d9d2cd449a754c494264e1809c50e34d64562b"
+			}
 		}]"#;
 
 		let deserialized: Vec<ValidatorSet> = serde_json::from_str(s).unwrap();
-		assert_eq!(deserialized.len(), 4);
+		assert_eq!(deserialized.len(), 5);
 
 		assert_eq!(deserialized[0], ValidatorSet::List(vec![Address(H160::from("0xc6d9d2cd449a754c494264e1809c50e34d64562b"))]));
 		assert_eq!(deserialized[1], ValidatorSet::SafeContract(Address(H160::from("0xc6d9d2cd449a754c494264e1809c50e34d64562b"))));
@@ -74,5 +121,31 @@ mod tests {
 			},
 			_ => assert!(false),
 		}
+		assert_eq!(deserialized[4], ValidatorSet::File(ValidatorFile {
+			path: "validators.json".into(),
+			signer: Address(H160::from("0xc6d9d2cd449a754c494264e1809c50e34d64562b")),
+		}));
+	}
+
+	#[test]
+	fn signed_validators_deserialization() {
+		let s = r#"{
+			"transitions": [{
+				"block": 0,
+				"validators": ["0xc6d9d2cd449a754c494264e1809c50e34d64562b"],
+				"signature": "0x00"
+			}, {
+				"block": "0x10",
+				"validators": [],
+				"signature": "0x0102"
+			}]
+		}"#;
+
+		let deserialized = SignedValidators::load(s.as_bytes()).unwrap();
+		assert_eq!(deserialized.transitions.len(), 2);
+		assert_eq!(deserialized.transitions[0].block, Uint(U256::from(0)));
+		assert_eq!(deserialized.transitions[0].validators.len(), 1);
+		assert_eq!(deserialized.transitions[1].block, Uint(U256::from(16)));
+		assert_eq!(&*deserialized.transitions[1].signature, &[1u8, 2][..]);
 	}
 }
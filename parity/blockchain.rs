@@ -15,7 +15,7 @@
 // along with Parity.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::str::{FromStr, from_utf8};
-use std::{io, fs};
+use std::{io, fs, iter};
 use std::io::{BufReader, BufRead};
 use std::time::{Instant, Duration};
 use std::thread::sleep;
@@ -26,6 +26,7 @@ use ethereum_types::{U256, H256, Address};
 use bytes::ToPretty;
 use rlp::PayloadInfo;
 use ethcore::account_provider::AccountProvider;
+use ethcore::{BlockChainDB, check_database, prune_history, prune_state, repair_journal};
 use ethcore::client::{Mode, DatabaseCompactionProfile, VMType, Nonce, Balance, BlockChainClient, BlockId, BlockInfo, ImportBlock};
 use ethcore::error::{ImportErrorKind, ErrorKind as EthcoreErrorKind, Error as EthcoreError};
 use ethcore::miner::Miner;
@@ -37,9 +38,11 @@ use informant::{Informant, FullNodeInformantData, MillisecondDuration};
 use params::{SpecType, Pruning, Switch, tracing_switch_to_bool, fatdb_switch_to_bool};
 use helpers::{to_client_config, execute_upgrades};
 use dir::Directories;
+use journaldb::{self, Algorithm};
 use user_defaults::UserDefaults;
+use state_dump::{self, StateDumpFormat, AccountRecord};
 use ethcore_private_tx;
-use db;
+use db::{self, DatabaseBackend};
 
 #[derive(Debug, PartialEq)]
 pub enum DataFormat {
@@ -68,9 +71,12 @@ impl FromStr for DataFormat {
 #[derive(Debug, PartialEq)]
 pub enum BlockchainCmd {
 	Kill(KillBlockchain),
+	MigrateBackend(MigrateBackend),
+	Database(DatabaseCmd),
 	Import(ImportBlockchain),
 	Export(ExportBlockchain),
 	ExportState(ExportState),
+	ImportState(ImportState),
 }
 
 #[derive(Debug, PartialEq)]
@@ -80,6 +86,45 @@ pub struct KillBlockchain {
 	pub pruning: Pruning,
 }
 
+#[derive(Debug, PartialEq)]
+pub struct MigrateBackend {
+	pub spec: SpecType,
+	pub dirs: Directories,
+	pub pruning: Pruning,
+	pub compaction: DatabaseCompactionProfile,
+	pub to: DatabaseBackend,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DatabaseAction {
+	/// Print key counts and sizes of every column.
+	Stats,
+	/// Rewrite the database to reclaim space.
+	Compact,
+	/// Re-encode bodies and receipts stored by older versions in the compressed format.
+	Compress,
+	/// Check the canonical chain and the state of the best block.
+	Check,
+	/// Remove bodies and receipts of blocks before the given one.
+	PruneHistory(u64),
+	/// Remove the state of all but the given number of recent blocks from an archive database.
+	PruneState(u64),
+	/// Check the state journal against the stored state.
+	CheckJournal,
+	/// Rebuild the state journal from the state of the given block, or the best block.
+	RepairJournal(Option<u64>),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DatabaseCmd {
+	pub spec: SpecType,
+	pub dirs: Directories,
+	pub pruning: Pruning,
+	pub cache_config: CacheConfig,
+	pub compaction: DatabaseCompactionProfile,
+	pub action: DatabaseAction,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct ImportBlockchain {
 	pub spec: SpecType,
@@ -91,6 +136,7 @@ pub struct ImportBlockchain {
 	pub pruning_history: u64,
 	pub pruning_memory: usize,
 	pub compaction: DatabaseCompactionProfile,
+	pub db_backend: Option<DatabaseBackend>,
 	pub tracing: Switch,
 	pub fat_db: Switch,
 	pub vm_type: VMType,
@@ -139,11 +185,21 @@ pub struct ExportState {
 	pub min_balance: Option<U256>,
 	pub max_balance: Option<U256>,
 	pub max_round_blocks_to_import: usize,
+	pub stream: Option<StateDumpFormat>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ImportState {
+	pub file_path: Option<String>,
+	pub format: Option<StateDumpFormat>,
+	pub out: Option<String>,
 }
 
 pub fn execute(cmd: BlockchainCmd) -> Result<(), String> {
 	match cmd {
 		BlockchainCmd::Kill(kill_cmd) => kill_db(kill_cmd),
+		BlockchainCmd::MigrateBackend(migrate_cmd) => migrate_db_backend(migrate_cmd),
+		BlockchainCmd::Database(db_cmd) => execute_database(db_cmd),
 		BlockchainCmd::Import(import_cmd) => {
 			if import_cmd.light {
 				execute_import_light(import_cmd)
@@ -152,7 +208,11 @@ pub fn execute(cmd: BlockchainCmd) -> Result<(), String> {
 			}
 		}
 		BlockchainCmd::Export(export_cmd) => execute_export(export_cmd),
-		BlockchainCmd::ExportState(export_cmd) => execute_export_state(export_cmd),
+		BlockchainCmd::ExportState(export_cmd) => match export_cmd.stream {
+			Some(format) => execute_export_state_stream(export_cmd, format),
+			None => execute_export_state(export_cmd),
+		},
+		BlockchainCmd::ImportState(import_cmd) => execute_import_state(import_cmd),
 	}
 }
 
@@ -206,9 +266,11 @@ fn execute_import_light(cmd: ImportBlockchain) -> Result<(), String> {
 	config.queue.verifier_settings = cmd.verifier_settings;
 
 	// initialize database.
+	let db_backend = DatabaseBackend::resolve(cmd.db_backend, &client_path)?;
 	let db = db::open_db(&client_path.to_str().expect("DB path could not be converted to string."),
 						 &cmd.cache_config,
-						 &cmd.compaction).map_err(|e| format!("Failed to open database: {:?}", e))?;
+						 &cmd.compaction,
+						 db_backend).map_err(|e| format!("Failed to open database: {:?}", e))?;
 
 	// TODO: could epoch signals be avilable at the end of the file?
 	let fetch = ::light::client::fetch::unavailable();
@@ -362,7 +424,8 @@ fn execute_import(cmd: ImportBlockchain) -> Result<(), String> {
 
 	client_config.queue.verifier_settings = cmd.verifier_settings;
 
-	let restoration_db_handler = db::restoration_db_handler(&client_path, &client_config);
+	let db_backend = DatabaseBackend::resolve(cmd.db_backend, &client_path)?;
+	let restoration_db_handler = db::restoration_db_handler(&client_path, &client_config, db_backend);
 	let client_db = restoration_db_handler.open(&client_path)
 		.map_err(|e| format!("Failed to open database {:?}", e))?;
 
@@ -554,7 +617,8 @@ fn start_client(
 		max_round_blocks_to_import,
 	);
 
-	let restoration_db_handler = db::restoration_db_handler(&client_path, &client_config);
+	let db_backend = DatabaseBackend::resolve(None, &client_path)?;
+	let restoration_db_handler = db::restoration_db_handler(&client_path, &client_config, db_backend);
 	let client_db = restoration_db_handler.open(&client_path)
 		.map_err(|e| format!("Failed to open database {:?}", e))?;
 
@@ -709,6 +773,196 @@ fn execute_export_state(cmd: ExportState) -> Result<(), String> {
 	Ok(())
 }
 
+fn execute_export_state_stream(cmd: ExportState, format: StateDumpFormat) -> Result<(), String> {
+	let service = start_client(
+		cmd.dirs,
+		cmd.spec,
+		cmd.pruning,
+		cmd.pruning_history,
+		cmd.pruning_memory,
+		cmd.tracing,
+		cmd.fat_db,
+		cmd.compaction,
+		cmd.cache_config,
+		true,
+		cmd.max_round_blocks_to_import,
+	)?;
+
+	let client = service.client();
+	let at = cmd.at;
+	let block = client.block_header(at).ok_or("Specified block not found")?;
+
+	let out: Box<io::Write> = match cmd.file_path {
+		Some(f) => Box::new(fs::File::create(&f).map_err(|_| format!("Cannot write to file given: {}", f))?),
+		None => Box::new(io::stdout()),
+	};
+
+	let header = state_dump::Header {
+		version: state_dump::VERSION,
+		number: block.number(),
+		hash: block.hash(),
+		state_root: block.state_root(),
+		complete: cmd.storage && cmd.code && cmd.min_balance.is_none() && cmd.max_balance.is_none(),
+	};
+	let mut writer = state_dump::Writer::new(io::BufWriter::new(out), format, &header)
+		.map_err(|e| format!("Write error: {}", e))?;
+
+	let mut last: Option<Address> = None;
+	let mut i = 0usize;
+	loop {
+		let accounts = client.list_accounts(at, last.as_ref(), 1000).ok_or("Specified block not found")?;
+		if accounts.is_empty() {
+			break;
+		}
+
+		for account in accounts.into_iter() {
+			last = Some(account);
+
+			let balance = client.balance(&account, at.into()).unwrap_or_else(U256::zero);
+			if cmd.min_balance.map_or(false, |m| balance < m) || cmd.max_balance.map_or(false, |m| balance > m) {
+				continue; //filtered out
+			}
+
+			let mut record = AccountRecord {
+				address: account,
+				balance: balance,
+				nonce: client.nonce(&account, at).unwrap_or_else(U256::zero),
+				code: Vec::new(),
+				storage: Default::default(),
+			};
+
+			if cmd.code {
+				record.code = client.code(&account, at.into()).unwrap_or(None).unwrap_or_else(Vec::new);
+			}
+
+			if cmd.storage && client.storage_root(&account, at).map_or(false, |root| root != KECCAK_NULL_RLP) {
+				let mut last_storage: Option<H256> = None;
+				loop {
+					let keys = client.list_storage(at, &account, last_storage.as_ref(), 1000).ok_or("Specified block not found")?;
+					if keys.is_empty() {
+						break;
+					}
+
+					for key in keys.into_iter() {
+						record.storage.insert(key, client.storage_at(&account, &key, at.into()).unwrap_or_else(Default::default));
+						last_storage = Some(key);
+					}
+				}
+			}
+
+			writer.write_account(&record).map_err(|e| format!("Write error: {}", e))?;
+			i += 1;
+			if i % 10000 == 0 {
+				info!("Account #{}", i);
+			}
+		}
+	}
+
+	writer.flush().map_err(|e| format!("Write error: {}", e))?;
+	info!("Export completed.");
+	Ok(())
+}
+
+fn execute_import_state(cmd: ImportState) -> Result<(), String> {
+	let input: Box<io::Read> = match cmd.file_path {
+		Some(f) => Box::new(fs::File::open(&f).map_err(|_| format!("Cannot open given file: {}", f))?),
+		None => Box::new(io::stdin()),
+	};
+
+	let reader = state_dump::Reader::new(BufReader::new(input), cmd.format)?;
+	let header = reader.header().clone();
+	info!("Importing state of block #{} ({:x})", header.number, header.hash);
+
+	let out: Box<io::Write> = match cmd.out {
+		Some(ref f) => Box::new(fs::File::create(f).map_err(|_| format!("Cannot write to file given: {}", f))?),
+		None => Box::new(io::stdout()),
+	};
+
+	// the state root is computed in a scratch database, so the accounts are never all in memory.
+	let scratch_path = ::std::env::temp_dir().join(format!("parity-import-state-{}", ::std::process::id()));
+	let result = import_state_accounts(reader, io::BufWriter::new(out), &scratch_path);
+	let _ = fs::remove_dir_all(&scratch_path);
+	let (root, imported) = result?;
+
+	if root != header.state_root {
+		if header.complete {
+			if let Some(ref f) = cmd.out {
+				let _ = fs::remove_file(f);
+			}
+			return Err(format!("Imported state has wrong state root: expected {:x}, got {:x}", header.state_root, root));
+		}
+		warn!("State dump is incomplete; the imported state root {:x} doesn't match block #{}", root, header.number);
+	}
+
+	info!("Import of {} accounts completed with state root {:x}.", imported, root);
+	Ok(())
+}
+
+// Write the accounts of a state dump as genesis accounts, while building their state in batches
+// in a scratch database. Returns the state root and the number of accounts.
+fn import_state_accounts<R: BufRead, W: io::Write>(reader: state_dump::Reader<R>, mut out: W, scratch_path: &::std::path::Path) -> Result<(H256, u64), String> {
+	use std::collections::BTreeMap;
+	use std::mem;
+	use ethcore::pod_account::PodAccount;
+	use ethcore::pod_state::PodState;
+	use ethcore::state::State;
+	use ethcore::state_db::StateDB;
+	use kvdb::DBTransaction;
+
+	// number of accounts written to the state at once.
+	const BATCH_SIZE: usize = 10000;
+
+	let write_error = |e: io::Error| format!("Write error: {}", e);
+	let scratch = db::open_db(&scratch_path.to_string_lossy(), &Default::default(), &Default::default(), DatabaseBackend::RocksDb)
+		.map_err(|e| format!("Failed to open scratch database: {:?}", e))?;
+	let new_state_db = || StateDB::new(journaldb::new(scratch.key_value().clone(), Algorithm::Archive, ::ethcore::db::COL_STATE), 0);
+	let mut root = KECCAK_NULL_RLP;
+
+	// write a batch of accounts into the state, returning the new state root.
+	let commit = |root: H256, accounts: BTreeMap<Address, PodAccount>| -> Result<H256, String> {
+		let mut state = State::from_existing(new_state_db(), root, U256::zero(), Default::default())
+			.map_err(|e| format!("State error: {}", e))?;
+		state.populate_from(PodState::from(accounts));
+		state.commit().map_err(|e| format!("State error: {}", e))?;
+
+		let (root, mut state_db) = state.drop();
+		let mut batch = DBTransaction::new();
+		state_db.journal_under(&mut batch, 0, &H256::zero()).map_err(|e| format!("Database error: {}", e))?;
+		scratch.key_value().write(batch).map_err(|e| format!("Database error: {}", e))?;
+		Ok(root)
+	};
+
+	write!(out, "{{\n\t\"accounts\": {{").map_err(&write_error)?;
+	let mut batch = BTreeMap::new();
+	let mut imported = 0;
+	for record in reader {
+		let record = record?;
+		let account = PodAccount {
+			balance: record.balance,
+			nonce: record.nonce,
+			code: Some(record.code),
+			storage: record.storage,
+		};
+
+		let address = ::serde_json::to_string(&record.address).map_err(|e| format!("Write error: {}", e))?;
+		let separator = if imported == 0 { "" } else { "," };
+		write!(out, "{}\n\t\t{}: ", separator, address).map_err(&write_error)?;
+		::serde_json::to_writer(&mut out, &account).map_err(|e| format!("Write error: {}", e))?;
+
+		batch.insert(record.address, account);
+		imported += 1;
+		if batch.len() == BATCH_SIZE {
+			root = commit(root, mem::replace(&mut batch, BTreeMap::new()))?;
+			info!("Account #{}", imported);
+		}
+	}
+	root = commit(root, batch)?;
+	write!(out, "\n\t}}\n}}\n").map_err(&write_error)?;
+	out.flush().map_err(&write_error)?;
+
+	Ok((root, imported))
+}
+
 pub fn kill_db(cmd: KillBlockchain) -> Result<(), String> {
 	let spec = cmd.spec.spec(&cmd.dirs.cache)?;
 	let genesis_hash = spec.genesis_header().hash();
@@ -724,6 +978,188 @@ pub fn kill_db(cmd: KillBlockchain) -> Result<(), String> {
 	Ok(())
 }
 
+pub fn migrate_db_backend(cmd: MigrateBackend) -> Result<(), String> {
+	let spec = cmd.spec.spec(&cmd.dirs.cache)?;
+	let genesis_hash = spec.genesis_header().hash();
+	let db_dirs = cmd.dirs.database(genesis_hash, None, spec.data_dir);
+	let user_defaults = UserDefaults::load(&db_dirs.user_defaults_path())?;
+	let algorithm = cmd.pruning.to_algorithm(&user_defaults);
+
+	execute_upgrades(&cmd.dirs.base, &db_dirs, algorithm, &cmd.compaction)?;
+
+	let client_path = db_dirs.client_path(algorithm);
+	info!("Migrating database at {} to the {} backend", client_path.display(), cmd.to);
+	db::migrate_backend(&client_path, cmd.to, &cmd.compaction)?;
+	info!("Database migrated.");
+	Ok(())
+}
+
+pub fn execute_database(cmd: DatabaseCmd) -> Result<(), String> {
+	let spec = cmd.spec.spec(&cmd.dirs.cache)?;
+	let genesis_hash = spec.genesis_header().hash();
+	let db_dirs = cmd.dirs.database(genesis_hash, None, spec.data_dir.clone());
+	let user_defaults_path = db_dirs.user_defaults_path();
+	let mut user_defaults = UserDefaults::load(&user_defaults_path)?;
+	let algorithm = cmd.pruning.to_algorithm(&user_defaults);
+	let client_path = db_dirs.client_path(algorithm);
+
+	if DatabaseBackend::detect(&client_path).is_none() {
+		return Err(format!("No database found at {}", client_path.display()));
+	}
+
+	// inspecting a database must not change it, so it isn't upgraded first.
+	let read_only = match cmd.action {
+		DatabaseAction::Stats | DatabaseAction::Check | DatabaseAction::CheckJournal => true,
+		_ => false,
+	};
+	if read_only {
+		let db_path = db_dirs.db_path(algorithm);
+		if db::is_migration_needed(&db_path).map_err(|e| format!("{}", e))? {
+			return Err(format!("The database at {} has to be upgraded first. Run `parity db compact` to upgrade it.", db_path.display()));
+		}
+	} else {
+		execute_upgrades(&cmd.dirs.base, &db_dirs, algorithm, &cmd.compaction)?;
+	}
+
+	let open = || -> Result<Arc<BlockChainDB>, String> {
+		let db_backend = DatabaseBackend::resolve(None, &client_path)?;
+		db::open_db(&client_path.to_string_lossy(), &cmd.cache_config, &cmd.compaction, db_backend)
+			.map_err(|e| format!("Failed to open database: {:?}", e))
+	};
+
+	match cmd.action {
+		DatabaseAction::Stats => {
+			let db = open()?;
+			let columns = iter::once(None).chain((0..::ethcore::db::NUM_COLUMNS.unwrap_or(0)).map(Some));
+			println!("{:>8} {:>12} {:>12} {:>14}", "Column", "Keys", "Key bytes", "Value bytes");
+			for col in columns {
+				let (mut keys, mut key_bytes, mut value_bytes) = (0u64, 0u64, 0u64);
+				for (key, value) in db.key_value().iter(col) {
+					keys += 1;
+					key_bytes += key.len() as u64;
+					value_bytes += value.len() as u64;
+				}
+				let name = col.map_or("default".to_owned(), |c| c.to_string());
+				println!("{:>8} {:>12} {:>12} {:>14}", name, keys, key_bytes, value_bytes);
+			}
+			Ok(())
+		},
+		DatabaseAction::Compact => {
+			info!("Compacting database at {}", client_path.display());
+			db::compact(&client_path, &cmd.compaction)?;
+			info!("Database compacted.");
+			Ok(())
+		},
+		DatabaseAction::Compress => {
+			let compressed = db::compress_history(&**open()?.key_value())
+				.map_err(|e| format!("Failed to compress bodies and receipts: {}", e))?;
+			info!("Re-encoded {} bodies and receipts. Run `parity db compact` to reclaim disk space.", compressed);
+			Ok(())
+		},
+		DatabaseAction::Check => {
+			let check = check_database(open()?, &spec.genesis_block(), algorithm);
+			info!("Checked {} headers, {} bodies, {} receipts and {} accounts.", check.headers, check.bodies, check.receipts, check.accounts);
+			if check.is_ok() {
+				info!("Database is consistent.");
+				Ok(())
+			} else {
+				for error in &check.errors {
+					warn!("{}", error);
+				}
+				Err(format!("Found {} inconsistencies.", check.errors.len()))
+			}
+		},
+		DatabaseAction::PruneHistory(before) => {
+			let pruned = prune_history(open()?, &spec.genesis_block(), before)
+				.map_err(|e| format!("Failed to prune history: {}", e))?;
+			info!("Removed bodies and receipts of {} blocks. Run `parity db compact` to reclaim disk space.", pruned);
+			Ok(())
+		},
+		DatabaseAction::PruneState(keep) => {
+			if algorithm != Algorithm::Archive {
+				return Err(format!("Only archive databases can be pruned, this one uses {}.", algorithm));
+			}
+			if user_defaults.fat_db {
+				return Err("Databases with fat DB enabled can't be pruned.".into());
+			}
+			let target = db_dirs.db_path(Algorithm::OverlayRecent);
+			if target.exists() {
+				return Err(format!("A fast pruning database already exists at {}. Remove it first.", target.display()));
+			}
+
+			let marks_path = db_dirs.db_path(Algorithm::Archive).join("prune-state");
+			{
+				let marks = db::open_scratch_db(&marks_path)?;
+				info!("Pruning the state of all but the latest {} blocks. The command can be resumed if interrupted.", keep);
+				let pruned = prune_state(open()?, &spec.genesis_block(), &**marks, keep)
+					.map_err(|e| format!("Failed to prune state: {}", e))?;
+				info!("Kept {} state entries and removed {}.", pruned.marked, pruned.deleted);
+			}
+			fs::remove_dir_all(&marks_path).map_err(|e| format!("Error removing {}: {:?}", marks_path.display(), e))?;
+
+			fs::rename(db_dirs.db_path(Algorithm::Archive), &target)
+				.map_err(|e| format!("Error moving database to {}: {:?}", target.display(), e))?;
+			user_defaults.pruning = Algorithm::OverlayRecent;
+			user_defaults.save(&user_defaults_path)?;
+			info!("Database converted to fast pruning. Run `parity db compact` to reclaim disk space.");
+			Ok(())
+		},
+		DatabaseAction::CheckJournal => {
+			let db = open()?;
+			let check = journaldb::check_journal(&**db.key_value(), ::ethcore::db::COL_STATE, algorithm)
+				.map_err(|e| format!("Failed to check the journal: {}", e))?;
+			match (check.earliest_era, check.latest_era) {
+				(Some(earliest), Some(latest)) => info!("Checked {} journal records of eras #{} to #{}.", check.records, earliest, latest),
+				_ => info!("The journal is empty."),
+			}
+			if check.is_ok() {
+				info!("Journal is consistent.");
+				return Ok(());
+			}
+
+			for &(era, index) in &check.orphaned {
+				warn!("Journal record #{}.{} is never replayed.", era, index);
+			}
+			for &(era, index) in &check.corrupted {
+				warn!("Journal record #{}.{} is corrupted.", era, index);
+			}
+			for key in &check.dangling {
+				warn!("Journaled key {:x} is missing from the database.", key);
+			}
+			for key in &check.over_referenced {
+				warn!("Key {:x} is referenced by no journal record and will never be removed.", key);
+			}
+			Err(format!(
+				"Found {} orphaned and {} corrupted records, {} dangling and {} over-referenced keys. Run with --repair to rebuild the journal.",
+				check.orphaned.len(), check.corrupted.len(), check.dangling.len(), check.over_referenced.len(),
+			))
+		},
+		DatabaseAction::RepairJournal(block) => {
+			if user_defaults.fat_db {
+				return Err("Databases with fat DB enabled can't be repaired.".into());
+			}
+
+			let marks_path = db_dirs.db_path(algorithm).join("repair-journal");
+			{
+				let marks = db::open_scratch_db(&marks_path)?;
+				info!("Rebuilding the journal. The command can be resumed if interrupted, using the same --block.");
+				let repair = repair_journal(open()?, &spec.genesis_block(), algorithm, &**marks, block)
+					.map_err(|e| format!("Failed to repair the journal: {}", e))?;
+				info!(
+					"Kept {} state entries and removed {}, dropped {} journal records and {} markers.",
+					repair.marked, repair.deleted, repair.reset.records, repair.reset.markers,
+				);
+				if repair.reverted > 0 {
+					info!("Reverted {} blocks whose state was lost.", repair.reverted);
+				}
+			}
+			fs::remove_dir_all(&marks_path).map_err(|e| format!("Error removing {}: {:?}", marks_path.display(), e))?;
+			info!("Journal repaired.");
+			Ok(())
+		},
+	}
+}
+
 #[cfg(test)]
 mod test {
 	use super::DataFormat;
@@ -75,6 +75,23 @@ usage! {
 		{
 			"Import blockchain data from a file to the given --chain database (default: mainnet)",
 
+			CMD cmd_import_state
+			{
+				"Build a chain spec compatible genesis state from a state dump made with `parity export state --stream`",
+
+				ARG arg_import_state_format: (Option<String>) = None,
+				"--format=[FORMAT]",
+				"Read the state dump in a given format. FORMAT must be either 'jsonl' or 'rlp'. (default: auto)",
+
+				ARG arg_import_state_out: (Option<String>) = None,
+				"--out=[FILE]",
+				"Path to write the genesis accounts to. (default: stdout)",
+
+				ARG arg_import_state_file: (Option<String>) = None,
+				"[FILE]",
+				"Path to the state dump to import from",
+			}
+
 			ARG arg_import_format: (Option<String>) = None,
 			"--format=[FORMAT]",
 			"Import in a given format. FORMAT must be either 'hex' or 'binary'. (default: auto)",
@@ -137,6 +154,10 @@ usage! {
 				"--format=[FORMAT]",
 				"Export in a given format. FORMAT must be either 'hex' or 'binary'. (default: binary)",
 
+				ARG arg_export_state_stream: (Option<String>) = None,
+				"--stream=[FORMAT]",
+				"Export a streaming state dump with one record per account instead of a single JSON object. FORMAT must be either 'jsonl' or 'rlp'.",
+
 				ARG arg_export_state_file: (Option<String>) = None,
 				"[FILE]",
 				"Path to the exported file",
@@ -178,12 +199,30 @@ usage! {
 		{
 			"Make a snapshot of the database of the given --chain (default: mainnet)",
 
+			CMD cmd_snapshot_verify
+			{
+				"Verify a snapshot file for the given --chain (default: mainnet) by checking every chunk and rebuilding its state and blocks in a scratch database",
+
+				ARG arg_snapshot_verify_file: (Option<String>) = None,
+				"<FILE>",
+				"Path to the snapshot file to verify",
+			}
+
+			CMD cmd_snapshot_inspect
+			{
+				"Print the manifest, chunk sizes, account count and block range of a snapshot file",
+
+				ARG arg_snapshot_inspect_file: (Option<String>) = None,
+				"<FILE>",
+				"Path to the snapshot file to inspect",
+			}
+
 			ARG arg_snapshot_at: (String) = "latest",
 			"--at=[BLOCK]",
 			"Take a snapshot at the given block, which may be an index, hash, or latest. Note that taking snapshots at non-recent blocks will only work with --pruning archive",
 
 			ARG arg_snapshot_file: (Option<String>) = None,
-			"<FILE>",
+			"[FILE]",
 			"Path to the file to export to",
 		}
 
@@ -194,6 +233,10 @@ usage! {
 			ARG arg_restore_file: (Option<String>) = None,
 			"[FILE]",
 			"Path to the file to restore from",
+
+			ARG arg_restore_delta: (Option<String>) = None,
+			"--delta=[DIRS]",
+			"Comma-separated list of delta snapshot directories to apply on top of the restored snapshot, oldest first.",
 		}
 
 		CMD cmd_tools
@@ -217,6 +260,58 @@ usage! {
 			CMD cmd_db_kill {
 				"Clean the database of the given --chain (default: mainnet)",
 			}
+
+			CMD cmd_db_stats {
+				"Print the number of keys and their sizes in every column of the database of the given --chain (default: mainnet)",
+			}
+
+			CMD cmd_db_compact {
+				"Compact the database of the given --chain (default: mainnet) by rewriting it",
+			}
+
+			CMD cmd_db_compress {
+				"Re-encode the block bodies and receipts stored by older versions in the database of the given --chain (default: mainnet) in the compressed format",
+			}
+
+			CMD cmd_db_check {
+				"Check the canonical chain and the state of the best block in the database of the given --chain (default: mainnet)",
+			}
+
+			CMD cmd_db_prune_history {
+				"Remove the bodies and receipts of ancient blocks from the database of the given --chain (default: mainnet)",
+
+				ARG arg_db_prune_history_before: (Option<u64>) = None,
+				"--before=[NUM]",
+				"Remove the bodies and receipts of blocks before block NUM. Headers are kept.",
+			}
+
+			CMD cmd_db_prune_state {
+				"Remove the state of old blocks from the archive database of the given --chain (default: mainnet) and convert it to fast pruning",
+
+				ARG arg_db_prune_state_keep: (u64) = 64u64,
+				"--keep=[NUM]",
+				"Keep the state of the NUM most recent blocks.",
+			}
+
+			CMD cmd_db_check_journal {
+				"Check the state journal of the pruned database of the given --chain (default: mainnet) against the stored state",
+
+				FLAG flag_db_check_journal_repair: (bool) = false,
+				"--repair",
+				"Rebuild the journal from the state of a trusted block, reverting any later blocks.",
+
+				ARG arg_db_check_journal_block: (Option<u64>) = None,
+				"--block=[NUM]",
+				"Trust the state of block NUM when repairing. Defaults to the best block.",
+			}
+
+			CMD cmd_db_migrate_backend {
+				"Convert the database of the given --chain (default: mainnet) to another key-value backend",
+
+				ARG arg_db_migrate_backend_to: (Option<String>) = None,
+				"--to=[BACKEND]",
+				"Backend to convert the database to. BACKEND may be one of: rocksdb, lmdb.",
+			}
 		}
 
 		CMD cmd_export_hardcoded_sync
@@ -403,6 +498,14 @@ usage! {
 			"--warp-barrier=[NUM]",
 			"When warp enabled never attempt regular sync before warping to block NUM.",
 
+			ARG arg_sync_mode: (String) = "full", or |c: &Config| c.network.as_ref()?.sync_mode.clone(),
+			"--sync-mode=[MODE]",
+			"Set how the initial state is retrieved. MODE can be one of: full - import every block from genesis or the last snapshot; state - download the state of a recent block from peers' account and storage range proofs, then import the blocks after it. State mode is only supported on proof-of-work chains and disables warp sync.",
+
+			ARG arg_checkpoint: (Option<String>) = None, or |c: &Config| c.network.as_ref()?.checkpoint.clone(),
+			"--checkpoint=[HASH@NUM]",
+			"Sync from a trusted block instead of genesis. With warp sync the state is restored from a snapshot of block NUM with hash HASH or a later one. With state sync mode the headers are downloaded back from the block to the last known one, then its state is downloaded. Blocks before it are downloaded later as ancient blocks and chains without it are rejected. Not supported on chains with validator set transitions.",
+
 			ARG arg_port: (u16) = 30303u16, or |c: &Config| c.network.as_ref()?.port.clone(),
 			"--port=[PORT]",
 			"Override the port on which the node should listen.",
@@ -435,6 +538,22 @@ usage! {
 			"--max-pending-peers=[NUM]",
 			"Allow up to NUM pending connections.",
 
+			ARG arg_max_upload_rate: (Option<u64>) = None, or |c: &Config| c.network.as_ref()?.max_upload_rate.clone(),
+			"--max-upload-rate=[KBPS]",
+			"Limit uploads to all peers to KBPS kilobytes per second.",
+
+			ARG arg_max_peer_upload_rate: (Option<u64>) = None, or |c: &Config| c.network.as_ref()?.max_peer_upload_rate.clone(),
+			"--max-peer-upload-rate=[KBPS]",
+			"Limit uploads to each peer to KBPS kilobytes per second.",
+
+			ARG arg_warp_upload_rate: (Option<u64>) = None, or |c: &Config| c.network.as_ref()?.warp_upload_rate.clone(),
+			"--warp-upload-rate=[KBPS]",
+			"Limit uploads of snapshot data to peers doing a warp sync to KBPS kilobytes per second.",
+
+			ARG arg_light_upload_rate: (Option<u64>) = None, or |c: &Config| c.network.as_ref()?.light_upload_rate.clone(),
+			"--light-upload-rate=[KBPS]",
+			"Limit uploads to light clients to KBPS kilobytes per second.",
+
 			ARG arg_network_id: (Option<u64>) = None, or |c: &Config| c.network.as_ref()?.id.clone(),
 			"--network-id=[INDEX]",
 			"Override the network identifier from the chain we are on.",
@@ -443,6 +562,14 @@ usage! {
 			"--bootnodes=[NODES]",
 			"Override the bootnodes from our chain. NODES should be comma-delimited enodes.",
 
+			ARG arg_dns_node_lists: (Option<String>) = None, or |c: &Config| c.network.as_ref()?.dns_node_lists.as_ref().map(|vec| vec.join(",")),
+			"--dns-node-lists=[URLS]",
+			"Bootstrap from EIP-1459 node lists published in DNS. URLS should be comma-delimited enrtree:// URLs.",
+
+			ARG arg_dns_servers: (Option<String>) = None, or |c: &Config| c.network.as_ref()?.dns_servers.as_ref().map(|vec| vec.join(",")),
+			"--dns-servers=[SERVERS]",
+			"Resolve DNS node lists with these name servers instead of those of /etc/resolv.conf. SERVERS should be comma-delimited IP addresses, with an optional port. Public name servers are used if none are configured or found.",
+
 			ARG arg_node_key: (Option<String>) = None, or |c: &Config| c.network.as_ref()?.node_key.clone(),
 			"--node-key=[KEY]",
 			"Specify node secret key, either as 64-character hex string or input to SHA3 operation.",
@@ -489,7 +616,7 @@ usage! {
 
 			ARG arg_jsonrpc_apis: (String) = "web3,eth,pubsub,net,parity,private,parity_pubsub,traces,rpc,shh,shh_pubsub", or |c: &Config| c.rpc.as_ref()?.apis.as_ref().map(|vec| vec.join(",")),
 			"--jsonrpc-apis=[APIS]",
-			"Specify the APIs available through the HTTP JSON-RPC interface using a comma-delimited list of API names. Possible names are: all, safe, debug, web3, net, eth, pubsub, personal, signer, parity, parity_pubsub, parity_accounts, parity_set, traces, rpc, secretstore, shh, shh_pubsub. You can also disable a specific API by putting '-' in the front, example: all,-personal. 'safe' enables the following APIs: web3, net, eth, pubsub, parity, parity_pubsub, traces, rpc, shh, shh_pubsub",
+			"Specify the APIs available through the HTTP JSON-RPC interface using a comma-delimited list of API names. Possible names are: all, safe, debug, web3, net, eth, pubsub, personal, signer, parity, parity_pubsub, parity_accounts, parity_set, traces, rpc, secretstore, shh, shh_pubsub, evm. You can also disable a specific API by putting '-' in the front, example: all,-personal. 'safe' enables the following APIs: web3, net, eth, pubsub, parity, parity_pubsub, traces, rpc, shh, shh_pubsub",
 
 			ARG arg_jsonrpc_hosts: (String) = "none", or |c: &Config| c.rpc.as_ref()?.hosts.as_ref().map(|vec| vec.join(",")),
 			"--jsonrpc-hosts=[HOSTS]",
@@ -530,7 +657,7 @@ usage! {
 
 			ARG arg_ws_apis: (String) = "web3,eth,pubsub,net,parity,parity_pubsub,private,traces,rpc,shh,shh_pubsub", or |c: &Config| c.websockets.as_ref()?.apis.as_ref().map(|vec| vec.join(",")),
 			"--ws-apis=[APIS]",
-			"Specify the JSON-RPC APIs available through the WebSockets interface using a comma-delimited list of API names. Possible names are: all, safe, web3, net, eth, pubsub, personal, signer, parity, parity_pubsub, parity_accounts, parity_set, traces, rpc, secretstore, shh, shh_pubsub. You can also disable a specific API by putting '-' in the front, example: all,-personal. 'safe' enables the following APIs: web3, net, eth, pubsub, parity, parity_pubsub, traces, rpc, shh, shh_pubsub",
+			"Specify the JSON-RPC APIs available through the WebSockets interface using a comma-delimited list of API names. Possible names are: all, safe, web3, net, eth, pubsub, personal, signer, parity, parity_pubsub, parity_accounts, parity_set, traces, rpc, secretstore, shh, shh_pubsub, evm. You can also disable a specific API by putting '-' in the front, example: all,-personal. 'safe' enables the following APIs: web3, net, eth, pubsub, parity, parity_pubsub, traces, rpc, shh, shh_pubsub",
 
 			ARG arg_ws_origins: (String) = "parity://*,chrome-extension://*,moz-extension://*", or |c: &Config| c.websockets.as_ref()?.origins.as_ref().map(|vec| vec.join(",")),
 			"--ws-origins=[URL]",
@@ -555,7 +682,7 @@ usage! {
 
 			ARG arg_ipc_apis: (String) = "web3,eth,pubsub,net,parity,parity_pubsub,parity_accounts,private,traces,rpc,shh,shh_pubsub", or |c: &Config| c.ipc.as_ref()?.apis.as_ref().map(|vec| vec.join(",")),
 			"--ipc-apis=[APIS]",
-			"Specify custom API set available via JSON-RPC over IPC using a comma-delimited list of API names. Possible names are: all, safe, web3, net, eth, pubsub, personal, signer, parity, parity_pubsub, parity_accounts, parity_set, traces, rpc, secretstore, shh, shh_pubsub. You can also disable a specific API by putting '-' in the front, example: all,-personal. 'safe' enables the following APIs: web3, net, eth, pubsub, parity, parity_pubsub, traces, rpc, shh, shh_pubsub",
+			"Specify custom API set available via JSON-RPC over IPC using a comma-delimited list of API names. Possible names are: all, safe, web3, net, eth, pubsub, personal, signer, parity, parity_pubsub, parity_accounts, parity_set, traces, rpc, secretstore, shh, shh_pubsub, evm. You can also disable a specific API by putting '-' in the front, example: all,-personal. 'safe' enables the following APIs: web3, net, eth, pubsub, parity, parity_pubsub, traces, rpc, shh, shh_pubsub",
 
 		["API and Console Options – IPFS"]
 			FLAG flag_ipfs_api: (bool) = false, or |c: &Config| c.ipfs.as_ref()?.enable.clone(),
@@ -701,6 +828,10 @@ usage! {
 			"--infinite-pending-block",
 			"Pending block will be created with maximal possible gas limit and will execute all transactions in the queue. Note that such block is invalid and should never be attempted to be mined.",
 
+			FLAG flag_ethash_full_dag: (bool) = false, or |c: &Config| c.mining.as_ref()?.ethash_full_dag.clone(),
+			"--ethash-full-dag",
+			"Generate the full Ethash DAG of the current and next epoch in the background and use it for block verification. Requires several GB of disk space in the cache directory.",
+
 			FLAG flag_no_persistent_txqueue: (bool) = false, or |c: &Config| c.parity.as_ref()?.no_persistent_txqueue,
 			"--no-persistent-txqueue",
 			"Don't save pending local transactions to disk to be restored whenever the node restarts.",
@@ -860,6 +991,14 @@ usage! {
 			"--pruning-memory=[MB]",
 			"The ideal amount of memory in megabytes to use to store recent states. As many states as possible will be kept within this limit, and at least --pruning-history states will always be kept.",
 
+			ARG arg_history_expiry: (Option<u64>) = None, or |c: &Config| c.footprint.as_ref()?.history_expiry.clone(),
+			"--history-expiry=[BLOCKS]",
+			"Keep the bodies and receipts of only the most recent BLOCKS blocks, removing older ones in the background. Headers are always kept. At least 30000 blocks are kept.",
+
+			FLAG flag_flat_state: (bool) = false, or |c: &Config| c.footprint.as_ref()?.flat_state.clone(),
+			"--flat-state",
+			"Keep a flat copy of the accounts and storage of recent states to speed up state reads, e.g. for eth_call. Generating it on an existing database takes a while.",
+
 			ARG arg_cache_size_db: (u32) = 128u32, or |c: &Config| c.footprint.as_ref()?.cache_size_db.clone(),
 			"--cache-size-db=[MB]",
 			"Override database cache size.",
@@ -880,6 +1019,14 @@ usage! {
 			"--db-compaction=[TYPE]",
 			"Database compaction type. TYPE may be one of: ssd - suitable for SSDs and fast HDDs; hdd - suitable for slow HDDs; auto - determine automatically.",
 
+			ARG arg_db_backend: (Option<String>) = None, or |c: &Config| c.footprint.as_ref()?.db_backend.clone(),
+			"--db-backend=[BACKEND]",
+			"Key-value database backend used for new databases. BACKEND may be one of: rocksdb, lmdb. Existing databases are always opened with the backend they were created with; use `parity db migrate-backend` to convert them.",
+
+			ARG arg_db_lmdb_map_size: (Option<usize>) = None, or |c: &Config| c.footprint.as_ref()?.db_lmdb_map_size.clone(),
+			"--db-lmdb-map-size=[MB]",
+			"Maximum size of an LMDB database in megabytes, reserved as address space when it is opened. Applies with --db-backend=lmdb. Defaults to 1048576 on 64-bit systems and 1024 on 32-bit ones.",
+
 			ARG arg_fat_db: (String) = "auto", or |c: &Config| c.footprint.as_ref()?.fat_db.clone(),
 			"--fat-db=[BOOL]",
 			"Build appropriate information to allow enumeration of all accounts and storage keys. Doubles the size of the state database. BOOL may be one of on, off or auto.",
@@ -906,6 +1053,18 @@ usage! {
 			"--snapshot-threads=[NUM]",
 			"Enables multiple threads for snapshots creation.",
 
+			ARG arg_snapshot_period: (u64) = 5000u64, or |c: &Config| c.snapshots.as_ref()?.period.clone(),
+			"--snapshot-period=[BLOCKS]",
+			"Number of blocks between automated snapshots.",
+
+			ARG arg_snapshot_retention: (usize) = 1usize, or |c: &Config| c.snapshots.as_ref()?.retention.clone(),
+			"--snapshot-retention=[NUM]",
+			"Number of full snapshots to keep on disk. Older snapshots are moved to the snapshot archive directory instead of being deleted.",
+
+			ARG arg_snapshot_deltas: (usize) = 0usize, or |c: &Config| c.snapshots.as_ref()?.deltas.clone(),
+			"--snapshot-deltas=[NUM]",
+			"Number of delta snapshots to take between two full automated snapshots. Delta snapshots require the state of the previous snapshot to still be available, e.g. with --pruning archive.",
+
 		["Whisper Options"]
 			FLAG flag_whisper: (bool) = false, or |c: &Config| c.whisper.as_ref()?.enabled,
 			"--whisper",
@@ -1214,16 +1373,24 @@ struct Ui {
 struct Network {
 	warp: Option<bool>,
 	warp_barrier: Option<u64>,
+	sync_mode: Option<String>,
+	checkpoint: Option<String>,
 	port: Option<u16>,
 	interface: Option<String>,
 	min_peers: Option<u16>,
 	max_peers: Option<u16>,
 	snapshot_peers: Option<u16>,
 	max_pending_peers: Option<u16>,
+	max_upload_rate: Option<u64>,
+	max_peer_upload_rate: Option<u64>,
+	warp_upload_rate: Option<u64>,
+	light_upload_rate: Option<u64>,
 	nat: Option<String>,
 	allow_ips: Option<String>,
 	id: Option<u64>,
 	bootnodes: Option<Vec<String>>,
+	dns_node_lists: Option<Vec<String>>,
+	dns_servers: Option<Vec<String>>,
 	discovery: Option<bool>,
 	node_key: Option<String>,
 	reserved_peers: Option<String>,
@@ -1357,6 +1524,7 @@ struct Mining {
 	notify_work: Option<Vec<String>>,
 	refuse_service_transactions: Option<bool>,
 	infinite_pending_block: Option<bool>,
+	ethash_full_dag: Option<bool>,
 	max_round_blocks_to_import: Option<usize>,
 }
 
@@ -1375,6 +1543,8 @@ struct Footprint {
 	pruning: Option<String>,
 	pruning_history: Option<u64>,
 	pruning_memory: Option<usize>,
+	history_expiry: Option<u64>,
+	flat_state: Option<bool>,
 	fast_and_loose: Option<bool>,
 	cache_size: Option<u32>,
 	cache_size_db: Option<u32>,
@@ -1382,6 +1552,8 @@ struct Footprint {
 	cache_size_queue: Option<u32>,
 	cache_size_state: Option<u32>,
 	db_compaction: Option<String>,
+	db_backend: Option<String>,
+	db_lmdb_map_size: Option<usize>,
 	fat_db: Option<String>,
 	scale_verifiers: Option<bool>,
 	num_verifiers: Option<usize>,
@@ -1392,6 +1564,9 @@ struct Footprint {
 struct Snapshots {
 	disable_periodic: Option<bool>,
 	processing_threads: Option<usize>,
+	period: Option<u64>,
+	retention: Option<usize>,
+	deltas: Option<usize>,
 }
 
 #[derive(Default, Debug, PartialEq, Deserialize)]
@@ -1509,6 +1684,57 @@ mod tests {
 		assert_eq!(args.arg_export_state_at, "latest");
 	}
 
+	#[test]
+	fn should_parse_db_subcommands() {
+		let args = Args::parse(&["parity", "db", "stats"]).unwrap();
+		assert!(args.cmd_db && args.cmd_db_stats);
+
+		let args = Args::parse(&["parity", "db", "compress"]).unwrap();
+		assert!(args.cmd_db && args.cmd_db_compress && !args.cmd_db_compact);
+
+		let args = Args::parse(&["parity", "db", "prune-history", "--before", "1000"]).unwrap();
+		assert!(args.cmd_db && args.cmd_db_prune_history);
+		assert_eq!(args.arg_db_prune_history_before, Some(1000));
+
+		let args = Args::parse(&["parity", "db", "prune-state"]).unwrap();
+		assert!(args.cmd_db && args.cmd_db_prune_state);
+		assert_eq!(args.arg_db_prune_state_keep, 64);
+
+		let args = Args::parse(&["parity", "db", "prune-state", "--keep", "128"]).unwrap();
+		assert_eq!(args.arg_db_prune_state_keep, 128);
+
+		let args = Args::parse(&["parity", "db", "check-journal"]).unwrap();
+		assert!(args.cmd_db && args.cmd_db_check_journal);
+		assert!(!args.flag_db_check_journal_repair);
+
+		let args = Args::parse(&["parity", "db", "check-journal", "--repair", "--block", "100"]).unwrap();
+		assert!(args.flag_db_check_journal_repair);
+		assert_eq!(args.arg_db_check_journal_block, Some(100));
+	}
+
+	#[test]
+	fn should_parse_db_migrate_backend() {
+		let args = Args::parse(&["parity", "db", "migrate-backend", "--to", "lmdb"]).unwrap();
+		assert!(args.cmd_db && args.cmd_db_migrate_backend && !args.cmd_db_kill);
+		assert_eq!(args.arg_db_migrate_backend_to, Some("lmdb".into()));
+	}
+
+	#[test]
+	fn should_parse_snapshot_subcommands() {
+		let args = Args::parse(&["parity", "snapshot", "verify", "file.dump"]).unwrap();
+		assert!(args.cmd_snapshot && args.cmd_snapshot_verify);
+		assert_eq!(args.arg_snapshot_verify_file, Some("file.dump".into()));
+		assert_eq!(args.arg_snapshot_file, None);
+
+		let args = Args::parse(&["parity", "snapshot", "inspect", "file.dump"]).unwrap();
+		assert!(args.cmd_snapshot && args.cmd_snapshot_inspect);
+		assert_eq!(args.arg_snapshot_inspect_file, Some("file.dump".into()));
+
+		let args = Args::parse(&["parity", "snapshot", "file.dump"]).unwrap();
+		assert!(args.cmd_snapshot && !args.cmd_snapshot_verify && !args.cmd_snapshot_inspect);
+		assert_eq!(args.arg_snapshot_file, Some("file.dump".into()));
+	}
+
 	#[test]
 	fn should_parse_multiple_values() {
 		let args = Args::parse(&["parity", "account", "import", "~/1", "~/2"]).unwrap();
@@ -1599,6 +1825,7 @@ mod tests {
 			cmd_wallet: false,
 			cmd_wallet_import: false,
 			cmd_import: false,
+			cmd_import_state: false,
 			cmd_export: false,
 			cmd_export_blocks: false,
 			cmd_export_state: false,
@@ -1608,11 +1835,21 @@ mod tests {
 			cmd_signer_reject: false,
 			cmd_signer_new_token: false,
 			cmd_snapshot: false,
+			cmd_snapshot_verify: false,
+			cmd_snapshot_inspect: false,
 			cmd_restore: false,
 			cmd_tools: false,
 			cmd_tools_hash: false,
 			cmd_db: false,
 			cmd_db_kill: false,
+			cmd_db_stats: false,
+			cmd_db_compact: false,
+			cmd_db_compress: false,
+			cmd_db_check: false,
+			cmd_db_prune_history: false,
+			cmd_db_prune_state: false,
+			cmd_db_check_journal: false,
+			cmd_db_migrate_backend: false,
 			cmd_export_hardcoded_sync: false,
 
 			// Arguments
@@ -1623,9 +1860,21 @@ mod tests {
 			arg_export_blocks_format: None,
 			arg_export_state_file: None,
 			arg_export_state_format: None,
+			arg_export_state_stream: None,
+			arg_import_state_format: None,
+			arg_import_state_out: None,
+			arg_import_state_file: None,
 			arg_snapshot_file: None,
+			arg_snapshot_verify_file: None,
+			arg_snapshot_inspect_file: None,
 			arg_restore_file: None,
+			arg_restore_delta: None,
 			arg_tools_hash_file: None,
+			arg_db_prune_history_before: None,
+			arg_db_prune_state_keep: 64u64,
+			flag_db_check_journal_repair: false,
+			arg_db_check_journal_block: None,
+			arg_db_migrate_backend_to: None,
 
 			arg_signer_sign_id: None,
 			arg_signer_reject_id: None,
@@ -1686,16 +1935,24 @@ mod tests {
 
 			// -- Networking Options
 			flag_no_warp: false,
+			arg_sync_mode: "full".into(),
+			arg_checkpoint: None,
 			arg_port: 30303u16,
 			arg_interface: "all".into(),
 			arg_min_peers: Some(25u16),
 			arg_max_peers: Some(50u16),
 			arg_max_pending_peers: 64u16,
+			arg_max_upload_rate: None,
+			arg_max_peer_upload_rate: None,
+			arg_warp_upload_rate: None,
+			arg_light_upload_rate: None,
 			arg_snapshot_peers: 0u16,
 			arg_allow_ips: "all".into(),
 			arg_nat: "any".into(),
 			arg_network_id: Some(1),
 			arg_bootnodes: Some("".into()),
+			arg_dns_node_lists: None,
+			arg_dns_servers: None,
 			flag_no_discovery: false,
 			arg_node_key: None,
 			arg_reserved_peers: Some("./path_to_file".into()),
@@ -1796,6 +2053,7 @@ mod tests {
 			arg_notify_work: Some("http://localhost:3001".into()),
 			flag_refuse_service_transactions: false,
 			flag_infinite_pending_block: false,
+			flag_ethash_full_dag: false,
 			arg_max_round_blocks_to_import: 12usize,
 
 			flag_stratum: false,
@@ -1808,6 +2066,8 @@ mod tests {
 			arg_pruning: "auto".into(),
 			arg_pruning_history: 64u64,
 			arg_pruning_memory: 500usize,
+			arg_history_expiry: Some(100_000),
+			flag_flat_state: true,
 			arg_cache_size_db: 64u32,
 			arg_cache_size_blocks: 8u32,
 			arg_cache_size_queue: 50u32,
@@ -1815,6 +2075,8 @@ mod tests {
 			arg_cache_size: Some(128),
 			flag_fast_and_loose: false,
 			arg_db_compaction: "ssd".into(),
+			arg_db_backend: Some("rocksdb".into()),
+			arg_db_lmdb_map_size: Some(2048),
 			arg_fat_db: "auto".into(),
 			flag_scale_verifiers: true,
 			arg_num_verifiers: Some(6),
@@ -1833,6 +2095,9 @@ mod tests {
 			arg_snapshot_at: "latest".into(),
 			flag_no_periodic_snapshot: false,
 			arg_snapshot_threads: None,
+			arg_snapshot_period: 5000u64,
+			arg_snapshot_retention: 1usize,
+			arg_snapshot_deltas: 0usize,
 
 			// -- Light options.
 			arg_on_demand_response_time_window: Some(2),
@@ -1959,16 +2224,24 @@ mod tests {
 			network: Some(Network {
 				warp: Some(false),
 				warp_barrier: None,
+				sync_mode: None,
+				checkpoint: None,
 				port: None,
 				interface: None,
 				min_peers: Some(10),
 				max_peers: Some(20),
 				max_pending_peers: Some(30),
+				max_upload_rate: None,
+				max_peer_upload_rate: None,
+				warp_upload_rate: None,
+				light_upload_rate: None,
 				snapshot_peers: Some(40),
 				allow_ips: Some("public".into()),
 				nat: Some("any".into()),
 				id: None,
 				bootnodes: None,
+				dns_node_lists: None,
+				dns_servers: None,
 				discovery: Some(true),
 				node_key: None,
 				reserved_peers: Some("./path/to/reserved_peers".into()),
@@ -2074,6 +2347,7 @@ mod tests {
 				notify_work: None,
 				refuse_service_transactions: None,
 				infinite_pending_block: None,
+				ethash_full_dag: None,
 				max_round_blocks_to_import: None,
 			}),
 			footprint: Some(Footprint {
@@ -2081,6 +2355,8 @@ mod tests {
 				pruning: Some("fast".into()),
 				pruning_history: Some(64),
 				pruning_memory: None,
+				history_expiry: None,
+				flat_state: None,
 				fast_and_loose: None,
 				cache_size: None,
 				cache_size_db: Some(256),
@@ -2088,6 +2364,8 @@ mod tests {
 				cache_size_queue: Some(100),
 				cache_size_state: Some(25),
 				db_compaction: Some("ssd".into()),
+				db_backend: None,
+				db_lmdb_map_size: None,
 				fat_db: Some("off".into()),
 				scale_verifiers: Some(false),
 				num_verifiers: None,
@@ -2102,6 +2380,9 @@ mod tests {
 			snapshots: Some(Snapshots {
 				disable_periodic: Some(true),
 				processing_threads: None,
+				period: None,
+				retention: None,
+				deltas: None,
 			}),
 			misc: Some(Misc {
 				logging: Some("own_tx=trace".into()),
@@ -38,7 +38,8 @@ use num_cpus;
 use rpc::{IpcConfiguration, HttpConfiguration, WsConfiguration};
 use parity_rpc::NetworkSettings;
 use cache::CacheConfig;
-use helpers::{to_duration, to_mode, to_block_id, to_u256, to_pending_set, to_price, geth_ipc_path, parity_ipc_path, to_bootnodes, to_addresses, to_address, to_queue_strategy, to_queue_penalization, passwords_from_files};
+use db::DatabaseBackend;
+use helpers::{to_duration, to_mode, to_block_id, to_u256, to_pending_set, to_price, geth_ipc_path, parity_ipc_path, to_bootnodes, to_dns_node_lists, to_dns_servers, to_addresses, to_address, to_queue_strategy, to_queue_penalization, passwords_from_files};
 use dir::helpers::{replace_home, replace_home_and_local};
 use params::{ResealPolicy, AccountsConfig, GasPricerConfig, MinerExtras, SpecType};
 use ethcore_logger::Config as LogConfig;
@@ -48,7 +49,7 @@ use ethcore_private_tx::{ProviderConfig, EncryptorConfig};
 use secretstore::{NodeSecretKey, Configuration as SecretStoreConfiguration, ContractAddress as SecretStoreContractAddress};
 use updater::{UpdatePolicy, UpdateFilter, ReleaseTrack};
 use run::RunCmd;
-use blockchain::{BlockchainCmd, ImportBlockchain, ExportBlockchain, KillBlockchain, ExportState, DataFormat};
+use blockchain::{BlockchainCmd, ImportBlockchain, ExportBlockchain, KillBlockchain, MigrateBackend, DatabaseCmd, DatabaseAction, ExportState, ImportState, DataFormat};
 use export_hardcoded_sync::ExportHsyncCmd;
 use presale::ImportWallet;
 use account::{AccountCmd, NewAccount, ListAccounts, ImportAccounts, ImportFromGethAccounts};
@@ -136,7 +137,13 @@ impl Configuration {
 		let tracing = self.args.arg_tracing.parse()?;
 		let fat_db = self.args.arg_fat_db.parse()?;
 		let compaction = self.args.arg_db_compaction.parse()?;
+		let db_backend = match self.args.arg_db_backend {
+			Some(ref backend) => Some(self.db_backend(backend)?),
+			None => None,
+		};
 		let warp_sync = !self.args.flag_no_warp;
+		let sync_mode = self.sync_mode()?;
+		let checkpoint = self.checkpoint()?;
 		let geth_compatibility = self.args.flag_geth;
 		let experimental_rpcs = self.args.flag_jsonrpc_experimental;
 		let ipfs_conf = self.ipfs_config();
@@ -182,6 +189,44 @@ impl Configuration {
 				dirs: dirs,
 				pruning: pruning,
 			}))
+		} else if self.args.cmd_db && (self.args.cmd_db_stats || self.args.cmd_db_compact || self.args.cmd_db_compress || self.args.cmd_db_check || self.args.cmd_db_prune_history || self.args.cmd_db_prune_state || self.args.cmd_db_check_journal) {
+			let action = if self.args.cmd_db_stats {
+				DatabaseAction::Stats
+			} else if self.args.cmd_db_compact {
+				DatabaseAction::Compact
+			} else if self.args.cmd_db_compress {
+				DatabaseAction::Compress
+			} else if self.args.cmd_db_check {
+				DatabaseAction::Check
+			} else if self.args.cmd_db_prune_history {
+				DatabaseAction::PruneHistory(self.args.arg_db_prune_history_before
+					.ok_or_else(|| "Missing block number. Use --before=[NUM].".to_owned())?)
+			} else if self.args.cmd_db_prune_state {
+				DatabaseAction::PruneState(self.args.arg_db_prune_state_keep)
+			} else if self.args.flag_db_check_journal_repair {
+				DatabaseAction::RepairJournal(self.args.arg_db_check_journal_block)
+			} else {
+				DatabaseAction::CheckJournal
+			};
+			Cmd::Blockchain(BlockchainCmd::Database(DatabaseCmd {
+				spec: spec,
+				dirs: dirs,
+				pruning: pruning,
+				cache_config: cache_config,
+				compaction: compaction,
+				action: action,
+			}))
+		} else if self.args.cmd_db && self.args.cmd_db_migrate_backend {
+			let to = self.args.arg_db_migrate_backend_to.as_ref()
+				.ok_or_else(|| "Missing target backend. Use --to=[BACKEND].".to_owned())
+				.and_then(|backend| self.db_backend(backend))?;
+			Cmd::Blockchain(BlockchainCmd::MigrateBackend(MigrateBackend {
+				spec: spec,
+				dirs: dirs,
+				pruning: pruning,
+				compaction: compaction,
+				to: to,
+			}))
 		} else if self.args.cmd_account {
 			let account_cmd = if self.args.cmd_account_new {
 				let new_acc = NewAccount {
@@ -226,6 +271,16 @@ impl Configuration {
 				password_file: self.accounts_config()?.password_files.first().map(|x| x.to_owned()),
 			};
 			Cmd::ImportPresaleWallet(presale_cmd)
+		} else if self.args.cmd_import_state {
+			let import_cmd = ImportState {
+				file_path: self.args.arg_import_state_file.clone(),
+				format: match self.args.arg_import_state_format {
+					Some(ref format) => Some(format.parse()?),
+					None => None,
+				},
+				out: self.args.arg_import_state_out.clone(),
+			};
+			Cmd::Blockchain(BlockchainCmd::ImportState(import_cmd))
 		} else if self.args.cmd_import {
 			let import_cmd = ImportBlockchain {
 				spec: spec,
@@ -237,6 +292,7 @@ impl Configuration {
 				pruning_history: pruning_history,
 				pruning_memory: self.args.arg_pruning_memory,
 				compaction: compaction,
+				db_backend: db_backend,
 				tracing: tracing,
 				fat_db: fat_db,
 				vm_type: vm_type,
@@ -286,12 +342,23 @@ impl Configuration {
 					min_balance: self.args.arg_export_state_min_balance.and_then(|s| to_u256(&s).ok()),
 					max_balance: self.args.arg_export_state_max_balance.and_then(|s| to_u256(&s).ok()),
 					max_round_blocks_to_import: self.args.arg_max_round_blocks_to_import,
+					stream: match self.args.arg_export_state_stream {
+						Some(ref format) => Some(format.parse()?),
+						None => None,
+					},
 				};
 				Cmd::Blockchain(BlockchainCmd::ExportState(export_cmd))
 			} else {
 				unreachable!();
 			}
 		} else if self.args.cmd_snapshot {
+			let (kind, file_path) = if self.args.cmd_snapshot_verify {
+				(snapshot::Kind::Verify, self.args.arg_snapshot_verify_file.clone())
+			} else if self.args.cmd_snapshot_inspect {
+				(snapshot::Kind::Inspect, self.args.arg_snapshot_inspect_file.clone())
+			} else {
+				(snapshot::Kind::Take, self.args.arg_snapshot_file.clone())
+			};
 			let snapshot_cmd = SnapshotCommand {
 				cache_config: cache_config,
 				dirs: dirs,
@@ -302,8 +369,10 @@ impl Configuration {
 				tracing: tracing,
 				fat_db: fat_db,
 				compaction: compaction,
-				file_path: self.args.arg_snapshot_file.clone(),
-				kind: snapshot::Kind::Take,
+				db_backend: db_backend,
+				file_path: file_path,
+				deltas: Vec::new(),
+				kind: kind,
 				block_at: to_block_id(&self.args.arg_snapshot_at)?,
 				max_round_blocks_to_import: self.args.arg_max_round_blocks_to_import,
 				snapshot_conf: snapshot_conf,
@@ -320,7 +389,11 @@ impl Configuration {
 				tracing: tracing,
 				fat_db: fat_db,
 				compaction: compaction,
+				db_backend: db_backend,
 				file_path: self.args.arg_restore_file.clone(),
+				deltas: self.args.arg_restore_delta.as_ref()
+					.map(|dirs| dirs.split(',').map(Into::into).collect())
+					.unwrap_or_default(),
 				kind: snapshot::Kind::Restore,
 				block_at: to_block_id("latest")?, // unimportant.
 				max_round_blocks_to_import: self.args.arg_max_round_blocks_to_import,
@@ -354,6 +427,8 @@ impl Configuration {
 				pruning: pruning,
 				pruning_history: pruning_history,
 				pruning_memory: self.args.arg_pruning_memory,
+				history_expiry: self.args.arg_history_expiry,
+				flat_state: self.args.flag_flat_state,
 				daemon: daemon,
 				logger_config: logger_config.clone(),
 				miner_options: self.miner_options()?,
@@ -375,9 +450,12 @@ impl Configuration {
 				tracing: tracing,
 				fat_db: fat_db,
 				compaction: compaction,
+				db_backend: db_backend,
 				vm_type: vm_type,
 				warp_sync: warp_sync,
 				warp_barrier: self.args.arg_warp_barrier,
+				sync_mode,
+				checkpoint,
 				geth_compatibility: geth_compatibility,
 				experimental_rpcs,
 				net_settings: self.network_settings()?,
@@ -394,6 +472,7 @@ impl Configuration {
 				serve_light: !self.args.flag_no_serve_light,
 				light: self.args.flag_light,
 				no_persistent_txqueue: self.args.flag_no_persistent_txqueue,
+				ethash_full_dag: self.args.flag_ethash_full_dag,
 				whisper: whisper_config,
 				no_hardcoded_sync: self.args.flag_no_hardcoded_sync,
 				max_round_blocks_to_import: self.args.arg_max_round_blocks_to_import,
@@ -620,6 +699,30 @@ impl Configuration {
 		})
 	}
 
+	fn sync_mode(&self) -> Result<sync::SyncMode, String> {
+		match self.args.arg_sync_mode.as_str() {
+			"full" => Ok(sync::SyncMode::Full),
+			"state" => Ok(sync::SyncMode::State),
+			mode => Err(format!("Invalid sync mode: {}", mode)),
+		}
+	}
+
+	fn db_backend(&self, backend: &str) -> Result<DatabaseBackend, String> {
+		match backend.parse()? {
+			DatabaseBackend::Lmdb { map_size } => Ok(DatabaseBackend::Lmdb {
+				map_size: self.args.arg_db_lmdb_map_size.map_or(map_size, |mb| mb.saturating_mul(1024 * 1024)),
+			}),
+			backend => Ok(backend),
+		}
+	}
+
+	fn checkpoint(&self) -> Result<Option<sync::Checkpoint>, String> {
+		match self.args.arg_checkpoint {
+			Some(ref checkpoint) => checkpoint.parse().map(Some),
+			None => Ok(None),
+		}
+	}
+
 	fn ipfs_config(&self) -> IpfsConfiguration {
 		IpfsConfiguration {
 			enabled: self.args.flag_ipfs_api,
@@ -717,6 +820,8 @@ impl Configuration {
 		let mut ret = NetworkConfiguration::new();
 		ret.nat_enabled = self.args.arg_nat == "any" || self.args.arg_nat == "upnp";
 		ret.boot_nodes = to_bootnodes(&self.args.arg_bootnodes)?;
+		ret.dns_node_lists = to_dns_node_lists(&self.args.arg_dns_node_lists)?;
+		ret.dns_servers = to_dns_servers(&self.args.arg_dns_servers)?;
 		let (listen, public) = self.net_addresses()?;
 		ret.listen_address = Some(format!("{}", listen));
 		ret.public_address = public.map(|p| format!("{}", p));
@@ -733,6 +838,11 @@ impl Configuration {
 		ret.snapshot_peers = self.snapshot_peers();
 		ret.ip_filter = self.ip_filter()?;
 		ret.max_pending_peers = self.max_pending_peers();
+		let kbps_to_bytes = |rate: u64| rate * 1024;
+		ret.max_upload_rate = self.args.arg_max_upload_rate.map(kbps_to_bytes);
+		ret.max_peer_upload_rate = self.args.arg_max_peer_upload_rate.map(kbps_to_bytes);
+		ret.warp_upload_rate = self.args.arg_warp_upload_rate.map(kbps_to_bytes);
+		ret.light_upload_rate = self.args.arg_light_upload_rate.map(kbps_to_bytes);
 		let mut net_path = PathBuf::from(self.directories().base);
 		net_path.push("network");
 		ret.config_path = Some(net_path.to_str().unwrap().to_owned());
@@ -921,6 +1031,12 @@ impl Configuration {
 				Some(threads) if threads > 0 => threads,
 				_ => ::std::cmp::max(1, num_cpus::get() / 2),
 			},
+			period: match self.args.arg_snapshot_period {
+				0 => return Err("--snapshot-period must be greater than 0".into()),
+				period => period,
+			},
+			retention: ::std::cmp::max(1, self.args.arg_snapshot_retention),
+			deltas: self.args.arg_snapshot_deltas,
 		};
 
 		Ok(conf)
@@ -1179,7 +1295,8 @@ mod tests {
 	use updater::{UpdatePolicy, UpdateFilter, ReleaseTrack};
 
 	use account::{AccountCmd, NewAccount, ImportAccounts, ListAccounts};
-	use blockchain::{BlockchainCmd, ImportBlockchain, ExportBlockchain, DataFormat, ExportState};
+	use blockchain::{BlockchainCmd, ImportBlockchain, ExportBlockchain, MigrateBackend, DatabaseCmd, DatabaseAction, DataFormat, ExportState, ImportState};
+	use state_dump::StateDumpFormat;
 	use cli::Args;
 	use dir::{Directories, default_hypervisor_path};
 	use helpers::{default_network_config};
@@ -1274,6 +1391,7 @@ mod tests {
 			pruning_history: 64,
 			pruning_memory: 32,
 			compaction: Default::default(),
+			db_backend: None,
 			tracing: Default::default(),
 			fat_db: Default::default(),
 			vm_type: VMType::Interpreter,
@@ -1330,9 +1448,117 @@ mod tests {
 			min_balance: None,
 			max_balance: None,
 			max_round_blocks_to_import: 12,
+			stream: None,
 		})));
 	}
 
+	#[test]
+	fn test_command_state_import() {
+		let args = vec!["parity", "import", "state", "--format", "rlp", "--out", "genesis.json", "state.rlp"];
+		let conf = parse(&args);
+		assert_eq!(conf.into_command().unwrap().cmd, Cmd::Blockchain(BlockchainCmd::ImportState(ImportState {
+			file_path: Some("state.rlp".into()),
+			format: Some(StateDumpFormat::Rlp),
+			out: Some("genesis.json".into()),
+		})));
+	}
+
+	#[test]
+	fn test_command_db_compress() {
+		let args = vec!["parity", "db", "compress"];
+		let conf = parse(&args);
+		assert_eq!(conf.into_command().unwrap().cmd, Cmd::Blockchain(BlockchainCmd::Database(DatabaseCmd {
+			spec: Default::default(),
+			dirs: Default::default(),
+			pruning: Default::default(),
+			cache_config: Default::default(),
+			compaction: Default::default(),
+			action: DatabaseAction::Compress,
+		})));
+	}
+
+	#[test]
+	fn test_command_db_prune_history() {
+		let args = vec!["parity", "db", "prune-history", "--before", "1000"];
+		let conf = parse(&args);
+		assert_eq!(conf.into_command().unwrap().cmd, Cmd::Blockchain(BlockchainCmd::Database(DatabaseCmd {
+			spec: Default::default(),
+			dirs: Default::default(),
+			pruning: Default::default(),
+			cache_config: Default::default(),
+			compaction: Default::default(),
+			action: DatabaseAction::PruneHistory(1000),
+		})));
+
+		let args = vec!["parity", "db", "prune-history"];
+		assert!(parse(&args).into_command().is_err());
+	}
+
+	#[test]
+	fn test_command_db_prune_state() {
+		let args = vec!["parity", "db", "prune-state", "--keep", "128"];
+		let conf = parse(&args);
+		assert_eq!(conf.into_command().unwrap().cmd, Cmd::Blockchain(BlockchainCmd::Database(DatabaseCmd {
+			spec: Default::default(),
+			dirs: Default::default(),
+			pruning: Default::default(),
+			cache_config: Default::default(),
+			compaction: Default::default(),
+			action: DatabaseAction::PruneState(128),
+		})));
+	}
+
+	#[test]
+	fn test_command_db_check_journal() {
+		let args = vec!["parity", "db", "check-journal"];
+		let conf = parse(&args);
+		assert_eq!(conf.into_command().unwrap().cmd, Cmd::Blockchain(BlockchainCmd::Database(DatabaseCmd {
+			spec: Default::default(),
+			dirs: Default::default(),
+			pruning: Default::default(),
+			cache_config: Default::default(),
+			compaction: Default::default(),
+			action: DatabaseAction::CheckJournal,
+		})));
+
+		let args = vec!["parity", "db", "check-journal", "--repair", "--block", "100"];
+		let conf = parse(&args);
+		assert_eq!(conf.into_command().unwrap().cmd, Cmd::Blockchain(BlockchainCmd::Database(DatabaseCmd {
+			spec: Default::default(),
+			dirs: Default::default(),
+			pruning: Default::default(),
+			cache_config: Default::default(),
+			compaction: Default::default(),
+			action: DatabaseAction::RepairJournal(Some(100)),
+		})));
+	}
+
+	#[test]
+	fn test_command_db_migrate_backend() {
+		let args = vec!["parity", "db", "migrate-backend", "--to", "lmdb"];
+		let conf = parse(&args);
+		assert_eq!(conf.into_command().unwrap().cmd, Cmd::Blockchain(BlockchainCmd::MigrateBackend(MigrateBackend {
+			spec: Default::default(),
+			dirs: Default::default(),
+			pruning: Default::default(),
+			compaction: Default::default(),
+			to: DatabaseBackend::Lmdb { map_size: ::kvdb_lmdb::DEFAULT_MAP_SIZE },
+		})));
+
+		let args = vec!["parity", "db", "migrate-backend", "--to", "lmdb", "--db-lmdb-map-size", "2048"];
+		let conf = parse(&args);
+		assert_eq!(conf.into_command().unwrap().cmd, Cmd::Blockchain(BlockchainCmd::MigrateBackend(MigrateBackend {
+			spec: Default::default(),
+			dirs: Default::default(),
+			pruning: Default::default(),
+			compaction: Default::default(),
+			to: DatabaseBackend::Lmdb { map_size: 2048 * 1024 * 1024 },
+		})));
+
+		let args = vec!["parity", "db", "migrate-backend", "--to", "leveldb"];
+		assert!(parse(&args).into_command().is_err());
+	}
+
 	#[test]
 	fn test_command_blockchain_export_with_custom_format() {
 		let args = vec!["parity", "export", "blocks", "--format", "hex", "blockchain.json"];
@@ -1401,6 +1627,8 @@ mod tests {
 			pruning: Default::default(),
 			pruning_history: 64,
 			pruning_memory: 32,
+			history_expiry: None,
+			flat_state: false,
 			daemon: None,
 			logger_config: Default::default(),
 			miner_options: Default::default(),
@@ -1413,6 +1641,8 @@ mod tests {
 			network_id: None,
 			warp_sync: true,
 			warp_barrier: None,
+			sync_mode: sync::SyncMode::Full,
+			checkpoint: None,
 			acc_conf: Default::default(),
 			gas_pricer_conf: Default::default(),
 			miner_extras: Default::default(),
@@ -1429,6 +1659,7 @@ mod tests {
 			mode: Default::default(),
 			tracing: Default::default(),
 			compaction: Default::default(),
+			db_backend: None,
 			vm_type: Default::default(),
 			geth_compatibility: false,
 			experimental_rpcs: false,
@@ -1450,6 +1681,7 @@ mod tests {
 			light: false,
 			no_hardcoded_sync: false,
 			no_persistent_txqueue: false,
+			ethash_full_dag: false,
 			whisper: Default::default(),
 			max_round_blocks_to_import: 12,
 			on_demand_response_time_window: None,
@@ -1463,6 +1695,18 @@ mod tests {
 		assert_eq!(conf.into_command().unwrap().cmd, Cmd::Run(expected));
 	}
 
+	#[test]
+	fn should_pass_ethash_full_dag_to_the_spec() {
+		let spec_params_full_dag = |args: &[&str]| match parse(args).into_command().unwrap().cmd {
+			Cmd::Run(cmd) => cmd.spec_params(None).full_dag,
+			cmd => panic!("Unexpected command: {:?}", cmd),
+		};
+
+		assert!(!spec_params_full_dag(&["parity"]));
+		assert!(spec_params_full_dag(&["parity", "--ethash-full-dag"]));
+		assert!(!spec_params_full_dag(&["parity", "--light", "--ethash-full-dag"]));
+	}
+
 	#[test]
 	fn should_parse_mining_options() {
 		// given
@@ -1792,6 +2036,41 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn test_checkpoint_arg() {
+		let hash = "0xd4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3";
+		let checkpoint = format!("{}@1000", hash);
+		let args = vec!["parity", "--checkpoint", checkpoint.as_str()];
+		let conf = Configuration::parse_cli(&args).unwrap();
+		match conf.into_command().unwrap().cmd {
+			Cmd::Run(c) => {
+				let checkpoint = c.checkpoint.unwrap();
+				assert_eq!(checkpoint.number, 1000);
+				assert_eq!(format!("0x{:x}", checkpoint.hash), hash);
+			}
+			_ => panic!("Should be Cmd::Run"),
+		}
+
+		let args = vec!["parity", "--checkpoint", hash];
+		let conf = Configuration::parse_cli(&args).unwrap();
+		assert!(conf.into_command().is_err());
+	}
+
+	#[test]
+	fn test_upload_rate_args() {
+		let args = vec!["parity", "--max-upload-rate=512", "--light-upload-rate=64"];
+		let conf = Configuration::parse_cli(&args).unwrap();
+		match conf.into_command().unwrap().cmd {
+			Cmd::Run(c) => {
+				assert_eq!(c.net_conf.max_upload_rate, Some(512 * 1024));
+				assert_eq!(c.net_conf.max_peer_upload_rate, None);
+				assert_eq!(c.net_conf.warp_upload_rate, None);
+				assert_eq!(c.net_conf.light_upload_rate, Some(64 * 1024));
+			}
+			_ => panic!("Should be Cmd::Run"),
+		}
+	}
+
 	#[test]
 	fn should_apply_ports_shift() {
 		// given
@@ -0,0 +1,130 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Key-value database backend selection.
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use kvdb_lmdb;
+
+// file present in every RocksDB database directory.
+const ROCKSDB_CURRENT_FILE: &'static str = "CURRENT";
+
+/// On-disk key-value database backend.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DatabaseBackend {
+	/// RocksDB (default).
+	RocksDb,
+	/// LMDB.
+	Lmdb {
+		/// Maximum size of the memory map in bytes, the most the database can grow to.
+		map_size: usize,
+	},
+}
+
+impl Default for DatabaseBackend {
+	fn default() -> Self {
+		DatabaseBackend::RocksDb
+	}
+}
+
+impl FromStr for DatabaseBackend {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"rocksdb" => Ok(DatabaseBackend::RocksDb),
+			"lmdb" => Ok(DatabaseBackend::Lmdb { map_size: kvdb_lmdb::DEFAULT_MAP_SIZE }),
+			x => Err(format!("Invalid database backend: {}", x))
+		}
+	}
+}
+
+impl fmt::Display for DatabaseBackend {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			DatabaseBackend::RocksDb => write!(f, "rocksdb"),
+			DatabaseBackend::Lmdb { .. } => write!(f, "lmdb"),
+		}
+	}
+}
+
+impl DatabaseBackend {
+	/// Detect the backend of the database at the given path, if there is one.
+	pub fn detect(client_path: &Path) -> Option<DatabaseBackend> {
+		if kvdb_lmdb::Database::exists(client_path) {
+			Some(DatabaseBackend::Lmdb { map_size: kvdb_lmdb::DEFAULT_MAP_SIZE })
+		} else if client_path.join(ROCKSDB_CURRENT_FILE).is_file() {
+			Some(DatabaseBackend::RocksDb)
+		} else {
+			None
+		}
+	}
+
+	/// Pick the backend to open the database at the given path with.
+	///
+	/// An existing database is always opened with its own backend; asking for a
+	/// different one is an error. New databases use the requested backend. The requested
+	/// LMDB map size applies to existing LMDB databases too.
+	pub fn resolve(requested: Option<DatabaseBackend>, client_path: &Path) -> Result<DatabaseBackend, String> {
+		match (DatabaseBackend::detect(client_path), requested) {
+			(Some(DatabaseBackend::Lmdb { .. }), Some(requested @ DatabaseBackend::Lmdb { .. })) => Ok(requested),
+			(Some(existing), Some(requested)) if existing != requested => Err(format!(
+				"Database at {} uses the {} backend, but {} was requested. Run `parity db migrate-backend --to={}` to convert it.",
+				client_path.display(), existing, requested, requested,
+			)),
+			(Some(existing), _) => Ok(existing),
+			(None, requested) => Ok(requested.unwrap_or_default()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs::{self, File};
+	use tempdir::TempDir;
+	use kvdb_lmdb::DEFAULT_MAP_SIZE;
+	use super::DatabaseBackend;
+
+	#[test]
+	fn test_database_backend_parsing() {
+		let lmdb = DatabaseBackend::Lmdb { map_size: DEFAULT_MAP_SIZE };
+		assert_eq!(DatabaseBackend::RocksDb, "rocksdb".parse().unwrap());
+		assert_eq!(lmdb, "lmdb".parse().unwrap());
+		assert!("leveldb".parse::<DatabaseBackend>().is_err());
+		assert_eq!(lmdb.to_string(), "lmdb");
+	}
+
+	#[test]
+	fn existing_database_backend_wins() {
+		let tempdir = TempDir::new("").unwrap();
+		let path = tempdir.path().join("db");
+		let lmdb = DatabaseBackend::Lmdb { map_size: DEFAULT_MAP_SIZE };
+		let large_lmdb = DatabaseBackend::Lmdb { map_size: DEFAULT_MAP_SIZE * 2 };
+
+		assert_eq!(DatabaseBackend::resolve(None, &path), Ok(DatabaseBackend::RocksDb));
+		assert_eq!(DatabaseBackend::resolve(Some(lmdb), &path), Ok(lmdb));
+
+		fs::create_dir_all(&path).unwrap();
+		File::create(path.join("data.mdb")).unwrap();
+		assert_eq!(DatabaseBackend::detect(&path), Some(lmdb));
+		assert_eq!(DatabaseBackend::resolve(None, &path), Ok(lmdb));
+		assert_eq!(DatabaseBackend::resolve(Some(large_lmdb), &path), Ok(large_lmdb));
+		assert!(DatabaseBackend::resolve(Some(DatabaseBackend::RocksDb), &path).is_err());
+	}
+}
@@ -18,8 +18,10 @@
 
 #[path="rocksdb/mod.rs"]
 mod impls;
+mod backend;
 
-pub use self::impls::{open_db, restoration_db_handler, migrate};
+pub use self::backend::DatabaseBackend;
+pub use self::impls::{open_db, open_scratch_db, restoration_db_handler, migrate, is_migration_needed, migrate_backend, compact, compress_history};
 
 #[cfg(feature = "secretstore")]
 pub use self::impls::open_secretstore_db;
@@ -14,17 +14,19 @@
 // You should have received a copy of the GNU General Public License
 // along with Parity.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::fs;
-use std::io::{Read, Write, Error as IoError, ErrorKind};
+use std::{fs, mem};
+use std::io::{self, Read, Write, Error as IoError, ErrorKind};
 use std::path::{Path, PathBuf};
 use std::fmt::{Display, Formatter, Error as FmtError};
 use super::migration_rocksdb::{Manager as MigrationManager, Config as MigrationConfig, ChangeColumns};
 use super::kvdb_rocksdb::{CompactionProfile, DatabaseConfig};
 use ethcore::client::DatabaseCompactionProfile;
-use ethcore::{self, db};
+use ethcore::{self, db, CompressedColumn, is_block_receipts_key};
+use kvdb::{DBTransaction, KeyValueDB};
 
 use super::helpers;
 use super::blooms::migrate_blooms;
+use super::DatabaseBackend;
 
 /// The migration from v10 to v11.
 /// Adds a column for node info.
@@ -42,10 +44,69 @@ pub const TO_V12: ChangeColumns = ChangeColumns {
 	version: 12,
 };
 
+/// The migration from v13 to v14.
+/// Adds a column for the flat state.
+pub const TO_V14: ChangeColumns = ChangeColumns {
+	pre_columns: Some(8),
+	post_columns: Some(9),
+	version: 14,
+};
+
+/// The migration from v14 to v15.
+/// Block bodies and receipts may be stored in the compressed format, which older versions
+/// can't read. Values already stored are left as they are, `parity db compress` re-encodes them.
+pub const TO_V15: ChangeColumns = ChangeColumns {
+	pre_columns: Some(9),
+	post_columns: Some(9),
+	version: 15,
+};
+
+/// Re-encodes a block body or receipts entry in the compressed storage format.
+/// Other entries are kept as they are.
+fn compress_value(col: Option<u32>, key: &[u8], value: &[u8]) -> io::Result<Vec<u8>> {
+	let column = match col {
+		c if c == db::COL_BODIES => Some(CompressedColumn::Bodies),
+		c if c == db::COL_EXTRA && is_block_receipts_key(key) => Some(CompressedColumn::Receipts),
+		_ => None,
+	};
+
+	match column {
+		Some(column) if !CompressedColumn::is_compressed(value) => Ok(column.encode(&column.decode(value)?)),
+		_ => Ok(value.to_vec()),
+	}
+}
+
+/// Re-encodes the block bodies and receipts stored by older versions in the compressed
+/// storage format, in place. Returns the number of re-encoded values.
+pub fn compress_history(db: &KeyValueDB) -> io::Result<u64> {
+	let mut compressed = 0;
+	for &col in &[db::COL_BODIES, db::COL_EXTRA] {
+		let mut batch = DBTransaction::new();
+		for (key, value) in db.iter(col) {
+			let encoded = compress_value(col, &key, &value)?;
+			// values which don't compress well are stored as they are.
+			if encoded[..] == value[..] {
+				continue;
+			}
+
+			batch.put(col, &key, &encoded);
+			compressed += 1;
+			if compressed % BATCH_SIZE as u64 == 0 {
+				db.write(mem::replace(&mut batch, DBTransaction::new()))?;
+				info!("Re-encoded {} bodies and receipts", compressed);
+			}
+		}
+		db.write(batch)?;
+	}
+
+	db.flush()?;
+	Ok(compressed)
+}
+
 /// Database is assumed to be at default version, when no version file is found.
 const DEFAULT_VERSION: u32 = 5;
 /// Current version of database models.
-const CURRENT_VERSION: u32 = 13;
+const CURRENT_VERSION: u32 = 15;
 /// A version of database at which blooms-db was introduced
 const BLOOMS_DB_VERSION: u32 = 13;
 /// Defines how many items are migrated to the new version of database at once.
@@ -147,6 +208,8 @@ fn consolidated_database_migrations(compaction_profile: &CompactionProfile) -> R
 	let mut manager = MigrationManager::new(default_migration_settings(compaction_profile));
 	manager.add_migration(TO_V11).map_err(|_| Error::MigrationImpossible)?;
 	manager.add_migration(TO_V12).map_err(|_| Error::MigrationImpossible)?;
+	manager.add_migration(TO_V14).map_err(|_| Error::MigrationImpossible)?;
+	manager.add_migration(TO_V15).map_err(|_| Error::MigrationImpossible)?;
 	Ok(manager)
 }
 
@@ -182,10 +245,33 @@ fn migrate_database(version: u32, db_path: &Path, mut migrations: MigrationManag
 	fs::remove_dir_all(&backup_path).map_err(Into::into)
 }
 
+/// Migrates an LMDB database.
+///
+/// Missing columns are created whenever an LMDB database is opened, and no later migration
+/// alters existing data, so only the version has to be bumped.
+fn migrate_lmdb_database(version: u32) -> Result<(), Error> {
+	// the blooms-db migration only supports RocksDB.
+	if version < BLOOMS_DB_VERSION {
+		return Err(Error::MigrationImpossible);
+	}
+
+	Ok(())
+}
+
 fn exists(path: &Path) -> bool {
 	fs::metadata(path).is_ok()
 }
 
+/// Whether the database at the given path has to be migrated before it can be used.
+pub fn is_migration_needed(path: &Path) -> Result<bool, Error> {
+	let version = current_version(path)?;
+	if version > CURRENT_VERSION {
+		return Err(Error::FutureDBVersion);
+	}
+
+	Ok(version < CURRENT_VERSION && exists(&consolidated_database_path(path)))
+}
+
 /// Migrates the database.
 pub fn migrate(path: &Path, compaction_profile: &DatabaseCompactionProfile) -> Result<(), Error> {
 	let compaction_profile = helpers::compaction_profile(&compaction_profile, path);
@@ -209,18 +295,24 @@ pub fn migrate(path: &Path, compaction_profile: &DatabaseCompactionProfile) -> R
 	// Further migrations
 	if version < CURRENT_VERSION && exists(&db_path) {
 		println!("Migrating database from version {} to {}", version, CURRENT_VERSION);
-		migrate_database(version, &db_path, consolidated_database_migrations(&compaction_profile)?)?;
-
-		if version < BLOOMS_DB_VERSION {
-			println!("Migrating blooms to blooms-db...");
-			let db_config = DatabaseConfig {
-				max_open_files: 64,
-				memory_budget: None,
-				compaction: compaction_profile,
-				columns: db::NUM_COLUMNS,
-			};
-
-			migrate_blooms(&db_path, &db_config).map_err(Error::BloomsDB)?;
+		// the migration manager can only open RocksDB databases.
+		match DatabaseBackend::detect(&db_path) {
+			Some(DatabaseBackend::RocksDb) | None => {
+				migrate_database(version, &db_path, consolidated_database_migrations(&compaction_profile)?)?;
+
+				if version < BLOOMS_DB_VERSION {
+					println!("Migrating blooms to blooms-db...");
+					let db_config = DatabaseConfig {
+						max_open_files: 64,
+						memory_budget: None,
+						compaction: compaction_profile,
+						columns: db::NUM_COLUMNS,
+					};
+
+					migrate_blooms(&db_path, &db_config).map_err(Error::BloomsDB)?;
+				}
+			},
+			Some(_) => migrate_lmdb_database(version)?,
 		}
 
 		println!("Migration finished");
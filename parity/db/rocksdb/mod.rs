@@ -17,7 +17,7 @@
 extern crate kvdb_rocksdb;
 extern crate migration_rocksdb;
 
-use std::{io, fs};
+use std::{io, fs, mem};
 use std::sync::Arc;
 use std::path::Path;
 use blooms_db;
@@ -25,15 +25,20 @@ use ethcore::{BlockChainDBHandler, BlockChainDB};
 use ethcore::db::NUM_COLUMNS;
 use ethcore::client::{ClientConfig, DatabaseCompactionProfile};
 use kvdb::KeyValueDB;
+use kvdb_lmdb;
 use self::kvdb_rocksdb::{Database, DatabaseConfig};
 
 use cache::CacheConfig;
+use super::DatabaseBackend;
 
 mod blooms;
 mod migration;
 mod helpers;
 
-pub use self::migration::migrate;
+pub use self::migration::{migrate, is_migration_needed, compress_history};
+
+// number of entries copied per transaction when rewriting a database.
+const REWRITE_BATCH_SIZE: usize = 1024;
 
 struct AppDB {
 	key_value: Arc<KeyValueDB>,
@@ -66,27 +71,35 @@ pub fn open_secretstore_db(data_path: &str) -> Result<Arc<KeyValueDB>, String> {
 	Ok(Arc::new(Database::open_default(&db_path).map_err(|e| format!("Error opening database: {:?}", e))?))
 }
 
+/// Open a single-column scratch DB used to hold temporary data of long running database operations.
+pub fn open_scratch_db(path: &Path) -> Result<Arc<KeyValueDB>, String> {
+	let path = path.to_str().ok_or_else(|| "Invalid scratch database path".to_string())?;
+	Ok(Arc::new(Database::open_default(path).map_err(|e| format!("Error opening database: {:?}", e))?))
+}
+
 /// Create a restoration db handler using the config generated by `client_path` and `client_config`.
-pub fn restoration_db_handler(client_path: &Path, client_config: &ClientConfig) -> Box<BlockChainDBHandler> {
+pub fn restoration_db_handler(client_path: &Path, client_config: &ClientConfig, backend: DatabaseBackend) -> Box<BlockChainDBHandler> {
 	let client_db_config = helpers::client_db_config(client_path, client_config);
 
 	struct RestorationDBHandler {
 		config: DatabaseConfig,
+		backend: DatabaseBackend,
 	}
 
 	impl BlockChainDBHandler for RestorationDBHandler {
 		fn open(&self, db_path: &Path) -> io::Result<Arc<BlockChainDB>> {
-			open_database(&db_path.to_string_lossy(), &self.config)
+			open_database(&db_path.to_string_lossy(), &self.config, self.backend)
 		}
 	}
 
 	Box::new(RestorationDBHandler {
 		config: client_db_config,
+		backend: backend,
 	})
 }
 
 /// Open a new main DB.
-pub fn open_db(client_path: &str, cache_config: &CacheConfig, compaction: &DatabaseCompactionProfile) -> io::Result<Arc<BlockChainDB>> {
+pub fn open_db(client_path: &str, cache_config: &CacheConfig, compaction: &DatabaseCompactionProfile, backend: DatabaseBackend) -> io::Result<Arc<BlockChainDB>> {
 	let path = Path::new(client_path);
 
 	let db_config = DatabaseConfig {
@@ -95,10 +108,10 @@ pub fn open_db(client_path: &str, cache_config: &CacheConfig, compaction: &Datab
 		.. DatabaseConfig::with_columns(NUM_COLUMNS)
 	};
 
-	open_database(client_path, &db_config)
+	open_database(client_path, &db_config, backend)
 }
 
-pub fn open_database(client_path: &str, config: &DatabaseConfig) -> io::Result<Arc<BlockChainDB>> {
+pub fn open_database(client_path: &str, config: &DatabaseConfig, backend: DatabaseBackend) -> io::Result<Arc<BlockChainDB>> {
 	let path = Path::new(client_path);
 
 	let blooms_path = path.join("blooms");
@@ -107,10 +120,104 @@ pub fn open_database(client_path: &str, config: &DatabaseConfig) -> io::Result<A
 	fs::create_dir_all(&trace_blooms_path)?;
 
 	let db = AppDB {
-		key_value: Arc::new(Database::open(&config, client_path)?),
+		key_value: open_key_value(client_path, config, backend)?,
 		blooms: blooms_db::Database::open(blooms_path)?,
 		trace_blooms: blooms_db::Database::open(trace_blooms_path)?,
 	};
 
 	Ok(Arc::new(db))
 }
+
+fn open_key_value(path: &str, config: &DatabaseConfig, backend: DatabaseBackend) -> io::Result<Arc<KeyValueDB>> {
+	Ok(match backend {
+		DatabaseBackend::RocksDb => Arc::new(Database::open(config, path)?),
+		DatabaseBackend::Lmdb { map_size } => {
+			let config = kvdb_lmdb::DatabaseConfig {
+				map_size: map_size,
+				.. kvdb_lmdb::DatabaseConfig::with_columns(config.columns)
+			};
+			Arc::new(kvdb_lmdb::Database::open(&config, path)?)
+		},
+	})
+}
+
+/// Convert the main DB at `client_path` to the given backend.
+///
+/// Every column is copied into a new database next to `client_path`, which then
+/// replaces the original. The blooms databases are moved over as they are.
+pub fn migrate_backend(client_path: &Path, to: DatabaseBackend, compaction: &DatabaseCompactionProfile) -> Result<(), String> {
+	let from = DatabaseBackend::detect(client_path)
+		.ok_or_else(|| format!("No database found at {}", client_path.display()))?;
+	// the detected LMDB map size is always the default, so only the kind of backend counts.
+	if mem::discriminant(&from) == mem::discriminant(&to) {
+		return Err(format!("Database at {} already uses the {} backend.", client_path.display(), to));
+	}
+
+	rewrite_database(client_path, from, to, compaction)
+}
+
+/// Compact the main DB at `client_path` by rewriting it into a fresh database.
+pub fn compact(client_path: &Path, compaction: &DatabaseCompactionProfile) -> Result<(), String> {
+	let backend = DatabaseBackend::detect(client_path)
+		.ok_or_else(|| format!("No database found at {}", client_path.display()))?;
+
+	rewrite_database(client_path, backend, backend, compaction)
+}
+
+// copy every column of the database at `client_path` into a new one with the given backend,
+// then replace the original with it.
+fn rewrite_database(client_path: &Path, from: DatabaseBackend, to: DatabaseBackend, compaction: &DatabaseCompactionProfile) -> Result<(), String> {
+	let temp_path = client_path.with_file_name("temp_rewrite");
+	let backup_path = client_path.with_file_name("temp_backup");
+	if temp_path.exists() {
+		fs::remove_dir_all(&temp_path).map_err(|e| format!("Error removing {}: {}", temp_path.display(), e))?;
+	}
+
+	let config = DatabaseConfig {
+		compaction: helpers::compaction_profile(compaction, client_path),
+		.. DatabaseConfig::with_columns(NUM_COLUMNS)
+	};
+
+	{
+		let source = open_key_value(&client_path.to_string_lossy(), &config, from)
+			.map_err(|e| format!("Error opening database: {}", e))?;
+		let dest = open_key_value(&temp_path.to_string_lossy(), &config, to)
+			.map_err(|e| format!("Error creating database: {}", e))?;
+
+		let columns = ::std::iter::once(None).chain((0..NUM_COLUMNS.unwrap_or(0)).map(Some));
+		for col in columns {
+			let mut copied = 0;
+			let mut batch = dest.transaction();
+			for (key, value) in source.iter(col) {
+				batch.put(col, &key, &value);
+				copied += 1;
+				if copied % REWRITE_BATCH_SIZE == 0 {
+					dest.write(batch).map_err(|e| format!("Error writing database: {}", e))?;
+					batch = dest.transaction();
+				}
+			}
+			dest.write(batch).map_err(|e| format!("Error writing database: {}", e))?;
+			info!("Copied {} entries of column {:?}", copied, col);
+		}
+
+		dest.flush().map_err(|e| format!("Error writing database: {}", e))?;
+	}
+
+	let move_dir = |from: &Path, to: &Path| fs::rename(from, to)
+		.map_err(|e| format!("Error moving {} to {}: {}", from.display(), to.display(), e));
+
+	move_dir(client_path, &backup_path)?;
+	if let Err(e) = move_dir(&temp_path, client_path) {
+		move_dir(&backup_path, client_path)?;
+		return Err(e);
+	}
+
+	for blooms in &["blooms", "trace_blooms"] {
+		if backup_path.join(blooms).exists() {
+			move_dir(&backup_path.join(blooms), &client_path.join(blooms))?;
+		}
+	}
+
+	fs::remove_dir_all(&backup_path).map_err(|e| format!("Error removing {}: {}", backup_path.display(), e))?;
+	Ok(())
+}
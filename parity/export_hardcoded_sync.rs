@@ -27,7 +27,7 @@ use helpers::execute_upgrades;
 use dir::Directories;
 use cache::CacheConfig;
 use user_defaults::UserDefaults;
-use db;
+use db::{self, DatabaseBackend};
 
 // Number of minutes before a given gas price corpus should expire.
 // Light client only.
@@ -86,9 +86,12 @@ pub fn execute(cmd: ExportHsyncCmd) -> Result<String, String> {
 	config.queue.max_mem_use = cmd.cache_config.queue() as usize * 1024 * 1024;
 
 	// initialize database.
-	let db = db::open_db(&db_dirs.client_path(algorithm).to_str().expect("DB path could not be converted to string."),
+	let client_path = db_dirs.client_path(algorithm);
+	let db_backend = DatabaseBackend::resolve(None, &client_path)?;
+	let db = db::open_db(&client_path.to_str().expect("DB path could not be converted to string."),
 						 &cmd.cache_config,
-						 &cmd.compaction).map_err(|e| format!("Failed to open database {:?}", e))?;
+						 &cmd.compaction,
+						 db_backend).map_err(|e| format!("Failed to open database {:?}", e))?;
 
 	let service = light_client::Service::start(config, &spec, UnavailableDataFetcher, db, cache)
 		.map_err(|e| format!("Error starting light client: {}", e))?;
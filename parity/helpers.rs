@@ -27,7 +27,7 @@ use cache::CacheConfig;
 use dir::DatabaseDirectories;
 use dir::helpers::replace_home;
 use upgrade::{upgrade, upgrade_data_paths};
-use sync::{validate_node_url, self};
+use sync::{validate_node_url, validate_enrtree_url, validate_dns_server, self};
 use db::migrate;
 use path;
 use ethkey::Password;
@@ -178,6 +178,34 @@ pub fn to_bootnodes(bootnodes: &Option<String>) -> Result<Vec<String>, String> {
 	}
 }
 
+/// Validates and formats DNS node lists option.
+pub fn to_dns_node_lists(lists: &Option<String>) -> Result<Vec<String>, String> {
+	match *lists {
+		Some(ref x) if !x.is_empty() => x.split(',').map(|s| {
+			match validate_enrtree_url(s) {
+				None => Ok(s.to_owned()),
+				Some(_) => Err(format!("Invalid DNS node list format given: {}", s)),
+			}
+		}).collect(),
+		Some(_) => Ok(vec![]),
+		None => Ok(vec![])
+	}
+}
+
+/// Validates and formats DNS servers option.
+pub fn to_dns_servers(servers: &Option<String>) -> Result<Vec<String>, String> {
+	match *servers {
+		Some(ref x) if !x.is_empty() => x.split(',').map(|s| {
+			match validate_dns_server(s) {
+				None => Ok(s.to_owned()),
+				Some(_) => Err(format!("Invalid DNS server address given: {}", s)),
+			}
+		}).collect(),
+		Some(_) => Ok(vec![]),
+		None => Ok(vec![])
+	}
+}
+
 #[cfg(test)]
 pub fn default_network_config() -> ::sync::NetworkConfiguration {
 	use sync::{NetworkConfiguration};
@@ -191,6 +219,8 @@ pub fn default_network_config() -> ::sync::NetworkConfiguration {
 		nat_enabled: true,
 		discovery_enabled: true,
 		boot_nodes: Vec::new(),
+		dns_node_lists: Vec::new(),
+		dns_servers: Vec::new(),
 		use_secret: None,
 		max_peers: 50,
 		min_peers: 25,
@@ -200,6 +230,10 @@ pub fn default_network_config() -> ::sync::NetworkConfiguration {
 		reserved_nodes: Vec::new(),
 		allow_non_reserved: true,
 		client_version: ::parity_version::version(),
+		max_upload_rate: None,
+		max_peer_upload_rate: None,
+		warp_upload_rate: None,
+		light_upload_rate: None,
 	}
 }
 
@@ -332,7 +366,7 @@ mod tests {
 	use ethcore::client::{Mode, BlockId};
 	use ethcore::miner::PendingSet;
 	use ethkey::Password;
-	use super::{to_duration, to_mode, to_block_id, to_u256, to_pending_set, to_address, to_addresses, to_price, geth_ipc_path, to_bootnodes, password_from_file};
+	use super::{to_duration, to_mode, to_block_id, to_u256, to_pending_set, to_address, to_addresses, to_price, geth_ipc_path, to_bootnodes, to_dns_node_lists, to_dns_servers, password_from_file};
 
 	#[test]
 	fn test_to_duration() {
@@ -472,4 +506,22 @@ but the first password is trimmed
 		assert_eq!(to_bootnodes(&Some(one_bootnode.into())), Ok(vec![one_bootnode.into()]));
 		assert_eq!(to_bootnodes(&Some(two_bootnodes.into())), Ok(vec![one_bootnode.into(), one_bootnode.into()]));
 	}
+
+	#[test]
+	fn test_to_dns_node_lists() {
+		let list = "enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@nodes.example.org";
+
+		assert_eq!(to_dns_node_lists(&Some("".into())), Ok(vec![]));
+		assert_eq!(to_dns_node_lists(&None), Ok(vec![]));
+		assert_eq!(to_dns_node_lists(&Some(list.into())), Ok(vec![list.into()]));
+		assert!(to_dns_node_lists(&Some("enrtree://AM5FCQLW@nodes.example.org".into())).is_err());
+	}
+
+	#[test]
+	fn test_to_dns_servers() {
+		assert_eq!(to_dns_servers(&Some("".into())), Ok(vec![]));
+		assert_eq!(to_dns_servers(&None), Ok(vec![]));
+		assert_eq!(to_dns_servers(&Some("1.1.1.1,[::1]:5353".into())), Ok(vec!["1.1.1.1".into(), "[::1]:5353".into()]));
+		assert!(to_dns_servers(&Some("one.one.one.one".into())).is_err());
+	}
 }
@@ -57,6 +57,7 @@ extern crate ethcore_transaction as transaction;
 extern crate ethereum_types;
 extern crate ethkey;
 extern crate kvdb;
+extern crate kvdb_lmdb;
 extern crate parity_hash_fetch as hash_fetch;
 extern crate parity_ipfs_api;
 extern crate parity_local_store as local_store;
@@ -104,6 +105,7 @@ mod run;
 mod secretstore;
 mod signer;
 mod snapshot;
+mod state_dump;
 mod upgrade;
 mod user_defaults;
 mod whisper;
@@ -80,6 +80,8 @@ pub enum Api {
 	/// Geth-compatible (best-effort) debug API (Potentially UNSAFE)
 	/// NOTE We don't aim to support all methods, only the ones that are useful.
 	Debug,
+	/// Development chain controls (UNSAFE: Rewrites the chain; only enabled explicitly)
+	Evm,
 }
 
 impl FromStr for Api {
@@ -91,6 +93,7 @@ impl FromStr for Api {
 		match s {
 			"debug" => Ok(Debug),
 			"eth" => Ok(Eth),
+			"evm" => Ok(Evm),
 			"net" => Ok(Net),
 			"parity" => Ok(Parity),
 			"parity_accounts" => Ok(ParityAccounts),
@@ -177,6 +180,7 @@ fn to_modules(apis: &HashSet<Api>) -> BTreeMap<String, String> {
 			Api::Debug => ("debug", "1.0"),
 			Api::Eth => ("eth", "1.0"),
 			Api::EthPubSub => ("pubsub", "1.0"),
+			Api::Evm => ("evm", "1.0"),
 			Api::Net => ("net", "1.0"),
 			Api::Parity => ("parity", "1.0"),
 			Api::ParityAccounts => ("parity_accounts", "1.0"),
@@ -411,6 +415,7 @@ impl FullDependencies {
 					).to_delegate(),
 				),
 				Api::Traces => handler.extend_with(TracesClient::new(&self.client).to_delegate()),
+				Api::Evm => handler.extend_with(EvmClient::new(&self.client, &self.miner).to_delegate()),
 				Api::Rpc => {
 					let modules = to_modules(&apis);
 					handler.extend_with(RpcClient::new(modules).to_delegate());
@@ -645,6 +650,9 @@ impl<C: LightChainClient + 'static> LightDependencies<C> {
 						.to_delegate(),
 				),
 				Api::Traces => handler.extend_with(light::TracesClient.to_delegate()),
+				Api::Evm => {
+					warn!(target: "rpc", "Evm API is not available in light client mode.")
+				}
 				Api::Rpc => {
 					let modules = to_modules(&apis);
 					handler.extend_with(RpcClient::new(modules).to_delegate());
@@ -779,6 +787,7 @@ mod test {
 		assert_eq!(Api::ParityAccounts, "parity_accounts".parse().unwrap());
 		assert_eq!(Api::ParitySet, "parity_set".parse().unwrap());
 		assert_eq!(Api::Traces, "traces".parse().unwrap());
+		assert_eq!(Api::Evm, "evm".parse().unwrap());
 		assert_eq!(Api::Rpc, "rpc".parse().unwrap());
 		assert_eq!(Api::SecretStore, "secretstore".parse().unwrap());
 		assert_eq!(Api::Private, "private".parse().unwrap());
@@ -63,12 +63,9 @@ use rpc;
 use rpc_apis;
 use secretstore;
 use signer;
-use db;
+use db::{self, DatabaseBackend};
 use ethkey::Password;
 
-// how often to take periodic snapshots.
-const SNAPSHOT_PERIOD: u64 = 5000;
-
 // how many blocks to wait before starting a periodic snapshot.
 const SNAPSHOT_HISTORY: u64 = 100;
 
@@ -93,6 +90,10 @@ pub struct RunCmd {
 	pub pruning: Pruning,
 	pub pruning_history: u64,
 	pub pruning_memory: usize,
+	/// Some if bodies and receipts of blocks older than this many blocks should be removed.
+	pub history_expiry: Option<u64>,
+	/// Whether a flat copy of recent states is kept.
+	pub flat_state: bool,
 	/// Some if execution should be daemonized. Contains pid_file path.
 	pub daemon: Option<String>,
 	pub logger_config: LogConfig,
@@ -106,6 +107,8 @@ pub struct RunCmd {
 	pub network_id: Option<u64>,
 	pub warp_sync: bool,
 	pub warp_barrier: Option<u64>,
+	pub sync_mode: sync::SyncMode,
+	pub checkpoint: Option<sync::Checkpoint>,
 	pub acc_conf: AccountsConfig,
 	pub gas_pricer_conf: GasPricerConfig,
 	pub miner_extras: MinerExtras,
@@ -114,6 +117,7 @@ pub struct RunCmd {
 	pub tracing: Switch,
 	pub fat_db: Switch,
 	pub compaction: DatabaseCompactionProfile,
+	pub db_backend: Option<DatabaseBackend>,
 	pub vm_type: VMType,
 	pub geth_compatibility: bool,
 	pub experimental_rpcs: bool,
@@ -134,6 +138,7 @@ pub struct RunCmd {
 	pub serve_light: bool,
 	pub light: bool,
 	pub no_persistent_txqueue: bool,
+	pub ethash_full_dag: bool,
 	pub whisper: ::whisper::Config,
 	pub no_hardcoded_sync: bool,
 	pub max_round_blocks_to_import: usize,
@@ -144,6 +149,18 @@ pub struct RunCmd {
 	pub on_demand_request_consecutive_failures: Option<usize>,
 }
 
+impl RunCmd {
+	/// Runtime parameters to load the chain spec with. Light clients never verify
+	/// proof-of-work with a full DAG, so they don't build one.
+	pub fn spec_params(&self, optimize_for: Option<OptimizeFor>) -> SpecParams {
+		SpecParams {
+			cache_dir: self.dirs.cache.as_ref(),
+			optimization_setting: optimize_for,
+			full_dag: self.ethash_full_dag && !self.light,
+		}
+	}
+}
+
 // node info fetcher for the local store.
 struct FullNodeInfo {
 	miner: Option<Arc<Miner>>, // TODO: only TXQ needed, just use that after decoupling.
@@ -175,7 +192,7 @@ fn execute_light_impl(cmd: RunCmd, logger: Arc<RotatingLogger>) -> Result<Runnin
 	use parking_lot::{Mutex, RwLock};
 
 	// load spec
-	let spec = cmd.spec.spec(SpecParams::new(cmd.dirs.cache.as_ref(), OptimizeFor::Memory))?;
+	let spec = cmd.spec.spec(cmd.spec_params(Some(OptimizeFor::Memory)))?;
 
 	// load genesis hash
 	let genesis_hash = spec.genesis_header().hash();
@@ -254,9 +271,12 @@ fn execute_light_impl(cmd: RunCmd, logger: Arc<RotatingLogger>) -> Result<Runnin
 	};
 
 	// initialize database.
-	let db = db::open_db(&db_dirs.client_path(algorithm).to_str().expect("DB path could not be converted to string."),
+	let client_path = db_dirs.client_path(algorithm);
+	let db_backend = DatabaseBackend::resolve(cmd.db_backend, &client_path)?;
+	let db = db::open_db(&client_path.to_str().expect("DB path could not be converted to string."),
 						 &cmd.cache_config,
-						 &cmd.compaction).map_err(|e| format!("Failed to open database {:?}", e))?;
+						 &cmd.compaction,
+						 db_backend).map_err(|e| format!("Failed to open database {:?}", e))?;
 
 	let service = light_client::Service::start(config, &spec, fetch, db, cache.clone())
 		.map_err(|e| format!("Error starting light client: {}", e))?;
@@ -387,7 +407,7 @@ fn execute_impl<Cr, Rr>(cmd: RunCmd, logger: Arc<RotatingLogger>, on_client_rq:
 		Rr: Fn() + 'static + Send
 {
 	// load spec
-	let spec = cmd.spec.spec(&cmd.dirs.cache)?;
+	let spec = cmd.spec.spec(cmd.spec_params(None))?;
 
 	// load genesis hash
 	let genesis_hash = spec.genesis_header().hash();
@@ -463,7 +483,19 @@ fn execute_impl<Cr, Rr>(cmd: RunCmd, logger: Arc<RotatingLogger>, on_client_rq:
 	}
 
 	sync_config.fork_block = spec.fork_block();
-	let mut warp_sync = spec.engine.supports_warp() && cmd.warp_sync;
+	sync_config.hard_forks = spec.hard_forks();
+	sync_config.sync_mode = match cmd.sync_mode {
+		sync::SyncMode::State if !spec.engine.supports_warp() => {
+			warn!("Warning: State sync is disabled because the chain doesn't support snapshots.");
+			sync::SyncMode::Full
+		},
+		sync::SyncMode::State if fat_db || tracing || algorithm != Algorithm::OverlayRecent => {
+			warn!("Warning: State sync is disabled because of Fat DB, tracing or non-default pruning mode.");
+			sync::SyncMode::Full
+		},
+		mode => mode,
+	};
+	let mut warp_sync = spec.engine.supports_warp() && cmd.warp_sync && sync_config.sync_mode == sync::SyncMode::Full;
 	if warp_sync {
 		// Logging is not initialized yet, so we print directly to stderr
 		if fat_db {
@@ -477,11 +509,22 @@ fn execute_impl<Cr, Rr>(cmd: RunCmd, logger: Arc<RotatingLogger>, on_client_rq:
 			warp_sync = false;
 		}
 	}
-	sync_config.warp_sync = match (warp_sync, cmd.warp_barrier) {
-		(true, Some(block)) => sync::WarpSync::OnlyAndAfter(block),
-		(true, _) => sync::WarpSync::Enabled,
+	if cmd.checkpoint.is_some() {
+		match spec.engine.snapshot_components() {
+			Some(ref components) if components.has_epoch_transitions() =>
+				return Err("Syncing from a checkpoint is not supported on chains with validator set transitions, as the validators up to the checkpoint can't be proven from its headers.".into()),
+			Some(_) => (),
+			None => return Err("Syncing from a checkpoint is not supported on chains without snapshots.".into()),
+		}
+	}
+	sync_config.warp_sync = match (warp_sync, cmd.warp_barrier, cmd.checkpoint) {
+		(true, Some(block), _) => sync::WarpSync::OnlyAndAfter(block),
+		// Wait for a snapshot at or after the checkpoint rather than syncing from genesis
+		(true, None, Some(checkpoint)) => sync::WarpSync::OnlyAndAfter(checkpoint.number),
+		(true, None, None) => sync::WarpSync::Enabled,
 		_ => sync::WarpSync::Disabled,
 	};
+	sync_config.checkpoint = cmd.checkpoint;
 	sync_config.download_old_blocks = cmd.download_old_blocks;
 	sync_config.serve_light = cmd.serve_light;
 
@@ -565,6 +608,8 @@ fn execute_impl<Cr, Rr>(cmd: RunCmd, logger: Arc<RotatingLogger>, on_client_rq:
 	client_config.queue.verifier_settings = cmd.verifier_settings;
 	client_config.transaction_verification_queue_size = ::std::cmp::max(2048, txpool_size / 4);
 	client_config.snapshot = cmd.snapshot_conf.clone();
+	client_config.history_expiry = cmd.history_expiry;
+	client_config.flat_state = cmd.flat_state;
 
 	// set up bootnodes
 	let mut net_conf = cmd.net_conf;
@@ -575,7 +620,8 @@ fn execute_impl<Cr, Rr>(cmd: RunCmd, logger: Arc<RotatingLogger>, on_client_rq:
 	// set network path.
 	net_conf.net_config_path = Some(db_dirs.network_path().to_string_lossy().into_owned());
 
-	let restoration_db_handler = db::restoration_db_handler(&client_path, &client_config);
+	let db_backend = DatabaseBackend::resolve(cmd.db_backend, &client_path)?;
+	let restoration_db_handler = db::restoration_db_handler(&client_path, &client_config, db_backend);
 	let client_db = restoration_db_handler.open(&client_path)
 		.map_err(|e| format!("Failed to open database {:?}", e))?;
 
@@ -828,7 +874,7 @@ fn execute_impl<Cr, Rr>(cmd: RunCmd, logger: Arc<RotatingLogger>, on_client_rq:
 				service.client(),
 				move || is_major_importing(Some(sync.status().state), client.queue_info()),
 				service.io().channel(),
-				SNAPSHOT_PERIOD,
+				cmd.snapshot_conf.period,
 				SNAPSHOT_HISTORY,
 			));
 
@@ -16,14 +16,17 @@
 
 //! Snapshot and restoration commands.
 
+use std::fs;
 use std::time::Duration;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use hash::keccak;
+use bytes::Bytes;
+use ethereum_types::H256;
 use ethcore::account_provider::AccountProvider;
-use ethcore::snapshot::{Progress, RestorationStatus, SnapshotConfiguration, SnapshotService as SS};
-use ethcore::snapshot::io::{SnapshotReader, PackedReader, PackedWriter};
+use ethcore::snapshot::{self, Progress, RestorationStatus, SnapshotConfiguration, SnapshotService as SS};
+use ethcore::snapshot::io::{SnapshotReader, PackedReader, PackedWriter, LooseReader};
 use ethcore::snapshot::service::Service as SnapshotService;
 use ethcore::client::{Mode, DatabaseCompactionProfile, VMType};
 use ethcore::miner::Miner;
@@ -36,7 +39,7 @@ use helpers::{to_client_config, execute_upgrades};
 use dir::Directories;
 use user_defaults::UserDefaults;
 use ethcore_private_tx;
-use db;
+use db::{self, DatabaseBackend};
 
 /// Kinds of snapshot commands.
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -44,7 +47,11 @@ pub enum Kind {
 	/// Take a snapshot.
 	Take,
 	/// Restore a snapshot.
-	Restore
+	Restore,
+	/// Verify a snapshot file.
+	Verify,
+	/// Print the contents of a snapshot file.
+	Inspect,
 }
 
 /// Command for snapshot creation or restoration.
@@ -59,25 +66,70 @@ pub struct SnapshotCommand {
 	pub tracing: Switch,
 	pub fat_db: Switch,
 	pub compaction: DatabaseCompactionProfile,
+	pub db_backend: Option<DatabaseBackend>,
 	pub file_path: Option<String>,
+	pub deltas: Vec<String>,
 	pub kind: Kind,
 	pub block_at: BlockId,
 	pub max_round_blocks_to_import: usize,
 	pub snapshot_conf: SnapshotConfiguration,
 }
 
+// helper for opening either a packed snapshot file or a loose snapshot directory,
+// like the ones kept in the snapshot archive.
+fn open_reader(path: &str) -> Result<Box<SnapshotReader>, String> {
+	if Path::new(path).is_dir() {
+		let reader = LooseReader::new(path.into())
+			.map_err(|e| format!("Couldn't open snapshot directory: {}", e))?;
+		Ok(Box::new(reader))
+	} else {
+		let reader = PackedReader::new(Path::new(path))
+			.map_err(|e| format!("Couldn't open snapshot file: {}", e))?
+			.ok_or("Snapshot file has invalid format.".to_owned())?;
+		Ok(Box::new(reader))
+	}
+}
+
+// helper for reading a chunk from a reader and checking its hash.
+fn read_chunk<R: SnapshotReader + ?Sized>(reader: &R, hash: H256) -> Result<Bytes, String> {
+	let chunk = reader.chunk(hash)
+		.map_err(|e| format!("Encountered error while reading chunk {:?}: {}", hash, e))?;
+
+	let actual = keccak(&chunk);
+	if actual != hash {
+		return Err(format!("Mismatched chunk hash. Expected {:?}, got {:?}", hash, actual));
+	}
+
+	Ok(chunk)
+}
+
 // helper for reading chunks from arbitrary reader and feeding them into the
-// service.
-fn restore_using<R: SnapshotReader>(snapshot: Arc<SnapshotService>, reader: &R, recover: bool) -> Result<(), String> {
+// service. state chunks of the delta snapshots are fed after those of the base
+// snapshot, and the blocks are taken from the last delta.
+fn restore_using<R: SnapshotReader + ?Sized>(snapshot: Arc<SnapshotService>, reader: &R, deltas: &[LooseReader], recover: bool) -> Result<(), String> {
 	let manifest = reader.manifest();
 
-	info!("Restoring to block #{} (0x{:?})", manifest.block_number, manifest.block_hash);
+	let delta_manifests: Vec<_> = deltas.iter()
+		.map(|delta| delta.delta_manifest().cloned().ok_or("Not a delta snapshot.".to_owned()))
+		.collect::<Result<_, _>>()?;
 
-	snapshot.init_restore(manifest.clone(), recover).map_err(|e| {
+	match delta_manifests.last() {
+		Some(last) => {
+			info!("Restoring to block #{} (0x{:?}) through {} delta snapshots",
+				last.manifest.block_number, last.manifest.block_hash, deltas.len());
+			snapshot.init_restore_with_deltas(manifest.clone(), delta_manifests.clone())
+		}
+		None => {
+			info!("Restoring to block #{} (0x{:?})", manifest.block_number, manifest.block_hash);
+			snapshot.init_restore(manifest.clone(), recover)
+		}
+	}.map_err(|e| {
 		format!("Failed to begin restoration: {}", e)
 	})?;
 
-	let (num_state, num_blocks) = (manifest.state_hashes.len(), manifest.block_hashes.len());
+	let num_state = manifest.state_hashes.len() + deltas.iter().map(|d| d.manifest().state_hashes.len()).sum::<usize>();
+	let blocks_manifest = deltas.last().map_or(reader.manifest(), |d| d.manifest());
+	let num_blocks = blocks_manifest.block_hashes.len();
 
 	let informant_handle = snapshot.clone();
 	::std::thread::spawn(move || {
@@ -94,30 +146,32 @@ fn restore_using<R: SnapshotReader>(snapshot: Arc<SnapshotService>, reader: &R,
  			return Err("Restoration failed".into());
  		}
 
- 		let chunk = reader.chunk(state_hash)
-			.map_err(|e| format!("Encountered error while reading chunk {:?}: {}", state_hash, e))?;
-
-		let hash = keccak(&chunk);
-		if hash != state_hash {
-			return Err(format!("Mismatched chunk hash. Expected {:?}, got {:?}", state_hash, hash));
-		}
-
+		let chunk = read_chunk(reader, state_hash)?;
  		snapshot.feed_state_chunk(state_hash, &chunk);
  	}
 
+	for delta in deltas {
+		info!("Applying delta snapshot at #{}", delta.manifest().block_number);
+		for &state_hash in &delta.manifest().state_hashes {
+			if snapshot.status() == RestorationStatus::Failed {
+				return Err("Restoration failed".into());
+			}
+
+			let chunk = read_chunk(delta, state_hash)?;
+			snapshot.feed_state_chunk(state_hash, &chunk);
+		}
+	}
+
 	info!("Restoring blocks");
-	for &block_hash in &manifest.block_hashes {
+	for &block_hash in &blocks_manifest.block_hashes {
 		if snapshot.status() == RestorationStatus::Failed {
 			return Err("Restoration failed".into());
 		}
 
- 		let chunk = reader.chunk(block_hash)
-			.map_err(|e| format!("Encountered error while reading chunk {:?}: {}", block_hash, e))?;
-
-		let hash = keccak(&chunk);
-		if hash != block_hash {
-			return Err(format!("Mismatched chunk hash. Expected {:?}, got {:?}", block_hash, hash));
-		}
+		let chunk = match deltas.last() {
+			Some(delta) => read_chunk(delta, block_hash)?,
+			None => read_chunk(reader, block_hash)?,
+		};
 		snapshot.feed_block_chunk(block_hash, &chunk);
 	}
 
@@ -185,7 +239,8 @@ impl SnapshotCommand {
 
 		client_config.snapshot = self.snapshot_conf;
 
-		let restoration_db_handler = db::restoration_db_handler(&client_path, &client_config);
+		let db_backend = DatabaseBackend::resolve(self.db_backend, &client_path)?;
+		let restoration_db_handler = db::restoration_db_handler(&client_path, &client_config, db_backend);
 		let client_db = restoration_db_handler.open(&client_path)
 			.map_err(|e| format!("Failed to open database {:?}", e))?;
 
@@ -209,6 +264,9 @@ impl SnapshotCommand {
 	/// restore from a snapshot
 	pub fn restore(self) -> Result<(), String> {
 		let file = self.file_path.clone();
+		let deltas = self.deltas.iter()
+			.map(|dir| LooseReader::delta(dir.into()).map_err(|e| format!("Couldn't open delta snapshot at '{}': {}", dir, e)))
+			.collect::<Result<Vec<_>, _>>()?;
 		let service = self.start_service()?;
 
 		warn!("Snapshot restoration is experimental and the format may be subject to change.");
@@ -219,19 +277,15 @@ impl SnapshotCommand {
 		if let Some(file) = file {
 			info!("Attempting to restore from snapshot at '{}'", file);
 
-			let reader = PackedReader::new(Path::new(&file))
-				.map_err(|e| format!("Couldn't open snapshot file: {}", e))
-				.and_then(|x| x.ok_or("Snapshot file has invalid format.".into()));
-
-			let reader = reader?;
-			restore_using(snapshot, &reader, true)?;
+			let reader = open_reader(&file)?;
+			restore_using(snapshot, &*reader, &deltas, true)?;
 		} else {
 			info!("Attempting to restore from local snapshot.");
 
 			// attempting restoration with recovery will lead to deadlock
 			// as we currently hold a read lock on the service's reader.
 			match *snapshot.reader() {
-				Some(ref reader) => restore_using(snapshot.clone(), reader, false)?,
+				Some(ref reader) => restore_using(snapshot.clone(), reader, &deltas, false)?,
 				None => return Err("No local snapshot found.".into()),
 			}
 		}
@@ -239,6 +293,63 @@ impl SnapshotCommand {
 		Ok(())
 	}
 
+	/// Verify a snapshot file by rebuilding it into a scratch database.
+	pub fn verify(self) -> Result<String, String> {
+		let file = self.file_path.clone().ok_or("No file path provided.".to_owned())?;
+		let reader = open_reader(&file)?;
+
+		let spec = self.spec.spec(&self.dirs.cache)?;
+		let genesis_hash = spec.genesis_header().hash();
+		let db_dirs = self.dirs.database(genesis_hash, None, spec.data_dir.clone());
+
+		let scratch_path = db_dirs.snapshot_path().join("verification");
+		let _ = fs::remove_dir_all(&scratch_path);
+		let db_backend = DatabaseBackend::resolve(self.db_backend, &scratch_path)?;
+		let db = db::open_db(&scratch_path.to_string_lossy(), &self.cache_config, &self.compaction, db_backend)
+			.map_err(|e| format!("Failed to open scratch database: {}", e))?;
+
+		let manifest = reader.manifest().clone();
+		info!("Verifying snapshot at block #{} (0x{:?})", manifest.block_number, manifest.block_hash);
+
+		let result = snapshot::verify_snapshot(&*reader, &*spec.engine, &spec.genesis_block(), db);
+		let _ = fs::remove_dir_all(&scratch_path);
+		result.map_err(|e| format!("Snapshot verification failed: {}", e))?;
+
+		Ok(format!("Snapshot at block #{} is valid: {} state chunks and {} block chunks verified.",
+			manifest.block_number, manifest.state_hashes.len(), manifest.block_hashes.len()))
+	}
+
+	/// Print the manifest and contents summary of a snapshot file.
+	pub fn inspect(self) -> Result<String, String> {
+		let file = self.file_path.clone().ok_or("No file path provided.".to_owned())?;
+		let reader = open_reader(&file)?;
+
+		let info = snapshot::inspect_snapshot(&*reader)
+			.map_err(|e| format!("Failed to inspect snapshot: {}", e))?;
+		let manifest = &info.manifest;
+
+		let blocks = match info.first_block {
+			Some(first) => format!("#{} to #{}", first, manifest.block_number),
+			None => format!("up to #{}", manifest.block_number),
+		};
+
+		Ok(format!("Version: {}\n\
+			Block: #{} (0x{:?})\n\
+			State root: 0x{:?}\n\
+			State chunks: {} ({} bytes compressed, {} bytes decompressed, largest {} bytes)\n\
+			Accounts: {}\n\
+			Block chunks: {} ({} bytes compressed, {} bytes decompressed, largest {} bytes)\n\
+			Blocks: {}",
+			manifest.version,
+			manifest.block_number, manifest.block_hash,
+			manifest.state_root,
+			info.state_chunks.count, info.state_chunks.compressed_size, info.state_chunks.size, info.state_chunks.largest,
+			info.accounts,
+			info.block_chunks.count, info.block_chunks.compressed_size, info.block_chunks.size, info.block_chunks.largest,
+			blocks
+		))
+	}
+
 	/// Take a snapshot from the head of the chain.
 	pub fn take_snapshot(self) -> Result<(), String> {
 		let file_path = self.file_path.clone().ok_or("No file path provided.".to_owned())?;
@@ -286,9 +397,9 @@ impl SnapshotCommand {
 /// Execute this snapshot command.
 pub fn execute(cmd: SnapshotCommand) -> Result<String, String> {
 	match cmd.kind {
-		Kind::Take => cmd.take_snapshot()?,
-		Kind::Restore => cmd.restore()?,
+		Kind::Take => cmd.take_snapshot().map(|_| String::new()),
+		Kind::Restore => cmd.restore().map(|_| String::new()),
+		Kind::Verify => cmd.verify(),
+		Kind::Inspect => cmd.inspect(),
 	}
-
-	Ok(String::new())
 }
@@ -0,0 +1,378 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Streaming state dump format.
+//!
+//! A state dump is a header record followed by one record per account, sorted by
+//! address hash, in one of two layouts:
+//!
+//! - JSON Lines (`jsonl`): one JSON object per line. The header is
+//!   `{"version":1,"number":N,"hash":"0x..","stateRoot":"0x..","complete":true}`
+//!   and each account is
+//!   `{"address":"0x..","balance":"0x..","nonce":"0x..","code":"0x..","storage":{"0x..":"0x.."}}`.
+//! - RLP stream (`rlp`): concatenated RLP lists. The header is
+//!   `[version, number, hash, state_root, complete]` and each account is
+//!   `[address, balance, nonce, code, [[key, value], ...]]`.
+//!
+//! `complete` is `false` when accounts, code or storage were filtered out of the
+//! dump, in which case the dumped state won't match `stateRoot`.
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Read, Write};
+use std::str::FromStr;
+
+use bytes::Bytes;
+use ethereum_types::{U256, H256, Address};
+use rlp::{PayloadInfo, Rlp, RlpStream};
+use rustc_hex::{FromHex, ToHex};
+use serde::{Deserialize, Deserializer, Serializer};
+use serde_json;
+
+/// Version of the state dump format.
+pub const VERSION: u64 = 1;
+
+/// Layout of a state dump.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StateDumpFormat {
+	/// One JSON object per line.
+	JsonLines,
+	/// Concatenated RLP lists.
+	Rlp,
+}
+
+impl FromStr for StateDumpFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"jsonl" | "json-lines" => Ok(StateDumpFormat::JsonLines),
+			"rlp" => Ok(StateDumpFormat::Rlp),
+			x => Err(format!("Invalid state dump format: {}", x))
+		}
+	}
+}
+
+/// The first record of a state dump.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Header {
+	/// Format version.
+	pub version: u64,
+	/// Number of the block the state was dumped at.
+	pub number: u64,
+	/// Hash of the block the state was dumped at.
+	pub hash: H256,
+	/// State root of the block the state was dumped at.
+	#[serde(rename="stateRoot")]
+	pub state_root: H256,
+	/// Whether the dump contains the whole state.
+	pub complete: bool,
+}
+
+/// A single account of a state dump.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccountRecord {
+	/// Address of the account.
+	pub address: Address,
+	/// Balance of the account.
+	pub balance: U256,
+	/// Nonce of the account.
+	pub nonce: U256,
+	/// Code of the account.
+	#[serde(default, serialize_with="bytes_to_hex", deserialize_with="bytes_from_hex")]
+	pub code: Bytes,
+	/// Storage of the account.
+	#[serde(default)]
+	pub storage: BTreeMap<H256, H256>,
+}
+
+fn bytes_to_hex<S: Serializer>(bytes: &Bytes, serializer: S) -> Result<S::Ok, S::Error> {
+	serializer.collect_str(&format_args!("0x{}", bytes.to_hex()))
+}
+
+fn bytes_from_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+	use serde::de::Error;
+
+	let s = String::deserialize(deserializer)?;
+	let s = if s.starts_with("0x") { &s[2..] } else { &s[..] };
+	s.from_hex().map_err(|e| D::Error::custom(format!("Invalid code: {}", e)))
+}
+
+/// Writes a state dump record by record.
+pub struct Writer<W> {
+	out: W,
+	format: StateDumpFormat,
+}
+
+impl<W: Write> Writer<W> {
+	/// Create a new writer, writing the header right away.
+	pub fn new(out: W, format: StateDumpFormat, header: &Header) -> io::Result<Self> {
+		let mut writer = Writer {
+			out: out,
+			format: format,
+		};
+
+		match format {
+			StateDumpFormat::JsonLines => writer.write_json(header)?,
+			StateDumpFormat::Rlp => {
+				let mut stream = RlpStream::new_list(5);
+				stream
+					.append(&header.version)
+					.append(&header.number)
+					.append(&header.hash)
+					.append(&header.state_root)
+					.append(&header.complete);
+				writer.out.write_all(&stream.out())?;
+			}
+		}
+
+		Ok(writer)
+	}
+
+	/// Write a single account.
+	pub fn write_account(&mut self, account: &AccountRecord) -> io::Result<()> {
+		match self.format {
+			StateDumpFormat::JsonLines => self.write_json(account),
+			StateDumpFormat::Rlp => {
+				let mut stream = RlpStream::new_list(5);
+				stream
+					.append(&account.address)
+					.append(&account.balance)
+					.append(&account.nonce)
+					.append(&account.code)
+					.begin_list(account.storage.len());
+
+				for (key, value) in &account.storage {
+					stream.begin_list(2).append(key).append(value);
+				}
+
+				self.out.write_all(&stream.out())
+			}
+		}
+	}
+
+	/// Flush the underlying output.
+	pub fn flush(&mut self) -> io::Result<()> {
+		self.out.flush()
+	}
+
+	fn write_json<T: ::serde::Serialize>(&mut self, record: &T) -> io::Result<()> {
+		serde_json::to_writer(&mut self.out, record)?;
+		self.out.write_all(b"\n")
+	}
+}
+
+/// Reads a state dump record by record.
+pub struct Reader<R> {
+	input: R,
+	format: StateDumpFormat,
+	header: Header,
+}
+
+impl<R: BufRead> Reader<R> {
+	/// Create a new reader, reading the header right away. When no format is given,
+	/// it's detected from the first byte of the dump.
+	pub fn new(mut input: R, format: Option<StateDumpFormat>) -> Result<Self, String> {
+		let format = match format {
+			Some(format) => format,
+			None => match input.fill_buf().map_err(|e| format!("Error reading state dump: {}", e))?.first() {
+				Some(&b'{') => StateDumpFormat::JsonLines,
+				Some(_) => StateDumpFormat::Rlp,
+				None => return Err("State dump is empty.".into()),
+			},
+		};
+
+		let header = match format {
+			StateDumpFormat::JsonLines => {
+				let line = read_line(&mut input)?.ok_or("State dump is empty.")?;
+				serde_json::from_str(&line).map_err(|e| format!("Invalid state dump header: {}", e))?
+			}
+			StateDumpFormat::Rlp => {
+				let item = read_rlp_item(&mut input)?.ok_or("State dump is empty.")?;
+				let rlp = Rlp::new(&item);
+				let decode = || -> Result<Header, ::rlp::DecoderError> {
+					Ok(Header {
+						version: rlp.val_at(0)?,
+						number: rlp.val_at(1)?,
+						hash: rlp.val_at(2)?,
+						state_root: rlp.val_at(3)?,
+						complete: rlp.val_at(4)?,
+					})
+				};
+				decode().map_err(|e| format!("Invalid state dump header: {}", e))?
+			}
+		};
+
+		if header.version != VERSION {
+			return Err(format!("Unsupported state dump version: {}", header.version));
+		}
+
+		Ok(Reader {
+			input: input,
+			format: format,
+			header: header,
+		})
+	}
+
+	/// The header of the dump.
+	pub fn header(&self) -> &Header {
+		&self.header
+	}
+
+	fn next_account(&mut self) -> Result<Option<AccountRecord>, String> {
+		match self.format {
+			StateDumpFormat::JsonLines => match read_line(&mut self.input)? {
+				Some(line) => serde_json::from_str(&line)
+					.map(Some)
+					.map_err(|e| format!("Invalid account record: {}", e)),
+				None => Ok(None),
+			},
+			StateDumpFormat::Rlp => match read_rlp_item(&mut self.input)? {
+				Some(item) => decode_account(&Rlp::new(&item))
+					.map(Some)
+					.map_err(|e| format!("Invalid account record: {}", e)),
+				None => Ok(None),
+			},
+		}
+	}
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+	type Item = Result<AccountRecord, String>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		match self.next_account() {
+			Ok(account) => account.map(Ok),
+			Err(e) => Some(Err(e)),
+		}
+	}
+}
+
+fn decode_account(rlp: &Rlp) -> Result<AccountRecord, ::rlp::DecoderError> {
+	let mut storage = BTreeMap::new();
+	for entry in rlp.at(4)?.iter() {
+		storage.insert(entry.val_at(0)?, entry.val_at(1)?);
+	}
+
+	Ok(AccountRecord {
+		address: rlp.val_at(0)?,
+		balance: rlp.val_at(1)?,
+		nonce: rlp.val_at(2)?,
+		code: rlp.val_at(3)?,
+		storage: storage,
+	})
+}
+
+// read the next non-empty line, if any.
+fn read_line<R: BufRead>(input: &mut R) -> Result<Option<String>, String> {
+	let mut line = String::new();
+	loop {
+		line.clear();
+		if input.read_line(&mut line).map_err(|e| format!("Error reading state dump: {}", e))? == 0 {
+			return Ok(None);
+		}
+
+		if !line.trim().is_empty() {
+			return Ok(Some(line));
+		}
+	}
+}
+
+// read the next RLP item, if any.
+fn read_rlp_item<R: Read>(input: &mut R) -> Result<Option<Vec<u8>>, String> {
+	let read_error = |e: io::Error| format!("Error reading state dump: {}", e);
+
+	let mut item = vec![0u8; 1];
+	if input.read(&mut item).map_err(read_error)? == 0 {
+		return Ok(None);
+	}
+
+	// long strings and lists carry the length of their length in the first byte.
+	let header_len = match item[0] {
+		b @ 0xb8...0xbf => 1 + (b - 0xb7) as usize,
+		b @ 0xf8...0xff => 1 + (b - 0xf7) as usize,
+		_ => 1,
+	};
+	item.resize(header_len, 0);
+	input.read_exact(&mut item[1..]).map_err(read_error)?;
+
+	let total = PayloadInfo::from(&item).map_err(|e| format!("Invalid RLP in state dump: {}", e))?.total();
+	item.resize(total, 0);
+	input.read_exact(&mut item[header_len..]).map_err(read_error)?;
+
+	Ok(Some(item))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn roundtrip(format: StateDumpFormat) {
+		let header = Header {
+			version: VERSION,
+			number: 1000,
+			hash: H256::from(1),
+			state_root: H256::from(2),
+			complete: true,
+		};
+
+		let mut storage = BTreeMap::new();
+		storage.insert(H256::from(3), H256::from(4));
+		let accounts = vec![
+			AccountRecord {
+				address: Address::from(5),
+				balance: 6.into(),
+				nonce: 0.into(),
+				code: Vec::new(),
+				storage: BTreeMap::new(),
+			},
+			AccountRecord {
+				address: Address::from(7),
+				balance: U256::max_value(),
+				nonce: 8.into(),
+				code: vec![0x60; 100],
+				storage: storage,
+			},
+		];
+
+		let mut out = Vec::new();
+		{
+			let mut writer = Writer::new(&mut out, format, &header).unwrap();
+			for account in &accounts {
+				writer.write_account(account).unwrap();
+			}
+		}
+
+		let reader = Reader::new(&out[..], None).unwrap();
+		assert_eq!(reader.header(), &header);
+		assert_eq!(reader.collect::<Result<Vec<_>, _>>().unwrap(), accounts);
+	}
+
+	#[test]
+	fn jsonl_roundtrip() {
+		roundtrip(StateDumpFormat::JsonLines);
+	}
+
+	#[test]
+	fn rlp_roundtrip() {
+		roundtrip(StateDumpFormat::Rlp);
+	}
+
+	#[test]
+	fn rejects_unknown_version() {
+		let dump = b"{\"version\":2,\"number\":1,\"hash\":\"0x0000000000000000000000000000000000000000000000000000000000000000\",\"stateRoot\":\"0x0000000000000000000000000000000000000000000000000000000000000000\",\"complete\":true}\n";
+		assert!(Reader::new(&dump[..], None).is_err());
+	}
+}
@@ -219,6 +219,15 @@ pub fn unavailable_block() -> Error {
 	}
 }
 
+pub fn history_pruned(first_history_block: u64) -> Error {
+	Error {
+		code: ErrorCode::ServerError(codes::UNSUPPORTED_REQUEST),
+		message: format!("Block bodies and receipts before #{} are no longer available: they were pruned by --history-expiry \
+			or by `parity db prune-history`.", first_history_block),
+		data: None,
+	}
+}
+
 pub fn check_block_number_existence<'a, T, C>(
 	client: &'a C,
 	num: BlockNumber,
@@ -230,9 +239,19 @@ pub fn check_block_number_existence<'a, T, C>(
 	move |response| {
 		if response.is_none() {
 			if let BlockNumber::Num(block_number) = num {
+				let BlockChainInfo { best_block_number, first_history_block, .. } = client.chain_info();
+				if allow_missing_blocks {
+					return Ok(response);
+				}
+				// the history of blocks before the first history block has expired
+				if let Some(first_history_block) = first_history_block {
+					if block_number < first_history_block {
+						return Err(history_pruned(first_history_block));
+					}
+				}
 				// tried to fetch block number and got nothing even though the block number is
 				// less than the latest block number
-				if block_number < client.chain_info().best_block_number && !allow_missing_blocks {
+				if block_number < best_block_number {
 					return Err(unavailable_block());
 				}
 			}
@@ -154,6 +154,12 @@ pub fn base_logs<C, M, T: StateInfo + 'static> (client: &C, miner: &M, filter: F
 		Ok(value) => value,
 		Err(err) => return Box::new(future::err(err)),
 	};
+	if let Some(first_history_block) = client.chain_info().first_history_block {
+		// receipts of blocks before the first history block have expired
+		if client.block_number(filter.from_block.clone()).map_or(false, |from| from < first_history_block) {
+			return Box::new(future::err(errors::history_pruned(first_history_block)));
+		}
+	}
 	let mut logs = match client.logs(filter.clone()) {
 		Ok(logs) => logs
 			.into_iter()
@@ -871,7 +877,7 @@ impl<C, SN: ?Sized, S: ?Sized, M, EM, T: StateInfo + 'static> Eth for EthClient<
 		let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
 		if no_new_work_timeout > 0 && timestamp + no_new_work_timeout < now {
 			Err(errors::no_new_work())
-		} else if self.options.send_block_number_in_get_work {
+		} else if self.options.send_block_number_in_get_work || self.client.engine().work_package_requires_number(number) {
 			Ok(Work {
 				pow_hash: pow_hash.into(),
 				seed_hash: seed_hash.into(),
@@ -0,0 +1,190 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Development chain control rpc implementation.
+
+use std::cmp;
+use std::sync::Arc;
+
+use ethcore::client::{BlockChainClient, ChainInfo, EngineInfo};
+use ethcore::engines::DevControls;
+use ethcore::miner::{self, MinerService};
+
+use jsonrpc_core::Result;
+use jsonrpc_macros::Trailing;
+use v1::helpers::errors;
+use v1::traits::Evm;
+use v1::types::U64;
+
+/// Development chain control rpc implementation.
+pub struct EvmClient<C, M> {
+	client: Arc<C>,
+	miner: Arc<M>,
+}
+
+impl<C, M> EvmClient<C, M> {
+	/// Creates new `EvmClient`.
+	pub fn new(client: &Arc<C>, miner: &Arc<M>) -> Self {
+		EvmClient {
+			client: client.clone(),
+			miner: miner.clone(),
+		}
+	}
+}
+
+impl<C, M> EvmClient<C, M> where
+	C: miner::BlockChainClient + BlockChainClient + EngineInfo,
+{
+	fn dev(&self) -> Result<&DevControls> {
+		self.client.engine().dev_controls()
+			.ok_or_else(|| errors::unsupported("The chain's engine doesn't support development controls.", None))
+	}
+}
+
+impl<C, M> Evm for EvmClient<C, M> where
+	C: miner::BlockChainClient + BlockChainClient + EngineInfo + 'static,
+	M: MinerService + 'static,
+{
+	fn mine(&self, blocks: Trailing<U64>) -> Result<U64> {
+		let dev = self.dev()?;
+		let blocks = cmp::max(blocks.unwrap_or_default().into(), 1u64);
+
+		dev.set_force_seal(true);
+		let mut best = self.client.chain_info().best_block_number;
+		for _ in 0..blocks {
+			self.miner.update_sealing(&*self.client);
+
+			let new_best = self.client.chain_info().best_block_number;
+			if new_best == best {
+				dev.set_force_seal(false);
+				return Err(errors::internal("Unable to seal a block", ""));
+			}
+			best = new_best;
+		}
+		dev.set_force_seal(false);
+
+		Ok(best.into())
+	}
+
+	fn increase_time(&self, seconds: U64) -> Result<U64> {
+		Ok(self.dev()?.increase_time(seconds.into()).into())
+	}
+
+	fn set_next_block_timestamp(&self, timestamp: U64) -> Result<bool> {
+		let timestamp: u64 = timestamp.into();
+		let chain_info = self.client.chain_info();
+		// the pending block is built on the best block with a timestamp from the clock already.
+		let pending_timestamp = self.miner.pending_block_header(chain_info.best_block_number).map_or(0, |h| h.timestamp());
+		if timestamp < cmp::max(chain_info.best_block_timestamp, pending_timestamp) {
+			return Err(errors::invalid_params("timestamp", "Must not be before the best or pending block"));
+		}
+
+		self.dev()?.set_next_timestamp(timestamp);
+		Ok(true)
+	}
+
+	fn snapshot(&self) -> Result<U64> {
+		let best = self.client.chain_info().best_block_hash;
+		Ok((self.dev()?.snapshot(best) as u64).into())
+	}
+
+	fn revert(&self, id: U64) -> Result<bool> {
+		let id: u64 = id.into();
+		let best = match self.dev()?.revert(id as usize) {
+			Some(best) => best,
+			None => return Ok(false),
+		};
+
+		self.client.revert_to(best).map_err(|e| errors::internal("Unable to revert the chain", e))?;
+		Ok(true)
+	}
+
+	fn set_automine(&self, automine: bool) -> Result<bool> {
+		self.dev()?.set_automine(automine);
+		if automine {
+			self.miner.update_sealing(&*self.client);
+		}
+		Ok(true)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use ethcore::client::{Balance, ChainInfo, Client, ClientConfig};
+	use ethcore::miner::{Miner, MinerService};
+	use ethcore::spec::Spec;
+	use ethcore::test_helpers;
+	use ethereum_types::{Address, U256};
+	use ethkey::Secret;
+	use hash::keccak;
+	use io::IoChannel;
+	use transaction::{Action, Transaction};
+
+	use jsonrpc_core::IoHandler;
+	use v1::{Metadata, Evm, EvmClient};
+
+	fn request(io: &IoHandler<Metadata>, method: &str, params: &str) -> String {
+		let request = format!(r#"{{"jsonrpc":"2.0","method":"{}","params":{},"id":1}}"#, method, params);
+		io.handle_request_sync(&request).unwrap()
+	}
+
+	#[test]
+	fn revert_restores_state_and_block_number() {
+		let spec = Spec::new_instant();
+		let miner = Arc::new(Miner::new_for_tests(&spec, None));
+		let client = Client::new(
+			ClientConfig::default(),
+			&spec,
+			test_helpers::new_db(),
+			miner.clone(),
+			IoChannel::disconnected(),
+		).unwrap();
+		let mut io = IoHandler::default();
+		io.extend_with(EvmClient::new(&client, &miner).to_delegate());
+
+		assert_eq!(request(&io, "evm_snapshot", "[]"), r#"{"jsonrpc":"2.0","result":"0x1","id":1}"#);
+		assert_eq!(request(&io, "evm_setAutomine", "[false]"), r#"{"jsonrpc":"2.0","result":true,"id":1}"#);
+
+		// the development account of the instant seal chain.
+		let secret = Secret::from(keccak(""));
+		let recipient = Address::from(0x1234);
+		let transaction = Transaction {
+			nonce: 0.into(),
+			gas_price: 0.into(),
+			gas: 21_000.into(),
+			action: Action::Call(recipient),
+			value: 100.into(),
+			data: vec![],
+		}.sign(&secret, None);
+		let hash = transaction.hash();
+		miner.import_own_transaction(&*client, transaction.into()).unwrap();
+		assert_eq!(client.chain_info().best_block_number, 0);
+
+		assert_eq!(request(&io, "evm_mine", r#"["0x3"]"#), r#"{"jsonrpc":"2.0","result":"0x3","id":1}"#);
+		assert_eq!(client.latest_balance(&recipient), U256::from(100));
+
+		assert_eq!(request(&io, "evm_revert", r#"["0x1"]"#), r#"{"jsonrpc":"2.0","result":true,"id":1}"#);
+		assert_eq!(client.chain_info().best_block_number, 0);
+		assert_eq!(client.latest_balance(&recipient), U256::zero());
+
+		// the chain continues from the reverted block, without the reverted state.
+		assert!(miner.remove_transaction(&hash).is_some());
+		assert_eq!(request(&io, "evm_mine", "[]"), r#"{"jsonrpc":"2.0","result":"0x1","id":1}"#);
+		assert_eq!(client.latest_balance(&recipient), U256::zero());
+	}
+}
@@ -23,7 +23,7 @@ use version::version_data;
 use crypto::DEFAULT_MAC;
 use ethkey::{crypto::ecies, Brain, Generator};
 use ethstore::random_phrase;
-use sync::LightSyncProvider;
+use sync::{LightSyncProvider, ManageNetwork};
 use ethcore::account_provider::AccountProvider;
 use ethcore_logger::RotatingLogger;
 
@@ -179,6 +179,7 @@ impl Parity for ParityClient {
 			connected: peer_numbers.connected,
 			max: peer_numbers.max as u32,
 			peers: peers,
+			traffic: self.light_dispatch.sync.traffic().into(),
 		})
 	}
 
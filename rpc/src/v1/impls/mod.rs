@@ -20,6 +20,7 @@ mod debug;
 mod eth;
 mod eth_filter;
 mod eth_pubsub;
+mod evm;
 mod net;
 mod parity;
 mod parity_accounts;
@@ -41,6 +42,7 @@ pub use self::debug::DebugClient;
 pub use self::eth::{EthClient, EthClientOptions};
 pub use self::eth_filter::EthFilterClient;
 pub use self::eth_pubsub::EthPubSubClient;
+pub use self::evm::EvmClient;
 pub use self::net::NetClient;
 pub use self::parity::ParityClient;
 pub use self::parity_accounts::ParityAccountsClient;
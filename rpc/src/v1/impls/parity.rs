@@ -193,7 +193,8 @@ impl<C, M, U, S> Parity for ParityClient<C, M, U> where
 			active: sync_status.num_active_peers,
 			connected: sync_status.num_peers,
 			max: sync_status.current_max_peers(num_peers_range.start, num_peers_range.end - 1),
-			peers: peers
+			peers: peers,
+			traffic: self.net.traffic().into(),
 		})
 	}
 
@@ -14,10 +14,10 @@
 // You should have received a copy of the GNU General Public License
 // along with Parity.  If not, see <http://www.gnu.org/licenses/>.
 
-use ethcore::snapshot::{ManifestData, RestorationStatus, SnapshotService};
+use ethcore::snapshot::{ManifestData, RestorationStatus, SnapshotService, StateSyncData};
 
 use bytes::Bytes;
-use ethereum_types::H256;
+use ethereum_types::{H256, U256};
 use parking_lot::Mutex;
 
 /// Mocked snapshot service (used for sync info extensions).
@@ -50,5 +50,8 @@ impl SnapshotService for TestSnapshotService {
 	fn abort_restore(&self) { }
 	fn restore_state_chunk(&self, _hash: H256, _chunk: Bytes) { }
 	fn restore_block_chunk(&self, _hash: H256, _chunk: Bytes) { }
+	fn begin_state_restore(&self, _block: Bytes, _receipts: Bytes, _total_difficulty: U256) { }
+	fn restore_state_data(&self, _data: StateSyncData) { }
+	fn missing_state_data(&self, _count: usize) -> Vec<H256> { Vec::new() }
 	fn shutdown(&self) { }
 }
@@ -19,7 +19,7 @@
 use std::collections::BTreeMap;
 use ethereum_types::H256;
 use parking_lot::RwLock;
-use sync::{SyncProvider, EthProtocolInfo, SyncStatus, SyncState, PeerInfo, TransactionStats};
+use sync::{SyncProvider, EthProtocolInfo, SyncStatus, SyncState, PeerInfo, TransactionStats, NetworkTraffic, TrafficStats};
 
 /// TestSyncProvider config.
 pub struct Config {
@@ -83,8 +83,13 @@ impl SyncProvider for TestSyncProvider {
 					version: 62,
 					difficulty: Some(40.into()),
 					head: 50.into(),
+					reputation: 12,
 				}),
 				pip_info: None,
+				traffic: NetworkTraffic {
+					total: TrafficStats { ingress: 1200, egress: 800 },
+					protocols: vec![("eth".to_owned(), TrafficStats { ingress: 1000, egress: 600 })].into_iter().collect(),
+				},
 			},
 			PeerInfo {
 				id: None,
@@ -95,9 +100,11 @@ impl SyncProvider for TestSyncProvider {
 				eth_info: Some(EthProtocolInfo {
 					version: 64,
 					difficulty: None,
-					head: 60.into()
+					head: 60.into(),
+					reputation: -3,
 				}),
 				pip_info: None,
+				traffic: NetworkTraffic::default(),
 			}
 		]
 	}
@@ -113,12 +120,14 @@ impl SyncProvider for TestSyncProvider {
 				propagated_to: map![
 					128.into() => 16
 				],
+				announced_to: map![],
 			},
 			5.into() => TransactionStats {
 				first_seen: 16,
 				propagated_to: map![
 					16.into() => 1
 				],
+				announced_to: map![],
 			}
 		]
 	}
@@ -254,6 +254,26 @@ fn rpc_eth_logs_error() {
 	assert_eq!(tester.io.handle_request_sync(request), Some(response.to_owned()));
 }
 
+#[test]
+fn rpc_eth_history_pruned() {
+	let tester = EthTester::default();
+	tester.add_blocks(10, EachBlockWith::Nothing);
+	*tester.client.first_history_block.write() = Some(5);
+	// body of an expired block is no longer available.
+	tester.client.numbers.write().remove(&2);
+
+	let request = r#"{"jsonrpc": "2.0", "method": "eth_getBlockByNumber", "params": ["0x2", false], "id": 1}"#;
+	let response = r#"{"jsonrpc":"2.0","error":{"code":-32000,"message":"Block bodies and receipts before #5 are no longer available: they were pruned by --history-expiry or by `parity db prune-history`."},"id":1}"#;
+	assert_eq!(tester.io.handle_request_sync(request), Some(response.to_owned()));
+
+	let request = r#"{"jsonrpc": "2.0", "method": "eth_getLogs", "params": [{"fromBlock":"0x2"}], "id": 1}"#;
+	assert_eq!(tester.io.handle_request_sync(request), Some(response.to_owned()));
+
+	let request = r#"{"jsonrpc": "2.0", "method": "eth_getLogs", "params": [{"fromBlock":"0x5"}], "id": 1}"#;
+	let response = r#"{"jsonrpc":"2.0","result":[],"id":1}"#;
+	assert_eq!(tester.io.handle_request_sync(request), Some(response.to_owned()));
+}
+
 #[test]
 fn rpc_logs_filter() {
 	let tester = EthTester::default();
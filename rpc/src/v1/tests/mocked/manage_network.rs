@@ -15,7 +15,7 @@
 // along with Parity.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::ops::Range;
-use sync::ManageNetwork;
+use sync::{ManageNetwork, NetworkTraffic};
 use self::ethcore_network::{ProtocolId, NetworkContext};
 
 extern crate ethcore_network;
@@ -32,4 +32,5 @@ impl ManageNetwork for TestManageNetwork {
 	fn stop_network(&self) {}
 	fn num_peers_range(&self) -> Range<u32> { 25 .. 51 }
 	fn with_proto_context(&self, _: ProtocolId, _: &mut FnMut(&NetworkContext)) { }
+	fn traffic(&self) -> NetworkTraffic { NetworkTraffic::default() }
 }
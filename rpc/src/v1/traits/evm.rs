@@ -0,0 +1,54 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Development chain control rpc interface.
+
+use jsonrpc_core::Result;
+use jsonrpc_macros::Trailing;
+
+use v1::types::U64;
+
+build_rpc_trait! {
+	/// Development chain control rpc interface, for engines supporting it.
+	pub trait Evm {
+		/// Seals the given number of blocks (at least one), even if there are no transactions.
+		/// Returns the new best block number.
+		#[rpc(name = "evm_mine")]
+		fn mine(&self, Trailing<U64>) -> Result<U64>;
+
+		/// Moves the clock forward by the given number of seconds.
+		/// Returns the total number of seconds added.
+		#[rpc(name = "evm_increaseTime")]
+		fn increase_time(&self, U64) -> Result<U64>;
+
+		/// Sets the timestamp of the next sealed block.
+		#[rpc(name = "evm_setNextBlockTimestamp")]
+		fn set_next_block_timestamp(&self, U64) -> Result<bool>;
+
+		/// Records the current chain head and clock, returning an id to revert to.
+		#[rpc(name = "evm_snapshot")]
+		fn snapshot(&self) -> Result<U64>;
+
+		/// Reverts the chain and clock to the given snapshot, discarding it and all later ones.
+		/// Returns false if there is no such snapshot.
+		#[rpc(name = "evm_revert")]
+		fn revert(&self, U64) -> Result<bool>;
+
+		/// Enables or disables sealing blocks as soon as transactions arrive.
+		#[rpc(name = "evm_setAutomine")]
+		fn set_automine(&self, bool) -> Result<bool>;
+	}
+}
@@ -20,6 +20,7 @@ pub mod debug;
 pub mod eth;
 pub mod eth_pubsub;
 pub mod eth_signing;
+pub mod evm;
 pub mod net;
 pub mod parity;
 pub mod parity_accounts;
@@ -38,6 +39,7 @@ pub use self::debug::Debug;
 pub use self::eth::{Eth, EthFilter};
 pub use self::eth_pubsub::EthPubSub;
 pub use self::eth_signing::EthSigning;
+pub use self::evm::Evm;
 pub use self::net::Net;
 pub use self::parity::Parity;
 pub use self::parity_accounts::ParityAccounts;
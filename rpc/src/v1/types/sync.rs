@@ -46,6 +46,8 @@ pub struct Peers {
 	pub max: u32,
 	/// Detailed information on peers
 	pub peers: Vec<PeerInfo>,
+	/// Bytes transferred with all peers since start
+	pub traffic: NetworkTrafficInfo,
 }
 
 /// Peer connection information
@@ -71,6 +73,44 @@ pub struct PeerNetworkInfo {
 	pub remote_address: String,
 	/// Local endpoint address
 	pub local_address: String,
+	/// Bytes exchanged with the peer
+	pub traffic: NetworkTrafficInfo,
+}
+
+/// Bytes transferred in total and by protocol
+#[derive(Default, Debug, Serialize)]
+pub struct NetworkTrafficInfo {
+	/// All packets
+	pub total: TrafficInfo,
+	/// Packets of each protocol
+	pub protocols: BTreeMap<String, TrafficInfo>,
+}
+
+impl From<sync::NetworkTraffic> for NetworkTrafficInfo {
+	fn from(traffic: sync::NetworkTraffic) -> Self {
+		NetworkTrafficInfo {
+			total: traffic.total.into(),
+			protocols: traffic.protocols.into_iter().map(|(protocol, t)| (protocol, t.into())).collect(),
+		}
+	}
+}
+
+/// Bytes transferred
+#[derive(Default, Debug, Serialize)]
+pub struct TrafficInfo {
+	/// Bytes received
+	pub ingress: u64,
+	/// Bytes sent
+	pub egress: u64,
+}
+
+impl From<sync::TrafficStats> for TrafficInfo {
+	fn from(traffic: sync::TrafficStats) -> Self {
+		TrafficInfo {
+			ingress: traffic.ingress,
+			egress: traffic.egress,
+		}
+	}
 }
 
 /// Peer protocols information
@@ -91,6 +131,8 @@ pub struct EthProtocolInfo {
 	pub difficulty: Option<U256>,
 	/// SHA3 of peer best block hash
 	pub head: String,
+	/// Peer reputation rating
+	pub reputation: i32,
 }
 
 impl From<sync::EthProtocolInfo> for EthProtocolInfo {
@@ -99,6 +141,7 @@ impl From<sync::EthProtocolInfo> for EthProtocolInfo {
 			version: info.version,
 			difficulty: info.difficulty.map(Into::into),
 			head: format!("{:x}", info.head),
+			reputation: info.reputation,
 		}
 	}
 }
@@ -151,6 +194,8 @@ pub struct TransactionStats {
 	pub first_seen: u64,
 	/// Peers this transaction was propagated to with count.
 	pub propagated_to: BTreeMap<H512, usize>,
+	/// Peers this transaction's hash was announced to with count.
+	pub announced_to: BTreeMap<H512, usize>,
 }
 
 impl From<SyncPeerInfo> for PeerInfo {
@@ -162,6 +207,7 @@ impl From<SyncPeerInfo> for PeerInfo {
 			network: PeerNetworkInfo {
 				remote_address: p.remote_address,
 				local_address: p.local_address,
+				traffic: p.traffic.into(),
 			},
 			protocols: PeerProtocolsInfo {
 				eth: p.eth_info.map(Into::into),
@@ -179,6 +225,10 @@ impl From<SyncTransactionStats> for TransactionStats {
 				.into_iter()
 				.map(|(id, count)| (id.into(), count))
 				.collect(),
+			announced_to: s.announced_to
+				.into_iter()
+				.map(|(id, count)| (id.into(), count))
+				.collect(),
 		}
 	}
 }
@@ -241,9 +291,12 @@ mod tests {
 			propagated_to: map![
 				10.into() => 50
 			],
+			announced_to: map![
+				11.into() => 2
+			],
 		};
 
 		let serialized = serde_json::to_string(&stats).unwrap();
-		assert_eq!(serialized, r#"{"firstSeen":100,"propagatedTo":{"0x0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000a":50}}"#)
+		assert_eq!(serialized, r#"{"firstSeen":100,"propagatedTo":{"0x0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000a":50},"announcedTo":{"0x0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000b":2}}"#)
 	}
 }
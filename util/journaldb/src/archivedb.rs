@@ -155,6 +155,15 @@ impl JournalDB for ArchiveDB {
 		Ok(0)
 	}
 
+	fn revert_to_era(&mut self, batch: &mut DBTransaction, era: u64) -> io::Result<u32> {
+		// nothing is journalled, the nodes of later eras are kept like those of any other fork.
+		if self.latest_era.map_or(false, |e| e > era) {
+			batch.put(self.column, &LATEST_ERA_KEY, &encode(&era));
+			self.latest_era = Some(era);
+		}
+		Ok(0)
+	}
+
 	fn inject(&mut self, batch: &mut DBTransaction) -> io::Result<u32> {
 		let mut inserts = 0usize;
 		let mut deletes = 0usize;
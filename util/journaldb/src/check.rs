@@ -0,0 +1,413 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Consistency checks of the journal kept in the backing database.
+//!
+//! Every algorithm except `Archive` journals the inserts and removals of recent commits
+//! under `DatabaseKey`s, replaying them from the latest era downwards until an era without
+//! records is found. An unclean shutdown may leave records behind that are never replayed,
+//! or journal inserts whose values never made it to disk.
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::mem;
+
+use ethereum_types::H256;
+use hashdb::Hasher;
+use keccak_hasher::KeccakHasher;
+use kvdb::{DBTransaction, DBValue, KeyValueDB};
+use rlp::{decode, encode, DecoderError, Rlp, RlpStream};
+use util::{DatabaseKey, DatabaseValueView};
+use super::{Algorithm, LATEST_ERA_KEY};
+
+// number of deletions buffered before they are written out.
+const BATCH_SIZE: usize = 100_000;
+
+/// Inconsistencies found between the journal and the backing database.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct JournalCheck {
+	/// Latest journaled era.
+	pub latest_era: Option<u64>,
+	/// Earliest era still replayed from the journal.
+	pub earliest_era: Option<u64>,
+	/// Number of journal records replayed when the database is opened.
+	pub records: u64,
+	/// Journal records which are never replayed, as `(era, index)`.
+	pub orphaned: Vec<(u64, usize)>,
+	/// Journal records which can't be decoded, or whose inserts don't match their keys.
+	pub corrupted: Vec<(u64, usize)>,
+	/// Keys inserted by the journal which are missing from the backing database.
+	pub dangling: Vec<H256>,
+	/// Keys holding references which no replayed journal record accounts for.
+	/// These are never released, so their values leak.
+	pub over_referenced: Vec<H256>,
+}
+
+impl JournalCheck {
+	/// Returns true if no inconsistencies were found.
+	pub fn is_ok(&self) -> bool {
+		self.orphaned.is_empty() && self.corrupted.is_empty() && self.dangling.is_empty() && self.over_referenced.is_empty()
+	}
+}
+
+/// Result of resetting the journal.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct JournalReset {
+	/// Number of journal records removed.
+	pub records: u64,
+	/// Number of journaled values written to the backing database.
+	pub written: u64,
+	/// Number of reference markers removed.
+	pub markers: u64,
+	/// Number of references released on behalf of the removed records.
+	pub released: u64,
+}
+
+struct Record {
+	id: H256,
+	inserts: Vec<(H256, Option<DBValue>)>,
+	deletes: Vec<H256>,
+}
+
+fn decode_record(data: &[u8], algorithm: Algorithm) -> Result<Record, DecoderError> {
+	let view = DatabaseValueView::from_rlp(data);
+	let inserts = match algorithm {
+		// `OverlayRecentDB` keeps the inserted values in the journal until they become canonical.
+		Algorithm::OverlayRecent => Rlp::new(data).at(1)?.iter().map(|r| {
+			Ok((r.val_at(0)?, Some(DBValue::from_slice(r.at(1)?.data()?))))
+		}).collect::<Result<Vec<_>, DecoderError>>()?,
+		_ => view.inserts()?.into_iter().map(|key| (key, None)).collect(),
+	};
+
+	Ok(Record {
+		id: view.id()?,
+		inserts,
+		deletes: view.deletes()?,
+	})
+}
+
+// Parses the key of a journal record back into its era and index.
+fn journal_key(key: &[u8]) -> Option<(u64, usize)> {
+	if key.len() >= 32 {
+		return None;
+	}
+	let rlp = Rlp::new(key);
+	if !rlp.is_list() || rlp.item_count().ok()? != 3 || rlp.at(2).ok()?.data().ok()? != &[0u8; 10][..] {
+		return None;
+	}
+	Some((rlp.val_at(0).ok()?, rlp.val_at(1).ok()?))
+}
+
+// Key of the marker `EarlyMergeDB` sets for journaled keys which were already in the database.
+fn is_marker_key(key: &[u8]) -> bool {
+	key.len() == 33 && key[32] == 0
+}
+
+fn invalid_data(e: DecoderError) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn latest_era(db: &KeyValueDB, col: Option<u32>) -> io::Result<Option<u64>> {
+	match db.get(col, &LATEST_ERA_KEY)? {
+		Some(value) => decode(&value).map(Some).map_err(invalid_data),
+		None => Ok(None),
+	}
+}
+
+// Release references of a `RefCountedDB` value, keeping at least one so values of the
+// remaining state survive an inconsistent journal. Returns the number of released references.
+fn release_references(db: &KeyValueDB, col: Option<u32>, batch: &mut DBTransaction, key: &H256, count: u32) -> io::Result<u32> {
+	let payload = match db.get(col, key)? {
+		Some(payload) => payload,
+		None => return Ok(0),
+	};
+	let rlp = Rlp::new(&payload);
+	let references: u32 = rlp.val_at(0).map_err(invalid_data)?;
+	let kept = references.saturating_sub(count).max(1);
+	if kept < references {
+		let mut stream = RlpStream::new_list(2);
+		stream.append(&kept);
+		stream.append(&rlp.at(1).and_then(|value| value.data()).map_err(invalid_data)?);
+		batch.put(col, key, &stream.out());
+	}
+	Ok(references.saturating_sub(kept))
+}
+
+/// Check the journal of the given algorithm against the backing column.
+pub fn check_journal(db: &KeyValueDB, col: Option<u32>, algorithm: Algorithm) -> io::Result<JournalCheck> {
+	let mut check = JournalCheck::default();
+	check.latest_era = latest_era(db, col)?;
+	if algorithm == Algorithm::Archive {
+		return Ok(check);
+	}
+
+	// replay the journal the way the databases do when opened.
+	let mut replayed = HashSet::new();
+	let mut live_inserts = HashSet::new();
+	if let Some(mut era) = check.latest_era {
+		loop {
+			let mut db_key = DatabaseKey { era, index: 0 };
+			while let Some(data) = db.get(col, &encode(&db_key))? {
+				replayed.insert((era, db_key.index));
+				check.records += 1;
+				match decode_record(&data, algorithm) {
+					Ok(record) => {
+						if record.inserts.iter().any(|&(ref key, ref value)| value.as_ref().map_or(false, |v| KeccakHasher::hash(v) != *key)) {
+							check.corrupted.push((era, db_key.index));
+						}
+						for (key, value) in record.inserts {
+							if value.is_none() && db.get(col, &key)?.is_none() {
+								check.dangling.push(key);
+							}
+							live_inserts.insert(key);
+						}
+					},
+					Err(_) => check.corrupted.push((era, db_key.index)),
+				}
+				db_key.index += 1;
+				check.earliest_era = Some(era);
+			}
+			if db_key.index == 0 || era == 0 {
+				break;
+			}
+			era -= 1;
+		}
+	}
+
+	// anything else in the journal is left over from an interrupted commit or canonicalization.
+	let mut orphaned_inserts = HashSet::new();
+	for (key, _) in db.iter(col) {
+		if let Some(position) = journal_key(&key) {
+			if replayed.contains(&position) {
+				continue;
+			}
+			check.orphaned.push(position);
+			if algorithm == Algorithm::RefCounted {
+				if let Some(Ok(record)) = db.get(col, &key)?.map(|data| decode_record(&data, algorithm)) {
+					orphaned_inserts.extend(record.inserts.into_iter().map(|(key, _)| key));
+				}
+			}
+		} else if algorithm == Algorithm::EarlyMerge && is_marker_key(&key) {
+			let marked = H256::from_slice(&key[..32]);
+			if !live_inserts.contains(&marked) {
+				check.over_referenced.push(marked);
+			}
+		}
+	}
+
+	// `RefCountedDB` counted a reference for every insert of the orphaned records.
+	for key in orphaned_inserts {
+		if !live_inserts.contains(&key) && db.get(col, &key)?.is_some() {
+			check.over_referenced.push(key);
+		}
+	}
+
+	check.orphaned.sort();
+	check.dangling.sort();
+	check.over_referenced.sort();
+	Ok(check)
+}
+
+/// Remove the whole journal, leaving the backing column as the only state of the database,
+/// canonical as of `era`. `canonical` returns the id of the canonical commit of an era up to
+/// `era`.
+///
+/// Values only kept in the journal are written to the backing column first, so every state
+/// readable before the reset is still complete afterwards, though entries of states other than
+/// the canonical one are no longer tracked and should be swept by the caller.
+///
+/// `RefCountedDB` references are released the way canonicalizing each removed record would:
+/// the removals of canonical records and the inserts of all others.
+pub fn reset_journal(db: &KeyValueDB, col: Option<u32>, algorithm: Algorithm, era: u64, canonical: &Fn(u64) -> Option<H256>) -> io::Result<JournalReset> {
+	let mut reset = JournalReset::default();
+	let mut batch = DBTransaction::new();
+	let mut pending = 0;
+	let mut written = HashMap::new();
+	let mut released = HashMap::new();
+
+	for (key, value) in db.iter(col) {
+		if let Some((record_era, _)) = journal_key(&key) {
+			if algorithm == Algorithm::RefCounted {
+				if let Ok(record) = decode_record(&value, algorithm) {
+					let keys = if record_era <= era && canonical(record_era) == Some(record.id) {
+						record.deletes
+					} else {
+						record.inserts.into_iter().map(|(key, _)| key).collect()
+					};
+					for key in keys {
+						*released.entry(key).or_insert(0u32) += 1;
+					}
+				}
+			}
+			if algorithm == Algorithm::OverlayRecent {
+				if let Ok(record) = decode_record(&value, algorithm) {
+					trace!(target: "jdb", "reset_journal: flushing inserts of {}", record.id);
+					for (key, value) in record.inserts {
+						if let Some(value) = value {
+							if KeccakHasher::hash(&value) == key {
+								written.insert(key, value);
+							}
+						}
+					}
+				}
+			}
+			batch.delete(col, &key);
+			reset.records += 1;
+		} else if algorithm == Algorithm::EarlyMerge && is_marker_key(&key) {
+			batch.delete(col, &key);
+			reset.markers += 1;
+		} else {
+			continue;
+		}
+
+		pending += 1;
+		if pending == BATCH_SIZE {
+			db.write(mem::replace(&mut batch, DBTransaction::new()))?;
+			pending = 0;
+		}
+	}
+
+	for (key, value) in written {
+		if db.get(col, &key)?.is_none() {
+			batch.put(col, &key, &value);
+			reset.written += 1;
+		}
+	}
+
+	for (key, count) in released {
+		reset.released += release_references(db, col, &mut batch, &key, count)? as u64;
+	}
+
+	batch.put(col, &LATEST_ERA_KEY, &encode(&era));
+	db.write(batch)?;
+	Ok(reset)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+	use hashdb::HashDB;
+	use keccak::keccak;
+	use kvdb_memorydb;
+	use super::*;
+	use super::super::{new, JournalDB};
+
+	fn journaled(algorithm: Algorithm) -> (Arc<KeyValueDB>, H256, H256) {
+		let backing = Arc::new(kvdb_memorydb::create(0));
+		let mut jdb = new(backing.clone(), algorithm, None);
+		let foo = jdb.insert(b"foo");
+		jdb.commit_batch(0, &keccak(b"0"), None).unwrap();
+		let bar = jdb.insert(b"bar");
+		jdb.commit_batch(1, &keccak(b"1"), None).unwrap();
+		jdb.commit_batch(2, &keccak(b"2"), None).unwrap();
+		// `foo` becomes canonical, `bar` is still journaled.
+		jdb.commit_batch(3, &keccak(b"3"), Some((0, keccak(b"0")))).unwrap();
+		(backing, foo, bar)
+	}
+
+	#[test]
+	fn consistent_journals_pass() {
+		for algorithm in Algorithm::all_types() {
+			let (backing, _, _) = journaled(algorithm);
+			let check = check_journal(&*backing, None, algorithm).unwrap();
+			assert!(check.is_ok(), "{}: {:?}", algorithm, check);
+			assert_eq!(check.latest_era, Some(3));
+		}
+	}
+
+	#[test]
+	fn finds_dangling_inserts() {
+		let (backing, _, bar) = journaled(Algorithm::EarlyMerge);
+		let mut batch = DBTransaction::new();
+		batch.delete(None, &bar);
+		backing.write(batch).unwrap();
+
+		let check = check_journal(&*backing, None, Algorithm::EarlyMerge).unwrap();
+		assert_eq!(check.dangling, vec![bar]);
+		assert_eq!(check.records, 3);
+	}
+
+	#[test]
+	fn finds_orphaned_records() {
+		let (backing, _, bar) = journaled(Algorithm::RefCounted);
+		// a missing record for era 2 stops the replay, orphaning era 1.
+		let mut batch = DBTransaction::new();
+		batch.delete(None, &encode(&DatabaseKey { era: 2, index: 0 }));
+		backing.write(batch).unwrap();
+
+		let check = check_journal(&*backing, None, Algorithm::RefCounted).unwrap();
+		assert_eq!(check.orphaned, vec![(1, 0)]);
+		assert_eq!(check.over_referenced, vec![bar]);
+		assert_eq!(check.earliest_era, Some(3));
+	}
+
+	#[test]
+	fn finds_stale_markers() {
+		let (backing, foo, bar) = journaled(Algorithm::EarlyMerge);
+		// markers are only valid while the key is inserted by the journal.
+		let mut batch = DBTransaction::new();
+		for key in &[foo, bar] {
+			let mut marker = key.to_vec();
+			marker.push(0);
+			batch.put(None, &marker, &[1u8]);
+		}
+		backing.write(batch).unwrap();
+
+		let check = check_journal(&*backing, None, Algorithm::EarlyMerge).unwrap();
+		assert_eq!(check.over_referenced, vec![foo]);
+	}
+
+	#[test]
+	fn reset_keeps_journaled_values() {
+		let (backing, foo, bar) = journaled(Algorithm::OverlayRecent);
+		assert!(backing.get(None, &bar).unwrap().is_none());
+
+		let reset = reset_journal(&*backing, None, Algorithm::OverlayRecent, 3, &|_| None).unwrap();
+		assert_eq!(reset, JournalReset { records: 3, written: 1, markers: 0, released: 0 });
+
+		let jdb = new(backing.clone(), Algorithm::OverlayRecent, None);
+		assert_eq!(jdb.latest_era(), Some(3));
+		assert_eq!(jdb.earliest_era(), None);
+		assert_eq!(jdb.get(&foo).unwrap().to_vec(), b"foo".to_vec());
+		assert_eq!(jdb.get(&bar).unwrap().to_vec(), b"bar".to_vec());
+		assert!(check_journal(&*backing, None, Algorithm::OverlayRecent).unwrap().is_ok());
+	}
+
+	#[test]
+	fn reset_releases_references() {
+		let backing = Arc::new(kvdb_memorydb::create(0));
+		let mut jdb = new(backing.clone(), Algorithm::RefCounted, None);
+		let foo = jdb.insert(b"foo");
+		jdb.commit_batch(0, &keccak(b"0"), None).unwrap();
+		jdb.insert(b"foo");
+		let bar = jdb.insert(b"bar");
+		jdb.remove(&foo);
+		jdb.commit_batch(1, &keccak(b"1"), None).unwrap();
+		jdb.insert(b"foo");
+		jdb.commit_batch(2, &keccak(b"2"), None).unwrap();
+		jdb.commit_batch(3, &keccak(b"3"), Some((0, keccak(b"0")))).unwrap();
+		let references = |key: &H256| Rlp::new(&backing.get(None, key).unwrap().unwrap()).val_at::<u32>(0).unwrap();
+		assert_eq!(references(&foo), 3);
+
+		// era 1 is canonical and releases its removal of `foo`, era 2 was reverted and releases
+		// its insert. The reference of `bar` inserted by era 1 is kept.
+		let canonical = |era: u64| if era <= 1 { Some(keccak(format!("{}", era))) } else { None };
+		let reset = reset_journal(&*backing, None, Algorithm::RefCounted, 1, &canonical).unwrap();
+		assert_eq!(reset.released, 2);
+		assert_eq!(references(&foo), 1);
+		assert_eq!(references(&bar), 1);
+	}
+}
@@ -43,6 +43,7 @@ use std::sync::Arc;
 /// Export the journaldb module.
 mod traits;
 mod archivedb;
+mod check;
 mod earlymergedb;
 mod overlayrecentdb;
 mod refcounteddb;
@@ -53,6 +54,7 @@ pub mod overlaydb;
 
 /// Export the `JournalDB` trait.
 pub use self::traits::JournalDB;
+pub use self::check::{JournalCheck, JournalReset, check_journal, reset_journal};
 
 /// Journal database operating strategy.
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -407,6 +407,46 @@ impl JournalDB for OverlayRecentDB {
 		Ok(ops as u32)
 	}
 
+	fn revert_to_era(&mut self, batch: &mut DBTransaction, era: u64) -> io::Result<u32> {
+		trace!(target: "journaldb", "revert: #{}", era);
+
+		let mut journal_overlay = self.journal_overlay.write();
+		let journal_overlay = &mut *journal_overlay;
+
+		let latest_era = match journal_overlay.latest_era {
+			Some(latest_era) if latest_era > era => latest_era,
+			_ => return Ok(0),
+		};
+
+		let mut ops = 0;
+		for dead_era in (era + 1)..(latest_era + 1) {
+			let records = match journal_overlay.journal.remove(&dead_era) {
+				Some(records) => records,
+				None => continue,
+			};
+			for (index, journal) in records.into_iter().enumerate() {
+				trace!(target: "journaldb", "Delete journal for time #{}.{}: {}, reverted: +{} entries", dead_era, index, journal.id, journal.insertions.len());
+				batch.delete(self.column, &encode(&DatabaseKey { era: dead_era, index }));
+				// removals only take effect once canonical, so only the insertions are undone
+				for k in journal.insertions {
+					if let Some(val) = journal_overlay.backing_overlay.remove_and_purge(&to_short_key(&k)) {
+						journal_overlay.cumulative_size -= val.len();
+					}
+				}
+				ops += 1;
+			}
+		}
+
+		trace!(target: "journaldb", "Set latest era to {}", era);
+		batch.put_vec(self.column, &LATEST_ERA_KEY, encode(&era).to_vec());
+		journal_overlay.latest_era = Some(era);
+		if journal_overlay.journal.is_empty() {
+			journal_overlay.earliest_era = None;
+		}
+
+		Ok(ops)
+	}
+
 	fn flush(&self) {
 		self.journal_overlay.write().pending_overlay.clear();
 	}
@@ -630,6 +670,42 @@ mod tests {
 		assert!(!jdb.contains(&bar));
 	}
 
+	#[test]
+	fn revert_to_era() {
+		// history is 1
+		let mut jdb = new_db();
+
+		let foo = jdb.insert(b"foo");
+		jdb.commit_batch(0, &keccak(b"0"), None).unwrap();
+		assert!(jdb.can_reconstruct_refs());
+
+		jdb.remove(&foo);
+		let bar = jdb.insert(b"bar");
+		jdb.commit_batch(1, &keccak(b"1"), Some((0, keccak(b"0")))).unwrap();
+		let baz = jdb.insert(b"baz");
+		jdb.commit_batch(2, &keccak(b"2"), None).unwrap();
+		assert!(jdb.contains(&bar));
+		assert!(jdb.contains(&baz));
+
+		let mut batch = jdb.backing().transaction();
+		assert_eq!(jdb.revert_to_era(&mut batch, 0).unwrap(), 2);
+		jdb.backing().write(batch).unwrap();
+		assert!(jdb.can_reconstruct_refs());
+		assert_eq!(jdb.latest_era(), Some(0));
+		assert!(jdb.contains(&foo));
+		assert!(!jdb.contains(&bar));
+		assert!(!jdb.contains(&baz));
+
+		// the reverted eras are journalled again by other blocks.
+		let qux = jdb.insert(b"qux");
+		jdb.commit_batch(1, &keccak(b"1b"), Some((0, keccak(b"0")))).unwrap();
+		jdb.commit_batch(2, &keccak(b"2b"), Some((1, keccak(b"1b")))).unwrap();
+		assert!(jdb.can_reconstruct_refs());
+		assert!(jdb.contains(&foo));
+		assert!(jdb.contains(&qux));
+		assert!(!jdb.contains(&bar));
+	}
+
 	#[test]
 	fn overwrite() {
 		// history is 1
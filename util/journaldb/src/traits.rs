@@ -55,6 +55,12 @@ pub trait JournalDB: HashDB<KeccakHasher, DBValue> {
 	/// Mark a given block as canonical, indicating that competing blocks' states may be pruned out.
 	fn mark_canonical(&mut self, batch: &mut DBTransaction, era: u64, id: &H256) -> io::Result<u32>;
 
+	/// Drop the journal of every era after the given one, along with the nodes only those eras
+	/// inserted, as if they were never journalled. The given era becomes the latest one.
+	fn revert_to_era(&mut self, _batch: &mut DBTransaction, _era: u64) -> io::Result<u32> {
+		Err(io::Error::new(io::ErrorKind::Other, "Reverting the journal is not supported by this pruning algorithm"))
+	}
+
 	/// Commit all queued insert and delete operations without affecting any journalling -- this requires that all insertions
 	/// and deletions are indeed canonical and will likely lead to an invalid database if that assumption is violated.
 	///
@@ -0,0 +1,511 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! LMDB backed `KeyValueDB`.
+//!
+//! Every column is a named LMDB database within a single environment, so a
+//! transaction spanning several columns is committed atomically. LMDB has no
+//! background compaction, which keeps write latency predictable.
+
+extern crate kvdb;
+extern crate lmdb;
+extern crate parking_lot;
+
+#[macro_use]
+extern crate log;
+
+#[cfg(test)]
+extern crate tempdir;
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::{fs, io, mem};
+
+use kvdb::{DBOp, DBTransaction, DBValue, KeyValueDB};
+use lmdb::{Cursor, DatabaseFlags, Environment, Transaction, WriteFlags};
+use parking_lot::{Mutex, RwLock};
+
+/// Name of the LMDB data file within the database directory.
+pub const DATA_FILE: &'static str = "data.mdb";
+
+/// Default maximum size of the memory map. The data file grows on demand up to this size.
+#[cfg(target_pointer_width = "64")]
+pub const DEFAULT_MAP_SIZE: usize = 1 << 40;
+/// Default maximum size of the memory map. The data file grows on demand up to this size.
+#[cfg(not(target_pointer_width = "64"))]
+pub const DEFAULT_MAP_SIZE: usize = 1 << 30;
+
+// maximum number of concurrent read transactions.
+const MAX_READERS: u32 = 1024;
+
+// number of entries read from the database at a time while iterating.
+const ITER_BATCH: usize = 1024;
+
+fn other_io_err<E: ::std::fmt::Display>(e: E) -> io::Error {
+	io::Error::new(io::ErrorKind::Other, format!("{}", e))
+}
+
+/// Database configuration
+#[derive(Clone, Debug, PartialEq)]
+pub struct DatabaseConfig {
+	/// Number of columns, besides the default one.
+	pub columns: Option<u32>,
+	/// Maximum size of the memory map.
+	pub map_size: usize,
+}
+
+impl DatabaseConfig {
+	/// Create a new `DatabaseConfig` with the given number of columns.
+	pub fn with_columns(columns: Option<u32>) -> Self {
+		DatabaseConfig {
+			columns: columns,
+			.. Default::default()
+		}
+	}
+}
+
+impl Default for DatabaseConfig {
+	fn default() -> Self {
+		DatabaseConfig {
+			columns: None,
+			map_size: DEFAULT_MAP_SIZE,
+		}
+	}
+}
+
+enum KeyState {
+	Insert(DBValue),
+	Delete,
+}
+
+struct DBAndColumns {
+	env: Environment,
+	// the default column comes first.
+	cols: Vec<lmdb::Database>,
+}
+
+fn col_index(col: Option<u32>) -> usize {
+	col.map_or(0, |c| c as usize + 1)
+}
+
+fn open_env(config: &DatabaseConfig, path: &Path) -> io::Result<DBAndColumns> {
+	fs::create_dir_all(path)?;
+
+	let columns = config.columns.unwrap_or(0);
+	let env = Environment::new()
+		.set_max_dbs(columns + 1)
+		.set_max_readers(MAX_READERS)
+		.set_map_size(config.map_size)
+		.open(path)
+		.map_err(other_io_err)?;
+
+	let mut cols = vec![env.create_db(Some("default"), DatabaseFlags::empty()).map_err(other_io_err)?];
+	for i in 0..columns {
+		cols.push(env.create_db(Some(&format!("col{}", i)), DatabaseFlags::empty()).map_err(other_io_err)?);
+	}
+
+	Ok(DBAndColumns {
+		env: env,
+		cols: cols,
+	})
+}
+
+/// Key-value database backed by LMDB.
+///
+/// Writes are buffered in memory until the database is flushed.
+pub struct Database {
+	db: RwLock<Option<DBAndColumns>>,
+	config: DatabaseConfig,
+	path: PathBuf,
+	overlay: RwLock<Vec<HashMap<Vec<u8>, KeyState>>>,
+	flushing: RwLock<Vec<HashMap<Vec<u8>, KeyState>>>,
+	flushing_lock: Mutex<()>,
+}
+
+impl Database {
+	/// Open the database at the given path, creating it if it doesn't exist.
+	pub fn open(config: &DatabaseConfig, path: &str) -> io::Result<Database> {
+		let path = PathBuf::from(path);
+		let db = open_env(config, &path)?;
+		let columns = db.cols.len();
+
+		Ok(Database {
+			db: RwLock::new(Some(db)),
+			config: config.clone(),
+			path: path,
+			overlay: RwLock::new((0..columns).map(|_| HashMap::new()).collect()),
+			flushing: RwLock::new((0..columns).map(|_| HashMap::new()).collect()),
+			flushing_lock: Mutex::new(()),
+		})
+	}
+
+	/// Whether the directory at the given path holds an LMDB database.
+	pub fn exists(path: &Path) -> bool {
+		path.join(DATA_FILE).is_file()
+	}
+
+	fn write_flushing(&self) -> io::Result<()> {
+		let db = self.db.read();
+		let db = db.as_ref().ok_or_else(|| other_io_err("Database is closed"))?;
+
+		let mut txn = db.env.begin_rw_txn().map_err(other_io_err)?;
+		for (idx, changes) in self.flushing.read().iter().enumerate() {
+			for (key, state) in changes {
+				match *state {
+					KeyState::Insert(ref value) => {
+						let value: &[u8] = &*value;
+						txn.put(db.cols[idx], key, &value, WriteFlags::empty())
+					},
+					KeyState::Delete => match txn.del(db.cols[idx], key, None) {
+						Err(lmdb::Error::NotFound) => Ok(()),
+						res => res,
+					},
+				}.map_err(other_io_err)?;
+			}
+		}
+
+		txn.commit().map_err(other_io_err)
+	}
+
+	fn iter_col<'a>(&'a self, col: Option<u32>, prefix: &[u8]) -> Box<Iterator<Item=(Box<[u8]>, Box<[u8]>)> + 'a> {
+		// take a snapshot of the buffered writes, merged with the environment while iterating.
+		// lock order as in `flush`.
+		let idx = col_index(col);
+		let mut pending = BTreeMap::new();
+		{
+			let overlay = self.overlay.read();
+			let flushing = self.flushing.read();
+			for changes in &[&flushing[idx], &overlay[idx]] {
+				for (key, state) in changes.iter().filter(|&(key, _)| key.starts_with(prefix)) {
+					let value = match *state {
+						KeyState::Insert(ref value) => Some(value.to_vec().into_boxed_slice()),
+						KeyState::Delete => None,
+					};
+					pending.insert(key.clone().into_boxed_slice(), value);
+				}
+			}
+		}
+
+		Box::new(BatchIter {
+			db: self,
+			col: idx,
+			prefix: prefix.to_vec(),
+			from: prefix.to_vec(),
+			batch: VecDeque::new(),
+			pending: pending.into_iter().collect(),
+			done: false,
+		})
+	}
+}
+
+impl KeyValueDB for Database {
+	fn get(&self, col: Option<u32>, key: &[u8]) -> io::Result<Option<DBValue>> {
+		let idx = col_index(col);
+		for pending in &[&self.overlay, &self.flushing] {
+			match pending.read()[idx].get(key) {
+				Some(&KeyState::Insert(ref value)) => return Ok(Some(value.clone())),
+				Some(&KeyState::Delete) => return Ok(None),
+				None => {},
+			}
+		}
+
+		let db = self.db.read();
+		let db = match *db {
+			Some(ref db) => db,
+			None => return Ok(None),
+		};
+
+		let txn = db.env.begin_ro_txn().map_err(other_io_err)?;
+		match txn.get(db.cols[idx], &key) {
+			Ok(value) => Ok(Some(DBValue::from_slice(value))),
+			Err(lmdb::Error::NotFound) => Ok(None),
+			Err(e) => Err(other_io_err(e)),
+		}
+	}
+
+	fn get_by_prefix(&self, col: Option<u32>, prefix: &[u8]) -> Option<Box<[u8]>> {
+		self.iter_col(col, prefix).next().map(|(_, value)| value)
+	}
+
+	fn write_buffered(&self, transaction: DBTransaction) {
+		let mut overlay = self.overlay.write();
+		for op in transaction.ops {
+			match op {
+				DBOp::Insert { col, key, value } => overlay[col_index(col)].insert(key.to_vec(), KeyState::Insert(value)),
+				DBOp::Delete { col, key } => overlay[col_index(col)].insert(key.to_vec(), KeyState::Delete),
+			};
+		}
+	}
+
+	fn flush(&self) -> io::Result<()> {
+		let _lock = self.flushing_lock.lock();
+		mem::swap(&mut *self.overlay.write(), &mut *self.flushing.write());
+
+		let res = self.write_flushing();
+
+		// on failure, keep the changes around for the next attempt. newer writes win.
+		let mut overlay = self.overlay.write();
+		for (idx, changes) in self.flushing.write().iter_mut().enumerate() {
+			for (key, state) in changes.drain() {
+				if res.is_err() {
+					overlay[idx].entry(key).or_insert(state);
+				}
+			}
+		}
+
+		res
+	}
+
+	fn iter<'a>(&'a self, col: Option<u32>) -> Box<Iterator<Item=(Box<[u8]>, Box<[u8]>)> + 'a> {
+		self.iter_col(col, &[])
+	}
+
+	fn iter_from_prefix<'a>(&'a self, col: Option<u32>, prefix: &'a [u8]) -> Box<Iterator<Item=(Box<[u8]>, Box<[u8]>)> + 'a> {
+		self.iter_col(col, prefix)
+	}
+
+	fn restore(&self, new_db: &str) -> io::Result<()> {
+		let _lock = self.flushing_lock.lock();
+		let mut db = self.db.write();
+
+		// close the environment before moving its files.
+		*db = None;
+		for changes in self.overlay.write().iter_mut() {
+			changes.clear();
+		}
+
+		let mut backup = self.path.clone().into_os_string();
+		backup.push(".bak");
+		let backup = PathBuf::from(backup);
+
+		fs::rename(&self.path, &backup)?;
+		if let Err(e) = fs::rename(new_db, &self.path) {
+			warn!(target: "db", "Failed to move restored database into place: {}", e);
+			fs::rename(&backup, &self.path)?;
+			*db = Some(open_env(&self.config, &self.path)?);
+			return Err(e);
+		}
+
+		fs::remove_dir_all(&backup)?;
+		*db = Some(open_env(&self.config, &self.path)?);
+		Ok(())
+	}
+}
+
+impl Drop for Database {
+	fn drop(&mut self) {
+		if let Err(e) = self.flush() {
+			warn!(target: "db", "Failed to flush database on close: {}", e);
+		}
+	}
+}
+
+// iterates over a column in batches, each read in its own transaction, so that
+// no transaction outlives a call to `next`. The buffered writes taken when the
+// iterator was created override the entries read from the environment.
+struct BatchIter<'a> {
+	db: &'a Database,
+	col: usize,
+	prefix: Vec<u8>,
+	from: Vec<u8>,
+	batch: VecDeque<(Box<[u8]>, Box<[u8]>)>,
+	pending: VecDeque<(Box<[u8]>, Option<Box<[u8]>>)>,
+	done: bool,
+}
+
+impl<'a> BatchIter<'a> {
+	fn fill(&mut self) -> io::Result<()> {
+		let db = self.db.db.read();
+		let db = db.as_ref().ok_or_else(|| other_io_err("Database is closed"))?;
+
+		let txn = db.env.begin_ro_txn().map_err(other_io_err)?;
+		let mut cursor = txn.open_ro_cursor(db.cols[self.col]).map_err(other_io_err)?;
+		let iter = match self.from.is_empty() {
+			true => cursor.iter_start(),
+			false => cursor.iter_from(&self.from),
+		};
+
+		for item in iter {
+			let (key, value) = item.map_err(other_io_err)?;
+			if !key.starts_with(&self.prefix) {
+				self.done = true;
+				break;
+			}
+
+			self.batch.push_back((key.to_vec().into_boxed_slice(), value.to_vec().into_boxed_slice()));
+			if self.batch.len() == ITER_BATCH {
+				break;
+			}
+		}
+
+		if self.batch.len() < ITER_BATCH {
+			self.done = true;
+		}
+
+		// the next batch starts right after the last key read.
+		if let Some(&(ref key, _)) = self.batch.back() {
+			self.from = key.to_vec();
+			self.from.push(0);
+		}
+
+		Ok(())
+	}
+}
+
+impl<'a> Iterator for BatchIter<'a> {
+	type Item = (Box<[u8]>, Box<[u8]>);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			if self.batch.is_empty() && !self.done {
+				if let Err(e) = self.fill() {
+					warn!(target: "db", "Failed to read from database: {}", e);
+					self.done = true;
+				}
+			}
+
+			let order = match (self.batch.front(), self.pending.front()) {
+				(None, None) => return None,
+				(Some(_), None) => Ordering::Less,
+				(None, Some(_)) => Ordering::Greater,
+				(Some(&(ref stored, _)), Some(&(ref pending, _))) => stored.cmp(pending),
+			};
+
+			if order != Ordering::Greater {
+				let stored = self.batch.pop_front().expect("front is some; qed");
+				if order == Ordering::Less {
+					return Some(stored);
+				}
+			}
+
+			// a buffered write at or before the next stored key, deleted keys are skipped.
+			if let (key, Some(value)) = self.pending.pop_front().expect("front is some; qed") {
+				return Some((key, value));
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tempdir::TempDir;
+
+	fn open(path: &Path) -> Database {
+		Database::open(&DatabaseConfig::with_columns(Some(2)), path.to_str().unwrap()).unwrap()
+	}
+
+	#[test]
+	fn get_write_and_delete() {
+		let tempdir = TempDir::new("").unwrap();
+		let db = open(tempdir.path());
+
+		let mut batch = db.transaction();
+		batch.put(None, b"key", b"default");
+		batch.put(Some(1), b"key", b"col1");
+		db.write_buffered(batch);
+
+		// buffered writes are visible before flushing.
+		assert_eq!(&*db.get(None, b"key").unwrap().unwrap(), b"default");
+		db.flush().unwrap();
+		assert_eq!(&*db.get(None, b"key").unwrap().unwrap(), b"default");
+		assert_eq!(&*db.get(Some(1), b"key").unwrap().unwrap(), b"col1");
+		assert!(db.get(Some(0), b"key").unwrap().is_none());
+
+		let mut batch = db.transaction();
+		batch.delete(Some(1), b"key");
+		db.write(batch).unwrap();
+		assert!(db.get(Some(1), b"key").unwrap().is_none());
+
+		drop(db);
+		let db = open(tempdir.path());
+		assert_eq!(&*db.get(None, b"key").unwrap().unwrap(), b"default");
+		assert!(Database::exists(tempdir.path()));
+	}
+
+	#[test]
+	fn iterates_in_batches_and_by_prefix() {
+		let tempdir = TempDir::new("").unwrap();
+		let db = open(tempdir.path());
+
+		let mut batch = db.transaction();
+		for i in 0..(ITER_BATCH as u32 * 2 + 10) {
+			let key = [(i >> 8) as u8, i as u8];
+			batch.put(Some(0), &key, &key);
+		}
+		db.write_buffered(batch);
+
+		let all: Vec<_> = db.iter(Some(0)).collect();
+		assert_eq!(all.len(), ITER_BATCH * 2 + 10);
+		assert!(all.windows(2).all(|w| w[0].0 < w[1].0));
+
+		let prefixed: Vec<_> = db.iter_from_prefix(Some(0), &[1]).collect();
+		assert_eq!(prefixed.len(), 256);
+		assert!(prefixed.iter().all(|&(ref key, _)| key[0] == 1));
+		assert_eq!(&*db.get_by_prefix(Some(0), &[2]).unwrap(), &[2, 0]);
+	}
+
+	#[test]
+	fn iterates_over_buffered_writes_without_flushing() {
+		let tempdir = TempDir::new("").unwrap();
+		let db = open(tempdir.path());
+
+		let mut batch = db.transaction();
+		batch.put(None, b"a", b"stored");
+		batch.put(None, b"b", b"stored");
+		batch.put(None, b"c", b"stored");
+		db.write(batch).unwrap();
+
+		let mut batch = db.transaction();
+		batch.delete(None, b"a");
+		batch.put(None, b"b", b"buffered");
+		batch.put(None, b"d", b"buffered");
+		db.write_buffered(batch);
+
+		let all: Vec<_> = db.iter(None).map(|(key, value)| (key.to_vec(), value.to_vec())).collect();
+		assert_eq!(all, vec![
+			(b"b".to_vec(), b"buffered".to_vec()),
+			(b"c".to_vec(), b"stored".to_vec()),
+			(b"d".to_vec(), b"buffered".to_vec()),
+		]);
+		assert!(!db.overlay.read()[0].is_empty());
+	}
+
+	#[test]
+	fn restores_from_other_database() {
+		let tempdir = TempDir::new("").unwrap();
+		let db = open(&tempdir.path().join("main"));
+		db.write({
+			let mut batch = db.transaction();
+			batch.put(None, b"old", b"value");
+			batch
+		}).unwrap();
+
+		{
+			let other = open(&tempdir.path().join("other"));
+			other.write({
+				let mut batch = other.transaction();
+				batch.put(None, b"new", b"value");
+				batch
+			}).unwrap();
+		}
+
+		db.restore(tempdir.path().join("other").to_str().unwrap()).unwrap();
+		assert!(db.get(None, b"old").unwrap().is_none());
+		assert_eq!(&*db.get(None, b"new").unwrap().unwrap(), b"value");
+	}
+}
@@ -0,0 +1,185 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Upload rate limits and traffic accounting shared by all sessions.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::time::Instant;
+use network::{NetworkConfiguration, PacketId, ProtocolId, TrafficStats};
+
+/// Limits a byte rate, allowing bursts of up to a second worth of bytes. Packets pass as long
+/// as the bucket isn't empty and may take it into debt, so packets larger than a burst still
+/// get through eventually.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+	rate: u64,
+	tokens: i64,
+	updated: Instant,
+}
+
+impl TokenBucket {
+	pub fn new(rate: u64) -> TokenBucket {
+		TokenBucket {
+			rate,
+			tokens: rate as i64,
+			updated: Instant::now(),
+		}
+	}
+
+	fn refill(&mut self, now: Instant) {
+		let elapsed = now.duration_since(self.updated);
+		let elapsed_ms = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_nanos() / 1_000_000);
+		let added = (self.rate * elapsed_ms / 1000) as i64;
+		if added > 0 {
+			self.tokens = cmp::min(self.tokens + added, self.rate as i64);
+			self.updated = now;
+		}
+	}
+
+	/// Whether anything can be sent now.
+	pub fn is_available(&mut self, now: Instant) -> bool {
+		self.refill(now);
+		self.tokens > 0
+	}
+
+	/// Account for bytes sent.
+	pub fn take(&mut self, bytes: usize) {
+		self.tokens -= bytes as i64;
+	}
+}
+
+/// Upload limits and traffic counters of the host.
+pub struct Bandwidth {
+	global: Option<TokenBucket>,
+	protocols: HashMap<ProtocolId, TokenBucket>,
+	packets: Vec<(ProtocolId, Vec<PacketId>, TokenBucket)>,
+	peer_rate: Option<u64>,
+	traffic: TrafficStats,
+	protocol_traffic: HashMap<ProtocolId, TrafficStats>,
+}
+
+impl Bandwidth {
+	pub fn new(config: &NetworkConfiguration) -> Bandwidth {
+		Bandwidth {
+			global: config.max_upload_rate.map(TokenBucket::new),
+			protocols: config.protocol_upload_rates.iter().map(|(protocol, rate)| (*protocol, TokenBucket::new(*rate))).collect(),
+			packets: config.packet_upload_rates.iter().map(|limit| (limit.protocol, limit.packets.clone(), TokenBucket::new(limit.rate))).collect(),
+			peer_rate: config.max_peer_upload_rate,
+			traffic: TrafficStats::default(),
+			protocol_traffic: HashMap::new(),
+		}
+	}
+
+	/// Whether any upload limit is set.
+	pub fn is_limited(&self) -> bool {
+		self.global.is_some() || self.peer_rate.is_some() || !self.protocols.is_empty() || !self.packets.is_empty()
+	}
+
+	/// Upload limit for a new session.
+	pub fn peer_bucket(&self) -> Option<TokenBucket> {
+		self.peer_rate.map(TokenBucket::new)
+	}
+
+	/// Try to send a packet of a protocol to a peer with the given limit. Returns `false`
+	/// if the packet has to wait.
+	pub fn try_upload(&mut self, protocol: ProtocolId, packet_id: PacketId, peer: Option<&mut TokenBucket>, bytes: usize) -> bool {
+		let now = Instant::now();
+		let mut buckets: Vec<&mut TokenBucket> = peer.into_iter()
+			.chain(self.global.as_mut())
+			.chain(self.protocols.get_mut(&protocol))
+			.chain(self.packets.iter_mut()
+				.filter(|limit| limit.0 == protocol && limit.1.contains(&packet_id))
+				.map(|limit| &mut limit.2))
+			.collect();
+		if !buckets.iter_mut().all(|bucket| bucket.is_available(now)) {
+			return false;
+		}
+		for bucket in buckets {
+			bucket.take(bytes);
+		}
+		true
+	}
+
+	/// Count bytes transferred, for the protocol if given or in total otherwise.
+	pub fn note_traffic(&mut self, protocol: Option<ProtocolId>, traffic: &TrafficStats) {
+		let stats = match protocol {
+			Some(protocol) => self.protocol_traffic.entry(protocol).or_insert_with(TrafficStats::default),
+			None => &mut self.traffic,
+		};
+		add_traffic(stats, traffic);
+	}
+
+	/// Bytes transferred since start, in total and by each protocol.
+	pub fn traffic(&self) -> (TrafficStats, HashMap<ProtocolId, TrafficStats>) {
+		(self.traffic, self.protocol_traffic.clone())
+	}
+}
+
+pub fn add_traffic(stats: &mut TrafficStats, traffic: &TrafficStats) {
+	stats.ingress += traffic.ingress;
+	stats.egress += traffic.egress;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+	use network::PacketUploadRate;
+
+	#[test]
+	fn bucket_limits_rate() {
+		let mut bucket = TokenBucket::new(1000);
+		let start = bucket.updated;
+		assert!(bucket.is_available(start));
+		bucket.take(1500);
+		assert!(!bucket.is_available(start + Duration::from_millis(400)));
+		assert!(bucket.is_available(start + Duration::from_millis(600)));
+		// bursts don't exceed a second worth of bytes
+		assert!(bucket.is_available(start + Duration::from_secs(10)));
+		bucket.take(1000);
+		assert!(!bucket.is_available(start + Duration::from_secs(10)));
+	}
+
+	#[test]
+	fn limits_peers_and_protocols() {
+		let mut config = NetworkConfiguration::new();
+		config.max_peer_upload_rate = Some(1000);
+		config.protocol_upload_rates.insert(*b"par", 1500);
+		let mut bandwidth = Bandwidth::new(&config);
+		let (mut peer1, mut peer2) = (bandwidth.peer_bucket().unwrap(), bandwidth.peer_bucket().unwrap());
+
+		assert!(bandwidth.try_upload(*b"par", 0x01, Some(&mut peer1), 1000));
+		assert!(!bandwidth.try_upload(*b"par", 0x01, Some(&mut peer1), 100));
+		assert!(bandwidth.try_upload(*b"par", 0x01, Some(&mut peer2), 600));
+		// protocol limit is shared by all peers
+		assert!(!bandwidth.try_upload(*b"par", 0x01, Some(&mut peer2), 100));
+		assert!(bandwidth.try_upload(*b"eth", 0x01, Some(&mut peer2), 100));
+	}
+
+	#[test]
+	fn limits_packets() {
+		let mut config = NetworkConfiguration::new();
+		config.packet_upload_rates.push(PacketUploadRate { protocol: *b"par", packets: vec![0x12, 0x14], rate: 1000 });
+		let mut bandwidth = Bandwidth::new(&config);
+
+		assert!(bandwidth.try_upload(*b"par", 0x14, None, 1500));
+		// the limit is shared by the listed packets
+		assert!(!bandwidth.try_upload(*b"par", 0x12, None, 100));
+		assert!(bandwidth.try_upload(*b"par", 0x04, None, 100));
+		assert!(bandwidth.try_upload(*b"eth", 0x14, None, 100));
+	}
+}
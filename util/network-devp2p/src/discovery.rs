@@ -19,6 +19,7 @@ use std::net::SocketAddr;
 use std::collections::{HashSet, HashMap, VecDeque};
 use std::collections::hash_map::Entry;
 use std::default::Default;
+use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use hash::keccak;
 use ethereum_types::{H256, H520};
@@ -26,7 +27,9 @@ use rlp::{Rlp, RlpStream};
 use node_table::*;
 use network::{Error, ErrorKind};
 use ethkey::{Secret, KeyPair, sign, recover};
-use network::IpFilter;
+use network::{IpFilter, EnrFilter};
+use discv5::Discv5;
+use enr::Enr;
 
 use PROTOCOL_VERSION;
 
@@ -141,15 +144,18 @@ pub struct Discovery<'a> {
 	adding_nodes: Vec<NodeEntry>,
 	ip_filter: IpFilter,
 	request_backoff: &'a [Duration],
+	v5: Discv5,
 }
 
 pub struct TableUpdates {
 	pub added: HashMap<NodeId, NodeEntry>,
 	pub removed: HashSet<NodeId>,
+	pub records: HashMap<NodeId, Enr>,
 }
 
 impl<'a> Discovery<'a> {
 	pub fn new(key: &KeyPair, public: NodeEndpoint, ip_filter: IpFilter) -> Discovery<'static> {
+		let v5 = Discv5::new(key, public.clone(), ip_filter.clone());
 		Discovery {
 			id: *key.public(),
 			id_hash: keccak(key.public()),
@@ -167,6 +173,19 @@ impl<'a> Discovery<'a> {
 			adding_nodes: Vec::new(),
 			ip_filter,
 			request_backoff: &REQUEST_BACKOFF,
+			v5,
+		}
+	}
+
+	/// Set the filter for node records found by v5 discovery.
+	pub fn set_enr_filter(&mut self, filter: Arc<EnrFilter>) {
+		self.v5.set_enr_filter(filter);
+	}
+
+	/// Add known node records to the v5 table.
+	pub fn add_records(&mut self, records: Vec<Enr>) {
+		for record in records {
+			self.v5.add_record(record);
 		}
 	}
 
@@ -212,6 +231,7 @@ impl<'a> Discovery<'a> {
 			} else { false };
 
 			if !updated {
+				self.v5.add_candidate(&e);
 				added_map.insert(e.id, e.clone());
 				bucket.nodes.push_front(BucketEntry::new(e));
 
@@ -223,7 +243,7 @@ impl<'a> Discovery<'a> {
 		if let Some(node) = ping {
 			self.try_ping(node);
 		}
-		Some(TableUpdates { added: added_map, removed: HashSet::new() })
+		Some(TableUpdates { added: added_map, removed: HashSet::new(), records: HashMap::new() })
 	}
 
 	/// Starts the discovery process at round 0
@@ -422,14 +442,14 @@ impl<'a> Discovery<'a> {
 
 
 	pub fn on_packet(&mut self, packet: &[u8], from: SocketAddr) -> Result<Option<TableUpdates>, Error> {
-		// validate packet
+		// validate packet, anything that isn't a v4 packet may be a v5 one
 		if packet.len() < 32 + 65 + 4 + 1 {
-			return Err(ErrorKind::BadProtocol.into());
+			return self.v5.on_packet(packet, from);
 		}
 
 		let hash_signed = keccak(&packet[32..]);
 		if hash_signed[..] != packet[0..32] {
-			return Err(ErrorKind::BadProtocol.into());
+			return self.v5.on_packet(packet, from);
 		}
 
 		let signed = &packet[(32 + 65)..];
@@ -678,6 +698,7 @@ impl<'a> Discovery<'a> {
 	pub fn round(&mut self) {
 		self.check_expired(Instant::now());
 		self.update_new_nodes();
+		self.v5.round();
 
 		if self.discovery_round.is_some() {
 			self.discover();
@@ -692,14 +713,19 @@ impl<'a> Discovery<'a> {
 		if self.discovery_round.is_none() {
 			self.start();
 		}
+		self.v5.update_record();
+		self.v5.lookup();
 	}
 
 	pub fn any_sends_queued(&self) -> bool {
-		!self.send_queue.is_empty()
+		!self.send_queue.is_empty() || self.v5.any_sends_queued()
 	}
 
 	pub fn dequeue_send(&mut self) -> Option<Datagram> {
-		self.send_queue.pop_front()
+		match self.send_queue.pop_front() {
+			Some(datagram) => Some(datagram),
+			None => self.v5.dequeue_send(),
+		}
 	}
 
 	pub fn requeue_send(&mut self, datagram: Datagram) {
@@ -0,0 +1,848 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Node discovery v5.
+//!
+//! Runs next to the v4 protocol and shares its UDP socket. Packet headers are masked with the
+//! recipient's node id and messages are encrypted with session keys, agreed on through a
+//! WHOAREYOU challenge and a handshake carrying an identity proof. Nodes are known by their
+//! ENRs; `FINDNODE` walks the table by log distance and `TOPICQUERY` asks for the nodes whose
+//! record carries an entry with a key hashing to the topic.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crypto::{aes, digest, hmac};
+use crypto::aes_gcm::{Encryptor, Decryptor};
+use ethereum_types::H256;
+use ethkey::{KeyPair, Secret, Public, Random, Generator, Signature, sign, verify_public};
+use ethkey::math::{public_mul_secret, public_to_compressed, public_from_compressed};
+use hash::keccak;
+use parity_bytes::Bytes;
+use rand::{self, Rng};
+use rlp::{Rlp, RlpStream};
+use network::{Error, ErrorKind, EnrFilter, IpFilter};
+use discovery::{Datagram, NodeEntry, TableUpdates, MAX_DATAGRAM_SIZE};
+use enr::Enr;
+use node_table::{NodeId, NodeEndpoint};
+
+const PROTOCOL_ID: &[u8] = b"discv5";
+const VERSION: u16 = 1;
+
+const FLAG_MESSAGE: u8 = 0;
+const FLAG_WHOAREYOU: u8 = 1;
+const FLAG_HANDSHAKE: u8 = 2;
+
+const MASKING_IV_SIZE: usize = 16;
+const STATIC_HEADER_SIZE: usize = 23;			// protocol id, version, flag, nonce, authdata size
+const NONCE_SIZE: usize = 12;
+const ID_NONCE_SIZE: usize = 16;
+const MIN_PACKET_SIZE: usize = MASKING_IV_SIZE + STATIC_HEADER_SIZE + ID_NONCE_SIZE + 8;	// WHOAREYOU
+
+const MESSAGE_PING: u8 = 0x01;
+const MESSAGE_PONG: u8 = 0x02;
+const MESSAGE_FIND_NODE: u8 = 0x03;
+const MESSAGE_NODES: u8 = 0x04;
+const MESSAGE_TOPIC_QUERY: u8 = 0x0a;
+
+const KEY_AGREEMENT_INFO: &[u8] = b"discovery v5 key agreement";
+const ID_PROOF_PREFIX: &[u8] = b"discovery v5 identity proof";
+
+const ALPHA: usize = 3;					// Concurrent requests per lookup and candidates contacted per round.
+const MAX_RECORDS: usize = 1024;		// Max records kept in the table.
+const MAX_CANDIDATES: usize = 256;		// Max nodes waiting to be asked for their record.
+const MAX_NODES_RESPONSE: usize = 16;	// Max records returned for a request.
+const MAX_NODES_PER_PACKET: usize = 3;	// Records are up to 300 bytes, keeps NODES packets under the datagram limit.
+const MAX_NODES_PACKETS: usize = 6;		// Max NODES packets accepted for a single request.
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+const SESSION_TIMEOUT: Duration = Duration::from_secs(3600);
+
+type SessionKey = [u8; 16];
+type Nonce = [u8; NONCE_SIZE];
+
+/// Remote node we know the public key and address of.
+#[derive(Clone, Debug)]
+struct Contact {
+	id: NodeId,
+	hash: H256,
+	address: SocketAddr,
+}
+
+impl Contact {
+	fn new(entry: &NodeEntry) -> Self {
+		Contact {
+			id: entry.id,
+			hash: keccak(&entry.id),
+			address: entry.endpoint.udp_address(),
+		}
+	}
+}
+
+struct Session {
+	contact: Contact,
+	encrypt_key: SessionKey,
+	decrypt_key: SessionKey,
+	last_seen: Instant,
+}
+
+/// WHOAREYOU we sent, waiting for the handshake.
+struct Challenge {
+	data: Bytes,
+	sent_at: Instant,
+}
+
+/// Message to a node we have no session with, waiting for its WHOAREYOU.
+struct PendingHandshake {
+	contact: Contact,
+	nonce: Nonce,
+	message: Bytes,
+	sent_at: Instant,
+}
+
+struct Request {
+	node: H256,
+	sent_at: Instant,
+	responses: usize,
+}
+
+/// Unmasked packet header.
+struct Header {
+	flag: u8,
+	nonce: Nonce,
+	authdata: Bytes,
+	/// Masking IV followed by the unmasked header, authenticated along with the message.
+	data: Bytes,
+}
+
+pub struct Discv5 {
+	key: KeyPair,
+	hash: H256,
+	endpoint: NodeEndpoint,
+	record: Enr,
+	records: HashMap<H256, Enr>,
+	candidates: VecDeque<Contact>,
+	sessions: HashMap<H256, Session>,
+	challenges: HashMap<H256, Challenge>,
+	pending: HashMap<H256, PendingHandshake>,
+	requests: HashMap<u64, Request>,
+	next_request_id: u64,
+	discovered: Vec<Enr>,
+	send_queue: VecDeque<Datagram>,
+	ip_filter: IpFilter,
+	enr_filter: Option<Arc<EnrFilter>>,
+}
+
+impl Discv5 {
+	pub fn new(key: &KeyPair, endpoint: NodeEndpoint, ip_filter: IpFilter) -> Discv5 {
+		// Starting from the current time keeps the sequence number growing across restarts.
+		let seq = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+		let record = Enr::new(key, seq, &endpoint, Vec::new())
+			.expect("signing with a valid key pair can't fail; qed");
+		Discv5 {
+			key: key.clone(),
+			hash: keccak(key.public()),
+			endpoint,
+			record,
+			records: HashMap::new(),
+			candidates: VecDeque::new(),
+			sessions: HashMap::new(),
+			challenges: HashMap::new(),
+			pending: HashMap::new(),
+			requests: HashMap::new(),
+			next_request_id: 0,
+			discovered: Vec::new(),
+			send_queue: VecDeque::new(),
+			ip_filter,
+			enr_filter: None,
+		}
+	}
+
+	/// Our own node record.
+	pub fn record(&self) -> &Enr {
+		&self.record
+	}
+
+	/// Set the filter advertising an entry in our record and checking the records of discovered nodes.
+	pub fn set_enr_filter(&mut self, filter: Arc<EnrFilter>) {
+		self.enr_filter = Some(filter);
+		self.update_record();
+	}
+
+	/// Refresh the entry advertised on behalf of the filter, signing a new record if it changed.
+	pub fn update_record(&mut self) {
+		let (key, value) = match self.enr_filter {
+			Some(ref filter) => (filter.key().to_vec(), filter.local_value()),
+			None => return,
+		};
+		if self.record.get(&key) == Some(&value[..]) {
+			return;
+		}
+		match Enr::new(&self.key, self.record.seq() + 1, &self.endpoint, vec![(key, value)]) {
+			Ok(record) => {
+				trace!(target: "discovery", "Updated node record, seq={}", record.seq());
+				self.record = record;
+			},
+			Err(e) => warn!(target: "discovery", "Error signing node record: {:?}", e),
+		}
+	}
+
+	/// Add a known record to the table without reporting it.
+	pub fn add_record(&mut self, record: Enr) {
+		let hash = record.node_hash();
+		if hash != self.hash && (self.records.len() < MAX_RECORDS || self.records.contains_key(&hash)) {
+			self.records.insert(hash, record);
+		}
+	}
+
+	/// Queue a node found through v4 discovery, to be asked for its record.
+	pub fn add_candidate(&mut self, entry: &NodeEntry) {
+		let contact = Contact::new(entry);
+		if contact.hash == self.hash || self.records.contains_key(&contact.hash) || self.candidates.len() >= MAX_CANDIDATES {
+			return;
+		}
+		if self.candidates.iter().any(|c| c.hash == contact.hash) {
+			return;
+		}
+		self.candidates.push_back(contact);
+	}
+
+	/// Expire requests and contact a few candidates.
+	pub fn round(&mut self) {
+		self.check_expired(Instant::now());
+		for _ in 0..ALPHA {
+			let contact = match self.candidates.pop_front() {
+				Some(contact) => contact,
+				None => break,
+			};
+			// distance 0 returns the record of the node itself
+			if let Err(e) = self.find_node(&contact, &[0]) {
+				warn!(target: "discovery", "Error sending v5 FindNode to {:?}: {:?}", contact.address, e);
+			}
+		}
+	}
+
+	/// Walk towards a random target, and look for nodes advertising the filter's topic.
+	pub fn lookup(&mut self) {
+		let target = H256::random();
+		let mut nearest: Vec<Contact> = self.records.values()
+			.filter_map(|r| r.node_entry())
+			.map(|entry| Contact::new(&entry))
+			.collect();
+		nearest.sort_unstable_by_key(|c| c.hash ^ target);
+		nearest.truncate(ALPHA);
+
+		for contact in &nearest {
+			let distance = log_distance(&target, &contact.hash).unwrap_or(1);
+			let distances: Vec<u32> = [distance, distance + 1, distance - 1].iter()
+				.cloned()
+				.filter(|d| *d >= 1 && *d <= 256)
+				.collect();
+			if let Err(e) = self.find_node(contact, &distances) {
+				warn!(target: "discovery", "Error sending v5 FindNode to {:?}: {:?}", contact.address, e);
+			}
+		}
+
+		let topic = self.enr_filter.as_ref().map(|f| keccak(f.key()));
+		if let (Some(topic), Some(contact)) = (topic, nearest.first()) {
+			if let Err(e) = self.topic_query(contact, &topic) {
+				warn!(target: "discovery", "Error sending v5 TopicQuery to {:?}: {:?}", contact.address, e);
+			}
+		}
+	}
+
+	pub fn any_sends_queued(&self) -> bool {
+		!self.send_queue.is_empty()
+	}
+
+	pub fn dequeue_send(&mut self) -> Option<Datagram> {
+		self.send_queue.pop_front()
+	}
+
+	pub fn on_packet(&mut self, packet: &[u8], from: SocketAddr) -> Result<Option<TableUpdates>, Error> {
+		let (header, message) = self.decode_header(packet)?;
+		match header.flag {
+			FLAG_MESSAGE => self.on_message_packet(&header, message, from)?,
+			FLAG_WHOAREYOU => self.on_whoareyou(&header, from)?,
+			FLAG_HANDSHAKE => self.on_handshake(&header, message, from)?,
+			_ => return Err(ErrorKind::BadProtocol.into()),
+		}
+		Ok(self.take_updates())
+	}
+
+	fn take_updates(&mut self) -> Option<TableUpdates> {
+		if self.discovered.is_empty() {
+			return None;
+		}
+		let mut added = HashMap::new();
+		let mut records = HashMap::new();
+		for record in self.discovered.drain(..) {
+			if let Some(entry) = record.node_entry() {
+				added.insert(entry.id, entry);
+				records.insert(*record.id(), record);
+			}
+		}
+		Some(TableUpdates { added, removed: HashSet::new(), records })
+	}
+
+	fn decode_header<'p>(&self, packet: &'p [u8]) -> Result<(Header, &'p [u8]), Error> {
+		if packet.len() < MIN_PACKET_SIZE || packet.len() > MAX_DATAGRAM_SIZE {
+			return Err(ErrorKind::BadProtocol.into());
+		}
+		let masking_key = &self.hash[0..16];
+		let masking_iv = &packet[0..MASKING_IV_SIZE];
+
+		let mut static_header = [0u8; STATIC_HEADER_SIZE];
+		aes::decrypt_128_ctr(masking_key, masking_iv, &packet[MASKING_IV_SIZE..(MASKING_IV_SIZE + STATIC_HEADER_SIZE)], &mut static_header)?;
+		if &static_header[0..6] != PROTOCOL_ID || static_header[6..8] != [(VERSION >> 8) as u8, VERSION as u8] {
+			return Err(ErrorKind::BadProtocol.into());
+		}
+		let authdata_size = ((static_header[21] as usize) << 8) | static_header[22] as usize;
+		let header_end = MASKING_IV_SIZE + STATIC_HEADER_SIZE + authdata_size;
+		if packet.len() < header_end {
+			return Err(ErrorKind::BadProtocol.into());
+		}
+
+		let mut data = vec![0u8; header_end];
+		data[0..MASKING_IV_SIZE].copy_from_slice(masking_iv);
+		aes::decrypt_128_ctr(masking_key, masking_iv, &packet[MASKING_IV_SIZE..header_end], &mut data[MASKING_IV_SIZE..])?;
+		let mut nonce = [0u8; NONCE_SIZE];
+		nonce.copy_from_slice(&static_header[9..21]);
+		let header = Header {
+			flag: static_header[8],
+			nonce,
+			authdata: data[(MASKING_IV_SIZE + STATIC_HEADER_SIZE)..].to_vec(),
+			data,
+		};
+		Ok((header, &packet[header_end..]))
+	}
+
+	fn on_message_packet(&mut self, header: &Header, message: &[u8], from: SocketAddr) -> Result<(), Error> {
+		if header.authdata.len() != 32 {
+			return Err(ErrorKind::BadProtocol.into());
+		}
+		let src = H256::from_slice(&header.authdata);
+		let plain = match self.sessions.get(&src) {
+			Some(session) => decrypt_message(&session.decrypt_key, header, message).ok(),
+			None => None,
+		};
+		match plain {
+			Some(plain) => {
+				if let Some(session) = self.sessions.get_mut(&src) {
+					session.last_seen = Instant::now();
+					session.contact.address = from;
+				}
+				self.on_message(&src, &plain, from)
+			},
+			None => {
+				trace!(target: "discovery", "No v5 session with {:?}, sending WhoAreYou", from);
+				self.send_whoareyou(src, &header.nonce, from)
+			},
+		}
+	}
+
+	fn send_whoareyou(&mut self, src: H256, nonce: &Nonce, address: SocketAddr) -> Result<(), Error> {
+		if self.challenges.contains_key(&src) {
+			return Ok(());
+		}
+		let mut authdata = vec![0u8; ID_NONCE_SIZE + 8];
+		rand::thread_rng().fill_bytes(&mut authdata[0..ID_NONCE_SIZE]);
+		// ask for the record unless we already know it
+		let seq = self.records.get(&src).map_or(0, |r| r.seq());
+		for i in 0..8 {
+			authdata[ID_NONCE_SIZE + i] = (seq >> (56 - 8 * i)) as u8;
+		}
+		let (packet, data) = assemble_packet(&src, FLAG_WHOAREYOU, nonce, &authdata, None)?;
+		self.challenges.insert(src, Challenge { data, sent_at: Instant::now() });
+		self.send_to(packet, address);
+		Ok(())
+	}
+
+	fn on_whoareyou(&mut self, header: &Header, from: SocketAddr) -> Result<(), Error> {
+		if header.authdata.len() != ID_NONCE_SIZE + 8 {
+			return Err(ErrorKind::BadProtocol.into());
+		}
+		let dest = match self.pending.iter().find(|&(_, p)| p.nonce == header.nonce && p.contact.address == from) {
+			Some((dest, _)) => *dest,
+			None => {
+				debug!(target: "discovery", "Unexpected v5 WhoAreYou from {:?}", from);
+				return Ok(());
+			},
+		};
+		let pending = self.pending.remove(&dest).expect("found in the map above; qed");
+		let known_seq = header.authdata[ID_NONCE_SIZE..].iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+
+		let ephemeral = Random.generate()?;
+		let (initiator_key, recipient_key) = derive_keys(ephemeral.secret(), &pending.contact.id, &self.hash, &dest, &header.data)?;
+		let ephemeral_public = public_to_compressed(ephemeral.public())?;
+		let signature = sign(self.key.secret(), &id_proof_hash(&header.data, &ephemeral_public, &dest))?;
+
+		let mut authdata = self.hash.to_vec();
+		authdata.push(64);
+		authdata.push(ephemeral_public.len() as u8);
+		authdata.extend_from_slice(&signature[0..64]);
+		authdata.extend_from_slice(&ephemeral_public);
+		if known_seq < self.record.seq() {
+			authdata.extend_from_slice(&::rlp::encode(&self.record));
+		}
+		let (packet, _) = assemble_packet(&dest, FLAG_HANDSHAKE, &random_nonce(), &authdata, Some((&initiator_key, &pending.message)))?;
+
+		trace!(target: "discovery", "Established v5 session with {:?}", from);
+		self.sessions.insert(dest, Session {
+			contact: pending.contact,
+			encrypt_key: initiator_key,
+			decrypt_key: recipient_key,
+			last_seen: Instant::now(),
+		});
+		self.send_to(packet, from);
+		Ok(())
+	}
+
+	fn on_handshake(&mut self, header: &Header, message: &[u8], from: SocketAddr) -> Result<(), Error> {
+		let authdata = &header.authdata;
+		if authdata.len() < 34 {
+			return Err(ErrorKind::BadProtocol.into());
+		}
+		let src = H256::from_slice(&authdata[0..32]);
+		let (signature_size, key_size) = (authdata[32] as usize, authdata[33] as usize);
+		if signature_size != 64 || key_size != 33 || authdata.len() < 34 + signature_size + key_size {
+			return Err(ErrorKind::BadProtocol.into());
+		}
+		let id_signature = &authdata[34..98];
+		let ephemeral_public = &authdata[98..131];
+		let challenge = match self.challenges.remove(&src) {
+			Some(challenge) => challenge,
+			None => {
+				debug!(target: "discovery", "Unexpected v5 handshake from {:?}", from);
+				return Ok(());
+			},
+		};
+
+		let record = if authdata.len() > 131 {
+			Some(::rlp::decode::<Enr>(&authdata[131..])?)
+		} else {
+			self.records.get(&src).cloned()
+		};
+		let record = match record {
+			Some(ref record) if record.node_hash() == src => record.clone(),
+			_ => return Err(ErrorKind::Auth.into()),
+		};
+		let signature = Signature::from_rsv(&H256::from_slice(&id_signature[0..32]), &H256::from_slice(&id_signature[32..64]), 0);
+		if !verify_public(record.id(), &signature, &id_proof_hash(&challenge.data, ephemeral_public, &self.hash))? {
+			return Err(ErrorKind::Auth.into());
+		}
+
+		let ephemeral_public = public_from_compressed(ephemeral_public)?;
+		let (initiator_key, recipient_key) = derive_keys(self.key.secret(), &ephemeral_public, &src, &self.hash, &challenge.data)?;
+		let plain = decrypt_message(&initiator_key, header, message)?;
+
+		trace!(target: "discovery", "Established v5 session with {:?}", from);
+		let contact = Contact { id: *record.id(), hash: src, address: from };
+		self.sessions.insert(src, Session {
+			contact,
+			encrypt_key: recipient_key,
+			decrypt_key: initiator_key,
+			last_seen: Instant::now(),
+		});
+		self.note_record(record);
+		self.on_message(&src, &plain, from)
+	}
+
+	fn on_message(&mut self, src: &H256, plain: &[u8], from: SocketAddr) -> Result<(), Error> {
+		if plain.is_empty() {
+			return Err(ErrorKind::BadProtocol.into());
+		}
+		let rlp = Rlp::new(&plain[1..]);
+		match plain[0] {
+			MESSAGE_PING => self.on_ping(src, &rlp, from),
+			// we don't send pings, sessions are refreshed by the requests themselves
+			MESSAGE_PONG => Ok(()),
+			MESSAGE_FIND_NODE => self.on_find_node(src, &rlp),
+			MESSAGE_NODES => self.on_nodes(src, &rlp),
+			MESSAGE_TOPIC_QUERY => self.on_topic_query(src, &rlp),
+			id => {
+				debug!(target: "discovery", "Unknown v5 message: {}", id);
+				Ok(())
+			},
+		}
+	}
+
+	fn on_ping(&mut self, src: &H256, rlp: &Rlp, from: SocketAddr) -> Result<(), Error> {
+		let request_id: Bytes = rlp.val_at(0)?;
+		let seq: u64 = rlp.val_at(1)?;
+		let mut response = RlpStream::new_list(4);
+		response.append(&request_id);
+		response.append(&self.record.seq());
+		match from.ip() {
+			IpAddr::V4(ip) => response.append(&ip.octets().to_vec()),
+			IpAddr::V6(ip) => response.append(&ip.octets().to_vec()),
+		};
+		response.append(&from.port());
+		self.reply(src, message(MESSAGE_PONG, &response))?;
+
+		if self.records.get(src).map_or(true, |r| r.seq() < seq) {
+			let contact = self.sessions.get(src).map(|s| s.contact.clone());
+			if let Some(contact) = contact {
+				self.find_node(&contact, &[0])?;
+			}
+		}
+		Ok(())
+	}
+
+	fn on_find_node(&mut self, src: &H256, rlp: &Rlp) -> Result<(), Error> {
+		let request_id: Bytes = rlp.val_at(0)?;
+		let distances: Vec<u32> = rlp.list_at(1)?;
+		let mut records = Vec::new();
+		for distance in distances {
+			if records.len() >= MAX_NODES_RESPONSE {
+				break;
+			}
+			if distance == 0 {
+				records.push(self.record.clone());
+			} else {
+				records.extend(self.records.values()
+					.filter(|r| log_distance(&self.hash, &r.node_hash()) == Some(distance))
+					.cloned());
+			}
+		}
+		records.truncate(MAX_NODES_RESPONSE);
+		self.send_nodes(src, &request_id, &records)
+	}
+
+	fn on_topic_query(&mut self, src: &H256, rlp: &Rlp) -> Result<(), Error> {
+		let request_id: Bytes = rlp.val_at(0)?;
+		let topic: H256 = rlp.val_at(1)?;
+		let mut records: Vec<Enr> = self.records.values()
+			.filter(|r| r.has_topic(&topic))
+			.take(MAX_NODES_RESPONSE)
+			.cloned()
+			.collect();
+		if records.len() < MAX_NODES_RESPONSE && self.record.has_topic(&topic) {
+			records.push(self.record.clone());
+		}
+		self.send_nodes(src, &request_id, &records)
+	}
+
+	fn send_nodes(&mut self, src: &H256, request_id: &Bytes, records: &[Enr]) -> Result<(), Error> {
+		let packets: Vec<&[Enr]> = if records.is_empty() {
+			vec![records]
+		} else {
+			records.chunks(MAX_NODES_PER_PACKET).collect()
+		};
+		let total = packets.len() as u64;
+		for records in packets {
+			let mut response = RlpStream::new_list(3);
+			response.append(request_id);
+			response.append(&total);
+			response.append_list(records);
+			self.reply(src, message(MESSAGE_NODES, &response))?;
+		}
+		Ok(())
+	}
+
+	fn on_nodes(&mut self, src: &H256, rlp: &Rlp) -> Result<(), Error> {
+		let request_id: u64 = rlp.val_at(0)?;
+		let total: usize = rlp.val_at(1)?;
+		let done = match self.requests.get_mut(&request_id) {
+			Some(request) => {
+				if request.node != *src {
+					debug!(target: "discovery", "Unexpected v5 Nodes from {:#x}", src);
+					return Ok(());
+				}
+				request.responses += 1;
+				request.responses >= total || request.responses >= MAX_NODES_PACKETS
+			},
+			None => {
+				debug!(target: "discovery", "Unexpected v5 Nodes from {:#x}", src);
+				return Ok(());
+			},
+		};
+		if done {
+			self.requests.remove(&request_id);
+		}
+
+		for r in rlp.at(2)?.iter().take(MAX_NODES_PER_PACKET) {
+			match r.as_val::<Enr>() {
+				Ok(record) => self.note_record(record),
+				Err(e) => debug!(target: "discovery", "Invalid record in v5 Nodes from {:#x}: {:?}", src, e),
+			}
+		}
+		Ok(())
+	}
+
+	/// Store a record and report it, unless the filter rejects it.
+	fn note_record(&mut self, record: Enr) {
+		let hash = record.node_hash();
+		if hash == self.hash || self.records.get(&hash).map_or(false, |r| r.seq() >= record.seq()) {
+			return;
+		}
+		match record.endpoint() {
+			Some(ref endpoint) if endpoint.is_valid() && endpoint.is_allowed(&self.ip_filter) => {},
+			_ => return,
+		}
+		self.candidates.retain(|c| c.hash != hash);
+		let allowed = self.enr_filter.as_ref().map_or(true, |filter| {
+			record.get(filter.key()).map_or(false, |value| filter.value_allowed(value))
+		});
+		if allowed {
+			self.discovered.push(record.clone());
+		} else {
+			trace!(target: "discovery", "Skipping node {:#x} with incompatible record", record.id());
+		}
+		self.add_record(record);
+	}
+
+	fn find_node(&mut self, contact: &Contact, distances: &[u32]) -> Result<(), Error> {
+		let request_id = self.new_request(contact);
+		let mut request = RlpStream::new_list(2);
+		request.append(&request_id);
+		request.append_list(distances);
+		self.send_message(contact, message(MESSAGE_FIND_NODE, &request))
+	}
+
+	fn topic_query(&mut self, contact: &Contact, topic: &H256) -> Result<(), Error> {
+		let request_id = self.new_request(contact);
+		let mut request = RlpStream::new_list(2);
+		request.append(&request_id);
+		request.append(topic);
+		self.send_message(contact, message(MESSAGE_TOPIC_QUERY, &request))
+	}
+
+	fn new_request(&mut self, contact: &Contact) -> u64 {
+		let request_id = self.next_request_id;
+		self.next_request_id += 1;
+		self.requests.insert(request_id, Request { node: contact.hash, sent_at: Instant::now(), responses: 0 });
+		request_id
+	}
+
+	fn reply(&mut self, dest: &H256, message: Bytes) -> Result<(), Error> {
+		let contact = match self.sessions.get(dest) {
+			Some(session) => session.contact.clone(),
+			None => return Ok(()),
+		};
+		self.send_message(&contact, message)
+	}
+
+	fn send_message(&mut self, contact: &Contact, message: Bytes) -> Result<(), Error> {
+		let key = self.sessions.get(&contact.hash).map(|s| s.encrypt_key);
+		let packet = match key {
+			Some(key) => assemble_packet(&contact.hash, FLAG_MESSAGE, &random_nonce(), &self.hash, Some((&key, &message)))?.0,
+			None => {
+				if self.pending.contains_key(&contact.hash) {
+					trace!(target: "discovery", "Dropping v5 message to {:?}, handshake in progress", contact.address);
+					return Ok(());
+				}
+				// A packet the remote can't decrypt makes it send a WHOAREYOU challenge.
+				let nonce = random_nonce();
+				let mut random_key = [0u8; 16];
+				let mut random_message = [0u8; 20];
+				rand::thread_rng().fill_bytes(&mut random_key);
+				rand::thread_rng().fill_bytes(&mut random_message);
+				let (packet, _) = assemble_packet(&contact.hash, FLAG_MESSAGE, &nonce, &self.hash, Some((&random_key, &random_message)))?;
+				self.pending.insert(contact.hash, PendingHandshake {
+					contact: contact.clone(),
+					nonce,
+					message,
+					sent_at: Instant::now(),
+				});
+				packet
+			},
+		};
+		self.send_to(packet, contact.address);
+		Ok(())
+	}
+
+	fn send_to(&mut self, payload: Bytes, address: SocketAddr) {
+		self.send_queue.push_back(Datagram { payload, address });
+	}
+
+	fn check_expired(&mut self, now: Instant) {
+		self.requests.retain(|_, r| now.duration_since(r.sent_at) < REQUEST_TIMEOUT);
+		self.pending.retain(|_, p| now.duration_since(p.sent_at) < HANDSHAKE_TIMEOUT);
+		self.challenges.retain(|_, c| now.duration_since(c.sent_at) < HANDSHAKE_TIMEOUT);
+		self.sessions.retain(|_, s| now.duration_since(s.last_seen) < SESSION_TIMEOUT);
+	}
+}
+
+/// Log2 of the XOR distance between two node ids, `None` if they are equal.
+fn log_distance(a: &H256, b: &H256) -> Option<u32> {
+	let distance = *a ^ *b;
+	let leading_zeros = distance.iter().position(|&byte| byte != 0)
+		.map(|i| i as u32 * 8 + distance[i].leading_zeros())?;
+	Some(256 - leading_zeros)
+}
+
+fn message(id: u8, rlp: &RlpStream) -> Bytes {
+	let mut message = vec![id];
+	message.extend_from_slice(rlp.as_raw());
+	message
+}
+
+fn random_nonce() -> Nonce {
+	let mut nonce = [0u8; NONCE_SIZE];
+	rand::thread_rng().fill_bytes(&mut nonce);
+	nonce
+}
+
+/// Assemble a packet to `dest`: masking IV, masked header and the message encrypted with the given key.
+/// Also returns the masking IV followed by the unmasked header.
+fn assemble_packet(dest: &H256, flag: u8, nonce: &Nonce, authdata: &[u8], message: Option<(&SessionKey, &[u8])>) -> Result<(Bytes, Bytes), Error> {
+	let mut data = vec![0u8; MASKING_IV_SIZE];
+	rand::thread_rng().fill_bytes(&mut data);
+	data.extend_from_slice(PROTOCOL_ID);
+	data.push((VERSION >> 8) as u8);
+	data.push(VERSION as u8);
+	data.push(flag);
+	data.extend_from_slice(nonce);
+	data.push((authdata.len() >> 8) as u8);
+	data.push(authdata.len() as u8);
+	data.extend_from_slice(authdata);
+
+	let mut packet = vec![0u8; data.len()];
+	packet[0..MASKING_IV_SIZE].copy_from_slice(&data[0..MASKING_IV_SIZE]);
+	aes::encrypt_128_ctr(&dest[0..16], &data[0..MASKING_IV_SIZE], &data[MASKING_IV_SIZE..], &mut packet[MASKING_IV_SIZE..])?;
+	if let Some((key, message)) = message {
+		let mut encryptor = Encryptor::aes_128_gcm(key)?;
+		encryptor.associate(&data);
+		packet.extend(encryptor.encrypt(nonce, message.to_vec())?);
+	}
+	Ok((packet, data))
+}
+
+fn decrypt_message(key: &SessionKey, header: &Header, message: &[u8]) -> Result<Bytes, Error> {
+	let mut decryptor = Decryptor::aes_128_gcm(key)?;
+	decryptor.associate(&header.data);
+	Ok(decryptor.decrypt(&header.nonce, message.to_vec())?)
+}
+
+/// Derive the initiator and recipient session keys from the ECDH of `secret` and `public`
+/// with HKDF-SHA256, salted with the challenge.
+fn derive_keys(secret: &Secret, public: &Public, initiator: &H256, recipient: &H256, challenge: &[u8]) -> Result<(SessionKey, SessionKey), Error> {
+	let mut shared = *public;
+	public_mul_secret(&mut shared, secret)?;
+	let shared = public_to_compressed(&shared)?;
+
+	let prk = hmac::sign(&hmac::SigKey::sha256(challenge), &shared);
+	let mut info = KEY_AGREEMENT_INFO.to_vec();
+	info.extend_from_slice(initiator);
+	info.extend_from_slice(recipient);
+	info.push(1);
+	let key_data = hmac::sign(&hmac::SigKey::sha256(&prk[..]), &info);
+
+	let mut initiator_key = [0u8; 16];
+	let mut recipient_key = [0u8; 16];
+	initiator_key.copy_from_slice(&key_data[0..16]);
+	recipient_key.copy_from_slice(&key_data[16..32]);
+	Ok((initiator_key, recipient_key))
+}
+
+/// Hash signed by the initiator to prove its identity in the handshake.
+fn id_proof_hash(challenge: &[u8], ephemeral_public: &[u8], recipient: &H256) -> H256 {
+	let mut input = ID_PROOF_PREFIX.to_vec();
+	input.extend_from_slice(challenge);
+	input.extend_from_slice(ephemeral_public);
+	input.extend_from_slice(recipient);
+	H256::from_slice(&digest::sha256(&input)[..])
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::str::FromStr;
+
+	struct TestFilter(Vec<u8>);
+
+	impl EnrFilter for TestFilter {
+		fn key(&self) -> &[u8] { b"eth" }
+		fn local_value(&self) -> Vec<u8> { ::rlp::encode(&self.0).into_vec() }
+		fn value_allowed(&self, value: &[u8]) -> bool { value == &self.local_value()[..] }
+	}
+
+	fn node(port: u16) -> (Discv5, NodeEntry) {
+		let key = Random.generate().unwrap();
+		let endpoint = NodeEndpoint { address: SocketAddr::from_str(&format!("127.0.0.1:{}", port)).unwrap(), udp_port: port };
+		let entry = NodeEntry { id: *key.public(), endpoint: endpoint.clone() };
+		(Discv5::new(&key, endpoint, IpFilter::default()), entry)
+	}
+
+	/// Deliver all queued packets of `from` to `to`, returning the last table updates.
+	fn deliver(from: &mut Discv5, from_entry: &NodeEntry, to: &mut Discv5) -> Option<TableUpdates> {
+		let mut updates = None;
+		while let Some(datagram) = from.dequeue_send() {
+			if let Some(u) = to.on_packet(&datagram.payload, from_entry.endpoint.udp_address()).unwrap() {
+				updates = Some(u);
+			}
+		}
+		updates
+	}
+
+	#[test]
+	fn log_distance_of_ids() {
+		let a = H256::zero();
+		let mut b = H256::zero();
+		assert_eq!(log_distance(&a, &b), None);
+		b[31] = 1;
+		assert_eq!(log_distance(&a, &b), Some(1));
+		b[0] = 0x80;
+		assert_eq!(log_distance(&a, &b), Some(256));
+	}
+
+	#[test]
+	fn handshake_and_find_node() {
+		let (mut a, a_entry) = node(40501);
+		let (mut b, b_entry) = node(40502);
+
+		a.add_candidate(&b_entry);
+		a.round();
+		// random packet, WHOAREYOU, handshake with FINDNODE
+		assert!(deliver(&mut a, &a_entry, &mut b).is_none());
+		assert!(deliver(&mut b, &b_entry, &mut a).is_none());
+		let updates = deliver(&mut a, &a_entry, &mut b).expect("b learns the record of a from the handshake");
+		assert_eq!(updates.records[&a_entry.id], a.record().clone());
+
+		// NODES with the record of b
+		let updates = deliver(&mut b, &b_entry, &mut a).expect("a receives the record of b");
+		assert_eq!(updates.added[&b_entry.id].endpoint, b_entry.endpoint);
+		assert_eq!(updates.records[&b_entry.id], b.record().clone());
+		assert!(a.requests.is_empty());
+
+		// established sessions are used for further requests
+		let contact = Contact::new(&b_entry);
+		a.find_node(&contact, &[0]).unwrap();
+		deliver(&mut a, &a_entry, &mut b);
+		assert!(b.challenges.is_empty());
+	}
+
+	#[test]
+	fn filters_incompatible_records() {
+		let (mut a, a_entry) = node(40503);
+		let (mut b, b_entry) = node(40504);
+		a.set_enr_filter(Arc::new(TestFilter(b"mainnet".to_vec())));
+		b.set_enr_filter(Arc::new(TestFilter(b"testnet".to_vec())));
+		assert!(a.record().get(b"eth").is_some());
+
+		a.add_candidate(&b_entry);
+		a.round();
+		deliver(&mut a, &a_entry, &mut b);
+		deliver(&mut b, &b_entry, &mut a);
+		assert!(deliver(&mut a, &a_entry, &mut b).is_none());
+		assert!(deliver(&mut b, &b_entry, &mut a).is_none());
+		// the record is still kept to answer lookups
+		assert!(a.records.contains_key(&keccak(&b_entry.id)));
+	}
+}
@@ -0,0 +1,766 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Node lists published in DNS (EIP-1459).
+//!
+//! A list is a merkle tree of TXT records below a domain, signed by the key named in its
+//! `enrtree://<key>@<domain>` URL. The root record at the domain points to a tree of node
+//! records and to a tree of links to other lists. Every other entry lives at a subdomain named
+//! after the hash of its content, so resolved entries never change and are kept between syncs.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, UdpSocket};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use ethereum_types::H256;
+use ethkey::{Public, Signature, verify_public};
+use ethkey::math::{public_from_compressed, public_to_compressed};
+use hash::keccak;
+use rand;
+use network::{Error, ErrorKind};
+use enr::Enr;
+
+const ROOT_PREFIX: &str = "enrtree-root:v1";
+const BRANCH_PREFIX: &str = "enrtree-branch:";
+const RECORD_PREFIX: &str = "enr:";
+const LINK_PREFIX: &str = "enrtree://";
+
+const MAX_ENTRIES: usize = 2000;	// Max tree entries visited in a sync, linked lists included.
+const DNS_TIMEOUT: Duration = Duration::from_secs(2);
+const DNS_PORT: u16 = 53;
+const DNS_TYPE_TXT: u16 = 16;
+// Public name servers, for when none are configured or found in `/etc/resolv.conf`.
+const FALLBACK_DNS_SERVERS: &[&str] = &["1.1.1.1", "8.8.8.8"];
+
+/// Location and signing key of a node list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnrTreeUrl {
+	pub public: Public,
+	pub domain: String,
+}
+
+impl FromStr for EnrTreeUrl {
+	type Err = Error;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		if !s.starts_with(LINK_PREFIX) {
+			return Err(ErrorKind::AddressParse.into());
+		}
+		let mut parts = s[LINK_PREFIX.len()..].splitn(2, '@');
+		let (key, domain) = match (parts.next(), parts.next()) {
+			(Some(key), Some(domain)) if !domain.is_empty() => (key, domain),
+			_ => return Err(ErrorKind::AddressParse.into()),
+		};
+		let key = decode_base32(key).ok_or(ErrorKind::AddressParse)?;
+		let public = public_from_compressed(&key).map_err(|_| ErrorKind::InvalidNodeId)?;
+		Ok(EnrTreeUrl { public, domain: domain.to_owned() })
+	}
+}
+
+impl fmt::Display for EnrTreeUrl {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let key = public_to_compressed(&self.public).map_err(|_| fmt::Error)?;
+		write!(f, "{}{}@{}", LINK_PREFIX, encode_base32(&key), self.domain)
+	}
+}
+
+/// Check if a node list url is valid.
+pub fn validate_enrtree_url(url: &str) -> Option<Error> {
+	EnrTreeUrl::from_str(url).err()
+}
+
+/// Source of TXT records.
+pub trait TxtResolver: Send + Sync {
+	/// All TXT records of a name. A name without records resolves to an empty list.
+	fn txt(&self, name: &str) -> Result<Vec<String>, Error>;
+}
+
+/// Check if a name server address, an IP address with an optional port, is valid.
+pub fn validate_dns_server(server: &str) -> Option<Error> {
+	match parse_dns_server(server) {
+		Some(_) => None,
+		None => Some(ErrorKind::AddressParse.into()),
+	}
+}
+
+fn parse_dns_server(server: &str) -> Option<SocketAddr> {
+	server.parse::<SocketAddr>().ok()
+		.or_else(|| server.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, DNS_PORT)))
+}
+
+/// Resolves TXT records with the configured name servers, else those of `/etc/resolv.conf`,
+/// else public ones. The servers are asked in turn until one answers.
+pub struct SystemResolver {
+	servers: Vec<SocketAddr>,
+	timeout: Duration,
+}
+
+impl SystemResolver {
+	pub fn new(servers: &[String]) -> Result<SystemResolver, Error> {
+		let mut servers = servers.iter()
+			.map(|server| parse_dns_server(server).ok_or_else(|| Error::from(ErrorKind::AddressParse)))
+			.collect::<Result<Vec<_>, _>>()?;
+		if servers.is_empty() {
+			servers = match fs::read_to_string("/etc/resolv.conf") {
+				Ok(conf) => conf.lines()
+					.filter_map(|line| {
+						let mut parts = line.split_whitespace();
+						match (parts.next(), parts.next()) {
+							(Some("nameserver"), Some(ip)) => ip.parse::<IpAddr>().ok(),
+							_ => None,
+						}
+					})
+					.map(|ip| SocketAddr::new(ip, DNS_PORT))
+					.collect(),
+				Err(e) => {
+					debug!(target: "network", "Error reading /etc/resolv.conf: {:?}", e);
+					Vec::new()
+				},
+			};
+		}
+		if servers.is_empty() {
+			info!(target: "network", "No name servers found, resolving DNS node lists with {}", FALLBACK_DNS_SERVERS.join(", "));
+			servers = FALLBACK_DNS_SERVERS.iter().filter_map(|server| parse_dns_server(server)).collect();
+		}
+		Ok(SystemResolver { servers, timeout: DNS_TIMEOUT })
+	}
+
+	fn query(&self, server: SocketAddr, name: &str) -> Result<Vec<String>, Error> {
+		let id = rand::random::<u16>();
+		let query = txt_query(id, name)?;
+		let mut response = self.query_udp(server, id, &query)?;
+		// the full response only fits in a TCP message
+		if is_truncated(&response) {
+			trace!(target: "network", "Truncated DNS response for {} from {}, retrying over TCP", name, server);
+			response = self.query_tcp(server, &query)?;
+		}
+		parse_txt_response(id, &response)
+	}
+
+	fn query_udp(&self, server: SocketAddr, id: u16, query: &[u8]) -> Result<Vec<u8>, Error> {
+		let socket = UdpSocket::bind(if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+		socket.send_to(query, server)?;
+
+		// packets from elsewhere don't extend the time to wait for the answer
+		let deadline = Instant::now() + self.timeout;
+		let mut buf = [0u8; 4096];
+		loop {
+			let now = Instant::now();
+			if now >= deadline {
+				return Err(io::Error::new(io::ErrorKind::TimedOut, "DNS query timed out").into());
+			}
+			socket.set_read_timeout(Some(deadline - now))?;
+			let (len, from) = socket.recv_from(&mut buf)?;
+			if from == server && len >= 2 && buf[0..2] == [(id >> 8) as u8, id as u8] {
+				return Ok(buf[0..len].to_vec());
+			}
+		}
+	}
+
+	fn query_tcp(&self, server: SocketAddr, query: &[u8]) -> Result<Vec<u8>, Error> {
+		let mut stream = TcpStream::connect_timeout(&server, self.timeout)?;
+		stream.set_read_timeout(Some(self.timeout))?;
+		stream.set_write_timeout(Some(self.timeout))?;
+
+		// messages are prefixed with their length over TCP
+		let mut request = vec![(query.len() >> 8) as u8, query.len() as u8];
+		request.extend_from_slice(query);
+		stream.write_all(&request)?;
+
+		let mut len = [0u8; 2];
+		stream.read_exact(&mut len)?;
+		let mut response = vec![0u8; ((len[0] as usize) << 8) | len[1] as usize];
+		stream.read_exact(&mut response)?;
+		Ok(response)
+	}
+}
+
+impl TxtResolver for SystemResolver {
+	fn txt(&self, name: &str) -> Result<Vec<String>, Error> {
+		let mut result = Err(ErrorKind::AddressResolve(None).into());
+		for server in &self.servers {
+			result = self.query(*server, name);
+			match result {
+				Ok(_) => break,
+				Err(ref e) => debug!(target: "network", "Error resolving {} with {}: {:?}", name, server, e),
+			}
+		}
+		result
+	}
+}
+
+fn is_truncated(msg: &[u8]) -> bool {
+	msg.len() > 2 && msg[2] & 0x02 != 0
+}
+
+fn txt_query(id: u16, name: &str) -> Result<Vec<u8>, Error> {
+	// header: id, recursion desired, one question
+	let mut query = vec![(id >> 8) as u8, id as u8, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+	for label in name.split('.').filter(|label| !label.is_empty()) {
+		if label.len() > 63 {
+			return Err(ErrorKind::AddressParse.into());
+		}
+		query.push(label.len() as u8);
+		query.extend_from_slice(label.as_bytes());
+	}
+	query.push(0);
+	query.extend_from_slice(&[(DNS_TYPE_TXT >> 8) as u8, DNS_TYPE_TXT as u8, 0, 1]);
+	Ok(query)
+}
+
+fn parse_txt_response(id: u16, msg: &[u8]) -> Result<Vec<String>, Error> {
+	let read_u16 = |pos: usize| ((msg[pos] as u16) << 8) | msg[pos + 1] as u16;
+	if msg.len() < 12 || read_u16(0) != id {
+		return Err(ErrorKind::BadProtocol.into());
+	}
+	if is_truncated(msg) {
+		debug!(target: "network", "Truncated DNS response");
+		return Err(ErrorKind::BadProtocol.into());
+	}
+	match msg[3] & 0x0f {
+		0 => {},
+		// name does not exist
+		3 => return Ok(Vec::new()),
+		_ => return Err(ErrorKind::AddressResolve(None).into()),
+	}
+
+	let mut pos = 12;
+	for _ in 0..read_u16(4) {
+		pos = skip_name(msg, pos)? + 4;
+	}
+	let mut records = Vec::new();
+	for _ in 0..read_u16(6) {
+		pos = skip_name(msg, pos)?;
+		if pos + 10 > msg.len() {
+			return Err(ErrorKind::BadProtocol.into());
+		}
+		let (record_type, data_len) = (read_u16(pos), read_u16(pos + 8) as usize);
+		pos += 10;
+		if pos + data_len > msg.len() {
+			return Err(ErrorKind::BadProtocol.into());
+		}
+		if record_type == DNS_TYPE_TXT {
+			// a TXT record is a sequence of length-prefixed strings
+			let data = &msg[pos..(pos + data_len)];
+			let mut text = Vec::new();
+			let mut i = 0;
+			while i < data.len() {
+				let len = data[i] as usize;
+				if i + 1 + len > data.len() {
+					return Err(ErrorKind::BadProtocol.into());
+				}
+				text.extend_from_slice(&data[(i + 1)..(i + 1 + len)]);
+				i += 1 + len;
+			}
+			records.push(String::from_utf8(text).map_err(|_| ErrorKind::BadProtocol)?);
+		}
+		pos += data_len;
+	}
+	Ok(records)
+}
+
+fn skip_name(msg: &[u8], mut pos: usize) -> Result<usize, Error> {
+	loop {
+		match msg.get(pos) {
+			None => return Err(ErrorKind::BadProtocol.into()),
+			Some(&0) => return Ok(pos + 1),
+			// compression pointer
+			Some(&len) if len & 0xc0 == 0xc0 => return if pos + 2 <= msg.len() {
+				Ok(pos + 2)
+			} else {
+				Err(ErrorKind::BadProtocol.into())
+			},
+			Some(&len) => pos += 1 + len as usize,
+		}
+	}
+}
+
+struct Root {
+	records: String,
+	links: String,
+}
+
+impl Root {
+	fn parse(text: &str, public: &Public) -> Result<Root, Error> {
+		let signed_len = text.find(" sig=").ok_or(ErrorKind::BadProtocol)?;
+		let (mut records, mut links, mut signature) = (None, None, None);
+		let mut fields = text.split_whitespace();
+		if fields.next() != Some(ROOT_PREFIX) {
+			return Err(ErrorKind::BadProtocol.into());
+		}
+		for field in fields {
+			if field.starts_with("e=") {
+				records = Some(field[2..].to_owned());
+			} else if field.starts_with("l=") {
+				links = Some(field[2..].to_owned());
+			} else if field.starts_with("sig=") {
+				signature = decode_base64(&field[4..]);
+			}
+		}
+
+		let signature = match signature {
+			Some(ref sig) if sig.len() == 65 => {
+				let v = if sig[64] >= 27 { sig[64] - 27 } else { sig[64] };
+				Signature::from_rsv(&H256::from_slice(&sig[0..32]), &H256::from_slice(&sig[32..64]), v)
+			},
+			_ => return Err(ErrorKind::BadProtocol.into()),
+		};
+		if !verify_public(public, &signature, &keccak(&text[0..signed_len]))? {
+			return Err(ErrorKind::Auth.into());
+		}
+		match (records, links) {
+			(Some(records), Some(links)) => Ok(Root { records, links }),
+			_ => Err(ErrorKind::BadProtocol.into()),
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+enum Entry {
+	Branch(Vec<String>),
+	Record(Enr),
+	Link(EnrTreeUrl),
+}
+
+impl Entry {
+	fn parse(text: &str) -> Result<Entry, Error> {
+		if text.starts_with(BRANCH_PREFIX) {
+			let children = text[BRANCH_PREFIX.len()..].split(',')
+				.filter(|child| !child.is_empty())
+				.map(|child| child.to_owned())
+				.collect();
+			Ok(Entry::Branch(children))
+		} else if text.starts_with(RECORD_PREFIX) {
+			let data = decode_base64(&text[RECORD_PREFIX.len()..]).ok_or(ErrorKind::BadProtocol)?;
+			Ok(Entry::Record(::rlp::decode(&data)?))
+		} else if text.starts_with(LINK_PREFIX) {
+			Ok(Entry::Link(text.parse()?))
+		} else {
+			Err(ErrorKind::BadProtocol.into())
+		}
+	}
+}
+
+/// Results of a sync in progress.
+#[derive(Default)]
+struct SyncState {
+	entries: HashMap<String, Entry>,
+	records: Vec<Enr>,
+	links: Vec<EnrTreeUrl>,
+	visited: usize,
+}
+
+/// Node list published in DNS, with the entries resolved so far.
+pub struct DnsNodeList {
+	url: EnrTreeUrl,
+	resolver: Arc<TxtResolver>,
+	entries: HashMap<String, Entry>,
+}
+
+impl DnsNodeList {
+	pub fn new(url: EnrTreeUrl, resolver: Arc<TxtResolver>) -> DnsNodeList {
+		DnsNodeList {
+			url,
+			resolver,
+			entries: HashMap::new(),
+		}
+	}
+
+	pub fn url(&self) -> &EnrTreeUrl {
+		&self.url
+	}
+
+	/// Resolve the list and the lists it links to, returning all node records. Only the roots
+	/// and the entries not seen in the previous sync are queried.
+	pub fn sync(&mut self) -> Result<Vec<Enr>, Error> {
+		let mut state = SyncState::default();
+		let mut lists = vec![self.url.clone()];
+		let mut visited_lists = HashSet::new();
+		while let Some(url) = lists.pop() {
+			if !visited_lists.insert(url.domain.clone()) {
+				continue;
+			}
+			if let Err(e) = self.sync_list(&url, &mut state) {
+				// a broken link doesn't invalidate the rest
+				if url == self.url {
+					return Err(e);
+				}
+				debug!(target: "network", "Error syncing linked node list {}: {:?}", url.domain, e);
+			}
+			lists.extend(state.links.drain(..));
+		}
+		self.entries = state.entries;
+		Ok(state.records)
+	}
+
+	fn sync_list(&self, url: &EnrTreeUrl, state: &mut SyncState) -> Result<(), Error> {
+		let root = self.resolver.txt(&url.domain)?.into_iter()
+			.find(|text| text.starts_with(ROOT_PREFIX))
+			.ok_or(ErrorKind::BadProtocol)?;
+		let root = Root::parse(&root, &url.public)?;
+		self.walk(&url.domain, &root.records, false, state)?;
+		self.walk(&url.domain, &root.links, true, state)
+	}
+
+	/// Visit the tree below `hash`, collecting records or links.
+	fn walk(&self, domain: &str, hash: &str, links: bool, state: &mut SyncState) -> Result<(), Error> {
+		let mut pending = vec![hash.to_owned()];
+		while let Some(hash) = pending.pop() {
+			if state.visited >= MAX_ENTRIES {
+				warn!(target: "network", "Node list {} is too large, ignoring entries past {}", self.url.domain, MAX_ENTRIES);
+				return Ok(());
+			}
+			state.visited += 1;
+			match self.resolve_entry(domain, &hash, state)? {
+				Entry::Branch(children) => pending.extend(children),
+				Entry::Record(ref record) if !links => state.records.push(record.clone()),
+				Entry::Link(ref url) if links => state.links.push(url.clone()),
+				_ => debug!(target: "network", "Unexpected entry {} in node list {}", hash, domain),
+			}
+		}
+		Ok(())
+	}
+
+	fn resolve_entry(&self, domain: &str, hash: &str, state: &mut SyncState) -> Result<Entry, Error> {
+		let name = format!("{}.{}", hash, domain);
+		let known = self.entries.get(&name).or_else(|| state.entries.get(&name)).cloned();
+		let entry = match known {
+			Some(entry) => entry,
+			None => {
+				let expected = decode_base32(hash).ok_or(ErrorKind::BadProtocol)?;
+				if expected.len() < 12 || expected.len() > 32 {
+					return Err(ErrorKind::BadProtocol.into());
+				}
+				let text = self.resolver.txt(&name)?.into_iter()
+					.find(|text| keccak(text.as_bytes())[0..expected.len()] == expected[..])
+					.ok_or(ErrorKind::BadProtocol)?;
+				Entry::parse(&text)?
+			},
+		};
+		state.entries.insert(name, entry.clone());
+		Ok(entry)
+	}
+}
+
+/// Encode as unpadded base32 (RFC 4648).
+fn encode_base32(data: &[u8]) -> String {
+	encode_bits(data, 5, b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567")
+}
+
+fn encode_bits(data: &[u8], width: usize, alphabet: &[u8]) -> String {
+	let mut out = String::with_capacity((data.len() * 8 + width - 1) / width);
+	let (mut buffer, mut bits) = (0u32, 0);
+	for byte in data {
+		buffer = (buffer << 8) | *byte as u32;
+		bits += 8;
+		while bits >= width {
+			bits -= width;
+			out.push(alphabet[(buffer >> bits) as usize & ((1 << width) - 1)] as char);
+		}
+		buffer &= (1 << bits) - 1;
+	}
+	if bits > 0 {
+		out.push(alphabet[(buffer << (width - bits)) as usize & ((1 << width) - 1)] as char);
+	}
+	out
+}
+
+/// Decode unpadded base32 (RFC 4648), case insensitive.
+fn decode_base32(s: &str) -> Option<Vec<u8>> {
+	let mut out = Vec::with_capacity(s.len() * 5 / 8);
+	let (mut buffer, mut bits) = (0u32, 0);
+	for c in s.bytes() {
+		let value = match c {
+			b'A'...b'Z' => c - b'A',
+			b'a'...b'z' => c - b'a',
+			b'2'...b'7' => c - b'2' + 26,
+			_ => return None,
+		};
+		buffer = (buffer << 5) | value as u32;
+		bits += 5;
+		if bits >= 8 {
+			bits -= 8;
+			out.push((buffer >> bits) as u8);
+			buffer &= (1 << bits) - 1;
+		}
+	}
+	Some(out)
+}
+
+/// Decode unpadded URL-safe base64 (RFC 4648).
+fn decode_base64(s: &str) -> Option<Vec<u8>> {
+	let mut out = Vec::with_capacity(s.len() * 3 / 4);
+	let (mut buffer, mut bits) = (0u32, 0);
+	for c in s.trim_right_matches('=').bytes() {
+		let value = match c {
+			b'A'...b'Z' => c - b'A',
+			b'a'...b'z' => c - b'a' + 26,
+			b'0'...b'9' => c - b'0' + 52,
+			b'-' => 62,
+			b'_' => 63,
+			_ => return None,
+		};
+		buffer = (buffer << 6) | value as u32;
+		bits += 6;
+		if bits >= 8 {
+			bits -= 8;
+			out.push((buffer >> bits) as u8);
+			buffer &= (1 << bits) - 1;
+		}
+	}
+	Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::net::TcpListener;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::thread;
+	use parking_lot::RwLock;
+	use ethkey::{KeyPair, Random, Generator, sign};
+	use node_table::NodeEndpoint;
+
+	/// Local stand-in for DNS, counting queries.
+	#[derive(Default)]
+	struct TestResolver {
+		names: RwLock<HashMap<String, String>>,
+		queries: AtomicUsize,
+	}
+
+	impl TxtResolver for TestResolver {
+		fn txt(&self, name: &str) -> Result<Vec<String>, Error> {
+			self.queries.fetch_add(1, Ordering::SeqCst);
+			Ok(self.names.read().get(name).cloned().into_iter().collect())
+		}
+	}
+
+	fn encode_base64(data: &[u8]) -> String {
+		encode_bits(data, 6, b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_")
+	}
+
+	fn tree_url(key: &KeyPair, domain: &str) -> String {
+		EnrTreeUrl { public: *key.public(), domain: domain.to_owned() }.to_string()
+	}
+
+	/// Publish a signed tree of records and links with one branch for each, returning the URL.
+	fn publish(resolver: &TestResolver, key: &KeyPair, domain: &str, seq: u32, records: &[Enr], links: &[String]) -> String {
+		let mut names = resolver.names.write();
+		let (e, l) = {
+			let mut put = |text: String| {
+				let hash = encode_base32(&keccak(text.as_bytes())[0..16]);
+				names.insert(format!("{}.{}", hash, domain), text);
+				hash
+			};
+			let records: Vec<_> = records.iter().map(|r| put(format!("enr:{}", encode_base64(&::rlp::encode(r))))).collect();
+			let links: Vec<_> = links.iter().cloned().map(&mut put).collect();
+			(put(format!("enrtree-branch:{}", records.join(","))), put(format!("enrtree-branch:{}", links.join(","))))
+		};
+
+		let content = format!("enrtree-root:v1 e={} l={} seq={}", e, l, seq);
+		let signature = sign(key.secret(), &keccak(&content)).unwrap();
+		let mut sig = signature[0..64].to_vec();
+		sig.push(signature.v());
+		names.insert(domain.to_owned(), format!("{} sig={}", content, encode_base64(&sig)));
+		tree_url(key, domain)
+	}
+
+	// Response to a TXT query for `a.b` with the given answers, named by compression pointers.
+	fn txt_response(id: u16, flags: u8, answers: &[(u16, &[u8])]) -> Vec<u8> {
+		let mut msg = vec![(id >> 8) as u8, id as u8, 0x81, flags, 0, 1, 0, answers.len() as u8, 0, 0, 0, 0];
+		msg.extend_from_slice(&[1, b'a', 1, b'b', 0, 0, 16, 0, 1]);
+		for &(record_type, data) in answers {
+			msg.extend_from_slice(&[0xc0, 12, (record_type >> 8) as u8, record_type as u8, 0, 1, 0, 0, 0, 60, 0, data.len() as u8]);
+			msg.extend_from_slice(data);
+		}
+		msg
+	}
+
+	fn record(port: u16) -> Enr {
+		let endpoint = NodeEndpoint { address: SocketAddr::from_str(&format!("10.0.0.1:{}", port)).unwrap(), udp_port: port };
+		Enr::new(&Random.generate().unwrap(), 1, &endpoint, Vec::new()).unwrap()
+	}
+
+	#[test]
+	fn parses_tree_urls() {
+		let url: EnrTreeUrl = "enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@nodes.example.org".parse().unwrap();
+		assert_eq!(url.domain, "nodes.example.org");
+		assert_eq!(url.to_string(), "enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@nodes.example.org");
+
+		assert!(validate_enrtree_url("enode://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@nodes.example.org").is_some());
+		assert!(validate_enrtree_url("enrtree://AM5FCQLWIZX2QFPNJAP7VUERCCRNGRHWZG3YYHIUV7BVDQ5FDPRT2@").is_some());
+		assert!(validate_enrtree_url("enrtree://AM5FCQLWIZX2Q@nodes.example.org").is_some());
+	}
+
+	#[test]
+	fn base_decoding() {
+		let data = keccak(b"data");
+		assert_eq!(decode_base32(&encode_base32(&data)), Some(data.to_vec()));
+		assert_eq!(decode_base32(&encode_base32(&data).to_lowercase()), Some(data.to_vec()));
+		assert_eq!(decode_base64(&encode_base64(&data)), Some(data.to_vec()));
+		assert_eq!(decode_base32("A1"), None);
+		assert_eq!(decode_base64("A+"), None);
+	}
+
+	#[test]
+	fn parses_txt_responses() {
+		let msg = txt_response(7, 0x80, &[(DNS_TYPE_TXT, &b"\x04enr:\x03abc"[..]), (1, &[10u8, 0, 0, 1][..])]);
+		assert_eq!(parse_txt_response(7, &msg).unwrap(), vec!["enr:abc".to_owned()]);
+		assert!(parse_txt_response(8, &msg).is_err());
+		// name does not exist
+		assert_eq!(parse_txt_response(7, &txt_response(7, 0x83, &[])).unwrap(), Vec::<String>::new());
+	}
+
+	#[test]
+	fn rejects_truncated_and_short_responses() {
+		let mut msg = txt_response(7, 0x80, &[(DNS_TYPE_TXT, &b"\x04enr:\x03abc"[..])]);
+		for len in 0..msg.len() {
+			assert!(parse_txt_response(7, &msg[..len]).is_err(), "response cut at {} bytes", len);
+		}
+
+		// a string longer than its record
+		assert!(parse_txt_response(7, &txt_response(7, 0x80, &[(DNS_TYPE_TXT, &b"\x05enr:"[..])])).is_err());
+
+		msg[2] |= 0x02;
+		assert!(parse_txt_response(7, &msg).is_err());
+	}
+
+	#[test]
+	fn configures_name_servers() {
+		let resolver = SystemResolver::new(&["10.0.0.1".into(), "[::1]:5353".into()]).unwrap();
+		assert_eq!(resolver.servers, vec!["10.0.0.1:53".parse().unwrap(), "[::1]:5353".parse().unwrap()]);
+		assert!(SystemResolver::new(&["nameserver".into()]).is_err());
+		assert!(validate_dns_server("10.0.0.1:5353").is_none());
+		assert!(validate_dns_server("10.0.0.1:").is_some());
+
+		// the system's or the public name servers otherwise
+		assert!(!SystemResolver::new(&[]).unwrap().servers.is_empty());
+	}
+
+	#[test]
+	fn retries_truncated_responses_over_tcp() {
+		let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let server = udp.local_addr().unwrap();
+		let tcp = TcpListener::bind(server).unwrap();
+
+		let handle = thread::spawn(move || {
+			let mut buf = [0u8; 512];
+			let (_, from) = udp.recv_from(&mut buf).unwrap();
+			let mut truncated = txt_response(((buf[0] as u16) << 8) | buf[1] as u16, 0x80, &[]);
+			truncated[2] |= 0x02;
+			udp.send_to(&truncated, from).unwrap();
+
+			let (mut stream, _) = tcp.accept().unwrap();
+			let mut len = [0u8; 2];
+			stream.read_exact(&mut len).unwrap();
+			let mut query = vec![0u8; ((len[0] as usize) << 8) | len[1] as usize];
+			stream.read_exact(&mut query).unwrap();
+			let response = txt_response(((query[0] as u16) << 8) | query[1] as u16, 0x80, &[(DNS_TYPE_TXT, &b"\x04enr:\x03abc"[..])]);
+			stream.write_all(&[(response.len() >> 8) as u8, response.len() as u8]).unwrap();
+			stream.write_all(&response).unwrap();
+		});
+
+		let resolver = SystemResolver { servers: vec![server], timeout: Duration::from_secs(5) };
+		assert_eq!(resolver.txt("a.b").unwrap(), vec!["enr:abc".to_owned()]);
+		handle.join().unwrap();
+	}
+
+	#[test]
+	fn packets_from_elsewhere_dont_extend_timeout() {
+		let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let resolver = SystemResolver { servers: vec![server.local_addr().unwrap()], timeout: Duration::from_millis(500) };
+
+		let handle = thread::spawn(move || {
+			let mut buf = [0u8; 512];
+			let (len, from) = server.recv_from(&mut buf).unwrap();
+			// keep answering from another address until well past the timeout
+			let response = txt_response(((buf[0] as u16) << 8) | buf[1] as u16, 0x80, &[]);
+			for _ in 0..30 {
+				let _ = other.send_to(&response, from);
+				thread::sleep(Duration::from_millis(50));
+			}
+			len
+		});
+
+		let start = Instant::now();
+		assert!(resolver.txt("a.b").is_err());
+		assert!(start.elapsed() < Duration::from_millis(1000));
+		assert!(handle.join().unwrap() > 0);
+	}
+
+	#[test]
+	fn skips_names() {
+		let msg = [1, b'a', 1, b'b', 0, 0xc0, 0, 1, b'c', 0xc0, 0, 1];
+		assert_eq!(skip_name(&msg, 0).unwrap(), 5);
+		assert_eq!(skip_name(&msg, 5).unwrap(), 7);
+		assert_eq!(skip_name(&msg, 7).unwrap(), 11);
+		// pointer cut off
+		assert!(skip_name(&msg[..6], 5).is_err());
+		// no terminating label
+		assert!(skip_name(&msg, 11).is_err());
+		assert!(skip_name(&msg, 12).is_err());
+	}
+
+	#[test]
+	fn syncs_tree_with_links() {
+		let resolver = Arc::new(TestResolver::default());
+		let (key, linked_key) = (Random.generate().unwrap(), Random.generate().unwrap());
+		let (records, linked_records) = (vec![record(30303), record(30304)], vec![record(30305)]);
+		let link = publish(&resolver, &linked_key, "linked.example.org", 1, &linked_records, &[]);
+		let url = publish(&resolver, &key, "nodes.example.org", 1, &records, &[link]);
+
+		let mut list = DnsNodeList::new(url.parse().unwrap(), resolver.clone());
+		let mut synced = list.sync().unwrap();
+		synced.sort_by_key(|r| r.endpoint().unwrap().udp_port);
+		assert_eq!(synced, vec![records[0].clone(), records[1].clone(), linked_records[0].clone()]);
+	}
+
+	#[test]
+	fn rejects_root_signed_by_other_key() {
+		let resolver = Arc::new(TestResolver::default());
+		let (key, other) = (Random.generate().unwrap(), Random.generate().unwrap());
+		publish(&resolver, &other, "nodes.example.org", 1, &[record(30303)], &[]);
+
+		let mut list = DnsNodeList::new(tree_url(&key, "nodes.example.org").parse().unwrap(), resolver);
+		assert!(list.sync().is_err());
+	}
+
+	#[test]
+	fn resync_only_queries_changes() {
+		let resolver = Arc::new(TestResolver::default());
+		let key = Random.generate().unwrap();
+		let mut records = vec![record(30303), record(30304)];
+		let url = publish(&resolver, &key, "nodes.example.org", 1, &records, &[]);
+		let mut list = DnsNodeList::new(url.parse().unwrap(), resolver.clone());
+
+		assert_eq!(list.sync().unwrap().len(), 2);
+		// root, two branches and two records
+		assert_eq!(resolver.queries.swap(0, Ordering::SeqCst), 5);
+
+		assert_eq!(list.sync().unwrap().len(), 2);
+		assert_eq!(resolver.queries.swap(0, Ordering::SeqCst), 1);
+
+		records.push(record(30305));
+		publish(&resolver, &key, "nodes.example.org", 2, &records, &[]);
+		assert_eq!(list.sync().unwrap().len(), 3);
+		// root, new record branch and the new record
+		assert_eq!(resolver.queries.swap(0, Ordering::SeqCst), 3);
+	}
+}
@@ -0,0 +1,225 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Ethereum Node Records (EIP-778).
+//!
+//! A record is a signed, versioned list of key/value pairs describing a node. Only the `v4`
+//! identity scheme is supported: the record carries the compressed secp256k1 public key of the
+//! node and is signed with its secret key.
+
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use ethereum_types::{H256, H512};
+use ethkey::{self, KeyPair, Signature, sign, verify_public};
+use ethkey::math::{public_to_compressed, public_from_compressed};
+use hash::keccak;
+use parity_bytes::Bytes;
+use rlp::{Rlp, RlpStream, Encodable, Decodable, DecoderError};
+use node_table::{NodeId, NodeEndpoint};
+use discovery::NodeEntry;
+
+/// Maximum size of an encoded record.
+pub const MAX_RECORD_SIZE: usize = 300;
+
+const ID_SCHEME: &[u8] = b"v4";
+
+/// Signed node record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Enr {
+	seq: u64,
+	/// Entries sorted by key, values kept RLP-encoded.
+	pairs: BTreeMap<Bytes, Bytes>,
+	signature: Bytes,
+	id: NodeId,
+}
+
+impl Enr {
+	/// Create and sign a record for the given key and endpoint. `entries` are additional
+	/// key/value pairs with RLP-encoded values.
+	pub fn new(key: &KeyPair, seq: u64, endpoint: &NodeEndpoint, entries: Vec<(Bytes, Bytes)>) -> Result<Enr, ethkey::Error> {
+		let mut pairs = BTreeMap::new();
+		pairs.insert(b"id".to_vec(), ::rlp::encode(&ID_SCHEME.to_vec()).into_vec());
+		pairs.insert(b"secp256k1".to_vec(), ::rlp::encode(&public_to_compressed(key.public())?).into_vec());
+		match endpoint.address.ip() {
+			IpAddr::V4(ip) => pairs.insert(b"ip".to_vec(), ::rlp::encode(&ip.octets().to_vec()).into_vec()),
+			IpAddr::V6(ip) => pairs.insert(b"ip6".to_vec(), ::rlp::encode(&ip.octets().to_vec()).into_vec()),
+		};
+		pairs.insert(b"tcp".to_vec(), ::rlp::encode(&endpoint.address.port()).into_vec());
+		pairs.insert(b"udp".to_vec(), ::rlp::encode(&endpoint.udp_port).into_vec());
+		for (k, v) in entries {
+			pairs.insert(k, v);
+		}
+
+		let mut record = Enr {
+			seq,
+			pairs,
+			signature: Bytes::new(),
+			id: *key.public(),
+		};
+		let signature = sign(key.secret(), &record.signing_hash())?;
+		record.signature = signature[0..64].to_vec();
+		Ok(record)
+	}
+
+	/// Sequence number, increased whenever the record changes.
+	pub fn seq(&self) -> u64 {
+		self.seq
+	}
+
+	/// Public key of the node.
+	pub fn id(&self) -> &NodeId {
+		&self.id
+	}
+
+	/// Node id as used for discovery distances: hash of the public key.
+	pub fn node_hash(&self) -> H256 {
+		keccak(&self.id)
+	}
+
+	/// RLP-encoded value of an entry.
+	pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+		self.pairs.get(key).map(|v| &v[..])
+	}
+
+	/// Check if any entry key hashes to the given topic.
+	pub fn has_topic(&self, topic: &H256) -> bool {
+		self.pairs.keys().any(|k| keccak(k) == *topic)
+	}
+
+	/// Node endpoint advertised by the record, if any.
+	pub fn endpoint(&self) -> Option<NodeEndpoint> {
+		let ip = match (self.value::<Bytes>(b"ip"), self.value::<Bytes>(b"ip6")) {
+			(Some(ref ip), _) if ip.len() == 4 => IpAddr::V4(Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3])),
+			(_, Some(ref ip)) if ip.len() == 16 => {
+				let mut octets = [0u8; 16];
+				octets.copy_from_slice(ip);
+				IpAddr::V6(Ipv6Addr::from(octets))
+			},
+			_ => return None,
+		};
+		let tcp_port: u16 = self.value(b"tcp")?;
+		let udp_port = self.value(b"udp").unwrap_or(tcp_port);
+		Some(NodeEndpoint { address: SocketAddr::new(ip, tcp_port), udp_port })
+	}
+
+	/// Discovery entry of the node, if the record advertises an endpoint.
+	pub fn node_entry(&self) -> Option<NodeEntry> {
+		self.endpoint().map(|endpoint| NodeEntry { id: self.id, endpoint })
+	}
+
+	fn value<T: Decodable>(&self, key: &[u8]) -> Option<T> {
+		self.get(key).and_then(|v| ::rlp::decode(v).ok())
+	}
+
+	fn append_content(&self, s: &mut RlpStream) {
+		s.append(&self.seq);
+		for (k, v) in &self.pairs {
+			s.append(k);
+			s.append_raw(v, 1);
+		}
+	}
+
+	fn signing_hash(&self) -> H256 {
+		let mut s = RlpStream::new_list(1 + self.pairs.len() * 2);
+		self.append_content(&mut s);
+		keccak(s.as_raw())
+	}
+}
+
+impl Encodable for Enr {
+	fn rlp_append(&self, s: &mut RlpStream) {
+		s.begin_list(2 + self.pairs.len() * 2);
+		s.append(&self.signature);
+		self.append_content(s);
+	}
+}
+
+impl Decodable for Enr {
+	fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+		if rlp.as_raw().len() > MAX_RECORD_SIZE {
+			return Err(DecoderError::Custom("Record too large"));
+		}
+		let count = rlp.item_count()?;
+		if count < 2 || count % 2 != 0 {
+			return Err(DecoderError::RlpIncorrectListLen);
+		}
+		let signature: Bytes = rlp.val_at(0)?;
+		let seq: u64 = rlp.val_at(1)?;
+		let mut pairs = BTreeMap::new();
+		let mut last_key: Option<Bytes> = None;
+		for i in (2..count).step_by(2) {
+			let key: Bytes = rlp.val_at(i)?;
+			// keys must be sorted and unique
+			if last_key.as_ref().map_or(false, |last| *last >= key) {
+				return Err(DecoderError::Custom("Unsorted record keys"));
+			}
+			last_key = Some(key.clone());
+			pairs.insert(key, rlp.at(i + 1)?.as_raw().to_vec());
+		}
+
+		let mut record = Enr { seq, pairs, signature, id: H512::default() };
+		if record.value::<Bytes>(b"id").map_or(true, |scheme| scheme != ID_SCHEME) {
+			return Err(DecoderError::Custom("Unknown identity scheme"));
+		}
+		let compressed: Bytes = record.value(b"secp256k1").ok_or(DecoderError::Custom("Missing public key"))?;
+		record.id = public_from_compressed(&compressed).map_err(|_| DecoderError::Custom("Invalid public key"))?;
+		if record.signature.len() != 64 {
+			return Err(DecoderError::Custom("Invalid signature"));
+		}
+		let signature = Signature::from_rsv(&H256::from_slice(&record.signature[0..32]), &H256::from_slice(&record.signature[32..64]), 0);
+		match verify_public(&record.id, &signature, &record.signing_hash()) {
+			Ok(true) => Ok(record),
+			_ => Err(DecoderError::Custom("Invalid signature")),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::str::FromStr;
+	use ethkey::{Random, Generator};
+
+	fn endpoint() -> NodeEndpoint {
+		NodeEndpoint { address: SocketAddr::from_str("10.0.0.1:30303").unwrap(), udp_port: 30301 }
+	}
+
+	#[test]
+	fn record_roundtrip() {
+		let key = Random.generate().unwrap();
+		let record = Enr::new(&key, 7, &endpoint(), vec![(b"eth".to_vec(), ::rlp::encode(&b"chain".to_vec()).into_vec())]).unwrap();
+		let encoded = ::rlp::encode(&record);
+		assert!(encoded.len() <= MAX_RECORD_SIZE);
+
+		let decoded: Enr = ::rlp::decode(&encoded).unwrap();
+		assert_eq!(decoded, record);
+		assert_eq!(decoded.seq(), 7);
+		assert_eq!(decoded.id(), key.public());
+		assert_eq!(decoded.endpoint(), Some(endpoint()));
+		assert_eq!(decoded.get(b"eth"), Some(&::rlp::encode(&b"chain".to_vec())[..]));
+		assert!(decoded.has_topic(&keccak(b"eth")));
+	}
+
+	#[test]
+	fn tampered_record_is_rejected() {
+		let key = Random.generate().unwrap();
+		let record = Enr::new(&key, 1, &endpoint(), Vec::new()).unwrap();
+		let mut tampered = record.clone();
+		tampered.seq = 2;
+
+		assert!(::rlp::decode::<Enr>(&::rlp::encode(&tampered)).is_err());
+	}
+}
@@ -24,6 +24,8 @@ use std::cmp::{min, max};
 use std::path::{Path, PathBuf};
 use std::io::{Read, Write, self};
 use std::fs;
+use std::mem;
+use std::thread;
 use std::time::Duration;
 use ethkey::{KeyPair, Secret, Random, Generator};
 use hash::keccak;
@@ -35,17 +37,20 @@ use ethereum_types::H256;
 use rlp::{RlpStream, Encodable};
 
 use session::{Session, SessionData};
+use bandwidth::Bandwidth;
 use io::*;
 use PROTOCOL_VERSION;
 use node_table::*;
 use network::{NetworkConfiguration, NetworkIoMessage, ProtocolId, PeerId, PacketId};
 use network::{NonReservedPeerMode, NetworkContext as NetworkContextTrait};
-use network::{SessionInfo, Error, ErrorKind, DisconnectReason, NetworkProtocolHandler};
+use network::{SessionInfo, Error, ErrorKind, DisconnectReason, NetworkProtocolHandler, TrafficStats};
 use discovery::{Discovery, TableUpdates, NodeEntry, MAX_DATAGRAM_SIZE};
+use dns::{DnsNodeList, EnrTreeUrl, SystemResolver, TxtResolver};
+use enr::Enr;
 use ip_utils::{map_external_address, select_public_address};
 use parity_path::restrict_permissions_owner;
 use parking_lot::{Mutex, RwLock};
-use network::{ConnectionFilter, ConnectionDirection};
+use network::{ConnectionFilter, ConnectionDirection, EnrFilter};
 
 type Slab<T> = ::slab::Slab<T, usize>;
 
@@ -62,6 +67,8 @@ const DISCOVERY_REFRESH: TimerToken = SYS_TIMER + 4;
 const FAST_DISCOVERY_REFRESH: TimerToken = SYS_TIMER + 5;
 const DISCOVERY_ROUND: TimerToken = SYS_TIMER + 6;
 const NODE_TABLE: TimerToken = SYS_TIMER + 7;
+const DNS_SYNC: TimerToken = SYS_TIMER + 8;
+const BANDWIDTH: TimerToken = SYS_TIMER + 9;
 const FIRST_SESSION: StreamToken = 0;
 const LAST_SESSION: StreamToken = FIRST_SESSION + MAX_SESSIONS - 1;
 const USER_TIMER: TimerToken = LAST_SESSION + 256;
@@ -78,6 +85,10 @@ const FAST_DISCOVERY_REFRESH_TIMEOUT: Duration = Duration::from_secs(10);
 const DISCOVERY_ROUND_TIMEOUT: Duration = Duration::from_millis(300);
 // for NODE_TABLE TimerToken
 const NODE_TABLE_TIMEOUT: Duration = Duration::from_secs(300);
+// for resyncing DNS node lists
+const DNS_SYNC_TIMEOUT: Duration = Duration::from_secs(1800);
+// for BANDWIDTH TimerToken, sending packets held back by upload limits
+const BANDWIDTH_TIMEOUT: Duration = Duration::from_millis(100);
 
 #[derive(Debug, PartialEq, Eq)]
 /// Protocol info
@@ -144,7 +155,14 @@ impl<'s> NetworkContextTrait for NetworkContext<'s> {
 	fn send_protocol(&self, protocol: ProtocolId, peer: PeerId, packet_id: PacketId, data: Vec<u8>) -> Result<(), Error> {
 		let session = self.resolve_session(peer);
 		if let Some(session) = session {
-			session.lock().send_packet(self.io, Some(protocol), packet_id as u8, &data)?;
+			let result = session.lock().send_packet(self.io, Some(protocol), packet_id as u8, &data);
+			if let Err(ref e) = result {
+				// the peer asked for more than the upload limits let through
+				if let ErrorKind::Disconnect(_) = *e.kind() {
+					self.disconnect_peer(peer);
+				}
+			}
+			result?;
 		} else  {
 			trace!(target: "network", "Send: Peer no longer exist")
 		}
@@ -261,11 +279,15 @@ pub struct Host {
 	reserved_nodes: RwLock<HashSet<NodeId>>,
 	stopping: AtomicBool,
 	filter: Option<Arc<ConnectionFilter>>,
+	enr_filter: Option<Arc<EnrFilter>>,
+	dns_lists: Arc<Mutex<Vec<DnsNodeList>>>,
+	dns_records: Arc<Mutex<Vec<Enr>>>,
+	bandwidth: Arc<Mutex<Bandwidth>>,
 }
 
 impl Host {
 	/// Create a new instance
-	pub fn new(mut config: NetworkConfiguration, filter: Option<Arc<ConnectionFilter>>) -> Result<Host, Error> {
+	pub fn new(mut config: NetworkConfiguration, filter: Option<Arc<ConnectionFilter>>, enr_filter: Option<Arc<EnrFilter>>) -> Result<Host, Error> {
 		let mut listen_address = match config.listen_address {
 			None => SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), DEFAULT_PORT)),
 			Some(addr) => addr,
@@ -293,7 +315,9 @@ impl Host {
 		let local_endpoint = NodeEndpoint { address: listen_address, udp_port };
 
 		let boot_nodes = config.boot_nodes.clone();
+		let dns_lists = Host::dns_node_lists(&config.dns_node_lists, &config.dns_servers);
 		let reserved_nodes = config.reserved_nodes.clone();
+		let bandwidth = Bandwidth::new(&config);
 		config.max_handshakes = min(config.max_handshakes, MAX_HANDSHAKES as u32);
 
 		let mut host = Host {
@@ -317,6 +341,10 @@ impl Host {
 			reserved_nodes: RwLock::new(HashSet::new()),
 			stopping: AtomicBool::new(false),
 			filter,
+			enr_filter,
+			dns_lists: Arc::new(Mutex::new(dns_lists)),
+			dns_records: Arc::new(Mutex::new(Vec::new())),
+			bandwidth: Arc::new(Mutex::new(bandwidth)),
 		};
 
 		for n in boot_nodes {
@@ -345,6 +373,85 @@ impl Host {
 		}
 	}
 
+	/// Add a node found in a node record.
+	fn add_record(&self, record: Enr) {
+		let entry = match record.node_entry() {
+			Some(entry) => entry,
+			None => {
+				debug!(target: "network", "Ignoring node record without endpoint: {:?}", record.id());
+				return;
+			},
+		};
+		let mut node = Node::new(entry.id, entry.endpoint.clone());
+		node.enr = Some(record.clone());
+		self.nodes.write().add_node(node);
+		if let Some(ref mut discovery) = *self.discovery.lock() {
+			discovery.add_node(entry);
+			discovery.add_records(vec![record]);
+		}
+	}
+
+	fn dns_node_lists(urls: &[String], servers: &[String]) -> Vec<DnsNodeList> {
+		if urls.is_empty() {
+			return Vec::new();
+		}
+		let resolver: Arc<TxtResolver> = match SystemResolver::new(servers) {
+			Ok(resolver) => Arc::new(resolver),
+			Err(e) => {
+				warn!(target: "network", "DNS node lists disabled, invalid name servers: {:?}", e);
+				return Vec::new();
+			},
+		};
+		urls.iter().filter_map(|url| match EnrTreeUrl::from_str(url) {
+			Ok(url) => Some(DnsNodeList::new(url, resolver.clone())),
+			Err(e) => {
+				warn!(target: "network", "Invalid DNS node list {}: {:?}", url, e);
+				None
+			},
+		}).collect()
+	}
+
+	/// Resync DNS node lists on a separate thread, as resolving them may take a long time.
+	/// The nodes they contain are added once `DnsNodeListsSynced` is received.
+	fn sync_dns_node_lists(&self, io: &IoContext<NetworkIoMessage>) {
+		let dns_lists = self.dns_lists.clone();
+		let records = self.dns_records.clone();
+		let channel = io.channel();
+		let spawned = thread::Builder::new()
+			.name("DNS node lists".into())
+			.spawn(move || {
+				// the previous sync is still running.
+				let mut lists = match dns_lists.try_lock() {
+					Some(lists) => lists,
+					None => return,
+				};
+				let mut synced = Vec::new();
+				for list in lists.iter_mut() {
+					match list.sync() {
+						Ok(list_records) => {
+							debug!(target: "network", "Synced DNS node list {}: {} nodes", list.url().domain, list_records.len());
+							synced.extend(list_records);
+						},
+						Err(e) => debug!(target: "network", "Error syncing DNS node list {}: {:?}", list.url().domain, e),
+					}
+				}
+				records.lock().extend(synced);
+				channel.send(NetworkIoMessage::DnsNodeListsSynced).unwrap_or_else(|e| debug!("Error sending IO notification: {:?}", e));
+			});
+		if let Err(e) = spawned {
+			warn!(target: "network", "Error starting DNS node list sync: {:?}", e);
+		}
+	}
+
+	/// Add the nodes of synced DNS node lists.
+	fn add_dns_records(&self, io: &IoContext<NetworkIoMessage>) {
+		let records = mem::replace(&mut *self.dns_records.lock(), Vec::new());
+		for record in records {
+			self.add_record(record);
+		}
+		io.update_registration(DISCOVERY).unwrap_or_else(|e| debug!("Error updating discovery registration: {:?}", e));
+	}
+
 	pub fn add_reserved_node(&self, id: &str) -> Result<(), Error> {
 		let n = Node::from_str(id)?;
 
@@ -401,6 +508,11 @@ impl Host {
 		info.public_endpoint.as_ref().map(|e| format!("{}", Node::new(*info.id(), e.clone())))
 	}
 
+	/// Total and per protocol traffic of all sessions since start.
+	pub fn traffic(&self) -> (TrafficStats, HashMap<ProtocolId, TrafficStats>) {
+		self.bandwidth.lock().traffic()
+	}
+
 	pub fn local_url(&self) -> String {
 		let info = self.info.read();
 		format!("{}", Node::new(*info.id(), info.local_endpoint.clone()))
@@ -476,12 +588,16 @@ impl Host {
 		};
 
 		if let Some(mut discovery) = discovery {
+			if let Some(ref filter) = self.enr_filter {
+				discovery.set_enr_filter(filter.clone());
+			}
 			let mut udp_addr = local_endpoint.address;
 			udp_addr.set_port(local_endpoint.udp_port);
 			let socket = UdpSocket::bind(&udp_addr).expect("Error binding UDP socket");
 			*self.udp_socket.lock() = Some(socket);
 
 			discovery.add_node_list(self.nodes.read().entries());
+			discovery.add_records(self.nodes.read().records());
 			*self.discovery.lock() = Some(discovery);
 			io.register_stream(DISCOVERY)?;
 			io.register_timer(FAST_DISCOVERY_REFRESH, FAST_DISCOVERY_REFRESH_TIMEOUT)?;
@@ -489,6 +605,12 @@ impl Host {
 			io.register_timer(DISCOVERY_ROUND, DISCOVERY_ROUND_TIMEOUT)?;
 		}
 		io.register_timer(NODE_TABLE, NODE_TABLE_TIMEOUT)?;
+		if !self.dns_lists.lock().is_empty() {
+			io.register_timer_once(DNS_SYNC, Duration::from_secs(0))?;
+		}
+		if self.bandwidth.lock().is_limited() {
+			io.register_timer(BANDWIDTH, BANDWIDTH_TIMEOUT)?;
+		}
 		io.register_stream(TCP_ACCEPT)?;
 		Ok(())
 	}
@@ -579,7 +701,13 @@ impl Host {
 		// iterate over all nodes, reserved ones coming first.
 		// if we are pinned to only reserved nodes, ignore all others.
 		let nodes = reserved_nodes.iter().cloned().chain(if !pin {
-			self.nodes.read().nodes(&allow_ips)
+			let table = self.nodes.read();
+			let mut ids = table.nodes(&allow_ips);
+			if let Some(ref filter) = self.enr_filter {
+				// skip nodes whose record shows them on another chain
+				ids.retain(|id| table.record_allowed(id, &**filter));
+			}
+			ids
 		} else {
 			Vec::new()
 		});
@@ -642,7 +770,7 @@ impl Host {
 
 		let token = sessions.insert_with_opt(|token| {
 			trace!(target: "network", "{}: Initiating session {:?}", token, id);
-			match Session::new(io, socket, token, id, &nonce, &self.info.read()) {
+			match Session::new(io, socket, token, id, &nonce, &self.info.read(), self.bandwidth.clone()) {
 				Ok(s) => Some(Arc::new(Mutex::new(s))),
 				Err(e) => {
 					debug!(target: "network", "Session create error: {:?}", e);
@@ -692,6 +820,18 @@ impl Host {
 		}
 	}
 
+	fn flush_throttled(&self, io: &IoContext<NetworkIoMessage>) {
+		let sessions: Vec<_> = self.sessions.read().iter().cloned().collect();
+		for session in sessions {
+			let mut s = session.lock();
+			if s.is_throttled() {
+				if let Err(e) = s.flush_throttled(io) {
+					trace!(target: "network", "Session write error: {}: {:?}", s.token(), e);
+				}
+			}
+		}
+	}
+
 	fn connection_closed(&self, token: StreamToken, io: &IoContext<NetworkIoMessage>) {
 		trace!(target: "network", "Connection closed: {}", token);
 		self.kill_connection(token, io, true);
@@ -1056,6 +1196,11 @@ impl IoHandler<NetworkIoMessage> for Host {
 				self.nodes.write().clear_useless();
 				self.nodes.write().save();
 			},
+			DNS_SYNC => {
+				self.sync_dns_node_lists(io);
+				io.register_timer_once(DNS_SYNC, DNS_SYNC_TIMEOUT).unwrap_or_else(|e| debug!("Error registering DNS sync timer: {:?}", e));
+			},
+			BANDWIDTH => self.flush_throttled(io),
 			_ => match self.timers.read().get(&token).cloned() {
 				Some(timer) => match self.handlers.read().get(&timer.protocol).cloned() {
 					None => { warn!(target: "network", "No handler found for protocol: {:?}", timer.protocol) },
@@ -1132,6 +1277,7 @@ impl IoHandler<NetworkIoMessage> for Host {
 			},
 			NetworkIoMessage::InitPublicInterface =>
 				self.init_public_interface(io).unwrap_or_else(|e| warn!("Error initializing public interface: {:?}", e)),
+			NetworkIoMessage::DnsNodeListsSynced => self.add_dns_records(io),
 			_ => {}	// ignore others.
 		}
 	}
@@ -1265,6 +1411,6 @@ fn host_client_url() {
 	let mut config = NetworkConfiguration::new_local();
 	let key = "6f7b0d801bc7b5ce7bbd930b84fd0369b3eb25d09be58d64ba811091046f3aa2".parse().unwrap();
 	config.use_secret = Some(key);
-	let host: Host = Host::new(config, None).unwrap();
+	let host: Host = Host::new(config, None, None).unwrap();
 	assert!(host.local_url().starts_with("enode://101b3ef5a4ea7a1c7928e24c4c75fd053c235d7b80c22ae5c03d145d0ac7396e2a4ffff9adee3133a7b05044a5cee08115fd65145e5165d646bde371010d803c@"));
 }
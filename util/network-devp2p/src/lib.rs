@@ -103,6 +103,10 @@ mod connection;
 mod handshake;
 mod session;
 mod discovery;
+mod discv5;
+mod dns;
+mod bandwidth;
+mod enr;
 mod service;
 mod node_table;
 mod ip_utils;
@@ -112,5 +116,6 @@ pub use host::NetworkContext;
 
 pub use io::TimerToken;
 pub use node_table::{validate_node_url, NodeId};
+pub use dns::{validate_enrtree_url, validate_dns_server};
 
 const PROTOCOL_VERSION: u32 = 5;
@@ -15,9 +15,10 @@
 // along with Parity.  If not, see <http://www.gnu.org/licenses/>.
 
 use discovery::{TableUpdates, NodeEntry};
+use enr::Enr;
 use ethereum_types::H512;
 use ip_utils::*;
-use network::{Error, ErrorKind, AllowIP, IpFilter};
+use network::{Error, ErrorKind, AllowIP, IpFilter, EnrFilter};
 use rlp::{Rlp, RlpStream, DecoderError};
 use serde_json;
 use std::collections::{HashMap, HashSet};
@@ -179,6 +180,7 @@ pub struct Node {
 	pub endpoint: NodeEndpoint,
 	pub peer_type: PeerType,
 	pub last_contact: Option<NodeContact>,
+	pub enr: Option<Enr>,
 }
 
 impl Node {
@@ -188,6 +190,7 @@ impl Node {
 			endpoint,
 			peer_type: PeerType::Optional,
 			last_contact: None,
+			enr: None,
 		}
 	}
 }
@@ -218,6 +221,7 @@ impl FromStr for Node {
 			endpoint,
 			peer_type: PeerType::Optional,
 			last_contact: None,
+			enr: None,
 		})
 	}
 }
@@ -256,8 +260,11 @@ impl NodeTable {
 
 	/// Add a node to table
 	pub fn add_node(&mut self, mut node: Node) {
-		// preserve node last_contact
-		node.last_contact = self.nodes.get(&node.id).and_then(|n| n.last_contact);
+		// preserve node last_contact and record
+		if let Some(n) = self.nodes.get(&node.id) {
+			node.last_contact = n.last_contact;
+			node.enr = node.enr.take().or_else(|| n.enr.clone());
+		}
 		self.nodes.insert(node.id, node);
 	}
 
@@ -331,6 +338,19 @@ impl NodeTable {
 		}).collect()
 	}
 
+	/// Node records of all entries.
+	pub fn records(&self) -> Vec<Enr> {
+		self.nodes.values().filter_map(|n| n.enr.clone()).collect()
+	}
+
+	/// Check the record of a node against the filter. Nodes without a known record are allowed.
+	pub fn record_allowed(&self, id: &NodeId, filter: &EnrFilter) -> bool {
+		match self.nodes.get(id).and_then(|n| n.enr.as_ref()) {
+			Some(enr) => enr.get(filter.key()).map_or(false, |value| filter.value_allowed(value)),
+			None => true,
+		}
+	}
+
 	/// Get particular node
 	pub fn get_mut(&mut self, id: &NodeId) -> Option<&mut Node> {
 		self.nodes.get_mut(id)
@@ -347,6 +367,11 @@ impl NodeTable {
 			let entry = self.nodes.entry(node.id).or_insert_with(|| Node::new(node.id, node.endpoint.clone()));
 			entry.endpoint = node.endpoint;
 		}
+		for (id, record) in update.records.drain() {
+			if let Some(node) = self.nodes.get_mut(&id) {
+				node.enr = Some(record);
+			}
+		}
 		for r in update.removed {
 			if !reserved.contains(&r) {
 				self.nodes.remove(&r);
@@ -454,6 +479,7 @@ pub fn validate_node_url(url: &str) -> Option<Error> {
 
 mod json {
 	use super::*;
+	use rustc_hex::{FromHex, ToHex};
 
 	#[derive(Serialize, Deserialize)]
 	pub struct NodeTable {
@@ -485,6 +511,8 @@ mod json {
 	pub struct Node {
 		pub url: String,
 		pub last_contact: Option<NodeContact>,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		pub enr: Option<String>,
 	}
 
 	impl Node {
@@ -492,6 +520,10 @@ mod json {
 			match super::Node::from_str(&self.url) {
 				Ok(mut node) => {
 					node.last_contact = self.last_contact.map(|c| c.into_node_contact());
+					node.enr = self.enr
+						.and_then(|enr| enr.from_hex().ok())
+						.and_then(|enr| ::rlp::decode::<Enr>(&enr).ok())
+						.filter(|enr| *enr.id() == node.id);
 					Some(node)
 				},
 				_ => None,
@@ -512,7 +544,8 @@ mod json {
 
 			Node {
 				url: format!("{}", node),
-				last_contact
+				last_contact,
+				enr: node.enr.as_ref().map(|enr| ::rlp::encode(enr).to_hex()),
 			}
 		}
 	}
@@ -526,6 +559,7 @@ mod tests {
 	use std::str::FromStr;
 	use tempdir::TempDir;
 	use ipnetwork::IpNetwork;
+	use ethkey::{Random, Generator};
 
 	#[test]
 	fn endpoint_parse() {
@@ -678,6 +712,28 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn table_save_load_records() {
+		let tempdir = TempDir::new("").unwrap();
+		let key = Random.generate().unwrap();
+		let endpoint = NodeEndpoint::from_str("22.99.55.44:7770").unwrap();
+		let record = Enr::new(&key, 1, &endpoint, Vec::new()).unwrap();
+
+		{
+			let mut table = NodeTable::new(Some(tempdir.path().to_str().unwrap().to_owned()));
+			let mut node = Node::new(*key.public(), endpoint.clone());
+			node.enr = Some(record.clone());
+			table.add_node(node);
+			// re-adding the node without a record keeps the known one
+			table.add_node(Node::new(*key.public(), endpoint));
+		}
+
+		{
+			let table = NodeTable::new(Some(tempdir.path().to_str().unwrap().to_owned()));
+			assert_eq!(table.records(), vec![record]);
+		}
+	}
+
 	#[test]
 	fn custom_allow() {
 		let filter = IpFilter {
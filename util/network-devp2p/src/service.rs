@@ -15,15 +15,16 @@
 // along with Parity.  If not, see <http://www.gnu.org/licenses/>.
 
 use network::{Error, NetworkConfiguration, NetworkProtocolHandler, NonReservedPeerMode};
-use network::{NetworkContext, PeerId, ProtocolId, NetworkIoMessage};
+use network::{NetworkContext, PeerId, ProtocolId, NetworkIoMessage, TrafficStats};
 use host::Host;
 use io::*;
 use parking_lot::RwLock;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::ops::Range;
 use std::sync::Arc;
 use ansi_term::Colour;
-use network::ConnectionFilter;
+use network::{ConnectionFilter, EnrFilter};
 
 struct HostHandler {
 	public_url: RwLock<Option<String>>
@@ -50,6 +51,7 @@ pub struct NetworkService {
 	host_handler: Arc<HostHandler>,
 	config: NetworkConfiguration,
 	filter: Option<Arc<ConnectionFilter>>,
+	enr_filter: RwLock<Option<Arc<EnrFilter>>>,
 }
 
 impl NetworkService {
@@ -65,9 +67,15 @@ impl NetworkService {
 			config,
 			host_handler,
 			filter,
+			enr_filter: RwLock::new(None),
 		})
 	}
 
+	/// Set the filter for node records found by discovery. Takes effect when the network is started.
+	pub fn set_enr_filter(&self, filter: Arc<EnrFilter>) {
+		*self.enr_filter.write() = Some(filter);
+	}
+
 	/// Register a new protocol handler with the event loop.
 	pub fn register_protocol(
 		&self,
@@ -115,6 +123,12 @@ impl NetworkService {
 		host.as_ref().map(|h| h.local_url())
 	}
 
+	/// Returns total and per protocol traffic since start.
+	pub fn traffic(&self) -> (TrafficStats, HashMap<ProtocolId, TrafficStats>) {
+		let host = self.host.read();
+		host.as_ref().map_or_else(Default::default, |h| h.traffic())
+	}
+
 	/// Start network IO.
 	///
 	/// In case of error, also returns the listening address for better error reporting.
@@ -122,7 +136,7 @@ impl NetworkService {
 		let mut host = self.host.write();
 		let listen_addr = self.config.listen_address;
 		if host.is_none() {
-			let h = Arc::new(Host::new(self.config.clone(), self.filter.clone())
+			let h = Arc::new(Host::new(self.config.clone(), self.filter.clone(), self.enr_filter.read().clone())
 				.map_err(|err| (err, listen_addr))?);
 			self.io_service.register_handler(h.clone())
 				.map_err(|err| (err.into(), listen_addr))?;
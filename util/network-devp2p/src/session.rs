@@ -16,7 +16,8 @@
 
 use std::{str, io};
 use std::net::SocketAddr;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use mio::*;
@@ -27,10 +28,12 @@ use rlp::{Rlp, RlpStream, EMPTY_LIST_RLP};
 use connection::{EncryptedConnection, Packet, Connection, MAX_PAYLOAD_SIZE};
 use handshake::Handshake;
 use io::{IoContext, StreamToken};
-use network::{Error, ErrorKind, DisconnectReason, SessionInfo, ProtocolId, PeerCapabilityInfo};
-use network::SessionCapabilityInfo;
+use network::{Error, ErrorKind, DisconnectReason, SessionInfo, PacketId, ProtocolId, PeerCapabilityInfo};
+use network::{SessionCapabilityInfo, TrafficStats};
 use host::*;
 use node_table::NodeId;
+use bandwidth::{Bandwidth, TokenBucket, add_traffic};
+use parking_lot::Mutex;
 use snappy;
 
 // Timeout must be less than (interval - 1).
@@ -38,6 +41,9 @@ const PING_TIMEOUT: Duration = Duration::from_secs(60);
 const PING_INTERVAL: Duration = Duration::from_secs(120);
 const MIN_PROTOCOL_VERSION: u32 = 4;
 const MIN_COMPRESSION_PROTOCOL_VERSION: u32 = 5;
+// Peers asking for more than this many bytes while the upload limits hold their packets back
+// get disconnected.
+const MAX_THROTTLED_SIZE: usize = 16 * 1024 * 1024;
 
 #[derive(Debug, Clone)]
 enum ProtocolState {
@@ -63,6 +69,64 @@ pub struct Session {
 	// Protocol states -- accumulates pending packets until signaled as ready.
 	protocol_states: HashMap<ProtocolId, ProtocolState>,
 	compression: bool,
+	/// Upload limits and traffic counters of the host.
+	bandwidth: Arc<Mutex<Bandwidth>>,
+	/// Whether any upload limit is set, otherwise packets are sent right away.
+	limited: bool,
+	/// Upload limit of the session.
+	upload: Option<TokenBucket>,
+	/// Protocol packets waiting for the upload limits to allow sending.
+	throttled: ThrottledPackets,
+}
+
+/// Protocol packets waiting for the upload limits, sent in the order the protocols queued them.
+///
+/// Requests get no priority over the responses queued before them: packet ids mean nothing at
+/// this layer, and protocols without request ids match responses to requests by their order.
+/// A request only waits while this session or the protocol is uploading at its limit, and
+/// requests to other peers go out meanwhile.
+#[derive(Default)]
+struct ThrottledPackets {
+	packets: VecDeque<(ProtocolId, PacketId, Vec<u8>)>,
+	/// Total size of the waiting packets.
+	size: usize,
+}
+
+impl ThrottledPackets {
+	/// Queue a packet. Fails and drops all packets if the peer asked for too much.
+	fn push(&mut self, protocol: ProtocolId, packet_id: PacketId, data: Vec<u8>) -> Result<(), Error> {
+		if self.size + data.len() > MAX_THROTTLED_SIZE {
+			self.packets.clear();
+			self.size = 0;
+			bail!(ErrorKind::Disconnect(DisconnectReason::UselessPeer));
+		}
+		self.size += data.len();
+		self.packets.push_back((protocol, packet_id, data));
+		Ok(())
+	}
+
+	/// Take the first packet if `can_send` allows it given its protocol, id and size.
+	fn pop_if<F>(&mut self, can_send: F) -> Option<(ProtocolId, Vec<u8>)> where F: FnOnce(ProtocolId, PacketId, usize) -> bool {
+		let sendable = match self.packets.front() {
+			Some(&(protocol, packet_id, ref data)) => can_send(protocol, packet_id, data.len()),
+			None => false,
+		};
+		if !sendable {
+			return None;
+		}
+		self.packets.pop_front().map(|(protocol, _, data)| {
+			self.size -= data.len();
+			(protocol, data)
+		})
+	}
+
+	fn len(&self) -> usize {
+		self.packets.len()
+	}
+
+	fn is_empty(&self) -> bool {
+		self.packets.is_empty()
+	}
 }
 
 enum State {
@@ -101,12 +165,16 @@ impl Session {
 	/// Create a new session out of completed handshake. This clones the handshake connection object
 	/// and leaves the handshake in limbo to be de-registered from the event loop.
 	pub fn new<Message>(io: &IoContext<Message>, socket: TcpStream, token: StreamToken, id: Option<&NodeId>,
-		nonce: &H256, host: &HostInfo) -> Result<Session, Error>
+		nonce: &H256, host: &HostInfo, bandwidth: Arc<Mutex<Bandwidth>>) -> Result<Session, Error>
 		where Message: Send + Clone + Sync + 'static {
 		let originated = id.is_some();
 		let mut handshake = Handshake::new(token, id, socket, nonce).expect("Can't create handshake");
 		let local_addr = handshake.connection.local_addr_str();
 		handshake.start(io, host, originated)?;
+		let (limited, upload) = {
+			let bandwidth = bandwidth.lock();
+			(bandwidth.is_limited(), bandwidth.peer_bucket())
+		};
 		Ok(Session {
 			state: State::Handshake(handshake),
 			had_hello: false,
@@ -120,12 +188,18 @@ impl Session {
 				originated,
 				remote_address: "Handshake".to_owned(),
 				local_address: local_addr,
+				traffic: TrafficStats::default(),
+				protocol_traffic: HashMap::new(),
 			},
 			ping_time: Instant::now(),
 			pong_time: None,
 			expired: false,
 			protocol_states: HashMap::new(),
 			compression: false,
+			bandwidth,
+			limited,
+			upload,
+			throttled: ThrottledPackets::default(),
 		})
 	}
 
@@ -286,7 +360,61 @@ impl Session {
 			payload = &compressed[0..len];
 		}
 		rlp.append_raw(payload, 1);
-		self.send(io, &rlp.drain())
+		match protocol {
+			Some(protocol) if !self.limited => {
+				let data = rlp.drain();
+				self.send(io, &data)?;
+				self.note_traffic(Some(protocol), 0, data.len());
+				Ok(())
+			},
+			Some(protocol) => {
+				if let Err(e) = self.throttled.push(protocol, packet_id, rlp.out()) {
+					debug!(target: "network", "{}: Too much data waiting for the upload limits, disconnecting", self.token());
+					return Err(e);
+				}
+				self.flush_throttled(io)
+			},
+			None => self.send(io, &rlp.drain()),
+		}
+	}
+
+	/// Send the protocol packets the upload limits allow, in order.
+	pub fn flush_throttled<Message>(&mut self, io: &IoContext<Message>) -> Result<(), Error> where Message: Send + Sync + Clone {
+		loop {
+			let packet = {
+				let (bandwidth, upload) = (&self.bandwidth, &mut self.upload);
+				self.throttled.pop_if(|protocol, packet_id, len| bandwidth.lock().try_upload(protocol, packet_id, upload.as_mut(), len))
+			};
+			match packet {
+				Some((protocol, data)) => {
+					self.send(io, &data)?;
+					self.note_traffic(Some(protocol), 0, data.len());
+				},
+				None => break,
+			}
+		}
+		if !self.throttled.is_empty() {
+			trace!(target: "network", "{}: Upload limited, {} packets waiting", self.token(), self.throttled.len());
+		}
+		Ok(())
+	}
+
+	/// Whether there are protocol packets waiting for the upload limits.
+	pub fn is_throttled(&self) -> bool {
+		!self.throttled.is_empty()
+	}
+
+	/// Count bytes transferred, for the protocol if given or in total otherwise.
+	fn note_traffic(&mut self, protocol: Option<ProtocolId>, received: usize, sent: usize) {
+		let traffic = TrafficStats { ingress: received as u64, egress: sent as u64 };
+		{
+			let stats = match protocol {
+				Some(protocol) => self.info.protocol_traffic.entry(protocol).or_insert_with(TrafficStats::default),
+				None => &mut self.info.traffic,
+			};
+			add_traffic(stats, &traffic);
+		}
+		self.bandwidth.lock().note_traffic(protocol, &traffic);
 	}
 
 	/// Keep this session alive. Returns false if ping timeout happened
@@ -331,6 +459,7 @@ impl Session {
 		if packet.data.len() < 2 {
 			return Err(ErrorKind::BadProtocol.into());
 		}
+		self.note_traffic(None, packet.data.len(), 0);
 		let packet_id = packet.data[0];
 		if packet_id != PACKET_HELLO && packet_id != PACKET_DISCONNECT && !self.had_hello {
 			return Err(ErrorKind::BadProtocol.into());
@@ -383,6 +512,7 @@ impl Session {
 				// map to protocol
 				let protocol = self.info.capabilities[i].protocol;
 				let protocol_packet_id = packet_id - self.info.capabilities[i].id_offset;
+				self.note_traffic(Some(protocol), packet.data.len(), 0);
 
 				match *self.protocol_states.entry(protocol).or_insert_with(|| ProtocolState::Pending(Vec::new())) {
 					ProtocolState::Connected => {
@@ -504,11 +634,49 @@ impl Session {
 		match self.state {
 			State::Handshake(_) => {
 				warn!(target:"network", "Unexpected send request");
+				return Ok(());
 			},
 			State::Session(ref mut s) => {
 				s.send_packet(io, data)?
 			},
 		}
+		self.note_traffic(None, 0, data.len());
 		Ok(())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn throttled_packets_are_sent_in_order() {
+		let mut throttled = ThrottledPackets::default();
+		throttled.push(*b"eth", 1, vec![1; 10]).unwrap();
+		throttled.push(*b"par", 2, vec![2; 20]).unwrap();
+		assert_eq!(throttled.size, 30);
+
+		assert_eq!(throttled.pop_if(|_, _, _| false), None);
+		assert_eq!(throttled.pop_if(|protocol, packet_id, len| protocol == *b"eth" && packet_id == 1 && len == 10), Some((*b"eth", vec![1; 10])));
+		assert_eq!(throttled.size, 20);
+		assert_eq!(throttled.pop_if(|_, _, _| true), Some((*b"par", vec![2; 20])));
+		assert_eq!(throttled.pop_if(|_, _, _| true), None);
+		assert!(throttled.is_empty());
+	}
+
+	#[test]
+	fn throttled_packets_overflow() {
+		let mut throttled = ThrottledPackets::default();
+		for _ in 0..16 {
+			throttled.push(*b"par", 1, vec![0; 1024 * 1024]).unwrap();
+		}
+		assert_eq!(throttled.size, MAX_THROTTLED_SIZE);
+
+		let err = throttled.push(*b"eth", 2, vec![0]).unwrap_err();
+		assert_matches!(err.kind(), &ErrorKind::Disconnect(DisconnectReason::UselessPeer));
+		// the waiting packets are dropped along with the peer
+		assert!(throttled.is_empty());
+		assert_eq!(throttled.size, 0);
+		throttled.push(*b"eth", 2, vec![0]).unwrap();
+	}
+}
@@ -0,0 +1,28 @@
+// Copyright 2015-2018 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Node record filter trait.
+
+/// Node record filter. Lets a protocol advertise an entry in our own node record (EIP-778)
+/// and skip discovered nodes whose record doesn't carry a compatible one.
+pub trait EnrFilter : Send + Sync {
+	/// Key of the record entry this filter is concerned with.
+	fn key(&self) -> &[u8];
+	/// Current RLP-encoded value of the entry in our own record.
+	fn local_value(&self) -> Vec<u8>;
+	/// Check an RLP-encoded entry value of a discovered node. Returns `true` if the node is useful to us.
+	fn value_allowed(&self, value: &[u8]) -> bool;
+}
@@ -32,9 +32,11 @@ extern crate assert_matches;
 extern crate error_chain;
 
 mod connection_filter;
+mod enr_filter;
 mod error;
 
 pub use connection_filter::{ConnectionFilter, ConnectionDirection};
+pub use enr_filter::EnrFilter;
 pub use io::TimerToken;
 pub use error::{Error, ErrorKind, DisconnectReason};
 
@@ -89,6 +91,8 @@ pub enum NetworkIoMessage {
 	DisablePeer(PeerId),
 	/// Network has been started with the host as the given enode.
 	NetworkStarted(String),
+	/// DNS node lists were synced and the nodes they contain can be added.
+	DnsNodeListsSynced,
 }
 
 /// Shared session information
@@ -112,6 +116,19 @@ pub struct SessionInfo {
 	pub remote_address: String,
 	/// Local endpoint address of the session
 	pub local_address: String,
+	/// Bytes transferred over the session
+	pub traffic: TrafficStats,
+	/// Bytes transferred over the session by each protocol
+	pub protocol_traffic: HashMap<ProtocolId, TrafficStats>,
+}
+
+/// Bytes transferred.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TrafficStats {
+	/// Bytes received
+	pub ingress: u64,
+	/// Bytes sent
+	pub egress: u64,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -185,6 +202,10 @@ pub struct NetworkConfiguration {
 	pub discovery_enabled: bool,
 	/// List of initial node addresses
 	pub boot_nodes: Vec<String>,
+	/// List of EIP-1459 DNS node list URLs
+	pub dns_node_lists: Vec<String>,
+	/// Name servers to resolve DNS node lists with, the system's by default
+	pub dns_servers: Vec<String>,
 	/// Use provided node key instead of default
 	pub use_secret: Option<Secret>,
 	/// Minimum number of connected peers to maintain
@@ -203,6 +224,25 @@ pub struct NetworkConfiguration {
 	pub ip_filter: IpFilter,
 	/// Client identifier
 	pub client_version: String,
+	/// Upload limit for all peers together, in bytes per second
+	pub max_upload_rate: Option<u64>,
+	/// Upload limit for each peer, in bytes per second
+	pub max_peer_upload_rate: Option<u64>,
+	/// Upload limits for all peers of a protocol, in bytes per second
+	pub protocol_upload_rates: HashMap<ProtocolId, u64>,
+	/// Upload limits for some packets of a protocol
+	pub packet_upload_rates: Vec<PacketUploadRate>,
+}
+
+/// Upload limit for some packets of a protocol, shared by all peers.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PacketUploadRate {
+	/// Protocol the packets belong to
+	pub protocol: ProtocolId,
+	/// Limited packets
+	pub packets: Vec<PacketId>,
+	/// Limit for all the packets together, in bytes per second
+	pub rate: u64,
 }
 
 impl Default for NetworkConfiguration {
@@ -223,6 +263,8 @@ impl NetworkConfiguration {
 			nat_enabled: true,
 			discovery_enabled: true,
 			boot_nodes: Vec::new(),
+			dns_node_lists: Vec::new(),
+			dns_servers: Vec::new(),
 			use_secret: None,
 			min_peers: 25,
 			max_peers: 50,
@@ -232,6 +274,10 @@ impl NetworkConfiguration {
 			reserved_nodes: Vec::new(),
 			non_reserved_mode: NonReservedPeerMode::Accept,
 			client_version: "Parity-network".into(),
+			max_upload_rate: None,
+			max_peer_upload_rate: None,
+			protocol_upload_rates: HashMap::new(),
+			packet_upload_rates: Vec::new(),
 		}
 	}
 